@@ -1,9 +1,12 @@
 //! AresaDB Server Binary
 //!
-//! Standalone server for remote database access.
+//! Standalone server for remote database access, plus admin commands for
+//! managing a running replicated cluster.
 
 use anyhow::Result;
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use std::collections::HashMap;
+use std::net::SocketAddr;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[derive(Parser)]
@@ -26,9 +29,235 @@ struct Args {
     #[arg(short, long, default_value = "true")]
     compression: bool,
 
+    /// Largest request frame accepted, in bytes
+    #[arg(long, default_value_t = aresadb::server::DEFAULT_MAX_FRAME_SIZE)]
+    max_frame_size: usize,
+
+    /// Close connections without requests for this many seconds
+    #[arg(long, default_value = "300")]
+    idle_timeout: u64,
+
+    /// Fail requests that run longer than this many seconds
+    #[arg(long, default_value = "60")]
+    request_timeout: u64,
+
+    /// Seconds to let in-flight requests finish on SIGTERM or Ctrl-C
+    #[arg(long, default_value = "30")]
+    shutdown_timeout: u64,
+
     /// Number of shards (0 for single-node mode)
     #[arg(short, long, default_value = "0")]
     shards: usize,
+
+    /// Node ID; enables Raft replication with --peers
+    #[arg(long)]
+    node_id: Option<String>,
+
+    /// Other cluster members as id=host:port (their client addresses)
+    #[arg(long, value_delimiter = ',')]
+    peers: Vec<String>,
+
+    /// Peer listeners of the other members as id=host:port
+    #[arg(long, value_delimiter = ',')]
+    peer_addrs: Vec<String>,
+
+    /// Address clients should use to reach this node (defaults to --bind)
+    #[arg(long)]
+    advertise: Option<String>,
+
+    /// Accept consensus messages from other members on this address
+    #[arg(long, default_value = "127.0.0.1:7433")]
+    peer_bind: String,
+
+    /// Address other members should use to reach the peer listener
+    /// (defaults to --peer-bind)
+    #[arg(long)]
+    peer_advertise: Option<String>,
+
+    /// Secret shared by all members; peers must present it to connect
+    #[arg(long)]
+    cluster_secret: Option<String>,
+
+    /// Join an existing cluster instead of forming one; --peers must
+    /// include its current leader
+    #[arg(long)]
+    join: bool,
+
+    /// PEM certificate chain; serve TLS with --tls-key
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<String>,
+
+    /// PEM private key for --tls-cert
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<String>,
+
+    /// PEM roots trusted when connecting to peers or a server (defaults
+    /// to --tls-cert)
+    #[arg(long, global = true)]
+    tls_ca: Option<String>,
+
+    /// Name to verify server certificates against instead of the host
+    #[arg(long, global = true)]
+    tls_server_name: Option<String>,
+
+    /// Reject connections that do not authenticate
+    #[arg(long)]
+    require_auth: bool,
+
+    /// Also serve the HTTP/JSON API on this address (host:port)
+    #[arg(long)]
+    http: Option<String>,
+
+    /// Also speak the PostgreSQL wire protocol on this address (host:port)
+    #[arg(long)]
+    pg: Option<String>,
+
+    /// API token used for admin commands
+    #[arg(long, global = true)]
+    token: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<AdminCommand>,
+}
+
+/// Admin commands sent to a running server
+#[derive(Subcommand)]
+enum AdminCommand {
+    /// Manage cluster membership
+    Members {
+        #[command(subcommand)]
+        action: MembersAction,
+
+        /// Any cluster member; requests are redirected to the leader
+        #[arg(long, global = true, default_value = "127.0.0.1:7432")]
+        server: String,
+    },
+
+    /// Hand leadership to another voter
+    TransferLeader {
+        /// Node ID of the new leader
+        id: String,
+
+        /// Any cluster member; requests are redirected to the leader
+        #[arg(long, default_value = "127.0.0.1:7432")]
+        server: String,
+    },
+
+    /// Manage user accounts and API tokens in --database (offline)
+    ///
+    /// A replicated node keeps its accounts apart from the replicated data,
+    /// so run this on every node that should accept them.
+    Users {
+        #[command(subcommand)]
+        action: UsersAction,
+    },
+
+    /// Manage roles and read the audit log in --database (offline)
+    Access {
+        #[command(subcommand)]
+        action: AccessAction,
+    },
+
+    /// Print the OpenAPI document of the HTTP API
+    Openapi,
+}
+
+#[derive(Subcommand)]
+enum MembersAction {
+    /// List voters and learners
+    List,
+
+    /// Add a node; it is promoted to voter once it has caught up
+    Add {
+        /// Node ID
+        id: String,
+        /// Client address of the node (host:port)
+        address: String,
+        /// Address of the node's peer listener (host:port)
+        peer_address: String,
+        /// Keep the node as a non-voting learner
+        #[arg(long)]
+        learner: bool,
+    },
+
+    /// Remove a node
+    Remove {
+        /// Node ID
+        id: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum UsersAction {
+    /// List users
+    List,
+
+    /// Create a user
+    Add {
+        name: String,
+        #[arg(long)]
+        password: String,
+        /// Roles to give the user, e.g. `admin`
+        #[arg(long, value_delimiter = ',')]
+        role: Vec<String>,
+    },
+
+    /// Change a user's password
+    Passwd {
+        name: String,
+        #[arg(long)]
+        password: String,
+    },
+
+    /// Remove a user and revoke their tokens
+    Remove { name: String },
+
+    /// Issue an API token for a user; it is shown only once
+    Token {
+        name: String,
+        #[arg(long)]
+        label: Option<String>,
+    },
+
+    /// List API tokens
+    Tokens {
+        /// Only this user's tokens
+        name: Option<String>,
+    },
+
+    /// Revoke an API token by ID
+    Revoke { id: String },
+}
+
+#[derive(Subcommand)]
+enum AccessAction {
+    /// Run a GRANT or REVOKE statement
+    Exec {
+        /// e.g. "GRANT READ ON user TO analyst" or "GRANT ROLE analyst TO ada"
+        statement: String,
+    },
+
+    /// List roles and their grants
+    Roles,
+
+    /// Show denied requests, most recent first
+    Audit {
+        #[arg(long, default_value = "50")]
+        limit: usize,
+    },
+}
+
+/// Parse `id=host:port` peer specs
+fn parse_peers(specs: &[String]) -> Result<HashMap<String, SocketAddr>> {
+    specs
+        .iter()
+        .map(|spec| {
+            let (id, addr) = spec
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("Invalid peer '{}', expected id=host:port", spec))?;
+            Ok((id.to_string(), addr.parse()?))
+        })
+        .collect()
 }
 
 #[tokio::main]
@@ -41,7 +270,19 @@ async fn main() -> Result<()> {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    let args = Args::parse();
+    let mut args = Args::parse();
+
+    if let Some(command) = args.command.take() {
+        return match command {
+            AdminCommand::Users { action } => run_users(&args.database, action).await,
+            AdminCommand::Access { action } => run_access(&args.database, action).await,
+            AdminCommand::Openapi => {
+                println!("{}", serde_json::to_string_pretty(&aresadb::server::openapi())?);
+                Ok(())
+            }
+            command => run_admin(&args, command).await,
+        };
+    }
 
     tracing::info!("Starting AresaDB server...");
     tracing::info!("Database path: {}", args.database);
@@ -51,44 +292,342 @@ async fn main() -> Result<()> {
         bind_addr: args.bind.parse()?,
         max_connections: args.max_connections,
         compression: args.compression,
+        tls: config_tls(&args),
+        require_auth: args.require_auth,
+        max_frame_size: args.max_frame_size,
+        idle_timeout_secs: args.idle_timeout,
+        request_timeout_secs: args.request_timeout,
+        shutdown_timeout_secs: args.shutdown_timeout,
         ..Default::default()
     };
 
-    let server = if args.shards > 0 {
+    let mut server = if let Some(node_id) = args.node_id.clone() {
+        let peers = parse_peers(&args.peers)?;
+        let peer_addrs = parse_peers(&args.peer_addrs)?;
+        if let Some(id) = peers.keys().find(|id| !peer_addrs.contains_key(*id)) {
+            anyhow::bail!("No --peer-addrs entry for peer {}", id);
+        }
+        let Some(cluster_secret) = args.cluster_secret.clone() else {
+            anyhow::bail!("Replicated mode needs --cluster-secret");
+        };
+        tracing::info!("Replicated mode as {} with {} peers", node_id, peers.len());
+
+        let db = open_or_create(&args.database).await?;
+
+        let mut addresses: HashMap<String, String> = peers
+            .iter()
+            .map(|(id, addr)| (id.clone(), addr.to_string()))
+            .collect();
+        addresses.insert(node_id.clone(), args.advertise.clone().unwrap_or_else(|| args.bind.clone()));
+        let mut peer_addresses: HashMap<String, String> = peer_addrs
+            .iter()
+            .map(|(id, addr)| (id.clone(), addr.to_string()))
+            .collect();
+        peer_addresses.insert(node_id.clone(), args.peer_advertise.clone().unwrap_or_else(|| args.peer_bind.clone()));
+
+        let replica_config = aresadb::distributed::ReplicaConfig {
+            node_id,
+            peers: peers.keys().cloned().collect(),
+            addresses,
+            peer_addresses,
+            join: args.join,
+            ..Default::default()
+        };
+        let raft_dir = std::path::Path::new(&args.database).join(".aresadb/raft");
+        let replica = aresadb::distributed::ReplicaSet::open(replica_config, raft_dir)?;
+
+        let security = aresadb::server::PeerSecurity {
+            tls: client_tls(&args)?,
+            cluster_secret: Some(cluster_secret.clone()),
+        };
+        let transport = aresadb::server::TcpTransport::with_security(peer_addrs, args.compression, security);
+        let raft = aresadb::distributed::RaftNode::start(replica, db, transport);
+        let config = aresadb::server::ServerConfig {
+            peer_addr: Some(args.peer_bind.parse()?),
+            cluster_secret: Some(cluster_secret),
+            ..config
+        };
+        // Accounts stay out of the replicated data, where installing a
+        // snapshot from the leader would replace them
+        let accounts_dir = std::path::Path::new(&args.database).join(".aresadb/accounts");
+        let accounts = open_or_create(&accounts_dir.to_string_lossy()).await?;
+        let handler = aresadb::server::RequestHandler::with_replication(raft).with_accounts(accounts);
+        aresadb::server::Server::with_handler(handler, config)
+    } else if args.shards > 0 {
         tracing::info!("Sharded mode with {} shards", args.shards);
 
+        // An existing store keeps its persisted layout; change it with
+        // `aresadb shards rebalance`
+
         let shard_config = aresadb::distributed::ShardConfig {
             num_shards: args.shards,
             base_path: std::path::PathBuf::from(&args.database),
             ..Default::default()
         };
 
-        let shards = aresadb::distributed::ShardManager::new(shard_config).await?;
-        aresadb::server::Server::with_shards(shards, config)
+        let shards = std::sync::Arc::new(
+            aresadb::distributed::ShardManager::open_or_create(shard_config).await?,
+        );
+
+        // Finish a rebalance that was interrupted by a restart
+        if shards.layout().migration.is_some() {
+            shards.start_rebalance();
+        }
+        // Shards hold only user data; accounts live in a database of their own
+        let accounts = open_or_create(&args.database).await?;
+        let handler = aresadb::server::RequestHandler::with_shards(shards).with_accounts(accounts);
+        aresadb::server::Server::with_handler(handler, config)
     } else {
         tracing::info!("Single-node mode");
 
-        // Try to open existing database, or create new one
-        let db = match aresadb::storage::Database::open(&args.database).await {
-            Ok(db) => db,
-            Err(_) => {
-                tracing::info!("Creating new database");
-                aresadb::storage::Database::create(&args.database, "aresadb").await?
-            }
-        };
-
+        let db = open_or_create(&args.database).await?;
         aresadb::server::Server::new(db, config)
     };
 
-    // Handle shutdown signal
-    let shutdown = server.shutdown.clone();
+    if let Some(http) = &args.http {
+        server = server.with_http(aresadb::server::HttpConfig {
+            bind_addr: http.parse()?,
+            tls: config_tls(&args),
+            require_auth: args.require_auth,
+            idle_timeout_secs: args.idle_timeout,
+            ..Default::default()
+        });
+    }
+    if let Some(pg) = &args.pg {
+        server = server.with_pg(aresadb::server::PgConfig {
+            bind_addr: pg.parse()?,
+            tls: config_tls(&args),
+            require_auth: args.require_auth,
+            idle_timeout_secs: args.idle_timeout,
+            ..Default::default()
+        });
+    }
+
+    // Drain and flush on SIGTERM or Ctrl-C
+    let shutdown = server.shutdown_token();
     tokio::spawn(async move {
-        tokio::signal::ctrl_c().await.ok();
+        shutdown_signal().await;
         tracing::info!("Shutdown signal received");
-        *shutdown.write() = true;
+        shutdown.cancel();
     });
 
     server.run().await?;
 
     Ok(())
 }
+
+/// Resolve on Ctrl-C, or SIGTERM where there are signals
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = sigterm.recv() => {}
+                }
+                return;
+            }
+            Err(e) => tracing::warn!("Cannot listen for SIGTERM: {}", e),
+        }
+    }
+    tokio::signal::ctrl_c().await.ok();
+}
+
+/// Certificate and key to serve, if given
+fn config_tls(args: &Args) -> Option<aresadb::server::TlsConfig> {
+    match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => Some(aresadb::server::TlsConfig::new(cert, key)),
+        _ => None,
+    }
+}
+
+/// Try to open an existing database, or create a new one
+async fn open_or_create(path: &str) -> Result<aresadb::storage::Database> {
+    match aresadb::storage::Database::open(path).await {
+        Ok(db) => Ok(db),
+        Err(_) => {
+            tracing::info!("Creating new database");
+            aresadb::storage::Database::create(path, "aresadb").await
+        }
+    }
+}
+
+/// TLS configuration for outgoing connections, if TLS is in use
+fn client_tls(args: &Args) -> Result<Option<aresadb::server::ClientTlsConfig>> {
+    let Some(ca) = args.tls_ca.as_ref().or(args.tls_cert.as_ref()) else {
+        return Ok(None);
+    };
+
+    let mut tls = aresadb::server::ClientTlsConfig::new().add_ca_file(ca)?;
+    if let Some(name) = &args.tls_server_name {
+        tls = tls.server_name(name);
+    }
+    Ok(Some(tls))
+}
+
+/// Accounts of the store at `database`, and its replicated data if any
+///
+/// Replicated nodes keep accounts in a database of their own beside the
+/// Raft log, and roles in the replicated data; other stores keep both in
+/// the database itself.
+async fn open_accounts(database: &str) -> Result<(aresadb::storage::Database, Option<aresadb::storage::Database>)> {
+    let state = std::path::Path::new(database).join(".aresadb");
+    if state.join("raft").exists() {
+        let accounts = open_or_create(&state.join("accounts").to_string_lossy()).await?;
+        Ok((accounts, Some(aresadb::storage::Database::open(database).await?)))
+    } else {
+        Ok((open_or_create(database).await?, None))
+    }
+}
+
+/// Access control data over the databases [`open_accounts`] returned
+fn access_store<'a>(
+    accounts: &'a aresadb::storage::Database,
+    replicated: Option<&'a aresadb::storage::Database>,
+) -> aresadb::server::AccessStore<'a> {
+    match replicated {
+        Some(roles) => aresadb::server::AccessStore::with_roles(accounts, roles),
+        None => aresadb::server::AccessStore::new(accounts),
+    }
+}
+
+/// Manage accounts directly in the database files
+async fn run_users(path: &str, action: UsersAction) -> Result<()> {
+    let (db, replicated) = open_accounts(path).await?;
+    let users = aresadb::server::UserStore::new(&db);
+    let access = access_store(&db, replicated.as_ref());
+
+    match action {
+        UsersAction::List => {
+            println!("{:<16} {}", "USER", "ROLES");
+            for name in users.list_users().await? {
+                println!("{:<16} {}", name, access.user_roles(&name).await?.join(","));
+            }
+        }
+        UsersAction::Add { name, password, role } => {
+            users.create_user(&name, &password).await?;
+            for role in &role {
+                access.assign_role(&name, role).await?;
+            }
+            println!("Created user {}", name);
+        }
+        UsersAction::Passwd { name, password } => {
+            users.set_password(&name, &password).await?;
+            println!("Changed password for {}", name);
+        }
+        UsersAction::Remove { name } => {
+            users.delete_user(&name).await?;
+            println!("Removed user {}", name);
+        }
+        UsersAction::Token { name, label } => {
+            println!("{}", users.create_token(&name, label.as_deref()).await?);
+        }
+        UsersAction::Tokens { name } => {
+            println!("{:<38} {:<16} {}", "ID", "USER", "LABEL");
+            for token in users.list_tokens(name.as_deref()).await? {
+                println!("{:<38} {:<16} {}", token.id, token.username, token.label.as_deref().unwrap_or(""));
+            }
+        }
+        UsersAction::Revoke { id } => {
+            users.revoke_token(&id).await?;
+            println!("Revoked token {}", id);
+        }
+    }
+
+    Ok(())
+}
+
+/// Manage roles directly in the database files
+async fn run_access(path: &str, action: AccessAction) -> Result<()> {
+    use aresadb::server::AccessStatement;
+
+    let (db, replicated) = open_accounts(path).await?;
+    let access = access_store(&db, replicated.as_ref());
+
+    match action {
+        AccessAction::Exec { statement } => {
+            let parsed = AccessStatement::parse(&statement)
+                .ok_or_else(|| anyhow::anyhow!("Expected a GRANT or REVOKE statement"))??;
+            let changes_role = matches!(parsed, AccessStatement::Grant { .. } | AccessStatement::Revoke { .. });
+            if replicated.is_some() && changes_role {
+                anyhow::bail!("Roles of a replicated node change through the log; run this on the leader's server");
+            }
+            access.execute(&parsed).await?;
+            println!("OK");
+        }
+        AccessAction::Roles => {
+            for (role, grants) in access.roles().await? {
+                let grants: Vec<String> = grants.iter().map(|g| g.to_string()).collect();
+                println!("{:<16} {}", role, grants.join(", "));
+            }
+        }
+        AccessAction::Audit { limit } => {
+            for denial in access.denials(Some(limit)).await? {
+                println!("{}  {}", denial.at, denial);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Run an admin command against a cluster, following leader redirects
+async fn run_admin(args: &Args, command: AdminCommand) -> Result<()> {
+    use aresadb::client::ClientBuilder;
+    use aresadb::distributed::NotLeader;
+
+    let server = match &command {
+        AdminCommand::Members { server, .. } | AdminCommand::TransferLeader { server, .. } => server.clone(),
+        AdminCommand::Users { .. } | AdminCommand::Access { .. } | AdminCommand::Openapi => unreachable!("runs offline"),
+    };
+
+    let tls = client_tls(args)?;
+    let mut addr = server;
+    let mut redirected = false;
+    let members = loop {
+        let mut builder = ClientBuilder::new().address(&addr);
+        if let Some(tls) = &tls {
+            builder = builder.tls(tls.clone());
+        }
+        if let Some(token) = &args.token {
+            builder = builder.token(token);
+        }
+        let mut client = builder.build().await?;
+        let result = match &command {
+            AdminCommand::Members { action: MembersAction::List, .. } => client.members().await,
+            AdminCommand::Members { action: MembersAction::Add { id, address, peer_address, learner }, .. } => {
+                client.add_member(id, address, peer_address, !learner).await
+            }
+            AdminCommand::Members { action: MembersAction::Remove { id }, .. } => client.remove_member(id).await,
+            AdminCommand::TransferLeader { id, .. } => client.transfer_leadership(id).await,
+            AdminCommand::Users { .. } | AdminCommand::Access { .. } | AdminCommand::Openapi => unreachable!(),
+        };
+
+        match result {
+            Ok(members) => break members,
+            Err(e) => match (e.downcast_ref::<NotLeader>(), redirected) {
+                (Some(NotLeader { leader_addr: Some(leader), .. }), false) => {
+                    addr = leader.clone();
+                    redirected = true;
+                }
+                _ => return Err(e),
+            },
+        }
+    };
+
+    println!("{:<16} {:<24} {:<8} {:<7} {}", "ID", "ADDRESS", "ROLE", "LEADER", "MATCH");
+    for member in members {
+        println!(
+            "{:<16} {:<24} {:<8} {:<7} {}",
+            member.id,
+            member.address.as_deref().unwrap_or("-"),
+            format!("{:?}", member.role).to_lowercase(),
+            if member.is_leader { "yes" } else { "" },
+            member.match_index.map(|m| m.to_string()).unwrap_or_else(|| "-".to_string()),
+        );
+    }
+
+    Ok(())
+}
@@ -3,9 +3,11 @@
 //! Fluent API for building AresaDB clients.
 
 use anyhow::{Result, Context};
-use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
 
-use super::Client;
+use super::{Client, ClientPool, PoolConfig};
+use crate::server::{ClientTlsConfig, Credentials, DEFAULT_MAX_FRAME_SIZE};
 
 /// Builder for creating AresaDB clients
 #[derive(Debug, Clone)]
@@ -14,6 +16,12 @@ pub struct ClientBuilder {
     port: u16,
     pub(crate) compression: bool,
     timeout_secs: u64,
+    max_frame_size: usize,
+    tls: Option<ClientTlsConfig>,
+    ca_files: Vec<PathBuf>,
+    server_name: Option<String>,
+    credentials: Option<Credentials>,
+    pool: PoolConfig,
 }
 
 impl Default for ClientBuilder {
@@ -30,6 +38,12 @@ impl ClientBuilder {
             port: 7432,
             compression: true,
             timeout_secs: 10,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            tls: None,
+            ca_files: Vec::new(),
+            server_name: None,
+            credentials: None,
+            pool: PoolConfig::default(),
         }
     }
 
@@ -70,19 +84,126 @@ impl ClientBuilder {
         self
     }
 
+    /// Largest response frame to accept, in bytes
+    ///
+    /// The server answers a request whose response would be larger with a
+    /// `FrameTooLarge` error instead.
+    pub fn max_frame_size(mut self, bytes: usize) -> Self {
+        self.max_frame_size = bytes;
+        self
+    }
+
+    /// Connect over TLS with the given configuration
+    pub fn tls(mut self, config: ClientTlsConfig) -> Self {
+        self.tls = Some(config);
+        self
+    }
+
+    /// Connect over TLS, trusting the certificates in a PEM file
+    ///
+    /// May be called more than once; the files are read on `build`.
+    pub fn tls_ca_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.ca_files.push(path.into());
+        self
+    }
+
+    /// Verify the server certificate against this name instead of the host
+    pub fn tls_server_name(mut self, name: impl Into<String>) -> Self {
+        self.server_name = Some(name.into());
+        self
+    }
+
+    /// Authenticate with a username and password
+    pub fn credentials(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.credentials = Some(Credentials::password(username, password));
+        self
+    }
+
+    /// Authenticate with an API token
+    pub fn token(mut self, token: impl Into<String>) -> Self {
+        self.credentials = Some(Credentials::token(token));
+        self
+    }
+
+    /// Pool settings used by [`build_pool`](Self::build_pool)
+    pub fn pool(mut self, config: PoolConfig) -> Self {
+        self.pool = config;
+        self
+    }
+
+    /// Connections a pool keeps open even when idle
+    pub fn min_connections(mut self, count: usize) -> Self {
+        self.pool.min_connections = count;
+        self
+    }
+
+    /// Most connections a pool opens at once
+    pub fn max_connections(mut self, count: usize) -> Self {
+        self.pool.max_connections = count;
+        self
+    }
+
+    /// Limit on each attempt at a pooled request
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.pool.request_timeout = timeout;
+        self
+    }
+
+    /// Times a pool retries a request that failed in a way safe to retry
+    pub fn max_retries(mut self, retries: u32) -> Self {
+        self.pool.max_retries = retries;
+        self
+    }
+
+    /// How often a pool checks its idle connections
+    pub fn health_check_interval(mut self, interval: Duration) -> Self {
+        self.pool.health_check_interval = interval;
+        self
+    }
+
+    /// Build and connect a pool of clients
+    pub async fn build_pool(self) -> Result<ClientPool> {
+        let config = self.pool.clone();
+        ClientPool::connect(self, config).await
+    }
+
     /// Build and connect the client
     pub async fn build(self) -> Result<Client> {
-        let addr: SocketAddr = format!("{}:{}", self.host, self.port)
-            .parse()
-            .context("Invalid server address")?;
+        let address = self.get_address();
+        let addr = tokio::net::lookup_host(&address)
+            .await
+            .with_context(|| format!("Invalid server address: {}", address))?
+            .next()
+            .with_context(|| format!("No address found for {}", address))?;
 
-        let mut client = Client::connect(addr).await?;
+        let tls = self.tls_config()?;
+        let connect = Client::open(
+            addr,
+            &self.host,
+            tls.as_ref(),
+            self.credentials.as_ref(),
+            self.compression,
+            self.max_frame_size,
+        );
+        tokio::time::timeout(Duration::from_secs(self.timeout_secs), connect)
+            .await
+            .with_context(|| format!("Timed out connecting to {}", address))?
+    }
 
-        if !self.compression {
-            client.compressor = None;
+    /// TLS configuration, if any TLS option was set
+    fn tls_config(&self) -> Result<Option<ClientTlsConfig>> {
+        if self.tls.is_none() && self.ca_files.is_empty() && self.server_name.is_none() {
+            return Ok(None);
         }
 
-        Ok(client)
+        let mut config = self.tls.clone().unwrap_or_default();
+        for path in &self.ca_files {
+            config = config.add_ca_file(path)?;
+        }
+        if let Some(name) = &self.server_name {
+            config = config.server_name(name);
+        }
+        Ok(Some(config))
     }
 
     /// Get the configured address
@@ -118,6 +239,21 @@ mod tests {
         assert_eq!(builder.timeout_secs, 30);
     }
 
+    #[test]
+    fn test_builder_pool() {
+        let builder = ClientBuilder::new()
+            .min_connections(2)
+            .max_connections(4)
+            .request_timeout(Duration::from_secs(5))
+            .max_retries(1);
+
+        assert_eq!(builder.pool.min_connections, 2);
+        assert_eq!(builder.pool.max_connections, 4);
+        assert_eq!(builder.pool.request_timeout, Duration::from_secs(5));
+        assert_eq!(builder.pool.max_retries, 1);
+        assert_eq!(builder.pool.health_check_interval, PoolConfig::default().health_check_interval);
+    }
+
     #[test]
     fn test_builder_address() {
         let builder = ClientBuilder::new().address("db.example.com:9000");
@@ -126,6 +262,20 @@ mod tests {
         assert_eq!(builder.port, 9000);
     }
 
+    #[test]
+    fn test_builder_security() {
+        let builder = ClientBuilder::new()
+            .tls_ca_file("ca.pem")
+            .tls_server_name("db.internal")
+            .token("ares_abc");
+
+        assert_eq!(builder.ca_files, vec![PathBuf::from("ca.pem")]);
+        assert_eq!(builder.server_name.as_deref(), Some("db.internal"));
+        assert!(matches!(builder.credentials, Some(Credentials::Token(ref t)) if t == "ares_abc"));
+        assert!(builder.tls_config().is_err());
+        assert!(ClientBuilder::new().tls_config().unwrap().is_none());
+    }
+
     #[test]
     fn test_get_address() {
         let builder = ClientBuilder::new()
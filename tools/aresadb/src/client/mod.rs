@@ -1,44 +1,76 @@
 //! AresaDB Client SDK
 //!
-//! Client library for connecting to AresaDB servers.
+//! Client library for connecting to AresaDB servers. [`Client`] owns a
+//! single connection; [`ClientPool`] shares several between tasks.
 
 mod connection;
 mod builder;
+mod pool;
 
 pub use connection::Connection;
 pub use builder::ClientBuilder;
+pub use pool::{ClientPool, PooledClient, PoolConfig};
 
-use anyhow::{Result, Context, bail};
+use anyhow::{Result, bail};
+use std::collections::{HashMap, VecDeque};
 use std::net::SocketAddr;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
 
-use crate::storage::{Node, Edge, Value};
-use crate::server::protocol::{Request, Response, ErrorCode};
-use crate::distributed::Compressor;
+use crate::storage::{ChangeEvent, ChangeFilter, DistanceMetric, Node, Edge, Value};
+use crate::server::{
+    read_message, write_message, ClientTlsConfig, Credentials, FrameCodec, Hello, Request, Response,
+    SearchHit, Stream, DEFAULT_MAX_FRAME_SIZE,
+};
+use crate::distributed::{MemberInfo, NotLeader};
+
+/// Requests [`Client::pipeline`] keeps unanswered at once
+const PIPELINE_WINDOW: usize = 32;
 
 /// AresaDB client for remote connections
 pub struct Client {
     /// Server address
     addr: SocketAddr,
-    /// Active TCP stream
-    stream: TcpStream,
-    /// Compressor for data transfer
-    compressor: Option<Compressor>,
+    /// Active connection, plain or TLS
+    stream: Box<dyn Stream>,
+    /// Frame encoding settled in the handshake
+    codec: FrameCodec,
+    /// ID for the next request; 0 is reserved for connection errors
+    next_id: u64,
+    /// Responses received while waiting for another request's
+    pending: HashMap<u64, Response>,
+    /// User the server authenticated us as
+    user: Option<String>,
 }
 
 impl Client {
-    /// Create a new client connected to the server
+    /// Create a new client connected to the server, over plain TCP and
+    /// without credentials
+    ///
+    /// Use [`Client::builder`] for TLS or authentication.
     pub async fn connect(addr: impl Into<SocketAddr>) -> Result<Self> {
         let addr = addr.into();
-        let stream = TcpStream::connect(&addr)
-            .await
-            .context("Failed to connect to server")?;
+        Self::open(addr, &addr.ip().to_string(), None, None, true, DEFAULT_MAX_FRAME_SIZE).await
+    }
+
+    /// Connect and perform the handshake
+    pub(crate) async fn open(
+        addr: SocketAddr,
+        host: &str,
+        tls: Option<&ClientTlsConfig>,
+        credentials: Option<&Credentials>,
+        compression: bool,
+        max_frame_size: usize,
+    ) -> Result<Self> {
+        let mut stream = crate::server::dial(addr, host, tls).await?;
+        let hello = Hello::new(compression, max_frame_size, credentials.cloned());
+        let (welcome, codec) = crate::server::handshake(&mut stream, &hello).await?;
 
         Ok(Self {
             addr,
             stream,
-            compressor: Some(Compressor::new()),
+            codec,
+            next_id: 1,
+            pending: HashMap::new(),
+            user: welcome.user,
         })
     }
 
@@ -52,6 +84,11 @@ impl Client {
         self.addr
     }
 
+    /// User the connection is authenticated as, if any
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
     /// Ping the server
     pub async fn ping(&mut self) -> Result<()> {
         let response = self.send_request(Request::Ping).await?;
@@ -175,6 +212,92 @@ impl Client {
         }
     }
 
+    /// Get edges into a node
+    pub async fn get_edges_to(&mut self, node_id: &str, edge_type: Option<&str>) -> Result<Vec<Edge>> {
+        let response = self.send_request(Request::GetEdgesTo {
+            node_id: node_id.to_string(),
+            edge_type: edge_type.map(String::from),
+        }).await?;
+
+        match response {
+            Response::Edges(edges) => Ok(edges),
+            Response::Error { message, .. } => bail!("Query failed: {}", message),
+            _ => bail!("Unexpected response"),
+        }
+    }
+
+    /// Delete an edge
+    pub async fn delete_edge(&mut self, edge_id: &str) -> Result<()> {
+        let response = self.send_request(Request::DeleteEdge {
+            edge_id: edge_id.to_string(),
+        }).await?;
+
+        match response {
+            Response::Ok => Ok(()),
+            Response::Error { message, .. } => bail!("Delete edge failed: {}", message),
+            _ => bail!("Unexpected response"),
+        }
+    }
+
+    /// Traverse the graph breadth-first from a node
+    pub async fn traverse(
+        &mut self,
+        start_id: &str,
+        depth: u32,
+        edge_types: Option<Vec<String>>,
+    ) -> Result<(Vec<Node>, Vec<Edge>)> {
+        let response = self.send_request(Request::Traverse {
+            start_id: start_id.to_string(),
+            depth,
+            edge_types,
+        }).await?;
+
+        match response {
+            Response::TraversalResult { nodes, edges, .. } => Ok((nodes, edges)),
+            Response::Error { message, .. } => bail!("Traversal failed: {}", message),
+            _ => bail!("Unexpected response"),
+        }
+    }
+
+    /// Find the shortest path of at most `max_depth` edges between two nodes
+    pub async fn shortest_path(&mut self, from_id: &str, to_id: &str, max_depth: u32) -> Result<Option<Vec<Node>>> {
+        let response = self.send_request(Request::ShortestPath {
+            from_id: from_id.to_string(),
+            to_id: to_id.to_string(),
+            max_depth,
+        }).await?;
+
+        match response {
+            Response::Path(path) => Ok(path),
+            Response::Error { message, .. } => bail!("Shortest path failed: {}", message),
+            _ => bail!("Unexpected response"),
+        }
+    }
+
+    /// The `k` nodes of a type whose `field` embedding is nearest to `vector`
+    pub async fn similarity_search(
+        &mut self,
+        node_type: &str,
+        field: &str,
+        vector: &[f32],
+        k: usize,
+        metric: DistanceMetric,
+    ) -> Result<Vec<SearchHit>> {
+        let response = self.send_request(Request::SimilaritySearch {
+            node_type: node_type.to_string(),
+            field: field.to_string(),
+            vector: vector.to_vec(),
+            k,
+            metric,
+        }).await?;
+
+        match response {
+            Response::SearchResults(hits) => Ok(hits),
+            Response::Error { message, .. } => bail!("Similarity search failed: {}", message),
+            _ => bail!("Unexpected response"),
+        }
+    }
+
     /// Execute a SQL query
     pub async fn query(&mut self, sql: &str, limit: Option<usize>) -> Result<QueryResult> {
         let response = self.send_request(Request::Query {
@@ -237,45 +360,164 @@ impl Client {
         }
     }
 
+    /// List cluster members (replicated servers only)
+    pub async fn members(&mut self) -> Result<Vec<MemberInfo>> {
+        let response = self.send_request(Request::ListMembers).await?;
+        Self::expect_members(response, "List members")
+    }
+
+    /// Add a replica serving clients at `address` and peers at `peer_address`
+    ///
+    /// Voters join as learners and are promoted once they have caught up.
+    pub async fn add_member(
+        &mut self,
+        id: &str,
+        address: &str,
+        peer_address: &str,
+        voter: bool,
+    ) -> Result<Vec<MemberInfo>> {
+        let response = self.send_request(Request::AddMember {
+            id: id.to_string(),
+            address: address.to_string(),
+            peer_address: peer_address.to_string(),
+            voter,
+        }).await?;
+        Self::expect_members(response, "Add member")
+    }
+
+    /// Remove a replica from the cluster
+    pub async fn remove_member(&mut self, id: &str) -> Result<Vec<MemberInfo>> {
+        let response = self.send_request(Request::RemoveMember { id: id.to_string() }).await?;
+        Self::expect_members(response, "Remove member")
+    }
+
+    /// Hand leadership to the voter `id`
+    pub async fn transfer_leadership(&mut self, id: &str) -> Result<Vec<MemberInfo>> {
+        let response = self.send_request(Request::TransferLeadership { id: id.to_string() }).await?;
+        Self::expect_members(response, "Leadership transfer")
+    }
+
+    /// Stream changes after sequence `after`, or only those still to come
+    /// when `after` is `None`
+    ///
+    /// The connection is given over to the subscription; keep the last
+    /// sequence handled to resume from it on a new one.
+    pub async fn subscribe(mut self, after: Option<u64>, filter: ChangeFilter) -> Result<Subscription> {
+        let id = self.send(Request::Subscribe { after, filter }).await?;
+
+        match self.wait_for(id).await? {
+            Response::Subscribed { last_seq } => Ok(Subscription { client: self, id, last_seq }),
+            Response::Error { message, .. } => bail!("Subscribe failed: {}", message),
+            _ => bail!("Unexpected response"),
+        }
+    }
+
+    /// Send a request without waiting for its response, returning its ID
+    ///
+    /// Collect the response with [`recv`](Self::recv). The server runs
+    /// pipelined requests concurrently, so send a request that depends on
+    /// another's effect only after that one's response has arrived.
+    pub async fn send(&mut self, request: Request) -> Result<u64> {
+        let id = self.next_id;
+        self.next_id += 1;
+        write_message(&mut self.stream, &self.codec, id, &request).await?;
+        Ok(id)
+    }
+
+    /// Receive the next response, with the ID of the request it answers
+    ///
+    /// Responses arrive in the order the server completes them, not the
+    /// order the requests were sent.
+    pub async fn recv(&mut self) -> Result<(u64, Response)> {
+        if let Some(&id) = self.pending.keys().next() {
+            let response = self.pending.remove(&id).unwrap();
+            return Ok((id, response));
+        }
+        self.read_response().await
+    }
+
+    /// Send a batch of requests over the one connection, returning the
+    /// responses in request order
+    ///
+    /// Up to 32 requests are in flight at a time. Their order of execution
+    /// is not defined, so the batch should not contain requests that
+    /// depend on each other.
+    pub async fn pipeline(&mut self, requests: Vec<Request>) -> Result<Vec<Response>> {
+        let mut ids = Vec::with_capacity(requests.len());
+        let mut unanswered = VecDeque::new();
+        let mut responses = HashMap::with_capacity(requests.len());
+
+        for request in requests {
+            if unanswered.len() == PIPELINE_WINDOW {
+                let id = unanswered.pop_front().unwrap();
+                responses.insert(id, self.wait_for(id).await?);
+            }
+            let id = self.send(request).await?;
+            ids.push(id);
+            unanswered.push_back(id);
+        }
+        for id in unanswered {
+            responses.insert(id, self.wait_for(id).await?);
+        }
+
+        Ok(ids.into_iter().map(|id| responses.remove(&id).unwrap()).collect())
+    }
+
     // === Private methods ===
 
+    fn expect_members(response: Response, operation: &str) -> Result<Vec<MemberInfo>> {
+        match response {
+            Response::Members(members) => Ok(members),
+            Response::Error { message, .. } => bail!("{} failed: {}", operation, message),
+            _ => bail!("Unexpected response"),
+        }
+    }
+
+
+    /// Send a request and wait for its response, whatever it is
+    pub(crate) async fn call(&mut self, request: Request) -> Result<Response> {
+        let id = self.send(request).await?;
+        self.wait_for(id).await
+    }
+
+    /// Send a request and wait for its response
     async fn send_request(&mut self, request: Request) -> Result<Response> {
-        // Serialize request
-        let body = bincode::serialize(&request)?;
-
-        // Compress if enabled
-        let body = if let Some(ref comp) = self.compressor {
-            comp.compress(&body)?
-        } else {
-            body
-        };
-
-        // Send length + body
-        let len = body.len() as u32;
-        self.stream.write_all(&len.to_le_bytes()).await?;
-        self.stream.write_all(&body).await?;
-        self.stream.flush().await?;
-
-        // Read response length
-        let mut len_buf = [0u8; 4];
-        self.stream.read_exact(&mut len_buf).await?;
-        let msg_len = u32::from_le_bytes(len_buf) as usize;
-
-        // Read response body
-        let mut body = vec![0u8; msg_len];
-        self.stream.read_exact(&mut body).await?;
-
-        // Decompress if enabled
-        let body = if let Some(ref comp) = self.compressor {
-            comp.decompress(&body)?
-        } else {
-            body
-        };
-
-        // Deserialize response
-        let response: Response = bincode::deserialize(&body)?;
+        let response = self.call(request).await?;
+
+        // Writes sent to a follower fail with the leader's location
+        if let Response::NotLeader { leader_id, leader_addr } = response {
+            return Err(NotLeader { leader_id, leader_addr }.into());
+        }
+
         Ok(response)
     }
+
+    /// Wait for the response to request `id`, keeping any others that
+    /// arrive first
+    async fn wait_for(&mut self, id: u64) -> Result<Response> {
+        if let Some(response) = self.pending.remove(&id) {
+            return Ok(response);
+        }
+        loop {
+            let (received, response) = self.read_response().await?;
+            if received == id {
+                return Ok(response);
+            }
+            self.pending.insert(received, response);
+        }
+    }
+
+    /// Read one response off the connection
+    async fn read_response(&mut self) -> Result<(u64, Response)> {
+        let (id, response) = read_message(&mut self.stream, &self.codec).await?;
+        if id == crate::server::CONNECTION_ERROR_ID {
+            match response {
+                Response::Error { code, message } => bail!("Connection closed by server: {}: {}", code, message),
+                other => bail!("Unexpected connection message: {:?}", other),
+            }
+        }
+        Ok((id, response))
+    }
 }
 
 /// Query result from the server
@@ -296,6 +538,29 @@ pub struct DatabaseStatus {
     pub size_bytes: u64,
 }
 
+/// Changes streamed over a connection of their own
+pub struct Subscription {
+    client: Client,
+    id: u64,
+    last_seq: u64,
+}
+
+impl Subscription {
+    /// Latest sequence when the subscription started
+    pub fn last_seq(&self) -> u64 {
+        self.last_seq
+    }
+
+    /// Wait for the next change
+    pub async fn next(&mut self) -> Result<ChangeEvent> {
+        match self.client.wait_for(self.id).await? {
+            Response::Change(event) => Ok(event),
+            Response::Error { message, .. } => bail!("Subscription failed: {}", message),
+            _ => bail!("Unexpected response"),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
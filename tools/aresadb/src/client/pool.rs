@@ -0,0 +1,752 @@
+//! Connection Pool
+//!
+//! [`ClientPool`] shares connections to a server between tasks. It is cheap
+//! to clone, and every call checks a connection out for just that request,
+//! so no lock is held across the network round trip.
+//!
+//! Connections that fail are replaced. A request is resent on a fresh
+//! connection only when that cannot apply it twice: when it never left, or
+//! when it is [idempotent](Request::is_idempotent). Against a replicated
+//! cluster the pool follows `NotLeader` redirects to the leader and falls
+//! back to the other members it has seen if the leader becomes unreachable.
+
+use anyhow::{anyhow, bail, Context, Result};
+use parking_lot::{Mutex, RwLock};
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::timeout;
+use tracing::{debug, warn};
+
+use super::{Client, ClientBuilder, DatabaseStatus, QueryResult};
+use crate::distributed::{MemberInfo, NotLeader};
+use crate::server::{ErrorCode, Request, Response, SearchHit};
+use crate::storage::{DistanceMetric, Edge, Node, Value};
+
+/// Pool sizing, timeouts and retry policy
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    /// Connections kept open even when idle
+    pub min_connections: usize,
+    /// Most connections open at once; further requests wait for one
+    pub max_connections: usize,
+    /// Limit on each attempt at a request, including waiting for a connection
+    pub request_timeout: Duration,
+    /// Times a failed request is retried
+    pub max_retries: u32,
+    /// Pause before the first retry, growing linearly with each one
+    pub retry_backoff: Duration,
+    /// Idle connections are pinged this often, and before reuse once idle
+    /// this long
+    pub health_check_interval: Duration,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            min_connections: 1,
+            max_connections: 10,
+            request_timeout: Duration::from_secs(30),
+            max_retries: 3,
+            retry_backoff: Duration::from_millis(100),
+            health_check_interval: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Cloneable client sharing a pool of connections
+#[derive(Clone)]
+pub struct ClientPool {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    /// Settings for new connections; the address is replaced per connection
+    builder: ClientBuilder,
+    config: PoolConfig,
+    /// Address new connections go to: the leader, once known
+    target: RwLock<String>,
+    /// Other cluster members, tried when the target is unreachable
+    members: RwLock<Vec<String>>,
+    /// Connections waiting to be checked out, most recently used last
+    idle: Mutex<Vec<Idle>>,
+    /// One permit per connection that may be checked out
+    permits: Arc<Semaphore>,
+    /// Connections open, idle or checked out
+    open: AtomicUsize,
+}
+
+struct Idle {
+    client: Client,
+    address: String,
+    since: Instant,
+}
+
+/// How an attempt at a request failed
+enum Failure {
+    /// Before the request was written, so it was not applied
+    Unsent(anyhow::Error),
+    /// After it was written; the server may have applied it
+    Sent(anyhow::Error),
+}
+
+impl ClientPool {
+    /// Open `config.min_connections` connections to the builder's address
+    ///
+    /// At least one connection is made, so a wrong address or bad
+    /// credentials fail here rather than on the first request.
+    pub async fn connect(builder: ClientBuilder, config: PoolConfig) -> Result<Self> {
+        if config.max_connections == 0 || config.min_connections > config.max_connections {
+            bail!("Pool needs 0 < min_connections <= max_connections");
+        }
+
+        let inner = Arc::new(Inner {
+            target: RwLock::new(builder.get_address()),
+            members: RwLock::new(Vec::new()),
+            idle: Mutex::new(Vec::new()),
+            permits: Arc::new(Semaphore::new(config.max_connections)),
+            open: AtomicUsize::new(0),
+            builder,
+            config,
+        });
+
+        let (mut client, address) = inner.connect().await?;
+        inner.discover(&mut client).await;
+        inner.put_idle(client, address);
+        inner.fill().await;
+
+        let weak = Arc::downgrade(&inner);
+        let interval = inner.config.health_check_interval;
+        tokio::spawn(health_checks(weak, interval));
+
+        Ok(Self { inner })
+    }
+
+    /// Address requests currently go to
+    pub fn address(&self) -> String {
+        self.inner.target.read().clone()
+    }
+
+    /// Connections open, idle or checked out
+    pub fn open_connections(&self) -> usize {
+        self.inner.open.load(Ordering::SeqCst)
+    }
+
+    /// Connections waiting to be checked out
+    pub fn idle_connections(&self) -> usize {
+        self.inner.idle.lock().len()
+    }
+
+    /// Check out a connection for several requests, such as a transaction
+    /// or a [pipeline](Client::pipeline)
+    ///
+    /// It goes back to the pool when dropped. Requests on it are not
+    /// retried or redirected.
+    pub async fn get(&self) -> Result<PooledClient> {
+        let wait = self.inner.config.request_timeout;
+        timeout(wait, self.inner.checkout())
+            .await
+            .map_err(|_| anyhow!("Timed out after {:?} waiting for a connection", wait))?
+    }
+
+    /// Send a request on a pooled connection, retrying where that is safe
+    pub async fn request(&self, request: Request) -> Result<Response> {
+        let inner = &self.inner;
+        let config = &inner.config;
+        let idempotent = request.is_idempotent();
+
+        let mut attempt = 0;
+        loop {
+            let error = match inner.attempt(&request).await {
+                Ok(Response::NotLeader { leader_id, leader_addr }) => {
+                    // Followers refuse writes without applying them, so any request may be resent
+                    let known = leader_addr.is_some();
+                    if let Some(leader) = &leader_addr {
+                        inner.follow_leader(leader);
+                    }
+                    if attempt >= config.max_retries {
+                        return Err(NotLeader { leader_id, leader_addr }.into());
+                    }
+                    attempt += 1;
+                    if !known {
+                        // Mid-election; give it a moment
+                        tokio::time::sleep(config.retry_backoff * attempt).await;
+                    }
+                    continue;
+                }
+                Ok(Response::Error { code: code @ (ErrorCode::ServerOverloaded | ErrorCode::ServerBusy), message }) => {
+                    // Refused before running, so safe to resend
+                    anyhow!("{}: {}", code, message)
+                }
+                Ok(response) => return Ok(response),
+                Err(Failure::Unsent(e)) => e,
+                Err(Failure::Sent(e)) if idempotent => e,
+                Err(Failure::Sent(e)) => {
+                    return Err(e.context(format!("{} failed and may have been applied, so it was not retried", request.name())));
+                }
+            };
+
+            if attempt >= config.max_retries {
+                return Err(error.context(format!("{} failed after {} attempts", request.name(), attempt + 1)));
+            }
+            attempt += 1;
+            debug!("Retrying {} (attempt {}): {:#}", request.name(), attempt, error);
+            tokio::time::sleep(config.retry_backoff * attempt).await;
+        }
+    }
+
+    /// Ping the server
+    pub async fn ping(&self) -> Result<()> {
+        match self.request(Request::Ping).await? {
+            Response::Pong => Ok(()),
+            other => Err(failed(other, "Ping")),
+        }
+    }
+
+    /// Insert a new node
+    pub async fn insert_node(&self, node_type: &str, properties: serde_json::Value) -> Result<Node> {
+        let properties = Value::from_json(properties)?;
+        match self.request(Request::InsertNode { node_type: node_type.to_string(), properties }).await? {
+            Response::Node(node) => Ok(node),
+            other => Err(failed(other, "Insert")),
+        }
+    }
+
+    /// Get a node by ID
+    pub async fn get_node(&self, id: &str) -> Result<Option<Node>> {
+        match self.request(Request::GetNode { id: id.to_string() }).await? {
+            Response::MaybeNode(node) => Ok(node),
+            other => Err(failed(other, "Get")),
+        }
+    }
+
+    /// Update a node
+    pub async fn update_node(&self, id: &str, properties: serde_json::Value) -> Result<Node> {
+        let properties = Value::from_json(properties)?;
+        match self.request(Request::UpdateNode { id: id.to_string(), properties }).await? {
+            Response::Node(node) => Ok(node),
+            other => Err(failed(other, "Update")),
+        }
+    }
+
+    /// Delete a node
+    pub async fn delete_node(&self, id: &str) -> Result<()> {
+        match self.request(Request::DeleteNode { id: id.to_string() }).await? {
+            Response::Ok => Ok(()),
+            other => Err(failed(other, "Delete")),
+        }
+    }
+
+    /// Get nodes by type
+    pub async fn get_nodes_by_type(&self, node_type: &str, limit: Option<usize>) -> Result<Vec<Node>> {
+        match self.request(Request::GetNodesByType { node_type: node_type.to_string(), limit }).await? {
+            Response::Nodes(nodes) => Ok(nodes),
+            other => Err(failed(other, "Query")),
+        }
+    }
+
+    /// Create an edge
+    pub async fn create_edge(
+        &self,
+        from_id: &str,
+        to_id: &str,
+        edge_type: &str,
+        properties: Option<serde_json::Value>,
+    ) -> Result<Edge> {
+        let request = Request::CreateEdge {
+            from_id: from_id.to_string(),
+            to_id: to_id.to_string(),
+            edge_type: edge_type.to_string(),
+            properties: properties.map(Value::from_json).transpose()?,
+        };
+        match self.request(request).await? {
+            Response::Edge(edge) => Ok(edge),
+            other => Err(failed(other, "Create edge")),
+        }
+    }
+
+    /// Get edges from a node
+    pub async fn get_edges_from(&self, node_id: &str, edge_type: Option<&str>) -> Result<Vec<Edge>> {
+        let request = Request::GetEdgesFrom { node_id: node_id.to_string(), edge_type: edge_type.map(String::from) };
+        match self.request(request).await? {
+            Response::Edges(edges) => Ok(edges),
+            other => Err(failed(other, "Query")),
+        }
+    }
+
+    /// Get edges into a node
+    pub async fn get_edges_to(&self, node_id: &str, edge_type: Option<&str>) -> Result<Vec<Edge>> {
+        let request = Request::GetEdgesTo { node_id: node_id.to_string(), edge_type: edge_type.map(String::from) };
+        match self.request(request).await? {
+            Response::Edges(edges) => Ok(edges),
+            other => Err(failed(other, "Query")),
+        }
+    }
+
+    /// Delete an edge
+    pub async fn delete_edge(&self, edge_id: &str) -> Result<()> {
+        match self.request(Request::DeleteEdge { edge_id: edge_id.to_string() }).await? {
+            Response::Ok => Ok(()),
+            other => Err(failed(other, "Delete edge")),
+        }
+    }
+
+    /// Traverse the graph breadth-first from a node
+    pub async fn traverse(&self, start_id: &str, depth: u32, edge_types: Option<Vec<String>>) -> Result<(Vec<Node>, Vec<Edge>)> {
+        match self.request(Request::Traverse { start_id: start_id.to_string(), depth, edge_types }).await? {
+            Response::TraversalResult { nodes, edges, .. } => Ok((nodes, edges)),
+            other => Err(failed(other, "Traversal")),
+        }
+    }
+
+    /// Find the shortest path of at most `max_depth` edges between two nodes
+    pub async fn shortest_path(&self, from_id: &str, to_id: &str, max_depth: u32) -> Result<Option<Vec<Node>>> {
+        let request = Request::ShortestPath { from_id: from_id.to_string(), to_id: to_id.to_string(), max_depth };
+        match self.request(request).await? {
+            Response::Path(path) => Ok(path),
+            other => Err(failed(other, "Shortest path")),
+        }
+    }
+
+    /// The `k` nodes of a type whose `field` embedding is nearest to `vector`
+    pub async fn similarity_search(
+        &self,
+        node_type: &str,
+        field: &str,
+        vector: &[f32],
+        k: usize,
+        metric: DistanceMetric,
+    ) -> Result<Vec<SearchHit>> {
+        let request = Request::SimilaritySearch {
+            node_type: node_type.to_string(),
+            field: field.to_string(),
+            vector: vector.to_vec(),
+            k,
+            metric,
+        };
+        match self.request(request).await? {
+            Response::SearchResults(hits) => Ok(hits),
+            other => Err(failed(other, "Similarity search")),
+        }
+    }
+
+    /// Execute a SQL query; only `SELECT`s are retried
+    pub async fn query(&self, sql: &str, limit: Option<usize>) -> Result<QueryResult> {
+        match self.request(Request::Query { sql: sql.to_string(), limit }).await? {
+            Response::QueryResult { columns, rows, rows_affected, execution_time_ms } => {
+                Ok(QueryResult { columns, rows, rows_affected, execution_time_ms })
+            }
+            other => Err(failed(other, "Query")),
+        }
+    }
+
+    /// Get database status
+    pub async fn status(&self) -> Result<DatabaseStatus> {
+        match self.request(Request::Status).await? {
+            Response::Status { name, node_count, edge_count, size_bytes } => {
+                Ok(DatabaseStatus { name, node_count, edge_count, size_bytes })
+            }
+            other => Err(failed(other, "Status")),
+        }
+    }
+
+    /// List cluster members (replicated servers only)
+    pub async fn members(&self) -> Result<Vec<MemberInfo>> {
+        match self.request(Request::ListMembers).await? {
+            Response::Members(members) => Ok(members),
+            other => Err(failed(other, "List members")),
+        }
+    }
+}
+
+/// Error for a response other than the one expected
+fn failed(response: Response, operation: &str) -> anyhow::Error {
+    match response {
+        Response::Error { message, .. } => anyhow!("{} failed: {}", operation, message),
+        _ => anyhow!("Unexpected response"),
+    }
+}
+
+impl Inner {
+    /// One try at a request on a checked out connection
+    async fn attempt(self: &Arc<Self>, request: &Request) -> std::result::Result<Response, Failure> {
+        let limit = self.config.request_timeout;
+        let started = Instant::now();
+
+        let mut client = match timeout(limit, self.checkout()).await {
+            Ok(Ok(client)) => client,
+            Ok(Err(e)) => return Err(Failure::Unsent(e)),
+            Err(_) => return Err(Failure::Unsent(anyhow!("Timed out after {:?} waiting for a connection", limit))),
+        };
+
+        let remaining = limit.saturating_sub(started.elapsed());
+        match timeout(remaining, client.call(request.clone())).await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(e)) => {
+                client.discard();
+                Err(Failure::Sent(e))
+            }
+            Err(_) => {
+                // A late response would be read as the next request's
+                client.discard();
+                Err(Failure::Sent(anyhow!("Request timed out after {:?}", limit)))
+            }
+        }
+    }
+
+    /// Take an idle connection, or open one if there is room
+    async fn checkout(self: &Arc<Self>) -> Result<PooledClient> {
+        let permit = Arc::clone(&self.permits).acquire_owned().await.context("Pool is closed")?;
+
+        loop {
+            let Some(mut idle) = self.idle.lock().pop() else {
+                break;
+            };
+            if idle.since.elapsed() >= self.config.health_check_interval && !self.healthy(&mut idle.client).await {
+                self.open.fetch_sub(1, Ordering::SeqCst);
+                continue;
+            }
+            return Ok(PooledClient { client: Some(idle.client), address: idle.address, pool: Arc::clone(self), _permit: permit });
+        }
+
+        let (client, address) = self.connect().await?;
+        Ok(PooledClient { client: Some(client), address, pool: Arc::clone(self), _permit: permit })
+    }
+
+    /// Open a connection to the target, or failing that to another member
+    async fn connect(&self) -> Result<(Client, String)> {
+        let target = self.target.read().clone();
+        let mut candidates = vec![target.clone()];
+        candidates.extend(self.members.read().iter().filter(|member| **member != target).cloned());
+
+        let mut last_error = None;
+        for address in candidates {
+            match self.builder.clone().address(&address).build().await {
+                Ok(client) => {
+                    self.open.fetch_add(1, Ordering::SeqCst);
+                    if address != target {
+                        warn!("{} is unreachable, switching to {}", target, address);
+                        *self.target.write() = address.clone();
+                    }
+                    return Ok((client, address));
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow!("No address to connect to")))
+    }
+
+    /// Learn the cluster's members and move to its leader
+    ///
+    /// Servers that aren't replicated refuse, which is fine.
+    async fn discover(&self, client: &mut Client) {
+        let Ok(Ok(Response::Members(members))) = timeout(self.config.request_timeout, client.call(Request::ListMembers)).await else {
+            return;
+        };
+        *self.members.write() = members.iter().filter_map(|member| member.address.clone()).collect();
+        if let Some(leader) = members.iter().find(|member| member.is_leader).and_then(|member| member.address.as_deref()) {
+            self.follow_leader(leader);
+        }
+    }
+
+    /// Send new connections to `leader`, closing idle ones to the old target
+    fn follow_leader(&self, leader: &str) {
+        let previous = {
+            let mut target = self.target.write();
+            if *target == leader {
+                return;
+            }
+            std::mem::replace(&mut *target, leader.to_string())
+        };
+        debug!("Following leader {} (was {})", leader, previous);
+
+        let mut members = self.members.write();
+        if !members.contains(&previous) {
+            members.push(previous);
+        }
+        drop(members);
+
+        let stale = {
+            let mut idle = self.idle.lock();
+            let before = idle.len();
+            idle.retain(|idle| idle.address == leader);
+            before - idle.len()
+        };
+        self.open.fetch_sub(stale, Ordering::SeqCst);
+    }
+
+    fn put_idle(&self, client: Client, address: String) {
+        self.idle.lock().push(Idle { client, address, since: Instant::now() });
+    }
+
+    async fn healthy(&self, client: &mut Client) -> bool {
+        matches!(timeout(self.config.request_timeout, client.call(Request::Ping)).await, Ok(Ok(Response::Pong)))
+    }
+
+    /// Ping idle connections, dropping dead ones, and open connections up
+    /// to the minimum
+    async fn health_check(self: &Arc<Self>) {
+        let idle = std::mem::take(&mut *self.idle.lock());
+        for mut idle in idle {
+            // Checked out connections count against the maximum while pinged
+            let Ok(_permit) = Arc::clone(&self.permits).try_acquire_owned() else {
+                self.idle.lock().push(idle);
+                continue;
+            };
+            if self.healthy(&mut idle.client).await {
+                idle.since = Instant::now();
+                self.idle.lock().push(idle);
+            } else {
+                debug!("Closing unhealthy connection to {}", idle.address);
+                self.open.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+        self.fill().await;
+    }
+
+    /// Open idle connections until `min_connections` are open
+    async fn fill(&self) {
+        while self.open.load(Ordering::SeqCst) < self.config.min_connections {
+            match self.connect().await {
+                Ok((client, address)) => self.put_idle(client, address),
+                Err(e) => {
+                    debug!("Failed to open pooled connection: {:#}", e);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Check idle connections until the pool is dropped
+async fn health_checks(pool: Weak<Inner>, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        let Some(pool) = pool.upgrade() else {
+            return;
+        };
+        pool.health_check().await;
+    }
+}
+
+/// A connection checked out of a [`ClientPool`]
+pub struct PooledClient {
+    client: Option<Client>,
+    address: String,
+    pool: Arc<Inner>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl PooledClient {
+    /// Close the connection instead of returning it to the pool
+    pub fn discard(mut self) {
+        if self.client.take().is_some() {
+            self.pool.open.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+impl Deref for PooledClient {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        self.client.as_ref().expect("connection is checked out")
+    }
+}
+
+impl DerefMut for PooledClient {
+    fn deref_mut(&mut self) -> &mut Client {
+        self.client.as_mut().expect("connection is checked out")
+    }
+}
+
+impl Drop for PooledClient {
+    fn drop(&mut self) {
+        let Some(client) = self.client.take() else {
+            return;
+        };
+        // Connections to a former leader are not reused
+        if *self.pool.target.read() == self.address {
+            self.pool.put_idle(client, std::mem::take(&mut self.address));
+        } else {
+            self.pool.open.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{
+        read_handshake, read_message, write_handshake, write_message, FrameCodec, HandshakeReply, Hello, Server,
+        ServerConfig, Welcome, DEFAULT_MAX_FRAME_SIZE, PROTOCOL_VERSION,
+    };
+    use crate::storage::Database;
+    use std::net::SocketAddr;
+    use std::sync::atomic::AtomicBool;
+    use tempfile::TempDir;
+    use tokio::net::TcpListener;
+
+    /// What a fake server does with a request
+    enum Action {
+        Reply(Response),
+        /// Close the connection without answering
+        Drop,
+        /// Never answer
+        Ignore,
+    }
+
+    /// Server speaking the protocol with canned responses
+    async fn fake_server(respond: impl Fn(&Request) -> Action + Send + Sync + 'static) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let respond = Arc::new(respond);
+
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let respond = Arc::clone(&respond);
+                tokio::spawn(async move {
+                    let _: Hello = read_handshake(&mut stream).await.unwrap();
+                    let welcome = Welcome {
+                        protocol_version: PROTOCOL_VERSION,
+                        compression: None,
+                        max_frame_size: DEFAULT_MAX_FRAME_SIZE as u64,
+                        user: None,
+                    };
+                    write_handshake(&mut stream, &HandshakeReply::Welcome(welcome)).await.unwrap();
+                    let codec = FrameCodec::new(false, DEFAULT_MAX_FRAME_SIZE, DEFAULT_MAX_FRAME_SIZE);
+
+                    while let Ok((id, request)) = read_message::<_, Request>(&mut stream, &codec).await {
+                        match respond(&request) {
+                            Action::Reply(response) => write_message(&mut stream, &codec, id, &response).await.unwrap(),
+                            Action::Drop => return,
+                            Action::Ignore => {}
+                        }
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    async fn real_server(temp: &TempDir) -> SocketAddr {
+        let db = Database::create(temp.path().join("db"), "test").await.unwrap();
+        let addr = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let server = Arc::new(Server::new(db, ServerConfig { bind_addr: addr, ..Default::default() }));
+        tokio::spawn(async move { server.run().await });
+        for _ in 0..100 {
+            if tokio::net::TcpStream::connect(addr).await.is_ok() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        addr
+    }
+
+    fn config() -> PoolConfig {
+        PoolConfig {
+            min_connections: 1,
+            max_connections: 3,
+            request_timeout: Duration::from_millis(500),
+            max_retries: 2,
+            retry_backoff: Duration::from_millis(10),
+            health_check_interval: Duration::from_secs(60),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pool_shares_connections() {
+        let temp = TempDir::new().unwrap();
+        let addr = real_server(&temp).await;
+        let pool = ClientBuilder::new().address(&addr.to_string()).pool(config()).build_pool().await.unwrap();
+        assert_eq!(pool.open_connections(), 1);
+
+        let tasks: Vec<_> = (0..24)
+            .map(|i| {
+                let pool = pool.clone();
+                tokio::spawn(async move {
+                    let node = pool.insert_node("item", serde_json::json!({ "n": i })).await.unwrap();
+                    pool.get_node(&node.id.to_string()).await.unwrap().unwrap()
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert!(pool.open_connections() <= 3);
+        assert_eq!(pool.get_nodes_by_type("item", None).await.unwrap().len(), 24);
+
+        // A checked out connection comes back when dropped
+        let idle = pool.idle_connections();
+        let mut client = pool.get().await.unwrap();
+        client.ping().await.unwrap();
+        assert_eq!(pool.idle_connections(), idle - 1);
+        drop(client);
+        assert_eq!(pool.idle_connections(), idle);
+    }
+
+    #[tokio::test]
+    async fn test_retries_only_idempotent_requests() {
+        let pings = Arc::new(AtomicUsize::new(0));
+        let inserts = Arc::new(AtomicUsize::new(0));
+        let (p, i) = (Arc::clone(&pings), Arc::clone(&inserts));
+        let addr = fake_server(move |request| match request {
+            // The first of each is cut off mid-request
+            Request::Ping if p.fetch_add(1, Ordering::SeqCst) == 0 => Action::Drop,
+            Request::Ping => Action::Reply(Response::Pong),
+            Request::InsertNode { node_type, properties } => match i.fetch_add(1, Ordering::SeqCst) {
+                0 => Action::Drop,
+                _ => Action::Reply(Response::Node(Node::new(node_type, properties.clone()))),
+            },
+            Request::Status => Action::Ignore,
+            _ => Action::Reply(Response::error(ErrorCode::InvalidRequest, "Server is not replicated")),
+        })
+        .await;
+        let pool = ClientBuilder::new().address(&addr.to_string()).pool(config()).build_pool().await.unwrap();
+
+        pool.ping().await.unwrap();
+        assert_eq!(pings.load(Ordering::SeqCst), 2);
+
+        let error = pool.insert_node("item", serde_json::json!({})).await.unwrap_err();
+        assert!(format!("{:#}", error).contains("may have been applied"));
+        assert_eq!(inserts.load(Ordering::SeqCst), 1);
+        pool.insert_node("item", serde_json::json!({})).await.unwrap();
+
+        // Each attempt times out, then the request gives up
+        let started = Instant::now();
+        assert!(pool.status().await.is_err());
+        assert!(started.elapsed() >= Duration::from_millis(1500));
+        assert!(pool.open_connections() <= 3);
+    }
+
+    #[tokio::test]
+    async fn test_follows_leader() {
+        let temp = TempDir::new().unwrap();
+        let leader = real_server(&temp).await;
+        let redirected = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&redirected);
+        let follower = fake_server(move |request| match request {
+            Request::Ping => Action::Reply(Response::Pong),
+            Request::ListMembers => Action::Reply(Response::error(ErrorCode::InvalidRequest, "Server is not replicated")),
+            _ => {
+                flag.store(true, Ordering::SeqCst);
+                Action::Reply(Response::NotLeader {
+                    leader_id: Some("n1".to_string()),
+                    leader_addr: Some(leader.to_string()),
+                })
+            }
+        })
+        .await;
+
+        let pool = ClientBuilder::new().address(&follower.to_string()).pool(config()).build_pool().await.unwrap();
+        let node = pool.insert_node("item", serde_json::json!({ "name": "a" })).await.unwrap();
+        assert!(redirected.load(Ordering::SeqCst));
+        assert_eq!(pool.address(), leader.to_string());
+
+        let mut direct = ClientBuilder::new().address(&leader.to_string()).build().await.unwrap();
+        assert!(direct.get_node(&node.id.to_string()).await.unwrap().is_some());
+    }
+}
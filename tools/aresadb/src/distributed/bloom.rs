@@ -310,6 +310,62 @@ impl CountingBloomFilter {
         self.count = 0;
     }
 
+    /// Get the estimated false positive probability
+    pub fn estimated_fpp(&self) -> f64 {
+        let k = self.num_hashes as f64;
+        let n = self.count as f64;
+        let m = self.num_counters as f64;
+        (1.0 - (-k * n / m).exp()).powf(k)
+    }
+
+    /// Get the memory usage in bytes
+    pub fn memory_usage(&self) -> usize {
+        self.counters.len() * 8
+    }
+
+    /// Serialize the filter to bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(12 + self.counters.len() * 8);
+
+        result.extend_from_slice(&(self.num_counters as u32).to_le_bytes());
+        result.extend_from_slice(&self.num_hashes.to_le_bytes());
+        result.extend_from_slice(&(self.count as u32).to_le_bytes());
+
+        for word in &self.counters {
+            result.extend_from_slice(&word.to_le_bytes());
+        }
+
+        result
+    }
+
+    /// Deserialize a filter from bytes
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() < 12 {
+            return None;
+        }
+
+        let num_counters = u32::from_le_bytes(data[0..4].try_into().ok()?) as usize;
+        let num_hashes = u32::from_le_bytes(data[4..8].try_into().ok()?);
+        let count = u32::from_le_bytes(data[8..12].try_into().ok()?) as usize;
+
+        let num_words = num_counters.div_ceil(16);
+        if num_counters == 0 || data.len() != 12 + num_words * 8 {
+            return None;
+        }
+
+        let counters = data[12..]
+            .chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+
+        Some(Self {
+            counters,
+            num_counters,
+            num_hashes,
+            count,
+        })
+    }
+
     // === Private methods ===
 
     fn hash_pair(&self, item: &[u8]) -> (u64, u64) {
@@ -345,7 +401,9 @@ impl CountingBloomFilter {
         let counter_idx = (idx % 16) * 4;
         let current = (self.counters[word_idx] >> counter_idx) & 0xF;
 
-        if current > 0 {
+        // A saturated counter no longer knows how many items hit it, so it
+        // stays set rather than risk a false negative
+        if current > 0 && current < 15 {
             self.counters[word_idx] -= 1u64 << counter_idx;
         }
     }
@@ -477,4 +535,20 @@ mod tests {
         filter.remove(b"test");
         assert!(!filter.may_contain(b"test"));
     }
+
+    #[test]
+    fn test_counting_bloom_serialization() {
+        let mut filter = CountingBloomFilter::new(1000, 0.01);
+        filter.insert(b"test");
+        filter.insert(b"data");
+        filter.remove(b"data");
+
+        let bytes = filter.to_bytes();
+        let restored = CountingBloomFilter::from_bytes(&bytes).unwrap();
+
+        assert!(restored.may_contain(b"test"));
+        assert!(!restored.may_contain(b"data"));
+        assert_eq!(restored.count(), 1);
+        assert!(CountingBloomFilter::from_bytes(&bytes[..bytes.len() - 1]).is_none());
+    }
 }
@@ -0,0 +1,185 @@
+//! Cluster Membership
+//!
+//! The set of replicas is itself replicated: every change is a log entry
+//! carrying the complete new [`Membership`], which takes effect as soon as
+//! it is appended. Changes are single-server — each one adds or removes at
+//! most one voter — so the old and new majorities always overlap and no
+//! joint configuration is needed. A new replica first joins as a learner,
+//! which receives the log but neither votes nor counts towards commits,
+//! and is promoted once it has caught up.
+
+use anyhow::{Result, bail};
+use serde::{Serialize, Deserialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Replicas taking part in the cluster
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Membership {
+    /// Replicas that vote and count towards commit majorities
+    pub voters: BTreeSet<String>,
+    /// Replicas that only receive the log
+    pub learners: BTreeSet<String>,
+    /// Client-facing address of each replica, when known
+    pub addresses: BTreeMap<String, String>,
+    /// Address each replica accepts consensus messages on, when known
+    #[serde(default)]
+    pub peer_addresses: BTreeMap<String, String>,
+}
+
+impl Membership {
+    /// Membership with the given voters and no learners
+    pub fn new(voters: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            voters: voters.into_iter().collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Whether `id` votes
+    pub fn is_voter(&self, id: &str) -> bool {
+        self.voters.contains(id)
+    }
+
+    /// Whether `id` is a learner
+    pub fn is_learner(&self, id: &str) -> bool {
+        self.learners.contains(id)
+    }
+
+    /// Whether `id` is a voter or learner
+    pub fn contains(&self, id: &str) -> bool {
+        self.is_voter(id) || self.is_learner(id)
+    }
+
+    /// All voters and learners
+    pub fn members(&self) -> impl Iterator<Item = &String> {
+        self.voters.iter().chain(self.learners.iter())
+    }
+
+    /// Votes or acknowledgements needed for a majority
+    pub fn quorum(&self) -> usize {
+        self.voters.len() / 2 + 1
+    }
+
+    /// Membership after applying `change`
+    pub fn apply(&self, change: &MembershipChange) -> Result<Self> {
+        let mut next = self.clone();
+        match change {
+            MembershipChange::AddLearner { id, address, peer_address } => {
+                if self.contains(id) {
+                    bail!("{} is already a member", id);
+                }
+                next.learners.insert(id.clone());
+                if let Some(address) = address {
+                    next.addresses.insert(id.clone(), address.clone());
+                }
+                if let Some(peer_address) = peer_address {
+                    next.peer_addresses.insert(id.clone(), peer_address.clone());
+                }
+            }
+            MembershipChange::Promote { id } => {
+                if !next.learners.remove(id) {
+                    bail!("{} is not a learner", id);
+                }
+                next.voters.insert(id.clone());
+            }
+            MembershipChange::Remove { id } => {
+                if !self.contains(id) {
+                    bail!("{} is not a member", id);
+                }
+                if self.is_voter(id) && self.voters.len() == 1 {
+                    bail!("Cannot remove the last voter");
+                }
+                next.voters.remove(id);
+                next.learners.remove(id);
+                next.addresses.remove(id);
+                next.peer_addresses.remove(id);
+            }
+        }
+        Ok(next)
+    }
+}
+
+/// A single-server membership change
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MembershipChange {
+    /// Start replicating to a new non-voting replica
+    AddLearner {
+        /// Node ID
+        id: String,
+        /// Client-facing address
+        address: Option<String>,
+        /// Address of the replica's peer listener
+        #[serde(default)]
+        peer_address: Option<String>,
+    },
+    /// Turn a learner into a voter
+    Promote {
+        /// Node ID of the learner
+        id: String,
+    },
+    /// Remove a voter or learner
+    Remove {
+        /// Node ID
+        id: String,
+    },
+}
+
+/// Role of a replica
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MemberRole {
+    /// Votes and counts towards majorities
+    Voter,
+    /// Receives the log without voting
+    Learner,
+}
+
+/// One replica as reported to administrators
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemberInfo {
+    /// Node ID
+    pub id: String,
+    /// Client-facing address
+    pub address: Option<String>,
+    /// Voter or learner
+    pub role: MemberRole,
+    /// Whether this replica is the leader
+    pub is_leader: bool,
+    /// Highest log index known to be replicated there (leader only)
+    pub match_index: Option<u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single_server_changes() {
+        let membership = Membership::new(["a".to_string(), "b".to_string(), "c".to_string()]);
+        assert_eq!(membership.quorum(), 2);
+
+        let added = membership
+            .apply(&MembershipChange::AddLearner {
+                id: "d".into(),
+                address: Some("127.0.0.1:7004".into()),
+                peer_address: Some("127.0.0.1:8004".into()),
+            })
+            .unwrap();
+        assert!(added.is_learner("d"));
+        assert_eq!(added.peer_addresses.get("d").map(String::as_str), Some("127.0.0.1:8004"));
+        assert_eq!(added.quorum(), 2);
+        assert!(added.apply(&MembershipChange::AddLearner { id: "a".into(), address: None, peer_address: None }).is_err());
+
+        let promoted = added.apply(&MembershipChange::Promote { id: "d".into() }).unwrap();
+        assert!(promoted.is_voter("d"));
+        assert_eq!(promoted.quorum(), 3);
+        assert!(promoted.apply(&MembershipChange::Promote { id: "d".into() }).is_err());
+
+        let removed = promoted.apply(&MembershipChange::Remove { id: "d".into() }).unwrap();
+        assert!(!removed.contains("d"));
+        assert!(removed.addresses.is_empty());
+        assert!(removed.peer_addresses.is_empty());
+
+        let single = Membership::new(["a".to_string()]);
+        assert!(single.apply(&MembershipChange::Remove { id: "a".into() }).is_err());
+    }
+}
@@ -5,7 +5,7 @@
 //! - Write-ahead logging for durability
 //! - Bloom filters for fast negative lookups
 //! - LZ4 compression for storage efficiency
-//! - Replication for fault tolerance
+//! - Replication for fault tolerance (Raft, with a networked driver)
 //! - Streaming for large result sets
 
 mod bloom;
@@ -13,13 +13,22 @@ mod compression;
 mod shard;
 mod wal;
 mod replication;
+mod membership;
+mod raft_log;
+mod raft;
 mod streaming;
 
 pub use bloom::{BloomFilter, CountingBloomFilter};
 pub use compression::{Compressor, CompressionStats};
-pub use shard::{ShardManager, ShardConfig, Shard};
+pub use shard::{ShardManager, ShardConfig, Shard, ShardLayout, ShardMigration, RebalanceProgress};
 pub use wal::{WriteAheadLog, WalEntry, WalEntryType};
-pub use replication::{ReplicaSet, ReplicaConfig, ReplicaState};
+pub use replication::{
+    ReplicaSet, ReplicaConfig, ReplicaState,
+    ConsensusMessage, Envelope, LogEntry, ReplicationCommand, NodeUpdate, MetadataEntry,
+};
+pub use membership::{Membership, MembershipChange, MemberRole, MemberInfo};
+pub use raft_log::{RaftLog, HardState, SnapshotMeta, SnapshotWriter};
+pub use raft::{RaftNode, RaftTransport, NotLeader, InProcessNetwork, InProcessTransport};
 pub use streaming::{ResultStream, StreamSender, Cursor};
 
 #[cfg(test)]
@@ -0,0 +1,1167 @@
+//! Networked Raft Driver
+//!
+//! Runs a [`ReplicaSet`] against a real clock and transport: ticks it every
+//! heartbeat interval, feeds it incoming messages, sends what it produces,
+//! and applies committed entries to the node's database. Writes are
+//! proposed through [`RaftNode::propose`], which resolves once the entry is
+//! committed and applied locally.
+//!
+//! Every `snapshot_threshold` applied entries the database is snapshotted
+//! and the log compacted; snapshots received from the leader replace the
+//! database contents before any later entry is applied. Both run on a
+//! blocking thread, so the event loop keeps exchanging heartbeats meanwhile.
+//!
+//! Membership is managed online through [`RaftNode::add_member`],
+//! [`RaftNode::remove_member`] and [`RaftNode::transfer_leadership`]; the
+//! transport is told whenever the set of peers changes.
+
+use anyhow::Result;
+use parking_lot::{Mutex, RwLock};
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, Notify};
+
+use super::membership::{MemberInfo, Membership, MembershipChange};
+use super::replication::{ConsensusMessage, Envelope, ReplicaSet, ReplicationCommand};
+use crate::metrics::metrics;
+use crate::storage::Database;
+
+/// Default time a proposal waits to be committed
+const DEFAULT_PROPOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Error returned when a write reaches a node that is not the leader
+#[derive(Debug, Clone, thiserror::Error)]
+#[error(
+    "not the leader{}",
+    match (leader_id, leader_addr) {
+        (Some(id), Some(addr)) => format!("; leader is {} at {}", id, addr),
+        (Some(id), None) => format!("; leader is {}", id),
+        _ => "; no leader elected yet".to_string(),
+    }
+)]
+pub struct NotLeader {
+    /// ID of the current leader, if known
+    pub leader_id: Option<String>,
+    /// Client-facing address of the current leader, if known
+    pub leader_addr: Option<String>,
+}
+
+/// Delivers consensus messages to peers
+///
+/// Sending must not block: Raft tolerates lost messages, so transports
+/// queue or drop rather than wait on slow peers.
+pub trait RaftTransport: Send + Sync {
+    /// Queue a message for delivery
+    fn send(&self, envelope: Envelope);
+
+    /// Called when the set of peers changes; `membership` excludes the
+    /// local node
+    fn update_members(&self, _membership: &Membership) {}
+}
+
+/// Proposal waiting to be applied
+struct Waiter {
+    term: u64,
+    tx: oneshot::Sender<Result<()>>,
+}
+
+/// Snapshot being built or restored off the event loop
+struct SnapshotTask {
+    /// Index of the snapshot being restored; `None` while compacting
+    restoring: Option<u64>,
+    handle: tokio::task::JoinHandle<Result<()>>,
+}
+
+/// A replica driven by a clock and a transport
+pub struct RaftNode {
+    replica: Arc<ReplicaSet>,
+    db: Arc<Database>,
+    transport: Arc<dyn RaftTransport>,
+    inbox: mpsc::UnboundedSender<ConsensusMessage>,
+    waiters: Mutex<HashMap<u64, Waiter>>,
+    /// Serializes application of committed entries
+    apply_lock: tokio::sync::Mutex<()>,
+    snapshot_task: Mutex<Option<SnapshotTask>>,
+    propose_timeout: Duration,
+    /// Membership last reported to the transport
+    members: Mutex<Membership>,
+    shutdown: Notify,
+}
+
+impl RaftNode {
+    /// Start driving `replica`, applying committed entries to `db`
+    pub fn start(
+        replica: ReplicaSet,
+        db: Database,
+        transport: Arc<dyn RaftTransport>,
+    ) -> Arc<Self> {
+        Self::start_with_timeout(replica, db, transport, DEFAULT_PROPOSE_TIMEOUT)
+    }
+
+    /// Start with a custom proposal timeout
+    pub fn start_with_timeout(
+        replica: ReplicaSet,
+        db: Database,
+        transport: Arc<dyn RaftTransport>,
+        propose_timeout: Duration,
+    ) -> Arc<Self> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let node = Arc::new(Self {
+            replica: Arc::new(replica),
+            db: Arc::new(db),
+            transport,
+            inbox: tx,
+            waiters: Mutex::new(HashMap::new()),
+            apply_lock: tokio::sync::Mutex::new(()),
+            snapshot_task: Mutex::new(None),
+            propose_timeout,
+            members: Mutex::new(Membership::default()),
+            shutdown: Notify::new(),
+        });
+
+        tokio::spawn(Arc::clone(&node).run(rx));
+        node
+    }
+
+    /// Event loop: ticks, incoming messages, sending and applying
+    async fn run(self: Arc<Self>, mut rx: mpsc::UnboundedReceiver<ConsensusMessage>) {
+        let interval_ms = self.replica.config().heartbeat_interval_ms.max(1);
+        let mut ticker = tokio::time::interval(Duration::from_millis(interval_ms));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            let outgoing = tokio::select! {
+                _ = ticker.tick() => self.replica.tick(),
+                msg = rx.recv() => match msg {
+                    Some(msg) => self.replica.step(msg),
+                    None => break,
+                },
+                _ = self.shutdown.notified() => break,
+            };
+
+            self.sync_members();
+            match outgoing {
+                Ok(envelopes) => self.send_all(envelopes),
+                // Nothing was promised to peers, so dropping is safe
+                Err(e) => tracing::error!("Raft step failed on {}: {:#}", self.replica.node_id(), e),
+            }
+
+            self.apply_committed().await;
+            self.record_metrics();
+        }
+
+        tracing::debug!("Raft loop for {} stopped", self.replica.node_id());
+    }
+
+    /// Publish term, indexes and leadership
+    fn record_metrics(&self) {
+        let m = metrics();
+        let node = [self.replica.node_id()];
+        m.raft_term.with_label_values(&node).set(self.replica.term() as i64);
+        m.raft_commit_index.with_label_values(&node).set(self.replica.commit_index() as i64);
+        m.raft_applied_index.with_label_values(&node).set(self.replica.last_applied() as i64);
+        m.raft_leader.with_label_values(&node).set(self.replica.is_leader() as i64);
+    }
+
+    /// Tell the transport about membership changes
+    fn sync_members(&self) {
+        let mut membership = self.replica.membership();
+        let me = self.replica.node_id();
+        membership.voters.remove(me);
+        membership.learners.remove(me);
+        membership.addresses.remove(me);
+        membership.peer_addresses.remove(me);
+
+        let mut known = self.members.lock();
+        if *known != membership {
+            self.transport.update_members(&membership);
+            *known = membership;
+        }
+    }
+
+    fn send_all(&self, envelopes: Vec<Envelope>) {
+        for envelope in envelopes {
+            self.transport.send(envelope);
+        }
+    }
+
+    /// Apply committed entries and resolve their proposals
+    async fn apply_committed(&self) {
+        let _guard = self.apply_lock.lock().await;
+
+        // No entries are applied while a restore runs, since they build on
+        // its state; a failed restore is retried on the next iteration
+        let snapshot_running = self.reap_snapshot_task().await;
+        if !snapshot_running {
+            match self.replica.pending_restore() {
+                Ok(Some((meta, data))) => {
+                    self.start_restore(meta.index, data);
+                    return;
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    tracing::error!("Failed to open snapshot on {}: {:#}", self.replica.node_id(), e);
+                    return;
+                }
+            }
+        }
+
+        for entry in self.replica.get_unapplied_entries() {
+            let result = entry.command.apply(self.db.local()).await;
+            if let Err(ref e) = result {
+                if is_storage_failure(e) {
+                    // This replica would silently diverge if it moved on;
+                    // the entry is retried on the next iteration
+                    tracing::error!(
+                        "Failed to apply entry {} on {}: {:#}",
+                        entry.index, self.replica.node_id(), e
+                    );
+                    return;
+                }
+                tracing::warn!("Entry {} was rejected: {:#}", entry.index, e);
+            }
+            self.replica.mark_applied(entry.index);
+
+            if let Some(waiter) = self.waiters.lock().remove(&entry.index) {
+                let result = if waiter.term == entry.term {
+                    result
+                } else {
+                    Err(anyhow::anyhow!("Entry {} was superseded by a new leader", entry.index))
+                };
+                let _ = waiter.tx.send(result);
+            }
+        }
+
+        if !snapshot_running && self.replica.should_snapshot() {
+            if let Err(e) = self.start_compaction() {
+                tracing::warn!("Failed to compact raft log on {}: {:#}", self.replica.node_id(), e);
+            }
+        }
+    }
+
+    /// Collect a finished snapshot task, returning whether one still runs
+    async fn reap_snapshot_task(&self) -> bool {
+        let finished = {
+            let mut task = self.snapshot_task.lock();
+            match task.as_ref() {
+                Some(running) if !running.handle.is_finished() => return true,
+                _ => task.take(),
+            }
+        };
+        let Some(task) = finished else { return false };
+
+        let node_id = self.replica.node_id();
+        match (task.restoring, task.handle.await.map_err(anyhow::Error::from).and_then(|r| r)) {
+            (Some(index), Ok(())) => self.waiters.lock().retain(|&i, _| i > index),
+            (Some(_), Err(e)) => tracing::error!("Failed to restore snapshot on {}: {:#}", node_id, e),
+            (None, Ok(())) => {}
+            (None, Err(e)) => tracing::warn!("Failed to compact raft log on {}: {:#}", node_id, e),
+        }
+        false
+    }
+
+    /// Replace the database contents with a snapshot from the leader
+    fn start_restore(&self, index: u64, data: Box<dyn Read + Send>) {
+        let local = self.db.local().clone();
+        let replica = Arc::clone(&self.replica);
+        let handle = tokio::task::spawn_blocking(move || {
+            local.load_snapshot(data, true)?;
+            replica.snapshot_restored(index)
+        });
+        *self.snapshot_task.lock() = Some(SnapshotTask { restoring: Some(index), handle });
+    }
+
+    /// Snapshot the database at the applied index and truncate the log
+    ///
+    /// Called with the apply lock held so the read view matches the index;
+    /// the snapshot is then written while later entries are applied.
+    fn start_compaction(&self) -> Result<()> {
+        let index = self.replica.last_applied();
+        let view = self.db.local().read_view()?;
+        let mut data = self.replica.snapshot_writer()?;
+        let replica = Arc::clone(&self.replica);
+        let handle = tokio::task::spawn_blocking(move || {
+            view.snapshot(&mut data, true)?;
+            replica.compact(index, data)
+        });
+        *self.snapshot_task.lock() = Some(SnapshotTask { restoring: None, handle });
+        Ok(())
+    }
+
+    /// Replicate a command and wait until it is applied locally
+    ///
+    /// Fails with [`NotLeader`] on followers.
+    pub async fn propose(&self, command: ReplicationCommand) -> Result<()> {
+        self.submit(|replica| replica.append_command(command)).await
+    }
+
+    /// Append an entry with `append` and wait until it is applied locally
+    async fn submit(&self, append: impl FnOnce(&ReplicaSet) -> Result<u64>) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+
+        {
+            // Register before the entry can possibly be applied
+            let mut waiters = self.waiters.lock();
+            if !self.replica.is_leader() {
+                return Err(self.not_leader().into());
+            }
+            let index = append(&self.replica)?;
+            let term = self.replica.term_at(index).unwrap_or_default();
+            waiters.insert(index, Waiter { term, tx });
+        }
+
+        self.sync_members();
+        self.send_all(self.replica.replicate());
+        // A single-node cluster commits on append
+        self.apply_committed().await;
+
+        match tokio::time::timeout(self.propose_timeout, rx).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => anyhow::bail!("Raft node shut down before the write committed"),
+            Err(_) => anyhow::bail!("Timed out waiting for the write to commit"),
+        }
+    }
+
+    /// Apply a single membership change once it is committed
+    pub async fn change_membership(&self, change: MembershipChange) -> Result<()> {
+        self.submit(|replica| replica.change_membership(change)).await
+    }
+
+    /// Add a voter: join it as a learner, wait for it to catch up, then
+    /// promote it
+    pub async fn add_member(&self, id: &str, address: Option<String>, peer_address: Option<String>) -> Result<()> {
+        let membership = self.replica.membership();
+        if !membership.is_learner(id) {
+            self.add_learner(id, address, peer_address).await?;
+        }
+        self.wait_for(self.propose_timeout, || self.replica.is_caught_up(id)).await
+            .map_err(|_| anyhow::anyhow!("{} did not catch up with the log; it remains a learner", id))?;
+        self.change_membership(MembershipChange::Promote { id: id.to_string() }).await
+    }
+
+    /// Add a non-voting learner
+    pub async fn add_learner(&self, id: &str, address: Option<String>, peer_address: Option<String>) -> Result<()> {
+        self.change_membership(MembershipChange::AddLearner { id: id.to_string(), address, peer_address }).await
+    }
+
+    /// Remove a voter or learner
+    ///
+    /// Removing the leader itself makes it step down once the change is
+    /// committed; the remaining voters then elect a new leader.
+    pub async fn remove_member(&self, id: &str) -> Result<()> {
+        self.change_membership(MembershipChange::Remove { id: id.to_string() }).await
+    }
+
+    /// Hand leadership to the voter `target` and wait until it takes over
+    pub async fn transfer_leadership(&self, target: &str) -> Result<()> {
+        if !self.replica.is_leader() {
+            return Err(self.not_leader().into());
+        }
+        let envelopes = self.replica.transfer_leadership(target)?;
+        self.send_all(envelopes);
+
+        let timeout = Duration::from_millis(self.replica.config().election_timeout_ms.1.max(1) * 2);
+        self.wait_for(timeout, || self.replica.leader().as_deref() == Some(target)).await
+            .map_err(|_| anyhow::anyhow!("{} did not take over leadership in time", target))
+    }
+
+    /// Members with their roles and replication progress
+    pub fn members(&self) -> Vec<MemberInfo> {
+        self.replica.members()
+    }
+
+    /// Poll `condition` every heartbeat until it holds or `timeout` passes
+    async fn wait_for(&self, timeout: Duration, condition: impl Fn() -> bool) -> Result<()> {
+        let interval = Duration::from_millis(self.replica.config().heartbeat_interval_ms.max(1));
+        let deadline = tokio::time::Instant::now() + timeout;
+        while !condition() {
+            if tokio::time::Instant::now() >= deadline {
+                anyhow::bail!("Timed out");
+            }
+            tokio::time::sleep(interval).await;
+        }
+        Ok(())
+    }
+
+    /// Hand an incoming message to the event loop
+    pub fn deliver(&self, message: ConsensusMessage) {
+        let _ = self.inbox.send(message);
+    }
+
+    /// Sender feeding this node's event loop
+    pub fn inbox(&self) -> mpsc::UnboundedSender<ConsensusMessage> {
+        self.inbox.clone()
+    }
+
+    /// Consensus state
+    pub fn replica(&self) -> &ReplicaSet {
+        &self.replica
+    }
+
+    /// Local copy of the replicated database
+    pub fn database(&self) -> &Database {
+        &self.db
+    }
+
+    /// Error describing where writes should go instead
+    pub fn not_leader(&self) -> NotLeader {
+        NotLeader {
+            leader_id: self.replica.leader(),
+            leader_addr: self.replica.leader_address(),
+        }
+    }
+
+    /// Stop the event loop; pending proposals fail
+    pub fn shutdown(&self) {
+        self.shutdown.notify_one();
+        self.waiters.lock().clear();
+    }
+}
+
+/// Whether an apply error came from the local store rather than from the
+/// command itself
+///
+/// A rejected command (a missing node, a failed trigger check) leaves the
+/// state untouched and is rejected alike on every replica, so its entry
+/// still counts as applied. A storage failure is local to this replica.
+fn is_storage_failure(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause.is::<std::io::Error>()
+            || cause.is::<redb::Error>()
+            || cause.is::<redb::StorageError>()
+            || cause.is::<redb::TransactionError>()
+            || cause.is::<redb::TableError>()
+            || cause.is::<redb::CommitError>()
+    })
+}
+
+/// In-process network connecting [`RaftNode`]s, for tests and embedding
+///
+/// Links can be cut to simulate partitions.
+#[derive(Default)]
+pub struct InProcessNetwork {
+    nodes: RwLock<HashMap<String, mpsc::UnboundedSender<ConsensusMessage>>>,
+    /// Directed links that currently drop messages
+    blocked: RwLock<HashSet<(String, String)>>,
+}
+
+impl InProcessNetwork {
+    /// Create an empty network
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Transport for the node `id`
+    pub fn transport(self: &Arc<Self>, id: &str) -> Arc<InProcessTransport> {
+        Arc::new(InProcessTransport {
+            from: id.to_string(),
+            network: Arc::clone(self),
+        })
+    }
+
+    /// Route messages for `id` to `inbox`
+    pub fn register(&self, id: &str, inbox: mpsc::UnboundedSender<ConsensusMessage>) {
+        self.nodes.write().insert(id.to_string(), inbox);
+    }
+
+    /// Stop routing messages to `id`
+    pub fn unregister(&self, id: &str) {
+        self.nodes.write().remove(id);
+    }
+
+    /// Drop all traffic between the two groups
+    pub fn partition(&self, a: &[&str], b: &[&str]) {
+        let mut blocked = self.blocked.write();
+        for x in a {
+            for y in b {
+                blocked.insert((x.to_string(), y.to_string()));
+                blocked.insert((y.to_string(), x.to_string()));
+            }
+        }
+    }
+
+    /// Restore all links
+    pub fn heal(&self) {
+        self.blocked.write().clear();
+    }
+
+    fn deliver(&self, from: &str, envelope: Envelope) {
+        if self.blocked.read().contains(&(from.to_string(), envelope.to.clone())) {
+            return;
+        }
+        if let Some(inbox) = self.nodes.read().get(&envelope.to) {
+            let _ = inbox.send(envelope.message);
+        }
+    }
+}
+
+/// One node's view of an [`InProcessNetwork`]
+pub struct InProcessTransport {
+    from: String,
+    network: Arc<InProcessNetwork>,
+}
+
+impl RaftTransport for InProcessTransport {
+    fn send(&self, envelope: Envelope) {
+        self.network.deliver(&self.from, envelope);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distributed::ReplicaConfig;
+    use crate::storage::{Node, Value};
+    use std::collections::VecDeque;
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    fn config(id: &str, ids: &[&str]) -> ReplicaConfig {
+        ReplicaConfig {
+            node_id: id.to_string(),
+            peers: ids.iter().filter(|p| **p != id).map(|p| p.to_string()).collect(),
+            election_timeout_ms: (100, 200),
+            heartbeat_interval_ms: 10,
+            ..Default::default()
+        }
+    }
+
+    /// Deterministic cluster: logical ticks, FIFO delivery, no threads
+    struct Cluster {
+        _temp: TempDir,
+        ids: Vec<String>,
+        /// Members the cluster was bootstrapped with
+        initial: Vec<String>,
+        /// Nodes added later, which start without a membership
+        joiners: HashSet<String>,
+        dirs: HashMap<String, PathBuf>,
+        replicas: HashMap<String, ReplicaSet>,
+        dbs: HashMap<String, Database>,
+        queue: VecDeque<(String, Envelope)>,
+        blocked: HashSet<(String, String)>,
+        down: HashSet<String>,
+        /// Compaction settings as (threshold, chunk size)
+        snapshots: Option<(u64, usize)>,
+        /// InstallSnapshot messages delivered so far
+        snapshot_chunks: usize,
+    }
+
+    impl Cluster {
+        async fn new(ids: &[&str]) -> Self {
+            Self::build(ids, None).await
+        }
+
+        /// Cluster that compacts every `threshold` entries
+        async fn compacting(ids: &[&str], threshold: u64, chunk_size: usize) -> Self {
+            Self::build(ids, Some((threshold, chunk_size))).await
+        }
+
+        async fn build(ids: &[&str], snapshots: Option<(u64, usize)>) -> Self {
+            let temp = TempDir::new().unwrap();
+            let mut cluster = Self {
+                ids: ids.iter().map(|s| s.to_string()).collect(),
+                initial: ids.iter().map(|s| s.to_string()).collect(),
+                joiners: HashSet::new(),
+                dirs: HashMap::new(),
+                replicas: HashMap::new(),
+                dbs: HashMap::new(),
+                queue: VecDeque::new(),
+                blocked: HashSet::new(),
+                down: HashSet::new(),
+                snapshots,
+                snapshot_chunks: 0,
+                _temp: temp,
+            };
+            for id in ids {
+                let dir = cluster._temp.path().join(id);
+                let db = Database::create(&dir, id).await.unwrap();
+                let replica = ReplicaSet::open(cluster.config(id), dir.join(".aresadb/raft")).unwrap();
+                cluster.dirs.insert(id.to_string(), dir);
+                cluster.dbs.insert(id.to_string(), db);
+                cluster.replicas.insert(id.to_string(), replica);
+            }
+            cluster
+        }
+
+        fn config(&self, id: &str) -> ReplicaConfig {
+            let ids: Vec<&str> = self.initial.iter().map(|s| s.as_str()).collect();
+            let mut config = config(id, &ids);
+            config.join = self.joiners.contains(id);
+            if let Some((threshold, chunk_size)) = self.snapshots {
+                config.snapshot_threshold = threshold;
+                config.snapshot_chunk_size = chunk_size;
+            }
+            config
+        }
+
+        fn send(&mut self, from: &str, envelopes: Vec<Envelope>) {
+            for envelope in envelopes {
+                self.queue.push_back((from.to_string(), envelope));
+            }
+        }
+
+        /// Advance every live node one tick, then deliver until quiet
+        async fn tick(&mut self) {
+            for id in self.ids.clone() {
+                if self.down.contains(&id) {
+                    continue;
+                }
+                let out = self.replicas[&id].tick().unwrap();
+                self.send(&id, out);
+            }
+            self.deliver().await;
+        }
+
+        async fn deliver(&mut self) {
+            while let Some((from, envelope)) = self.queue.pop_front() {
+                let to = envelope.to.clone();
+                if self.down.contains(&to)
+                    || !self.replicas.contains_key(&to)
+                    || self.blocked.contains(&(from.clone(), to.clone()))
+                {
+                    continue;
+                }
+                if matches!(envelope.message, ConsensusMessage::InstallSnapshot { .. }) {
+                    self.snapshot_chunks += 1;
+                }
+                let out = self.replicas[&to].step(envelope.message).unwrap();
+                self.send(&to, out);
+            }
+            self.apply().await;
+        }
+
+        async fn apply(&mut self) {
+            for id in &self.ids {
+                if self.down.contains(id) {
+                    continue;
+                }
+                let replica = &self.replicas[id];
+                let storage = self.dbs[id].local();
+                if let Some((meta, data)) = replica.pending_restore().unwrap() {
+                    storage.restore_snapshot(data).await.unwrap();
+                    replica.snapshot_restored(meta.index).unwrap();
+                }
+                for entry in replica.get_unapplied_entries() {
+                    let _ = entry.command.apply(storage).await;
+                    replica.mark_applied(entry.index);
+                }
+                if replica.should_snapshot() {
+                    let mut data = replica.snapshot_writer().unwrap();
+                    storage.snapshot(&mut data).await.unwrap();
+                    replica.compact(replica.last_applied(), data).unwrap();
+                }
+            }
+        }
+
+        async fn run(&mut self, ticks: usize) {
+            for _ in 0..ticks {
+                self.tick().await;
+            }
+        }
+
+        fn leader(&self) -> Option<String> {
+            self.ids.iter()
+                .filter(|id| !self.down.contains(*id) && self.replicas[*id].is_leader())
+                .max_by_key(|id| self.replicas[*id].term())
+                .cloned()
+        }
+
+        async fn write(&mut self, id: &str, n: i64) -> Node {
+            let node = Node::new("item", Value::from_json(serde_json::json!({ "n": n })).unwrap());
+            self.replicas[id].append_command(ReplicationCommand::insert_node(&node).unwrap()).unwrap();
+            let out = self.replicas[id].replicate();
+            self.send(id, out);
+            self.deliver().await;
+            node
+        }
+
+        fn partition(&mut self, a: &[&str], b: &[&str]) {
+            for x in a {
+                for y in b {
+                    self.blocked.insert((x.to_string(), y.to_string()));
+                    self.blocked.insert((y.to_string(), x.to_string()));
+                }
+            }
+        }
+
+        fn stop(&mut self, id: &str) {
+            self.down.insert(id.to_string());
+            self.replicas.remove(id);
+            self.dbs.remove(id);
+        }
+
+        async fn restart(&mut self, id: &str) {
+            let dir = &self.dirs[id];
+            let db = Database::open(dir).await.unwrap();
+            let replica = ReplicaSet::open(self.config(id), dir.join(".aresadb/raft")).unwrap();
+            self.dbs.insert(id.to_string(), db);
+            self.replicas.insert(id.to_string(), replica);
+            self.down.remove(id);
+        }
+
+        /// Start a new, empty node that waits to be added to the cluster
+        async fn add_node(&mut self, id: &str) {
+            self.ids.push(id.to_string());
+            self.joiners.insert(id.to_string());
+            let dir = self._temp.path().join(id);
+            let db = Database::create(&dir, id).await.unwrap();
+            let replica = ReplicaSet::open(self.config(id), dir.join(".aresadb/raft")).unwrap();
+            self.dirs.insert(id.to_string(), dir);
+            self.dbs.insert(id.to_string(), db);
+            self.replicas.insert(id.to_string(), replica);
+        }
+
+        /// Propose a membership change on `leader` and replicate it
+        async fn change(&mut self, leader: &str, change: MembershipChange) {
+            self.replicas[leader].change_membership(change).unwrap();
+            let out = self.replicas[leader].replicate();
+            self.send(leader, out);
+            self.deliver().await;
+        }
+
+        /// Bring `id` back with empty storage, as a replacement machine would
+        async fn replace(&mut self, id: &str) {
+            self.stop(id);
+            let dir = self._temp.path().join(format!("{}-replacement", id));
+            let db = Database::create(&dir, id).await.unwrap();
+            let replica = ReplicaSet::open(self.config(id), dir.join(".aresadb/raft")).unwrap();
+            self.dirs.insert(id.to_string(), dir);
+            self.dbs.insert(id.to_string(), db);
+            self.replicas.insert(id.to_string(), replica);
+            self.down.remove(id);
+        }
+
+        async fn count(&self, id: &str) -> usize {
+            self.dbs[id].get_all_by_type("item", None).await.unwrap().len()
+        }
+    }
+
+    #[test]
+    fn test_storage_failures_are_told_from_rejections() {
+        let rejected = anyhow::anyhow!("Node not found: 42");
+        assert!(!is_storage_failure(&rejected));
+
+        let io = std::io::Error::other("disk full");
+        let failed = anyhow::Error::from(redb::StorageError::Io(io)).context("Failed to insert node");
+        assert!(is_storage_failure(&failed));
+    }
+
+    #[tokio::test]
+    async fn test_cluster_elects_single_leader_and_replicates() {
+        let mut cluster = Cluster::new(&["n1", "n2", "n3"]).await;
+        cluster.run(30).await;
+
+        let leader = cluster.leader().expect("leader elected");
+        let leaders = cluster.ids.iter().filter(|id| cluster.replicas[*id].is_leader()).count();
+        assert_eq!(leaders, 1);
+
+        let node = cluster.write(&leader, 1).await;
+        cluster.run(2).await;
+        for id in ["n1", "n2", "n3"] {
+            let stored = cluster.dbs[id].get_node(&node.id.to_string()).await.unwrap().unwrap();
+            assert_eq!(stored.properties, node.properties, "replica {}", id);
+            assert_eq!(stored.updated_at, node.updated_at, "replica {}", id);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_minority_leader_cannot_commit_after_partition() {
+        let mut cluster = Cluster::new(&["n1", "n2", "n3"]).await;
+        cluster.run(30).await;
+        let old = cluster.leader().unwrap();
+        let others: Vec<String> = cluster.ids.iter().filter(|id| **id != old).cloned().collect();
+        let others: Vec<&str> = others.iter().map(|s| s.as_str()).collect();
+
+        cluster.partition(&[&old], &others);
+        cluster.write(&old, 1).await;
+        cluster.run(40).await;
+
+        // The majority side elects a new leader and keeps accepting writes
+        let new = cluster.ids.iter()
+            .find(|id| **id != old && cluster.replicas[*id].is_leader())
+            .cloned()
+            .expect("majority elects a leader");
+        assert!(cluster.replicas[&new].term() > cluster.replicas[&old].term());
+        cluster.write(&new, 2).await;
+        cluster.run(2).await;
+        assert_eq!(cluster.count(&old).await, 0);
+
+        // After healing the stale write is discarded everywhere
+        cluster.blocked.clear();
+        cluster.run(10).await;
+        assert!(!cluster.replicas[&old].is_leader());
+        for id in ["n1", "n2", "n3"] {
+            assert_eq!(cluster.count(id).await, 1, "replica {}", id);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_restarted_follower_catches_up() {
+        let mut cluster = Cluster::new(&["n1", "n2", "n3"]).await;
+        cluster.run(30).await;
+        let leader = cluster.leader().unwrap();
+        let follower = cluster.ids.iter().find(|id| **id != leader).cloned().unwrap();
+
+        cluster.write(&leader, 1).await;
+        cluster.run(2).await;
+        cluster.stop(&follower);
+
+        cluster.write(&leader, 2).await;
+        cluster.write(&leader, 3).await;
+        cluster.run(2).await;
+
+        cluster.restart(&follower).await;
+        assert_eq!(cluster.count(&follower).await, 1);
+        cluster.run(5).await;
+        assert_eq!(cluster.count(&follower).await, 3);
+        assert_eq!(cluster.replicas[&follower].last_applied(), cluster.replicas[&leader].commit_index());
+    }
+
+    #[tokio::test]
+    async fn test_full_restart_keeps_log_and_term() {
+        let mut cluster = Cluster::new(&["n1", "n2", "n3"]).await;
+        cluster.run(30).await;
+        let leader = cluster.leader().unwrap();
+        cluster.write(&leader, 1).await;
+        cluster.run(2).await;
+        let term = cluster.replicas[&leader].term();
+
+        for id in ["n1", "n2", "n3"] {
+            cluster.stop(id);
+        }
+        for id in ["n1", "n2", "n3"] {
+            cluster.restart(id).await;
+            assert!(cluster.replicas[id].term() >= term);
+        }
+
+        cluster.run(30).await;
+        let leader = cluster.leader().expect("leader after restart");
+        cluster.write(&leader, 2).await;
+        cluster.run(2).await;
+        for id in ["n1", "n2", "n3"] {
+            assert_eq!(cluster.count(id).await, 2, "replica {}", id);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_empty_node_joins_compacted_cluster_via_snapshot() {
+        let mut cluster = Cluster::compacting(&["n1", "n2", "n3"], 8, 256).await;
+        cluster.stop("n3");
+        cluster.run(30).await;
+        let leader = cluster.leader().unwrap();
+
+        for n in 0..30 {
+            cluster.write(&leader, n).await;
+        }
+        cluster.run(2).await;
+        let snapshot_index = cluster.replicas[&leader].snapshot_index();
+        assert!(snapshot_index >= 24, "leader compacted through {}", snapshot_index);
+
+        cluster.replace("n3").await;
+        cluster.run(20).await;
+
+        assert_eq!(cluster.count("n3").await, 30);
+        assert!(cluster.snapshot_chunks > 1, "snapshot sent in chunks");
+        assert_eq!(cluster.replicas["n3"].last_applied(), cluster.replicas[&leader].commit_index());
+        assert!(cluster.replicas["n3"].snapshot_index() >= snapshot_index);
+
+        // Writes after the snapshot keep flowing through the log
+        cluster.write(&leader, 30).await;
+        cluster.run(2).await;
+        assert_eq!(cluster.count("n3").await, 31);
+    }
+
+    #[tokio::test]
+    async fn test_lagging_follower_restores_snapshot_after_restart() {
+        let mut cluster = Cluster::compacting(&["n1", "n2", "n3"], 5, 1024).await;
+        cluster.run(30).await;
+        let leader = cluster.leader().unwrap();
+        let follower = cluster.ids.iter().find(|id| **id != leader).cloned().unwrap();
+
+        cluster.write(&leader, 0).await;
+        cluster.run(2).await;
+        cluster.stop(&follower);
+
+        for n in 1..20 {
+            cluster.write(&leader, n).await;
+        }
+        cluster.run(2).await;
+        assert!(cluster.replicas[&leader].snapshot_index() > 2);
+
+        cluster.restart(&follower).await;
+        cluster.run(10).await;
+        assert_eq!(cluster.count(&follower).await, 20);
+
+        // The installed snapshot survives another restart
+        cluster.stop(&follower);
+        cluster.restart(&follower).await;
+        assert_eq!(cluster.count(&follower).await, 20);
+        assert!(cluster.replicas[&follower].pending_restore().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_learner_catches_up_and_is_promoted() {
+        let mut cluster = Cluster::new(&["n1", "n2", "n3"]).await;
+        cluster.run(30).await;
+        let leader = cluster.leader().unwrap();
+        for n in 0..5 {
+            cluster.write(&leader, n).await;
+        }
+
+        cluster.add_node("n4").await;
+        cluster.run(10).await;
+        // Not a member yet, so it neither campaigns nor receives the log
+        assert!(!cluster.replicas["n4"].is_leader());
+        assert_eq!(cluster.count("n4").await, 0);
+
+        cluster.change(&leader, MembershipChange::AddLearner { id: "n4".into(), address: None, peer_address: None }).await;
+        cluster.run(5).await;
+        assert_eq!(cluster.count("n4").await, 5);
+        assert!(cluster.replicas[&leader].is_caught_up("n4"));
+        assert!(cluster.replicas["n4"].membership().is_learner("n4"));
+
+        // A pending change blocks the next one until it commits
+        cluster.replicas[&leader].change_membership(MembershipChange::Promote { id: "n4".into() }).unwrap();
+        assert!(cluster.replicas[&leader]
+            .change_membership(MembershipChange::Remove { id: "n3".into() })
+            .is_err());
+        let out = cluster.replicas[&leader].replicate();
+        cluster.send(&leader, out);
+        cluster.deliver().await;
+        cluster.run(2).await;
+
+        for id in ["n1", "n2", "n3", "n4"] {
+            let membership = cluster.replicas[id].membership();
+            assert_eq!(membership.voters.len(), 4, "replica {}", id);
+            assert!(membership.learners.is_empty());
+        }
+
+        // With four voters a write needs three acknowledgements
+        let others: Vec<String> = cluster.ids.iter().filter(|id| **id != leader).cloned().collect();
+        cluster.stop(&others[0]);
+        cluster.stop(&others[1]);
+        cluster.write(&leader, 5).await;
+        cluster.run(5).await;
+        assert_eq!(cluster.count(&leader).await, 5);
+    }
+
+    #[tokio::test]
+    async fn test_learner_does_not_count_towards_commit() {
+        let mut cluster = Cluster::new(&["n1", "n2", "n3"]).await;
+        cluster.run(30).await;
+        let leader = cluster.leader().unwrap();
+        cluster.add_node("n4").await;
+        cluster.change(&leader, MembershipChange::AddLearner { id: "n4".into(), address: None, peer_address: None }).await;
+        cluster.run(2).await;
+
+        for id in ["n1", "n2", "n3"] {
+            if *id != leader {
+                cluster.stop(id);
+            }
+        }
+        cluster.write(&leader, 1).await;
+        cluster.run(5).await;
+        assert!(cluster.replicas["n4"].last_log_index() > cluster.replicas[&leader].commit_index());
+        assert_eq!(cluster.count(&leader).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_removed_leader_steps_down_and_cluster_continues() {
+        let mut cluster = Cluster::new(&["n1", "n2", "n3"]).await;
+        cluster.run(30).await;
+        let old = cluster.leader().unwrap();
+        cluster.write(&old, 1).await;
+
+        cluster.change(&old, MembershipChange::Remove { id: old.clone() }).await;
+        cluster.run(2).await;
+        assert!(!cluster.replicas[&old].is_leader());
+
+        cluster.run(40).await;
+        let new = cluster.leader().expect("remaining voters elect a leader");
+        assert_ne!(new, old);
+        let term = cluster.replicas[&new].term();
+
+        // The removed node never campaigns and is no longer replicated to
+        cluster.run(40).await;
+        assert_eq!(cluster.leader().as_deref(), Some(new.as_str()));
+        assert_eq!(cluster.replicas[&new].term(), term);
+        cluster.write(&new, 2).await;
+        cluster.run(2).await;
+        assert_eq!(cluster.count(&new).await, 2);
+        assert_eq!(cluster.count(&old).await, 1);
+        assert!(!cluster.replicas[&new].membership().contains(&old));
+    }
+
+    #[tokio::test]
+    async fn test_leadership_transfer() {
+        let mut cluster = Cluster::new(&["n1", "n2", "n3"]).await;
+        cluster.run(30).await;
+        let old = cluster.leader().unwrap();
+        let target = cluster.ids.iter().find(|id| **id != old).cloned().unwrap();
+        cluster.write(&old, 1).await;
+
+        let out = cluster.replicas[&old].transfer_leadership(&target).unwrap();
+        let node = Node::new("item", Value::from_json(serde_json::json!({ "n": 2 })).unwrap());
+        assert!(cluster.replicas[&old].append_command(ReplicationCommand::insert_node(&node).unwrap()).is_err());
+        cluster.send(&old, out);
+        cluster.deliver().await;
+
+        assert!(cluster.replicas[&target].is_leader());
+        assert!(!cluster.replicas[&old].is_leader());
+        cluster.run(3).await;
+        cluster.write(&target, 2).await;
+        cluster.run(2).await;
+        for id in ["n1", "n2", "n3"] {
+            assert_eq!(cluster.count(id).await, 2, "replica {}", id);
+        }
+        assert!(cluster.replicas[&old].transfer_leadership("n9").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_membership_survives_compaction_and_restart() {
+        let mut cluster = Cluster::compacting(&["n1", "n2", "n3"], 4, 512).await;
+        cluster.run(30).await;
+        let leader = cluster.leader().unwrap();
+        cluster.add_node("n4").await;
+        cluster.change(&leader, MembershipChange::AddLearner { id: "n4".into(), address: Some("10.0.0.4:7432".into()), peer_address: None }).await;
+        cluster.run(2).await;
+        cluster.change(&leader, MembershipChange::Promote { id: "n4".into() }).await;
+        for n in 0..12 {
+            cluster.write(&leader, n).await;
+        }
+        cluster.run(2).await;
+        assert!(cluster.replicas[&leader].snapshot_index() > 4);
+
+        // A node joining now learns the membership from the snapshot
+        cluster.add_node("n5").await;
+        cluster.change(&leader, MembershipChange::AddLearner { id: "n5".into(), address: None, peer_address: None }).await;
+        cluster.run(10).await;
+        assert_eq!(cluster.count("n5").await, 12);
+        let membership = cluster.replicas["n5"].membership();
+        assert!(membership.is_voter("n4"));
+        assert!(membership.is_learner("n5"));
+        assert_eq!(membership.addresses.get("n4").map(String::as_str), Some("10.0.0.4:7432"));
+
+        for id in ["n1", "n2", "n3", "n4"] {
+            cluster.stop(id);
+            cluster.restart(id).await;
+            assert!(cluster.replicas[id].membership().is_voter("n4"), "replica {}", id);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_raft_nodes_over_in_process_network() {
+        let temp = TempDir::new().unwrap();
+        let ids = ["a", "b", "c"];
+        let network = InProcessNetwork::new();
+        let mut nodes = Vec::new();
+
+        for id in ids {
+            let dir = temp.path().join(id);
+            let db = Database::create(&dir, id).await.unwrap();
+            let replica = ReplicaSet::open(config(id, &ids), dir.join(".aresadb/raft")).unwrap();
+            let node = RaftNode::start(replica, db, network.transport(id));
+            network.register(id, node.inbox());
+            nodes.push(node);
+        }
+
+        let mut leader = None;
+        for _ in 0..200 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            if let Some(node) = nodes.iter().find(|n| n.replica().is_leader()) {
+                leader = Some(Arc::clone(node));
+                break;
+            }
+        }
+        let leader = leader.expect("leader elected");
+
+        let follower = nodes.iter().find(|n| !n.replica().is_leader()).unwrap();
+        let node = Node::new("item", Value::from_json(serde_json::json!({ "n": 1 })).unwrap());
+        let err = follower.propose(ReplicationCommand::insert_node(&node).unwrap()).await.unwrap_err();
+        assert!(err.downcast_ref::<NotLeader>().is_some());
+
+        leader.propose(ReplicationCommand::insert_node(&node).unwrap()).await.unwrap();
+        assert!(leader.database().get_node(&node.id.to_string()).await.unwrap().is_some());
+
+        for _ in 0..100 {
+            let mut replicated = 0;
+            for n in &nodes {
+                if n.database().get_node(&node.id.to_string()).await.unwrap().is_some() {
+                    replicated += 1;
+                }
+            }
+            if replicated == nodes.len() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        for n in &nodes {
+            assert!(n.database().get_node(&node.id.to_string()).await.unwrap().is_some());
+            n.shutdown();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_raft_nodes_compact_and_restore_off_the_event_loop() {
+        async fn start(dir: &std::path::Path, id: &str, ids: &[&str], network: &Arc<InProcessNetwork>) -> Arc<RaftNode> {
+            let dir = dir.join(id);
+            let db = Database::create(&dir, id).await.unwrap();
+            let mut config = config(id, ids);
+            config.snapshot_threshold = 5;
+            config.snapshot_chunk_size = 256;
+            let replica = ReplicaSet::open(config, dir.join(".aresadb/raft")).unwrap();
+            let node = RaftNode::start(replica, db, network.transport(id));
+            network.register(id, node.inbox());
+            node
+        }
+
+        let temp = TempDir::new().unwrap();
+        let ids = ["a", "b", "c"];
+        let network = InProcessNetwork::new();
+        let nodes = vec![
+            start(temp.path(), "a", &ids, &network).await,
+            start(temp.path(), "b", &ids, &network).await,
+        ];
+
+        let mut leader = None;
+        for _ in 0..200 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            if let Some(node) = nodes.iter().find(|n| n.replica().is_leader()) {
+                leader = Some(Arc::clone(node));
+                break;
+            }
+        }
+        let leader = leader.expect("leader elected");
+        for n in 0..20 {
+            let node = Node::new("item", Value::from_json(serde_json::json!({ "n": n })).unwrap());
+            leader.propose(ReplicationCommand::insert_node(&node).unwrap()).await.unwrap();
+        }
+        for _ in 0..200 {
+            if leader.replica().snapshot_index() >= 5 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(leader.replica().snapshot_index() >= 5);
+
+        // The missing node catches up from the leader's snapshot
+        let late = start(temp.path(), "c", &ids, &network).await;
+        let mut count = 0;
+        for _ in 0..300 {
+            count = late.database().get_all_by_type("item", None).await.unwrap().len();
+            if count == 20 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(count, 20);
+        assert!(late.replica().snapshot_index() >= 5);
+
+        late.shutdown();
+        for n in nodes {
+            n.shutdown();
+        }
+    }
+}
@@ -0,0 +1,654 @@
+//! Persistent Raft Log
+//!
+//! Stores log entries and the term/vote hard state that Raft requires to
+//! survive restarts. Entries are cached in memory; when backed by a file,
+//! every change is committed to a redb database before it is acknowledged.
+//!
+//! Entries covered by a state machine snapshot are dropped; the log then
+//! starts right after the snapshot's index and keeps the snapshot itself so
+//! it can be sent to replicas that fall too far behind. Persistent logs
+//! keep snapshots as files beside the log, written and read in pieces, so
+//! a snapshot never has to fit in memory.
+
+use anyhow::{Result, Context};
+use redb::{Database as RedbDatabase, ReadableTable, TableDefinition};
+use serde::{Serialize, Deserialize};
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use super::membership::Membership;
+use super::replication::LogEntry;
+
+const LOG_TABLE: TableDefinition<u64, &[u8]> = TableDefinition::new("raft_log");
+const STATE_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("raft_state");
+
+const HARD_STATE_KEY: &str = "hard_state";
+const APPLIED_KEY: &str = "applied";
+const SNAPSHOT_META_KEY: &str = "snapshot_meta";
+const SNAPSHOT_MEMBERSHIP_KEY: &str = "snapshot_membership";
+
+/// Raft state that must be durable before replying to peers
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HardState {
+    /// Latest term this node has seen
+    pub term: u64,
+    /// Candidate voted for in `term`
+    pub voted_for: Option<String>,
+}
+
+/// Last log position covered by a snapshot
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotMeta {
+    /// Index of the last entry included
+    pub index: u64,
+    /// Term of that entry
+    pub term: u64,
+}
+
+/// Replicated log with optional on-disk persistence
+pub struct RaftLog {
+    /// Entries in index order; `entries[0]` has index `snapshot.index + 1`
+    entries: Vec<LogEntry>,
+    /// Position covered by the latest snapshot
+    snapshot: SnapshotMeta,
+    /// Serialized state machine at `snapshot`
+    snapshot_data: Option<SnapshotData>,
+    /// Size of `snapshot_data` in bytes
+    snapshot_len: u64,
+    /// Where snapshot files live (None for in-memory logs)
+    snapshot_dir: Option<PathBuf>,
+    /// Number of the next staging file in `snapshot_dir`
+    next_staging: u64,
+    /// Membership in effect at `snapshot`, if it was ever changed
+    snapshot_membership: Option<Membership>,
+    /// Persisted term/vote
+    hard_state: HardState,
+    /// Highest index applied to the state machine
+    applied: u64,
+    /// Backing database (None for in-memory logs)
+    db: Option<RedbDatabase>,
+}
+
+impl RaftLog {
+    /// Create a log that lives only in memory
+    pub fn memory() -> Self {
+        Self {
+            entries: Vec::new(),
+            snapshot: SnapshotMeta::default(),
+            snapshot_data: None,
+            snapshot_len: 0,
+            snapshot_dir: None,
+            next_staging: 0,
+            snapshot_membership: None,
+            hard_state: HardState::default(),
+            applied: 0,
+            db: None,
+        }
+    }
+
+    /// Open (or create) a persistent log at `path`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let db = RedbDatabase::create(path)
+            .with_context(|| format!("Failed to open raft log at {}", path.display()))?;
+
+        {
+            let write_txn = db.begin_write()?;
+            let _ = write_txn.open_table(LOG_TABLE)?;
+            let _ = write_txn.open_table(STATE_TABLE)?;
+            write_txn.commit()?;
+        }
+
+        let (entries, hard_state, applied, snapshot, snapshot_membership) = {
+            let read_txn = db.begin_read()?;
+
+            let log_table = read_txn.open_table(LOG_TABLE)?;
+            let mut entries = Vec::new();
+            for item in log_table.iter()? {
+                let (_, value) = item?;
+                let entry: LogEntry = bincode::deserialize(value.value())
+                    .context("Corrupt raft log entry")?;
+                entries.push(entry);
+            }
+
+            let state_table = read_txn.open_table(STATE_TABLE)?;
+            let hard_state = match state_table.get(HARD_STATE_KEY)? {
+                Some(value) => bincode::deserialize(value.value()).context("Corrupt raft hard state")?,
+                None => HardState::default(),
+            };
+            let applied = match state_table.get(APPLIED_KEY)? {
+                Some(value) => bincode::deserialize(value.value()).context("Corrupt raft applied index")?,
+                None => 0,
+            };
+            let snapshot = match state_table.get(SNAPSHOT_META_KEY)? {
+                Some(value) => bincode::deserialize(value.value()).context("Corrupt raft snapshot metadata")?,
+                None => SnapshotMeta::default(),
+            };
+            let snapshot_membership = match state_table.get(SNAPSHOT_MEMBERSHIP_KEY)? {
+                Some(value) => Some(bincode::deserialize(value.value()).context("Corrupt raft snapshot membership")?),
+                None => None,
+            };
+
+            (entries, hard_state, applied, snapshot, snapshot_membership)
+        };
+
+        // Anything but the current snapshot is left over from staging or
+        // from an install interrupted before its metadata was committed
+        let snapshot_dir = path.with_extension("snapshots");
+        std::fs::create_dir_all(&snapshot_dir)?;
+        let current = (snapshot.index > 0).then(|| snapshot_dir.join(snapshot_file_name(snapshot)));
+        for item in std::fs::read_dir(&snapshot_dir)? {
+            let file = item?.path();
+            if Some(&file) != current.as_ref() {
+                std::fs::remove_file(&file)?;
+            }
+        }
+        let (snapshot_data, snapshot_len) = match current {
+            Some(file) => {
+                let len = std::fs::metadata(&file)
+                    .with_context(|| format!("Missing raft snapshot {}", file.display()))?
+                    .len();
+                (Some(SnapshotData::File(file)), len)
+            }
+            None => (None, 0),
+        };
+
+        Ok(Self {
+            entries,
+            snapshot,
+            snapshot_data,
+            snapshot_len,
+            snapshot_dir: Some(snapshot_dir),
+            next_staging: 0,
+            snapshot_membership,
+            hard_state,
+            applied,
+            db: Some(db),
+        })
+    }
+
+    /// Whether the log is backed by a file
+    pub fn is_persistent(&self) -> bool {
+        self.db.is_some()
+    }
+
+    /// Persisted term/vote
+    pub fn hard_state(&self) -> &HardState {
+        &self.hard_state
+    }
+
+    /// Durably record the term and vote
+    pub fn save_hard_state(&mut self, state: HardState) -> Result<()> {
+        if state == self.hard_state {
+            return Ok(());
+        }
+        self.put_state(HARD_STATE_KEY, &state)?;
+        self.hard_state = state;
+        Ok(())
+    }
+
+    /// Highest index applied to the state machine
+    pub fn applied(&self) -> u64 {
+        self.applied
+    }
+
+    /// Record that entries up to `index` were applied
+    pub fn save_applied(&mut self, index: u64) -> Result<()> {
+        if index <= self.applied {
+            return Ok(());
+        }
+        self.put_state(APPLIED_KEY, &index)?;
+        self.applied = index;
+        Ok(())
+    }
+
+    /// Position covered by the latest snapshot
+    pub fn snapshot_meta(&self) -> SnapshotMeta {
+        self.snapshot
+    }
+
+    /// Size in bytes of the snapshot at [`snapshot_meta`](Self::snapshot_meta),
+    /// 0 if there is none
+    pub fn snapshot_len(&self) -> u64 {
+        self.snapshot_len
+    }
+
+    /// Up to `max` bytes of the snapshot starting at `offset`
+    pub fn read_snapshot(&self, offset: u64, max: usize) -> Result<Vec<u8>> {
+        let mut chunk = Vec::new();
+        match self.snapshot_data {
+            Some(SnapshotData::Memory(ref data)) => {
+                let start = (offset as usize).min(data.len());
+                let end = start.saturating_add(max).min(data.len());
+                chunk.extend_from_slice(&data[start..end]);
+            }
+            Some(SnapshotData::File(ref path)) => {
+                let mut file = File::open(path)
+                    .with_context(|| format!("Failed to open raft snapshot {}", path.display()))?;
+                file.seek(SeekFrom::Start(offset))?;
+                file.take(max as u64).read_to_end(&mut chunk)?;
+            }
+            None => {}
+        }
+        Ok(chunk)
+    }
+
+    /// Reader over the whole snapshot, for restoring the state machine
+    ///
+    /// A file snapshot stays readable through the reader even if a newer
+    /// one replaces it meanwhile.
+    pub fn open_snapshot(&self) -> Result<Box<dyn Read + Send>> {
+        Ok(match self.snapshot_data {
+            Some(SnapshotData::Memory(ref data)) => Box::new(io::Cursor::new(data.clone())),
+            Some(SnapshotData::File(ref path)) => Box::new(io::BufReader::new(
+                File::open(path).with_context(|| format!("Failed to open raft snapshot {}", path.display()))?,
+            )),
+            None => Box::new(io::empty()),
+        })
+    }
+
+    /// Somewhere to write a new snapshot before passing it to
+    /// [`install_snapshot`](Self::install_snapshot)
+    ///
+    /// Persistent logs stage it in a file, removed again if the writer is
+    /// dropped without being installed.
+    pub fn snapshot_writer(&mut self) -> Result<SnapshotWriter> {
+        let target = match self.snapshot_dir {
+            Some(ref dir) => {
+                self.next_staging += 1;
+                let path = dir.join(format!("staging-{}", self.next_staging));
+                let file = File::create(&path)
+                    .with_context(|| format!("Failed to create raft snapshot {}", path.display()))?;
+                Staging::File(BufWriter::new(file), Some(path))
+            }
+            None => Staging::Memory(Vec::new()),
+        };
+        Ok(SnapshotWriter { target, len: 0 })
+    }
+
+    /// Membership in effect at [`snapshot_meta`](Self::snapshot_meta)
+    pub fn snapshot_membership(&self) -> Option<&Membership> {
+        self.snapshot_membership.as_ref()
+    }
+
+    /// Index of the first entry still in the log
+    pub fn first_index(&self) -> u64 {
+        self.snapshot.index + 1
+    }
+
+    /// Index of the last entry (0 when empty)
+    pub fn last_index(&self) -> u64 {
+        self.snapshot.index + self.entries.len() as u64
+    }
+
+    /// Term of the last entry (0 when empty)
+    pub fn last_term(&self) -> u64 {
+        self.entries.last().map(|e| e.term).unwrap_or(self.snapshot.term)
+    }
+
+    /// Term of the entry at `index`
+    ///
+    /// Known for the snapshot's last entry and anything after it; index 0
+    /// has term 0.
+    pub fn term_at(&self, index: u64) -> Option<u64> {
+        if index == self.snapshot.index {
+            return Some(self.snapshot.term);
+        }
+        self.get(index).map(|e| e.term)
+    }
+
+    /// Entry at `index`, unless it was compacted away
+    pub fn get(&self, index: u64) -> Option<&LogEntry> {
+        if index <= self.snapshot.index {
+            return None;
+        }
+        self.entries.get((index - self.snapshot.index) as usize - 1)
+    }
+
+    /// Up to `max` entries starting at `index`
+    pub fn entries_from(&self, index: u64, max: usize) -> Vec<LogEntry> {
+        let start = (index.max(self.first_index()) - self.first_index()) as usize;
+        self.entries.iter().skip(start).take(max).cloned().collect()
+    }
+
+    /// Entries in `(after, up_to]` that are still in the log
+    pub fn range(&self, after: u64, up_to: u64) -> Vec<LogEntry> {
+        let after = after.max(self.snapshot.index);
+        let up_to = up_to.min(self.last_index());
+        if up_to <= after {
+            return Vec::new();
+        }
+        let base = self.snapshot.index;
+        self.entries[(after - base) as usize..(up_to - base) as usize].to_vec()
+    }
+
+    /// Append entries that directly follow the current last index
+    pub fn append(&mut self, entries: &[LogEntry]) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        for (offset, entry) in entries.iter().enumerate() {
+            let expected = self.last_index() + 1 + offset as u64;
+            if entry.index != expected {
+                anyhow::bail!("Raft log gap: expected index {}, got {}", expected, entry.index);
+            }
+        }
+
+        if let Some(ref db) = self.db {
+            let write_txn = db.begin_write()?;
+            {
+                let mut table = write_txn.open_table(LOG_TABLE)?;
+                for entry in entries {
+                    table.insert(entry.index, bincode::serialize(entry)?.as_slice())?;
+                }
+            }
+            write_txn.commit()?;
+        }
+
+        self.entries.extend_from_slice(entries);
+        Ok(())
+    }
+
+    /// Remove the entry at `index` and everything after it
+    pub fn truncate_from(&mut self, index: u64) -> Result<()> {
+        if index <= self.snapshot.index {
+            anyhow::bail!("Cannot truncate entry {} covered by a snapshot", index);
+        }
+        if index > self.last_index() {
+            return Ok(());
+        }
+
+        if let Some(ref db) = self.db {
+            let write_txn = db.begin_write()?;
+            {
+                let mut table = write_txn.open_table(LOG_TABLE)?;
+                for i in index..=self.last_index() {
+                    table.remove(i)?;
+                }
+            }
+            write_txn.commit()?;
+        }
+
+        self.entries.truncate((index - self.first_index()) as usize);
+        Ok(())
+    }
+
+    /// Replace everything up to `meta.index` with a snapshot
+    ///
+    /// Entries after the snapshot are kept only if the log agrees with it
+    /// at `meta.index`; otherwise the whole log is discarded.
+    ///
+    /// The snapshot file is moved into place before the metadata naming it
+    /// is committed; a crash in between leaves a stray file that the next
+    /// [`open`](Self::open) removes.
+    pub fn install_snapshot(
+        &mut self,
+        meta: SnapshotMeta,
+        membership: Option<Membership>,
+        mut data: SnapshotWriter,
+    ) -> Result<()> {
+        if meta.index < self.snapshot.index {
+            anyhow::bail!("Snapshot at {} is older than the current one at {}", meta.index, self.snapshot.index);
+        }
+
+        let len = data.len;
+        let installed = match data.target {
+            Staging::Memory(ref mut buffer) => SnapshotData::Memory(std::mem::take(buffer)),
+            Staging::File(ref mut file, ref mut staged) => {
+                file.flush()?;
+                file.get_ref().sync_all()?;
+                let dir = self.snapshot_dir.as_ref().context("Raft log has no snapshot directory")?;
+                let path = dir.join(snapshot_file_name(meta));
+                let staged = staged.take().expect("staging file present until installed");
+                std::fs::rename(&staged, &path)
+                    .with_context(|| format!("Failed to install raft snapshot {}", path.display()))?;
+                SnapshotData::File(path)
+            }
+        };
+
+        let keep_suffix = self.term_at(meta.index) == Some(meta.term);
+        let remove_up_to = if keep_suffix { meta.index } else { self.last_index() };
+
+        if let Some(ref db) = self.db {
+            let write_txn = db.begin_write()?;
+            {
+                let mut table = write_txn.open_table(LOG_TABLE)?;
+                for i in self.first_index()..=remove_up_to {
+                    table.remove(i)?;
+                }
+                let mut state = write_txn.open_table(STATE_TABLE)?;
+                state.insert(SNAPSHOT_META_KEY, bincode::serialize(&meta)?.as_slice())?;
+                match membership {
+                    Some(ref m) => {
+                        state.insert(SNAPSHOT_MEMBERSHIP_KEY, bincode::serialize(m)?.as_slice())?;
+                    }
+                    None => {
+                        state.remove(SNAPSHOT_MEMBERSHIP_KEY)?;
+                    }
+                }
+            }
+            write_txn.commit()?;
+        }
+
+        if keep_suffix {
+            let drop = (meta.index - self.snapshot.index) as usize;
+            self.entries.drain(..drop.min(self.entries.len()));
+        } else {
+            self.entries.clear();
+        }
+        self.snapshot = meta;
+        let replaced = self.snapshot_data.replace(installed);
+        self.snapshot_len = len;
+        self.snapshot_membership = membership;
+
+        // Reinstalling at the same position overwrote the file in place
+        if let Some(SnapshotData::File(old)) = replaced {
+            if !matches!(self.snapshot_data, Some(SnapshotData::File(ref path)) if *path == old) {
+                if let Err(e) = std::fs::remove_file(&old) {
+                    tracing::warn!("Failed to remove old raft snapshot {}: {}", old.display(), e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn put_state<T: Serialize>(&self, key: &str, value: &T) -> Result<()> {
+        if let Some(ref db) = self.db {
+            let write_txn = db.begin_write()?;
+            {
+                let mut table = write_txn.open_table(STATE_TABLE)?;
+                table.insert(key, bincode::serialize(value)?.as_slice())?;
+            }
+            write_txn.commit()?;
+        }
+        Ok(())
+    }
+}
+
+/// Name of the file holding the snapshot at `meta`
+fn snapshot_file_name(meta: SnapshotMeta) -> String {
+    format!("{:020}-{}.snap", meta.index, meta.term)
+}
+
+/// Where a log keeps its snapshot
+enum SnapshotData {
+    /// In memory, for in-memory logs
+    Memory(Vec<u8>),
+    /// In a file under the log's snapshot directory
+    File(PathBuf),
+}
+
+/// A snapshot being written, from [`RaftLog::snapshot_writer`]
+pub struct SnapshotWriter {
+    target: Staging,
+    /// Bytes written so far
+    len: u64,
+}
+
+enum Staging {
+    Memory(Vec<u8>),
+    /// Buffered file and its path, taken once the file is installed
+    File(BufWriter<File>, Option<PathBuf>),
+}
+
+impl SnapshotWriter {
+    /// Bytes written so far
+    pub fn written(&self) -> u64 {
+        self.len
+    }
+}
+
+impl Write for SnapshotWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = match self.target {
+            Staging::Memory(ref mut buffer) => buffer.write(buf)?,
+            Staging::File(ref mut file, _) => file.write(buf)?,
+        };
+        self.len += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.target {
+            Staging::Memory(_) => Ok(()),
+            Staging::File(ref mut file, _) => file.flush(),
+        }
+    }
+}
+
+impl Drop for SnapshotWriter {
+    fn drop(&mut self) {
+        if let Staging::File(_, Some(ref path)) = self.target {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distributed::replication::ReplicationCommand;
+    use tempfile::TempDir;
+
+    fn entry(term: u64, index: u64) -> LogEntry {
+        LogEntry { term, index, command: ReplicationCommand::Nop }
+    }
+
+    fn staged(log: &mut RaftLog, data: &[u8]) -> SnapshotWriter {
+        let mut writer = log.snapshot_writer().unwrap();
+        writer.write_all(data).unwrap();
+        writer
+    }
+
+    fn snapshot_bytes(log: &RaftLog) -> Vec<u8> {
+        let mut data = Vec::new();
+        log.open_snapshot().unwrap().read_to_end(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_log_survives_reopen() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("raft.redb");
+
+        {
+            let mut log = RaftLog::open(&path).unwrap();
+            log.append(&[entry(1, 1), entry(1, 2), entry(2, 3)]).unwrap();
+            log.save_hard_state(HardState { term: 2, voted_for: Some("n1".to_string()) }).unwrap();
+            log.save_applied(2).unwrap();
+            log.truncate_from(3).unwrap();
+        }
+
+        let log = RaftLog::open(&path).unwrap();
+        assert_eq!(log.last_index(), 2);
+        assert_eq!(log.last_term(), 1);
+        assert_eq!(log.hard_state().term, 2);
+        assert_eq!(log.hard_state().voted_for.as_deref(), Some("n1"));
+        assert_eq!(log.applied(), 2);
+    }
+
+    #[test]
+    fn test_compaction_keeps_suffix_and_persists() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("raft.redb");
+
+        {
+            let mut log = RaftLog::open(&path).unwrap();
+            log.append(&[entry(1, 1), entry(1, 2), entry(2, 3), entry(2, 4)]).unwrap();
+            let data = staged(&mut log, b"state");
+            log.install_snapshot(SnapshotMeta { index: 2, term: 1 }, None, data).unwrap();
+
+            assert_eq!(log.first_index(), 3);
+            assert_eq!(log.last_index(), 4);
+            assert_eq!(log.term_at(2), Some(1));
+            assert_eq!(log.term_at(1), None);
+            assert!(log.get(2).is_none());
+            assert_eq!(log.range(0, 4).len(), 2);
+            assert!(log.truncate_from(2).is_err());
+        }
+
+        let mut log = RaftLog::open(&path).unwrap();
+        assert_eq!(log.snapshot_meta(), SnapshotMeta { index: 2, term: 1 });
+        assert_eq!(snapshot_bytes(&log), b"state");
+        assert_eq!(log.snapshot_len(), 5);
+        assert_eq!(log.read_snapshot(1, 3).unwrap(), b"tat");
+        assert_eq!(log.read_snapshot(4, 10).unwrap(), b"e");
+        assert_eq!(log.last_index(), 4);
+        assert_eq!(log.entries_from(1, 10).len(), 2);
+
+        // A snapshot that disagrees with the log replaces it entirely
+        let data = staged(&mut log, b"newer");
+        log.install_snapshot(SnapshotMeta { index: 3, term: 5 }, None, data).unwrap();
+        assert_eq!(log.last_index(), 3);
+        assert_eq!(log.last_term(), 5);
+        log.append(&[entry(5, 4)]).unwrap();
+        assert_eq!(snapshot_bytes(&log), b"newer");
+    }
+
+    #[test]
+    fn test_snapshot_files_are_cleaned_up() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("raft.redb");
+        let dir = temp.path().join("raft.snapshots");
+        let files = || std::fs::read_dir(&dir).unwrap().count();
+
+        {
+            let mut log = RaftLog::open(&path).unwrap();
+            log.append(&[entry(1, 1), entry(1, 2)]).unwrap();
+
+            // A writer dropped without installing leaves nothing behind
+            drop(staged(&mut log, b"abandoned"));
+            assert_eq!(files(), 0);
+
+            let data = staged(&mut log, b"first");
+            log.install_snapshot(SnapshotMeta { index: 1, term: 1 }, None, data).unwrap();
+            let data = staged(&mut log, b"second");
+            log.install_snapshot(SnapshotMeta { index: 2, term: 1 }, None, data).unwrap();
+            assert_eq!(files(), 1);
+
+            // As if the process stopped while staging
+            std::mem::forget(staged(&mut log, b"partial"));
+            assert_eq!(files(), 2);
+        }
+
+        let log = RaftLog::open(&path).unwrap();
+        assert_eq!(files(), 1);
+        assert_eq!(snapshot_bytes(&log), b"second");
+    }
+
+    #[test]
+    fn test_append_rejects_gaps() {
+        let mut log = RaftLog::memory();
+        log.append(&[entry(1, 1)]).unwrap();
+        assert!(log.append(&[entry(1, 3)]).is_err());
+        assert_eq!(log.term_at(0), Some(0));
+        assert_eq!(log.term_at(1), Some(1));
+        assert_eq!(log.term_at(2), None);
+        assert_eq!(log.range(0, 5).len(), 1);
+    }
+}
@@ -2,14 +2,37 @@
 //!
 //! Implements leader election and data replication across multiple nodes.
 //! Uses a Raft-like consensus protocol for consistency.
+//!
+//! [`ReplicaSet`] is a pure state machine: it is driven by [`ReplicaSet::tick`]
+//! and [`ReplicaSet::step`] and returns the messages to send, leaving
+//! transport and timing to the caller (see [`super::RaftNode`]).
+//!
+//! Once the applied state machine is snapshotted via [`ReplicaSet::compact`],
+//! the covered log prefix is dropped. Peers that need entries older than the
+//! snapshot receive it in chunks through `InstallSnapshot` instead.
+//!
+//! The replicas taking part are tracked as a replicated [`Membership`]
+//! rather than the static `peers` list, which only seeds the initial
+//! configuration.
 
 use anyhow::{Result, bail};
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use serde::{Serialize, Deserialize};
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use std::path::Path;
 use std::time::{Duration, Instant};
-use tokio::sync::mpsc;
+
+use super::membership::{MemberInfo, MemberRole, Membership, MembershipChange};
+use super::raft_log::{HardState, RaftLog, SnapshotMeta, SnapshotWriter};
+use crate::storage::{Edge, EdgeId, LocalStorage, Node, NodeId, Timestamp, Value};
+
+/// Maximum number of entries sent in one AppendEntries message
+const MAX_APPEND_ENTRIES: usize = 256;
+
+/// File name of the persistent log inside a replica's directory
+const RAFT_LOG_FILE: &str = "raft.redb";
 
 /// Replica state
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -27,12 +50,36 @@ pub enum ReplicaState {
 pub struct ReplicaConfig {
     /// Unique node ID
     pub node_id: String,
-    /// List of peer addresses
+    /// IDs of the other nodes in the initial cluster
     pub peers: Vec<String>,
     /// Election timeout range in milliseconds
     pub election_timeout_ms: (u64, u64),
-    /// Heartbeat interval in milliseconds
+    /// Heartbeat interval in milliseconds (one tick)
     pub heartbeat_interval_ms: u64,
+    /// Client-facing address of each node, used to redirect writes
+    #[serde(default)]
+    pub addresses: HashMap<String, String>,
+    /// Address of each node's peer listener, where consensus messages go
+    #[serde(default)]
+    pub peer_addresses: HashMap<String, String>,
+    /// Applied entries kept in the log before it is compacted into a snapshot
+    #[serde(default = "default_snapshot_threshold")]
+    pub snapshot_threshold: u64,
+    /// Bytes of snapshot data sent per InstallSnapshot message
+    #[serde(default = "default_snapshot_chunk_size")]
+    pub snapshot_chunk_size: usize,
+    /// Join an existing cluster: wait to be added by its leader instead of
+    /// forming a cluster with `peers`
+    #[serde(default)]
+    pub join: bool,
+}
+
+fn default_snapshot_threshold() -> u64 {
+    10_000
+}
+
+fn default_snapshot_chunk_size() -> usize {
+    1024 * 1024
 }
 
 impl Default for ReplicaConfig {
@@ -42,10 +89,44 @@ impl Default for ReplicaConfig {
             peers: Vec::new(),
             election_timeout_ms: (150, 300),
             heartbeat_interval_ms: 50,
+            addresses: HashMap::new(),
+            peer_addresses: HashMap::new(),
+            snapshot_threshold: default_snapshot_threshold(),
+            snapshot_chunk_size: default_snapshot_chunk_size(),
+            join: false,
         }
     }
 }
 
+impl ReplicaConfig {
+    /// Election timeout bounds in ticks
+    fn election_ticks(&self) -> (u64, u64) {
+        let interval = self.heartbeat_interval_ms.max(1);
+        let low = (self.election_timeout_ms.0 / interval).max(2);
+        let high = (self.election_timeout_ms.1 / interval).max(low + 1);
+        (low, high)
+    }
+
+    /// Membership before any change is committed
+    fn initial_membership(&self) -> Membership {
+        if self.join {
+            return Membership::default();
+        }
+        let mut membership = Membership::new(
+            std::iter::once(self.node_id.clone()).chain(self.peers.iter().cloned()),
+        );
+        membership.addresses = self.addresses.iter()
+            .filter(|(id, _)| membership.contains(id))
+            .map(|(id, addr)| (id.clone(), addr.clone()))
+            .collect();
+        membership.peer_addresses = self.peer_addresses.iter()
+            .filter(|(id, _)| membership.contains(id))
+            .map(|(id, addr)| (id.clone(), addr.clone()))
+            .collect();
+        membership
+    }
+}
+
 /// Log entry for replication
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LogEntry {
@@ -58,6 +139,8 @@ pub struct LogEntry {
 }
 
 /// Commands that can be replicated
+///
+/// Payloads are JSON, matching how nodes and edges are stored.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ReplicationCommand {
     /// No-op (used for leader confirmation)
@@ -72,6 +155,126 @@ pub enum ReplicationCommand {
     InsertEdge(Vec<u8>),
     /// Delete an edge
     DeleteEdge(Vec<u8>),
+    /// Replace the cluster membership
+    ChangeMembership(Vec<u8>),
+    /// Write a metadata entry
+    PutMetadata(Vec<u8>),
+}
+
+/// Property update carried by [`ReplicationCommand::UpdateNode`]
+///
+/// The timestamp is chosen by the leader so every replica ends up with
+/// byte-identical nodes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeUpdate {
+    /// Node to update
+    pub id: NodeId,
+    /// Properties merged into the node
+    pub properties: Value,
+    /// New `updated_at` value
+    pub updated_at: Timestamp,
+}
+
+/// Entry carried by [`ReplicationCommand::PutMetadata`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetadataEntry {
+    /// Metadata key
+    pub key: String,
+    /// Value stored under it
+    pub value: Vec<u8>,
+}
+
+impl ReplicationCommand {
+    /// Command inserting `node`
+    pub fn insert_node(node: &Node) -> Result<Self> {
+        Ok(Self::InsertNode(serde_json::to_vec(node)?))
+    }
+
+    /// Command merging `properties` into a node
+    pub fn update_node(id: &NodeId, properties: Value) -> Result<Self> {
+        let update = NodeUpdate {
+            id: id.clone(),
+            properties,
+            updated_at: Timestamp::now(),
+        };
+        Ok(Self::UpdateNode(serde_json::to_vec(&update)?))
+    }
+
+    /// Command deleting a node and its edges
+    pub fn delete_node(id: &NodeId) -> Result<Self> {
+        Ok(Self::DeleteNode(serde_json::to_vec(id)?))
+    }
+
+    /// Command inserting `edge`
+    pub fn insert_edge(edge: &Edge) -> Result<Self> {
+        Ok(Self::InsertEdge(serde_json::to_vec(edge)?))
+    }
+
+    /// Command deleting an edge
+    pub fn delete_edge(id: &EdgeId) -> Result<Self> {
+        Ok(Self::DeleteEdge(serde_json::to_vec(id)?))
+    }
+
+    /// Command switching the cluster to `membership`
+    pub fn change_membership(membership: &Membership) -> Result<Self> {
+        Ok(Self::ChangeMembership(serde_json::to_vec(membership)?))
+    }
+
+    /// Command writing `value` under the metadata `key`
+    pub fn put_metadata(key: &str, value: &[u8]) -> Result<Self> {
+        let entry = MetadataEntry { key: key.to_string(), value: value.to_vec() };
+        Ok(Self::PutMetadata(serde_json::to_vec(&entry)?))
+    }
+
+    /// Membership carried by a [`ChangeMembership`](Self::ChangeMembership)
+    fn membership(&self) -> Option<Membership> {
+        match self {
+            Self::ChangeMembership(data) => serde_json::from_slice(data).ok(),
+            _ => None,
+        }
+    }
+
+    /// Apply the command to storage
+    ///
+    /// Applying the same command twice leaves storage unchanged, so entries
+    /// replayed after a crash are harmless.
+    pub async fn apply(&self, storage: &LocalStorage) -> Result<()> {
+        match self {
+            Self::Nop => Ok(()),
+            Self::InsertNode(data) => {
+                let node: Node = serde_json::from_slice(data)?;
+                storage.insert_node(&node).await.map(|_| ())
+            }
+            Self::UpdateNode(data) => {
+                let update: NodeUpdate = serde_json::from_slice(data)?;
+                let mut node = storage.get_node(&update.id).await?
+                    .ok_or_else(|| anyhow::anyhow!("Node not found: {}", update.id))?;
+                if let Value::Object(props) = update.properties {
+                    node.properties.extend(props);
+                }
+                node.updated_at = update.updated_at;
+                storage.insert_node(&node).await.map(|_| ())
+            }
+            Self::DeleteNode(data) => {
+                let id: NodeId = serde_json::from_slice(data)?;
+                storage.delete_node(&id).await
+            }
+            Self::InsertEdge(data) => {
+                let edge: Edge = serde_json::from_slice(data)?;
+                storage.insert_edge(&edge).await
+            }
+            Self::DeleteEdge(data) => {
+                let id: EdgeId = serde_json::from_slice(data)?;
+                storage.delete_edge(&id).await
+            }
+            // Takes effect in the consensus layer when appended
+            Self::ChangeMembership(_) => Ok(()),
+            Self::PutMetadata(data) => {
+                let entry: MetadataEntry = serde_json::from_slice(data)?;
+                storage.put_metadata(&entry.key, &entry.value)
+            }
+        }
+    }
 }
 
 /// Message types for consensus protocol
@@ -83,11 +286,16 @@ pub enum ConsensusMessage {
         candidate_id: String,
         last_log_index: u64,
         last_log_term: u64,
+        /// Sent on the leader's request, so voters that still hear from
+        /// it must not ignore the vote
+        leadership_transfer: bool,
     },
     /// Response to vote request
     VoteResponse {
         term: u64,
         vote_granted: bool,
+        /// Node that cast the vote
+        voter_id: String,
     },
     /// Append entries (heartbeat or replication)
     AppendEntries {
@@ -99,75 +307,223 @@ pub enum ConsensusMessage {
         leader_commit: u64,
     },
     /// Response to append entries
+    ///
+    /// On failure `match_index` is a hint of where the follower's log may
+    /// diverge, letting the leader skip back more than one entry at a time.
     AppendResponse {
         term: u64,
         success: bool,
         match_index: u64,
+        /// Node that handled the entries
+        follower_id: String,
+    },
+    /// One chunk of the leader's snapshot, for followers whose missing
+    /// entries were compacted away
+    InstallSnapshot {
+        /// Leader's term
+        term: u64,
+        /// Leader sending the snapshot
+        leader_id: String,
+        /// Last log index the snapshot covers
+        last_included_index: u64,
+        /// Term of the entry at `last_included_index`
+        last_included_term: u64,
+        /// Byte offset of `data` within the snapshot
+        offset: u64,
+        /// Snapshot bytes starting at `offset`
+        data: Vec<u8>,
+        /// Whether this is the last chunk
+        done: bool,
+        /// Membership in effect at `last_included_index`
+        membership: Option<Membership>,
+    },
+    /// Response to a snapshot chunk
+    ///
+    /// `next_offset` is where the follower expects the next chunk to start.
+    SnapshotResponse {
+        /// Follower's term
+        term: u64,
+        /// Node that received the chunk
+        follower_id: String,
+        /// Snapshot the chunk belonged to
+        last_included_index: u64,
+        /// Offset of the next chunk to send
+        next_offset: u64,
+        /// Whether the whole snapshot has been installed
+        done: bool,
+    },
+    /// Tell a caught-up voter to start an election right away, handing it
+    /// leadership
+    TimeoutNow {
+        /// Leader's term
+        term: u64,
+        /// Leader handing over
+        leader_id: String,
     },
 }
 
+/// A message addressed to a peer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope {
+    /// Destination node ID
+    pub to: String,
+    /// Message to deliver
+    pub message: ConsensusMessage,
+}
+
+/// Mutable Raft state, guarded by a single lock
+struct RaftCore {
+    state: ReplicaState,
+    /// Log plus durable term/vote
+    log: RaftLog,
+    commit_index: u64,
+    last_applied: u64,
+    /// For leader: next index to send to each peer
+    next_index: HashMap<String, u64>,
+    /// For leader: highest index known to be replicated on each peer
+    match_index: HashMap<String, u64>,
+    /// For candidate: votes received this term
+    votes: HashSet<String>,
+    leader_id: Option<String>,
+    /// Ticks since the last heartbeat (leader) or leader contact (others)
+    elapsed: u64,
+    /// Ticks without leader contact before starting an election
+    election_timeout: u64,
+    rng: StdRng,
+    /// For leader: snapshot bytes acknowledged by each peer being caught up
+    snapshot_offsets: HashMap<String, u64>,
+    /// For follower: snapshot being received, with the chunks written so far
+    incoming_snapshot: Option<(SnapshotMeta, SnapshotWriter)>,
+    /// Installed snapshot not yet restored into the state machine
+    restore_pending: bool,
+    /// Latest membership in the log, in effect from when it was appended
+    membership: Membership,
+    /// Index of the entry that set `membership` (0 if not from the log)
+    membership_index: u64,
+    /// For leader: voter taking over and the ticks left to do so
+    transfer: Option<(String, u64)>,
+}
+
+impl RaftCore {
+    fn term(&self) -> u64 {
+        self.log.hard_state().term
+    }
+
+    /// Members other than `me`
+    fn peers(&self, me: &str) -> Vec<String> {
+        self.membership.members().filter(|id| *id != me).cloned().collect()
+    }
+
+    /// Voters other than `me`
+    fn voting_peers(&self, me: &str) -> Vec<String> {
+        self.membership.voters.iter().filter(|id| *id != me).cloned().collect()
+    }
+
+    /// Whether votes from the current membership's voters form a majority
+    fn has_vote_quorum(&self) -> bool {
+        let votes = self.votes.iter().filter(|id| self.membership.is_voter(id)).count();
+        votes >= self.membership.quorum()
+    }
+
+    fn reset_election_timeout(&mut self, bounds: (u64, u64)) {
+        self.elapsed = 0;
+        self.election_timeout = self.rng.gen_range(bounds.0..=bounds.1);
+    }
+}
+
 /// Replica set for managing replication
 pub struct ReplicaSet {
     /// Configuration
     config: ReplicaConfig,
-    /// Current state
-    state: RwLock<ReplicaState>,
-    /// Current term
-    current_term: RwLock<u64>,
-    /// Who we voted for in current term
-    voted_for: RwLock<Option<String>>,
-    /// Replicated log
-    log: RwLock<Vec<LogEntry>>,
-    /// Index of highest committed entry
-    commit_index: RwLock<u64>,
-    /// Index of highest applied entry
-    last_applied: RwLock<u64>,
-    /// For leader: next index to send to each peer
-    next_index: RwLock<HashMap<String, u64>>,
-    /// For leader: highest index known to be replicated on each peer
-    match_index: RwLock<HashMap<String, u64>>,
+    /// Raft state
+    core: Mutex<RaftCore>,
     /// Last heartbeat received (for followers)
     last_heartbeat: RwLock<Instant>,
-    /// Current leader ID
-    leader_id: RwLock<Option<String>>,
 }
 
 impl ReplicaSet {
-    /// Create a new replica set
+    /// Create a new replica set with an in-memory log
     pub fn new(config: ReplicaConfig) -> Self {
-        Self {
+        Self::with_log(config, RaftLog::memory())
+    }
+
+    /// Open a replica whose log and term/vote persist under `dir`
+    pub fn open(config: ReplicaConfig, dir: impl AsRef<Path>) -> Result<Self> {
+        let log = RaftLog::open(dir.as_ref().join(RAFT_LOG_FILE))?;
+        Ok(Self::with_log(config, log))
+    }
+
+    fn with_log(config: ReplicaConfig, log: RaftLog) -> Self {
+        // Seeded from the node ID so simulations are reproducible while
+        // distinct nodes still pick different timeouts.
+        let mut rng = StdRng::seed_from_u64(xxhash_rust::xxh3::xxh3_64(config.node_id.as_bytes()));
+        let (low, high) = config.election_ticks();
+        let election_timeout = rng.gen_range(low..=high);
+
+        // Everything applied before a restart was committed, as is
+        // everything covered by the snapshot. A snapshot newer than the
+        // applied index was installed but never restored.
+        let snapshot_index = log.snapshot_meta().index;
+        let restore_pending = snapshot_index > log.applied();
+        let applied = log.applied().min(log.last_index()).max(snapshot_index);
+
+        let replica = Self {
+            core: Mutex::new(RaftCore {
+                state: ReplicaState::Follower,
+                log,
+                commit_index: applied,
+                last_applied: applied,
+                next_index: HashMap::new(),
+                match_index: HashMap::new(),
+                votes: HashSet::new(),
+                leader_id: None,
+                elapsed: 0,
+                election_timeout,
+                rng,
+                snapshot_offsets: HashMap::new(),
+                incoming_snapshot: None,
+                restore_pending,
+                membership: Membership::default(),
+                membership_index: 0,
+                transfer: None,
+            }),
             config,
-            state: RwLock::new(ReplicaState::Follower),
-            current_term: RwLock::new(0),
-            voted_for: RwLock::new(None),
-            log: RwLock::new(Vec::new()),
-            commit_index: RwLock::new(0),
-            last_applied: RwLock::new(0),
-            next_index: RwLock::new(HashMap::new()),
-            match_index: RwLock::new(HashMap::new()),
             last_heartbeat: RwLock::new(Instant::now()),
-            leader_id: RwLock::new(None),
+        };
+        {
+            let mut core = replica.core.lock();
+            replica.reload_membership(&mut core);
         }
+        replica
     }
 
     /// Get current state
     pub fn state(&self) -> ReplicaState {
-        *self.state.read()
+        self.core.lock().state
     }
 
     /// Get current term
     pub fn term(&self) -> u64 {
-        *self.current_term.read()
+        self.core.lock().term()
     }
 
     /// Check if this node is the leader
     pub fn is_leader(&self) -> bool {
-        *self.state.read() == ReplicaState::Leader
+        self.core.lock().state == ReplicaState::Leader
     }
 
     /// Get the current leader ID
     pub fn leader(&self) -> Option<String> {
-        self.leader_id.read().clone()
+        self.core.lock().leader_id.clone()
+    }
+
+    /// Client-facing address of the current leader, if known
+    pub fn leader_address(&self) -> Option<String> {
+        let leader = self.leader()?;
+        let core = self.core.lock();
+        core.membership.addresses.get(&leader)
+            .or_else(|| self.config.addresses.get(&leader))
+            .cloned()
     }
 
     /// Get the node ID
@@ -175,34 +531,214 @@ impl ReplicaSet {
         &self.config.node_id
     }
 
+    /// Get the configuration
+    pub fn config(&self) -> &ReplicaConfig {
+        &self.config
+    }
+
+    /// Index of the highest committed entry
+    pub fn commit_index(&self) -> u64 {
+        self.core.lock().commit_index
+    }
+
+    /// Index of the highest applied entry
+    pub fn last_applied(&self) -> u64 {
+        self.core.lock().last_applied
+    }
+
+    /// Index of the last log entry
+    pub fn last_log_index(&self) -> u64 {
+        self.core.lock().log.last_index()
+    }
+
+    /// Index of the last entry covered by the snapshot
+    pub fn snapshot_index(&self) -> u64 {
+        self.core.lock().log.snapshot_meta().index
+    }
+
+    /// Term of the entry at `index`, if it is in the log
+    pub fn term_at(&self, index: u64) -> Option<u64> {
+        self.core.lock().log.term_at(index)
+    }
+
+    /// Current cluster membership
+    pub fn membership(&self) -> Membership {
+        self.core.lock().membership.clone()
+    }
+
+    /// Members with their roles and, on the leader, replication progress
+    pub fn members(&self) -> Vec<MemberInfo> {
+        let core = self.core.lock();
+        let is_leader = core.state == ReplicaState::Leader;
+        core.membership.members()
+            .map(|id| MemberInfo {
+                id: id.clone(),
+                address: core.membership.addresses.get(id)
+                    .or_else(|| self.config.addresses.get(id))
+                    .cloned(),
+                role: if core.membership.is_voter(id) { MemberRole::Voter } else { MemberRole::Learner },
+                is_leader: core.leader_id.as_deref() == Some(id.as_str()),
+                match_index: match (is_leader, id == &self.config.node_id) {
+                    (true, true) => Some(core.log.last_index()),
+                    (true, false) => core.match_index.get(id).copied(),
+                    _ => None,
+                },
+            })
+            .collect()
+    }
+
+    /// Whether `id` has every committed entry (leader only)
+    pub fn is_caught_up(&self, id: &str) -> bool {
+        let core = self.core.lock();
+        core.state == ReplicaState::Leader
+            && core.match_index.get(id).is_some_and(|&m| m >= core.commit_index)
+    }
+
+    /// Append a membership change to the log (leader only)
+    ///
+    /// Only one change may be in flight, and the leader must have committed
+    /// an entry in its own term first so it knows the latest configuration.
+    pub fn change_membership(&self, change: MembershipChange) -> Result<u64> {
+        let mut core = self.core.lock();
+        if core.state != ReplicaState::Leader {
+            bail!("Not the leader");
+        }
+        if core.membership_index > core.commit_index {
+            bail!("A membership change is already in progress");
+        }
+        if core.log.term_at(core.commit_index) != Some(core.term()) {
+            bail!("Leader has not committed an entry in its term yet");
+        }
+
+        let membership = core.membership.apply(&change)?;
+        let index = core.log.last_index() + 1;
+        let term = core.term();
+        core.log.append(&[LogEntry {
+            term,
+            index,
+            command: ReplicationCommand::change_membership(&membership)?,
+        }])?;
+        tracing::info!("{} proposed membership change {:?} at index {}", self.config.node_id, change, index);
+        self.set_membership(&mut core, membership, index);
+        self.advance_commit(&mut core);
+
+        Ok(index)
+    }
+
+    /// Hand leadership to the voter `target`
+    ///
+    /// New writes are refused while the transfer is in progress. Once
+    /// `target` has the whole log it is told to start an election, which it
+    /// wins without waiting for a timeout. The transfer is abandoned after
+    /// an election timeout.
+    pub fn transfer_leadership(&self, target: &str) -> Result<Vec<Envelope>> {
+        let mut core = self.core.lock();
+        if core.state != ReplicaState::Leader {
+            bail!("Not the leader");
+        }
+        if target == self.config.node_id {
+            return Ok(Vec::new());
+        }
+        if !core.membership.is_voter(target) {
+            bail!("{} is not a voter", target);
+        }
+
+        let ticks = self.config.election_ticks().1;
+        core.transfer = Some((target.to_string(), ticks));
+        if core.match_index.get(target).copied() == Some(core.log.last_index()) {
+            return Ok(vec![self.timeout_now(&core, target)]);
+        }
+        Ok(self.append_entries_for(&core, target).into_iter().collect())
+    }
+
     /// Append a command to the log (leader only)
     pub fn append_command(&self, command: ReplicationCommand) -> Result<u64> {
-        if !self.is_leader() {
+        let mut core = self.core.lock();
+        if core.state != ReplicaState::Leader {
             bail!("Not the leader");
         }
+        if let Some((ref target, _)) = core.transfer {
+            bail!("Leadership is being transferred to {}", target);
+        }
 
-        let term = *self.current_term.read();
-        let mut log = self.log.write();
-        let index = log.len() as u64 + 1;
-
-        log.push(LogEntry {
+        let index = core.log.last_index() + 1;
+        let term = core.term();
+        core.log.append(&[LogEntry {
             term,
             index,
             command,
-        });
+        }])?;
+        self.advance_commit(&mut core);
 
         Ok(index)
     }
 
-    /// Process a consensus message
-    pub fn process_message(&self, msg: ConsensusMessage) -> Option<ConsensusMessage> {
+    /// AppendEntries for every peer (leader only)
+    ///
+    /// Call after [`append_command`](Self::append_command) to replicate
+    /// without waiting for the next heartbeat.
+    pub fn replicate(&self) -> Vec<Envelope> {
+        let core = self.core.lock();
+        if core.state != ReplicaState::Leader {
+            return Vec::new();
+        }
+        self.broadcast_append(&core)
+    }
+
+    /// Advance logical time by one heartbeat interval
+    ///
+    /// Leaders send heartbeats; followers and candidates start an election
+    /// once their randomized timeout expires.
+    pub fn tick(&self) -> Result<Vec<Envelope>> {
+        let mut core = self.core.lock();
+        core.elapsed += 1;
+
+        if core.state == ReplicaState::Leader {
+            core.elapsed = 0;
+            if let Some((target, ticks)) = core.transfer.take() {
+                if ticks > 1 {
+                    core.transfer = Some((target, ticks - 1));
+                } else {
+                    tracing::warn!("Leadership transfer to {} timed out", target);
+                }
+            }
+            return Ok(self.broadcast_append(&core));
+        }
+
+        if core.elapsed < core.election_timeout {
+            return Ok(Vec::new());
+        }
+
+        // Learners and removed replicas never stand for election
+        if !core.membership.is_voter(&self.config.node_id) {
+            core.elapsed = 0;
+            return Ok(Vec::new());
+        }
+
+        self.start_campaign(&mut core, false)
+    }
+
+    /// Process a message and return everything that should be sent in reply
+    pub fn step(&self, msg: ConsensusMessage) -> Result<Vec<Envelope>> {
+        let mut core = self.core.lock();
         match msg {
             ConsensusMessage::RequestVote {
                 term,
                 candidate_id,
                 last_log_index,
                 last_log_term,
-            } => Some(self.handle_request_vote(term, candidate_id, last_log_index, last_log_term)),
+                leadership_transfer,
+            } => {
+                let reply = self.handle_request_vote(
+                    &mut core,
+                    term,
+                    &candidate_id,
+                    last_log_index,
+                    last_log_term,
+                    leadership_transfer,
+                )?;
+                Ok(vec![Envelope { to: candidate_id, message: reply }])
+            }
 
             ConsensusMessage::AppendEntries {
                 term,
@@ -211,229 +747,642 @@ impl ReplicaSet {
                 prev_log_term,
                 entries,
                 leader_commit,
-            } => Some(self.handle_append_entries(
+            } => {
+                let reply = self.handle_append_entries(
+                    &mut core,
+                    term,
+                    &leader_id,
+                    prev_log_index,
+                    prev_log_term,
+                    entries,
+                    leader_commit,
+                )?;
+                Ok(vec![Envelope { to: leader_id, message: reply }])
+            }
+
+            ConsensusMessage::VoteResponse { term, vote_granted, voter_id } => {
+                self.handle_vote_response(&mut core, term, vote_granted, voter_id)
+            }
+
+            ConsensusMessage::AppendResponse { term, success, match_index, follower_id } => {
+                self.handle_append_response(&mut core, term, success, match_index, follower_id)
+            }
+
+            ConsensusMessage::InstallSnapshot {
                 term,
                 leader_id,
-                prev_log_index,
-                prev_log_term,
-                entries,
-                leader_commit,
-            )),
+                last_included_index,
+                last_included_term,
+                offset,
+                data,
+                done,
+                membership,
+            } => {
+                let meta = SnapshotMeta { index: last_included_index, term: last_included_term };
+                let reply = self.handle_install_snapshot(
+                    &mut core,
+                    term,
+                    &leader_id,
+                    meta,
+                    offset,
+                    data,
+                    done,
+                    membership,
+                )?;
+                Ok(vec![Envelope { to: leader_id, message: reply }])
+            }
 
-            ConsensusMessage::VoteResponse { term, vote_granted } => {
-                self.handle_vote_response(term, vote_granted);
-                None
+            ConsensusMessage::SnapshotResponse { term, follower_id, last_included_index, next_offset, done } => {
+                self.handle_snapshot_response(&mut core, term, follower_id, last_included_index, next_offset, done)
+            }
+
+            ConsensusMessage::TimeoutNow { term, leader_id } => {
+                if term != core.term() || !core.membership.is_voter(&self.config.node_id) {
+                    return Ok(Vec::new());
+                }
+                tracing::info!("{} taking over leadership from {}", self.config.node_id, leader_id);
+                self.start_campaign(&mut core, true)
             }
+        }
+    }
 
-            ConsensusMessage::AppendResponse { term, success, match_index } => {
-                self.handle_append_response(term, success, match_index);
+    /// Process a consensus message, returning the direct reply if any
+    ///
+    /// Persistence failures drop the message, which Raft tolerates.
+    pub fn process_message(&self, msg: ConsensusMessage) -> Option<ConsensusMessage> {
+        let is_request = matches!(
+            msg,
+            ConsensusMessage::RequestVote { .. }
+                | ConsensusMessage::AppendEntries { .. }
+                | ConsensusMessage::InstallSnapshot { .. }
+        );
+
+        match self.step(msg) {
+            Ok(mut out) if is_request => out.pop().map(|e| e.message),
+            Ok(_) => None,
+            Err(e) => {
+                tracing::error!("Dropping consensus message: {:#}", e);
                 None
             }
         }
     }
 
+    /// Adopt a newer term and fall back to follower
+    fn step_down(&self, core: &mut RaftCore, term: u64) -> Result<()> {
+        if term > core.term() {
+            core.log.save_hard_state(HardState { term, voted_for: None })?;
+            core.leader_id = None;
+        }
+        if core.state != ReplicaState::Follower {
+            core.state = ReplicaState::Follower;
+            core.reset_election_timeout(self.config.election_ticks());
+        }
+        core.votes.clear();
+        core.transfer = None;
+        Ok(())
+    }
+
     /// Handle a vote request
     fn handle_request_vote(
         &self,
+        core: &mut RaftCore,
         term: u64,
-        candidate_id: String,
+        candidate_id: &str,
         last_log_index: u64,
         last_log_term: u64,
-    ) -> ConsensusMessage {
-        let mut current_term = self.current_term.write();
-        let mut voted_for = self.voted_for.write();
+        leadership_transfer: bool,
+    ) -> Result<ConsensusMessage> {
+        // While a leader is known to be alive, ignore candidates: they are
+        // typically removed or partitioned replicas that would otherwise
+        // depose a healthy leader
+        let leader_alive = core.leader_id.is_some() && core.elapsed < self.config.election_ticks().0;
+        if term > core.term() && leader_alive && !leadership_transfer {
+            return Ok(ConsensusMessage::VoteResponse {
+                term: core.term(),
+                vote_granted: false,
+                voter_id: self.config.node_id.clone(),
+            });
+        }
 
         // Update term if necessary
-        if term > *current_term {
-            *current_term = term;
-            *voted_for = None;
-            *self.state.write() = ReplicaState::Follower;
+        if term > core.term() {
+            self.step_down(core, term)?;
         }
 
-        let vote_granted = if term < *current_term {
-            false
-        } else if voted_for.is_some() && voted_for.as_ref() != Some(&candidate_id) {
+        let current_term = core.term();
+        let voted_for = core.log.hard_state().voted_for.clone();
+
+        let vote_granted = if term < current_term
+            || (voted_for.is_some() && voted_for.as_deref() != Some(candidate_id))
+        {
             false
         } else {
             // Check if candidate's log is at least as up-to-date
-            let log = self.log.read();
-            let our_last_term = log.last().map(|e| e.term).unwrap_or(0);
-            let our_last_index = log.len() as u64;
+            let our_last_term = core.log.last_term();
+            let our_last_index = core.log.last_index();
 
             let log_ok = last_log_term > our_last_term
                 || (last_log_term == our_last_term && last_log_index >= our_last_index);
 
             if log_ok {
-                *voted_for = Some(candidate_id);
+                // The vote must be durable before it is sent
+                core.log.save_hard_state(HardState {
+                    term: current_term,
+                    voted_for: Some(candidate_id.to_string()),
+                })?;
+                core.elapsed = 0;
                 *self.last_heartbeat.write() = Instant::now();
-                true
-            } else {
-                false
             }
+            log_ok
         };
 
-        ConsensusMessage::VoteResponse {
-            term: *current_term,
+        Ok(ConsensusMessage::VoteResponse {
+            term: current_term,
             vote_granted,
-        }
+            voter_id: self.config.node_id.clone(),
+        })
     }
 
     /// Handle append entries (heartbeat/replication)
+    #[allow(clippy::too_many_arguments)]
     fn handle_append_entries(
         &self,
+        core: &mut RaftCore,
         term: u64,
-        leader_id: String,
-        prev_log_index: u64,
-        prev_log_term: u64,
-        entries: Vec<LogEntry>,
+        leader_id: &str,
+        mut prev_log_index: u64,
+        mut prev_log_term: u64,
+        mut entries: Vec<LogEntry>,
         leader_commit: u64,
-    ) -> ConsensusMessage {
-        let mut current_term = self.current_term.write();
+    ) -> Result<ConsensusMessage> {
+        let reply = |term: u64, success: bool, match_index: u64| ConsensusMessage::AppendResponse {
+            term,
+            success,
+            match_index,
+            follower_id: self.config.node_id.clone(),
+        };
 
         // Reply false if term < currentTerm
-        if term < *current_term {
-            return ConsensusMessage::AppendResponse {
-                term: *current_term,
-                success: false,
-                match_index: 0,
-            };
+        if term < core.term() {
+            return Ok(reply(core.term(), false, 0));
         }
 
         // Update term and convert to follower if necessary
-        if term > *current_term {
-            *current_term = term;
-            *self.voted_for.write() = None;
-        }
-        *self.state.write() = ReplicaState::Follower;
-        *self.leader_id.write() = Some(leader_id);
+        self.step_down(core, term)?;
+        core.leader_id = Some(leader_id.to_string());
+        core.elapsed = 0;
         *self.last_heartbeat.write() = Instant::now();
 
-        let mut log = self.log.write();
+        // Entries covered by our snapshot are committed and therefore match
+        let snapshot = core.log.snapshot_meta();
+        if prev_log_index < snapshot.index {
+            entries.retain(|e| e.index > snapshot.index);
+            prev_log_index = snapshot.index;
+            prev_log_term = snapshot.term;
+        }
 
         // Check if log contains entry at prevLogIndex with matching term
-        if prev_log_index > 0 {
-            if let Some(entry) = log.get(prev_log_index as usize - 1) {
-                if entry.term != prev_log_term {
-                    // Conflict - remove this and all following entries
-                    log.truncate(prev_log_index as usize - 1);
-                    return ConsensusMessage::AppendResponse {
-                        term: *current_term,
-                        success: false,
-                        match_index: log.len() as u64,
-                    };
+        if prev_log_index > core.log.last_index() {
+            return Ok(reply(term, false, core.log.last_index()));
+        }
+        if core.log.term_at(prev_log_index) != Some(prev_log_term) {
+            return Ok(reply(term, false, prev_log_index.saturating_sub(1)));
+        }
+
+        // Skip entries we already have; truncate at the first conflict
+        let last_new = prev_log_index + entries.len() as u64;
+        let mut first_new = entries.len();
+        let mut truncated = false;
+        for (i, entry) in entries.iter().enumerate() {
+            match core.log.term_at(entry.index) {
+                Some(t) if t == entry.term => continue,
+                Some(_) => {
+                    core.log.truncate_from(entry.index)?;
+                    first_new = i;
+                    truncated = true;
+                    break;
+                }
+                None => {
+                    first_new = i;
+                    break;
                 }
-            } else {
-                // Missing entries
-                return ConsensusMessage::AppendResponse {
-                    term: *current_term,
-                    success: false,
-                    match_index: log.len() as u64,
-                };
             }
         }
+        core.log.append(&entries[first_new..])?;
 
-        // Append new entries
-        for entry in entries {
-            if entry.index as usize > log.len() {
-                log.push(entry);
-            }
+        // Configuration entries take effect as soon as they are in the log
+        let changes_membership = entries[first_new..].iter()
+            .any(|e| matches!(e.command, ReplicationCommand::ChangeMembership(_)));
+        if truncated || changes_membership {
+            self.reload_membership(core);
         }
 
         // Update commit index
-        if leader_commit > *self.commit_index.read() {
-            let new_commit = leader_commit.min(log.len() as u64);
-            *self.commit_index.write() = new_commit;
+        if leader_commit > core.commit_index {
+            core.commit_index = leader_commit.min(last_new);
         }
 
-        ConsensusMessage::AppendResponse {
-            term: *current_term,
-            success: true,
-            match_index: log.len() as u64,
-        }
+        Ok(reply(term, true, last_new))
     }
 
     /// Handle vote response
-    fn handle_vote_response(&self, term: u64, vote_granted: bool) {
-        let current_term = *self.current_term.read();
-
-        if term > current_term {
-            *self.current_term.write() = term;
-            *self.voted_for.write() = None;
-            *self.state.write() = ReplicaState::Follower;
-            return;
+    fn handle_vote_response(
+        &self,
+        core: &mut RaftCore,
+        term: u64,
+        vote_granted: bool,
+        voter_id: String,
+    ) -> Result<Vec<Envelope>> {
+        if term > core.term() {
+            self.step_down(core, term)?;
+            return Ok(Vec::new());
         }
 
-        if *self.state.read() == ReplicaState::Candidate && vote_granted {
-            // Count votes (simplified - in real implementation would track votes)
-            // If majority, become leader
+        if core.state == ReplicaState::Candidate && term == core.term() && vote_granted {
+            core.votes.insert(voter_id);
+            if core.has_vote_quorum() {
+                return self.win_election(core);
+            }
         }
+
+        Ok(Vec::new())
     }
 
     /// Handle append response
-    fn handle_append_response(&self, term: u64, success: bool, match_index: u64) {
-        let current_term = *self.current_term.read();
+    fn handle_append_response(
+        &self,
+        core: &mut RaftCore,
+        term: u64,
+        success: bool,
+        match_index: u64,
+        follower_id: String,
+    ) -> Result<Vec<Envelope>> {
+        if term > core.term() {
+            self.step_down(core, term)?;
+            return Ok(Vec::new());
+        }
 
-        if term > current_term {
-            *self.current_term.write() = term;
-            *self.voted_for.write() = None;
-            *self.state.write() = ReplicaState::Follower;
-            return;
+        if core.state != ReplicaState::Leader || term != core.term() {
+            return Ok(Vec::new());
         }
 
-        if success && *self.state.read() == ReplicaState::Leader {
-            // Update match_index for the peer
-            // Check if we can advance commit_index
+        let last_index = core.log.last_index();
+        if success {
+            let matched = core.match_index.entry(follower_id.clone()).or_insert(0);
+            *matched = (*matched).max(match_index);
+            let next = *matched + 1;
+            core.next_index.insert(follower_id.clone(), next);
+            self.advance_commit(core);
+
+            // Keep streaming if the follower is still behind
+            if next <= last_index {
+                return Ok(self.append_entries_for(core, &follower_id).into_iter().collect());
+            }
+            if core.state == ReplicaState::Leader
+                && core.transfer.as_ref().is_some_and(|(target, _)| *target == follower_id)
+            {
+                return Ok(vec![self.timeout_now(core, &follower_id)]);
+            }
+            Ok(Vec::new())
+        } else {
+            let next = core.next_index.get(&follower_id).copied().unwrap_or(last_index + 1);
+            let next = next.saturating_sub(1).min(match_index + 1).max(1);
+            core.next_index.insert(follower_id.clone(), next);
+            Ok(self.append_entries_for(core, &follower_id).into_iter().collect())
         }
     }
 
-    /// Start an election
-    pub fn start_election(&self) -> ConsensusMessage {
-        let mut current_term = self.current_term.write();
-        *current_term += 1;
-        *self.state.write() = ReplicaState::Candidate;
-        *self.voted_for.write() = Some(self.config.node_id.clone());
-
-        let log = self.log.read();
-        let last_log_index = log.len() as u64;
-        let last_log_term = log.last().map(|e| e.term).unwrap_or(0);
-
-        ConsensusMessage::RequestVote {
-            term: *current_term,
-            candidate_id: self.config.node_id.clone(),
-            last_log_index,
-            last_log_term,
+    /// Handle one chunk of a snapshot from the leader
+    #[allow(clippy::too_many_arguments)]
+    fn handle_install_snapshot(
+        &self,
+        core: &mut RaftCore,
+        term: u64,
+        leader_id: &str,
+        meta: SnapshotMeta,
+        offset: u64,
+        data: Vec<u8>,
+        done: bool,
+        membership: Option<Membership>,
+    ) -> Result<ConsensusMessage> {
+        let reply = |term: u64, next_offset: u64, done: bool| ConsensusMessage::SnapshotResponse {
+            term,
+            follower_id: self.config.node_id.clone(),
+            last_included_index: meta.index,
+            next_offset,
+            done,
+        };
+
+        if term < core.term() {
+            return Ok(reply(core.term(), 0, false));
+        }
+
+        self.step_down(core, term)?;
+        core.leader_id = Some(leader_id.to_string());
+        core.elapsed = 0;
+        *self.last_heartbeat.write() = Instant::now();
+
+        // Already have everything the snapshot covers
+        if meta.index <= core.commit_index {
+            core.incoming_snapshot = None;
+            return Ok(reply(term, 0, true));
+        }
+
+        if offset == 0 {
+            core.incoming_snapshot = Some((meta, core.log.snapshot_writer()?));
+        }
+        let received = match core.incoming_snapshot {
+            Some((ref m, ref mut writer)) if *m == meta && writer.written() == offset => {
+                writer.write_all(&data)?;
+                writer.written()
+            }
+            // Out of order: ask the leader to resume from what we hold
+            Some((ref m, ref writer)) if *m == meta => return Ok(reply(term, writer.written(), false)),
+            _ => return Ok(reply(term, 0, false)),
+        };
+
+        if !done {
+            return Ok(reply(term, received, false));
         }
+
+        let (meta, data) = core.incoming_snapshot.take().expect("snapshot buffer present");
+        core.log.install_snapshot(meta, membership, data)?;
+        self.reload_membership(core);
+        core.commit_index = core.commit_index.max(meta.index);
+        core.last_applied = meta.index;
+        core.restore_pending = true;
+        tracing::info!("{} installed snapshot through index {}", self.config.node_id, meta.index);
+
+        Ok(reply(term, received, true))
+    }
+
+    /// Handle a follower's acknowledgement of a snapshot chunk
+    fn handle_snapshot_response(
+        &self,
+        core: &mut RaftCore,
+        term: u64,
+        follower_id: String,
+        last_included_index: u64,
+        next_offset: u64,
+        done: bool,
+    ) -> Result<Vec<Envelope>> {
+        if term > core.term() {
+            self.step_down(core, term)?;
+            return Ok(Vec::new());
+        }
+
+        if core.state != ReplicaState::Leader || term != core.term() {
+            return Ok(Vec::new());
+        }
+
+        if done {
+            core.snapshot_offsets.remove(&follower_id);
+            let matched = core.match_index.entry(follower_id.clone()).or_insert(0);
+            *matched = (*matched).max(last_included_index);
+            let next = *matched + 1;
+            core.next_index.insert(follower_id.clone(), next);
+            self.advance_commit(core);
+
+            if next <= core.log.last_index() {
+                return Ok(self.append_entries_for(core, &follower_id).into_iter().collect());
+            }
+            return Ok(Vec::new());
+        }
+
+        // A chunk of a snapshot we have since replaced restarts the transfer
+        let offset = if last_included_index == core.log.snapshot_meta().index { next_offset } else { 0 };
+        core.snapshot_offsets.insert(follower_id.clone(), offset);
+        Ok(self.append_entries_for(core, &follower_id).into_iter().collect())
+    }
+
+    /// Campaign and ask every other voter for its vote
+    fn start_campaign(&self, core: &mut RaftCore, leadership_transfer: bool) -> Result<Vec<Envelope>> {
+        let request = self.campaign(core, leadership_transfer)?;
+        if core.has_vote_quorum() {
+            return self.win_election(core);
+        }
+
+        Ok(core.voting_peers(&self.config.node_id).into_iter()
+            .map(|peer| Envelope { to: peer, message: request.clone() })
+            .collect())
+    }
+
+    /// Become a candidate for the next term, voting for ourselves
+    fn campaign(&self, core: &mut RaftCore, leadership_transfer: bool) -> Result<ConsensusMessage> {
+        let term = core.term() + 1;
+        core.log.save_hard_state(HardState {
+            term,
+            voted_for: Some(self.config.node_id.clone()),
+        })?;
+        core.state = ReplicaState::Candidate;
+        core.leader_id = None;
+        core.votes = HashSet::from([self.config.node_id.clone()]);
+        core.reset_election_timeout(self.config.election_ticks());
+
+        Ok(ConsensusMessage::RequestVote {
+            term,
+            candidate_id: self.config.node_id.clone(),
+            last_log_index: core.log.last_index(),
+            last_log_term: core.log.last_term(),
+            leadership_transfer,
+        })
+    }
+
+    /// Take leadership after winning a vote
+    fn win_election(&self, core: &mut RaftCore) -> Result<Vec<Envelope>> {
+        self.become_leader_locked(core);
+        tracing::info!("{} elected leader for term {}", self.config.node_id, core.term());
+
+        // Entries from earlier terms only commit once an entry from the
+        // current term does
+        let index = core.log.last_index() + 1;
+        let term = core.term();
+        core.log.append(&[LogEntry { term, index, command: ReplicationCommand::Nop }])?;
+        self.advance_commit(core);
+
+        Ok(self.broadcast_append(core))
+    }
+
+    /// Start an election
+    pub fn start_election(&self) -> Result<ConsensusMessage> {
+        let mut core = self.core.lock();
+        self.campaign(&mut core, false)
     }
 
     /// Become leader
     pub fn become_leader(&self) {
-        *self.state.write() = ReplicaState::Leader;
-        *self.leader_id.write() = Some(self.config.node_id.clone());
+        let mut core = self.core.lock();
+        self.become_leader_locked(&mut core);
+    }
+
+    fn become_leader_locked(&self, core: &mut RaftCore) {
+        core.state = ReplicaState::Leader;
+        core.leader_id = Some(self.config.node_id.clone());
+        core.votes.clear();
+        core.elapsed = 0;
 
         // Initialize next_index and match_index for peers
-        let log_len = self.log.read().len() as u64 + 1;
-        let mut next_index = self.next_index.write();
-        let mut match_index = self.match_index.write();
+        let next = core.log.last_index() + 1;
+        core.next_index.clear();
+        core.match_index.clear();
+        core.snapshot_offsets.clear();
+        core.transfer = None;
+        for peer in core.peers(&self.config.node_id) {
+            core.next_index.insert(peer.clone(), next);
+            core.match_index.insert(peer, 0);
+        }
+    }
+
+    /// Switch to `membership`, set by the entry at `index`
+    fn set_membership(&self, core: &mut RaftCore, membership: Membership, index: u64) {
+        core.membership = membership;
+        core.membership_index = index;
+
+        if core.state == ReplicaState::Leader {
+            let peers = core.peers(&self.config.node_id);
+            let next = core.log.last_index() + 1;
+            core.next_index.retain(|id, _| peers.contains(id));
+            core.match_index.retain(|id, _| peers.contains(id));
+            core.snapshot_offsets.retain(|id, _| peers.contains(id));
+            for peer in peers {
+                core.next_index.entry(peer.clone()).or_insert(next);
+                core.match_index.entry(peer).or_insert(0);
+            }
+        }
+    }
+
+    /// Membership set by the entry at or before `index`, if any
+    fn membership_entry_at(&self, core: &RaftCore, index: u64) -> Option<(Membership, u64)> {
+        (core.log.first_index()..=index.min(core.log.last_index()))
+            .rev()
+            .find_map(|i| core.log.get(i)?.command.membership().map(|m| (m, i)))
+    }
+
+    /// Recompute the membership from the log, snapshot and configuration
+    fn reload_membership(&self, core: &mut RaftCore) {
+        let (membership, index) = self.membership_entry_at(core, core.log.last_index())
+            .or_else(|| core.log.snapshot_membership().map(|m| (m.clone(), 0)))
+            .unwrap_or_else(|| (self.config.initial_membership(), 0));
+        self.set_membership(core, membership, index);
+    }
+
+    /// Commit the highest current-term entry stored on a majority of voters
+    fn advance_commit(&self, core: &mut RaftCore) {
+        let current_term = core.term();
+        let me = &self.config.node_id;
+        let quorum = core.membership.quorum();
+
+        for index in (core.commit_index + 1..=core.log.last_index()).rev() {
+            if core.log.term_at(index) != Some(current_term) {
+                break;
+            }
+            let replicas = usize::from(core.membership.is_voter(me))
+                + core.match_index.iter()
+                    .filter(|(id, &m)| m >= index && core.membership.is_voter(id))
+                    .count();
+            if replicas >= quorum {
+                core.commit_index = index;
+                break;
+            }
+        }
+
+        // A leader removed from the cluster hands over once that is committed
+        if core.state == ReplicaState::Leader
+            && !core.membership.is_voter(me)
+            && core.membership_index <= core.commit_index
+        {
+            tracing::info!("{} is no longer a voter; stepping down", me);
+            core.state = ReplicaState::Follower;
+            core.leader_id = None;
+            core.transfer = None;
+            core.reset_election_timeout(self.config.election_ticks());
+        }
+    }
+
+    fn broadcast_append(&self, core: &RaftCore) -> Vec<Envelope> {
+        core.peers(&self.config.node_id).iter()
+            .filter_map(|peer| self.append_entries_for(core, peer))
+            .collect()
+    }
+
+    /// TimeoutNow handing leadership to `peer`
+    fn timeout_now(&self, core: &RaftCore, peer: &str) -> Envelope {
+        Envelope {
+            to: peer.to_string(),
+            message: ConsensusMessage::TimeoutNow {
+                term: core.term(),
+                leader_id: self.config.node_id.clone(),
+            },
+        }
+    }
 
-        for peer in &self.config.peers {
-            next_index.insert(peer.clone(), log_len);
-            match_index.insert(peer.clone(), 0);
+    /// AppendEntries carrying whatever `peer` is missing
+    ///
+    /// Falls back to the next snapshot chunk when those entries were
+    /// compacted away, and to nothing if that chunk cannot be read.
+    fn append_entries_for(&self, core: &RaftCore, peer: &str) -> Option<Envelope> {
+        let next = core.next_index.get(peer).copied().unwrap_or(core.log.last_index() + 1);
+        let snapshot = core.log.snapshot_meta();
+        if next <= snapshot.index {
+            return self.snapshot_chunk_for(core, peer, snapshot);
         }
+        let prev_log_index = next - 1;
+
+        Some(Envelope {
+            to: peer.to_string(),
+            message: ConsensusMessage::AppendEntries {
+                term: core.term(),
+                leader_id: self.config.node_id.clone(),
+                prev_log_index,
+                prev_log_term: core.log.term_at(prev_log_index).unwrap_or(0),
+                entries: core.log.entries_from(next, MAX_APPEND_ENTRIES),
+                leader_commit: core.commit_index,
+            },
+        })
+    }
+
+    /// InstallSnapshot carrying the next chunk `peer` has not acknowledged
+    ///
+    /// None if the snapshot could not be read; the chunk is retried with
+    /// the next heartbeat.
+    fn snapshot_chunk_for(&self, core: &RaftCore, peer: &str, snapshot: SnapshotMeta) -> Option<Envelope> {
+        let len = core.log.snapshot_len();
+        let start = core.snapshot_offsets.get(peer).copied().unwrap_or(0).min(len);
+        let data = match core.log.read_snapshot(start, self.config.snapshot_chunk_size.max(1)) {
+            Ok(data) => data,
+            Err(e) => {
+                tracing::warn!("Failed to read snapshot for {}: {:#}", peer, e);
+                return None;
+            }
+        };
+        let end = start + data.len() as u64;
+
+        Some(Envelope {
+            to: peer.to_string(),
+            message: ConsensusMessage::InstallSnapshot {
+                term: core.term(),
+                leader_id: self.config.node_id.clone(),
+                last_included_index: snapshot.index,
+                last_included_term: snapshot.term,
+                offset: start,
+                data,
+                done: end == len,
+                membership: core.log.snapshot_membership().cloned(),
+            },
+        })
     }
 
     /// Create heartbeat message
     pub fn create_heartbeat(&self) -> ConsensusMessage {
-        let log = self.log.read();
-        let prev_log_index = log.len() as u64;
-        let prev_log_term = log.last().map(|e| e.term).unwrap_or(0);
+        let core = self.core.lock();
 
         ConsensusMessage::AppendEntries {
-            term: *self.current_term.read(),
+            term: core.term(),
             leader_id: self.config.node_id.clone(),
-            prev_log_index,
-            prev_log_term,
+            prev_log_index: core.log.last_index(),
+            prev_log_term: core.log.last_term(),
             entries: Vec::new(),
-            leader_commit: *self.commit_index.read(),
+            leader_commit: core.commit_index,
         }
     }
 
@@ -445,23 +1394,86 @@ impl ReplicaSet {
     }
 
     /// Get committed entries that haven't been applied
+    ///
+    /// Empty while an installed snapshot awaits restoring, since the
+    /// entries build on its state.
     pub fn get_unapplied_entries(&self) -> Vec<LogEntry> {
-        let log = self.log.read();
-        let commit_index = *self.commit_index.read();
-        let last_applied = *self.last_applied.read();
+        let core = self.core.lock();
+        if core.restore_pending {
+            return Vec::new();
+        }
+        core.log.range(core.last_applied, core.commit_index)
+    }
 
-        log.iter()
-            .skip(last_applied as usize)
-            .take((commit_index - last_applied) as usize)
-            .cloned()
-            .collect()
+    /// Snapshot that must be restored into the state machine before
+    /// applying further entries
+    pub fn pending_restore(&self) -> Result<Option<(SnapshotMeta, Box<dyn Read + Send>)>> {
+        let core = self.core.lock();
+        if !core.restore_pending {
+            return Ok(None);
+        }
+        Ok(Some((core.log.snapshot_meta(), core.log.open_snapshot()?)))
+    }
+
+    /// Record that the snapshot through `index` was restored
+    ///
+    /// A newer snapshot installed while it was restoring stays pending.
+    pub fn snapshot_restored(&self, index: u64) -> Result<()> {
+        let mut core = self.core.lock();
+        core.log.save_applied(index)?;
+        core.last_applied = core.last_applied.max(index);
+        if core.log.snapshot_meta().index == index {
+            core.restore_pending = false;
+        }
+        Ok(())
+    }
+
+    /// Whether enough entries were applied since the last snapshot to
+    /// compact the log
+    pub fn should_snapshot(&self) -> bool {
+        let core = self.core.lock();
+        !core.restore_pending
+            && core.last_applied.saturating_sub(core.log.snapshot_meta().index) >= self.config.snapshot_threshold.max(1)
+    }
+
+    /// Somewhere to write a snapshot of the state machine for
+    /// [`compact`](Self::compact)
+    pub fn snapshot_writer(&self) -> Result<SnapshotWriter> {
+        self.core.lock().log.snapshot_writer()
+    }
+
+    /// Replace the log up to `index` with a snapshot of the state machine
+    ///
+    /// `data` must reflect exactly the entries applied through `index`.
+    pub fn compact(&self, index: u64, data: SnapshotWriter) -> Result<()> {
+        let mut core = self.core.lock();
+        if index > core.last_applied {
+            bail!("Cannot snapshot index {} beyond the applied index {}", index, core.last_applied);
+        }
+        if index <= core.log.snapshot_meta().index {
+            return Ok(());
+        }
+        // A snapshot installed meanwhile may have replaced the entry
+        let Some(term) = core.log.term_at(index) else {
+            bail!("Entry {} is no longer in the log", index);
+        };
+        let membership = self.membership_entry_at(&core, index)
+            .map(|(m, _)| m)
+            .or_else(|| core.log.snapshot_membership().cloned());
+        core.log.install_snapshot(SnapshotMeta { index, term }, membership, data)?;
+        tracing::debug!("{} compacted log through index {}", self.config.node_id, index);
+        Ok(())
     }
 
     /// Mark entries as applied
     pub fn mark_applied(&self, up_to: u64) {
-        let mut last_applied = self.last_applied.write();
-        if up_to > *last_applied {
-            *last_applied = up_to;
+        let mut core = self.core.lock();
+        if up_to > core.last_applied {
+            core.last_applied = up_to;
+            // Losing this only causes idempotent re-application on restart
+            if let Err(e) = core.log.save_applied(up_to) {
+                tracing::warn!("Failed to persist applied index: {:#}", e);
+            }
         }
     }
 }
@@ -485,7 +1497,7 @@ mod tests {
         let config = ReplicaConfig::default();
         let replica = ReplicaSet::new(config);
 
-        let msg = replica.start_election();
+        let msg = replica.start_election().unwrap();
 
         assert_eq!(replica.state(), ReplicaState::Candidate);
         assert_eq!(replica.term(), 1);
@@ -535,10 +1547,11 @@ mod tests {
             candidate_id: "other".to_string(),
             last_log_index: 0,
             last_log_term: 0,
+            leadership_transfer: false,
         });
 
         match response {
-            Some(ConsensusMessage::VoteResponse { term, vote_granted }) => {
+            Some(ConsensusMessage::VoteResponse { term, vote_granted, .. }) => {
                 assert_eq!(term, 1);
                 assert!(vote_granted);
             }
@@ -561,4 +1574,49 @@ mod tests {
             _ => panic!("Expected AppendEntries"),
         }
     }
+
+    #[test]
+    fn test_single_node_elects_itself_and_commits() {
+        let config = ReplicaConfig::default();
+        let replica = ReplicaSet::new(config);
+
+        for _ in 0..20 {
+            replica.tick().unwrap();
+        }
+        assert!(replica.is_leader());
+
+        let index = replica.append_command(ReplicationCommand::Nop).unwrap();
+        assert_eq!(replica.commit_index(), index);
+        assert_eq!(replica.get_unapplied_entries().len() as u64, index);
+    }
+
+    #[test]
+    fn test_vote_persists_across_restart() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let config = ReplicaConfig { node_id: "n1".to_string(), ..Default::default() };
+
+        {
+            let replica = ReplicaSet::open(config.clone(), temp.path()).unwrap();
+            replica.process_message(ConsensusMessage::RequestVote {
+                term: 3,
+                candidate_id: "n2".to_string(),
+                last_log_index: 0,
+                last_log_term: 0,
+                leadership_transfer: false,
+            });
+        }
+
+        let replica = ReplicaSet::open(config, temp.path()).unwrap();
+        assert_eq!(replica.term(), 3);
+
+        // Already voted for n2 in term 3
+        let response = replica.process_message(ConsensusMessage::RequestVote {
+            term: 3,
+            candidate_id: "n3".to_string(),
+            last_log_index: 0,
+            last_log_term: 0,
+            leadership_transfer: false,
+        });
+        assert!(matches!(response, Some(ConsensusMessage::VoteResponse { vote_granted: false, .. })));
+    }
 }
@@ -2,15 +2,42 @@
 //!
 //! Distributes data across multiple storage backends using consistent hashing.
 //! Supports rebalancing when shards are added/removed.
-
-use anyhow::{Result, Context};
+//!
+//! The set of shards is recorded in a layout file next to the shard
+//! directories. Adding or removing a shard switches the hash ring at once
+//! and then moves the affected keys in the background: while the move is
+//! pending, writes go to the new owner (taking the key with them) and
+//! reads fall back to the previous owner, so the store keeps serving
+//! throughout.
+
+use anyhow::{Result, Context, bail};
 use parking_lot::RwLock;
-use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::collections::hash_map::Entry;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use xxhash_rust::xxh3::xxh3_64;
 
-use crate::storage::{LocalStorage, Node, Edge, NodeId, EdgeId, Value};
+use super::CountingBloomFilter;
+use crate::metrics::metrics;
+use crate::query::TraversalResult;
+use crate::storage::{LocalStorage, Node, Edge, NodeId, EdgeId, ReadView, Value};
+
+/// Layout file in the shard base directory
+const LAYOUT_FILE: &str = "shards.json";
+
+/// Lock stripes serialising writes per key against the rebalancer
+const KEY_LOCKS: usize = 64;
+
+/// Metadata key holding a shard's saved bloom filter
+const BLOOM_KEY: &str = "bloom_filter";
+
+/// Smallest number of keys a shard's bloom filter is sized for
+const MIN_BLOOM_ITEMS: usize = 10_000;
+
+/// Target false positive rate of shard bloom filters
+const BLOOM_FPP: f64 = 0.01;
 
 /// Configuration for shard manager
 #[derive(Debug, Clone)]
@@ -34,13 +61,17 @@ impl Default for ShardConfig {
 }
 
 /// Individual shard containing a LocalStorage instance
+///
+/// The shard's Bloom filter is saved to its metadata table when the shard
+/// is dropped and taken back out on open, so a shard that was not shut
+/// down cleanly has no saved filter and rebuilds it from its keys.
 pub struct Shard {
     /// Shard ID
     pub id: usize,
     /// Storage for this shard
     storage: LocalStorage,
     /// Bloom filter for fast negative lookups
-    bloom: RwLock<crate::distributed::BloomFilter>,
+    bloom: RwLock<CountingBloomFilter>,
 }
 
 impl Shard {
@@ -48,7 +79,7 @@ impl Shard {
     pub async fn new(id: usize, path: &Path) -> Result<Self> {
         let shard_path = path.join(format!("shard_{:04}", id));
         let storage = LocalStorage::create(&shard_path).await?;
-        let bloom = RwLock::new(crate::distributed::BloomFilter::new(100_000, 0.01));
+        let bloom = RwLock::new(CountingBloomFilter::new(MIN_BLOOM_ITEMS, BLOOM_FPP));
 
         Ok(Self { id, storage, bloom })
     }
@@ -57,7 +88,7 @@ impl Shard {
     pub async fn open(id: usize, path: &Path) -> Result<Self> {
         let shard_path = path.join(format!("shard_{:04}", id));
         let storage = LocalStorage::open(&shard_path).await?;
-        let bloom = RwLock::new(crate::distributed::BloomFilter::new(100_000, 0.01));
+        let bloom = RwLock::new(Self::load_bloom(&storage).await?);
 
         Ok(Self { id, storage, bloom })
     }
@@ -76,16 +107,195 @@ impl Shard {
     pub fn add_to_bloom(&self, key: &[u8]) {
         self.bloom.write().insert(key);
     }
+
+    /// Remove a key that was stored on this shard from the bloom filter
+    pub fn remove_from_bloom(&self, key: &[u8]) {
+        self.bloom.write().remove(key);
+    }
+
+    /// Estimated false positive rate of the bloom filter
+    pub fn bloom_fpp(&self) -> f64 {
+        self.bloom.read().estimated_fpp()
+    }
+
+    /// Rebuild the bloom filter from the stored keys, sized to fit them
+    pub async fn rebuild_bloom(&self) -> Result<()> {
+        let bloom = Self::build_bloom(&self.storage).await?;
+        *self.bloom.write() = bloom;
+        Ok(())
+    }
+
+    /// Save the bloom filter to the metadata table
+    pub fn save_bloom(&self) -> Result<()> {
+        self.storage.put_metadata(BLOOM_KEY, &self.bloom.read().to_bytes())
+    }
+
+    /// Saved filter if it is still current, otherwise a rebuilt one
+    async fn load_bloom(storage: &LocalStorage) -> Result<CountingBloomFilter> {
+        // Taking the filter out means a crash before the next save leaves
+        // nothing behind to be trusted
+        let saved = storage
+            .take_metadata(BLOOM_KEY)?
+            .and_then(|bytes| CountingBloomFilter::from_bytes(&bytes));
+
+        if let Some(bloom) = saved {
+            let stats = storage.stats().await?;
+            let current = bloom.count() as u64 == stats.node_count + stats.edge_count;
+            if current && bloom.estimated_fpp() <= BLOOM_FPP * 2.0 {
+                return Ok(bloom);
+            }
+        }
+
+        Self::build_bloom(storage).await
+    }
+
+    async fn build_bloom(storage: &LocalStorage) -> Result<CountingBloomFilter> {
+        let keys = storage.keys().await?;
+        // Leave room for the shard to double before the filter degrades
+        let mut bloom = CountingBloomFilter::new((keys.len() * 2).max(MIN_BLOOM_ITEMS), BLOOM_FPP);
+        for key in &keys {
+            bloom.insert(key);
+        }
+        Ok(bloom)
+    }
+}
+
+impl Drop for Shard {
+    fn drop(&mut self) {
+        if self.storage.is_readonly() {
+            return;
+        }
+        if let Err(e) = self.save_bloom() {
+            tracing::warn!("Failed to save bloom filter for shard {}: {:#}", self.id, e);
+        }
+    }
+}
+
+/// Shards that make up a sharded store, persisted as `shards.json`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShardLayout {
+    /// Number of virtual nodes per shard on the hash ring
+    pub virtual_nodes: usize,
+    /// Every open shard, including one that is being added or removed
+    pub shards: Vec<usize>,
+    /// Change whose keys are still being moved
+    pub migration: Option<ShardMigration>,
+}
+
+impl ShardLayout {
+    fn new(virtual_nodes: usize, shards: Vec<usize>) -> Self {
+        Self { virtual_nodes, shards, migration: None }
+    }
+
+    /// Read the layout stored under `base_path`, if any
+    pub fn load(base_path: &Path) -> Result<Option<Self>> {
+        let path = base_path.join(LAYOUT_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = std::fs::read(&path)
+            .with_context(|| format!("Failed to read shard layout {}", path.display()))?;
+        Ok(Some(serde_json::from_slice(&data)?))
+    }
+
+    fn save(&self, base_path: &Path) -> Result<()> {
+        // Write then rename so a crash never leaves a torn layout
+        let path = base_path.join(LAYOUT_FILE);
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    /// Shards that own keys once the pending change completes
+    pub fn target_shards(&self) -> Vec<usize> {
+        match self.migration {
+            Some(ShardMigration::Removing(id)) => {
+                self.shards.iter().copied().filter(|&s| s != id).collect()
+            }
+            _ => self.shards.clone(),
+        }
+    }
+
+    /// Shards that owned keys before the pending change
+    fn previous_shards(&self) -> Option<Vec<usize>> {
+        match self.migration? {
+            ShardMigration::Adding(id) => {
+                Some(self.shards.iter().copied().filter(|&s| s != id).collect())
+            }
+            ShardMigration::Removing(_) => Some(self.shards.clone()),
+        }
+    }
+}
+
+/// A shard being added to or removed from the ring
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ShardMigration {
+    /// Keys are moving onto this new shard
+    Adding(usize),
+    /// Keys are moving off this shard before it is dropped
+    Removing(usize),
+}
+
+/// Progress of a rebalance pass
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RebalanceProgress {
+    /// Change being applied, `None` once the layout is settled
+    pub migration: Option<ShardMigration>,
+    /// Nodes on the source shards when the pass started
+    pub total: u64,
+    /// Nodes examined so far
+    pub scanned: u64,
+    /// Nodes moved to their new shard
+    pub moved: u64,
+    /// Edges moved along with their source node
+    pub edges_moved: u64,
+}
+
+/// Routing state for the current layout
+struct Routing {
+    layout: ShardLayout,
+    /// Ring after the pending change
+    ring: ConsistentHashRing,
+    /// Ring before the pending change, while keys are being moved
+    previous: Option<ConsistentHashRing>,
+    shards: BTreeMap<usize, Arc<Shard>>,
+}
+
+impl Routing {
+    fn new(layout: ShardLayout, shards: BTreeMap<usize, Arc<Shard>>) -> Self {
+        let ring = ConsistentHashRing::with_shards(layout.virtual_nodes, layout.target_shards());
+        let previous = layout
+            .previous_shards()
+            .map(|ids| ConsistentHashRing::with_shards(layout.virtual_nodes, ids));
+        Self { layout, ring, previous, shards }
+    }
+
+    /// Owner of `key`, plus its previous owner if the key may still be there
+    fn route(&self, key: &[u8]) -> (Arc<Shard>, Option<Arc<Shard>>) {
+        let owner = self.ring.get_node(key);
+        let previous = self
+            .previous
+            .as_ref()
+            .map(|ring| ring.get_node(key))
+            .filter(|&id| id != owner)
+            .map(|id| self.shards[&id].clone());
+        (self.shards[&owner].clone(), previous)
+    }
 }
 
 /// Manages sharded storage using consistent hashing
 pub struct ShardManager {
     /// Configuration
     config: ShardConfig,
-    /// Hash ring for consistent hashing
-    ring: ConsistentHashRing,
-    /// Shards
-    shards: Vec<Arc<Shard>>,
+    /// Layout, hash rings and open shards
+    routing: RwLock<Routing>,
+    /// Per-key write locks, striped
+    key_locks: Vec<tokio::sync::Mutex<()>>,
+    /// Held by the running rebalance pass
+    rebalancing: tokio::sync::Mutex<()>,
+    /// Progress of the current or last rebalance pass
+    progress: RwLock<RebalanceProgress>,
 }
 
 impl ShardManager {
@@ -97,127 +307,383 @@ impl ShardManager {
 
     /// Create a new shard manager
     pub async fn new(config: ShardConfig) -> Result<Self> {
-        let mut ring = ConsistentHashRing::new(config.virtual_nodes);
-        let mut shards = Vec::with_capacity(config.num_shards);
+        std::fs::create_dir_all(&config.base_path)?;
+        let layout = ShardLayout::new(config.virtual_nodes, (0..config.num_shards).collect());
 
-        for i in 0..config.num_shards {
-            let shard = Shard::new(i, &config.base_path).await?;
-            ring.add_node(i);
-            shards.push(Arc::new(shard));
+        let mut shards = BTreeMap::new();
+        for &id in &layout.shards {
+            shards.insert(id, Arc::new(Shard::new(id, &config.base_path).await?));
         }
 
-        Ok(Self { config, ring, shards })
+        layout.save(&config.base_path)?;
+        Ok(Self::with_routing(config, Routing::new(layout, shards)))
     }
 
     /// Open existing shards
+    ///
+    /// The stored layout wins over `config.num_shards`; stores created
+    /// before layouts were persisted use the shard directories on disk.
     pub async fn open(config: ShardConfig) -> Result<Self> {
-        let mut ring = ConsistentHashRing::new(config.virtual_nodes);
-        let mut shards = Vec::with_capacity(config.num_shards);
+        let layout = match ShardLayout::load(&config.base_path)? {
+            Some(layout) => layout,
+            None => {
+                let mut ids = Self::discover(&config.base_path)?;
+                if ids.is_empty() {
+                    ids = (0..config.num_shards).collect();
+                }
+                ShardLayout::new(config.virtual_nodes, ids)
+            }
+        };
 
-        for i in 0..config.num_shards {
-            let shard = Shard::open(i, &config.base_path).await?;
-            ring.add_node(i);
-            shards.push(Arc::new(shard));
+        let mut shards = BTreeMap::new();
+        for &id in &layout.shards {
+            let shard = Shard::open(id, &config.base_path)
+                .await
+                .with_context(|| format!("Failed to open shard {}", id))?;
+            shards.insert(id, Arc::new(shard));
         }
 
-        Ok(Self { config, ring, shards })
+        layout.save(&config.base_path)?;
+        if let Some(migration) = layout.migration {
+            tracing::info!("Shard rebalance pending: {:?}", migration);
+        }
+        Ok(Self::with_routing(config, Routing::new(layout, shards)))
+    }
+
+    /// Open the shards under `config.base_path`, creating them if none exist
+    pub async fn open_or_create(config: ShardConfig) -> Result<Self> {
+        if Self::exists(&config.base_path)? {
+            Self::open(config).await
+        } else {
+            Self::new(config).await
+        }
+    }
+
+    /// Whether a sharded store exists at `base_path`
+    pub fn exists(base_path: &Path) -> Result<bool> {
+        Ok(base_path.join(LAYOUT_FILE).exists() || !Self::discover(base_path)?.is_empty())
+    }
+
+    /// Shard IDs with a directory under `base_path`
+    fn discover(base_path: &Path) -> Result<Vec<usize>> {
+        let mut ids = Vec::new();
+        if !base_path.exists() {
+            return Ok(ids);
+        }
+        for entry in std::fs::read_dir(base_path)? {
+            let name = entry?.file_name();
+            if let Some(id) = name.to_str().and_then(|n| n.strip_prefix("shard_")) {
+                if let Ok(id) = id.parse() {
+                    ids.push(id);
+                }
+            }
+        }
+        ids.sort_unstable();
+        Ok(ids)
+    }
+
+    fn with_routing(config: ShardConfig, routing: Routing) -> Self {
+        let progress = RebalanceProgress {
+            migration: routing.layout.migration,
+            ..Default::default()
+        };
+        Self {
+            config,
+            routing: RwLock::new(routing),
+            key_locks: (0..KEY_LOCKS).map(|_| tokio::sync::Mutex::new(())).collect(),
+            rebalancing: tokio::sync::Mutex::new(()),
+            progress: RwLock::new(progress),
+        }
     }
 
     /// Get shard for a key
-    pub fn get_shard(&self, key: &[u8]) -> &Arc<Shard> {
-        let shard_id = self.ring.get_node(key);
-        &self.shards[shard_id]
+    pub fn get_shard(&self, key: &[u8]) -> Arc<Shard> {
+        self.routing.read().route(key).0
     }
 
     /// Get shard for a node ID
-    pub fn get_shard_for_node(&self, node_id: &NodeId) -> &Arc<Shard> {
+    pub fn get_shard_for_node(&self, node_id: &NodeId) -> Arc<Shard> {
         self.get_shard(&node_id.uuid)
     }
 
+    /// Flush every shard's storage
+    pub async fn flush(&self) -> Result<()> {
+        for shard in self.shards() {
+            shard.storage().flush().await?;
+        }
+        Ok(())
+    }
+
     /// Get all shards
-    pub fn shards(&self) -> &[Arc<Shard>] {
-        &self.shards
+    pub fn shards(&self) -> Vec<Arc<Shard>> {
+        self.routing.read().shards.values().cloned().collect()
+    }
+
+    /// Current shard layout
+    pub fn layout(&self) -> ShardLayout {
+        self.routing.read().layout.clone()
+    }
+
+    /// Progress of the current or last rebalance pass
+    pub fn rebalance_progress(&self) -> RebalanceProgress {
+        self.progress.read().clone()
     }
 
     /// Insert a node into the appropriate shard
     pub async fn insert_node(&self, node: &Node) -> Result<()> {
-        let shard = self.get_shard_for_node(&node.id);
+        let _guard = self.key_lock(&node.id.uuid).lock().await;
+        let shard = self.settle(&node.id).await?;
         shard.add_to_bloom(&node.id.uuid);
-        shard.storage().insert_node(node).await
+        shard.storage().insert_node(node).await.map(|_| ())
     }
 
     /// Get a node by ID
     pub async fn get_node(&self, id: &NodeId) -> Result<Option<Node>> {
-        let shard = self.get_shard_for_node(id);
+        let (shard, previous) = self.routing.read().route(&id.uuid);
 
         // Use bloom filter for fast negative lookup
-        if !shard.may_contain(&id.uuid) {
-            return Ok(None);
+        if shard.may_contain(&id.uuid) {
+            if let Some(node) = shard.storage().get_node(id).await? {
+                return Ok(Some(node));
+            }
         }
 
-        shard.storage().get_node(id).await
+        match previous {
+            Some(previous) => previous.storage().get_node(id).await,
+            None => Ok(None),
+        }
     }
 
     /// Update a node
     pub async fn update_node(&self, id: &NodeId, properties: Value) -> Result<Node> {
-        let shard = self.get_shard_for_node(id);
+        let _guard = self.key_lock(&id.uuid).lock().await;
+        let shard = self.settle(id).await?;
         shard.storage().update_node(id, properties).await
     }
 
-    /// Delete a node
+    /// Delete a node along with its edges, on whichever shards they live
     pub async fn delete_node(&self, id: &NodeId) -> Result<()> {
-        let shard = self.get_shard_for_node(id);
-        shard.storage().delete_node(id).await
+        // Lock the node and all its neighbours; retry if edges appeared
+        // between reading them and taking the locks
+        let (_guards, shard, outgoing, incoming) = loop {
+            let outgoing = self.get_edges_from(id, None).await?;
+            let incoming = self.get_edges_to(id, None).await?;
+            let neighbours: Vec<&NodeId> = std::iter::once(id)
+                .chain(outgoing.iter().map(|e| &e.to))
+                .chain(incoming.iter().map(|e| &e.from))
+                .collect();
+            let guards = self.lock_keys(neighbours.iter().copied()).await;
+
+            for neighbour in &neighbours {
+                self.settle(neighbour).await?;
+            }
+            let shard = self.settle(id).await?;
+            let now_out = shard.storage().get_edges_from(id, None).await?;
+            let now_in = shard.storage().get_reverse_edges(id, None).await?;
+            if now_out.len() == outgoing.len() && now_in.len() == incoming.len() {
+                break (guards, shard, now_out, now_in);
+            }
+        };
+
+        for edge in &outgoing {
+            self.owner(&edge.to).storage().delete_reverse_edge(&edge.to, &edge.id).await?;
+            shard.remove_from_bloom(&edge.id.uuid);
+        }
+        for edge in &incoming {
+            let source = self.owner(&edge.from);
+            source.storage().delete_edge(&edge.id).await?;
+            source.remove_from_bloom(&edge.id.uuid);
+            shard.storage().delete_reverse_edge(id, &edge.id).await?;
+        }
+
+        // Only keys that were stored may come out of the counting filter
+        if shard.storage().get_node(id).await?.is_some() {
+            shard.storage().delete_node(id).await?;
+            shard.remove_from_bloom(&id.uuid);
+        }
+        Ok(())
     }
 
     /// Insert an edge
     pub async fn insert_edge(&self, edge: &Edge) -> Result<()> {
-        // Edges are stored with the source node's shard
-        let shard = self.get_shard_for_node(&edge.from);
+        // Edges are stored with the source node's shard, with a reverse
+        // entry on the target node's shard
+        let _guards = self.lock_keys([&edge.from, &edge.to]).await;
+        let shard = self.settle(&edge.from).await?;
+        let target = self.settle(&edge.to).await?;
         shard.add_to_bloom(&edge.id.uuid);
-        shard.storage().insert_edge(edge).await
+        shard.storage().insert_edge(edge).await?;
+        target.storage().insert_reverse_edge(edge).await
+    }
+
+    /// Delete an edge by ID
+    pub async fn delete_edge(&self, id: &EdgeId) -> Result<()> {
+        let edge = self
+            .find_edge(id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Edge not found: {}", id))?;
+
+        let _guards = self.lock_keys([&edge.from, &edge.to]).await;
+        let shard = self.settle(&edge.from).await?;
+        let target = self.settle(&edge.to).await?;
+        if shard.storage().get_edge(id).await?.is_some() {
+            shard.storage().delete_edge(id).await?;
+            shard.remove_from_bloom(&id.uuid);
+        }
+        target.storage().delete_reverse_edge(&edge.to, id).await
+    }
+
+    /// Find an edge without knowing its source node, asking every shard
+    pub async fn find_edge(&self, id: &EdgeId) -> Result<Option<Edge>> {
+        let lookups = self.shards().into_iter().map(|shard| async move {
+            shard.storage().get_edge(id).await
+        });
+        let found = futures::future::try_join_all(lookups).await?;
+        Ok(found.into_iter().flatten().next())
     }
 
     /// Get an edge by ID
     pub async fn get_edge(&self, id: &EdgeId, from_node: &NodeId) -> Result<Option<Edge>> {
-        let shard = self.get_shard_for_node(from_node);
-        shard.storage().get_edge(id).await
+        let (shard, previous) = self.routing.read().route(&from_node.uuid);
+        if let Some(edge) = shard.storage().get_edge(id).await? {
+            return Ok(Some(edge));
+        }
+
+        match previous {
+            Some(previous) => previous.storage().get_edge(id).await,
+            None => Ok(None),
+        }
     }
 
     /// Get edges from a node
     pub async fn get_edges_from(&self, node_id: &NodeId, edge_type: Option<&str>) -> Result<Vec<Edge>> {
-        let shard = self.get_shard_for_node(node_id);
-        shard.storage().get_edges_from(node_id, edge_type).await
+        let (shard, previous) = self.routing.read().route(&node_id.uuid);
+        let mut edges = shard.storage().get_edges_from(node_id, edge_type).await?;
+
+        if let Some(previous) = previous {
+            let seen: HashSet<EdgeId> = edges.iter().map(|e| e.id.clone()).collect();
+            let remaining = previous.storage().get_edges_from(node_id, edge_type).await?;
+            edges.extend(remaining.into_iter().filter(|e| !seen.contains(&e.id)));
+        }
+
+        Ok(edges)
+    }
+
+    /// Get edges into a node, from the reverse index on its shard
+    pub async fn get_edges_to(&self, node_id: &NodeId, edge_type: Option<&str>) -> Result<Vec<Edge>> {
+        let (shard, previous) = self.routing.read().route(&node_id.uuid);
+        let mut edges = shard.storage().get_reverse_edges(node_id, edge_type).await?;
+
+        if let Some(previous) = previous {
+            let seen: HashSet<EdgeId> = edges.iter().map(|e| e.id.clone()).collect();
+            let remaining = previous.storage().get_reverse_edges(node_id, edge_type).await?;
+            edges.extend(remaining.into_iter().filter(|e| !seen.contains(&e.id)));
+        }
+
+        Ok(edges)
     }
 
     /// Get nodes by type across all shards
+    ///
+    /// Every shard is asked for the full limit, since any one of them may
+    /// hold all the matches; results are concatenated in shard order.
     pub async fn get_nodes_by_type(&self, node_type: &str, limit: Option<usize>) -> Result<Vec<Node>> {
-        let mut all_nodes = Vec::new();
-        let per_shard_limit = limit.map(|l| (l / self.shards.len()).max(1));
+        let per_shard = self.scan_nodes(node_type, limit).await?;
+        let mut all_nodes: Vec<Node> = per_shard.into_iter().flatten().collect();
+        if let Some(lim) = limit {
+            all_nodes.truncate(lim);
+        }
+        Ok(all_nodes)
+    }
 
-        for shard in &self.shards {
-            let nodes = shard.storage().get_nodes_by_type(node_type, per_shard_limit).await?;
-            all_nodes.extend(nodes);
+    /// Nodes of a type on each shard, scanned in parallel
+    ///
+    /// A node caught mid-move can briefly be on two shards; only the copy
+    /// on its owner is kept.
+    pub async fn scan_nodes(&self, node_type: &str, limit: Option<usize>) -> Result<Vec<Vec<Node>>> {
+        let (shards, migrating) = {
+            let routing = self.routing.read();
+            (routing.shards.values().cloned().collect::<Vec<_>>(), routing.previous.is_some())
+        };
 
-            if let Some(lim) = limit {
-                if all_nodes.len() >= lim {
-                    all_nodes.truncate(lim);
-                    break;
-                }
+        let scans = shards.iter().map(|shard| shard.storage().get_nodes_by_type(node_type, limit));
+        let mut per_shard = futures::future::try_join_all(scans).await?;
+
+        if migrating {
+            let owned: Vec<Vec<bool>> = {
+                let routing = self.routing.read();
+                per_shard
+                    .iter()
+                    .zip(&shards)
+                    .map(|(nodes, shard)| {
+                        nodes.iter().map(|n| routing.ring.get_node(&n.id.uuid) == shard.id).collect()
+                    })
+                    .collect()
+            };
+            let on_owner: HashSet<NodeId> = per_shard
+                .iter()
+                .flatten()
+                .zip(owned.iter().flatten())
+                .filter(|(_, owned)| **owned)
+                .map(|(n, _)| n.id.clone())
+                .collect();
+            for (nodes, owned) in per_shard.iter_mut().zip(owned) {
+                let mut owned = owned.into_iter();
+                nodes.retain(|n| owned.next() == Some(true) || !on_owner.contains(&n.id));
             }
         }
 
-        Ok(all_nodes)
+        Ok(per_shard)
+    }
+
+    /// Run `fragment` over the nodes of a type on every shard
+    ///
+    /// Each shard is read on its own blocking thread and only the
+    /// fragment's result comes back. While keys are being moved, a copy on
+    /// a shard that no longer owns the node is skipped if the owner has it.
+    pub async fn scan_fragments<T, F>(&self, node_type: &str, fragment: F) -> Result<Vec<T>>
+    where
+        T: Send + 'static,
+        F: Fn(&mut dyn Iterator<Item = Node>) -> T + Send + Sync + 'static,
+    {
+        let (shards, moving) = {
+            let routing = self.routing.read();
+            let moving = routing.previous.is_some().then(|| Arc::new(routing.ring.clone()));
+            (Arc::new(routing.shards.clone()), moving)
+        };
+        let fragment = Arc::new(fragment);
+
+        let tasks: Vec<_> = shards
+            .keys()
+            .map(|&id| {
+                let (shards, moving, fragment) = (shards.clone(), moving.clone(), fragment.clone());
+                let node_type = node_type.to_string();
+                tokio::task::spawn_blocking(move || {
+                    scan_fragment(&shards, id, moving.as_deref(), &node_type, &*fragment)
+                })
+            })
+            .collect();
+
+        let mut results = Vec::with_capacity(tasks.len());
+        for task in tasks {
+            results.push(task.await??);
+        }
+        Ok(results)
     }
 
     /// Get statistics across all shards
     pub async fn stats(&self) -> Result<ShardStats> {
+        let (shards, migration) = {
+            let routing = self.routing.read();
+            (routing.shards.values().cloned().collect::<Vec<_>>(), routing.layout.migration)
+        };
+
         let mut total_nodes = 0;
         let mut total_edges = 0;
         let mut total_size = 0;
         let mut shard_stats = Vec::new();
 
-        for shard in &self.shards {
+        for shard in &shards {
             let stats = shard.storage().stats().await?;
             total_nodes += stats.node_count;
             total_edges += stats.edge_count;
@@ -231,14 +697,410 @@ impl ShardManager {
             });
         }
 
-        Ok(ShardStats {
-            num_shards: self.shards.len(),
+        let stats = ShardStats {
+            num_shards: shards.len(),
             total_nodes,
             total_edges,
             total_size,
             shards: shard_stats,
+            migration,
+        };
+        stats.record();
+        Ok(stats)
+    }
+
+    // ========== Graph Traversal ==========
+
+    /// Breadth-first traversal from `start`
+    ///
+    /// Each level is expanded with one batch of lookups per shard, run
+    /// concurrently, so crossing shards costs a round of fan-out rather
+    /// than a lookup per edge.
+    pub async fn traverse(
+        &self,
+        start: &NodeId,
+        max_depth: u32,
+        edge_types: Option<&[String]>,
+    ) -> Result<TraversalResult> {
+        let root = self
+            .get_node(start)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Start node not found: {}", start))?;
+
+        let mut visited: HashSet<NodeId> = HashSet::from([start.clone()]);
+        let mut nodes = vec![root.clone()];
+        let mut edges = Vec::new();
+        let mut adjacency = BTreeMap::new();
+        let mut frontier = vec![start.clone()];
+
+        for _ in 0..max_depth {
+            if frontier.is_empty() {
+                break;
+            }
+
+            let mut next = Vec::new();
+            for (id, outgoing) in self.expand(&frontier, edge_types).await? {
+                adjacency.insert(id.to_string(), outgoing.iter().map(|e| e.to.to_string()).collect());
+                for edge in outgoing {
+                    if visited.insert(edge.to.clone()) {
+                        next.push(edge.to.clone());
+                    }
+                    edges.push(edge);
+                }
+            }
+
+            let found = self.scatter(&next, |id| self.get_node(id)).await?;
+            nodes.extend(found.into_iter().filter_map(|(_, node)| node));
+            frontier = next;
+        }
+
+        Ok(TraversalResult {
+            root,
+            nodes,
+            edges,
+            depth: max_depth,
+            adjacency,
         })
     }
+
+    /// Shortest path of at most `max_depth` edges from `from` to `to`
+    pub async fn shortest_path(&self, from: &NodeId, to: &NodeId, max_depth: u32) -> Result<Option<Vec<Node>>> {
+        let mut parents: HashMap<NodeId, NodeId> = HashMap::new();
+        let mut visited: HashSet<NodeId> = HashSet::from([from.clone()]);
+        let mut frontier = vec![from.clone()];
+        let mut found = from == to;
+
+        for _ in 0..max_depth {
+            if found || frontier.is_empty() {
+                break;
+            }
+
+            let mut next = Vec::new();
+            for (id, outgoing) in self.expand(&frontier, None).await? {
+                for edge in outgoing {
+                    if visited.insert(edge.to.clone()) {
+                        parents.insert(edge.to.clone(), id.clone());
+                        found |= &edge.to == to;
+                        next.push(edge.to);
+                    }
+                }
+            }
+            frontier = next;
+        }
+
+        if !found {
+            return Ok(None);
+        }
+
+        let mut path = vec![to.clone()];
+        while let Some(parent) = parents.get(path.last().unwrap()) {
+            path.push(parent.clone());
+        }
+        path.reverse();
+
+        let mut nodes: HashMap<NodeId, Node> = self
+            .scatter(&path, |id| self.get_node(id))
+            .await?
+            .into_iter()
+            .filter_map(|(id, node)| Some((id, node?)))
+            .collect();
+        Ok(Some(path.iter().filter_map(|id| nodes.remove(id)).collect()))
+    }
+
+    /// Outgoing edges of every node in `frontier`, filtered by type
+    async fn expand(
+        &self,
+        frontier: &[NodeId],
+        edge_types: Option<&[String]>,
+    ) -> Result<Vec<(NodeId, Vec<Edge>)>> {
+        let mut expanded = self.scatter(frontier, |id| self.get_edges_from(id, None)).await?;
+        if let Some(types) = edge_types {
+            for (_, edges) in &mut expanded {
+                edges.retain(|e| types.contains(&e.edge_type));
+            }
+        }
+        Ok(expanded)
+    }
+
+    /// Run `lookup` for each ID, batched per owning shard with the batches
+    /// running concurrently
+    async fn scatter<'a, T, F, Fut>(&self, ids: &'a [NodeId], lookup: F) -> Result<Vec<(NodeId, T)>>
+    where
+        F: Fn(&'a NodeId) -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
+    {
+        let mut batches: BTreeMap<usize, Vec<&'a NodeId>> = BTreeMap::new();
+        {
+            let routing = self.routing.read();
+            for id in ids {
+                batches.entry(routing.ring.get_node(&id.uuid)).or_default().push(id);
+            }
+        }
+
+        let lookup = &lookup;
+        let batches = batches.into_values().map(|batch| async move {
+            let mut results = Vec::with_capacity(batch.len());
+            for id in batch {
+                results.push((id.clone(), lookup(id).await?));
+            }
+            Ok::<_, anyhow::Error>(results)
+        });
+
+        Ok(futures::future::try_join_all(batches).await?.into_iter().flatten().collect())
+    }
+
+    // ========== Rebalancing ==========
+
+    /// Add a new shard and return its ID
+    ///
+    /// The ring switches immediately; keys that now belong to the new
+    /// shard are moved by [`rebalance`](Self::rebalance).
+    pub async fn add_shard(&self) -> Result<usize> {
+        self.ensure_settled()?;
+        let id = self.routing.read().layout.shards.iter().max().map_or(0, |&max| max + 1);
+        let shard = Arc::new(Shard::new(id, &self.config.base_path).await?);
+
+        self.change_layout(|layout, shards| {
+            layout.shards.push(id);
+            layout.migration = Some(ShardMigration::Adding(id));
+            shards.insert(id, shard);
+            Ok(())
+        })
+        .await?;
+
+        tracing::info!("Added shard {}", id);
+        Ok(id)
+    }
+
+    /// Start removing shard `id`
+    ///
+    /// New writes stop going to it at once; its keys are moved to the
+    /// remaining shards by [`rebalance`](Self::rebalance), after which it
+    /// is deleted.
+    pub async fn remove_shard(&self, id: usize) -> Result<()> {
+        self.ensure_settled()?;
+        self.change_layout(|layout, _| {
+            if !layout.shards.contains(&id) {
+                bail!("Shard {} does not exist", id);
+            }
+            if layout.shards.len() == 1 {
+                bail!("Cannot remove the last shard");
+            }
+            layout.migration = Some(ShardMigration::Removing(id));
+            Ok(())
+        })
+        .await?;
+
+        tracing::info!("Removing shard {}", id);
+        Ok(())
+    }
+
+    /// Move keys for the pending layout change, if any
+    ///
+    /// Reads and writes keep working while this runs. Only shards that
+    /// lose keys are scanned, and only keys whose owner changed are moved.
+    pub async fn rebalance(&self) -> Result<RebalanceProgress> {
+        let _running = self.rebalancing.lock().await;
+
+        let (migration, sources) = {
+            let routing = self.routing.read();
+            let Some(migration) = routing.layout.migration else {
+                return Ok(self.rebalance_progress());
+            };
+            let sources: Vec<Arc<Shard>> = match migration {
+                ShardMigration::Adding(new) => {
+                    routing.shards.values().filter(|s| s.id != new).cloned().collect()
+                }
+                ShardMigration::Removing(old) => vec![routing.shards[&old].clone()],
+            };
+            (migration, sources)
+        };
+
+        let mut total = 0;
+        for source in &sources {
+            total += source.storage().stats().await?.node_count;
+        }
+        *self.progress.write() = RebalanceProgress {
+            migration: Some(migration),
+            total,
+            ..Default::default()
+        };
+
+        for source in &sources {
+            let ids: Vec<NodeId> = source
+                .storage()
+                .get_all_nodes(None)
+                .await?
+                .into_iter()
+                .map(|node| node.id)
+                .collect();
+
+            for id in ids {
+                let (moved, edges) = {
+                    let _guard = self.key_lock(&id.uuid).lock().await;
+                    let (owner, previous) = self.routing.read().route(&id.uuid);
+                    match previous {
+                        Some(previous) if previous.id == source.id => {
+                            Self::relocate(&id, &previous, &owner).await?
+                        }
+                        _ => (false, 0),
+                    }
+                };
+
+                let mut progress = self.progress.write();
+                progress.scanned += 1;
+                progress.moved += moved as u64;
+                progress.edges_moved += edges as u64;
+            }
+        }
+
+        let removed = self
+            .change_layout(|layout, shards| {
+                layout.migration = None;
+                if let ShardMigration::Removing(id) = migration {
+                    layout.shards.retain(|&s| s != id);
+                    return Ok(shards.remove(&id));
+                }
+                Ok(None)
+            })
+            .await?;
+
+        if let Some(shard) = removed {
+            let id = shard.id.to_string();
+            let path = shard.storage().path().to_path_buf();
+            drop(shard);
+            std::fs::remove_dir_all(&path)
+                .with_context(|| format!("Failed to delete {}", path.display()))?;
+
+            // Stop exporting the gauges of a shard that no longer exists
+            let m = metrics();
+            let _ = m.shard_nodes.remove_label_values(&[&id]);
+            let _ = m.shard_size.remove_label_values(&[&id]);
+        }
+
+        let mut progress = self.progress.write();
+        progress.migration = None;
+        tracing::info!(
+            "Rebalance finished ({:?}): moved {} nodes and {} edges",
+            migration, progress.moved, progress.edges_moved
+        );
+        Ok(progress.clone())
+    }
+
+    /// Run [`rebalance`](Self::rebalance) on a background task
+    pub fn start_rebalance(self: &Arc<Self>) -> tokio::task::JoinHandle<Result<RebalanceProgress>> {
+        let manager = self.clone();
+        tokio::spawn(async move { manager.rebalance().await })
+    }
+
+    // === Private methods ===
+
+    fn key_lock(&self, key: &[u8]) -> &tokio::sync::Mutex<()> {
+        &self.key_locks[Self::stripe(key)]
+    }
+
+    fn stripe(key: &[u8]) -> usize {
+        (xxh3_64(key) % KEY_LOCKS as u64) as usize
+    }
+
+    /// Lock several keys, taking stripes in order so callers cannot deadlock
+    async fn lock_keys<'a>(
+        &self,
+        ids: impl IntoIterator<Item = &'a NodeId>,
+    ) -> Vec<tokio::sync::MutexGuard<'_, ()>> {
+        let stripes: BTreeSet<usize> = ids.into_iter().map(|id| Self::stripe(&id.uuid)).collect();
+        let mut guards = Vec::with_capacity(stripes.len());
+        for stripe in stripes {
+            guards.push(self.key_locks[stripe].lock().await);
+        }
+        guards
+    }
+
+    /// Current owner of `id`
+    fn owner(&self, id: &NodeId) -> Arc<Shard> {
+        self.routing.read().route(&id.uuid).0
+    }
+
+    /// Shard that owns `id`, after pulling the key over from its previous
+    /// owner. The caller holds the key's lock.
+    async fn settle(&self, id: &NodeId) -> Result<Arc<Shard>> {
+        let (owner, previous) = self.routing.read().route(&id.uuid);
+        if let Some(previous) = previous {
+            Self::relocate(id, &previous, &owner).await?;
+        }
+        Ok(owner)
+    }
+
+    /// Move a node, its outgoing edges and its reverse-index entries from
+    /// one shard to another
+    ///
+    /// A copy already on the target was written after the layout changed
+    /// and wins. Returns whether a node moved and how many edges did.
+    async fn relocate(id: &NodeId, from: &Shard, to: &Shard) -> Result<(bool, usize)> {
+        let node = from.storage().get_node(id).await?;
+        let edges = from.storage().get_edges_from(id, None).await?;
+
+        for edge in from.storage().get_reverse_edges(id, None).await? {
+            to.storage().insert_reverse_edge(&edge).await?;
+            from.storage().delete_reverse_edge(id, &edge.id).await?;
+        }
+
+        if let Some(node) = &node {
+            if to.storage().get_node(id).await?.is_none() {
+                to.add_to_bloom(&id.uuid);
+                to.storage().insert_node(node).await?;
+            }
+        }
+
+        for edge in &edges {
+            to.add_to_bloom(&edge.id.uuid);
+            to.storage().insert_edge(edge).await?;
+            from.storage().delete_edge(&edge.id).await?;
+            from.remove_from_bloom(&edge.id.uuid);
+        }
+
+        if node.is_some() {
+            from.storage().evict_node(id).await?;
+            from.remove_from_bloom(&id.uuid);
+        }
+
+        Ok((node.is_some(), edges.len()))
+    }
+
+    fn ensure_settled(&self) -> Result<()> {
+        match self.routing.read().layout.migration {
+            Some(ShardMigration::Adding(id)) => {
+                bail!("Shard {} is still being added; finish the rebalance first", id)
+            }
+            Some(ShardMigration::Removing(id)) => {
+                bail!("Shard {} is still being removed; finish the rebalance first", id)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Apply a layout change, persist it and switch routing
+    ///
+    /// All key locks are held across the switch so no write is routed
+    /// with a stale ring.
+    async fn change_layout<T>(
+        &self,
+        change: impl FnOnce(&mut ShardLayout, &mut BTreeMap<usize, Arc<Shard>>) -> Result<T>,
+    ) -> Result<T> {
+        let mut guards = Vec::with_capacity(self.key_locks.len());
+        for lock in &self.key_locks {
+            guards.push(lock.lock().await);
+        }
+
+        let mut routing = self.routing.write();
+        let mut layout = routing.layout.clone();
+        let mut shards = routing.shards.clone();
+        let result = change(&mut layout, &mut shards)?;
+
+        layout.save(&self.config.base_path)?;
+        *routing = Routing::new(layout, shards);
+        Ok(result)
+    }
 }
 
 /// Statistics for all shards
@@ -249,6 +1111,30 @@ pub struct ShardStats {
     pub total_edges: u64,
     pub total_size: u64,
     pub shards: Vec<SingleShardStats>,
+    /// Layout change still being applied
+    pub migration: Option<ShardMigration>,
+}
+
+impl ShardStats {
+    /// Nodes on the fullest shard over the mean; 1 is perfectly even
+    pub fn imbalance(&self) -> f64 {
+        let max = self.shards.iter().map(|s| s.node_count).max().unwrap_or(0);
+        if self.total_nodes == 0 {
+            return 1.0;
+        }
+        max as f64 * self.shards.len() as f64 / self.total_nodes as f64
+    }
+
+    /// Publish per-shard gauges
+    fn record(&self) {
+        let m = metrics();
+        for shard in &self.shards {
+            let id = shard.id.to_string();
+            m.shard_nodes.with_label_values(&[&id]).set(shard.node_count as i64);
+            m.shard_size.with_label_values(&[&id]).set(shard.size_bytes as i64);
+        }
+        m.shard_imbalance.set(self.imbalance());
+    }
 }
 
 /// Statistics for a single shard
@@ -260,7 +1146,51 @@ pub struct SingleShardStats {
     pub size_bytes: u64,
 }
 
+/// Run `fragment` over shard `id`'s nodes of `node_type`
+///
+/// `moving` is the ring keys are moving to, if a move is in progress.
+fn scan_fragment<T>(
+    shards: &BTreeMap<usize, Arc<Shard>>,
+    id: usize,
+    moving: Option<&ConsistentHashRing>,
+    node_type: &str,
+    fragment: &dyn Fn(&mut dyn Iterator<Item = Node>) -> T,
+) -> Result<T> {
+    let view = shards[&id].storage().read_view()?;
+    let mut owners: HashMap<usize, ReadView> = HashMap::new();
+    let mut scanned = 0u64;
+    let mut keep = |node: Result<Node>| -> Result<Option<Node>> {
+        let node = node?;
+        scanned += 1;
+        let Some(ring) = moving else { return Ok(Some(node)) };
+        let owner = ring.get_node(&node.id.uuid);
+        if owner == id {
+            return Ok(Some(node));
+        }
+        let on_owner = match owners.entry(owner) {
+            Entry::Occupied(view) => view.into_mut(),
+            Entry::Vacant(slot) => slot.insert(shards[&owner].storage().read_view()?),
+        };
+        Ok((!on_owner.contains_node(&node.id)?).then_some(node))
+    };
+
+    let mut error = None;
+    let result = {
+        let mut nodes = view
+            .nodes_of_type(node_type)?
+            .map_while(|node| keep(node).map_err(|e| error = Some(e)).ok())
+            .flatten();
+        fragment(&mut nodes)
+    };
+    metrics().rows_scanned.inc_by(scanned);
+    match error {
+        Some(e) => Err(e),
+        None => Ok(result),
+    }
+}
+
 /// Consistent hash ring for distributing keys across shards
+#[derive(Clone)]
 struct ConsistentHashRing {
     /// Number of virtual nodes per physical node
     virtual_nodes: usize,
@@ -276,6 +1206,14 @@ impl ConsistentHashRing {
         }
     }
 
+    fn with_shards(virtual_nodes: usize, shards: impl IntoIterator<Item = usize>) -> Self {
+        let mut ring = Self::new(virtual_nodes);
+        for id in shards {
+            ring.add_node(id);
+        }
+        ring
+    }
+
     fn add_node(&mut self, node_id: usize) {
         for i in 0..self.virtual_nodes {
             let key = format!("node_{}_{}", node_id, i);
@@ -396,5 +1334,210 @@ mod tests {
         for shard_stat in &stats.shards {
             assert!(shard_stat.node_count > 0, "Shard {} has no nodes", shard_stat.id);
         }
+        assert!(stats.imbalance() >= 1.0 && stats.imbalance() < stats.num_shards as f64);
+    }
+
+    async fn populate(manager: &ShardManager, count: usize) -> Vec<NodeId> {
+        let mut ids: Vec<NodeId> = Vec::new();
+        for i in 0..count {
+            let node = Node::new("user", Value::from_json(serde_json::json!({ "n": i })).unwrap());
+            manager.insert_node(&node).await.unwrap();
+            if let Some(prev) = ids.last() {
+                let edge = Edge::new(node.id.clone(), prev.clone(), "follows", Value::Null);
+                manager.insert_edge(&edge).await.unwrap();
+            }
+            ids.push(node.id);
+        }
+        ids
+    }
+
+    #[tokio::test]
+    async fn test_add_shard_moves_only_affected_keys() {
+        let temp = TempDir::new().unwrap();
+        let config = ShardConfig {
+            num_shards: 4,
+            virtual_nodes: 100,
+            base_path: temp.path().to_path_buf(),
+        };
+        let manager = ShardManager::new(config).await.unwrap();
+        let ids = populate(&manager, 200).await;
+        let before: Vec<usize> = ids.iter().map(|id| manager.get_shard_for_node(id).id).collect();
+
+        let new_id = manager.add_shard().await.unwrap();
+        assert_eq!(new_id, 4);
+        assert!(manager.add_shard().await.is_err(), "one change at a time");
+
+        // Nothing has moved yet, but every key is still readable
+        for id in &ids {
+            assert!(manager.get_node(id).await.unwrap().is_some());
+        }
+        let updated = manager
+            .update_node(&ids[10], Value::from_json(serde_json::json!({ "n": -1 })).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(updated.id, ids[10]);
+
+        let progress = manager.rebalance().await.unwrap();
+        assert_eq!(progress.migration, None);
+        assert_eq!(progress.scanned, progress.total);
+
+        let stats = manager.stats().await.unwrap();
+        assert_eq!(stats.num_shards, 5);
+        assert_eq!(stats.total_nodes, 200);
+        assert_eq!(stats.total_edges, 199);
+        let owned = ids.iter().filter(|id| manager.get_shard_for_node(id).id == new_id).count();
+        assert!(owned > 0);
+        assert_eq!(stats.shards[4].node_count, owned as u64);
+
+        for (i, id) in ids.iter().enumerate() {
+            let owner = manager.get_shard_for_node(id);
+            assert!(owner.id == before[i] || owner.id == new_id, "key moved between old shards");
+            assert!(owner.storage().get_node(id).await.unwrap().is_some());
+            if i > 0 {
+                assert_eq!(manager.get_edges_from(id, None).await.unwrap().len(), 1);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_remove_shard_while_serving() {
+        let temp = TempDir::new().unwrap();
+        let config = ShardConfig {
+            num_shards: 3,
+            virtual_nodes: 100,
+            base_path: temp.path().to_path_buf(),
+        };
+        let manager = Arc::new(ShardManager::new(config.clone()).await.unwrap());
+        let ids = populate(&manager, 150).await;
+
+        manager.remove_shard(1).await.unwrap();
+        let task = manager.start_rebalance();
+
+        // Reads and writes keep working while keys move
+        let more = populate(&manager, 50).await;
+        for id in ids.iter().chain(&more) {
+            assert!(manager.get_node(id).await.unwrap().is_some());
+        }
+        manager.delete_node(&ids[0]).await.unwrap();
+
+        task.await.unwrap().unwrap();
+        assert!(!temp.path().join("shard_0001").exists());
+
+        let stats = manager.stats().await.unwrap();
+        assert_eq!(stats.num_shards, 2);
+        assert_eq!(stats.total_nodes, 199);
+        for id in ids.iter().skip(1).chain(&more) {
+            assert!(manager.get_node(id).await.unwrap().is_some());
+        }
+        drop(manager);
+
+        // The layout survives a restart regardless of the configured count
+        let reopened = ShardManager::open(ShardConfig { num_shards: 16, ..config }).await.unwrap();
+        assert_eq!(reopened.layout().shards, vec![0, 2]);
+        assert_eq!(reopened.stats().await.unwrap().total_nodes, 199);
+        for id in ids.iter().skip(1).chain(&more) {
+            assert!(reopened.get_node(id).await.unwrap().is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shard_bloom_saved_and_rebuilt() {
+        let temp = TempDir::new().unwrap();
+        async fn insert(shard: &Shard, n: usize, track: bool) -> Vec<Node> {
+            let nodes: Vec<Node> = (0..n).map(|i| Node::new("user", Value::Int(i as i64))).collect();
+            for node in &nodes {
+                if track {
+                    shard.add_to_bloom(&node.id.uuid);
+                }
+                shard.storage().insert_node(node).await.unwrap();
+            }
+            nodes
+        }
+
+        let shard = Shard::new(0, temp.path()).await.unwrap();
+        let mut nodes = insert(&shard, 50, true).await;
+        shard.remove_from_bloom(&nodes[0].id.uuid);
+        shard.storage().delete_node(&nodes[0].id).await.unwrap();
+        nodes.remove(0);
+        drop(shard);
+
+        // A cleanly closed shard reloads its saved filter
+        let shard = Shard::open(0, temp.path()).await.unwrap();
+        assert_eq!(shard.bloom.read().count(), 49);
+        assert!(nodes.iter().all(|n| shard.may_contain(&n.id.uuid)));
+        assert!(shard.storage().get_metadata(BLOOM_KEY).unwrap().is_none());
+
+        // Writes the filter missed make the saved copy stale
+        nodes.extend(insert(&shard, 10, false).await);
+        drop(shard);
+
+        let shard = Shard::open(0, temp.path()).await.unwrap();
+        assert_eq!(shard.bloom.read().count(), 59);
+        assert!(nodes.iter().all(|n| shard.may_contain(&n.id.uuid)));
+    }
+
+    #[tokio::test]
+    async fn test_cross_shard_edges_and_traversal() {
+        let temp = TempDir::new().unwrap();
+        let config = ShardConfig {
+            num_shards: 4,
+            virtual_nodes: 100,
+            base_path: temp.path().to_path_buf(),
+        };
+        let manager = ShardManager::new(config).await.unwrap();
+
+        // A chain of 20 nodes; consecutive nodes mostly sit on different shards
+        let mut ids = Vec::new();
+        for i in 0..20 {
+            let node = Node::new("user", Value::from_json(serde_json::json!({ "n": i })).unwrap());
+            manager.insert_node(&node).await.unwrap();
+            ids.push(node.id);
+        }
+        let mut edges = Vec::new();
+        for pair in ids.windows(2) {
+            let edge = Edge::new(pair[0].clone(), pair[1].clone(), "next", Value::Null);
+            manager.insert_edge(&edge).await.unwrap();
+            edges.push(edge);
+        }
+        let shortcut = Edge::new(ids[0].clone(), ids[10].clone(), "skip", Value::Null);
+        manager.insert_edge(&shortcut).await.unwrap();
+        assert!(ids.windows(2).any(|p| manager.get_shard_for_node(&p[0]).id != manager.get_shard_for_node(&p[1]).id));
+
+        let incoming = manager.get_edges_to(&ids[10], None).await.unwrap();
+        assert_eq!(incoming.len(), 2);
+        assert_eq!(manager.get_edges_to(&ids[10], Some("skip")).await.unwrap().len(), 1);
+        assert_eq!(manager.find_edge(&edges[5].id).await.unwrap().unwrap().to, ids[6]);
+
+        let traversal = manager.traverse(&ids[0], 3, None).await.unwrap();
+        assert_eq!(traversal.nodes.len(), 7); // 0-3 along the chain, 10-12 via the shortcut
+        let next_only = ["next".to_string()];
+        let traversal = manager.traverse(&ids[0], 3, Some(&next_only)).await.unwrap();
+        assert_eq!(traversal.nodes.len(), 4);
+
+        let path = manager.shortest_path(&ids[0], &ids[12], 5).await.unwrap().unwrap();
+        let path: Vec<NodeId> = path.into_iter().map(|n| n.id).collect();
+        assert_eq!(path, vec![ids[0].clone(), ids[10].clone(), ids[11].clone(), ids[12].clone()]);
+        assert!(manager.shortest_path(&ids[0], &ids[19], 5).await.unwrap().is_none());
+        assert!(manager.shortest_path(&ids[5], &ids[0], 10).await.unwrap().is_none());
+
+        // Deleting by ID removes both the edge and its reverse entry
+        manager.delete_edge(&shortcut.id).await.unwrap();
+        assert_eq!(manager.get_edges_to(&ids[10], None).await.unwrap().len(), 1);
+        assert!(manager.get_edges_from(&ids[0], Some("skip")).await.unwrap().is_empty());
+        assert!(manager.delete_edge(&shortcut.id).await.is_err());
+
+        // Deleting a node removes its edges on every shard
+        manager.delete_node(&ids[5]).await.unwrap();
+        assert!(manager.get_edges_from(&ids[4], None).await.unwrap().is_empty());
+        assert!(manager.get_edges_to(&ids[6], None).await.unwrap().is_empty());
+        assert_eq!(manager.stats().await.unwrap().total_edges, 17);
+
+        // Reverse entries follow their node when shards are added
+        manager.add_shard().await.unwrap();
+        manager.rebalance().await.unwrap();
+        for i in 7..20 {
+            assert_eq!(manager.get_edges_to(&ids[i], None).await.unwrap().len(), 1, "node {}", i);
+        }
+        assert_eq!(manager.traverse(&ids[6], 20, None).await.unwrap().nodes.len(), 14);
     }
 }
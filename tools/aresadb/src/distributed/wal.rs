@@ -13,8 +13,12 @@ use std::io::{BufReader, BufWriter, Read, Write, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
 
+use crate::metrics::metrics;
 use crate::storage::{Node, Edge, NodeId, EdgeId, Value, Timestamp};
 
+/// `wal_size` label of this log, fixed whatever its file is called
+const METRIC_LABEL: &str = "data";
+
 /// Entry type in the WAL
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum WalEntryType {
@@ -120,6 +124,31 @@ impl WalEntry {
         let entry: WalEntry = deserialize(entry_data)?;
         Ok((entry, 8 + len))
     }
+
+    /// Encode entries as a standalone segment, framed as in the log file
+    pub fn encode_segment(entries: &[WalEntry]) -> Result<Vec<u8>> {
+        let mut segment = Vec::new();
+        for entry in entries {
+            segment.extend_from_slice(&entry.to_bytes()?);
+        }
+        Ok(segment)
+    }
+
+    /// Decode a segment written by [`encode_segment`](Self::encode_segment)
+    ///
+    /// Unlike recovery from the log file, a torn or corrupt entry anywhere
+    /// in the segment is an error.
+    pub fn decode_segment(data: &[u8]) -> Result<Vec<WalEntry>> {
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        while offset < data.len() {
+            let (entry, consumed) = Self::from_bytes(&data[offset..])
+                .with_context(|| format!("Bad WAL segment entry at byte {}", offset))?;
+            entries.push(entry);
+            offset += consumed;
+        }
+        Ok(entries)
+    }
 }
 
 /// Write-Ahead Log manager
@@ -132,6 +161,8 @@ pub struct WriteAheadLog {
     lsn: AtomicU64,
     /// Sync mode (fsync on every write)
     sync_mode: bool,
+    /// Bytes in the WAL file
+    size: AtomicU64,
 }
 
 impl WriteAheadLog {
@@ -153,38 +184,34 @@ impl WriteAheadLog {
 
         // Find the last LSN by scanning the log
         let last_lsn = Self::find_last_lsn(&path).unwrap_or(0);
+        let size = file.metadata()?.len();
 
-        Ok(Self {
+        let wal = Self {
             path,
             file: Mutex::new(BufWriter::new(file)),
             lsn: AtomicU64::new(last_lsn + 1),
             sync_mode,
-        })
+            size: AtomicU64::new(size),
+        };
+        wal.record_size();
+        Ok(wal)
     }
 
     /// Append an entry to the WAL
     pub fn append(&self, entry_type: WalEntryType, data: Vec<u8>) -> Result<u64> {
         let lsn = self.lsn.fetch_add(1, Ordering::SeqCst);
-        let entry = WalEntry::new(lsn, entry_type, data);
-
-        let bytes = entry.to_bytes()?;
-
-        let mut file = self.file.lock();
-        file.write_all(&bytes)?;
-
-        if self.sync_mode {
-            file.flush()?;
-            file.get_ref().sync_data()?;
-        }
-
+        self.write_entry(&WalEntry::new(lsn, entry_type, data))?;
         Ok(lsn)
     }
 
     /// Append entry with transaction ID
     pub fn append_tx(&self, entry_type: WalEntryType, tx_id: u64, data: Vec<u8>) -> Result<u64> {
         let lsn = self.lsn.fetch_add(1, Ordering::SeqCst);
-        let entry = WalEntry::with_tx(lsn, entry_type, tx_id, data);
+        self.write_entry(&WalEntry::with_tx(lsn, entry_type, tx_id, data))?;
+        Ok(lsn)
+    }
 
+    fn write_entry(&self, entry: &WalEntry) -> Result<()> {
         let bytes = entry.to_bytes()?;
 
         let mut file = self.file.lock();
@@ -195,7 +222,19 @@ impl WriteAheadLog {
             file.get_ref().sync_data()?;
         }
 
-        Ok(lsn)
+        self.size.fetch_add(bytes.len() as u64, Ordering::SeqCst);
+        metrics().wal_appends.inc();
+        self.record_size();
+        Ok(())
+    }
+
+    /// Bytes in the WAL file, including writes not yet flushed
+    pub fn size_bytes(&self) -> u64 {
+        self.size.load(Ordering::SeqCst)
+    }
+
+    fn record_size(&self) {
+        metrics().wal_size.with_label_values(&[METRIC_LABEL]).set(self.size_bytes() as i64);
     }
 
     /// Log a node insert
@@ -273,11 +312,13 @@ impl WriteAheadLog {
 
         // Write remaining entries to a new file
         let temp_path = self.path.with_extension("wal.tmp");
+        let mut size = 0;
         {
             let mut temp_file = BufWriter::new(File::create(&temp_path)?);
             for entry in &remaining {
                 let bytes = entry.to_bytes()?;
                 temp_file.write_all(&bytes)?;
+                size += bytes.len() as u64;
             }
             temp_file.flush()?;
         }
@@ -293,6 +334,8 @@ impl WriteAheadLog {
             .open(&self.path)?;
 
         *self.file.lock() = BufWriter::new(file);
+        self.size.store(size, Ordering::SeqCst);
+        self.record_size();
 
         Ok(())
     }
@@ -413,6 +456,8 @@ mod tests {
         wal.append(WalEntryType::InsertNode, vec![2]).unwrap();
         wal.checkpoint().unwrap(); // LSN 3
         wal.append(WalEntryType::InsertNode, vec![4]).unwrap();
+        let before = wal.size_bytes();
+        assert_eq!(before, std::fs::metadata(&wal_path).unwrap().len());
 
         // Truncate before checkpoint
         wal.truncate_before(3).unwrap();
@@ -421,6 +466,8 @@ mod tests {
         let entries = wal.read_all().unwrap();
         assert_eq!(entries.len(), 2);
         assert!(entries[0].lsn >= 3);
+        assert!(wal.size_bytes() < before);
+        assert_eq!(wal.size_bytes(), std::fs::metadata(&wal_path).unwrap().len());
     }
 
     #[test]
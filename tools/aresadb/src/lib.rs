@@ -91,6 +91,7 @@ pub mod query;
 pub mod schema;
 pub mod output;
 pub mod cli;
+pub mod metrics;
 
 // V2: Distributed modules
 pub mod distributed;
@@ -110,7 +111,10 @@ pub use storage::{
     Database, DatabaseConfig, DatabaseStatus,
     Node, Edge, NodeId, EdgeId, Value, Timestamp,
     LocalStorage, BucketStorage, CacheLayer,
-    GraphView, KvView, SyncStats,
+    Change, ChangeEvent, ChangeFilter, ChangeStream, WriteHook, WriteKind,
+    GraphView, KvView, SyncStats, SyncConflict,
+    BackupStore, BackupEntry, BackupKind, RestorePoint, RestoreReport, VerifyReport,
+    DumpFormat, Exporter, ExportReport, Importer, ImportReport,
     ParallelExecutor, ParallelTraversalResult, SnapshotReader,
     VectorIndex, IndexStats,
 };
@@ -118,11 +122,13 @@ pub use storage::{
 pub use query::{
     QueryParser, QueryEngine, QueryResult, TraversalResult,
     ParsedQuery, QueryOperation, Condition, Operator, OrderBy,
+    Aggregate, AggregateFunction, ShardedQueryEngine,
 };
 
 pub use schema::{
     Schema, SchemaField, FieldType, SchemaManager,
     Migration, MigrationAction, MigrationGenerator,
+    Trigger, TriggerStatement, TriggerSet,
 };
 
 pub use distributed::{
@@ -137,15 +143,19 @@ pub use distributed::{
 pub use rag::{
     Chunker, ChunkStrategy, DocumentChunk,
     ContextRetriever, RetrievedContext, ContextChunk,
+    GraphRetriever, ScoreDecay,
+    EntityExtractor, RegexExtractor, LlmExtractor, EntityLinker,
+    Evaluator, EvalConfig, EvalReport, RetrievalMetrics,
     EmbeddingManager, EmbeddingProvider, OpenAIModel,
     HybridSearch, HybridSearchConfig, HybridSearchResult,
+    Tokenizer, BpeTokenizer,
 };
 
 #[cfg(feature = "server")]
 pub use server::{Server, ServerConfig};
 
 #[cfg(feature = "server")]
-pub use client::{Client, ClientBuilder};
+pub use client::{Client, ClientBuilder, ClientPool};
 
 /// Database format version for compatibility checking
 pub const FORMAT_VERSION: u32 = 1;
@@ -8,11 +8,16 @@ use colored::Colorize;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 mod cli;
+#[cfg(feature = "server")]
+mod client;
 mod distributed;
+mod metrics;
 mod output;
 mod query;
 mod rag;
 mod schema;
+#[cfg(feature = "server")]
+mod server;
 mod storage;
 
 use cli::commands::OutputFormat;
@@ -127,6 +132,63 @@ enum Commands {
         url: String,
     },
 
+    /// Back up the database to a directory or bucket URL
+    Backup {
+        /// Backup location (a directory, or s3://..., gs://..., file://...)
+        target: String,
+        /// Back up only the changes since the last backup
+        #[arg(long)]
+        incremental: bool,
+        /// Verify the backups at the location instead of taking one
+        #[arg(long, conflicts_with_all = ["incremental", "list"])]
+        verify: bool,
+        /// List the backups at the location instead of taking one
+        #[arg(long, conflicts_with = "incremental")]
+        list: bool,
+    },
+
+    /// Restore a backup into a new database
+    Restore {
+        /// Backup location
+        target: String,
+        /// Directory to restore into; must not exist or be empty
+        path: String,
+        /// Restore up to and including this change sequence (LSN)
+        #[arg(long, conflicts_with = "to_time")]
+        to_seq: Option<u64>,
+        /// Restore changes committed at or before this RFC 3339 time
+        #[arg(long)]
+        to_time: Option<String>,
+    },
+
+    /// Export every node and edge to JSONL, CSV or Parquet
+    Export {
+        /// Output file (JSONL) or directory (CSV, Parquet)
+        path: String,
+        /// Dump format
+        #[arg(long, value_enum, default_value = "jsonl")]
+        to: storage::DumpFormat,
+    },
+
+    /// Import nodes and edges from a JSONL, CSV or Parquet dump
+    Import {
+        /// Input file (JSONL) or directory (CSV, Parquet)
+        path: String,
+        /// Dump format
+        #[arg(long, value_enum, default_value = "jsonl")]
+        from: storage::DumpFormat,
+        /// CSV column mapping file (default: mapping.json in the directory)
+        #[arg(long)]
+        mapping: Option<String>,
+        /// Give imported nodes and edges new IDs, to add them to a
+        /// non-empty database
+        #[arg(long)]
+        remap_ids: bool,
+        /// Nodes or edges stored per batch
+        #[arg(long, default_value = "10000")]
+        batch_size: usize,
+    },
+
     /// Configuration commands
     Config {
         #[command(subcommand)]
@@ -136,6 +198,35 @@ enum Commands {
     /// Show database status
     Status,
 
+    /// Manage a sharded store
+    Shards {
+        #[command(subcommand)]
+        action: ShardsAction,
+    },
+
+    /// Watch changes to nodes and edges
+    ///
+    /// Prints the change log of a local database, or follows changes live
+    /// on a server.
+    Watch {
+        /// Start after this sequence (default: the whole log locally, new
+        /// changes on a server)
+        #[arg(long)]
+        after: Option<u64>,
+        /// Node types to watch (comma-separated)
+        #[arg(long)]
+        node_types: Option<String>,
+        /// Edge types to watch (comma-separated)
+        #[arg(long)]
+        edge_types: Option<String>,
+        /// Server to follow (host:port)
+        #[arg(long)]
+        server: Option<String>,
+        /// API token for the server
+        #[arg(long)]
+        token: Option<String>,
+    },
+
     /// Insert a node
     Insert {
         /// Node type (table name)
@@ -201,15 +292,18 @@ enum Commands {
         /// Document ID (for tracking chunks)
         #[arg(short, long, default_value = "doc")]
         document_id: String,
-        /// Chunking strategy: fixed, sentence, paragraph, semantic
+        /// Chunking strategy: fixed, sentence, paragraph, semantic, recursive
         #[arg(short, long, default_value = "fixed")]
         strategy: String,
-        /// Chunk size (chars for fixed, tokens for sentence)
+        /// Chunk size (chars for fixed, tokens for sentence and recursive)
         #[arg(short = 'S', long, default_value = "512")]
         size: usize,
-        /// Overlap between chunks (for fixed strategy)
+        /// Overlap between chunks (chars for fixed, tokens for recursive)
         #[arg(short, long, default_value = "50")]
         overlap: usize,
+        /// Tokenizer vocabulary file (tiktoken format, e.g. cl100k_base.tiktoken)
+        #[arg(long)]
+        tokenizer: Option<String>,
         /// Store chunks in database (requires --props for base properties)
         #[arg(long)]
         store: bool,
@@ -240,6 +334,15 @@ enum Commands {
         /// Output format: llm, json, text
         #[arg(short, long, default_value = "llm")]
         output: String,
+        /// Tokenizer vocabulary file (tiktoken format, e.g. cl100k_base.tiktoken)
+        #[arg(long)]
+        tokenizer: Option<String>,
+        /// Expand hits through the graph up to this many edges (0 = vector only)
+        #[arg(long, default_value = "0")]
+        graph_depth: u32,
+        /// Edge types to expand along (comma-separated)
+        #[arg(long, default_value = "next,part_of,mentions")]
+        edge_types: String,
     },
 
     /// Ingest document: chunk + embed + store in one step
@@ -259,16 +362,56 @@ enum Commands {
         /// OpenAI API key (or set OPENAI_API_KEY)
         #[arg(long)]
         api_key: Option<String>,
-        /// Chunk size
+        /// Chunking strategy: fixed, sentence, paragraph, semantic, recursive
+        #[arg(short, long, default_value = "fixed")]
+        strategy: String,
+        /// Chunk size (chars for fixed, tokens for sentence and recursive)
         #[arg(short = 'S', long, default_value = "512")]
         chunk_size: usize,
-        /// Chunk overlap
+        /// Chunk overlap (chars for fixed, tokens for recursive)
         #[arg(short = 'O', long, default_value = "50")]
         overlap: usize,
+        /// Tokenizer vocabulary file (tiktoken format, e.g. cl100k_base.tiktoken)
+        #[arg(long)]
+        tokenizer: Option<String>,
+        /// Extract entities and relations into the graph: regex, llm
+        #[arg(long)]
+        extract: Option<String>,
+        /// Base URL of an OpenAI-compatible endpoint for llm extraction
+        #[arg(long)]
+        llm_url: Option<String>,
+        /// Chat model for llm extraction
+        #[arg(long)]
+        llm_model: Option<String>,
         /// Additional properties (JSON)
         #[arg(long)]
         props: Option<String>,
     },
+
+    /// Evaluate retrieval quality over labelled queries
+    Eval {
+        /// JSONL file of {"query": ..., "relevant": [ids]} lines
+        queries: String,
+        /// Embedding provider: openai, local
+        #[arg(short, long, default_value = "local")]
+        provider: String,
+        /// OpenAI API key (or set OPENAI_API_KEY)
+        #[arg(long)]
+        api_key: Option<String>,
+        /// Configurations to compare (comma-separated: vector, keyword, hybrid,
+        /// hybrid-keyword, hybrid-vector, hybrid:<keyword weight>)
+        #[arg(short, long, default_value = "vector,keyword,hybrid,hybrid-keyword,hybrid-vector")]
+        configs: String,
+        /// Metric cutoff k
+        #[arg(short, long, default_value = "10")]
+        k: usize,
+        /// Node type to search
+        #[arg(short, long, default_value = "chunk")]
+        node_type: String,
+        /// Embedding field name
+        #[arg(long, default_value = "embedding")]
+        field: String,
+    },
 }
 
 #[derive(Subcommand)]
@@ -311,6 +454,30 @@ enum SchemaAction {
     },
     /// Run pending migrations
     Migrate,
+    /// Manage triggers on node types
+    Trigger {
+        #[command(subcommand)]
+        action: TriggerAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum TriggerAction {
+    /// Create a trigger (e.g., "audit AFTER INSERT ON user WEBHOOK 'https://...'")
+    Create {
+        /// Definition, with or without the leading CREATE TRIGGER
+        definition: String,
+    },
+    /// Drop a trigger
+    Drop {
+        /// Trigger name
+        name: String,
+        /// Succeed if there is no such trigger
+        #[arg(long)]
+        if_exists: bool,
+    },
+    /// List all triggers
+    List,
 }
 
 #[derive(Subcommand)]
@@ -331,6 +498,23 @@ enum ConfigAction {
     List,
 }
 
+#[derive(Subcommand)]
+enum ShardsAction {
+    /// Show the shard layout and per-shard counts
+    Status,
+    /// Change the shard layout, moving data while reporting progress
+    ///
+    /// Without options, finishes an interrupted rebalance.
+    Rebalance {
+        /// Grow or shrink to this many shards, one shard at a time
+        #[arg(short, long)]
+        shards: Option<usize>,
+        /// Remove this shard
+        #[arg(long, conflicts_with = "shards")]
+        remove: Option<usize>,
+    },
+}
+
 #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
 enum ViewMode {
     #[default]
@@ -387,6 +571,27 @@ async fn main() -> Result<()> {
             let db_path = cli.database.as_deref().unwrap_or(".");
             handle_sync(db_path, &url).await?;
         }
+        Some(Commands::Backup { target, incremental, verify, list }) => {
+            if verify {
+                handle_backup_verify(&target).await?;
+            } else if list {
+                handle_backup_list(&target).await?;
+            } else {
+                let db_path = cli.database.as_deref().unwrap_or(".");
+                handle_backup(db_path, &target, incremental).await?;
+            }
+        }
+        Some(Commands::Restore { target, path, to_seq, to_time }) => {
+            handle_restore(&target, &path, to_seq, to_time.as_deref()).await?;
+        }
+        Some(Commands::Export { path, to }) => {
+            let db_path = cli.database.as_deref().unwrap_or(".");
+            handle_export(db_path, &path, to).await?;
+        }
+        Some(Commands::Import { path, from, mapping, remap_ids, batch_size }) => {
+            let db_path = cli.database.as_deref().unwrap_or(".");
+            handle_import(db_path, &path, from, mapping.as_deref(), remap_ids, batch_size).await?;
+        }
         Some(Commands::Config { action }) => {
             handle_config(action).await?;
         }
@@ -394,6 +599,23 @@ async fn main() -> Result<()> {
             let db_path = cli.database.as_deref().unwrap_or(".");
             handle_status(db_path).await?;
         }
+        Some(Commands::Shards { action }) => {
+            let db_path = cli.database.as_deref().unwrap_or(".");
+            handle_shards(db_path, action).await?;
+        }
+        Some(Commands::Watch { after, node_types, edge_types, server, token }) => {
+            let filter = storage::ChangeFilter {
+                node_types: split_list(node_types.as_deref()),
+                edge_types: split_list(edge_types.as_deref()),
+            };
+            match server {
+                Some(addr) => watch_server(&addr, token, after, filter, cli.format).await?,
+                None => {
+                    let db_path = cli.database.as_deref().unwrap_or(".");
+                    watch_local(db_path, after, filter, cli.format).await?;
+                }
+            }
+        }
         Some(Commands::Insert { node_type, props }) => {
             let db_path = cli.database.as_deref().unwrap_or(".");
             handle_insert(db_path, &node_type, &props, cli.format).await?;
@@ -414,28 +636,42 @@ async fn main() -> Result<()> {
             let db_path = cli.database.as_deref().unwrap_or(".");
             handle_embed(db_path, &node_type, &props, &vector, &field, cli.format).await?;
         }
-        Some(Commands::Chunk { text, file, document_id, strategy, size, overlap, store, props }) => {
+        Some(Commands::Chunk { text, file, document_id, strategy, size, overlap, tokenizer, store, props }) => {
             let db_path = cli.database.as_deref().unwrap_or(".");
             handle_chunk(
                 db_path, text.as_deref(), file.as_deref(), &document_id,
-                &strategy, size, overlap, store, props.as_deref(), cli.format
+                &strategy, size, overlap, tokenizer.as_deref(), store, props.as_deref(), cli.format
             ).await?;
         }
-        Some(Commands::Context { query, vector, node_type, field, max_tokens, min_score, output }) => {
+        Some(Commands::Context {
+            query, vector, node_type, field, max_tokens, min_score, output, tokenizer, graph_depth, edge_types,
+        }) => {
             let db_path = cli.database.as_deref().unwrap_or(".");
             handle_context(
                 db_path, &query, &vector, &node_type, &field,
-                max_tokens, min_score, &output, cli.format
+                max_tokens, min_score, &output, tokenizer.as_deref(),
+                graph_depth, &edge_types, cli.format
             ).await?;
         }
-        Some(Commands::Ingest { text, file, document_id, provider, api_key, chunk_size, overlap, props }) => {
+        Some(Commands::Ingest {
+            text, file, document_id, provider, api_key, strategy, chunk_size, overlap, tokenizer,
+            extract, llm_url, llm_model, props,
+        }) => {
             let db_path = cli.database.as_deref().unwrap_or(".");
             handle_ingest(
                 db_path, text.as_deref(), file.as_deref(), &document_id,
-                &provider, api_key.as_deref(), chunk_size, overlap,
+                &provider, api_key.as_deref(), &strategy, chunk_size, overlap,
+                tokenizer.as_deref(), extract.as_deref(), llm_url.as_deref(), llm_model.as_deref(),
                 props.as_deref(), cli.format
             ).await?;
         }
+        Some(Commands::Eval { queries, provider, api_key, configs, k, node_type, field }) => {
+            let db_path = cli.database.as_deref().unwrap_or(".");
+            handle_eval(
+                db_path, &queries, &provider, api_key.as_deref(), &configs,
+                k, &node_type, &field, cli.format
+            ).await?;
+        }
         None => {
             if cli.query.is_empty() {
                 print_welcome();
@@ -653,6 +889,17 @@ async fn handle_schema(db_path: &str, action: SchemaAction) -> Result<()> {
             let schema = manager.get_schema(&name).await?;
             let renderer = Renderer::new(OutputFormat::Table);
             renderer.render_schema_details(&schema)?;
+
+            let triggers: Vec<_> = manager.list_triggers().await?
+                .into_iter()
+                .filter(|t| t.node_type == name)
+                .collect();
+            if !triggers.is_empty() {
+                println!("\n{}", "Triggers:".bold());
+                for trigger in triggers {
+                    println!("  {}", trigger.to_string().bright_cyan());
+                }
+            }
         }
         SchemaAction::Drop { name, force } => {
             manager.drop_schema(&name, force).await?;
@@ -670,11 +917,40 @@ async fn handle_schema(db_path: &str, action: SchemaAction) -> Result<()> {
                 migrations.len()
             );
         }
+        SchemaAction::Trigger { action } => handle_trigger(&manager, action).await?,
     }
 
     Ok(())
 }
 
+async fn handle_trigger(manager: &schema::SchemaManager, action: TriggerAction) -> Result<()> {
+    use schema::TriggerStatement;
+
+    let statement = match action {
+        TriggerAction::Create { definition } => {
+            let definition = definition.trim();
+            let is_statement = TriggerStatement::parse(definition).is_some();
+            let sql = if is_statement { definition.to_string() } else { format!("CREATE TRIGGER {}", definition) };
+            TriggerStatement::parse(&sql).expect("a CREATE TRIGGER statement")?
+        }
+        TriggerAction::Drop { name, if_exists } => TriggerStatement::Drop { name, if_exists },
+        TriggerAction::List => {
+            let triggers = manager.list_triggers().await?;
+            if triggers.is_empty() {
+                println!("No triggers");
+            }
+            for trigger in triggers {
+                println!("{}", trigger.to_string().bright_cyan());
+            }
+            return Ok(());
+        }
+    };
+
+    let message = manager.execute_trigger_statement(statement).await?;
+    println!("{} {}", "✓".bright_green().bold(), message);
+    Ok(())
+}
+
 async fn handle_view(
     db_path: &str,
     name: &str,
@@ -749,6 +1025,212 @@ async fn handle_push(db_path: &str, url: &str) -> Result<()> {
     Ok(())
 }
 
+async fn handle_backup(db_path: &str, target: &str, incremental: bool) -> Result<()> {
+    use storage::{BackupKind, BackupStore, Database};
+
+    println!(
+        "{} Backing up database to {}...",
+        "●".bright_blue(),
+        target.bright_cyan()
+    );
+
+    let db = Database::open(db_path).await?;
+    let store = BackupStore::open(target).await?;
+    match store.backup(&db, incremental).await? {
+        Some(entry) => println!(
+            "{} {} backup {} taken at change {} ({} nodes, {} edges, {} bytes)",
+            "✓".bright_green().bold(),
+            match entry.kind {
+                BackupKind::Full => "Full",
+                BackupKind::Incremental => "Incremental",
+            },
+            entry.id,
+            entry.seq,
+            entry.node_count,
+            entry.edge_count,
+            entry.size
+        ),
+        None => println!(
+            "{} No changes since the last backup",
+            "✓".bright_green().bold()
+        ),
+    }
+
+    Ok(())
+}
+
+async fn handle_backup_list(target: &str) -> Result<()> {
+    use storage::{BackupKind, BackupStore};
+
+    let store = BackupStore::open(target).await?;
+    let Some(catalog) = store.catalog().await? else {
+        println!("{} No backups at {}", "●".bright_blue(), target.bright_cyan());
+        return Ok(());
+    };
+
+    println!(
+        "{} Backups of {} at {}",
+        "●".bright_blue(),
+        catalog.database.bright_cyan(),
+        target.bright_cyan()
+    );
+    for entry in &catalog.entries {
+        println!(
+            "  {:>4}  {:<11}  {}  changes {}..{}  {} nodes, {} edges",
+            entry.id,
+            match entry.kind {
+                BackupKind::Full => "full",
+                BackupKind::Incremental => "incremental",
+            },
+            entry.created_at,
+            entry.base_seq,
+            entry.seq,
+            entry.node_count,
+            entry.edge_count
+        );
+    }
+
+    Ok(())
+}
+
+async fn handle_backup_verify(target: &str) -> Result<()> {
+    use storage::BackupStore;
+
+    println!(
+        "{} Verifying backups at {}...",
+        "●".bright_blue(),
+        target.bright_cyan()
+    );
+
+    let store = BackupStore::open(target).await?;
+    let report = store.verify().await?;
+    if report.is_ok() {
+        println!(
+            "{} {} backups verified",
+            "✓".bright_green().bold(),
+            report.checked
+        );
+        Ok(())
+    } else {
+        for problem in &report.problems {
+            println!("  {} {}", "✗".bright_red(), problem);
+        }
+        anyhow::bail!(
+            "{} of {} backups failed verification",
+            report.problems.len(),
+            report.checked
+        )
+    }
+}
+
+async fn handle_restore(
+    target: &str,
+    path: &str,
+    to_seq: Option<u64>,
+    to_time: Option<&str>,
+) -> Result<()> {
+    use storage::{BackupStore, RestorePoint, Timestamp};
+
+    let point = match (to_seq, to_time) {
+        (Some(seq), _) => RestorePoint::Seq(seq),
+        (None, Some(time)) => {
+            let time = chrono::DateTime::parse_from_rfc3339(time)
+                .map_err(|e| anyhow::anyhow!("Invalid --to-time '{}': {}", time, e))?;
+            RestorePoint::Time(Timestamp::from_datetime(time.with_timezone(&chrono::Utc)))
+        }
+        (None, None) => RestorePoint::Latest,
+    };
+
+    println!(
+        "{} Restoring {} into {}...",
+        "●".bright_blue(),
+        target.bright_cyan(),
+        path.bright_cyan()
+    );
+
+    let store = BackupStore::open(target).await?;
+    let report = store.restore(path, point).await?;
+
+    println!(
+        "{} Restored to change {} from backup {} and {} incrementals ({} changes replayed)",
+        "✓".bright_green().bold(),
+        report.seq,
+        report.full,
+        report.incrementals,
+        report.changes
+    );
+    println!(
+        "  {} nodes, {} edges",
+        report.node_count,
+        report.edge_count
+    );
+
+    Ok(())
+}
+
+async fn handle_export(db_path: &str, path: &str, format: storage::DumpFormat) -> Result<()> {
+    use storage::{Database, Exporter};
+
+    println!(
+        "{} Exporting database as {} to {}...",
+        "●".bright_blue(),
+        format,
+        path.bright_cyan()
+    );
+
+    let db = Database::open(db_path).await?;
+    let report = Exporter::new(format).export(&db, path).await?;
+
+    println!(
+        "{} Exported {} nodes and {} edges",
+        "✓".bright_green().bold(),
+        report.nodes,
+        report.edges
+    );
+    for file in &report.files {
+        println!("  {}", file.display());
+    }
+
+    Ok(())
+}
+
+async fn handle_import(
+    db_path: &str,
+    path: &str,
+    format: storage::DumpFormat,
+    mapping: Option<&str>,
+    remap_ids: bool,
+    batch_size: usize,
+) -> Result<()> {
+    use storage::{Database, Importer};
+
+    println!(
+        "{} Importing {} from {}...",
+        "●".bright_blue(),
+        format,
+        path.bright_cyan()
+    );
+
+    let db = Database::open(db_path).await?;
+    let mut importer = Importer::new(format)
+        .with_remap_ids(remap_ids)
+        .with_batch_size(batch_size);
+    if let Some(mapping) = mapping {
+        importer = importer.with_mapping(mapping);
+    }
+    let report = importer.import(&db, path).await?;
+
+    println!(
+        "{} Imported {} nodes and {} edges{}",
+        "✓".bright_green().bold(),
+        report.nodes,
+        report.edges,
+        if report.remapped { " with new IDs" } else { "" }
+    );
+
+    Ok(())
+}
+
 async fn handle_connect(url: &str, readonly: bool) -> Result<()> {
     use storage::Database;
 
@@ -758,12 +1240,21 @@ async fn handle_connect(url: &str, readonly: bool) -> Result<()> {
         url.bright_cyan()
     );
 
-    let _db = Database::connect_bucket(url, readonly).await?;
+    let db = Database::connect_bucket(url, readonly).await?;
+    let status = db.status().await?;
 
     println!(
-        "{} Connected! Use {} to start querying.",
+        "{} Connected to {} at generation {}{}",
         "✓".bright_green().bold(),
-        "aresadb repl".bright_green()
+        status.name.bright_cyan(),
+        db.remote_generation().unwrap_or_default(),
+        if db.is_readonly() { " (readonly)" } else { "" }
+    );
+    println!(
+        "  {} nodes, {} edges, {} served on demand",
+        status.node_count,
+        status.edge_count,
+        humansize::format_size(status.size_bytes, humansize::BINARY)
     );
 
     Ok(())
@@ -782,10 +1273,13 @@ async fn handle_sync(db_path: &str, url: &str) -> Result<()> {
     let stats = db.sync_with_bucket(url).await?;
 
     println!(
-        "{} Synced: {} uploaded, {} downloaded",
+        "{} Synced to generation {}: {} segments uploaded ({}), {} downloaded ({})",
         "✓".bright_green().bold(),
+        stats.generation,
         stats.uploaded,
-        stats.downloaded
+        humansize::format_size(stats.bytes_uploaded, humansize::BINARY),
+        stats.downloaded,
+        humansize::format_size(stats.bytes_downloaded, humansize::BINARY)
     );
 
     Ok(())
@@ -839,6 +1333,206 @@ async fn handle_status(db_path: &str) -> Result<()> {
     Ok(())
 }
 
+async fn handle_shards(db_path: &str, action: ShardsAction) -> Result<()> {
+    use distributed::{ShardConfig, ShardManager};
+    use std::sync::Arc;
+
+    let base_path = std::path::PathBuf::from(db_path);
+    if !ShardManager::exists(&base_path)? {
+        anyhow::bail!("No sharded store at {}", db_path);
+    }
+    let manager = Arc::new(ShardManager::open(ShardConfig { base_path, ..Default::default() }).await?);
+
+    if let ShardsAction::Rebalance { shards, remove } = action {
+        // Finish whatever an earlier run left behind first
+        run_rebalance(&manager).await?;
+
+        if let Some(id) = remove {
+            println!("{} Removing shard {}", "→".bright_cyan(), id);
+            manager.remove_shard(id).await?;
+            run_rebalance(&manager).await?;
+        }
+
+        if let Some(target) = shards {
+            if target == 0 {
+                anyhow::bail!("A sharded store needs at least one shard");
+            }
+            loop {
+                let current = manager.layout().shards;
+                match current.len().cmp(&target) {
+                    std::cmp::Ordering::Less => {
+                        let id = manager.add_shard().await?;
+                        println!("{} Adding shard {}", "→".bright_cyan(), id);
+                    }
+                    std::cmp::Ordering::Greater => {
+                        let id = current.iter().copied().max().unwrap_or_default();
+                        println!("{} Removing shard {}", "→".bright_cyan(), id);
+                        manager.remove_shard(id).await?;
+                    }
+                    std::cmp::Ordering::Equal => break,
+                }
+                run_rebalance(&manager).await?;
+            }
+        }
+        println!();
+    }
+
+    let stats = manager.stats().await?;
+    println!("{}", "Shard Layout".bright_yellow().bold());
+    println!("─────────────────────────────────────");
+    println!("  {} {}", "Shards:".bright_cyan(), stats.num_shards);
+    println!("  {} {}", "Nodes:".bright_cyan(), stats.total_nodes);
+    println!("  {} {}", "Edges:".bright_cyan(), stats.total_edges);
+    if let Some(migration) = stats.migration {
+        println!("  {} {:?} (run `aresadb shards rebalance`)", "Pending:".bright_red(), migration);
+    }
+    println!();
+    println!("  {:<8} {:>10} {:>10} {:>12}", "SHARD", "NODES", "EDGES", "SIZE");
+    for shard in &stats.shards {
+        println!(
+            "  {:<8} {:>10} {:>10} {:>12}",
+            shard.id,
+            shard.node_count,
+            shard.edge_count,
+            humansize::format_size(shard.size_bytes, humansize::BINARY),
+        );
+    }
+
+    Ok(())
+}
+
+/// Run the pending rebalance on a background task, printing its progress
+async fn run_rebalance(manager: &std::sync::Arc<distributed::ShardManager>) -> Result<()> {
+    if manager.layout().migration.is_none() {
+        return Ok(());
+    }
+
+    let task = manager.start_rebalance();
+    while !task.is_finished() {
+        let progress = manager.rebalance_progress();
+        print!(
+            "\r  {}/{} nodes scanned, {} moved",
+            progress.scanned, progress.total, progress.moved
+        );
+        std::io::Write::flush(&mut std::io::stdout())?;
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    }
+
+    let progress = task.await??;
+    println!(
+        "\r  {} {}/{} nodes scanned, moved {} nodes and {} edges",
+        "✓".bright_green().bold(),
+        progress.scanned,
+        progress.total,
+        progress.moved,
+        progress.edges_moved
+    );
+    Ok(())
+}
+
+/// Split a comma-separated option into trimmed items
+fn split_list(list: Option<&str>) -> Vec<String> {
+    list.map(|l| l.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default()
+}
+
+/// Print the retained change log of a local database
+///
+/// Nothing else can write to the database while it is open here, so this
+/// stops at the latest change rather than waiting for more.
+async fn watch_local(db_path: &str, after: Option<u64>, filter: storage::ChangeFilter, format: OutputFormat) -> Result<()> {
+    use futures::StreamExt;
+    use storage::Database;
+
+    let db = Database::open(db_path).await?;
+    let last = db.last_change_seq()?;
+    let mut seq = after.unwrap_or(0);
+    if seq >= last {
+        return Ok(());
+    }
+
+    // Unfiltered so the stream reaches the latest change even if it is left out
+    let mut changes = db.changes(Some(seq), storage::ChangeFilter::all())?;
+    while seq < last {
+        let Some(event) = changes.next().await else { break };
+        let event = event?;
+        seq = event.seq;
+        if filter.matches(&event.change) {
+            print_change(&event, format);
+        }
+    }
+    Ok(())
+}
+
+/// Follow changes on a server until interrupted
+#[cfg(feature = "server")]
+async fn watch_server(
+    addr: &str,
+    token: Option<String>,
+    after: Option<u64>,
+    filter: storage::ChangeFilter,
+    format: OutputFormat,
+) -> Result<()> {
+    let mut builder = client::ClientBuilder::new().address(addr);
+    if let Some(token) = token {
+        builder = builder.token(token);
+    }
+    let mut subscription = builder.build().await?.subscribe(after, filter).await?;
+    if format != OutputFormat::Json {
+        eprintln!(
+            "{} Watching {} from sequence {}",
+            "→".bright_cyan(),
+            addr,
+            after.unwrap_or(subscription.last_seq())
+        );
+    }
+
+    loop {
+        tokio::select! {
+            event = subscription.next() => print_change(&event?, format),
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+        }
+    }
+}
+
+#[cfg(not(feature = "server"))]
+async fn watch_server(
+    _addr: &str,
+    _token: Option<String>,
+    _after: Option<u64>,
+    _filter: storage::ChangeFilter,
+    _format: OutputFormat,
+) -> Result<()> {
+    anyhow::bail!("Watching a server needs aresadb built with the `server` feature")
+}
+
+/// Print one change, as a JSON line or a summary
+fn print_change(event: &storage::ChangeEvent, format: OutputFormat) {
+    if format == OutputFormat::Json {
+        println!("{}", event.to_json());
+        return;
+    }
+
+    let (type_name, id) = match (event.change.node(), event.change.edge()) {
+        (Some(node), _) => (node.node_type.clone(), node.id.to_string()),
+        (_, Some(edge)) => (edge.edge_type.clone(), format!("{} ({} → {})", edge.id, edge.from, edge.to)),
+        _ => return,
+    };
+    let kind = match event.change {
+        storage::Change::NodeInserted { .. } | storage::Change::EdgeInserted { .. } => event.change.kind().bright_green(),
+        storage::Change::NodeUpdated { .. } => event.change.kind().bright_yellow(),
+        _ => event.change.kind().bright_red(),
+    };
+    println!(
+        "{:>8}  {}  {:<14} {} {}",
+        event.seq.to_string().bright_cyan(),
+        event.timestamp,
+        kind,
+        type_name.bold(),
+        id
+    );
+}
+
 async fn handle_insert(db_path: &str, node_type: &str, props_json: &str, format: OutputFormat) -> Result<()> {
     use storage::Database;
     use output::Renderer;
@@ -1022,6 +1716,7 @@ async fn handle_chunk(
     strategy: &str,
     size: usize,
     overlap: usize,
+    tokenizer_path: Option<&str>,
     store: bool,
     props_json: Option<&str>,
     format: OutputFormat,
@@ -1039,24 +1734,9 @@ async fn handle_chunk(
     };
 
     // Create chunker with strategy
-    let chunk_strategy = match strategy.to_lowercase().as_str() {
-        "fixed" => rag::ChunkStrategy::FixedSize {
-            chunk_size: size,
-            overlap,
-        },
-        "sentence" => rag::ChunkStrategy::Sentence {
-            max_tokens: size,
-        },
-        "paragraph" => rag::ChunkStrategy::Paragraph {
-            max_size: size,
-        },
-        "semantic" => rag::ChunkStrategy::Semantic {
-            max_size: size,
-        },
-        _ => anyhow::bail!("Unknown strategy: {}. Use: fixed, sentence, paragraph, semantic", strategy),
-    };
-
-    let chunker = rag::Chunker::new(chunk_strategy);
+    let chunk_strategy = parse_chunk_strategy(strategy, size, overlap)?;
+    let chunker = rag::Chunker::new(chunk_strategy)
+        .with_tokenizer(rag::load_tokenizer(tokenizer_path)?);
     let chunks = chunker.chunk(document_id, &content);
 
     println!(
@@ -1136,6 +1816,33 @@ async fn handle_chunk(
     Ok(())
 }
 
+/// Parse a chunking strategy name from the CLI
+fn parse_chunk_strategy(strategy: &str, size: usize, overlap: usize) -> Result<rag::ChunkStrategy> {
+    Ok(match strategy.to_lowercase().as_str() {
+        "fixed" => rag::ChunkStrategy::FixedSize {
+            chunk_size: size,
+            overlap,
+        },
+        "sentence" => rag::ChunkStrategy::Sentence {
+            max_tokens: size,
+        },
+        "paragraph" => rag::ChunkStrategy::Paragraph {
+            max_size: size,
+        },
+        "semantic" => rag::ChunkStrategy::Semantic {
+            max_size: size,
+        },
+        "recursive" => rag::ChunkStrategy::Recursive {
+            max_tokens: size,
+            overlap_tokens: overlap,
+        },
+        _ => anyhow::bail!(
+            "Unknown strategy: {}. Use: fixed, sentence, paragraph, semantic, recursive",
+            strategy
+        ),
+    })
+}
+
 /// Handle context retrieval for RAG
 async fn handle_context(
     db_path: &str,
@@ -1146,9 +1853,13 @@ async fn handle_context(
     max_tokens: usize,
     min_score: f64,
     output_format: &str,
+    tokenizer_path: Option<&str>,
+    graph_depth: u32,
+    edge_types: &str,
     _format: OutputFormat,
 ) -> Result<()> {
     use storage::Database;
+    use query::QueryEngine;
 
     println!(
         "{} Retrieving context for: \"{}\"",
@@ -1162,15 +1873,37 @@ async fn handle_context(
     let query_vector: Vec<f32> = serde_json::from_str(vector_json)
         .map_err(|e| anyhow::anyhow!("Invalid vector JSON: {}. Expected format: [0.1, 0.2, ...]", e))?;
 
-    // Create context retriever
-    let retriever = rag::ContextRetriever::new(&db)
-        .node_type(node_type)
-        .embedding_field(field)
-        .content_field("content")
-        .max_tokens(max_tokens)
-        .min_score(min_score);
-
-    let context = retriever.retrieve(&query_vector, query_text).await?;
+    let tokenizer = rag::load_tokenizer(tokenizer_path)?;
+
+    let context = if graph_depth > 0 {
+        // Expand vector hits along the graph
+        let edge_types: Vec<&str> = edge_types
+            .split(',')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .collect();
+        let engine = QueryEngine::new(db);
+        let retriever = rag::GraphRetriever::new(&engine)
+            .node_type(node_type)
+            .embedding_field(field)
+            .content_field("content")
+            .max_depth(graph_depth)
+            .edge_types(&edge_types)
+            .max_tokens(max_tokens)
+            .min_score(min_score)
+            .tokenizer(tokenizer);
+        retriever.retrieve(&query_vector, query_text).await?
+    } else {
+        // Create context retriever
+        let retriever = rag::ContextRetriever::new(&db)
+            .node_type(node_type)
+            .embedding_field(field)
+            .content_field("content")
+            .max_tokens(max_tokens)
+            .min_score(min_score)
+            .tokenizer(tokenizer);
+        retriever.retrieve(&query_vector, query_text).await?
+    };
 
     println!(
         "{} Found {} chunks ({} estimated tokens)",
@@ -1195,6 +1928,60 @@ async fn handle_context(
     Ok(())
 }
 
+/// Handle eval command - compare retrieval configurations
+async fn handle_eval(
+    db_path: &str,
+    queries_path: &str,
+    provider_name: &str,
+    api_key: Option<&str>,
+    configs: &str,
+    k: usize,
+    node_type: &str,
+    field: &str,
+    format: OutputFormat,
+) -> Result<()> {
+    use storage::Database;
+    use output::Renderer;
+
+    let queries = rag::load_eval_queries(queries_path)?;
+    let configs: Vec<rag::EvalConfig> = configs
+        .split(',')
+        .filter(|s| !s.trim().is_empty())
+        .map(rag::EvalConfig::from_name)
+        .collect::<Result<_>>()?;
+
+    let embedder = rag::EmbeddingManager::from_name(provider_name, api_key)?;
+
+    println!(
+        "{} Evaluating {} queries against {} configurations (k = {}, provider: {})",
+        "●".bright_blue(),
+        queries.len().to_string().bright_yellow(),
+        configs.len().to_string().bright_yellow(),
+        k,
+        embedder.name().bright_cyan()
+    );
+
+    let db = Database::open(db_path).await?;
+    let report = rag::Evaluator::new(&db, &embedder)
+        .node_type(node_type)
+        .embedding_field(field)
+        .content_field("content")
+        .k(k)
+        .run(&queries, &configs)
+        .await?;
+
+    match format {
+        OutputFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(&report.to_json())?);
+        }
+        _ => {
+            Renderer::new(format).render_results(&report.to_query_result())?;
+        }
+    }
+
+    Ok(())
+}
+
 /// Handle ingest command - chunk + embed + store
 async fn handle_ingest(
     db_path: &str,
@@ -1203,8 +1990,13 @@ async fn handle_ingest(
     document_id: &str,
     provider_name: &str,
     api_key: Option<&str>,
+    strategy: &str,
     chunk_size: usize,
     overlap: usize,
+    tokenizer_path: Option<&str>,
+    extractor_name: Option<&str>,
+    llm_url: Option<&str>,
+    llm_model: Option<&str>,
     props_json: Option<&str>,
     _format: OutputFormat,
 ) -> Result<()> {
@@ -1235,15 +2027,22 @@ async fn handle_ingest(
         embedder.dimension()
     );
 
+    // Create entity extractor
+    let extractor = extractor_name
+        .map(|name| rag::extractor_from_name(name, llm_url, api_key, llm_model))
+        .transpose()?;
+    if let Some(ref extractor) = extractor {
+        println!("  Extractor: {}", extractor.name().bright_cyan());
+    }
+
     // Chunk the document
-    let chunker = rag::Chunker::new(rag::ChunkStrategy::FixedSize {
-        chunk_size,
-        overlap,
-    });
+    let chunker = rag::Chunker::new(parse_chunk_strategy(strategy, chunk_size, overlap)?)
+        .with_tokenizer(rag::load_tokenizer(tokenizer_path)?);
     let chunks = chunker.chunk(document_id, &content);
     println!(
-        "  Chunks: {} (size: {}, overlap: {})",
+        "  Chunks: {} ({}, size: {}, overlap: {})",
         chunks.len().to_string().bright_yellow(),
+        strategy,
         chunk_size,
         overlap
     );
@@ -1260,6 +2059,9 @@ async fn handle_ingest(
 
     // Process each chunk
     let mut inserted = 0;
+    let mut previous: Option<String> = None;
+    let mut linker = rag::EntityLinker::new(&db);
+    let mut link_stats = rag::LinkStats::default();
     let start = std::time::Instant::now();
 
     for (i, chunk) in chunks.iter().enumerate() {
@@ -1275,8 +2077,18 @@ async fn handle_ingest(
             obj.insert("total_chunks".to_string(), serde_json::json!(chunk.total_chunks));
         }
 
-        // Insert with embedding
-        db.insert_with_embedding("chunk", props, "embedding", embedding).await?;
+        // Insert with embedding, linking consecutive chunks for graph retrieval
+        let node = db.insert_with_embedding("chunk", props, "embedding", embedding).await?;
+        let node_id = node.id.to_string();
+        if let Some(prev) = previous.replace(node_id.clone()) {
+            db.create_edge(&prev, &node_id, "next", None).await?;
+        }
+
+        // Extract entities and relations into the graph
+        if let Some(ref extractor) = extractor {
+            let extraction = extractor.extract(&chunk.content).await?;
+            link_stats.merge(&linker.link(&node_id, &extraction).await?);
+        }
         inserted += 1;
 
         // Progress indicator
@@ -1302,5 +2114,15 @@ async fn handle_ingest(
         rate
     );
 
+    if extractor.is_some() {
+        println!(
+            "  Entities: {} new, {} reused; {} mentions, {} relations",
+            link_stats.entities_created.to_string().bright_yellow(),
+            link_stats.entities_reused,
+            link_stats.mentions,
+            link_stats.relations
+        );
+    }
+
     Ok(())
 }
@@ -0,0 +1,203 @@
+//! Metrics
+//!
+//! Process-wide Prometheus metrics for the server, query engine, cache,
+//! write-ahead log, shards and Raft. Components update them as they work;
+//! [`render`] returns them in the Prometheus text format, which the HTTP
+//! API serves at `/metrics`.
+//!
+//! Counters and histograms are recorded where the work happens. Values that
+//! are cheaper to read than to track, such as per-shard node counts, are
+//! gauges set whenever the owning component computes them.
+
+use prometheus::{
+    Encoder, Gauge, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use std::sync::OnceLock;
+
+/// Prefix of every metric name
+const NAMESPACE: &str = "aresadb";
+
+/// Content type of [`render`]'s output
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Every metric the process exports
+pub struct Metrics {
+    registry: Registry,
+
+    /// Requests handled, by request type and outcome
+    pub requests: IntCounterVec,
+    /// Time to handle a request, by request type
+    pub request_duration: HistogramVec,
+    /// Connections open on the binary protocol port
+    pub connections: IntGauge,
+    /// Connections accepted since start
+    pub connections_accepted: IntCounter,
+    /// Connections refused at the connection limit
+    pub connections_rejected: IntCounter,
+
+    /// Time to execute a SQL statement, by operation
+    pub query_duration: HistogramVec,
+    /// Nodes read by queries before filtering
+    pub rows_scanned: IntCounter,
+    /// Rows returned by queries
+    pub rows_returned: IntCounter,
+
+    /// Cache lookups that found an entry
+    pub cache_hits: IntCounter,
+    /// Cache lookups that missed
+    pub cache_misses: IntCounter,
+    /// Bytes held by all caches
+    pub cache_size: IntGauge,
+
+    /// Entries appended to write-ahead logs
+    pub wal_appends: IntCounter,
+    /// Size of the write-ahead log, by kind of log
+    pub wal_size: IntGaugeVec,
+
+    /// Nodes on each shard
+    pub shard_nodes: IntGaugeVec,
+    /// Bytes stored on each shard
+    pub shard_size: IntGaugeVec,
+    /// Nodes on the fullest shard over the mean; 1 is perfectly even
+    pub shard_imbalance: Gauge,
+
+    /// Current Raft term, by node
+    pub raft_term: IntGaugeVec,
+    /// Highest committed log index, by node
+    pub raft_commit_index: IntGaugeVec,
+    /// Highest applied log index, by node
+    pub raft_applied_index: IntGaugeVec,
+    /// 1 on the leader, 0 elsewhere, by node
+    pub raft_leader: IntGaugeVec,
+}
+
+/// The process-wide metrics
+pub fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::new)
+}
+
+/// All metrics in the Prometheus text format
+pub fn render() -> String {
+    metrics().render()
+}
+
+fn opts(name: &str, help: &str) -> Opts {
+    Opts::new(name, help).namespace(NAMESPACE)
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+
+        let requests = IntCounterVec::new(opts("requests_total", "Requests handled"), &["request", "status"]).unwrap();
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new("request_duration_seconds", "Time to handle a request").namespace(NAMESPACE),
+            &["request"],
+        )
+        .unwrap();
+        let connections = IntGauge::with_opts(opts("connections", "Open connections")).unwrap();
+        let connections_accepted = IntCounter::with_opts(opts("connections_accepted_total", "Connections accepted")).unwrap();
+        let connections_rejected =
+            IntCounter::with_opts(opts("connections_rejected_total", "Connections refused at the limit")).unwrap();
+
+        let query_duration = HistogramVec::new(
+            HistogramOpts::new("query_duration_seconds", "Time to execute a SQL statement").namespace(NAMESPACE),
+            &["operation"],
+        )
+        .unwrap();
+        let rows_scanned = IntCounter::with_opts(opts("query_rows_scanned_total", "Nodes read by queries")).unwrap();
+        let rows_returned = IntCounter::with_opts(opts("query_rows_returned_total", "Rows returned by queries")).unwrap();
+
+        let cache_hits = IntCounter::with_opts(opts("cache_hits_total", "Cache lookups that found an entry")).unwrap();
+        let cache_misses = IntCounter::with_opts(opts("cache_misses_total", "Cache lookups that missed")).unwrap();
+        let cache_size = IntGauge::with_opts(opts("cache_size_bytes", "Bytes held by caches")).unwrap();
+
+        let wal_appends = IntCounter::with_opts(opts("wal_appends_total", "Entries appended to write-ahead logs")).unwrap();
+        let wal_size = IntGaugeVec::new(opts("wal_size_bytes", "Size of a write-ahead log file"), &["wal"]).unwrap();
+
+        let shard_nodes = IntGaugeVec::new(opts("shard_nodes", "Nodes on a shard"), &["shard"]).unwrap();
+        let shard_size = IntGaugeVec::new(opts("shard_size_bytes", "Bytes stored on a shard"), &["shard"]).unwrap();
+        let shard_imbalance =
+            Gauge::with_opts(opts("shard_imbalance_ratio", "Nodes on the fullest shard over the mean")).unwrap();
+
+        let raft_term = IntGaugeVec::new(opts("raft_term", "Current Raft term"), &["node"]).unwrap();
+        let raft_commit_index = IntGaugeVec::new(opts("raft_commit_index", "Highest committed log index"), &["node"]).unwrap();
+        let raft_applied_index = IntGaugeVec::new(opts("raft_applied_index", "Highest applied log index"), &["node"]).unwrap();
+        let raft_leader = IntGaugeVec::new(opts("raft_leader", "Whether the node leads its cluster"), &["node"]).unwrap();
+
+        registry.register(Box::new(requests.clone())).unwrap();
+        registry.register(Box::new(request_duration.clone())).unwrap();
+        registry.register(Box::new(connections.clone())).unwrap();
+        registry.register(Box::new(connections_accepted.clone())).unwrap();
+        registry.register(Box::new(connections_rejected.clone())).unwrap();
+        registry.register(Box::new(query_duration.clone())).unwrap();
+        registry.register(Box::new(rows_scanned.clone())).unwrap();
+        registry.register(Box::new(rows_returned.clone())).unwrap();
+        registry.register(Box::new(cache_hits.clone())).unwrap();
+        registry.register(Box::new(cache_misses.clone())).unwrap();
+        registry.register(Box::new(cache_size.clone())).unwrap();
+        registry.register(Box::new(wal_appends.clone())).unwrap();
+        registry.register(Box::new(wal_size.clone())).unwrap();
+        registry.register(Box::new(shard_nodes.clone())).unwrap();
+        registry.register(Box::new(shard_size.clone())).unwrap();
+        registry.register(Box::new(shard_imbalance.clone())).unwrap();
+        registry.register(Box::new(raft_term.clone())).unwrap();
+        registry.register(Box::new(raft_commit_index.clone())).unwrap();
+        registry.register(Box::new(raft_applied_index.clone())).unwrap();
+        registry.register(Box::new(raft_leader.clone())).unwrap();
+
+        Self {
+            registry,
+            requests,
+            request_duration,
+            connections,
+            connections_accepted,
+            connections_rejected,
+            query_duration,
+            rows_scanned,
+            rows_returned,
+            cache_hits,
+            cache_misses,
+            cache_size,
+            wal_appends,
+            wal_size,
+            shard_nodes,
+            shard_size,
+            shard_imbalance,
+            raft_term,
+            raft_commit_index,
+            raft_applied_index,
+            raft_leader,
+        }
+    }
+
+    /// All metrics in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        // Encoding into memory only fails on invalid metric families, which
+        // registration has already ruled out
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer).expect("metrics encode");
+        String::from_utf8(buffer).expect("metrics are UTF-8")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_text_format() {
+        let m = metrics();
+        m.requests.with_label_values(&["Ping", "ok"]).inc();
+        m.raft_term.with_label_values(&["metrics-test"]).set(7);
+        m.request_duration.with_label_values(&["Ping"]).observe(0.002);
+
+        let text = render();
+        assert!(text.contains("# TYPE aresadb_requests_total counter"));
+        assert!(text.contains("aresadb_requests_total{request=\"Ping\",status=\"ok\"}"));
+        assert!(text.contains("aresadb_raft_term{node=\"metrics-test\"} 7"));
+        assert!(text.contains("aresadb_request_duration_seconds_bucket{request=\"Ping\",le=\"0.005\"}"));
+    }
+}
@@ -0,0 +1,250 @@
+//! Aggregate functions
+//!
+//! Aggregates are kept as mergeable partial states, so a sharded query can
+//! aggregate on every shard and combine the partials on the coordinator
+//! without moving the underlying rows.
+
+use std::collections::BTreeMap;
+
+use super::{column_value, compare_values, QueryResult};
+use crate::storage::{Node, Value};
+
+/// Supported aggregate functions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggregateFunction {
+    /// Number of rows, or of non-null values of a column
+    Count,
+    /// Sum of numeric values
+    Sum,
+    /// Mean of numeric values
+    Avg,
+    /// Smallest value
+    Min,
+    /// Largest value
+    Max,
+}
+
+impl AggregateFunction {
+    /// Look up a function by its SQL name
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_uppercase().as_str() {
+            "COUNT" => Some(Self::Count),
+            "SUM" => Some(Self::Sum),
+            "AVG" => Some(Self::Avg),
+            "MIN" => Some(Self::Min),
+            "MAX" => Some(Self::Max),
+            _ => None,
+        }
+    }
+}
+
+/// An aggregate in the select list
+#[derive(Debug, Clone)]
+pub struct Aggregate {
+    /// Function applied
+    pub function: AggregateFunction,
+    /// Column aggregated over; `None` for `COUNT(*)`
+    pub column: Option<String>,
+    /// Output column name
+    pub alias: String,
+}
+
+/// Partial state of one aggregate over a subset of rows
+#[derive(Debug, Clone, Default)]
+struct Partial {
+    /// Rows counted (non-null values, or all rows for `COUNT(*)`)
+    count: u64,
+    /// Integer sum while every value seen is an integer
+    int_sum: i64,
+    /// Floating-point sum of every value seen
+    float_sum: f64,
+    /// Whether a non-integer value was summed
+    has_float: bool,
+    min: Option<Value>,
+    max: Option<Value>,
+}
+
+impl Partial {
+    fn add(&mut self, value: Value) {
+        self.count += 1;
+        match value {
+            Value::Int(i) => {
+                self.int_sum = self.int_sum.wrapping_add(i);
+                self.float_sum += i as f64;
+            }
+            Value::Float(f) => {
+                self.has_float = true;
+                self.float_sum += f;
+            }
+            _ => {}
+        }
+        self.fold_extremes(&Some(value.clone()), &Some(value));
+    }
+
+    fn merge(&mut self, other: Partial) {
+        self.count += other.count;
+        self.int_sum = self.int_sum.wrapping_add(other.int_sum);
+        self.float_sum += other.float_sum;
+        self.has_float |= other.has_float;
+        self.fold_extremes(&other.min, &other.max);
+    }
+
+    fn fold_extremes(&mut self, min: &Option<Value>, max: &Option<Value>) {
+        if let Some(min) = min {
+            if self.min.as_ref().is_none_or(|m| compare_values(min, m).is_lt()) {
+                self.min = Some(min.clone());
+            }
+        }
+        if let Some(max) = max {
+            if self.max.as_ref().is_none_or(|m| compare_values(max, m).is_gt()) {
+                self.max = Some(max.clone());
+            }
+        }
+    }
+
+    fn finish(self, function: AggregateFunction) -> Value {
+        match function {
+            AggregateFunction::Count => Value::Int(self.count as i64),
+            _ if self.count == 0 => Value::Null,
+            AggregateFunction::Sum if self.has_float => Value::Float(self.float_sum),
+            AggregateFunction::Sum => Value::Int(self.int_sum),
+            AggregateFunction::Avg => Value::Float(self.float_sum / self.count as f64),
+            AggregateFunction::Min => self.min.unwrap_or(Value::Null),
+            AggregateFunction::Max => self.max.unwrap_or(Value::Null),
+        }
+    }
+}
+
+/// Grouped partial aggregates
+#[derive(Debug, Clone)]
+pub struct AggregateState {
+    group_by: Vec<String>,
+    aggregates: Vec<Aggregate>,
+    /// Group values and partials, keyed by the encoded group values
+    groups: BTreeMap<String, (Vec<Value>, Vec<Partial>)>,
+}
+
+impl AggregateState {
+    /// Empty state for the given grouping and aggregates
+    pub fn new(group_by: &[String], aggregates: &[Aggregate]) -> Self {
+        Self {
+            group_by: group_by.to_vec(),
+            aggregates: aggregates.to_vec(),
+            groups: BTreeMap::new(),
+        }
+    }
+
+    /// Fold a row into its group
+    pub fn add(&mut self, node: &Node) {
+        let key: Vec<Value> = self.group_by.iter().map(|c| column_value(node, c)).collect();
+        let encoded = serde_json::to_string(&key).unwrap_or_default();
+        let width = self.aggregates.len();
+        let (_, partials) = self
+            .groups
+            .entry(encoded)
+            .or_insert_with(|| (key, vec![Partial::default(); width]));
+
+        for (aggregate, partial) in self.aggregates.iter().zip(partials.iter_mut()) {
+            match &aggregate.column {
+                None => partial.count += 1,
+                Some(column) => {
+                    let value = column_value(node, column);
+                    if !value.is_null() {
+                        partial.add(value);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Combine partials computed over another subset of rows
+    pub fn merge(&mut self, other: AggregateState) {
+        for (encoded, (key, partials)) in other.groups {
+            match self.groups.get_mut(&encoded) {
+                Some((_, mine)) => {
+                    for (mine, theirs) in mine.iter_mut().zip(partials) {
+                        mine.merge(theirs);
+                    }
+                }
+                None => {
+                    self.groups.insert(encoded, (key, partials));
+                }
+            }
+        }
+    }
+
+    /// Final rows: group columns followed by one column per aggregate
+    pub fn finish(mut self) -> QueryResult {
+        // Without GROUP BY, aggregating no rows still yields one row
+        if self.group_by.is_empty() && self.groups.is_empty() {
+            let partials = vec![Partial::default(); self.aggregates.len()];
+            self.groups.insert(String::new(), (Vec::new(), partials));
+        }
+
+        let columns = self
+            .group_by
+            .iter()
+            .cloned()
+            .chain(self.aggregates.iter().map(|a| a.alias.clone()))
+            .collect();
+
+        let aggregates = self.aggregates;
+        let rows = self
+            .groups
+            .into_values()
+            .map(|(mut row, partials)| {
+                row.extend(partials.into_iter().zip(&aggregates).map(|(p, a)| p.finish(a.function)));
+                row
+            })
+            .collect();
+
+        QueryResult {
+            columns,
+            rows,
+            rows_affected: 0,
+            execution_time_ms: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(group: &str, amount: i64) -> Node {
+        Node::new("order", Value::from_json(serde_json::json!({ "group": group, "amount": amount })).unwrap())
+    }
+
+    #[test]
+    fn test_partials_merge_like_a_single_pass() {
+        let aggregates = vec![
+            Aggregate { function: AggregateFunction::Count, column: None, alias: "n".into() },
+            Aggregate { function: AggregateFunction::Sum, column: Some("amount".into()), alias: "total".into() },
+            Aggregate { function: AggregateFunction::Avg, column: Some("amount".into()), alias: "mean".into() },
+            Aggregate { function: AggregateFunction::Max, column: Some("amount".into()), alias: "top".into() },
+        ];
+        let group_by = vec!["group".to_string()];
+        let rows = [("a", 1), ("b", 10), ("a", 3), ("b", 20), ("a", 5)];
+
+        let mut whole = AggregateState::new(&group_by, &aggregates);
+        let mut left = AggregateState::new(&group_by, &aggregates);
+        let mut right = AggregateState::new(&group_by, &aggregates);
+        for (i, (group, amount)) in rows.iter().enumerate() {
+            let n = node(group, *amount);
+            whole.add(&n);
+            if i % 2 == 0 { left.add(&n) } else { right.add(&n) }
+        }
+        left.merge(right);
+
+        let merged = left.finish();
+        assert_eq!(merged.columns, vec!["group", "n", "total", "mean", "top"]);
+        assert_eq!(merged.rows, whole.finish().rows);
+        assert_eq!(
+            merged.rows[0],
+            vec![Value::String("a".into()), Value::Int(3), Value::Int(9), Value::Float(3.0), Value::Int(5)]
+        );
+
+        let empty = AggregateState::new(&[], &aggregates).finish();
+        assert_eq!(empty.rows, vec![vec![Value::Int(0), Value::Null, Value::Null, Value::Null]]);
+    }
+}
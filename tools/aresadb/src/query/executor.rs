@@ -7,10 +7,12 @@ use std::collections::{BTreeMap, HashSet, VecDeque};
 use std::time::Instant;
 
 use super::{
-    QueryParser, QueryPlanner, QueryPlan, ParsedQuery, QueryResult,
-    TraversalResult, Condition, QueryOperation,
+    AggregateState, QueryParser, QueryPlanner, QueryPlan, ParsedQuery, QueryResult,
+    TraversalResult, Condition, QueryOperation, finish_query,
 };
 use super::planner::PlanStep;
+use crate::metrics::metrics;
+use crate::schema::{SchemaManager, TriggerStatement};
 use crate::storage::{Database, Node, Edge, NodeId, Value, SimilarityResult};
 
 /// Query executor
@@ -30,10 +32,23 @@ impl QueryEngine {
         }
     }
 
+    /// Get the underlying database
+    pub fn database(&self) -> &Database {
+        &self.db
+    }
+
     /// Execute a SQL query
     pub async fn execute_sql(&self, sql: &str, limit: Option<usize>) -> Result<QueryResult> {
         let start = Instant::now();
 
+        // Trigger definitions live in the schema registry
+        if let Some(statement) = TriggerStatement::parse(sql) {
+            SchemaManager::new(self.db.clone()).execute_trigger_statement(statement?).await?;
+            let mut result = QueryResult::empty();
+            result.execution_time_ms = start.elapsed().as_millis() as u64;
+            return Ok(result);
+        }
+
         // Parse SQL
         let mut query = self.parser.parse(sql)?;
 
@@ -45,17 +60,15 @@ impl QueryEngine {
         // Handle vector search separately
         if query.operation == QueryOperation::VectorSearch {
             let results = self.execute_vector_search(&query).await?;
-            let mut result = self.vector_results_to_query_result(results).await?;
-            result.execution_time_ms = start.elapsed().as_millis() as u64;
-            return Ok(result);
+            let result = self.vector_results_to_query_result(results).await?;
+            return Ok(finish_query(&query, result, start));
         }
 
         // Plan and execute
         let plan = self.planner.plan(&query)?;
-        let mut result = self.execute_plan(&plan, &query).await?;
+        let result = self.execute_plan(&plan, &query).await?;
 
-        result.execution_time_ms = start.elapsed().as_millis() as u64;
-        Ok(result)
+        Ok(finish_query(&query, result, start))
     }
 
     /// Execute a parsed query
@@ -70,16 +83,14 @@ impl QueryEngine {
         // Handle vector search separately
         if query.operation == QueryOperation::VectorSearch {
             let results = self.execute_vector_search(&query).await?;
-            let mut result = self.vector_results_to_query_result(results).await?;
-            result.execution_time_ms = start.elapsed().as_millis() as u64;
-            return Ok(result);
+            let result = self.vector_results_to_query_result(results).await?;
+            return Ok(finish_query(&query, result, start));
         }
 
         let plan = self.planner.plan(&query)?;
-        let mut result = self.execute_plan(&plan, &query).await?;
+        let result = self.execute_plan(&plan, &query).await?;
 
-        result.execution_time_ms = start.elapsed().as_millis() as u64;
-        Ok(result)
+        Ok(finish_query(&query, result, start))
     }
 
     /// Execute a vector search query
@@ -94,6 +105,7 @@ impl QueryEngine {
             params.k,
             params.metric.clone(),
         ).await
+        .inspect(|results| metrics().rows_scanned.inc_by(results.len() as u64))
     }
 
     /// Convert vector search results to QueryResult
@@ -130,6 +142,10 @@ impl QueryEngine {
 
     /// Execute a query plan
     async fn execute_plan(&self, plan: &QueryPlan, query: &ParsedQuery) -> Result<QueryResult> {
+        if query.operation == QueryOperation::Select && query.is_aggregate() {
+            return self.execute_aggregate(query).await;
+        }
+
         let mut nodes: Option<Vec<Node>> = None;
         let mut insert_result: Option<Node> = None;
         let mut rows_affected: u64 = 0;
@@ -137,13 +153,13 @@ impl QueryEngine {
         for step in &plan.steps {
             match step {
                 PlanStep::FullScan { node_type } => {
-                    nodes = Some(self.db.get_all_by_type(node_type, None).await?);
+                    nodes = Some(self.scan(node_type).await?);
                 }
 
                 PlanStep::IndexLookup { node_type, field: _, value: _ } => {
                     // For now, fall back to full scan + filter
                     // TODO: Implement actual index lookup
-                    nodes = Some(self.db.get_all_by_type(node_type, None).await?);
+                    nodes = Some(self.scan(node_type).await?);
                 }
 
                 PlanStep::Filter { conditions } => {
@@ -219,23 +235,7 @@ impl QueryEngine {
             let mut r = QueryResult::from_nodes(n);
 
             // Apply column projection
-            if !query.columns.is_empty() {
-                let col_set: HashSet<&String> = query.columns.iter().collect();
-                r.columns.retain(|c| col_set.contains(c) || c == "id" || c == "type");
-
-                let keep_indices: Vec<usize> = r.columns
-                    .iter()
-                    .enumerate()
-                    .filter(|(_, c)| col_set.contains(*c) || *c == "id" || *c == "type")
-                    .map(|(i, _)| i)
-                    .collect();
-
-                r.rows = r.rows.into_iter()
-                    .map(|row| {
-                        keep_indices.iter().map(|&i| row[i].clone()).collect()
-                    })
-                    .collect();
-            }
+            r.project(&query.columns);
 
             r
         } else {
@@ -246,38 +246,36 @@ impl QueryEngine {
         Ok(result)
     }
 
-    /// Check if a node matches all conditions
-    fn matches_conditions(&self, node: &Node, conditions: &[Condition]) -> bool {
-        for condition in conditions {
-            let value = if condition.column == "id" {
-                Value::String(node.id.to_string())
-            } else if condition.column == "type" {
-                Value::String(node.node_type.clone())
-            } else {
-                node.get(&condition.column).cloned().unwrap_or(Value::Null)
-            };
-
-            if !condition.operator.matches(&value, &condition.value) {
-                return false;
+    /// Execute an aggregating SELECT
+    async fn execute_aggregate(&self, query: &ParsedQuery) -> Result<QueryResult> {
+        let mut state = AggregateState::new(&query.group_by, &query.aggregates);
+        for node in self.scan(&query.target).await? {
+            if self.matches_conditions(&node, &query.conditions) {
+                state.add(&node);
             }
         }
-        true
+
+        let mut result = state.finish();
+        result.sort_rows(&query.order_by);
+        result.paginate(query.offset, query.limit);
+        Ok(result)
+    }
+
+    /// Read every node of a type, counting them as scanned
+    async fn scan(&self, node_type: &str) -> Result<Vec<Node>> {
+        let nodes = self.db.get_all_by_type(node_type, None).await?;
+        metrics().rows_scanned.inc_by(nodes.len() as u64);
+        Ok(nodes)
+    }
+
+    /// Check if a node matches all conditions
+    fn matches_conditions(&self, node: &Node, conditions: &[Condition]) -> bool {
+        super::matches_conditions(node, conditions)
     }
 
     /// Compare two values for sorting
     fn compare_values(&self, a: &Value, b: &Value) -> std::cmp::Ordering {
-        match (a, b) {
-            (Value::Null, Value::Null) => std::cmp::Ordering::Equal,
-            (Value::Null, _) => std::cmp::Ordering::Less,
-            (_, Value::Null) => std::cmp::Ordering::Greater,
-            (Value::Int(ai), Value::Int(bi)) => ai.cmp(bi),
-            (Value::Float(af), Value::Float(bf)) => af.partial_cmp(bf).unwrap_or(std::cmp::Ordering::Equal),
-            (Value::Int(ai), Value::Float(bf)) => (*ai as f64).partial_cmp(bf).unwrap_or(std::cmp::Ordering::Equal),
-            (Value::Float(af), Value::Int(bi)) => af.partial_cmp(&(*bi as f64)).unwrap_or(std::cmp::Ordering::Equal),
-            (Value::String(as_), Value::String(bs)) => as_.cmp(bs),
-            (Value::Bool(ab), Value::Bool(bb)) => ab.cmp(bb),
-            _ => std::cmp::Ordering::Equal,
-        }
+        super::compare_values(a, b)
     }
 
     /// Perform graph traversal from a starting node
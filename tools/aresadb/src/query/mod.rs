@@ -5,10 +5,14 @@
 mod parser;
 mod planner;
 mod executor;
+mod aggregate;
+mod sharded;
 
 pub use parser::QueryParser;
 pub use planner::{QueryPlan, QueryPlanner, PlanStep};
 pub use executor::QueryEngine;
+pub use aggregate::{Aggregate, AggregateFunction, AggregateState};
+pub use sharded::ShardedQueryEngine;
 
 use crate::storage::{Node, Edge, Value};
 
@@ -126,6 +130,59 @@ impl QueryResult {
         }
     }
 
+    /// Keep only the selected columns, plus `id` and `type` when present
+    pub fn project(&mut self, columns: &[String]) {
+        if columns.is_empty() {
+            return;
+        }
+
+        let keep_indices: Vec<usize> = self.columns
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| columns.contains(c) || *c == "id" || *c == "type")
+            .map(|(i, _)| i)
+            .collect();
+
+        self.columns = keep_indices.iter().map(|&i| self.columns[i].clone()).collect();
+        self.rows = std::mem::take(&mut self.rows)
+            .into_iter()
+            .map(|row| keep_indices.iter().map(|&i| row[i].clone()).collect())
+            .collect();
+    }
+
+    /// Sort rows by result columns; unknown columns are ignored
+    pub fn sort_rows(&mut self, order_by: &[OrderBy]) {
+        let keys: Vec<(usize, bool)> = order_by
+            .iter()
+            .filter_map(|o| {
+                let index = self.columns.iter().position(|c| *c == o.column)?;
+                Some((index, o.descending))
+            })
+            .collect();
+        if keys.is_empty() {
+            return;
+        }
+
+        self.rows.sort_by(|a, b| {
+            keys.iter()
+                .map(|&(i, descending)| {
+                    let cmp = compare_values(&a[i], &b[i]);
+                    if descending { cmp.reverse() } else { cmp }
+                })
+                .find(|cmp| cmp.is_ne())
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+    }
+
+    /// Skip `offset` rows and keep at most `limit`
+    pub fn paginate(&mut self, offset: Option<usize>, limit: Option<usize>) {
+        let rows = std::mem::take(&mut self.rows).into_iter().skip(offset.unwrap_or(0));
+        self.rows = match limit {
+            Some(limit) => rows.take(limit).collect(),
+            None => rows.collect(),
+        };
+    }
+
     /// Get row count
     pub fn row_count(&self) -> usize {
         self.rows.len()
@@ -192,6 +249,17 @@ pub struct ParsedQuery {
     pub data: Option<BTreeMap<String, Value>>,
     /// Vector search parameters
     pub vector_search: Option<VectorSearchParams>,
+    /// Aggregates in the select list
+    pub aggregates: Vec<Aggregate>,
+    /// GROUP BY columns
+    pub group_by: Vec<String>,
+}
+
+impl ParsedQuery {
+    /// Whether the query aggregates rows rather than returning them
+    pub fn is_aggregate(&self) -> bool {
+        !self.aggregates.is_empty() || !self.group_by.is_empty()
+    }
 }
 
 /// Query operation type
@@ -320,4 +388,49 @@ pub struct OrderBy {
     pub descending: bool,
 }
 
+/// Value of a column on a node; `id` and `type` are built in
+pub(crate) fn column_value(node: &Node, column: &str) -> Value {
+    match column {
+        "id" => Value::String(node.id.to_string()),
+        "type" => Value::String(node.node_type.clone()),
+        _ => node.get(column).cloned().unwrap_or(Value::Null),
+    }
+}
+
+/// Whether a node satisfies every condition
+pub(crate) fn matches_conditions(node: &Node, conditions: &[Condition]) -> bool {
+    conditions
+        .iter()
+        .all(|c| c.operator.matches(&column_value(node, &c.column), &c.value))
+}
 
+/// Compare two values for sorting; nulls sort first
+pub(crate) fn compare_values(a: &Value, b: &Value) -> std::cmp::Ordering {
+    use std::cmp::Ordering;
+    match (a, b) {
+        (Value::Null, Value::Null) => Ordering::Equal,
+        (Value::Null, _) => Ordering::Less,
+        (_, Value::Null) => Ordering::Greater,
+        (Value::Int(ai), Value::Int(bi)) => ai.cmp(bi),
+        (Value::Float(af), Value::Float(bf)) => af.partial_cmp(bf).unwrap_or(Ordering::Equal),
+        (Value::Int(ai), Value::Float(bf)) => (*ai as f64).partial_cmp(bf).unwrap_or(Ordering::Equal),
+        (Value::Float(af), Value::Int(bi)) => af.partial_cmp(&(*bi as f64)).unwrap_or(Ordering::Equal),
+        (Value::String(as_), Value::String(bs)) => as_.cmp(bs),
+        (Value::Bool(ab), Value::Bool(bb)) => ab.cmp(bb),
+        _ => Ordering::Equal,
+    }
+}
+
+
+
+/// Stamp a result's execution time and record it in the query metrics
+pub(crate) fn finish_query(query: &ParsedQuery, mut result: QueryResult, start: std::time::Instant) -> QueryResult {
+    let elapsed = start.elapsed();
+    result.execution_time_ms = elapsed.as_millis() as u64;
+
+    let m = crate::metrics::metrics();
+    let operation = format!("{:?}", query.operation).to_lowercase();
+    m.query_duration.with_label_values(&[&operation]).observe(elapsed.as_secs_f64());
+    m.rows_returned.inc_by(result.rows.len() as u64);
+    result
+}
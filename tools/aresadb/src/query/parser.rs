@@ -4,14 +4,17 @@
 
 use anyhow::{Result, bail};
 use sqlparser::ast::{
-    BinaryOperator, Expr, Query, Select, SelectItem, SetExpr, Statement, TableFactor,
-    Value as SqlValue, OrderByExpr,
+    BinaryOperator, Expr, Function, FunctionArg, FunctionArgExpr, GroupByExpr, Query, Select,
+    SelectItem, SetExpr, Statement, TableFactor, Value as SqlValue, OrderByExpr,
 };
 use sqlparser::dialect::GenericDialect;
 use sqlparser::parser::Parser;
 use std::collections::BTreeMap;
 
-use super::{ParsedQuery, QueryOperation, Condition, Operator, OrderBy, VectorSearchParams};
+use super::{
+    Aggregate, AggregateFunction, ParsedQuery, QueryOperation, Condition, Operator, OrderBy,
+    VectorSearchParams,
+};
 use crate::storage::{Value, DistanceMetric};
 
 /// SQL query parser
@@ -73,6 +76,8 @@ impl QueryParser {
                     offset: None,
                     data,
                     vector_search: None,
+                    aggregates: Vec::new(),
+                    group_by: Vec::new(),
                 })
             }
             Statement::Update { table, assignments, selection, .. } => {
@@ -104,6 +109,8 @@ impl QueryParser {
                     offset: None,
                     data: Some(data),
                     vector_search: None,
+                    aggregates: Vec::new(),
+                    group_by: Vec::new(),
                 })
             }
             Statement::Delete { from, selection, .. } => {
@@ -130,6 +137,8 @@ impl QueryParser {
                     offset: None,
                     data: None,
                     vector_search: None,
+                    aggregates: Vec::new(),
+                    group_by: Vec::new(),
                 })
             }
             _ => bail!("Unsupported SQL statement type"),
@@ -172,6 +181,31 @@ impl QueryParser {
             })
             .collect();
 
+        // Extract aggregates
+        let mut aggregates = Vec::new();
+        for item in &select.projection {
+            let (expr, alias) = match item {
+                SelectItem::UnnamedExpr(expr) => (expr, None),
+                SelectItem::ExprWithAlias { expr, alias } => (expr, Some(alias.value.clone())),
+                _ => continue,
+            };
+            if let Expr::Function(function) = expr {
+                aggregates.push(self.convert_aggregate(function, alias)?);
+            }
+        }
+
+        // Extract GROUP BY
+        let group_by = match &select.group_by {
+            GroupByExpr::Expressions(exprs) => exprs
+                .iter()
+                .map(|expr| match expr {
+                    Expr::Identifier(ident) => Ok(ident.to_string()),
+                    _ => bail!("GROUP BY only supports column names"),
+                })
+                .collect::<Result<Vec<_>>>()?,
+            GroupByExpr::All => bail!("GROUP BY ALL not supported"),
+        };
+
         // Extract conditions from WHERE clause
         let conditions = select
             .selection
@@ -224,6 +258,30 @@ impl QueryParser {
             offset,
             data: None,
             vector_search: None,
+            aggregates,
+            group_by,
+        })
+    }
+
+    /// Convert an aggregate call such as `COUNT(*)` or `SUM(amount)`
+    fn convert_aggregate(&self, function: &Function, alias: Option<String>) -> Result<Aggregate> {
+        let name = function.name.to_string();
+        let kind = AggregateFunction::from_name(&name)
+            .ok_or_else(|| anyhow::anyhow!("Unsupported function: {}", name))?;
+
+        let column = match function.args.as_slice() {
+            [] | [FunctionArg::Unnamed(FunctionArgExpr::Wildcard)] if kind == AggregateFunction::Count => None,
+            [FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Identifier(ident)))] => Some(ident.to_string()),
+            _ => bail!("Unsupported arguments to {}", name),
+        };
+        if function.distinct {
+            bail!("{}(DISTINCT ...) not supported", name);
+        }
+
+        Ok(Aggregate {
+            function: kind,
+            column,
+            alias: alias.unwrap_or_else(|| function.to_string().to_lowercase()),
         })
     }
 
@@ -450,6 +508,8 @@ impl QueryParser {
                 k,
                 metric,
             }),
+            aggregates: Vec::new(),
+            group_by: Vec::new(),
         })
     }
 
@@ -578,6 +638,24 @@ mod tests {
         assert_eq!(params.metric, crate::storage::DistanceMetric::Euclidean);
         assert_eq!(params.k, 5);
     }
-}
-
 
+    #[test]
+    fn test_parse_aggregates() {
+        let parser = QueryParser::new();
+        let query = parser
+            .parse("SELECT city, COUNT(*), SUM(amount) AS total FROM orders GROUP BY city")
+            .unwrap();
+
+        assert!(query.is_aggregate());
+        assert_eq!(query.group_by, vec!["city"]);
+        assert_eq!(query.aggregates.len(), 2);
+        assert_eq!(query.aggregates[0].function, AggregateFunction::Count);
+        assert_eq!(query.aggregates[0].column, None);
+        assert_eq!(query.aggregates[0].alias, "count(*)");
+        assert_eq!(query.aggregates[1].column.as_deref(), Some("amount"));
+        assert_eq!(query.aggregates[1].alias, "total");
+
+        assert!(parser.parse("SELECT SUM(*) FROM orders").is_err());
+        assert!(parser.parse("SELECT LOWER(name) FROM orders").is_err());
+    }
+}
@@ -361,6 +361,8 @@ mod tests {
             offset: None,
             data: None,
             vector_search: None,
+            aggregates: vec![],
+            group_by: vec![],
         };
 
         let plan = planner.plan(&query).unwrap();
@@ -387,6 +389,8 @@ mod tests {
             offset: None,
             data: None,
             vector_search: None,
+            aggregates: vec![],
+            group_by: vec![],
         };
 
         let plan = planner.plan(&query).unwrap();
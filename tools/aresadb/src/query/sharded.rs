@@ -0,0 +1,364 @@
+//! Scatter-gather query execution over shards
+//!
+//! Every shard runs the same fragment in parallel on its own blocking
+//! thread: scan, filter, then either a partial aggregate or a sorted top-N
+//! of projected rows. Only those partial results leave the shard; the
+//! coordinator merges partial aggregates, or k-way merges the sorted
+//! fragments, before applying `OFFSET` and `LIMIT`.
+
+use anyhow::{Result, bail};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+use std::time::Instant;
+
+use super::{
+    column_value, compare_values, finish_query, matches_conditions, AggregateState, OrderBy, ParsedQuery,
+    QueryOperation, QueryParser, QueryResult,
+};
+use crate::distributed::ShardManager;
+use crate::storage::{Node, Value};
+
+/// Query executor for a sharded store
+pub struct ShardedQueryEngine {
+    shards: Arc<ShardManager>,
+    parser: QueryParser,
+}
+
+impl ShardedQueryEngine {
+    /// Create an engine over the given shards
+    pub fn new(shards: Arc<ShardManager>) -> Self {
+        Self {
+            shards,
+            parser: QueryParser::new(),
+        }
+    }
+
+    /// Get the underlying shard manager
+    pub fn shards(&self) -> &ShardManager {
+        &self.shards
+    }
+
+    /// Execute a SQL query
+    pub async fn execute_sql(&self, sql: &str, limit: Option<usize>) -> Result<QueryResult> {
+        let query = self.parser.parse(sql)?;
+        self.execute_parsed(&query, limit).await
+    }
+
+    /// Execute a parsed query
+    pub async fn execute_parsed(&self, query: &ParsedQuery, limit: Option<usize>) -> Result<QueryResult> {
+        let start = Instant::now();
+
+        let mut query = query.clone();
+        if let Some(l) = limit {
+            query.limit = Some(query.limit.map(|ql| ql.min(l)).unwrap_or(l));
+        }
+
+        let result = match query.operation {
+            QueryOperation::Select if query.is_aggregate() => self.aggregate(&query).await?,
+            QueryOperation::Select => self.select(&query).await?,
+            QueryOperation::Insert => self.insert(&query).await?,
+            QueryOperation::Update | QueryOperation::Delete => self.modify(&query).await?,
+            _ => bail!("{:?} is not supported in sharded mode", query.operation),
+        };
+
+        Ok(finish_query(&query, result, start))
+    }
+
+    /// Rows from every shard, merged in `ORDER BY` order
+    async fn select(&self, query: &ParsedQuery) -> Result<QueryResult> {
+        // Each shard only needs enough rows to fill the requested page
+        let top_n = query.limit.map(|l| l + query.offset.unwrap_or(0));
+        let keep: Option<Vec<String>> = (!query.columns.is_empty()).then(|| {
+            query.columns
+                .iter()
+                .chain(query.order_by.iter().map(|o| &o.column))
+                .cloned()
+                .collect()
+        });
+        let conditions = query.conditions.clone();
+        let order_by = query.order_by.clone();
+
+        let fragments = self
+            .scatter(&query.target, move |nodes| {
+                let rows = nodes
+                    .filter(|node| matches_conditions(node, &conditions))
+                    .map(|node| {
+                        let mut row = SortedRow::new(node, &order_by);
+                        if let Some(keep) = &keep {
+                            row.node.properties.retain(|k, _| keep.contains(k));
+                        }
+                        row
+                    });
+                match top_n {
+                    Some(n) => top_rows(rows, n, &order_by),
+                    None => {
+                        let mut rows: Vec<SortedRow> = rows.collect();
+                        rows.sort_by(|a, b| compare_keys(&a.key, &b.key, &order_by));
+                        rows
+                    }
+                }
+            })
+            .await?;
+
+        let nodes: Vec<Node> = merge_sorted(fragments, &query.order_by)
+            .skip(query.offset.unwrap_or(0))
+            .take(query.limit.unwrap_or(usize::MAX))
+            .collect();
+
+        let mut result = QueryResult::from_nodes(nodes);
+        result.project(&query.columns);
+        Ok(result)
+    }
+
+    /// Partial aggregates from every shard, merged and finished
+    async fn aggregate(&self, query: &ParsedQuery) -> Result<QueryResult> {
+        let (group_by, aggregates) = (query.group_by.clone(), query.aggregates.clone());
+        let conditions = query.conditions.clone();
+        let partials = self
+            .scatter(&query.target, move |nodes| {
+                let mut state = AggregateState::new(&group_by, &aggregates);
+                for node in nodes.filter(|n| matches_conditions(n, &conditions)) {
+                    state.add(&node);
+                }
+                state
+            })
+            .await?;
+
+        let mut state = AggregateState::new(&query.group_by, &query.aggregates);
+        for partial in partials {
+            state.merge(partial);
+        }
+
+        let mut result = state.finish();
+        result.sort_rows(&query.order_by);
+        result.paginate(query.offset, query.limit);
+        Ok(result)
+    }
+
+    async fn insert(&self, query: &ParsedQuery) -> Result<QueryResult> {
+        let Some(data) = &query.data else {
+            bail!("INSERT without values");
+        };
+        let node = Node::new(&query.target, Value::Object(data.clone()));
+        self.shards.insert_node(&node).await?;
+
+        let mut result = QueryResult::from_nodes(vec![node]);
+        result.rows_affected = 1;
+        Ok(result)
+    }
+
+    /// UPDATE or DELETE the matching nodes on every shard
+    async fn modify(&self, query: &ParsedQuery) -> Result<QueryResult> {
+        let conditions = query.conditions.clone();
+        let matched = self
+            .scatter(&query.target, move |nodes| {
+                nodes
+                    .filter(|node| matches_conditions(node, &conditions))
+                    .map(|node| node.id)
+                    .collect::<Vec<_>>()
+            })
+            .await?;
+
+        let mut rows_affected = 0;
+        for id in matched.into_iter().flatten() {
+            match (&query.operation, &query.data) {
+                (QueryOperation::Update, Some(data)) => {
+                    self.shards.update_node(&id, Value::Object(data.clone())).await?;
+                }
+                (QueryOperation::Update, None) => bail!("UPDATE without assignments"),
+                _ => self.shards.delete_node(&id).await?,
+            }
+            rows_affected += 1;
+        }
+
+        let mut result = QueryResult::empty();
+        result.rows_affected = rows_affected;
+        Ok(result)
+    }
+
+    /// Run `fragment` over the `target` nodes of every shard, inside each
+    /// shard's scan
+    async fn scatter<T, F>(&self, target: &str, fragment: F) -> Result<Vec<T>>
+    where
+        T: Send + 'static,
+        F: Fn(&mut dyn Iterator<Item = Node>) -> T + Send + Sync + 'static,
+    {
+        self.shards.scan_fragments(target, fragment).await
+    }
+}
+
+/// A row with its precomputed `ORDER BY` key
+struct SortedRow {
+    key: Vec<Value>,
+    node: Node,
+}
+
+impl SortedRow {
+    fn new(node: Node, order_by: &[OrderBy]) -> Self {
+        let key = order_by.iter().map(|o| column_value(&node, &o.column)).collect();
+        Self { key, node }
+    }
+}
+
+/// Compare sort keys column by column, honouring each direction
+fn compare_keys(a: &[Value], b: &[Value], order_by: &[OrderBy]) -> Ordering {
+    a.iter()
+        .zip(b)
+        .zip(order_by)
+        .map(|((a, b), order)| {
+            let cmp = compare_values(a, b);
+            if order.descending { cmp.reverse() } else { cmp }
+        })
+        .find(|cmp| cmp.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+/// A row held in a shard's bounded top-N heap
+struct Candidate<'a> {
+    row: SortedRow,
+    /// Scan position, so earlier rows win ties
+    seq: usize,
+    order_by: &'a [OrderBy],
+}
+
+impl Ord for Candidate<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Max-heap: the row that sorts last is on top, ready to be evicted
+        compare_keys(&self.row.key, &other.row.key, self.order_by).then(self.seq.cmp(&other.seq))
+    }
+}
+
+impl PartialOrd for Candidate<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Candidate<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate<'_> {}
+
+/// The first `n` of `rows` in `order_by` order, holding at most `n` at once
+fn top_rows(rows: impl Iterator<Item = SortedRow>, n: usize, order_by: &[OrderBy]) -> Vec<SortedRow> {
+    if n == 0 {
+        return Vec::new();
+    }
+    let mut heap = BinaryHeap::with_capacity(n.min(1024) + 1);
+    for (seq, row) in rows.enumerate() {
+        heap.push(Candidate { row, seq, order_by });
+        if heap.len() > n {
+            heap.pop();
+        }
+    }
+    heap.into_sorted_vec().into_iter().map(|c| c.row).collect()
+}
+
+/// Head of one shard's sorted fragment in the merge heap
+struct MergeHead<'a> {
+    row: SortedRow,
+    shard: usize,
+    order_by: &'a [OrderBy],
+}
+
+impl Ord for MergeHead<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // BinaryHeap is a max-heap: invert so the smallest key pops first,
+        // breaking ties by shard to keep the output stable
+        compare_keys(&self.row.key, &other.row.key, self.order_by)
+            .then(self.shard.cmp(&other.shard))
+            .reverse()
+    }
+}
+
+impl PartialOrd for MergeHead<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for MergeHead<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for MergeHead<'_> {}
+
+/// K-way merge of per-shard fragments that are each sorted by `order_by`
+fn merge_sorted(fragments: Vec<Vec<SortedRow>>, order_by: &[OrderBy]) -> impl Iterator<Item = Node> + '_ {
+    let mut sources: Vec<std::vec::IntoIter<SortedRow>> = fragments.into_iter().map(Vec::into_iter).collect();
+    let mut heap = BinaryHeap::with_capacity(sources.len());
+    for (shard, source) in sources.iter_mut().enumerate() {
+        if let Some(row) = source.next() {
+            heap.push(MergeHead { row, shard, order_by });
+        }
+    }
+
+    std::iter::from_fn(move || {
+        let head = heap.pop()?;
+        if let Some(row) = sources[head.shard].next() {
+            heap.push(MergeHead { row, shard: head.shard, order_by });
+        }
+        Some(head.row.node)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distributed::ShardConfig;
+    use tempfile::TempDir;
+
+    async fn engine(temp: &TempDir) -> ShardedQueryEngine {
+        let config = ShardConfig {
+            num_shards: 4,
+            virtual_nodes: 100,
+            base_path: temp.path().to_path_buf(),
+        };
+        ShardedQueryEngine::new(Arc::new(ShardManager::new(config).await.unwrap()))
+    }
+
+    #[tokio::test]
+    async fn test_sharded_select_matches_global_order() {
+        let temp = TempDir::new().unwrap();
+        let engine = engine(&temp).await;
+
+        for i in 0..40 {
+            let sql = format!(
+                "INSERT INTO users (name, age, team) VALUES ('user{:02}', {}, '{}')",
+                i,
+                (i * 7) % 40,
+                if i % 2 == 0 { "red" } else { "blue" }
+            );
+            engine.execute_sql(&sql, None).await.unwrap();
+        }
+
+        let page = engine
+            .execute_sql("SELECT name, age FROM users WHERE age >= 10 ORDER BY age DESC LIMIT 5 OFFSET 2", None)
+            .await
+            .unwrap();
+        let age = page.columns.iter().position(|c| c == "age").unwrap();
+        assert!(!page.columns.contains(&"team".to_string()));
+        let ages: Vec<Value> = page.rows.iter().map(|r| r[age].clone()).collect();
+        assert_eq!(ages, (33..=37).rev().map(Value::Int).collect::<Vec<_>>());
+
+        let counts = engine
+            .execute_sql("SELECT team, COUNT(*), MAX(age) FROM users GROUP BY team ORDER BY team", None)
+            .await
+            .unwrap();
+        assert_eq!(counts.rows.len(), 2);
+        assert_eq!(counts.rows[0][0], Value::String("blue".into()));
+        assert_eq!(counts.rows[0][1], Value::Int(20));
+        assert_eq!(counts.rows[1][1], Value::Int(20));
+
+        let deleted = engine.execute_sql("DELETE FROM users WHERE team = 'red'", None).await.unwrap();
+        assert_eq!(deleted.rows_affected, 20);
+        let remaining = engine.execute_sql("SELECT COUNT(*) FROM users", None).await.unwrap();
+        assert_eq!(remaining.rows[0][0], Value::Int(20));
+    }
+}
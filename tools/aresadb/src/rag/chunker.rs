@@ -4,6 +4,13 @@
 //! suitable for embedding and retrieval.
 
 use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use std::sync::Arc;
+
+use super::tokenizer::{Tokenizer, CharEstimateTokenizer};
+
+/// Separators tried in order by the recursive splitter, coarsest first
+const RECURSIVE_SEPARATORS: &[&str] = &["\n\n", "\n", ". ", "? ", "! ", "; ", ", ", " "];
 
 /// A chunk of a document
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -46,6 +53,14 @@ pub enum ChunkStrategy {
     Semantic {
         max_size: usize,
     },
+    /// Recursively split on paragraphs, lines, sentences and words until
+    /// every chunk fits in max_tokens, with overlap measured in tokens
+    Recursive {
+        /// Maximum tokens per chunk
+        max_tokens: usize,
+        /// Tokens repeated from the end of the previous chunk
+        overlap_tokens: usize,
+    },
 }
 
 impl Default for ChunkStrategy {
@@ -60,12 +75,22 @@ impl Default for ChunkStrategy {
 /// Document chunker
 pub struct Chunker {
     strategy: ChunkStrategy,
+    tokenizer: Arc<dyn Tokenizer>,
 }
 
 impl Chunker {
     /// Create a new chunker with the given strategy
     pub fn new(strategy: ChunkStrategy) -> Self {
-        Self { strategy }
+        Self {
+            strategy,
+            tokenizer: Arc::new(CharEstimateTokenizer),
+        }
+    }
+
+    /// Set the tokenizer used for token-based strategies
+    pub fn with_tokenizer(mut self, tokenizer: Arc<dyn Tokenizer>) -> Self {
+        self.tokenizer = tokenizer;
+        self
     }
 
     /// Create a chunker with default settings (512 chars, 50 overlap)
@@ -83,6 +108,11 @@ impl Chunker {
         Self::new(ChunkStrategy::Paragraph { max_size })
     }
 
+    /// Create a chunker for recursive token-bounded splitting
+    pub fn recursive_chunker(max_tokens: usize, overlap_tokens: usize) -> Self {
+        Self::new(ChunkStrategy::Recursive { max_tokens, overlap_tokens })
+    }
+
     /// Chunk a document into pieces
     pub fn chunk(&self, document_id: &str, content: &str) -> Vec<DocumentChunk> {
         match self.strategy {
@@ -98,6 +128,9 @@ impl Chunker {
             ChunkStrategy::Semantic { max_size } => {
                 self.chunk_semantic(document_id, content, max_size)
             }
+            ChunkStrategy::Recursive { max_tokens, overlap_tokens } => {
+                self.chunk_recursive(document_id, content, max_tokens, overlap_tokens)
+            }
         }
     }
 
@@ -172,13 +205,13 @@ impl Chunker {
         let mut chunk_index = 0;
 
         for sentence in sentences {
-            let sentence_len = sentence.chars().count();
-            let current_len = current_chunk.chars().count();
-
-            // Rough token estimate: chars / 4
-            let estimated_tokens = (current_len + sentence_len) / 4;
+            let candidate = if current_chunk.is_empty() {
+                sentence.to_string()
+            } else {
+                format!("{} {}", current_chunk, sentence)
+            };
 
-            if estimated_tokens > max_tokens && !current_chunk.is_empty() {
+            if self.count_tokens(&candidate) > max_tokens && !current_chunk.is_empty() {
                 // Save current chunk and start new one
                 let end_offset = current_start + current_chunk.len();
                 chunks.push(DocumentChunk {
@@ -195,10 +228,7 @@ impl Chunker {
                 current_start = end_offset;
                 current_chunk = sentence.to_string();
             } else {
-                if !current_chunk.is_empty() {
-                    current_chunk.push(' ');
-                }
-                current_chunk.push_str(sentence);
+                current_chunk = candidate;
             }
         }
 
@@ -370,6 +400,163 @@ impl Chunker {
         chunks
     }
 
+    /// Recursive token-bounded chunking with token overlap
+    fn chunk_recursive(
+        &self,
+        document_id: &str,
+        content: &str,
+        max_tokens: usize,
+        overlap_tokens: usize,
+    ) -> Vec<DocumentChunk> {
+        let max_tokens = max_tokens.max(1);
+        let overlap_tokens = overlap_tokens.min(max_tokens / 2);
+
+        let mut pieces = Vec::new();
+        self.split_recursive(content, 0..content.len(), RECURSIVE_SEPARATORS, max_tokens, &mut pieces);
+
+        // Merge pieces into chunks. Pieces are contiguous, so a chunk is
+        // always a single range of the original content.
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        let mut window: Vec<Range<usize>> = Vec::new();
+
+        for piece in pieces {
+            if let Some(first) = window.first() {
+                if self.count_tokens(&content[first.start..piece.end]) > max_tokens {
+                    let last = window.last().unwrap();
+                    ranges.push(first.start..last.end);
+
+                    // Keep trailing pieces as overlap while they fit both the
+                    // overlap budget and the room left for the next piece
+                    while let Some(first) = window.first() {
+                        let kept = &content[first.start..window.last().unwrap().end];
+                        let with_next = &content[first.start..piece.end];
+                        if self.count_tokens(kept) <= overlap_tokens
+                            && self.count_tokens(with_next) <= max_tokens
+                        {
+                            break;
+                        }
+                        window.remove(0);
+                    }
+                }
+            }
+            window.push(piece);
+        }
+
+        if let (Some(first), Some(last)) = (window.first(), window.last()) {
+            ranges.push(first.start..last.end);
+        }
+
+        let mut chunks: Vec<DocumentChunk> = ranges.into_iter()
+            .filter_map(|range| {
+                let slice = &content[range.clone()];
+                let trimmed_start = slice.len() - slice.trim_start().len();
+                let trimmed = slice.trim();
+                if trimmed.is_empty() {
+                    return None;
+                }
+                let start_offset = range.start + trimmed_start;
+                Some((start_offset, start_offset + trimmed.len(), trimmed.to_string()))
+            })
+            .enumerate()
+            .map(|(chunk_index, (start_offset, end_offset, text))| DocumentChunk {
+                id: format!("{}_{}", document_id, chunk_index),
+                document_id: document_id.to_string(),
+                content: text,
+                chunk_index,
+                total_chunks: 0,
+                start_offset,
+                end_offset,
+                metadata: None,
+            })
+            .collect();
+
+        let total = chunks.len();
+        for chunk in &mut chunks {
+            chunk.total_chunks = total;
+        }
+
+        chunks
+    }
+
+    /// Split a range into pieces of at most max_tokens, trying coarser separators first.
+    /// Separators stay attached to the end of the preceding piece.
+    fn split_recursive(
+        &self,
+        content: &str,
+        range: Range<usize>,
+        separators: &[&str],
+        max_tokens: usize,
+        out: &mut Vec<Range<usize>>,
+    ) {
+        if range.is_empty() {
+            return;
+        }
+
+        if self.count_tokens(&content[range.clone()]) <= max_tokens {
+            out.push(range);
+            return;
+        }
+
+        let Some((separator, rest)) = separators.split_first() else {
+            self.split_by_chars(content, range, max_tokens, out);
+            return;
+        };
+
+        let text = &content[range.clone()];
+        let mut start = 0;
+        let mut parts = Vec::new();
+        for (idx, _) in text.match_indices(separator) {
+            let end = idx + separator.len();
+            if end > start {
+                parts.push(range.start + start..range.start + end);
+            }
+            start = end;
+        }
+        if start < text.len() {
+            parts.push(range.start + start..range.end);
+        }
+
+        if parts.len() <= 1 {
+            self.split_recursive(content, range, rest, max_tokens, out);
+            return;
+        }
+
+        for part in parts {
+            self.split_recursive(content, part, rest, max_tokens, out);
+        }
+    }
+
+    /// Last resort: split on character boundaries, taking the longest prefix that fits
+    fn split_by_chars(
+        &self,
+        content: &str,
+        range: Range<usize>,
+        max_tokens: usize,
+        out: &mut Vec<Range<usize>>,
+    ) {
+        let boundaries: Vec<usize> = content[range.clone()]
+            .char_indices()
+            .map(|(i, _)| range.start + i)
+            .chain(std::iter::once(range.end))
+            .collect();
+
+        let mut from = 0;
+        while from + 1 < boundaries.len() {
+            // Binary search for the furthest boundary that still fits
+            let (mut lo, mut hi) = (from + 1, boundaries.len() - 1);
+            while lo < hi {
+                let mid = (lo + hi).div_ceil(2);
+                if self.count_tokens(&content[boundaries[from]..boundaries[mid]]) <= max_tokens {
+                    lo = mid;
+                } else {
+                    hi = mid - 1;
+                }
+            }
+            out.push(boundaries[from]..boundaries[lo]);
+            from = lo;
+        }
+    }
+
     /// Split text into sentences (simple implementation)
     fn split_sentences<'a>(&self, text: &'a str) -> Vec<&'a str> {
         let mut sentences = Vec::new();
@@ -406,8 +593,12 @@ impl Chunker {
 
     /// Estimate token count (rough approximation)
     pub fn estimate_tokens(text: &str) -> usize {
-        // Common approximation: ~4 characters per token for English
-        text.chars().count() / 4
+        CharEstimateTokenizer.count_tokens(text)
+    }
+
+    /// Count tokens with the chunker's tokenizer
+    pub fn count_tokens(&self, text: &str) -> usize {
+        self.tokenizer.count_tokens(text)
     }
 
     /// Get overlapping context around a chunk
@@ -484,6 +675,59 @@ mod tests {
         assert!(tokens < text.len());
     }
 
+    #[test]
+    fn test_recursive_chunking_respects_limit() {
+        let chunker = Chunker::recursive_chunker(20, 5);
+
+        let content = "First paragraph has a few sentences. It keeps going for a while.\n\n\
+            Second paragraph is here. It also has several sentences in it.\n\n\
+            Averyveryverylongwordwithoutanyspacesthatmustbesplitbycharacters.";
+        let chunks = chunker.chunk("doc5", content);
+
+        assert!(chunks.len() > 2);
+        for chunk in &chunks {
+            assert!(chunker.count_tokens(&chunk.content) <= 20, "Chunk too large: {:?}", chunk.content);
+            assert_eq!(&content[chunk.start_offset..chunk.end_offset], chunk.content);
+        }
+    }
+
+    #[test]
+    fn test_recursive_chunking_overlap() {
+        let chunker = Chunker::recursive_chunker(10, 4);
+
+        let content = "one two three four five six seven eight nine ten eleven twelve thirteen fourteen";
+        let chunks = chunker.chunk("doc6", content);
+
+        assert!(chunks.len() >= 2);
+        for pair in chunks.windows(2) {
+            // Overlapping chunks start before the previous one ends
+            assert!(pair[1].start_offset < pair[0].end_offset);
+        }
+    }
+
+    #[test]
+    fn test_chunker_with_tokenizer() {
+        use crate::rag::tokenizer::Tokenizer;
+
+        /// One token per whitespace-separated word
+        struct WordTokenizer;
+        impl Tokenizer for WordTokenizer {
+            fn count_tokens(&self, text: &str) -> usize {
+                text.split_whitespace().count()
+            }
+            fn name(&self) -> &str {
+                "words"
+            }
+        }
+
+        let chunker = Chunker::sentence_chunker(6).with_tokenizer(Arc::new(WordTokenizer));
+        let content = "One two three. Four five six. Seven eight nine.";
+        let chunks = chunker.chunk("doc7", content);
+
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].content, "One two three. Four five six.");
+    }
+
     #[test]
     fn test_empty_content() {
         let chunker = Chunker::default_chunker();
@@ -5,9 +5,11 @@
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
 use crate::storage::{Database, Node, DistanceMetric, SimilarityResult};
 use super::chunker::DocumentChunk;
+use super::tokenizer::{Tokenizer, CharEstimateTokenizer};
 
 /// Retrieved context with relevance scores
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -37,6 +39,22 @@ pub struct ContextChunk {
     pub chunk_index: Option<usize>,
     /// Additional metadata
     pub metadata: Option<serde_json::Value>,
+    /// How the chunk was reached (set by graph-augmented retrieval)
+    #[serde(default)]
+    pub provenance: Option<ChunkProvenance>,
+}
+
+/// Provenance of a chunk reached through graph expansion
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChunkProvenance {
+    /// Vector hit the expansion started from
+    pub seed_node_id: String,
+    /// Similarity score of the seed hit
+    pub seed_score: f64,
+    /// Number of edges between the seed and this chunk
+    pub hops: u32,
+    /// Edge types followed from the seed, in order
+    pub edge_path: Vec<String>,
 }
 
 impl RetrievedContext {
@@ -71,6 +89,7 @@ impl RetrievedContext {
                     "score": c.score,
                     "document_id": c.document_id,
                     "chunk_index": c.chunk_index,
+                    "provenance": c.provenance,
                 })
             }).collect::<Vec<_>>()
         })
@@ -95,6 +114,7 @@ pub struct ContextRetriever<'a> {
     max_tokens: usize,
     min_score: f64,
     metric: DistanceMetric,
+    tokenizer: Arc<dyn Tokenizer>,
 }
 
 impl<'a> ContextRetriever<'a> {
@@ -108,6 +128,7 @@ impl<'a> ContextRetriever<'a> {
             max_tokens: 4096,
             min_score: 0.0,
             metric: DistanceMetric::Cosine,
+            tokenizer: Arc::new(CharEstimateTokenizer),
         }
     }
 
@@ -147,6 +168,12 @@ impl<'a> ContextRetriever<'a> {
         self
     }
 
+    /// Set the tokenizer used to enforce the token budget
+    pub fn tokenizer(mut self, tokenizer: Arc<dyn Tokenizer>) -> Self {
+        self.tokenizer = tokenizer;
+        self
+    }
+
     /// Retrieve context for a query vector
     pub async fn retrieve(&self, query_vector: &[f32], query_text: &str) -> Result<RetrievedContext> {
         // Get more results than needed, then filter by token limit
@@ -173,7 +200,7 @@ impl<'a> ContextRetriever<'a> {
             // Get the node to extract content
             if let Some(node) = self.db.get_node(&result.node_id.to_string()).await? {
                 let content = self.extract_content(&node);
-                let tokens = estimate_tokens(&content);
+                let tokens = self.tokenizer.count_tokens(&content);
 
                 // Check token limit
                 if total_tokens + tokens > self.max_tokens {
@@ -190,6 +217,7 @@ impl<'a> ContextRetriever<'a> {
                     chunk_index: node.properties.get("chunk_index")
                         .and_then(|v| v.as_int().map(|i| i as usize)),
                     metadata: node.properties.get("metadata").map(|v| v.to_json()),
+                    provenance: None,
                 };
 
                 total_tokens += tokens;
@@ -257,6 +285,7 @@ impl<'a> ContextRetriever<'a> {
                     chunk_index: node.properties.get("chunk_index")
                         .and_then(|v| v.as_int().map(|i| i as usize)),
                     metadata: node.properties.get("metadata").map(|v| v.to_json()),
+                    provenance: None,
                 };
 
                 scored_chunks.push((combined_score, chunk));
@@ -271,7 +300,7 @@ impl<'a> ContextRetriever<'a> {
         let mut total_tokens = 0;
 
         for (_, chunk) in scored_chunks {
-            let tokens = estimate_tokens(&chunk.content);
+            let tokens = self.tokenizer.count_tokens(&chunk.content);
             if total_tokens + tokens > self.max_tokens {
                 break;
             }
@@ -287,12 +316,6 @@ impl<'a> ContextRetriever<'a> {
     }
 }
 
-/// Estimate token count from text
-fn estimate_tokens(text: &str) -> usize {
-    // Rough approximation: ~4 characters per token for English
-    text.chars().count() / 4
-}
-
 /// Simple keyword-based reranker
 pub fn keyword_reranker(query: &str, content: &str) -> f64 {
     let query_lower = query.to_lowercase();
@@ -371,6 +394,7 @@ mod tests {
                     document_id: Some("doc1".to_string()),
                     chunk_index: Some(0),
                     metadata: None,
+                    provenance: None,
                 },
                 ContextChunk {
                     node_id: "2".to_string(),
@@ -380,6 +404,7 @@ mod tests {
                     document_id: Some("doc2".to_string()),
                     chunk_index: Some(1),
                     metadata: None,
+                    provenance: None,
                 },
             ],
             estimated_tokens: 10,
@@ -395,7 +420,7 @@ mod tests {
     #[test]
     fn test_estimate_tokens() {
         let text = "This is a test with about forty characters.";
-        let tokens = estimate_tokens(text);
+        let tokens = CharEstimateTokenizer.count_tokens(text);
         assert!(tokens > 5 && tokens < 20);
     }
 }
@@ -0,0 +1,486 @@
+//! Retrieval evaluation for RAG applications
+//!
+//! Runs labelled queries against vector, keyword and hybrid retrieval
+//! configurations and reports recall@k, precision@k, MRR and nDCG@k, so
+//! that chunking, weighting and metric choices can be compared reproducibly.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::query::QueryResult;
+use crate::storage::{Database, DistanceMetric, Node, Value};
+use super::embeddings::EmbeddingManager;
+use super::hybrid::{HybridSearch, HybridSearchConfig};
+
+/// A labelled evaluation query
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EvalQuery {
+    /// Query text
+    pub query: String,
+    /// Node or chunk IDs (e.g. `doc_0`) of relevant chunks, or document
+    /// IDs of relevant documents
+    #[serde(alias = "relevant_ids")]
+    pub relevant: Vec<String>,
+    /// Precomputed query vector (embedded with the provider if absent)
+    #[serde(default)]
+    pub vector: Option<Vec<f32>>,
+}
+
+/// Load evaluation queries from a JSONL file
+pub fn load_eval_queries(path: impl AsRef<Path>) -> Result<Vec<EvalQuery>> {
+    let path = path.as_ref();
+    let data = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read eval queries from {}", path.display()))?;
+
+    data.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("Invalid eval query on line {}", i + 1))
+        })
+        .collect()
+}
+
+/// Retrieval mode under evaluation
+#[derive(Debug, Clone)]
+pub enum RetrievalMode {
+    /// Vector similarity only
+    Vector(DistanceMetric),
+    /// BM25-style keyword matching only
+    Keyword,
+    /// Reciprocal rank fusion of keyword and vector results
+    Hybrid(HybridSearchConfig),
+}
+
+/// A named retrieval configuration
+#[derive(Debug, Clone)]
+pub struct EvalConfig {
+    /// Name shown in reports
+    pub name: String,
+    /// Retrieval mode
+    pub mode: RetrievalMode,
+}
+
+impl EvalConfig {
+    /// Create a named configuration
+    pub fn new(name: &str, mode: RetrievalMode) -> Self {
+        Self { name: name.to_string(), mode }
+    }
+
+    /// Parse a configuration name
+    ///
+    /// Accepts `vector`, `keyword`, `hybrid`, `hybrid-keyword`,
+    /// `hybrid-vector` and `hybrid:<keyword_weight>` (e.g. `hybrid:0.3`).
+    pub fn from_name(name: &str) -> Result<Self> {
+        let lower = name.trim().to_lowercase();
+        let mode = match lower.as_str() {
+            "vector" => RetrievalMode::Vector(DistanceMetric::Cosine),
+            "keyword" => RetrievalMode::Keyword,
+            "hybrid" => RetrievalMode::Hybrid(HybridSearchConfig::default()),
+            "hybrid-keyword" => RetrievalMode::Hybrid(HybridSearchConfig::keyword_focused()),
+            "hybrid-vector" => RetrievalMode::Hybrid(HybridSearchConfig::vector_focused()),
+            other => match other.strip_prefix("hybrid:") {
+                Some(weight) => {
+                    let keyword_weight: f64 = weight.parse()
+                        .with_context(|| format!("Invalid hybrid keyword weight: {}", weight))?;
+                    if !(0.0..=1.0).contains(&keyword_weight) {
+                        anyhow::bail!("Hybrid keyword weight must be between 0 and 1: {}", weight);
+                    }
+                    RetrievalMode::Hybrid(HybridSearchConfig {
+                        keyword_weight,
+                        vector_weight: 1.0 - keyword_weight,
+                        ..Default::default()
+                    })
+                }
+                None => anyhow::bail!(
+                    "Unknown eval config: {}. Use: vector, keyword, hybrid, hybrid-keyword, hybrid-vector, hybrid:<weight>",
+                    name
+                ),
+            },
+        };
+        Ok(Self::new(&lower, mode))
+    }
+
+    /// The default comparison set
+    pub fn defaults() -> Vec<Self> {
+        ["vector", "keyword", "hybrid", "hybrid-keyword", "hybrid-vector"]
+            .iter()
+            .map(|name| Self::from_name(name).expect("built-in config"))
+            .collect()
+    }
+}
+
+/// Retrieval quality metrics (averaged over queries)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct RetrievalMetrics {
+    /// Fraction of relevant items found in the top k
+    pub recall: f64,
+    /// Fraction of the top k that is relevant
+    pub precision: f64,
+    /// Mean reciprocal rank of the first relevant item
+    pub mrr: f64,
+    /// Normalised discounted cumulative gain over the top k
+    pub ndcg: f64,
+}
+
+impl RetrievalMetrics {
+    /// Compute metrics for one ranked list of relevance judgements
+    ///
+    /// `judgements[i]` says whether the result at rank `i + 1` is relevant;
+    /// `num_relevant` is the total number of relevant items for the query.
+    pub fn compute(judgements: &[bool], num_relevant: usize, k: usize) -> Self {
+        let top = &judgements[..judgements.len().min(k)];
+        let hits = top.iter().filter(|&&r| r).count();
+
+        let recall = if num_relevant == 0 { 0.0 } else { hits as f64 / num_relevant as f64 };
+        let precision = if k == 0 { 0.0 } else { hits as f64 / k as f64 };
+        let mrr = top.iter()
+            .position(|&r| r)
+            .map(|i| 1.0 / (i + 1) as f64)
+            .unwrap_or(0.0);
+
+        let dcg: f64 = top.iter()
+            .enumerate()
+            .filter(|(_, &r)| r)
+            .map(|(i, _)| 1.0 / ((i + 2) as f64).log2())
+            .sum();
+        let idcg: f64 = (0..num_relevant.min(k))
+            .map(|i| 1.0 / ((i + 2) as f64).log2())
+            .sum();
+        let ndcg = if idcg == 0.0 { 0.0 } else { dcg / idcg };
+
+        Self { recall, precision, mrr, ndcg }
+    }
+
+    /// Average a set of per-query metrics
+    pub fn mean(metrics: &[RetrievalMetrics]) -> Self {
+        if metrics.is_empty() {
+            return Self::default();
+        }
+        let n = metrics.len() as f64;
+        Self {
+            recall: metrics.iter().map(|m| m.recall).sum::<f64>() / n,
+            precision: metrics.iter().map(|m| m.precision).sum::<f64>() / n,
+            mrr: metrics.iter().map(|m| m.mrr).sum::<f64>() / n,
+            ndcg: metrics.iter().map(|m| m.ndcg).sum::<f64>() / n,
+        }
+    }
+}
+
+/// Results for one configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigReport {
+    /// Configuration name
+    pub config: String,
+    /// Cutoff used for the metrics
+    pub k: usize,
+    /// Number of queries evaluated
+    pub queries: usize,
+    /// Mean metrics over all queries
+    pub metrics: RetrievalMetrics,
+}
+
+/// Results for all evaluated configurations
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EvalReport {
+    /// One entry per configuration, in evaluation order
+    pub configs: Vec<ConfigReport>,
+}
+
+impl EvalReport {
+    /// Format as JSON for structured output
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+
+    /// Convert to QueryResult for display
+    pub fn to_query_result(&self) -> QueryResult {
+        let columns = ["config", "k", "queries", "recall@k", "precision@k", "mrr", "ndcg@k"]
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+
+        let round = |v: f64| Value::Float((v * 10_000.0).round() / 10_000.0);
+        let rows = self.configs
+            .iter()
+            .map(|r| vec![
+                Value::String(r.config.clone()),
+                Value::Int(r.k as i64),
+                Value::Int(r.queries as i64),
+                round(r.metrics.recall),
+                round(r.metrics.precision),
+                round(r.metrics.mrr),
+                round(r.metrics.ndcg),
+            ])
+            .collect();
+
+        QueryResult {
+            columns,
+            rows,
+            rows_affected: 0,
+            execution_time_ms: 0,
+        }
+    }
+}
+
+/// Runs labelled queries against retrieval configurations
+pub struct Evaluator<'a> {
+    db: &'a Database,
+    embedder: &'a EmbeddingManager,
+    node_type: String,
+    embedding_field: String,
+    content_field: String,
+    k: usize,
+}
+
+impl<'a> Evaluator<'a> {
+    /// Create a new evaluator
+    pub fn new(db: &'a Database, embedder: &'a EmbeddingManager) -> Self {
+        Self {
+            db,
+            embedder,
+            node_type: "chunk".to_string(),
+            embedding_field: "embedding".to_string(),
+            content_field: "content".to_string(),
+            k: 10,
+        }
+    }
+
+    /// Set the node type to search
+    pub fn node_type(mut self, node_type: &str) -> Self {
+        self.node_type = node_type.to_string();
+        self
+    }
+
+    /// Set the field containing embeddings
+    pub fn embedding_field(mut self, field: &str) -> Self {
+        self.embedding_field = field.to_string();
+        self
+    }
+
+    /// Set the field containing text content
+    pub fn content_field(mut self, field: &str) -> Self {
+        self.content_field = field.to_string();
+        self
+    }
+
+    /// Set the metric cutoff k
+    pub fn k(mut self, k: usize) -> Self {
+        self.k = k;
+        self
+    }
+
+    /// Evaluate every configuration over the queries
+    pub async fn run(&self, queries: &[EvalQuery], configs: &[EvalConfig]) -> Result<EvalReport> {
+        // Embed each query once and share the vector across configurations
+        let mut vectors = Vec::with_capacity(queries.len());
+        for query in queries {
+            let vector = match &query.vector {
+                Some(v) => v.clone(),
+                None => self.embedder.embed(&query.query).await?,
+            };
+            vectors.push(vector);
+        }
+
+        let mut reports = Vec::with_capacity(configs.len());
+        for config in configs {
+            let mut per_query = Vec::with_capacity(queries.len());
+            for (query, vector) in queries.iter().zip(&vectors) {
+                let ranked = self.retrieve(&config.mode, &query.query, vector).await?;
+                let relevant: HashSet<&str> = query.relevant.iter().map(|s| s.as_str()).collect();
+                let judgements = judge(&ranked, &relevant);
+                per_query.push(RetrievalMetrics::compute(&judgements, relevant.len(), self.k));
+            }
+
+            reports.push(ConfigReport {
+                config: config.name.clone(),
+                k: self.k,
+                queries: queries.len(),
+                metrics: RetrievalMetrics::mean(&per_query),
+            });
+        }
+
+        Ok(EvalReport { configs: reports })
+    }
+
+    /// Run one retrieval and return the ranked nodes
+    async fn retrieve(&self, mode: &RetrievalMode, text: &str, vector: &[f32]) -> Result<Vec<Node>> {
+        let ids: Vec<String> = match mode {
+            RetrievalMode::Vector(metric) => {
+                self.db.similarity_search(vector, &self.node_type, &self.embedding_field, self.k, *metric)
+                    .await?
+                    .into_iter()
+                    .map(|r| r.node_id.to_string())
+                    .collect()
+            }
+            RetrievalMode::Keyword => {
+                HybridSearch::new(self.db)
+                    .keyword_search(text, &self.node_type, &self.content_field, self.k)
+                    .await?
+                    .into_iter()
+                    .map(|(id, _)| id.to_string())
+                    .collect()
+            }
+            RetrievalMode::Hybrid(config) => {
+                let results = HybridSearch::with_config(self.db, config.clone())
+                    .search(text, vector, &self.node_type, &self.content_field, &self.embedding_field, self.k)
+                    .await?;
+                return Ok(results.into_iter().filter_map(|r| r.node).collect());
+            }
+        };
+
+        let mut nodes = Vec::with_capacity(ids.len());
+        for id in ids {
+            if let Some(node) = self.db.get_node(&id).await? {
+                nodes.push(node);
+            }
+        }
+        Ok(nodes)
+    }
+}
+
+/// Judge each ranked node against the relevant set
+///
+/// A node is relevant if its node ID, its chunk ID (`<document_id>_<chunk_index>`,
+/// as the chunker names chunks) or its `document_id` is labelled relevant.
+/// Each relevant label is credited once, so several chunks of the same
+/// relevant document do not inflate recall.
+fn judge(ranked: &[Node], relevant: &HashSet<&str>) -> Vec<bool> {
+    let mut credited: HashSet<String> = HashSet::new();
+    ranked
+        .iter()
+        .map(|node| {
+            let id = node.id.to_string();
+            let document_id = node.properties.get("document_id")
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_string());
+            let chunk_id = document_id.as_ref().zip(
+                node.properties.get("chunk_index").and_then(|v| v.as_int()),
+            ).map(|(document_id, index)| format!("{}_{}", document_id, index));
+
+            let label = std::iter::once(id)
+                .chain(chunk_id)
+                .chain(document_id)
+                .find(|key| relevant.contains(key.as_str()));
+            match label {
+                Some(label) => credited.insert(label),
+                None => false,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_metrics_compute() {
+        // Relevant items at ranks 2 and 3, three relevant overall
+        let m = RetrievalMetrics::compute(&[false, true, true, false], 3, 4);
+        assert!((m.recall - 2.0 / 3.0).abs() < 1e-9);
+        assert!((m.precision - 0.5).abs() < 1e-9);
+        assert!((m.mrr - 0.5).abs() < 1e-9);
+
+        let dcg = 1.0 / 3f64.log2() + 1.0 / 4f64.log2();
+        let idcg = 1.0 + 1.0 / 3f64.log2() + 1.0 / 4f64.log2();
+        assert!((m.ndcg - dcg / idcg).abs() < 1e-9);
+
+        let perfect = RetrievalMetrics::compute(&[true, true], 2, 2);
+        assert_eq!(perfect, RetrievalMetrics { recall: 1.0, precision: 1.0, mrr: 1.0, ndcg: 1.0 });
+
+        let empty = RetrievalMetrics::compute(&[], 2, 5);
+        assert_eq!(empty, RetrievalMetrics::default());
+    }
+
+    #[test]
+    fn test_judge_chunk_labels() {
+        let chunk = |document_id: &str, index: i64| {
+            let mut properties = std::collections::BTreeMap::new();
+            properties.insert("document_id".to_string(), Value::String(document_id.to_string()));
+            properties.insert("chunk_index".to_string(), Value::Int(index));
+            Node::new("chunk", Value::Object(properties))
+        };
+        let ranked = vec![chunk("guide", 0), chunk("guide", 1), chunk("faq", 3), chunk("faq", 4)];
+        let node_id = ranked[3].id.to_string();
+
+        // Chunk IDs as the chunker prints them, a whole document and a node ID
+        let relevant: HashSet<&str> = ["guide_1", "faq", node_id.as_str()].into_iter().collect();
+        assert_eq!(judge(&ranked, &relevant), vec![false, true, true, true]);
+
+        // A document label is credited once however many of its chunks rank
+        let relevant: HashSet<&str> = ["guide"].into_iter().collect();
+        assert_eq!(judge(&ranked, &relevant), vec![true, false, false, false]);
+    }
+
+    #[test]
+    fn test_config_from_name() {
+        assert!(matches!(EvalConfig::from_name("keyword").unwrap().mode, RetrievalMode::Keyword));
+        match EvalConfig::from_name("hybrid:0.25").unwrap().mode {
+            RetrievalMode::Hybrid(c) => assert_eq!(c.vector_weight, 0.75),
+            _ => panic!("expected hybrid"),
+        }
+        assert!(EvalConfig::from_name("hybrid:2").is_err());
+        assert!(EvalConfig::from_name("bm42").is_err());
+    }
+
+    #[test]
+    fn test_load_eval_queries() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("queries.jsonl");
+        std::fs::write(&path, "{\"query\": \"a\", \"relevant\": [\"doc1\"]}\n\n{\"query\": \"b\", \"relevant_ids\": []}\n").unwrap();
+        let queries = load_eval_queries(&path).unwrap();
+        assert_eq!(queries.len(), 2);
+        assert_eq!(queries[0].relevant, vec!["doc1".to_string()]);
+
+        std::fs::write(&path, "{\"query\": \"a\"}\n").unwrap();
+        let err = load_eval_queries(&path).unwrap_err();
+        assert!(err.to_string().contains("line 1"));
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_configs() {
+        let temp = TempDir::new().unwrap();
+        let db = Database::create(temp.path(), "test").await.unwrap();
+        let embedder = EmbeddingManager::local_default();
+
+        let docs = [
+            ("rust", "Rust guarantees memory safety through ownership"),
+            ("bread", "Sourdough bread needs a long fermentation"),
+            ("tea", "Green tea is brewed at a lower temperature"),
+        ];
+        for (doc_id, content) in docs {
+            let embedding = embedder.embed(content).await.unwrap();
+            db.insert_with_embedding("chunk", serde_json::json!({
+                "content": content,
+                "document_id": doc_id,
+            }), "embedding", embedding).await.unwrap();
+        }
+
+        let queries = vec![
+            EvalQuery { query: "memory ownership".to_string(), relevant: vec!["rust".to_string()], vector: None },
+            EvalQuery { query: "sourdough fermentation".to_string(), relevant: vec!["bread".to_string()], vector: None },
+        ];
+
+        let report = Evaluator::new(&db, &embedder)
+            .k(2)
+            .run(&queries, &EvalConfig::defaults())
+            .await
+            .unwrap();
+
+        assert_eq!(report.configs.len(), 5);
+        let keyword = report.configs.iter().find(|r| r.config == "keyword").unwrap();
+        assert_eq!(keyword.queries, 2);
+        assert_eq!(keyword.metrics.recall, 1.0);
+        assert_eq!(keyword.metrics.mrr, 1.0);
+        assert_eq!(keyword.metrics.precision, 0.5);
+
+        let table = report.to_query_result();
+        assert_eq!(table.row_count(), 5);
+        assert_eq!(table.columns[3], "recall@k");
+    }
+}
@@ -0,0 +1,588 @@
+//! Entity and relation extraction for RAG ingest
+//!
+//! Turns chunk text into graph structure: extracted entities become
+//! `entity` nodes (deduplicated by normalised name), chunks are linked to
+//! them with `mentions` edges, and relations become typed entity-to-entity
+//! edges.
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use crate::storage::Database;
+
+/// Node type used for extracted entities
+pub const ENTITY_NODE_TYPE: &str = "entity";
+
+/// Edge type linking a chunk to the entities it mentions
+pub const MENTIONS_EDGE_TYPE: &str = "mentions";
+
+/// An entity found in a piece of text
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entity {
+    /// Surface name of the entity
+    pub name: String,
+    /// Entity type (e.g. "person", "organization")
+    #[serde(rename = "type", default = "default_entity_type")]
+    pub entity_type: String,
+}
+
+/// A directed relation between two entities
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Relation {
+    /// Name of the source entity
+    pub source: String,
+    /// Name of the target entity
+    pub target: String,
+    /// Relation type, used as the edge type
+    #[serde(rename = "type")]
+    pub relation_type: String,
+}
+
+/// Entities and relations extracted from a piece of text
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Extraction {
+    /// Extracted entities
+    #[serde(default)]
+    pub entities: Vec<Entity>,
+    /// Extracted relations
+    #[serde(default)]
+    pub relations: Vec<Relation>,
+}
+
+fn default_entity_type() -> String {
+    "entity".to_string()
+}
+
+/// Entity extractor trait
+#[async_trait]
+pub trait EntityExtractor: Send + Sync {
+    /// Extract entities and relations from text
+    async fn extract(&self, text: &str) -> Result<Extraction>;
+
+    /// Get extractor name
+    fn name(&self) -> &str;
+}
+
+/// Normalise an entity name for deduplication
+///
+/// Lowercases, collapses whitespace and strips surrounding punctuation, so
+/// "  Ada  Lovelace," and "ada lovelace" map to the same entity.
+pub fn normalize_entity_name(name: &str) -> String {
+    name.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .trim_matches(|c: char| !c.is_alphanumeric())
+        .to_lowercase()
+}
+
+/// Turn a free-form relation label into an edge type (`works at` -> `works_at`)
+fn normalize_relation_type(relation: &str) -> String {
+    relation
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_lowercase())
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+/// Rule-based extractor driven by regular expressions
+///
+/// Entity rules capture the whole match (or the `name` group) as an entity
+/// of the rule's type. Relation rules must define `source` and `target`
+/// groups; both endpoints are also emitted as entities.
+pub struct RegexExtractor {
+    entity_rules: Vec<(Regex, String)>,
+    relation_rules: Vec<(Regex, String)>,
+}
+
+impl RegexExtractor {
+    /// Create an extractor with no rules
+    pub fn empty() -> Self {
+        Self {
+            entity_rules: Vec::new(),
+            relation_rules: Vec::new(),
+        }
+    }
+
+    /// Add an entity rule
+    pub fn entity_rule(mut self, pattern: &str, entity_type: &str) -> Result<Self> {
+        let regex = Regex::new(pattern)
+            .with_context(|| format!("Invalid entity pattern: {}", pattern))?;
+        self.entity_rules.push((regex, entity_type.to_string()));
+        Ok(self)
+    }
+
+    /// Add a relation rule with `source` and `target` capture groups
+    pub fn relation_rule(mut self, pattern: &str, relation_type: &str) -> Result<Self> {
+        let regex = Regex::new(pattern)
+            .with_context(|| format!("Invalid relation pattern: {}", pattern))?;
+        let groups: Vec<&str> = regex.capture_names().flatten().collect();
+        if !groups.contains(&"source") || !groups.contains(&"target") {
+            anyhow::bail!("Relation pattern must define `source` and `target` groups: {}", pattern);
+        }
+        self.relation_rules.push((regex, relation_type.to_string()));
+        Ok(self)
+    }
+}
+
+impl Default for RegexExtractor {
+    /// Built-in rules: emails, URLs, capitalised multi-word names and a few
+    /// common relation phrasings between names
+    fn default() -> Self {
+        const NAME: &str = r"[A-Z][\w&-]*(?:\s+[A-Z][\w&-]*)+";
+        let relation = |verb: &str| {
+            format!(r"(?P<source>{NAME})\s+{verb}\s+(?:the\s+)?(?P<target>{NAME}|[A-Z][\w&-]+)")
+        };
+
+        Self::empty()
+            .entity_rule(r"\b[\w.+-]+@[\w-]+\.[\w.-]+\b", "email").unwrap()
+            .entity_rule(r"https?://[^\s)>\]]+", "url").unwrap()
+            .entity_rule(&format!(r"\b{NAME}\b"), "name").unwrap()
+            .relation_rule(&relation(r"(?:works|worked)\s+(?:at|for)"), "works_at").unwrap()
+            .relation_rule(&relation(r"(?:founded|co-founded)"), "founded").unwrap()
+            .relation_rule(&relation(r"(?:is|was)\s+(?:a\s+)?part\s+of"), "part_of").unwrap()
+            .relation_rule(&relation(r"(?:is|was)\s+located\s+in"), "located_in").unwrap()
+    }
+}
+
+#[async_trait]
+impl EntityExtractor for RegexExtractor {
+    async fn extract(&self, text: &str) -> Result<Extraction> {
+        let mut extraction = Extraction::default();
+        let mut seen: HashSet<String> = HashSet::new();
+
+        let mut push_entity = |extraction: &mut Extraction, name: &str, entity_type: &str| {
+            if seen.insert(normalize_entity_name(name)) {
+                extraction.entities.push(Entity {
+                    name: name.trim().to_string(),
+                    entity_type: entity_type.to_string(),
+                });
+            }
+        };
+
+        for (regex, entity_type) in &self.entity_rules {
+            for caps in regex.captures_iter(text) {
+                let m = caps.name("name").or_else(|| caps.get(0));
+                if let Some(m) = m {
+                    push_entity(&mut extraction, m.as_str(), entity_type);
+                }
+            }
+        }
+
+        for (regex, relation_type) in &self.relation_rules {
+            for caps in regex.captures_iter(text) {
+                let (Some(source), Some(target)) = (caps.name("source"), caps.name("target")) else {
+                    continue;
+                };
+                push_entity(&mut extraction, source.as_str(), "name");
+                push_entity(&mut extraction, target.as_str(), "name");
+                extraction.relations.push(Relation {
+                    source: source.as_str().trim().to_string(),
+                    target: target.as_str().trim().to_string(),
+                    relation_type: relation_type.clone(),
+                });
+            }
+        }
+
+        Ok(extraction)
+    }
+
+    fn name(&self) -> &str {
+        "regex"
+    }
+}
+
+const LLM_SYSTEM_PROMPT: &str = "Extract named entities and the relations between them from the \
+user's text. Respond with a single JSON object of the form \
+{\"entities\": [{\"name\": string, \"type\": string}], \
+\"relations\": [{\"source\": string, \"target\": string, \"type\": string}]}. \
+Relation sources and targets must be entity names. Use short snake_case relation types.";
+
+/// LLM-backed extractor using an OpenAI-compatible chat completions endpoint
+pub struct LlmExtractor {
+    base_url: String,
+    api_key: Option<String>,
+    model: String,
+    client: reqwest::Client,
+}
+
+impl LlmExtractor {
+    /// Default OpenAI API base URL
+    pub const DEFAULT_BASE_URL: &'static str = "https://api.openai.com/v1";
+
+    /// Default chat model
+    pub const DEFAULT_MODEL: &'static str = "gpt-4o-mini";
+
+    /// Create a new extractor against `base_url` (e.g. `http://localhost:11434/v1`)
+    pub fn new(base_url: &str, api_key: Option<String>, model: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key,
+            model: model.to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Create against the OpenAI API using the OPENAI_API_KEY environment variable
+    pub fn from_env(model: &str) -> Result<Self> {
+        let api_key = std::env::var("OPENAI_API_KEY")
+            .context("OPENAI_API_KEY environment variable not set")?;
+        Ok(Self::new(Self::DEFAULT_BASE_URL, Some(api_key), model))
+    }
+}
+
+#[derive(Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: Vec<ChatMessage<'a>>,
+    temperature: f32,
+    response_format: serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct ChatMessage<'a> {
+    role: &'a str,
+    content: &'a str,
+}
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    choices: Vec<ChatChoice>,
+}
+
+#[derive(Deserialize)]
+struct ChatChoice {
+    message: ChatResponseMessage,
+}
+
+#[derive(Deserialize)]
+struct ChatResponseMessage {
+    content: Option<String>,
+}
+
+/// Parse the model's JSON answer, tolerating a surrounding Markdown code fence
+fn parse_llm_extraction(content: &str) -> Result<Extraction> {
+    let trimmed = content.trim();
+    let json = trimmed
+        .strip_prefix("```json")
+        .or_else(|| trimmed.strip_prefix("```"))
+        .and_then(|s| s.strip_suffix("```"))
+        .unwrap_or(trimmed);
+    serde_json::from_str(json.trim()).context("LLM returned invalid extraction JSON")
+}
+
+#[async_trait]
+impl EntityExtractor for LlmExtractor {
+    async fn extract(&self, text: &str) -> Result<Extraction> {
+        let request = ChatRequest {
+            model: &self.model,
+            messages: vec![
+                ChatMessage { role: "system", content: LLM_SYSTEM_PROMPT },
+                ChatMessage { role: "user", content: text },
+            ],
+            temperature: 0.0,
+            response_format: serde_json::json!({"type": "json_object"}),
+        };
+
+        let mut builder = self.client
+            .post(format!("{}/chat/completions", self.base_url))
+            .header("Content-Type", "application/json")
+            .json(&request);
+        if let Some(ref key) = self.api_key {
+            builder = builder.header("Authorization", format!("Bearer {}", key));
+        }
+
+        let response = builder.send().await
+            .context("Failed to send extraction request")?;
+
+        if !response.status().is_success() {
+            let error_text = response.text().await.unwrap_or_default();
+            anyhow::bail!("Extraction API error: {}", error_text);
+        }
+
+        let result: ChatResponse = response.json().await
+            .context("Failed to parse extraction response")?;
+
+        let content = result.choices.into_iter().next()
+            .and_then(|c| c.message.content)
+            .ok_or_else(|| anyhow::anyhow!("No extraction returned"))?;
+
+        parse_llm_extraction(&content)
+    }
+
+    fn name(&self) -> &str {
+        "llm"
+    }
+}
+
+/// Create an extractor by name (`regex` or `llm`)
+pub fn extractor_from_name(
+    name: &str,
+    base_url: Option<&str>,
+    api_key: Option<&str>,
+    model: Option<&str>,
+) -> Result<Box<dyn EntityExtractor>> {
+    match name.to_lowercase().as_str() {
+        "regex" | "rules" => Ok(Box::new(RegexExtractor::default())),
+        "llm" | "openai" => {
+            let base_url = base_url.unwrap_or(LlmExtractor::DEFAULT_BASE_URL);
+            let api_key = api_key
+                .map(|k| k.to_string())
+                .or_else(|| std::env::var("OPENAI_API_KEY").ok());
+            if api_key.is_none() && base_url == LlmExtractor::DEFAULT_BASE_URL {
+                anyhow::bail!("LLM extraction requires API key (--api-key or OPENAI_API_KEY env var)");
+            }
+            Ok(Box::new(LlmExtractor::new(
+                base_url,
+                api_key,
+                model.unwrap_or(LlmExtractor::DEFAULT_MODEL),
+            )))
+        }
+        _ => anyhow::bail!("Unknown extractor: {}. Use: regex, llm", name),
+    }
+}
+
+/// Counts of graph elements written for an extraction
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LinkStats {
+    /// Entity nodes created
+    pub entities_created: usize,
+    /// Existing entity nodes reused
+    pub entities_reused: usize,
+    /// `mentions` edges created
+    pub mentions: usize,
+    /// Relation edges created
+    pub relations: usize,
+}
+
+impl LinkStats {
+    /// Accumulate another set of stats
+    pub fn merge(&mut self, other: &LinkStats) {
+        self.entities_created += other.entities_created;
+        self.entities_reused += other.entities_reused;
+        self.mentions += other.mentions;
+        self.relations += other.relations;
+    }
+}
+
+/// Writes extractions into the graph, deduplicating entities by normalised name
+pub struct EntityLinker<'a> {
+    db: &'a Database,
+    /// Normalised name -> entity node ID, loaded lazily from the database
+    entities: Option<HashMap<String, String>>,
+}
+
+impl<'a> EntityLinker<'a> {
+    /// Create a new linker
+    pub fn new(db: &'a Database) -> Self {
+        Self { db, entities: None }
+    }
+
+    /// Link a chunk node to the entities and relations extracted from it
+    pub async fn link(&mut self, chunk_id: &str, extraction: &Extraction) -> Result<LinkStats> {
+        let mut stats = LinkStats::default();
+        let mut mentioned: HashSet<String> = HashSet::new();
+
+        for entity in &extraction.entities {
+            if let Some(entity_id) = self.resolve(&entity.name, &entity.entity_type, &mut stats).await? {
+                if mentioned.insert(entity_id.clone()) {
+                    self.db.create_edge(chunk_id, &entity_id, MENTIONS_EDGE_TYPE, None).await?;
+                    stats.mentions += 1;
+                }
+            }
+        }
+
+        for relation in &extraction.relations {
+            let edge_type = normalize_relation_type(&relation.relation_type);
+            if edge_type.is_empty() {
+                continue;
+            }
+            let Some(source) = self.resolve(&relation.source, "entity", &mut stats).await? else { continue };
+            let Some(target) = self.resolve(&relation.target, "entity", &mut stats).await? else { continue };
+            if source == target {
+                continue;
+            }
+
+            let existing = self.db.get_edges_from(&source, Some(&edge_type)).await?;
+            if existing.iter().any(|e| e.to.to_string() == target) {
+                continue;
+            }
+            self.db.create_edge(&source, &target, &edge_type, None).await?;
+            stats.relations += 1;
+        }
+
+        Ok(stats)
+    }
+
+    /// Find or create the entity node for `name`
+    async fn resolve(&mut self, name: &str, entity_type: &str, stats: &mut LinkStats) -> Result<Option<String>> {
+        let normalized = normalize_entity_name(name);
+        if normalized.is_empty() {
+            return Ok(None);
+        }
+
+        if self.entities.is_none() {
+            let mut known = HashMap::new();
+            for node in self.db.get_all_by_type(ENTITY_NODE_TYPE, None).await? {
+                if let Some(key) = node.properties.get("normalized_name").and_then(|v| v.as_str()) {
+                    known.insert(key.to_string(), node.id.to_string());
+                }
+            }
+            self.entities = Some(known);
+        }
+        let entities = self.entities.as_mut().expect("entity cache loaded");
+
+        if let Some(id) = entities.get(&normalized) {
+            stats.entities_reused += 1;
+            return Ok(Some(id.clone()));
+        }
+
+        let node = self.db.insert_node(ENTITY_NODE_TYPE, serde_json::json!({
+            "name": name.split_whitespace().collect::<Vec<_>>().join(" "),
+            "normalized_name": normalized,
+            "entity_type": entity_type,
+        })).await?;
+        let id = node.id.to_string();
+        entities.insert(normalized, id.clone());
+        stats.entities_created += 1;
+        Ok(Some(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn test_normalize_entity_name() {
+        assert_eq!(normalize_entity_name("  Ada   Lovelace, "), "ada lovelace");
+        assert_eq!(normalize_entity_name("\"ACME Corp.\""), "acme corp");
+        assert_eq!(normalize_relation_type("Works At"), "works_at");
+    }
+
+    #[tokio::test]
+    async fn test_regex_extractor() {
+        let extractor = RegexExtractor::default();
+        let text = "Ada Lovelace works at Analytical Engines. Contact ada@example.com.";
+        let extraction = extractor.extract(text).await.unwrap();
+
+        let names: Vec<&str> = extraction.entities.iter().map(|e| e.name.as_str()).collect();
+        assert!(names.contains(&"Ada Lovelace"));
+        assert!(names.contains(&"Analytical Engines"));
+        assert!(names.contains(&"ada@example.com"));
+        assert_eq!(extraction.relations, vec![Relation {
+            source: "Ada Lovelace".to_string(),
+            target: "Analytical Engines".to_string(),
+            relation_type: "works_at".to_string(),
+        }]);
+    }
+
+    #[test]
+    fn test_relation_rule_requires_groups() {
+        assert!(RegexExtractor::empty().relation_rule(r"(?P<source>\w+) likes", "likes").is_err());
+    }
+
+    #[test]
+    fn test_parse_llm_extraction_fenced() {
+        let content = "```json\n{\"entities\": [{\"name\": \"Rust\", \"type\": \"language\"}]}\n```";
+        let extraction = parse_llm_extraction(content).unwrap();
+        assert_eq!(extraction.entities[0].entity_type, "language");
+        assert!(extraction.relations.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_llm_extractor_against_mock_endpoint() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 4096];
+            // Read until the JSON body has been fully received
+            loop {
+                let n = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some(split) = text.find("\r\n\r\n") {
+                    let length = text[..split].lines()
+                        .find_map(|l| l.to_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()))
+                        .unwrap_or(0);
+                    if request.len() >= split + 4 + length {
+                        break;
+                    }
+                }
+            }
+
+            let content = serde_json::json!({
+                "entities": [{"name": "Grace Hopper", "type": "person"}, {"name": "COBOL", "type": "language"}],
+                "relations": [{"source": "Grace Hopper", "target": "COBOL", "type": "designed"}],
+            }).to_string();
+            let body = serde_json::json!({"choices": [{"message": {"role": "assistant", "content": content}}]}).to_string();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let extractor = LlmExtractor::new(&format!("http://{}/v1", addr), Some("test-key".to_string()), "test-model");
+        let extraction = extractor.extract("Grace Hopper designed COBOL.").await.unwrap();
+        let request = server.await.unwrap();
+
+        assert!(request.starts_with("POST /v1/chat/completions"));
+        assert!(request.contains("Bearer test-key"));
+        assert!(request.contains("\"model\":\"test-model\""));
+        assert_eq!(extraction.entities.len(), 2);
+        assert_eq!(extraction.relations[0].relation_type, "designed");
+    }
+
+    #[tokio::test]
+    async fn test_linker_dedupes_entities() {
+        let temp = TempDir::new().unwrap();
+        let db = Database::create(temp.path(), "test").await.unwrap();
+        let first = db.insert_node("chunk", serde_json::json!({"content": "a"})).await.unwrap().id.to_string();
+        let second = db.insert_node("chunk", serde_json::json!({"content": "b"})).await.unwrap().id.to_string();
+
+        let extraction = Extraction {
+            entities: vec![
+                Entity { name: "Ada Lovelace".to_string(), entity_type: "person".to_string() },
+                Entity { name: "Analytical Engine".to_string(), entity_type: "machine".to_string() },
+            ],
+            relations: vec![Relation {
+                source: "Ada Lovelace".to_string(),
+                target: "analytical  engine".to_string(),
+                relation_type: "worked on".to_string(),
+            }],
+        };
+
+        let mut linker = EntityLinker::new(&db);
+        let stats = linker.link(&first, &extraction).await.unwrap();
+        assert_eq!(stats.entities_created, 2);
+        assert_eq!(stats.mentions, 2);
+        assert_eq!(stats.relations, 1);
+
+        // A fresh linker reloads known entities from the database
+        let mut linker = EntityLinker::new(&db);
+        let stats = linker.link(&second, &extraction).await.unwrap();
+        assert_eq!(stats.entities_created, 0);
+        assert_eq!(stats.mentions, 2);
+        assert_eq!(stats.relations, 0);
+
+        assert_eq!(db.get_all_by_type(ENTITY_NODE_TYPE, None).await.unwrap().len(), 2);
+        let mentions = db.get_edges_from(&second, Some(MENTIONS_EDGE_TYPE)).await.unwrap();
+        assert_eq!(mentions.len(), 2);
+        let mut relations = 0;
+        for node in db.get_all_by_type(ENTITY_NODE_TYPE, None).await.unwrap() {
+            relations += db.get_edges_from(&node.id.to_string(), Some("worked_on")).await.unwrap().len();
+        }
+        assert_eq!(relations, 1);
+    }
+}
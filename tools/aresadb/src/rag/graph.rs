@@ -0,0 +1,439 @@
+//! Graph-augmented retrieval (GraphRAG)
+//!
+//! Seeds retrieval with the top-k vector hits, then expands each hit along
+//! configured edge types, in both directions, so that neighbouring chunks,
+//! parent documents and chunks that mention the same entities are packed
+//! into the context alongside the hits.
+
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use crate::query::QueryEngine;
+use crate::storage::{DistanceMetric, Node};
+use super::context::{ChunkProvenance, ContextChunk, RetrievedContext};
+use super::extraction::MENTIONS_EDGE_TYPE;
+use super::tokenizer::{CharEstimateTokenizer, Tokenizer};
+
+/// Edge types expanded by default
+pub const DEFAULT_EXPANSION_EDGES: &[&str] = &["next", "part_of", "mentions"];
+
+/// How the score of an expanded node decays with its distance from the seed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScoreDecay {
+    /// Multiply the seed score by `factor` for every hop
+    Exponential(f64),
+    /// Subtract `step` times the seed score for every hop
+    Linear(f64),
+    /// Divide the seed score by `1 + hops`
+    Reciprocal,
+}
+
+impl ScoreDecay {
+    /// Score of a node `hops` edges away from a seed scored `seed_score`
+    pub fn apply(&self, seed_score: f64, hops: u32) -> f64 {
+        let hops_f = hops as f64;
+        let score = match self {
+            ScoreDecay::Exponential(factor) => seed_score * factor.powi(hops as i32),
+            ScoreDecay::Linear(step) => seed_score * (1.0 - step * hops_f),
+            ScoreDecay::Reciprocal => seed_score / (1.0 + hops_f),
+        };
+        score.max(0.0)
+    }
+}
+
+impl Default for ScoreDecay {
+    fn default() -> Self {
+        ScoreDecay::Exponential(0.5)
+    }
+}
+
+/// Context retriever that expands vector hits through the graph
+pub struct GraphRetriever<'a> {
+    engine: &'a QueryEngine,
+    node_type: String,
+    embedding_field: String,
+    content_field: String,
+    top_k: usize,
+    max_depth: u32,
+    edge_types: Vec<String>,
+    decay: ScoreDecay,
+    max_tokens: usize,
+    min_score: f64,
+    metric: DistanceMetric,
+    tokenizer: Arc<dyn Tokenizer>,
+}
+
+impl<'a> GraphRetriever<'a> {
+    /// Create a new graph retriever
+    pub fn new(engine: &'a QueryEngine) -> Self {
+        Self {
+            engine,
+            node_type: "chunk".to_string(),
+            embedding_field: "embedding".to_string(),
+            content_field: "content".to_string(),
+            top_k: 5,
+            max_depth: 1,
+            edge_types: DEFAULT_EXPANSION_EDGES.iter().map(|s| s.to_string()).collect(),
+            decay: ScoreDecay::default(),
+            max_tokens: 4096,
+            min_score: 0.0,
+            metric: DistanceMetric::Cosine,
+            tokenizer: Arc::new(CharEstimateTokenizer),
+        }
+    }
+
+    /// Set the node type to search
+    pub fn node_type(mut self, node_type: &str) -> Self {
+        self.node_type = node_type.to_string();
+        self
+    }
+
+    /// Set the field containing embeddings
+    pub fn embedding_field(mut self, field: &str) -> Self {
+        self.embedding_field = field.to_string();
+        self
+    }
+
+    /// Set the field containing text content
+    pub fn content_field(mut self, field: &str) -> Self {
+        self.content_field = field.to_string();
+        self
+    }
+
+    /// Set the number of vector hits used as expansion seeds
+    pub fn top_k(mut self, k: usize) -> Self {
+        self.top_k = k;
+        self
+    }
+
+    /// Set how many edges to follow away from each seed
+    pub fn max_depth(mut self, depth: u32) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Set the edge types to expand along
+    pub fn edge_types(mut self, edge_types: &[&str]) -> Self {
+        self.edge_types = edge_types.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    /// Set the score decay applied to expanded nodes
+    pub fn decay(mut self, decay: ScoreDecay) -> Self {
+        self.decay = decay;
+        self
+    }
+
+    /// Set maximum tokens to retrieve
+    pub fn max_tokens(mut self, tokens: usize) -> Self {
+        self.max_tokens = tokens;
+        self
+    }
+
+    /// Set minimum score threshold (applied after decay)
+    pub fn min_score(mut self, score: f64) -> Self {
+        self.min_score = score;
+        self
+    }
+
+    /// Set distance metric
+    pub fn metric(mut self, metric: DistanceMetric) -> Self {
+        self.metric = metric;
+        self
+    }
+
+    /// Set the tokenizer used to enforce the token budget
+    pub fn tokenizer(mut self, tokenizer: Arc<dyn Tokenizer>) -> Self {
+        self.tokenizer = tokenizer;
+        self
+    }
+
+    /// Retrieve graph-expanded context for a query vector
+    pub async fn retrieve(&self, query_vector: &[f32], query_text: &str) -> Result<RetrievedContext> {
+        let db = self.engine.database();
+        let seeds = db.similarity_search(
+            query_vector,
+            &self.node_type,
+            &self.embedding_field,
+            self.top_k,
+            self.metric,
+        ).await?;
+
+        // Best candidate per node id; a node reachable from several seeds
+        // keeps the highest decayed score and that path's provenance.
+        let mut candidates: HashMap<String, ContextChunk> = HashMap::new();
+
+        for seed in seeds {
+            if seed.score < self.min_score {
+                continue;
+            }
+            let seed_id = seed.node_id.to_string();
+            let reached = self.shortest_paths(&seed_id).await?;
+
+            for (node_id, edge_path) in reached {
+                let hops = edge_path.len() as u32;
+                let score = self.decay.apply(seed.score, hops);
+                if score < self.min_score {
+                    continue;
+                }
+                if candidates.get(&node_id).is_some_and(|c| c.score >= score) {
+                    continue;
+                }
+
+                let Some(node) = db.get_node(&node_id).await? else { continue };
+
+                let content = self.extract_content(&node);
+                if content.is_empty() {
+                    continue;
+                }
+
+                candidates.insert(node_id.clone(), ContextChunk {
+                    node_id,
+                    content,
+                    score,
+                    distance: if hops == 0 { seed.distance } else { 1.0 - score },
+                    document_id: node.properties.get("document_id")
+                        .and_then(|v| v.as_str().map(|s| s.to_string())),
+                    chunk_index: node.properties.get("chunk_index")
+                        .and_then(|v| v.as_int().map(|i| i as usize)),
+                    metadata: node.properties.get("metadata").map(|v| v.to_json()),
+                    provenance: Some(ChunkProvenance {
+                        seed_node_id: seed_id.clone(),
+                        seed_score: seed.score,
+                        hops,
+                        edge_path,
+                    }),
+                });
+            }
+        }
+
+        let mut ranked: Vec<ContextChunk> = candidates.into_values().collect();
+        ranked.sort_by(|a, b| {
+            b.score.partial_cmp(&a.score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.node_id.cmp(&b.node_id))
+        });
+
+        // Pack greedily: a chunk that does not fit is skipped so that smaller,
+        // lower-ranked neighbours can still use the remaining budget.
+        let mut chunks = Vec::new();
+        let mut total_tokens = 0;
+
+        for chunk in ranked {
+            let tokens = self.tokenizer.count_tokens(&chunk.content);
+            if total_tokens + tokens > self.max_tokens {
+                continue;
+            }
+            total_tokens += tokens;
+            chunks.push(chunk);
+        }
+
+        Ok(RetrievedContext {
+            chunks,
+            estimated_tokens: total_tokens,
+            query: query_text.to_string(),
+        })
+    }
+
+    /// Shortest edge-type path from a seed to every node within `max_depth`
+    /// hops, following the configured edges in both directions
+    async fn shortest_paths(&self, seed_id: &str) -> Result<BTreeMap<String, Vec<String>>> {
+        let mut paths = BTreeMap::from([(seed_id.to_string(), Vec::new())]);
+        let mut frontier = vec![seed_id.to_string()];
+
+        for _ in 0..self.max_depth {
+            let mut next_frontier = Vec::new();
+            for current in frontier {
+                let path = paths[&current].clone();
+                for (neighbour, edge_type) in self.neighbours(&current).await? {
+                    if paths.contains_key(&neighbour) {
+                        continue;
+                    }
+                    let mut next = path.clone();
+                    next.push(edge_type);
+                    paths.insert(neighbour.clone(), next);
+                    next_frontier.push(neighbour);
+                }
+            }
+            frontier = next_frontier;
+        }
+
+        Ok(paths)
+    }
+
+    /// Nodes one hop away from `node_id` along the configured edge types,
+    /// paired with the edge type that leads there
+    async fn neighbours(&self, node_id: &str) -> Result<Vec<(String, String)>> {
+        let db = self.engine.database();
+        let follows = |edge_type: &str| self.edge_types.iter().any(|t| t == edge_type);
+        let mut neighbours = Vec::new();
+
+        for edge in db.get_edges_from(node_id, None).await? {
+            if !follows(&edge.edge_type) {
+                continue;
+            }
+            let to = edge.to.to_string();
+            if edge.edge_type == MENTIONS_EDGE_TYPE {
+                // Entities carry no content of their own, so the same hop also
+                // reaches the other chunks that mention the entity.
+                for mention in db.get_edges_to(&to, Some(MENTIONS_EDGE_TYPE)).await? {
+                    let from = mention.from.to_string();
+                    if from != node_id {
+                        neighbours.push((from, edge.edge_type.clone()));
+                    }
+                }
+            }
+            neighbours.push((to, edge.edge_type));
+        }
+
+        for edge in db.get_edges_to(node_id, None).await? {
+            if follows(&edge.edge_type) {
+                neighbours.push((edge.from.to_string(), edge.edge_type));
+            }
+        }
+
+        Ok(neighbours)
+    }
+
+    /// Extract content from a node
+    fn extract_content(&self, node: &Node) -> String {
+        node.properties
+            .get(&self.content_field)
+            .and_then(|v| v.as_str().map(|s| s.to_string()))
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Database;
+    use std::collections::HashSet;
+    use tempfile::TempDir;
+
+    async fn chunk(db: &Database, content: &str, index: i64, embedding: Vec<f32>) -> String {
+        let props = serde_json::json!({
+            "content": content,
+            "document_id": "doc",
+            "chunk_index": index,
+        });
+        db.insert_with_embedding("chunk", props, "embedding", embedding)
+            .await
+            .unwrap()
+            .id
+            .to_string()
+    }
+
+    #[test]
+    fn test_score_decay() {
+        assert_eq!(ScoreDecay::Exponential(0.5).apply(0.8, 0), 0.8);
+        assert_eq!(ScoreDecay::Exponential(0.5).apply(0.8, 2), 0.2);
+        assert!((ScoreDecay::Linear(0.25).apply(0.8, 2) - 0.4).abs() < 1e-9);
+        assert_eq!(ScoreDecay::Linear(0.6).apply(0.8, 2), 0.0);
+        assert_eq!(ScoreDecay::Reciprocal.apply(0.9, 2), 0.3);
+    }
+
+    #[tokio::test]
+    async fn test_expands_neighbours_with_provenance() {
+        let temp = TempDir::new().unwrap();
+        let db = Database::create(temp.path(), "test").await.unwrap();
+
+        let first = chunk(&db, "Rust has ownership.", 0, vec![1.0, 0.0]).await;
+        let second = chunk(&db, "Borrowing follows from ownership.", 1, vec![0.0, 1.0]).await;
+        let unrelated = chunk(&db, "Bread needs yeast.", 2, vec![-1.0, 0.0]).await;
+        db.create_edge(&first, &second, "next", None).await.unwrap();
+        db.create_edge(&second, &unrelated, "next", None).await.unwrap();
+
+        let engine = QueryEngine::new(db);
+        let context = GraphRetriever::new(&engine)
+            .top_k(1)
+            .max_depth(1)
+            .edge_types(&["next"])
+            .retrieve(&[1.0, 0.0], "ownership")
+            .await
+            .unwrap();
+
+        let ids: Vec<&str> = context.chunks.iter().map(|c| c.node_id.as_str()).collect();
+        assert_eq!(ids, vec![first.as_str(), second.as_str()]);
+
+        let seed = context.chunks[0].provenance.as_ref().unwrap();
+        assert_eq!(seed.hops, 0);
+        assert!(seed.edge_path.is_empty());
+
+        let expanded = context.chunks[1].provenance.as_ref().unwrap();
+        assert_eq!(expanded.seed_node_id, first);
+        assert_eq!(expanded.hops, 1);
+        assert_eq!(expanded.edge_path, vec!["next".to_string()]);
+        assert!((context.chunks[1].score - context.chunks[0].score * 0.5).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_dedupes_and_respects_budget() {
+        let temp = TempDir::new().unwrap();
+        let db = Database::create(temp.path(), "test").await.unwrap();
+
+        let a = chunk(&db, "alpha alpha alpha", 0, vec![1.0, 0.0]).await;
+        let b = chunk(&db, "beta beta beta beta", 1, vec![0.9, 0.1]).await;
+        let shared = chunk(&db, &"gamma ".repeat(40), 2, vec![0.0, 1.0]).await;
+        db.create_edge(&a, &shared, "mentions", None).await.unwrap();
+        db.create_edge(&b, &shared, "mentions", None).await.unwrap();
+        db.create_edge(&a, &b, "next", None).await.unwrap();
+
+        let engine = QueryEngine::new(db);
+        let retriever = GraphRetriever::new(&engine).top_k(2).max_depth(1);
+
+        let context = retriever.retrieve(&[1.0, 0.0], "alpha").await.unwrap();
+        let ids: HashSet<&str> = context.chunks.iter().map(|c| c.node_id.as_str()).collect();
+        assert_eq!(context.chunks.len(), 3);
+        assert_eq!(ids.len(), 3);
+
+        // `b` is both a direct hit and a neighbour of `a`; the hit wins
+        let b_chunk = context.chunks.iter().find(|c| c.node_id == b).unwrap();
+        assert_eq!(b_chunk.provenance.as_ref().unwrap().hops, 0);
+
+        // The shared neighbour no longer fits once the budget is tight
+        let context = GraphRetriever::new(&engine)
+            .top_k(2)
+            .max_depth(1)
+            .max_tokens(20)
+            .retrieve(&[1.0, 0.0], "alpha")
+            .await
+            .unwrap();
+        assert!(context.chunks.iter().all(|c| c.node_id != shared));
+        assert!(context.estimated_tokens <= 20);
+    }
+
+    #[tokio::test]
+    async fn test_expands_incoming_edges_and_shared_entities() {
+        let temp = TempDir::new().unwrap();
+        let db = Database::create(temp.path(), "test").await.unwrap();
+
+        let previous = chunk(&db, "Ownership was introduced first.", 0, vec![0.0, 1.0]).await;
+        let hit = chunk(&db, "Rust has ownership.", 1, vec![1.0, 0.0]).await;
+        let elsewhere = chunk(&db, "Ferris is the Rust mascot.", 7, vec![-1.0, 0.0]).await;
+        db.create_edge(&previous, &hit, "next", None).await.unwrap();
+
+        let entity = db.insert_node("entity", serde_json::json!({"name": "Rust"})).await.unwrap().id.to_string();
+        db.create_edge(&hit, &entity, "mentions", None).await.unwrap();
+        db.create_edge(&elsewhere, &entity, "mentions", None).await.unwrap();
+
+        let engine = QueryEngine::new(db);
+        let context = GraphRetriever::new(&engine)
+            .top_k(1)
+            .max_depth(1)
+            .retrieve(&[1.0, 0.0], "ownership")
+            .await
+            .unwrap();
+
+        let path_to = |id: &str| {
+            context.chunks.iter()
+                .find(|c| c.node_id == id)
+                .map(|c| c.provenance.as_ref().unwrap().edge_path.clone())
+        };
+        assert_eq!(context.chunks.len(), 3);
+        assert_eq!(path_to(&previous), Some(vec!["next".to_string()]));
+        assert_eq!(path_to(&elsewhere), Some(vec!["mentions".to_string()]));
+        assert_eq!(path_to(&entity), None);
+    }
+}
@@ -145,8 +145,8 @@ impl<'a> HybridSearch<'a> {
         Ok(results)
     }
 
-    /// Simple keyword search using term matching
-    async fn keyword_search(
+    /// Keyword-only search using BM25-like term matching
+    pub async fn keyword_search(
         &self,
         query: &str,
         node_type: &str,
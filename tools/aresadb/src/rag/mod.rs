@@ -6,16 +6,32 @@
 mod chunker;
 mod context;
 mod embeddings;
+mod eval;
+mod extraction;
+mod graph;
 mod hybrid;
+mod tokenizer;
 
 pub use chunker::{Chunker, ChunkStrategy, DocumentChunk};
-pub use context::{ContextRetriever, RetrievedContext, ContextChunk};
+pub use context::{ContextRetriever, RetrievedContext, ContextChunk, ChunkProvenance};
 pub use embeddings::{
     EmbeddingProvider, EmbeddingManager,
     OpenAIEmbeddings, OpenAIModel,
     LocalHashEmbeddings, TfIdfEmbeddings,
 };
+pub use eval::{
+    EvalQuery, EvalConfig, EvalReport, ConfigReport, Evaluator,
+    RetrievalMode, RetrievalMetrics, load_eval_queries,
+};
+pub use extraction::{
+    EntityExtractor, RegexExtractor, LlmExtractor, EntityLinker,
+    Entity, Relation, Extraction, LinkStats,
+    extractor_from_name, normalize_entity_name,
+    ENTITY_NODE_TYPE, MENTIONS_EDGE_TYPE,
+};
+pub use graph::{GraphRetriever, ScoreDecay, DEFAULT_EXPANSION_EDGES};
 pub use hybrid::{HybridSearch, HybridSearchConfig, HybridSearchResult, keyword_search_sync};
+pub use tokenizer::{Tokenizer, CharEstimateTokenizer, BpeTokenizer, load_tokenizer};
 
 /// Default chunk size in characters
 pub const DEFAULT_CHUNK_SIZE: usize = 512;
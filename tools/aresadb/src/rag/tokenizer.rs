@@ -0,0 +1,368 @@
+//! Tokenizers for RAG applications
+//!
+//! Provides token counting for chunking and context budgeting:
+//! - Character-based estimate (~4 characters per token, no vocab needed)
+//! - Byte-pair encoding (BPE) compatible with tiktoken's cl100k_base,
+//!   loaded from a local `.tiktoken` vocabulary file
+
+use anyhow::{Result, Context, bail};
+use regex::Regex;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+/// cl100k_base pre-tokenization pattern.
+///
+/// tiktoken's original pattern contains `\s+(?!\S)`, which the `regex` crate
+/// doesn't support (no lookaround). That alternative is emulated in
+/// [`BpeTokenizer::split_pieces`] by giving back the last whitespace character
+/// of a run that is followed by a non-whitespace character.
+const CL100K_PATTERN: &str = concat!(
+    r"(?i:'s|'t|'re|'ve|'m|'ll|'d)",
+    r"|[^\r\n\p{L}\p{N}]?\p{L}+",
+    r"|\p{N}{1,3}",
+    r"| ?[^\s\p{L}\p{N}]+[\r\n]*",
+    r"|\s*[\r\n]+",
+    r"|\s+",
+);
+
+/// Tokenizer trait used for chunk sizing and context budgets
+pub trait Tokenizer: Send + Sync {
+    /// Count the tokens in a text
+    fn count_tokens(&self, text: &str) -> usize;
+
+    /// Get tokenizer name
+    fn name(&self) -> &str;
+}
+
+/// Character-based token estimate (~4 characters per token for English)
+///
+/// Needs no vocabulary, but over- or under-counts for code and non-English
+/// text. Use [`BpeTokenizer`] when the budget has to match the model.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CharEstimateTokenizer;
+
+impl CharEstimateTokenizer {
+    /// Characters per estimated token
+    pub const CHARS_PER_TOKEN: usize = 4;
+}
+
+impl Tokenizer for CharEstimateTokenizer {
+    fn count_tokens(&self, text: &str) -> usize {
+        text.chars().count() / Self::CHARS_PER_TOKEN
+    }
+
+    fn name(&self) -> &str {
+        "char-estimate"
+    }
+}
+
+/// Byte-pair encoding tokenizer (tiktoken-compatible)
+///
+/// Loads a vocabulary in tiktoken's format: one `<base64 token> <rank>`
+/// pair per line, as in `cl100k_base.tiktoken`.
+pub struct BpeTokenizer {
+    /// Token bytes -> rank (token ID)
+    encoder: HashMap<Vec<u8>, u32>,
+    /// Rank -> token bytes
+    decoder: HashMap<u32, Vec<u8>>,
+    /// Pre-tokenization pattern
+    pattern: Regex,
+    /// Name of the vocabulary
+    name: String,
+}
+
+impl BpeTokenizer {
+    /// Create a tokenizer from a token -> rank map with the cl100k pattern
+    pub fn new(encoder: HashMap<Vec<u8>, u32>, name: &str) -> Result<Self> {
+        for byte in 0..=255u8 {
+            if !encoder.contains_key(&vec![byte]) {
+                bail!("BPE vocabulary is missing single-byte token 0x{:02x}", byte);
+            }
+        }
+
+        let decoder = encoder.iter()
+            .map(|(bytes, &rank)| (rank, bytes.clone()))
+            .collect();
+
+        Ok(Self {
+            encoder,
+            decoder,
+            pattern: Regex::new(CL100K_PATTERN)?,
+            name: name.to_string(),
+        })
+    }
+
+    /// Load a tiktoken vocabulary file (e.g. `cl100k_base.tiktoken`)
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read tokenizer vocabulary {}", path.display()))?;
+
+        let name = path.file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("bpe");
+
+        Self::from_tiktoken_str(&data, name)
+    }
+
+    /// Parse a vocabulary in tiktoken format
+    pub fn from_tiktoken_str(data: &str, name: &str) -> Result<Self> {
+        let mut encoder = HashMap::new();
+
+        for (line_no, line) in data.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (token, rank) = line.split_once(' ')
+                .with_context(|| format!("Invalid vocabulary line {}", line_no + 1))?;
+            let bytes = decode_base64(token)
+                .with_context(|| format!("Invalid base64 token on line {}", line_no + 1))?;
+            let rank: u32 = rank.trim().parse()
+                .with_context(|| format!("Invalid rank on line {}", line_no + 1))?;
+
+            encoder.insert(bytes, rank);
+        }
+
+        Self::new(encoder, name)
+    }
+
+    /// Encode text into token IDs
+    pub fn encode(&self, text: &str) -> Vec<u32> {
+        let mut tokens = Vec::new();
+        for piece in self.split_pieces(text) {
+            self.encode_piece(piece.as_bytes(), &mut tokens);
+        }
+        tokens
+    }
+
+    /// Decode token IDs back into text
+    pub fn decode(&self, tokens: &[u32]) -> Result<String> {
+        let mut bytes = Vec::new();
+        for token in tokens {
+            let token_bytes = self.decoder.get(token)
+                .with_context(|| format!("Unknown token ID: {}", token))?;
+            bytes.extend_from_slice(token_bytes);
+        }
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    /// Number of tokens in the vocabulary
+    pub fn vocab_size(&self) -> usize {
+        self.encoder.len()
+    }
+
+    /// Split text into pre-tokenization pieces
+    fn split_pieces<'a>(&self, text: &'a str) -> Vec<&'a str> {
+        let mut pieces = Vec::new();
+        let mut pos = 0;
+
+        while pos < text.len() {
+            let Some(m) = self.pattern.find_at(text, pos) else {
+                break;
+            };
+
+            let mut end = m.end();
+            let piece = m.as_str();
+
+            // Emulate `\s+(?!\S)`: a whitespace run (not ending in a newline)
+            // followed by a non-space character leaves its last character
+            // to the next piece.
+            let is_space_run = piece.chars().all(char::is_whitespace)
+                && !piece.ends_with(['\r', '\n']);
+            if is_space_run && piece.chars().count() > 1 && !text[end..].is_empty()
+                && !text[end..].starts_with(char::is_whitespace)
+            {
+                if let Some((last_idx, _)) = piece.char_indices().next_back() {
+                    end = m.start() + last_idx;
+                }
+            }
+
+            pieces.push(&text[m.start()..end]);
+            pos = end;
+        }
+
+        pieces
+    }
+
+    /// Byte-pair merge a single piece into ranks
+    fn encode_piece(&self, piece: &[u8], out: &mut Vec<u32>) {
+        if let Some(&rank) = self.encoder.get(piece) {
+            out.push(rank);
+            return;
+        }
+
+        // Start from single bytes and repeatedly merge the lowest-ranked pair
+        let mut parts: Vec<(usize, usize)> = (0..piece.len()).map(|i| (i, i + 1)).collect();
+
+        loop {
+            let mut best: Option<(usize, u32)> = None;
+
+            for i in 0..parts.len().saturating_sub(1) {
+                let merged = &piece[parts[i].0..parts[i + 1].1];
+                if let Some(&rank) = self.encoder.get(merged) {
+                    if best.map(|(_, r)| rank < r).unwrap_or(true) {
+                        best = Some((i, rank));
+                    }
+                }
+            }
+
+            match best {
+                Some((i, _)) => {
+                    parts[i].1 = parts[i + 1].1;
+                    parts.remove(i + 1);
+                }
+                None => break,
+            }
+        }
+
+        for (start, end) in parts {
+            out.push(self.encoder[&piece[start..end]]);
+        }
+    }
+}
+
+impl Tokenizer for BpeTokenizer {
+    fn count_tokens(&self, text: &str) -> usize {
+        self.encode(text).len()
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Load a tokenizer: BPE from a vocabulary file if given, otherwise the character estimate
+pub fn load_tokenizer(vocab_path: Option<&str>) -> Result<Arc<dyn Tokenizer>> {
+    match vocab_path {
+        Some(path) => Ok(Arc::new(BpeTokenizer::from_file(path)?)),
+        None => Ok(Arc::new(CharEstimateTokenizer)),
+    }
+}
+
+/// Decode standard base64 (with optional padding)
+fn decode_base64(input: &str) -> Result<Vec<u8>> {
+    fn value(c: u8) -> Option<u32> {
+        match c {
+            b'A'..=b'Z' => Some((c - b'A') as u32),
+            b'a'..=b'z' => Some((c - b'a' + 26) as u32),
+            b'0'..=b'9' => Some((c - b'0' + 52) as u32),
+            b'+' => Some(62),
+            b'/' => Some(63),
+            _ => None,
+        }
+    }
+
+    let input = input.trim_end_matches('=').as_bytes();
+    let mut out = Vec::with_capacity(input.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+
+    for &c in input {
+        let v = value(c).with_context(|| format!("Invalid base64 character: {}", c as char))?;
+        buffer = (buffer << 6) | v;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_base64(bytes: &[u8]) -> String {
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut out = String::new();
+        for chunk in bytes.chunks(3) {
+            let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+            let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    out.push(ALPHABET[((n >> (18 - 6 * i)) & 63) as usize] as char);
+                } else {
+                    out.push('=');
+                }
+            }
+        }
+        out
+    }
+
+    /// Small vocabulary: all bytes plus a few merges, in tiktoken format
+    fn test_vocab() -> String {
+        let mut tokens: Vec<Vec<u8>> = (0..=255u8).map(|b| vec![b]).collect();
+        for merge in ["he", "ll", "hell", "hello", " w", "or", " wor", " world", "in", "ing"] {
+            tokens.push(merge.as_bytes().to_vec());
+        }
+        tokens.iter()
+            .enumerate()
+            .map(|(rank, bytes)| format!("{} {}", encode_base64(bytes), rank))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn test_char_estimate() {
+        let tokenizer = CharEstimateTokenizer;
+        assert_eq!(tokenizer.count_tokens("abcdefgh"), 2);
+        assert_eq!(tokenizer.count_tokens(""), 0);
+    }
+
+    #[test]
+    fn test_base64_decode() {
+        assert_eq!(decode_base64("aGVsbG8=").unwrap(), b"hello");
+        assert_eq!(decode_base64("IQ==").unwrap(), b"!");
+        assert!(decode_base64("a$b").is_err());
+    }
+
+    #[test]
+    fn test_bpe_merges() {
+        let tokenizer = BpeTokenizer::from_tiktoken_str(&test_vocab(), "test").unwrap();
+        assert_eq!(tokenizer.vocab_size(), 266);
+
+        // "hello" and " world" are single tokens
+        assert_eq!(tokenizer.count_tokens("hello world"), 2);
+
+        // Unknown text falls back to bytes
+        assert_eq!(tokenizer.count_tokens("xyz"), 3);
+    }
+
+    #[test]
+    fn test_bpe_roundtrip() {
+        let tokenizer = BpeTokenizer::from_tiktoken_str(&test_vocab(), "test").unwrap();
+        let text = "hello world!  Naïve  testing\n\nwith 12345 numbers.";
+        let tokens = tokenizer.encode(text);
+        assert_eq!(tokenizer.decode(&tokens).unwrap(), text);
+    }
+
+    #[test]
+    fn test_whitespace_pieces() {
+        let tokenizer = BpeTokenizer::from_tiktoken_str(&test_vocab(), "test").unwrap();
+
+        // The last space of a run attaches to the following word
+        let pieces = tokenizer.split_pieces("a   world");
+        assert_eq!(pieces, vec!["a", "  ", " world"]);
+
+        // Trailing whitespace stays together
+        let pieces = tokenizer.split_pieces("a   ");
+        assert_eq!(pieces, vec!["a", "   "]);
+
+        // Newline runs are kept whole
+        let pieces = tokenizer.split_pieces("a\n\nb");
+        assert_eq!(pieces, vec!["a", "\n\n", "b"]);
+    }
+
+    #[test]
+    fn test_missing_byte_tokens() {
+        let mut encoder = HashMap::new();
+        encoder.insert(b"a".to_vec(), 0);
+        assert!(BpeTokenizer::new(encoder, "broken").is_err());
+    }
+}
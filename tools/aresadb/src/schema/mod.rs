@@ -1,12 +1,17 @@
 //! Schema Management
 //!
-//! Provides schema definitions, validation, and migrations.
+//! Provides schema definitions, validation, migrations and triggers.
 
 mod registry;
 mod migration;
+mod trigger;
 
 pub use registry::{Schema, SchemaField, FieldType, SchemaRelation, RelationType};
 pub use migration::{Migration, MigrationAction, MigrationGenerator};
+pub use trigger::{
+    CheckOp, Rule, Trigger, TriggerAction, TriggerEvent, TriggerSet, TriggerStatement, TriggerTiming, Webhook,
+    DEAD_LETTER_TYPE, DEFAULT_WEBHOOK_RETRIES, TRIGGER_TYPE,
+};
 
 use anyhow::Result;
 use crate::storage::Database;
@@ -101,6 +106,52 @@ impl SchemaManager {
         Ok(migrations)
     }
 
+    /// Store a new trigger; it runs on writes as soon as this returns
+    pub async fn create_trigger(&self, trigger: &Trigger) -> Result<()> {
+        trigger.validate()?;
+        if self.trigger_node(&trigger.name).await?.is_some() {
+            anyhow::bail!("Trigger already exists: {}", trigger.name);
+        }
+        self.db.insert_node(TRIGGER_TYPE, trigger.to_properties()?).await?;
+        Ok(())
+    }
+
+    /// Remove a trigger, returning whether there was one
+    pub async fn drop_trigger(&self, name: &str) -> Result<bool> {
+        match self.trigger_node(name).await? {
+            Some(node) => {
+                self.db.delete_node(&node.id.to_string()).await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// List all triggers, by name
+    pub async fn list_triggers(&self) -> Result<Vec<Trigger>> {
+        let nodes = self.db.get_all_by_type(TRIGGER_TYPE, None).await?;
+        let mut triggers: Vec<Trigger> = nodes.iter().filter_map(Trigger::from_node).collect();
+        triggers.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(triggers)
+    }
+
+    /// Run a `CREATE TRIGGER` or `DROP TRIGGER` statement, describing
+    /// what it did
+    pub async fn execute_trigger_statement(&self, statement: TriggerStatement) -> Result<String> {
+        match statement {
+            TriggerStatement::Create(trigger) => {
+                self.create_trigger(&trigger).await?;
+                Ok(format!("Created trigger '{}' on {}", trigger.name, trigger.node_type))
+            }
+            TriggerStatement::Drop { name, if_exists } => {
+                if !self.drop_trigger(&name).await? && !if_exists {
+                    anyhow::bail!("Trigger not found: {}", name);
+                }
+                Ok(format!("Dropped trigger '{}'", name))
+            }
+        }
+    }
+
     /// Parse field definitions from string
     fn parse_fields(fields_str: &str) -> Result<Vec<SchemaField>> {
         let mut fields = Vec::new();
@@ -208,6 +259,12 @@ impl SchemaManager {
         Ok(())
     }
 
+    /// The `__trigger__` node holding the trigger called `name`
+    pub(crate) async fn trigger_node(&self, name: &str) -> Result<Option<crate::storage::Node>> {
+        let nodes = self.db.get_all_by_type(TRIGGER_TYPE, None).await?;
+        Ok(nodes.into_iter().find(|node| node.get("name").and_then(|n| n.as_str()) == Some(name)))
+    }
+
     async fn detect_migrations(&self) -> Result<Vec<Migration>> {
         // Compare current schemas with stored schemas
         // For now, return empty - full implementation would diff schemas
@@ -0,0 +1,844 @@
+//! Triggers
+//!
+//! Triggers attach to one node type. `BEFORE` triggers run simple rules on
+//! every node inserted or updated, inside the write, filling in defaults
+//! and rejecting nodes that break a check. `AFTER` triggers name a webhook
+//! that committed changes are posted to; the server delivers them (see
+//! `server::webhooks`).
+//!
+//! ```sql
+//! CREATE TRIGGER user_rules BEFORE INSERT OR UPDATE ON user
+//!     DEFAULT status = 'active', REQUIRE email, CHECK age >= 0,
+//!     CHECK email MATCHES '^[^@]+@[^@]+$';
+//! CREATE TRIGGER user_hook AFTER INSERT OR DELETE ON user
+//!     WEBHOOK 'https://example.com/hooks/users' RETRIES 5;
+//! DROP TRIGGER user_hook;
+//! ```
+//!
+//! Definitions are stored as `__trigger__` nodes next to the schemas, so
+//! they replicate and survive restarts like any other data.
+
+use anyhow::{bail, Result};
+use parking_lot::RwLock;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::storage::{Change, ChangeEvent, LocalStorage, Node, Value, WriteHook, WriteKind};
+
+/// Node type trigger definitions are stored as
+pub const TRIGGER_TYPE: &str = "__trigger__";
+
+/// Node type webhook deliveries that ran out of retries are stored as
+pub const DEAD_LETTER_TYPE: &str = "__dead_letter__";
+
+/// Retries after the first attempt when a webhook names none
+pub const DEFAULT_WEBHOOK_RETRIES: u32 = 3;
+
+/// When a trigger runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TriggerTiming {
+    /// Inside the write, before the node is stored
+    Before,
+    /// Once the change has committed
+    After,
+}
+
+/// Change a trigger runs on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TriggerEvent {
+    /// A node is inserted
+    Insert,
+    /// A node is updated
+    Update,
+    /// A node is deleted
+    Delete,
+}
+
+impl TriggerEvent {
+    /// The event a committed change is, if it is a node change
+    pub fn of(change: &Change) -> Option<Self> {
+        match change {
+            Change::NodeInserted { .. } => Some(TriggerEvent::Insert),
+            Change::NodeUpdated { .. } => Some(TriggerEvent::Update),
+            Change::NodeDeleted { .. } => Some(TriggerEvent::Delete),
+            _ => None,
+        }
+    }
+
+    fn keyword(&self) -> &'static str {
+        match self {
+            TriggerEvent::Insert => "INSERT",
+            TriggerEvent::Update => "UPDATE",
+            TriggerEvent::Delete => "DELETE",
+        }
+    }
+}
+
+/// Comparison in a `CHECK` rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CheckOp {
+    /// `=`
+    Eq,
+    /// `!=` or `<>`
+    Ne,
+    /// `<`
+    Lt,
+    /// `<=`
+    Le,
+    /// `>`
+    Gt,
+    /// `>=`
+    Ge,
+    /// `MATCHES`, a regular expression over a string field
+    Matches,
+}
+
+impl CheckOp {
+    fn from_keyword(word: &str) -> Option<Self> {
+        Some(match word.to_uppercase().as_str() {
+            "=" | "==" => CheckOp::Eq,
+            "!=" | "<>" => CheckOp::Ne,
+            "<" => CheckOp::Lt,
+            "<=" => CheckOp::Le,
+            ">" => CheckOp::Gt,
+            ">=" => CheckOp::Ge,
+            "MATCHES" => CheckOp::Matches,
+            _ => return None,
+        })
+    }
+
+    fn keyword(&self) -> &'static str {
+        match self {
+            CheckOp::Eq => "=",
+            CheckOp::Ne => "!=",
+            CheckOp::Lt => "<",
+            CheckOp::Le => "<=",
+            CheckOp::Gt => ">",
+            CheckOp::Ge => ">=",
+            CheckOp::Matches => "MATCHES",
+        }
+    }
+}
+
+/// A rule run by a `BEFORE` trigger
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Rule {
+    /// The field must be present and not null
+    Require {
+        /// Field checked
+        field: String,
+    },
+    /// The field must compare true against a value; a missing field passes
+    Check {
+        /// Field checked
+        field: String,
+        /// Comparison
+        op: CheckOp,
+        /// Value compared against
+        value: serde_json::Value,
+    },
+    /// Set the field when it is missing or null
+    Default {
+        /// Field filled in
+        field: String,
+        /// Value it gets
+        value: serde_json::Value,
+    },
+}
+
+impl Rule {
+    /// Compiled pattern of a `MATCHES` check, `None` for other rules
+    fn pattern(&self) -> Result<Option<Regex>> {
+        match self {
+            Rule::Check { op: CheckOp::Matches, value, .. } => {
+                let pattern = value.as_str().ok_or_else(|| anyhow::anyhow!("MATCHES takes a string pattern"))?;
+                Ok(Some(Regex::new(pattern)?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Apply the rule to `node`, describing the problem if it fails
+    ///
+    /// `pattern` is the rule's compiled [`pattern`](Self::pattern).
+    fn apply(&self, node: &mut Node, pattern: Option<&Regex>) -> std::result::Result<(), String> {
+        match self {
+            Rule::Require { field } => match node.get(field) {
+                Some(value) if !value.is_null() => Ok(()),
+                _ => Err(format!("'{}' is required", field)),
+            },
+            Rule::Check { field, op, value } => match node.get(field) {
+                Some(actual) if !actual.is_null() => {
+                    if check(&actual.to_json(), *op, value, pattern) {
+                        Ok(())
+                    } else {
+                        Err(format!("'{}' must be {} {}", field, op.keyword(), literal(value)))
+                    }
+                }
+                _ => Ok(()),
+            },
+            Rule::Default { field, value } => {
+                if node.get(field).is_none_or(Value::is_null) {
+                    let value = Value::from_json(value.clone()).map_err(|e| e.to_string())?;
+                    node.properties.insert(field.clone(), value);
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Compare a field against a `CHECK` value, or match it against the
+/// compiled `pattern` of a `MATCHES` check
+fn check(actual: &serde_json::Value, op: CheckOp, expected: &serde_json::Value, pattern: Option<&Regex>) -> bool {
+    use serde_json::Value as Json;
+
+    if op == CheckOp::Matches {
+        return match (actual, pattern) {
+            (Json::String(text), Some(regex)) => regex.is_match(text),
+            _ => false,
+        };
+    }
+
+    let ordering = match (actual, expected) {
+        (Json::Number(a), Json::Number(b)) => a.as_f64().partial_cmp(&b.as_f64()),
+        (Json::String(a), Json::String(b)) => Some(a.cmp(b)),
+        (Json::Bool(a), Json::Bool(b)) => Some(a.cmp(b)),
+        _ => None,
+    };
+    match (op, ordering) {
+        (CheckOp::Eq, Some(o)) => o == Ordering::Equal,
+        (CheckOp::Ne, Some(o)) => o != Ordering::Equal,
+        (CheckOp::Lt, Some(o)) => o == Ordering::Less,
+        (CheckOp::Le, Some(o)) => o != Ordering::Greater,
+        (CheckOp::Gt, Some(o)) => o == Ordering::Greater,
+        (CheckOp::Ge, Some(o)) => o != Ordering::Less,
+        // Values of different kinds are never equal and never ordered
+        (CheckOp::Ne, None) => true,
+        _ => false,
+    }
+}
+
+/// Where an `AFTER` trigger posts changes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Webhook {
+    /// HTTP or HTTPS URL receiving a JSON POST per change
+    pub url: String,
+    /// Attempts after the first before the change is dead-lettered
+    pub retries: u32,
+}
+
+/// What a trigger does
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TriggerAction {
+    /// Run rules on the node, in order
+    Rules(Vec<Rule>),
+    /// Post the change to a webhook
+    Webhook(Webhook),
+}
+
+/// A trigger on one node type
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Trigger {
+    /// Unique name
+    pub name: String,
+    /// Node type it runs on
+    pub node_type: String,
+    /// When it runs
+    pub timing: TriggerTiming,
+    /// Changes it runs on
+    pub events: Vec<TriggerEvent>,
+    /// What it does
+    pub action: TriggerAction,
+}
+
+impl Trigger {
+    /// Check the definition is one that can run
+    pub fn validate(&self) -> Result<()> {
+        if self.node_type.starts_with("__") {
+            bail!("Triggers cannot be defined on system type '{}'", self.node_type);
+        }
+        if self.events.is_empty() {
+            bail!("Trigger '{}' has no events", self.name);
+        }
+        match (&self.timing, &self.action) {
+            (TriggerTiming::Before, TriggerAction::Rules(rules)) => {
+                if self.events.contains(&TriggerEvent::Delete) {
+                    bail!("BEFORE triggers run on INSERT and UPDATE only");
+                }
+                if rules.is_empty() {
+                    bail!("Trigger '{}' has no rules", self.name);
+                }
+                for rule in rules {
+                    rule.pattern()?;
+                }
+            }
+            (TriggerTiming::After, TriggerAction::Webhook(webhook)) => {
+                let url = url::Url::parse(&webhook.url)?;
+                if url.scheme() != "http" && url.scheme() != "https" {
+                    bail!("Webhook URL must be http or https: {}", webhook.url);
+                }
+            }
+            (TriggerTiming::Before, _) => bail!("BEFORE triggers take rules, not a webhook"),
+            (TriggerTiming::After, _) => bail!("AFTER triggers take a WEBHOOK, not rules"),
+        }
+        Ok(())
+    }
+
+    /// Whether the trigger runs at `timing` on `event` to a node of `node_type`
+    pub fn fires(&self, timing: TriggerTiming, event: TriggerEvent, node_type: &str) -> bool {
+        self.timing == timing && self.node_type == node_type && self.events.contains(&event)
+    }
+
+    /// Read a definition back from its `__trigger__` node
+    pub fn from_node(node: &Node) -> Option<Self> {
+        let data = node.get("trigger_data")?.to_json();
+        serde_json::from_value(data).ok()
+    }
+
+    /// Properties of the `__trigger__` node holding the definition
+    pub(crate) fn to_properties(&self) -> Result<serde_json::Value> {
+        Ok(serde_json::json!({
+            "name": self.name,
+            "node_type": self.node_type,
+            "trigger_data": serde_json::to_value(self)?,
+        }))
+    }
+}
+
+/// Format a value as it is written in a statement
+fn literal(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => format!("'{}'", s.replace('\'', "''")),
+        other => other.to_string(),
+    }
+}
+
+impl fmt::Display for Trigger {
+    /// The `CREATE TRIGGER` statement defining the trigger
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let timing = match self.timing {
+            TriggerTiming::Before => "BEFORE",
+            TriggerTiming::After => "AFTER",
+        };
+        let events: Vec<&str> = self.events.iter().map(TriggerEvent::keyword).collect();
+        write!(f, "CREATE TRIGGER {} {} {} ON {} ", self.name, timing, events.join(" OR "), self.node_type)?;
+        match &self.action {
+            TriggerAction::Rules(rules) => {
+                let rules: Vec<String> = rules
+                    .iter()
+                    .map(|rule| match rule {
+                        Rule::Require { field } => format!("REQUIRE {}", field),
+                        Rule::Check { field, op, value } => {
+                            format!("CHECK {} {} {}", field, op.keyword(), literal(value))
+                        }
+                        Rule::Default { field, value } => format!("DEFAULT {} = {}", field, literal(value)),
+                    })
+                    .collect();
+                write!(f, "{}", rules.join(", "))
+            }
+            TriggerAction::Webhook(webhook) => {
+                write!(f, "WEBHOOK {} RETRIES {}", literal(&webhook.url.clone().into()), webhook.retries)
+            }
+        }
+    }
+}
+
+/// A `CREATE TRIGGER` or `DROP TRIGGER` statement
+#[derive(Debug, Clone, PartialEq)]
+pub enum TriggerStatement {
+    /// Define a new trigger
+    Create(Trigger),
+    /// Remove a trigger
+    Drop {
+        /// Trigger to remove
+        name: String,
+        /// Succeed when there is no such trigger
+        if_exists: bool,
+    },
+}
+
+/// A word, symbol or quoted string in a statement
+#[derive(Debug, Clone, PartialEq)]
+struct Token {
+    text: String,
+    quoted: bool,
+}
+
+fn tokenize(sql: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = sql.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '\'' || c == '"' {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    // A doubled quote stands for itself
+                    Some(q) if q == c && chars.peek() == Some(&c) => {
+                        chars.next();
+                        text.push(c);
+                    }
+                    Some(q) if q == c => break,
+                    Some(other) => text.push(other),
+                    None => bail!("Unterminated string"),
+                }
+            }
+            tokens.push(Token { text, quoted: true });
+        } else if "=!<>".contains(c) {
+            let mut text = String::from(c);
+            chars.next();
+            if let Some(&next) = chars.peek() {
+                if next == '=' || (c == '<' && next == '>') {
+                    text.push(next);
+                    chars.next();
+                }
+            }
+            tokens.push(Token { text, quoted: false });
+        } else if c == ',' || c == ';' {
+            chars.next();
+            tokens.push(Token { text: c.to_string(), quoted: false });
+        } else {
+            let mut text = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || ",;'\"=!<>".contains(c) {
+                    break;
+                }
+                text.push(c);
+                chars.next();
+            }
+            tokens.push(Token { text, quoted: false });
+        }
+    }
+    Ok(tokens)
+}
+
+/// Reads tokens in order
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek_is(&self, word: &str) -> bool {
+        self.tokens.get(self.pos).is_some_and(|t| !t.quoted && t.text.eq_ignore_ascii_case(word))
+    }
+
+    fn eat(&mut self, word: &str) -> bool {
+        let found = self.peek_is(word);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, word: &str) -> Result<()> {
+        if !self.eat(word) {
+            bail!("Expected {}{}", word, self.found());
+        }
+        Ok(())
+    }
+
+    fn found(&self) -> String {
+        match self.tokens.get(self.pos) {
+            Some(token) => format!(" but found '{}'", token.text),
+            None => " at the end of the statement".to_string(),
+        }
+    }
+
+    fn next(&mut self, what: &str) -> Result<Token> {
+        let token = self.tokens.get(self.pos).cloned()
+            .ok_or_else(|| anyhow::anyhow!("Expected {} at the end of the statement", what))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn name(&mut self, what: &str) -> Result<String> {
+        let token = self.next(what)?;
+        if !token.quoted && !token.text.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '.') {
+            bail!("Expected {} but found '{}'", what, token.text);
+        }
+        Ok(token.text)
+    }
+
+    fn literal(&mut self) -> Result<serde_json::Value> {
+        let token = self.next("a value")?;
+        if token.quoted {
+            return Ok(serde_json::Value::String(token.text));
+        }
+        let text = token.text.as_str();
+        if let Ok(i) = text.parse::<i64>() {
+            return Ok(i.into());
+        }
+        if let Ok(f) = text.parse::<f64>() {
+            return Ok(f.into());
+        }
+        Ok(match text.to_lowercase().as_str() {
+            "true" => true.into(),
+            "false" => false.into(),
+            "null" => serde_json::Value::Null,
+            _ => bail!("Expected a value but found '{}'; quote strings", text),
+        })
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+}
+
+impl TriggerStatement {
+    /// Parse a `CREATE TRIGGER` or `DROP TRIGGER` statement; `None` if
+    /// `sql` is neither
+    pub fn parse(sql: &str) -> Option<Result<Self>> {
+        let words: Vec<&str> = sql.split_whitespace().take(2).collect();
+        match words.as_slice() {
+            [verb, noun] if (verb.eq_ignore_ascii_case("CREATE") || verb.eq_ignore_ascii_case("DROP"))
+                && noun.eq_ignore_ascii_case("TRIGGER") => {}
+            _ => return None,
+        }
+
+        Some(tokenize(sql).and_then(|mut tokens| {
+            while tokens.last().is_some_and(|t| !t.quoted && t.text == ";") {
+                tokens.pop();
+            }
+            let mut parser = Parser { tokens, pos: 2 };
+            let statement = if words[0].eq_ignore_ascii_case("DROP") {
+                let if_exists = parser.eat("IF");
+                if if_exists {
+                    parser.expect("EXISTS")?;
+                }
+                TriggerStatement::Drop { name: parser.name("a trigger name")?, if_exists }
+            } else {
+                TriggerStatement::Create(Self::parse_create(&mut parser)?)
+            };
+            if !parser.at_end() {
+                bail!("Unexpected '{}' after the statement", parser.tokens[parser.pos].text);
+            }
+            Ok(statement)
+        }))
+    }
+
+    fn parse_create(parser: &mut Parser) -> Result<Trigger> {
+        let name = parser.name("a trigger name")?;
+        let timing = if parser.eat("BEFORE") {
+            TriggerTiming::Before
+        } else if parser.eat("AFTER") {
+            TriggerTiming::After
+        } else {
+            bail!("Expected BEFORE or AFTER{}", parser.found());
+        };
+
+        let mut events = Vec::new();
+        loop {
+            let event = if parser.eat("INSERT") {
+                TriggerEvent::Insert
+            } else if parser.eat("UPDATE") {
+                TriggerEvent::Update
+            } else if parser.eat("DELETE") {
+                TriggerEvent::Delete
+            } else {
+                bail!("Expected INSERT, UPDATE or DELETE{}", parser.found());
+            };
+            if !events.contains(&event) {
+                events.push(event);
+            }
+            if !parser.eat("OR") {
+                break;
+            }
+        }
+
+        parser.expect("ON")?;
+        let node_type = parser.name("a node type")?;
+
+        let action = if parser.eat("WEBHOOK") {
+            let url = parser.next("a webhook URL")?;
+            if !url.quoted {
+                bail!("Expected a quoted webhook URL but found '{}'", url.text);
+            }
+            let retries = if parser.eat("RETRIES") {
+                let count = parser.next("a retry count")?;
+                count.text.parse()
+                    .map_err(|_| anyhow::anyhow!("Expected a retry count but found '{}'", count.text))?
+            } else {
+                DEFAULT_WEBHOOK_RETRIES
+            };
+            TriggerAction::Webhook(Webhook { url: url.text, retries })
+        } else {
+            let mut rules = Vec::new();
+            loop {
+                rules.push(if parser.eat("REQUIRE") {
+                    Rule::Require { field: parser.name("a field")? }
+                } else if parser.eat("DEFAULT") {
+                    let field = parser.name("a field")?;
+                    parser.expect("=")?;
+                    Rule::Default { field, value: parser.literal()? }
+                } else if parser.eat("CHECK") {
+                    let field = parser.name("a field")?;
+                    let op = parser.next("a comparison")?;
+                    let op = CheckOp::from_keyword(&op.text).filter(|_| !op.quoted)
+                        .ok_or_else(|| anyhow::anyhow!("Expected a comparison but found '{}'", op.text))?;
+                    Rule::Check { field, op, value: parser.literal()? }
+                } else {
+                    bail!("Expected WEBHOOK, REQUIRE, DEFAULT or CHECK{}", parser.found());
+                });
+                if !parser.eat(",") {
+                    break;
+                }
+            }
+            TriggerAction::Rules(rules)
+        };
+
+        let trigger = Trigger { name, node_type, timing, events, action };
+        trigger.validate()?;
+        Ok(trigger)
+    }
+}
+
+/// The triggers of a database, kept current from its changes
+///
+/// Installed as the storage write hook, it runs `BEFORE` triggers on every
+/// write and picks up `__trigger__` nodes as they commit, whether they were
+/// written here or replicated from a leader.
+#[derive(Default)]
+pub struct TriggerSet {
+    triggers: RwLock<BTreeMap<String, Loaded>>,
+}
+
+/// A trigger as a [`TriggerSet`] runs it
+struct Loaded {
+    trigger: Trigger,
+    /// Compiled pattern of each rule, `None` for rules without one
+    patterns: Vec<Option<Regex>>,
+}
+
+impl Loaded {
+    fn new(trigger: Trigger) -> Result<Self> {
+        let patterns = match &trigger.action {
+            TriggerAction::Rules(rules) => rules.iter().map(Rule::pattern).collect::<Result<_>>()?,
+            TriggerAction::Webhook(_) => Vec::new(),
+        };
+        Ok(Self { trigger, patterns })
+    }
+}
+
+/// Read a definition back from its node, ready to run
+fn load_node(node: &Node) -> Option<Loaded> {
+    let trigger = Trigger::from_node(node)?;
+    match Loaded::new(trigger) {
+        Ok(loaded) => Some(loaded),
+        Err(e) => {
+            tracing::warn!("Skipping trigger stored in {}: {}", node.id, e);
+            None
+        }
+    }
+}
+
+impl TriggerSet {
+    /// Load the triggers stored in `storage`
+    pub async fn load(storage: &LocalStorage) -> Result<Self> {
+        let set = Self::default();
+        for node in storage.get_nodes_by_type(TRIGGER_TYPE, None).await? {
+            if let Some(loaded) = load_node(&node) {
+                set.triggers.write().insert(loaded.trigger.name.clone(), loaded);
+            }
+        }
+        Ok(set)
+    }
+
+    /// Load the triggers stored in `storage` and run them on its writes
+    pub async fn install(storage: &LocalStorage) -> Result<Arc<Self>> {
+        let set = Arc::new(Self::load(storage).await?);
+        storage.set_write_hook(set.clone());
+        Ok(set)
+    }
+
+    /// Triggers running at `timing` on `event` to a node of `node_type`,
+    /// in name order
+    pub fn matching(&self, timing: TriggerTiming, event: TriggerEvent, node_type: &str) -> Vec<Trigger> {
+        self.triggers
+            .read()
+            .values()
+            .map(|loaded| &loaded.trigger)
+            .filter(|t| t.fires(timing, event, node_type))
+            .cloned()
+            .collect()
+    }
+
+    /// Every trigger, in name order
+    pub fn all(&self) -> Vec<Trigger> {
+        self.triggers.read().values().map(|loaded| loaded.trigger.clone()).collect()
+    }
+
+    /// Follow a committed change to a trigger definition
+    pub fn observe(&self, change: &Change) {
+        let mut triggers = self.triggers.write();
+        match change {
+            Change::NodeInserted { node } if node.node_type == TRIGGER_TYPE => {
+                if let Some(loaded) = load_node(node) {
+                    triggers.insert(loaded.trigger.name.clone(), loaded);
+                }
+            }
+            Change::NodeUpdated { before, after } if after.node_type == TRIGGER_TYPE => {
+                if let Some(old) = Trigger::from_node(before) {
+                    triggers.remove(&old.name);
+                }
+                if let Some(loaded) = load_node(after) {
+                    triggers.insert(loaded.trigger.name.clone(), loaded);
+                }
+            }
+            Change::NodeDeleted { node } if node.node_type == TRIGGER_TYPE => {
+                if let Some(trigger) = Trigger::from_node(node) {
+                    triggers.remove(&trigger.name);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl WriteHook for TriggerSet {
+    fn before_write(&self, kind: WriteKind, node: &mut Node) -> Result<()> {
+        // Definitions that could not run are refused however they are written
+        if node.node_type == TRIGGER_TYPE {
+            return match Trigger::from_node(node) {
+                Some(trigger) => trigger.validate(),
+                None => bail!("Invalid trigger definition"),
+            };
+        }
+        let event = match kind {
+            WriteKind::Insert => TriggerEvent::Insert,
+            WriteKind::Update => TriggerEvent::Update,
+        };
+        let triggers = self.triggers.read();
+        for Loaded { trigger, patterns } in triggers.values() {
+            if !trigger.fires(TriggerTiming::Before, event, &node.node_type) {
+                continue;
+            }
+            if let TriggerAction::Rules(rules) = &trigger.action {
+                for (rule, pattern) in rules.iter().zip(patterns) {
+                    if let Err(problem) = rule.apply(node, pattern.as_ref()) {
+                        bail!("Trigger '{}' rejected the {}: {}", trigger.name, event.keyword().to_lowercase(), problem);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn after_commit(&self, events: &[ChangeEvent]) {
+        for event in events {
+            self.observe(&event.change);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Database;
+    use tempfile::TempDir;
+
+    fn parse(sql: &str) -> Result<TriggerStatement> {
+        TriggerStatement::parse(sql).expect("a trigger statement")
+    }
+
+    #[test]
+    fn test_parse_statements() {
+        assert!(TriggerStatement::parse("SELECT * FROM user").is_none());
+        assert!(TriggerStatement::parse("CREATE TABLE user (id INT)").is_none());
+
+        let TriggerStatement::Create(trigger) = parse(
+            "create trigger user_rules before insert or update on user \
+             DEFAULT status = 'it''s new', REQUIRE email, CHECK age >= 0, CHECK email MATCHES '^.+@.+$';",
+        ).unwrap() else { panic!("expected CREATE") };
+        assert_eq!(trigger.node_type, "user");
+        assert_eq!(trigger.timing, TriggerTiming::Before);
+        assert_eq!(trigger.events, vec![TriggerEvent::Insert, TriggerEvent::Update]);
+        let TriggerAction::Rules(ref rules) = trigger.action else { panic!("expected rules") };
+        assert_eq!(rules.len(), 4);
+        assert_eq!(rules[0], Rule::Default { field: "status".into(), value: "it's new".into() });
+        assert_eq!(rules[2], Rule::Check { field: "age".into(), op: CheckOp::Ge, value: 0.into() });
+
+        // The definition prints back as a statement that parses to itself
+        assert_eq!(parse(&trigger.to_string()).unwrap(), TriggerStatement::Create(trigger));
+
+        let TriggerStatement::Create(hook) = parse(
+            "CREATE TRIGGER audit AFTER DELETE ON user WEBHOOK 'https://example.com/hook'",
+        ).unwrap() else { panic!("expected CREATE") };
+        assert_eq!(hook.action, TriggerAction::Webhook(Webhook {
+            url: "https://example.com/hook".into(),
+            retries: DEFAULT_WEBHOOK_RETRIES,
+        }));
+
+        assert_eq!(
+            parse("DROP TRIGGER IF EXISTS audit").unwrap(),
+            TriggerStatement::Drop { name: "audit".into(), if_exists: true },
+        );
+
+        for bad in [
+            "CREATE TRIGGER t BEFORE DELETE ON user REQUIRE name",
+            "CREATE TRIGGER t BEFORE INSERT ON user WEBHOOK 'http://x'",
+            "CREATE TRIGGER t AFTER INSERT ON user REQUIRE name",
+            "CREATE TRIGGER t AFTER INSERT ON user WEBHOOK 'ftp://x'",
+            "CREATE TRIGGER t BEFORE INSERT ON __user__ REQUIRE name",
+            "CREATE TRIGGER t BEFORE INSERT ON user CHECK age ~ 3",
+            "CREATE TRIGGER t BEFORE INSERT ON user CHECK email MATCHES '('",
+            "CREATE TRIGGER t BEFORE INSERT ON user DEFAULT status = active",
+            "CREATE TRIGGER t BEFORE INSERT ON user REQUIRE name extra",
+        ] {
+            assert!(parse(bad).is_err(), "{} should not parse", bad);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_before_triggers_on_writes() {
+        let temp = TempDir::new().unwrap();
+        let db = Database::create(temp.path(), "test").await.unwrap();
+
+        let TriggerStatement::Create(trigger) = parse(
+            "CREATE TRIGGER rules BEFORE INSERT OR UPDATE ON user \
+             DEFAULT status = 'active', REQUIRE email, CHECK age >= 0, CHECK email MATCHES '@'",
+        ).unwrap() else { panic!("expected CREATE") };
+        db.insert_node(TRIGGER_TYPE, trigger.to_properties().unwrap()).await.unwrap();
+
+        // A definition whose pattern does not compile is never stored
+        let mut invalid = trigger.clone();
+        invalid.name = "invalid".into();
+        invalid.action = TriggerAction::Rules(vec![
+            Rule::Check { field: "email".into(), op: CheckOp::Matches, value: "(".into() },
+        ]);
+        assert!(db.insert_node(TRIGGER_TYPE, invalid.to_properties().unwrap()).await.is_err());
+
+        // Defaults are filled in and returned with the stored node
+        let user = db.insert_node("user", serde_json::json!({ "email": "a@b.c" })).await.unwrap();
+        assert_eq!(user.get("status").and_then(Value::as_str), Some("active"));
+        let stored = db.get_node(&user.id.to_string()).await.unwrap().unwrap();
+        assert_eq!(stored.get("status").and_then(Value::as_str), Some("active"));
+
+        // Failed rules reject the write and leave nothing behind
+        let err = db.insert_node("user", serde_json::json!({ "age": 3 })).await.unwrap_err();
+        assert!(err.to_string().contains("'email' is required"), "{}", err);
+        let err = db.update_node(&user.id.to_string(), serde_json::json!({ "age": -1 })).await.unwrap_err();
+        assert!(err.to_string().contains("'age' must be >= 0"), "{}", err);
+        let err = db.insert_node("user", serde_json::json!({ "email": "nobody" })).await.unwrap_err();
+        assert!(err.to_string().contains("'email' must be MATCHES '@'"), "{}", err);
+        assert_eq!(db.get_all_by_type("user", None).await.unwrap().len(), 1);
+
+        // Other types are untouched, and triggers load again on reopen
+        db.insert_node("post", serde_json::json!({})).await.unwrap();
+        drop(db);
+        let db = Database::open(temp.path()).await.unwrap();
+        assert!(db.insert_node("user", serde_json::json!({})).await.is_err());
+
+        // Dropping the definition stops the trigger
+        let definition = db.get_all_by_type(TRIGGER_TYPE, None).await.unwrap().remove(0);
+        db.delete_node(&definition.id.to_string()).await.unwrap();
+        db.insert_node("user", serde_json::json!({})).await.unwrap();
+    }
+}
@@ -0,0 +1,373 @@
+//! Authentication
+//!
+//! Every connection opens with a [`super::protocol::Hello`] that may carry
+//! credentials. Accounts live in the database as
+//! `__user__` nodes holding a salted PBKDF2 hash of the password, and API
+//! tokens as `__token__` nodes holding the token's SHA-256 digest, so no
+//! secret can be recovered from the data files. Requests cannot read or
+//! write either node type, nor the access control types of [`super::rbac`].
+
+use anyhow::{Result, bail};
+use ring::{digest, pbkdf2, rand::{SecureRandom, SystemRandom}};
+use serde::{Serialize, Deserialize};
+use std::num::NonZeroU32;
+use std::sync::OnceLock;
+
+use crate::storage::{Database, Node, Value};
+pub(crate) use crate::storage::is_reserved_type;
+
+/// Node type holding user accounts
+pub(crate) const USER_TYPE: &str = "__user__";
+
+/// Node type holding API tokens
+pub(crate) const TOKEN_TYPE: &str = "__token__";
+
+/// PBKDF2 rounds for new password hashes
+const PBKDF2_ROUNDS: u32 = 100_000;
+
+/// Prefix making tokens recognisable in logs and secret scanners
+pub(super) const TOKEN_PREFIX: &str = "ares_";
+
+/// Credentials presented in the handshake
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Credentials {
+    /// Username and password
+    Password {
+        /// Account name
+        username: String,
+        /// Plain password, checked against the stored hash
+        password: String,
+    },
+    /// API token
+    Token(String),
+}
+
+impl Credentials {
+    /// Username and password credentials
+    pub fn password(username: impl Into<String>, password: impl Into<String>) -> Self {
+        Credentials::Password {
+            username: username.into(),
+            password: password.into(),
+        }
+    }
+
+    /// API token credentials
+    pub fn token(token: impl Into<String>) -> Self {
+        Credentials::Token(token.into())
+    }
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Credentials::Password { username, .. } => {
+                f.debug_struct("Password").field("username", username).finish_non_exhaustive()
+            }
+            Credentials::Token(_) => f.write_str("Token(..)"),
+        }
+    }
+}
+
+/// An API token, without its secret
+#[derive(Debug, Clone)]
+pub struct TokenInfo {
+    /// ID used to revoke the token
+    pub id: String,
+    /// Owner of the token
+    pub username: String,
+    /// Free-form note given when the token was issued
+    pub label: Option<String>,
+}
+
+/// User accounts and API tokens stored in a database
+pub struct UserStore<'a> {
+    db: &'a Database,
+}
+
+impl<'a> UserStore<'a> {
+    /// Accounts stored in `db`
+    pub fn new(db: &'a Database) -> Self {
+        Self { db }
+    }
+
+    /// Create a user
+    pub async fn create_user(&self, username: &str, password: &str) -> Result<()> {
+        if username.is_empty() {
+            bail!("Username must not be empty");
+        }
+        if self.find_user(username).await?.is_some() {
+            bail!("User already exists: {}", username);
+        }
+
+        self.db
+            .insert_node(USER_TYPE, serde_json::json!({
+                "username": username,
+                "password_hash": hash_password_blocking(password).await?,
+            }))
+            .await?;
+        Ok(())
+    }
+
+    /// Change a user's password
+    pub async fn set_password(&self, username: &str, password: &str) -> Result<()> {
+        let user = self.require_user(username).await?;
+        self.db
+            .update_node(&user.id.to_string(), serde_json::json!({ "password_hash": hash_password_blocking(password).await? }))
+            .await?;
+        Ok(())
+    }
+
+    /// Delete a user and revoke their tokens
+    pub async fn delete_user(&self, username: &str) -> Result<()> {
+        let user = self.require_user(username).await?;
+        for token in self.db.get_all_by_type(TOKEN_TYPE, None).await? {
+            if string_property(&token, "username") == Some(username) {
+                self.db.delete_node(&token.id.to_string()).await?;
+            }
+        }
+        self.db.delete_node(&user.id.to_string()).await
+    }
+
+    /// Usernames, sorted
+    pub async fn list_users(&self) -> Result<Vec<String>> {
+        let mut users: Vec<String> = self
+            .db
+            .get_all_by_type(USER_TYPE, None)
+            .await?
+            .iter()
+            .filter_map(|n| string_property(n, "username").map(str::to_string))
+            .collect();
+        users.sort();
+        Ok(users)
+    }
+
+    /// Issue an API token for a user
+    ///
+    /// The token is returned only here; the store keeps its digest.
+    pub async fn create_token(&self, username: &str, label: Option<&str>) -> Result<String> {
+        self.require_user(username).await?;
+
+        let mut secret = [0u8; 32];
+        SystemRandom::new()
+            .fill(&mut secret)
+            .map_err(|_| anyhow::anyhow!("Failed to generate token"))?;
+        let token = format!("{}{}", TOKEN_PREFIX, to_hex(&secret));
+
+        self.db
+            .insert_node(TOKEN_TYPE, serde_json::json!({
+                "username": username,
+                "label": label,
+                "digest": token_digest(&token),
+            }))
+            .await?;
+        Ok(token)
+    }
+
+    /// Tokens issued to a user, or to everyone
+    pub async fn list_tokens(&self, username: Option<&str>) -> Result<Vec<TokenInfo>> {
+        Ok(self
+            .db
+            .get_all_by_type(TOKEN_TYPE, None)
+            .await?
+            .iter()
+            .filter(|n| username.is_none() || string_property(n, "username") == username)
+            .map(|n| TokenInfo {
+                id: n.id.to_string(),
+                username: string_property(n, "username").unwrap_or_default().to_string(),
+                label: string_property(n, "label").map(str::to_string),
+            })
+            .collect())
+    }
+
+    /// Revoke a token by its ID
+    pub async fn revoke_token(&self, id: &str) -> Result<()> {
+        match self.db.get_node(id).await? {
+            Some(node) if node.node_type == TOKEN_TYPE => self.db.delete_node(id).await,
+            _ => bail!("Token not found: {}", id),
+        }
+    }
+
+    /// Whether any account exists
+    pub async fn has_users(&self) -> Result<bool> {
+        Ok(!self.db.get_all_by_type(USER_TYPE, Some(1)).await?.is_empty())
+    }
+
+    /// Username the credentials belong to, or `None` if they are invalid
+    pub async fn authenticate(&self, credentials: &Credentials) -> Result<Option<String>> {
+        match credentials {
+            Credentials::Password { username, password } => {
+                let stored = self
+                    .find_user(username)
+                    .await?
+                    .and_then(|user| string_property(&user, "password_hash").map(str::to_string));
+
+                // Unknown users are checked against a dummy hash, so the time
+                // taken does not tell whether the account exists
+                let known = stored.is_some();
+                let password = password.clone();
+                let valid = tokio::task::spawn_blocking(move || {
+                    verify_password(stored.as_deref().unwrap_or_else(|| dummy_hash()), &password)
+                })
+                .await?;
+                Ok((known && valid).then(|| username.clone()))
+            }
+            Credentials::Token(token) => {
+                let digest = token_digest(token);
+                let owner = self
+                    .db
+                    .get_all_by_type(TOKEN_TYPE, None)
+                    .await?
+                    .iter()
+                    .find(|n| string_property(n, "digest") == Some(digest.as_str()))
+                    .and_then(|n| string_property(n, "username").map(str::to_string));
+
+                // Tokens of deleted users are rejected
+                match owner {
+                    Some(user) if self.find_user(&user).await?.is_some() => Ok(Some(user)),
+                    _ => Ok(None),
+                }
+            }
+        }
+    }
+
+    async fn find_user(&self, username: &str) -> Result<Option<Node>> {
+        Ok(self
+            .db
+            .get_all_by_type(USER_TYPE, None)
+            .await?
+            .into_iter()
+            .find(|n| string_property(n, "username") == Some(username)))
+    }
+
+    pub(super) async fn require_user(&self, username: &str) -> Result<Node> {
+        self.find_user(username)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("User not found: {}", username))
+    }
+}
+
+pub(super) fn string_property<'n>(node: &'n Node, key: &str) -> Option<&'n str> {
+    match node.properties.get(key) {
+        Some(Value::String(s)) => Some(s),
+        _ => None,
+    }
+}
+
+/// Hash a password as `pbkdf2-sha256$rounds$salt$hash`
+fn hash_password(password: &str) -> Result<String> {
+    let mut salt = [0u8; 16];
+    SystemRandom::new()
+        .fill(&mut salt)
+        .map_err(|_| anyhow::anyhow!("Failed to generate salt"))?;
+    Ok(hash_with_salt(password, &salt))
+}
+
+/// [`hash_password`] on a blocking thread, off the event loop
+async fn hash_password_blocking(password: &str) -> Result<String> {
+    let password = password.to_string();
+    tokio::task::spawn_blocking(move || hash_password(&password)).await?
+}
+
+/// Hash checked in place of a missing account's, costing the same to verify
+fn dummy_hash() -> &'static str {
+    static DUMMY: OnceLock<String> = OnceLock::new();
+    DUMMY.get_or_init(|| hash_with_salt("", &[0u8; 16]))
+}
+
+fn hash_with_salt(password: &str, salt: &[u8]) -> String {
+    let mut hash = [0u8; digest::SHA256_OUTPUT_LEN];
+    let rounds = NonZeroU32::new(PBKDF2_ROUNDS).unwrap();
+    pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, rounds, salt, password.as_bytes(), &mut hash);
+
+    format!("pbkdf2-sha256${}${}${}", PBKDF2_ROUNDS, to_hex(salt), to_hex(&hash))
+}
+
+fn verify_password(stored: &str, password: &str) -> bool {
+    let mut parts = stored.split('$');
+    let (Some("pbkdf2-sha256"), Some(rounds), Some(salt), Some(hash), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return false;
+    };
+    let (Some(rounds), Some(salt), Some(hash)) = (
+        rounds.parse().ok().and_then(NonZeroU32::new),
+        from_hex(salt),
+        from_hex(hash),
+    ) else {
+        return false;
+    };
+
+    pbkdf2::verify(pbkdf2::PBKDF2_HMAC_SHA256, rounds, &salt, password.as_bytes(), &hash).is_ok()
+}
+
+fn token_digest(token: &str) -> String {
+    to_hex(digest::digest(&digest::SHA256, token.as_bytes()).as_ref())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_password_hash_roundtrip() {
+        let hash = hash_password("hunter2").unwrap();
+        assert!(hash.starts_with("pbkdf2-sha256$"));
+        assert!(verify_password(&hash, "hunter2"));
+        assert!(!verify_password(&hash, "hunter3"));
+        assert!(!verify_password("garbage", "hunter2"));
+        assert_ne!(hash, hash_password("hunter2").unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_user_store() {
+        let temp = TempDir::new().unwrap();
+        let db = Database::create(temp.path(), "test").await.unwrap();
+        let store = UserStore::new(&db);
+
+        assert!(!store.has_users().await.unwrap());
+        store.create_user("alice", "secret").await.unwrap();
+        assert!(store.create_user("alice", "again").await.is_err());
+        assert_eq!(store.list_users().await.unwrap(), vec!["alice"]);
+
+        let ok = Credentials::password("alice", "secret");
+        assert_eq!(store.authenticate(&ok).await.unwrap().as_deref(), Some("alice"));
+        let wrong = Credentials::password("alice", "guess");
+        assert_eq!(store.authenticate(&wrong).await.unwrap(), None);
+        let unknown = Credentials::password("bob", "");
+        assert_eq!(store.authenticate(&unknown).await.unwrap(), None);
+
+        store.set_password("alice", "changed").await.unwrap();
+        assert_eq!(store.authenticate(&ok).await.unwrap(), None);
+
+        let token = store.create_token("alice", Some("ci")).await.unwrap();
+        assert!(token.starts_with(TOKEN_PREFIX));
+        let by_token = Credentials::token(&token);
+        assert_eq!(store.authenticate(&by_token).await.unwrap().as_deref(), Some("alice"));
+
+        let tokens = store.list_tokens(Some("alice")).await.unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].label.as_deref(), Some("ci"));
+        store.revoke_token(&tokens[0].id).await.unwrap();
+        assert_eq!(store.authenticate(&by_token).await.unwrap(), None);
+
+        let token = store.create_token("alice", None).await.unwrap();
+        store.delete_user("alice").await.unwrap();
+        assert_eq!(store.authenticate(&Credentials::token(token)).await.unwrap(), None);
+        assert!(store.list_tokens(None).await.unwrap().is_empty());
+    }
+}
@@ -1,28 +1,90 @@
 //! Request Handler
 //!
-//! Processes incoming requests and interacts with storage.
+//! Processes incoming requests and interacts with storage. Once accounts
+//! exist, every request is checked against the caller's grants first, and
+//! results mixing types are trimmed to what the caller may read.
 
 use anyhow::Result;
-use parking_lot::RwLock;
-use std::collections::HashMap;
+use parking_lot::{Mutex, RwLock};
+use std::collections::{HashMap, HashSet};
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::broadcast;
+use tracing::{warn, Instrument};
 
-use super::protocol::{Request, Response, ErrorCode};
-use crate::storage::{Database, Node, Edge, Value};
-use crate::distributed::ShardManager;
+use super::auth::{is_reserved_type, Credentials, UserStore, USER_TYPE};
+use super::protocol::{Request, Response, ErrorCode, HandshakeReply, SearchHit};
+use super::webhooks::{WebhookConfig, WebhookDispatcher};
+use super::rbac::{
+    query_access, AccessStatement, AccessStore, AccessWrite, Denial, Permission, Policy, Resource, AUDIT_TYPE, ROLE_TYPE,
+};
+use crate::storage::{
+    Change, ChangeEvent, ChangeFilter, ChangeStream, Database, DistanceMetric, Node, Edge, EdgeId, NodeId, Timestamp, Value,
+    VectorSearch,
+};
+use crate::distributed::{NotLeader, RaftNode, ReplicationCommand, ShardManager};
+use crate::query::{QueryEngine, QueryOperation, QueryParser, ShardedQueryEngine};
+use crate::schema::{Schema, SchemaManager, TriggerStatement, TRIGGER_TYPE};
+use crate::metrics::{self, metrics};
 
 /// Request handler for processing client requests
 pub struct RequestHandler {
     /// Database (single node mode)
     db: Option<Database>,
     /// Shard manager (distributed mode)
-    shards: Option<ShardManager>,
+    shards: Option<Arc<ShardManager>>,
+    /// Raft node (replicated mode); writes go through consensus
+    raft: Option<Arc<RaftNode>>,
+    /// Database holding user accounts, if not the one being served
+    accounts: Option<Database>,
+    /// Grants loaded from the accounts, until they change
+    policy: RwLock<Option<Arc<Policy>>>,
+    /// Replicated changes to the grants (replicated mode)
+    role_watch: Option<RoleWatch>,
     /// Active transactions
     transactions: RwLock<HashMap<u64, Transaction>>,
     /// Transaction ID counter
     tx_counter: AtomicU64,
 }
 
+/// Notices replicated changes to roles and accounts, which a follower
+/// applies without going through its handler
+struct RoleWatch {
+    changes: Mutex<broadcast::Receiver<ChangeEvent>>,
+    /// Snapshot index when last asked, as restoring one publishes no changes
+    snapshot: AtomicU64,
+}
+
+impl RoleWatch {
+    fn new(raft: &RaftNode) -> Self {
+        Self {
+            changes: Mutex::new(raft.database().local().subscribe_changes()),
+            snapshot: AtomicU64::new(raft.replica().snapshot_index()),
+        }
+    }
+
+    /// Whether the replicated grants may have changed since last asked
+    fn changed(&self, raft: &RaftNode) -> bool {
+        let snapshot = raft.replica().snapshot_index();
+        let mut changed = self.snapshot.swap(snapshot, Ordering::Relaxed) != snapshot;
+        let mut changes = self.changes.lock();
+        loop {
+            match changes.try_recv() {
+                Ok(event) => {
+                    changed |= event
+                        .change
+                        .node()
+                        .is_some_and(|n| n.node_type == ROLE_TYPE || n.node_type == USER_TYPE);
+                }
+                Err(broadcast::error::TryRecvError::Lagged(_)) => changed = true,
+                Err(_) => break,
+            }
+        }
+        changed
+    }
+}
+
 struct Transaction {
     // For future use with actual transaction support
     #[allow(dead_code)]
@@ -35,23 +97,466 @@ impl RequestHandler {
         Self {
             db: Some(db),
             shards: None,
+            raft: None,
+            accounts: None,
+            policy: RwLock::new(None),
+            role_watch: None,
             transactions: RwLock::new(HashMap::new()),
             tx_counter: AtomicU64::new(1),
         }
     }
 
     /// Create handler with shards
-    pub fn with_shards(shards: ShardManager) -> Self {
+    pub fn with_shards(shards: impl Into<Arc<ShardManager>>) -> Self {
+        Self {
+            db: None,
+            shards: Some(shards.into()),
+            raft: None,
+            accounts: None,
+            policy: RwLock::new(None),
+            role_watch: None,
+            transactions: RwLock::new(HashMap::new()),
+            tx_counter: AtomicU64::new(1),
+        }
+    }
+
+    /// Create handler for a replicated node
+    ///
+    /// Reads are served from the local replica; writes are accepted only on
+    /// the leader and answered once committed.
+    pub fn with_replication(raft: Arc<RaftNode>) -> Self {
+        let role_watch = Some(RoleWatch::new(&raft));
         Self {
             db: None,
-            shards: Some(shards),
+            shards: None,
+            raft: Some(raft),
+            accounts: None,
+            policy: RwLock::new(None),
+            role_watch,
             transactions: RwLock::new(HashMap::new()),
             tx_counter: AtomicU64::new(1),
         }
     }
 
-    /// Handle a request
+    /// Keep user accounts in `db`
+    ///
+    /// Sharded stores have no database of their own, so accounts need one
+    /// set here; otherwise they live in the database being served.
+    pub fn with_accounts(mut self, db: Database) -> Self {
+        self.accounts = Some(db);
+        self
+    }
+
+    /// Database serving reads
+    fn database(&self) -> Option<&Database> {
+        self.db.as_ref().or_else(|| self.raft.as_deref().map(RaftNode::database))
+    }
+
+    /// Raft node of a replicated server, for its peer listener
+    pub(crate) fn raft(&self) -> Option<&Arc<RaftNode>> {
+        self.raft.as_ref()
+    }
+
+    /// Dispatcher for the webhooks of the database served; `None` for
+    /// sharded stores, which don't run triggers
+    pub(crate) fn webhook_dispatcher(&self, config: WebhookConfig) -> Option<Result<WebhookDispatcher>> {
+        match (&self.raft, &self.db) {
+            (Some(raft), _) => Some(WebhookDispatcher::replicated(raft.clone(), config)),
+            (None, Some(db)) => Some(WebhookDispatcher::new(db.clone(), config)),
+            _ => None,
+        }
+    }
+
+    /// Make everything written so far durable, for shutdown
+    pub async fn flush(&self) -> Result<()> {
+        if let Some(db) = self.database() {
+            db.flush().await?;
+        }
+        if let Some(shards) = &self.shards {
+            shards.flush().await?;
+        }
+        if let Some(accounts) = &self.accounts {
+            accounts.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Store holding user accounts and API tokens
+    fn users(&self) -> Option<UserStore<'_>> {
+        self.accounts.as_ref().or_else(|| self.database()).map(UserStore::new)
+    }
+
+    /// Check handshake credentials, returning the user they belong to
+    ///
+    /// Connections without credentials are let in anonymously unless
+    /// `required` is set. A refusal is the reply to send the client.
+    pub async fn authenticate(
+        &self,
+        credentials: Option<&Credentials>,
+        required: bool,
+    ) -> std::result::Result<Option<String>, HandshakeReply> {
+        let reject = |code, message: &str| HandshakeReply::Rejected { code, message: message.to_string() };
+
+        let Some(credentials) = credentials else {
+            if required {
+                return Err(reject(ErrorCode::AuthenticationFailed, "Credentials required"));
+            }
+            return Ok(None);
+        };
+        let Some(users) = self.users() else {
+            return Err(reject(ErrorCode::AuthenticationFailed, "Server has no user accounts"));
+        };
+
+        match users.authenticate(credentials).await {
+            Ok(Some(user)) => Ok(Some(user)),
+            Ok(None) => Err(reject(ErrorCode::AuthenticationFailed, "Invalid credentials")),
+            Err(e) => Err(reject(ErrorCode::InternalError, &e.to_string())),
+        }
+    }
+
+    /// Whether `username` has an account, so logging in as them needs proof
+    pub(crate) async fn has_user(&self, username: &str) -> bool {
+        match self.users() {
+            Some(users) => users.require_user(username).await.is_ok(),
+            None => false,
+        }
+    }
+
+    /// Registered schemas whose nodes `user` may read
+    pub(crate) async fn readable_schemas(&self, user: Option<&str>) -> Result<Vec<Schema>> {
+        let Some(db) = self.accounts.as_ref().or_else(|| self.database()) else {
+            return Ok(Vec::new());
+        };
+        let policy = self.policy().await;
+        let schemas = SchemaManager::new(db.clone()).list_schemas().await?;
+        Ok(schemas
+            .into_iter()
+            .filter(|schema| !is_reserved_type(&schema.name))
+            .filter(|schema| policy.allows(user, Permission::Read, &Resource::node(schema.name.clone())))
+            .collect())
+    }
+
+    /// Store holding roles and the audit log
+    ///
+    /// A replicated server keeps the roles' grants in the replicated data.
+    fn access(&self) -> Option<AccessStore<'_>> {
+        let accounts = self.accounts.as_ref().or_else(|| self.database())?;
+        Some(match self.raft {
+            Some(ref raft) => AccessStore::with_roles(accounts, raft.database()),
+            None => AccessStore::new(accounts),
+        })
+    }
+
+    /// Whether nodes of `node_type` live in the replicated data, which only
+    /// changes through the log
+    fn is_replicated(&self, node_type: &str) -> bool {
+        self.raft.is_some() && (node_type == ROLE_TYPE || self.accounts.is_none())
+    }
+
+    /// Current grants, loaded on first use
+    ///
+    /// If they cannot be loaded every request is refused.
+    async fn policy(&self) -> Arc<Policy> {
+        if let (Some(watch), Some(raft)) = (&self.role_watch, &self.raft) {
+            if watch.changed(raft) {
+                self.reload_access();
+            }
+        }
+        if let Some(policy) = self.policy.read().clone() {
+            return policy;
+        }
+
+        let policy = match self.access() {
+            Some(access) => access.policy().await.unwrap_or_else(|e| {
+                warn!("Failed to load access control, denying all requests: {:#}", e);
+                Policy::deny_all()
+            }),
+            None => Policy::default(),
+        };
+        let policy = Arc::new(policy);
+        *self.policy.write() = Some(Arc::clone(&policy));
+        policy
+    }
+
+    /// Reload grants on the next request, after accounts changed outside
+    /// the handler
+    pub fn reload_access(&self) {
+        *self.policy.write() = None;
+    }
+
+    /// Refuse a request, recording the denial
+    async fn deny(&self, user: Option<&str>, operation: &str, permission: Permission, resource: Resource) -> Response {
+        let denial = Denial {
+            user: user.map(str::to_string),
+            operation: operation.to_string(),
+            permission,
+            resource,
+            at: Timestamp::now(),
+        };
+        warn!(target: "aresadb::audit", "{}", denial);
+        // Without accounts of its own a replicated node only logs denials
+        if let Some(access) = self.access().filter(|_| !self.is_replicated(AUDIT_TYPE)) {
+            if let Err(e) = access.record_denial(&denial).await {
+                warn!("Failed to record denial: {:#}", e);
+            }
+        }
+
+        Response::error(
+            ErrorCode::PermissionDenied,
+            format!("{} requires {} on {}", denial.operation, denial.permission, denial.resource),
+        )
+    }
+
+    /// Permissions a request needs before it runs
+    ///
+    /// Reads that can return several types are checked on their results
+    /// instead, and queries once parsed.
+    async fn required_access(&self, request: &Request) -> Vec<(Permission, Resource)> {
+        match request {
+            Request::InsertNode { node_type, .. } => vec![(Permission::Write, Resource::node(node_type))],
+            Request::GetNodesByType { node_type, .. } | Request::SimilaritySearch { node_type, .. } => {
+                vec![(Permission::Read, Resource::node(node_type))]
+            }
+            Request::UpdateNode { id, .. } => match self.node_type_of(id).await {
+                Some(node_type) => vec![(Permission::Write, Resource::node(node_type))],
+                None => Vec::new(),
+            },
+            Request::DeleteNode { id } => match self.node_type_of(id).await {
+                Some(node_type) => vec![(Permission::Delete, Resource::node(node_type))],
+                None => Vec::new(),
+            },
+            Request::CreateEdge { edge_type, .. } => vec![(Permission::Write, Resource::edge(edge_type))],
+            Request::GetEdgesFrom { edge_type: Some(edge_type), .. }
+            | Request::GetEdgesTo { edge_type: Some(edge_type), .. } => {
+                vec![(Permission::Read, Resource::edge(edge_type))]
+            }
+            Request::DeleteEdge { edge_id } => match self.edge_type_of(edge_id).await {
+                Some(edge_type) => vec![(Permission::Delete, Resource::edge(edge_type))],
+                None => Vec::new(),
+            },
+            Request::AddMember { .. }
+            | Request::RemoveMember { .. }
+            | Request::TransferLeadership { .. } => vec![(Permission::Admin, Resource::All)],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Trim a read to what `user` may see
+    ///
+    /// Edge lists and traversals drop what is not readable; a single node
+    /// or a path through an unreadable node is refused outright.
+    fn filter_response(
+        policy: &Policy,
+        user: Option<&str>,
+        response: Response,
+    ) -> std::result::Result<Response, (Permission, Resource)> {
+        let can_read_node = |node: &Node| policy.allows(user, Permission::Read, &Resource::node(&node.node_type));
+        let can_read_edge = |edge: &Edge| policy.allows(user, Permission::Read, &Resource::edge(&edge.edge_type));
+
+        match response {
+            Response::MaybeNode(Some(node)) if !can_read_node(&node) => {
+                Err((Permission::Read, Resource::node(node.node_type)))
+            }
+            Response::Path(Some(path)) => match path.iter().find(|n| !can_read_node(n)) {
+                Some(node) => Err((Permission::Read, Resource::node(&node.node_type))),
+                None => Ok(Response::Path(Some(path))),
+            },
+            Response::Edges(mut edges) => {
+                edges.retain(|e| can_read_edge(e));
+                Ok(Response::Edges(edges))
+            }
+            Response::TraversalResult { mut nodes, mut edges, depth } => {
+                nodes.retain(|n| can_read_node(n));
+                edges.retain(|e| can_read_edge(e));
+                Ok(Response::TraversalResult { nodes, edges, depth })
+            }
+            response => Ok(response),
+        }
+    }
+
+    async fn node_type_of(&self, id: &str) -> Option<String> {
+        match self.handle_get_node(id).await {
+            Response::MaybeNode(Some(node)) => Some(node.node_type),
+            _ => None,
+        }
+    }
+
+    async fn edge_type_of(&self, id: &str) -> Option<String> {
+        let id = EdgeId::parse(id).ok()?;
+        let edge = if let Some(db) = self.database() {
+            db.local().get_edge(&id).await
+        } else if let Some(ref shards) = self.shards {
+            shards.find_edge(&id).await
+        } else {
+            return None;
+        };
+        edge.ok().flatten().map(|e| e.edge_type)
+    }
+
+    /// Refuse requests that would expose or modify account nodes
+    async fn check_reserved(&self, request: &Request) -> Option<Response> {
+        let denied = || Some(Response::error(ErrorCode::PermissionDenied, "Reserved node type"));
+        match request {
+            Request::InsertNode { node_type, .. }
+            | Request::GetNodesByType { node_type, .. }
+            | Request::SimilaritySearch { node_type, .. }
+                if is_reserved_type(node_type) => denied(),
+            Request::UpdateNode { id, .. } | Request::DeleteNode { id } if self.is_reserved_node(id).await => {
+                denied()
+            }
+            Request::CreateEdge { from_id, to_id, .. }
+                if self.is_reserved_node(from_id).await || self.is_reserved_node(to_id).await => denied(),
+            _ => None,
+        }
+    }
+
+    async fn is_reserved_node(&self, id: &str) -> bool {
+        matches!(
+            self.handle_get_node(id).await,
+            Response::MaybeNode(Some(node)) if is_reserved_type(&node.node_type)
+        )
+    }
+
+    /// Replicate a write, mapping failures to a response
+    async fn propose(
+        &self,
+        raft: &RaftNode,
+        command: Result<ReplicationCommand>,
+        code: ErrorCode,
+    ) -> std::result::Result<(), Response> {
+        let result = match command {
+            Ok(command) => raft.propose(command).await,
+            Err(e) => return Err(Response::error(ErrorCode::InvalidRequest, e.to_string())),
+        };
+
+        result.map_err(|e| raft_error(e, code))
+    }
+
+    /// Handle a request from an anonymous connection
     pub async fn handle(&self, request: Request) -> Response {
+        self.handle_as(None, request).await
+    }
+
+    /// Handle a request on behalf of `user`
+    ///
+    /// Runs in a `request` span and is counted in the request metrics
+    /// whichever front end it arrived on.
+    pub async fn handle_as(&self, user: Option<&str>, request: Request) -> Response {
+        let name = request.name();
+        let span = tracing::info_span!("request", request = name, user = user.unwrap_or("-"));
+        let started = Instant::now();
+
+        let response = self.authorize(user, request).instrument(span).await;
+
+        let m = metrics();
+        m.request_duration.with_label_values(&[name]).observe(started.elapsed().as_secs_f64());
+        m.requests.with_label_values(&[name, &Self::status_label(&response)]).inc();
+        response
+    }
+
+    /// Outcome of a request as a metric label
+    fn status_label(response: &Response) -> String {
+        match response {
+            Response::Error { code, .. } => format!("{:?}", code),
+            Response::NotLeader { .. } => "NotLeader".to_string(),
+            _ => "ok".to_string(),
+        }
+    }
+
+    /// Metrics in the Prometheus text format, with shard gauges refreshed
+    pub async fn metrics(&self) -> String {
+        if let Some(shards) = &self.shards {
+            if let Err(e) = shards.stats().await {
+                warn!("Failed to read shard stats for metrics: {:#}", e);
+            }
+        }
+        metrics::render()
+    }
+
+    /// Open a change feed for `user`, with the latest sequence at the time
+    ///
+    /// Types named in the filter must be readable. Changes to other types
+    /// are checked one by one as they arrive with [`can_see`](Self::can_see).
+    pub(crate) async fn subscribe(
+        &self,
+        user: Option<&str>,
+        after: Option<u64>,
+        filter: ChangeFilter,
+    ) -> std::result::Result<(u64, ChangeStream), Response> {
+        let opened = self.open_feed(user, after, filter).await;
+        let status = match &opened {
+            Ok(_) => "ok".to_string(),
+            Err(response) => Self::status_label(response),
+        };
+        metrics().requests.with_label_values(&["Subscribe", &status]).inc();
+        opened
+    }
+
+    async fn open_feed(
+        &self,
+        user: Option<&str>,
+        after: Option<u64>,
+        filter: ChangeFilter,
+    ) -> std::result::Result<(u64, ChangeStream), Response> {
+        if filter.node_types.iter().any(|t| is_reserved_type(t)) {
+            return Err(Response::error(ErrorCode::PermissionDenied, "Reserved node type"));
+        }
+
+        let policy = self.policy().await;
+        let requested = filter.node_types.iter().map(Resource::node)
+            .chain(filter.edge_types.iter().map(Resource::edge));
+        for resource in requested {
+            if !policy.allows(user, Permission::Read, &resource) {
+                return Err(self.deny(user, "Subscribe", Permission::Read, resource).await);
+            }
+        }
+
+        let Some(db) = self.database() else {
+            return Err(Response::error(ErrorCode::InvalidRequest, "Change feeds are not available on sharded stores"));
+        };
+        let stream = db.changes(after, filter)
+            .map_err(|e| Response::error(ErrorCode::InvalidRequest, e.to_string()))?;
+        let last_seq = db.last_change_seq()
+            .map_err(|e| Response::error(ErrorCode::InternalError, e.to_string()))?;
+        Ok((last_seq, stream))
+    }
+
+    /// Whether `user` may receive `change` on a subscription
+    pub(crate) async fn can_see(&self, user: Option<&str>, change: &Change) -> bool {
+        let resource = match (change.node(), change.edge()) {
+            (Some(node), _) if is_reserved_type(&node.node_type) => return false,
+            (Some(node), _) => Resource::node(&node.node_type),
+            (_, Some(edge)) => Resource::edge(&edge.edge_type),
+            _ => return false,
+        };
+        self.policy().await.allows(user, Permission::Read, &resource)
+    }
+
+    async fn authorize(&self, user: Option<&str>, request: Request) -> Response {
+        if let Some(response) = self.check_reserved(&request).await {
+            return response;
+        }
+
+        let policy = self.policy().await;
+        if !policy.is_enforced() {
+            return self.dispatch(user, request).await;
+        }
+
+        let operation = request.name();
+        for (permission, resource) in self.required_access(&request).await {
+            if !policy.allows(user, permission, &resource) {
+                return self.deny(user, operation, permission, resource).await;
+            }
+        }
+
+        let response = self.dispatch(user, request).await;
+        match Self::filter_response(&policy, user, response) {
+            Ok(response) => response,
+            Err((permission, resource)) => self.deny(user, operation, permission, resource).await,
+        }
+    }
+
+    async fn dispatch(&self, user: Option<&str>, request: Request) -> Response {
         match request {
             Request::Ping => Response::Pong,
             Request::Disconnect => Response::Goodbye,
@@ -60,9 +565,10 @@ impl RequestHandler {
                 self.handle_insert_node(&node_type, properties).await
             }
 
-            Request::GetNode { id } => {
-                self.handle_get_node(&id).await
-            }
+            Request::GetNode { id } => match self.handle_get_node(&id).await {
+                Response::MaybeNode(Some(node)) if is_reserved_type(&node.node_type) => Response::MaybeNode(None),
+                response => response,
+            },
 
             Request::UpdateNode { id, properties } => {
                 self.handle_update_node(&id, properties).await
@@ -93,7 +599,7 @@ impl RequestHandler {
             }
 
             Request::Query { sql, limit } => {
-                self.handle_query(&sql, limit).await
+                self.handle_query(user, &sql, limit).await
             }
 
             Request::Traverse { start_id, depth, edge_types } => {
@@ -115,10 +621,85 @@ impl RequestHandler {
             Request::RollbackTransaction { tx_id } => {
                 self.handle_rollback_transaction(tx_id)
             }
+
+            // Peers authenticate with the cluster secret on their own listener
+            Request::Raft(_) => Response::error(
+                ErrorCode::PermissionDenied,
+                "Consensus messages are only accepted on the peer listener",
+            ),
+
+            Request::ListMembers => match self.raft {
+                Some(ref raft) => Response::Members(raft.members()),
+                None => Response::error(ErrorCode::InvalidRequest, "Server is not replicated"),
+            },
+
+            Request::AddMember { id, address, peer_address, voter } => {
+                self.handle_add_member(&id, address, peer_address, voter).await
+            }
+
+            Request::RemoveMember { id } => {
+                self.handle_remove_member(&id).await
+            }
+
+            Request::TransferLeadership { id } => {
+                self.handle_transfer_leadership(&id).await
+            }
+
+            Request::ShortestPath { from_id, to_id, max_depth } => {
+                self.handle_shortest_path(&from_id, &to_id, max_depth).await
+            }
+
+            Request::SimilaritySearch { node_type, field, vector, k, metric } => {
+                self.handle_similarity_search(&node_type, &field, &vector, k, metric).await
+            }
+
+            // Served by the connection, which streams the changes
+            Request::Subscribe { .. } => {
+                Response::error(ErrorCode::InvalidRequest, "Subscribe needs a streaming connection")
+            }
         }
     }
 
+    async fn handle_add_member(&self, id: &str, address: String, peer_address: String, voter: bool) -> Response {
+        let Some(ref raft) = self.raft else {
+            return Response::error(ErrorCode::InvalidRequest, "Server is not replicated");
+        };
+        let result = if voter {
+            raft.add_member(id, Some(address), Some(peer_address)).await
+        } else {
+            raft.add_learner(id, Some(address), Some(peer_address)).await
+        };
+        members_response(raft, result)
+    }
+
+    async fn handle_remove_member(&self, id: &str) -> Response {
+        let Some(ref raft) = self.raft else {
+            return Response::error(ErrorCode::InvalidRequest, "Server is not replicated");
+        };
+        members_response(raft, raft.remove_member(id).await)
+    }
+
+    async fn handle_transfer_leadership(&self, id: &str) -> Response {
+        let Some(ref raft) = self.raft else {
+            return Response::error(ErrorCode::InvalidRequest, "Server is not replicated");
+        };
+        members_response(raft, raft.transfer_leadership(id).await)
+    }
+
     async fn handle_insert_node(&self, node_type: &str, properties: Value) -> Response {
+        if let Some(ref raft) = self.raft {
+            let node = Node::new(node_type, properties);
+            if let Err(response) = self.propose(raft, ReplicationCommand::insert_node(&node), ErrorCode::InternalError).await {
+                return response;
+            }
+            // Triggers may have filled in defaults as it was applied
+            return match raft.database().local().get_node(&node.id).await {
+                Ok(Some(stored)) => Response::Node(stored),
+                Ok(None) => Response::Node(node),
+                Err(e) => Response::error(ErrorCode::InternalError, e.to_string()),
+            };
+        }
+
         let props_json = properties.to_json();
 
         let result = if let Some(ref db) = self.db {
@@ -137,7 +718,7 @@ impl RequestHandler {
     }
 
     async fn handle_get_node(&self, id: &str) -> Response {
-        let result = if let Some(ref db) = self.db {
+        let result = if let Some(db) = self.database() {
             db.get_node(id).await
         } else if let Some(ref shards) = self.shards {
             match crate::storage::NodeId::parse(id) {
@@ -155,6 +736,22 @@ impl RequestHandler {
     }
 
     async fn handle_update_node(&self, id: &str, properties: Value) -> Response {
+        if let Some(ref raft) = self.raft {
+            let node_id = match NodeId::parse(id) {
+                Ok(node_id) => node_id,
+                Err(e) => return Response::error(ErrorCode::InvalidRequest, e.to_string()),
+            };
+            let command = ReplicationCommand::update_node(&node_id, properties);
+            if let Err(response) = self.propose(raft, command, ErrorCode::NodeNotFound).await {
+                return response;
+            }
+            return match raft.database().get_node(id).await {
+                Ok(Some(node)) => Response::Node(node),
+                Ok(None) => Response::error(ErrorCode::NodeNotFound, format!("Node not found: {}", id)),
+                Err(e) => Response::error(ErrorCode::InternalError, e.to_string()),
+            };
+        }
+
         let props_json = properties.to_json();
 
         let result = if let Some(ref db) = self.db {
@@ -175,6 +772,14 @@ impl RequestHandler {
     }
 
     async fn handle_delete_node(&self, id: &str) -> Response {
+        if let Some(ref raft) = self.raft {
+            let command = NodeId::parse(id).and_then(|node_id| ReplicationCommand::delete_node(&node_id));
+            return match self.propose(raft, command, ErrorCode::NodeNotFound).await {
+                Ok(()) => Response::Ok,
+                Err(response) => response,
+            };
+        }
+
         let result = if let Some(ref db) = self.db {
             db.delete_node(id).await
         } else if let Some(ref shards) = self.shards {
@@ -193,7 +798,7 @@ impl RequestHandler {
     }
 
     async fn handle_get_nodes_by_type(&self, node_type: &str, limit: Option<usize>) -> Response {
-        let result = if let Some(ref db) = self.db {
+        let result = if let Some(db) = self.database() {
             db.get_all_by_type(node_type, limit).await
         } else if let Some(ref shards) = self.shards {
             shards.get_nodes_by_type(node_type, limit).await
@@ -207,6 +812,34 @@ impl RequestHandler {
         }
     }
 
+    async fn handle_similarity_search(
+        &self,
+        node_type: &str,
+        field: &str,
+        vector: &[f32],
+        k: usize,
+        metric: DistanceMetric,
+    ) -> Response {
+        let nodes = match self.handle_get_nodes_by_type(node_type, None).await {
+            Response::Nodes(nodes) => nodes,
+            response => return response,
+        };
+
+        let mut by_id: HashMap<NodeId, Node> = nodes.iter().map(|n| (n.id.clone(), n.clone())).collect();
+        let hits = VectorSearch::new(metric)
+            .search(vector, &nodes, field, k)
+            .into_iter()
+            .filter_map(|result| {
+                by_id.remove(&result.node_id).map(|node| SearchHit {
+                    node,
+                    score: result.score,
+                    distance: result.distance,
+                })
+            })
+            .collect();
+        Response::SearchResults(hits)
+    }
+
     async fn handle_create_edge(
         &self,
         from_id: &str,
@@ -214,7 +847,20 @@ impl RequestHandler {
         edge_type: &str,
         properties: Option<Value>,
     ) -> Response {
-        let props_json = properties.map(|p| p.to_json());
+        if let Some(ref raft) = self.raft {
+            let (from, to) = match (NodeId::parse(from_id), NodeId::parse(to_id)) {
+                (Ok(from), Ok(to)) => (from, to),
+                (Err(e), _) | (_, Err(e)) => return Response::error(ErrorCode::InvalidRequest, e.to_string()),
+            };
+            let props = properties.unwrap_or(Value::Object(Default::default()));
+            let edge = Edge::new(from, to, edge_type, props);
+            return match self.propose(raft, ReplicationCommand::insert_edge(&edge), ErrorCode::InternalError).await {
+                Ok(()) => Response::Edge(edge),
+                Err(response) => response,
+            };
+        }
+
+        let props_json = properties.as_ref().map(|p| p.to_json());
 
         let result = if let Some(ref db) = self.db {
             db.create_edge(from_id, to_id, edge_type, props_json).await
@@ -240,7 +886,7 @@ impl RequestHandler {
     }
 
     async fn handle_get_edges_from(&self, node_id: &str, edge_type: Option<&str>) -> Response {
-        let result = if let Some(ref db) = self.db {
+        let result = if let Some(db) = self.database() {
             db.get_edges_from(node_id, edge_type).await
         } else if let Some(ref shards) = self.shards {
             match crate::storage::NodeId::parse(node_id) {
@@ -258,10 +904,15 @@ impl RequestHandler {
     }
 
     async fn handle_get_edges_to(&self, node_id: &str, edge_type: Option<&str>) -> Response {
-        let result = if let Some(ref db) = self.db {
+        let result = if let Some(db) = self.database() {
             db.get_edges_to(node_id, edge_type).await
+        } else if let Some(ref shards) = self.shards {
+            match NodeId::parse(node_id) {
+                Ok(id) => shards.get_edges_to(&id, edge_type).await,
+                Err(e) => return Response::error(ErrorCode::InvalidRequest, e.to_string()),
+            }
         } else {
-            return Response::error(ErrorCode::InternalError, "Sharded mode doesn't support edges_to");
+            return Response::error(ErrorCode::InternalError, "No storage configured");
         };
 
         match result {
@@ -270,28 +921,242 @@ impl RequestHandler {
         }
     }
 
-    async fn handle_delete_edge(&self, _edge_id: &str) -> Response {
-        // TODO: Implement edge deletion
-        Response::error(ErrorCode::InternalError, "Not implemented")
+    async fn handle_delete_edge(&self, edge_id: &str) -> Response {
+        if let Some(ref raft) = self.raft {
+            let command = EdgeId::parse(edge_id).and_then(|id| ReplicationCommand::delete_edge(&id));
+            return match self.propose(raft, command, ErrorCode::EdgeNotFound).await {
+                Ok(()) => Response::Ok,
+                Err(response) => response,
+            };
+        }
+
+        let result = if let Some(ref db) = self.db {
+            db.delete_edge(edge_id).await
+        } else if let Some(ref shards) = self.shards {
+            match EdgeId::parse(edge_id) {
+                Ok(id) => shards.delete_edge(&id).await,
+                Err(e) => return Response::error(ErrorCode::InvalidRequest, e.to_string()),
+            }
+        } else {
+            return Response::error(ErrorCode::InternalError, "No storage configured");
+        };
+
+        match result {
+            Ok(()) => Response::Ok,
+            Err(e) => Response::error(ErrorCode::EdgeNotFound, e.to_string()),
+        }
+    }
+
+    async fn handle_query(&self, user: Option<&str>, sql: &str, limit: Option<usize>) -> Response {
+        if let Some(statement) = AccessStatement::parse(sql) {
+            return match statement {
+                Ok(statement) => self.handle_access_statement(user, &statement).await,
+                Err(e) => Response::error(ErrorCode::QueryParseError, e.to_string()),
+            };
+        }
+
+        if let Some(statement) = TriggerStatement::parse(sql) {
+            return match statement {
+                Ok(statement) => self.handle_trigger_statement(user, statement).await,
+                Err(e) => Response::error(ErrorCode::QueryParseError, e.to_string()),
+            };
+        }
+
+        let query = match QueryParser::new().parse(sql) {
+            Ok(query) => query,
+            Err(e) => return Response::error(ErrorCode::QueryParseError, e.to_string()),
+        };
+        if is_reserved_type(&query.target) {
+            return Response::error(ErrorCode::PermissionDenied, "Reserved node type");
+        }
+
+        let policy = self.policy().await;
+        let access = match query_access(&query) {
+            Ok(access) => access,
+            Err(e) => return Response::error(ErrorCode::QueryParseError, e.to_string()),
+        };
+        for (permission, resource) in access {
+            if !policy.allows(user, permission, &resource) {
+                return self.deny(user, "Query", permission, resource).await;
+            }
+        }
+
+        let result = if let Some(ref shards) = self.shards {
+            ShardedQueryEngine::new(shards.clone()).execute_parsed(&query, limit).await
+        } else if let Some(db) = self.database() {
+            // SQL writes would bypass consensus
+            if self.raft.is_some() && !matches!(query.operation, QueryOperation::Select | QueryOperation::VectorSearch) {
+                return Response::error(ErrorCode::InvalidRequest, "Only SELECT queries are supported in replicated mode");
+            }
+            QueryEngine::new(db.clone()).execute_parsed(&query, limit).await
+        } else {
+            return Response::error(ErrorCode::InternalError, "No storage configured");
+        };
+
+        match result {
+            Ok(result) => Response::QueryResult {
+                columns: result.columns,
+                rows: result.rows,
+                rows_affected: result.rows_affected,
+                execution_time_ms: result.execution_time_ms,
+            },
+            Err(e) => Response::error(ErrorCode::QueryExecutionError, e.to_string()),
+        }
+    }
+
+    /// Run `CREATE TRIGGER`/`DROP TRIGGER`; only admins may, once access
+    /// control is on
+    async fn handle_trigger_statement(&self, user: Option<&str>, statement: TriggerStatement) -> Response {
+        if !self.policy().await.allows(user, Permission::Admin, &Resource::All) {
+            return self.deny(user, "Query", Permission::Admin, Resource::All).await;
+        }
+        let Some(db) = self.database() else {
+            return Response::error(ErrorCode::InvalidRequest, "Triggers are not supported on sharded stores");
+        };
+
+        let start = Instant::now();
+        let manager = SchemaManager::new(db.clone());
+        let result = match self.raft {
+            Some(ref raft) => {
+                // The definition goes through consensus like any other node,
+                // and every replica picks it up as it applies
+                let command = match statement {
+                    TriggerStatement::Create(trigger) => {
+                        match manager.trigger_node(&trigger.name).await {
+                            Ok(None) => {}
+                            Ok(Some(_)) => {
+                                return Response::error(
+                                    ErrorCode::QueryExecutionError,
+                                    format!("Trigger already exists: {}", trigger.name),
+                                );
+                            }
+                            Err(e) => return Response::error(ErrorCode::InternalError, e.to_string()),
+                        }
+                        trigger.to_properties()
+                            .and_then(Value::from_json)
+                            .and_then(|props| ReplicationCommand::insert_node(&Node::new(TRIGGER_TYPE, props)))
+                    }
+                    TriggerStatement::Drop { name, if_exists } => match manager.trigger_node(&name).await {
+                        Ok(Some(node)) => ReplicationCommand::delete_node(&node.id),
+                        Ok(None) if if_exists => return Self::statement_done(start),
+                        Ok(None) => {
+                            return Response::error(ErrorCode::QueryExecutionError, format!("Trigger not found: {}", name));
+                        }
+                        Err(e) => return Response::error(ErrorCode::InternalError, e.to_string()),
+                    },
+                };
+                match self.propose(raft, command, ErrorCode::QueryExecutionError).await {
+                    Ok(()) => Ok(()),
+                    Err(response) => return response,
+                }
+            }
+            None => manager.execute_trigger_statement(statement).await.map(|_| ()),
+        };
+
+        match result {
+            Ok(()) => Self::statement_done(start),
+            Err(e) => Response::error(ErrorCode::QueryExecutionError, e.to_string()),
+        }
+    }
+
+    fn statement_done(start: Instant) -> Response {
+        Response::QueryResult {
+            columns: Vec::new(),
+            rows: Vec::new(),
+            rows_affected: 0,
+            execution_time_ms: start.elapsed().as_millis() as u64,
+        }
     }
 
-    async fn handle_query(&self, _sql: &str, _limit: Option<usize>) -> Response {
-        // TODO: Implement query execution
-        Response::error(ErrorCode::InternalError, "Query execution not implemented")
+    /// Run `GRANT`/`REVOKE`; only admins may, once access control is on
+    async fn handle_access_statement(&self, user: Option<&str>, statement: &AccessStatement) -> Response {
+        if !self.policy().await.allows(user, Permission::Admin, &Resource::All) {
+            return self.deny(user, "Query", Permission::Admin, Resource::All).await;
+        }
+        let Some(access) = self.access() else {
+            return Response::error(ErrorCode::InvalidRequest, "Server has no user accounts");
+        };
+
+        let start = std::time::Instant::now();
+        let result = match access.plan(statement).await {
+            // Grants change through the log like any other node, and every
+            // replica picks them up as it applies
+            Ok(Some(write)) => match self.raft {
+                Some(ref raft) if self.is_replicated(write.node_type()) => {
+                    let command = match write {
+                        AccessWrite::Insert(node) => ReplicationCommand::insert_node(&node),
+                        AccessWrite::Update { node, properties } => {
+                            Value::from_json(properties).and_then(|p| ReplicationCommand::update_node(&node.id, p))
+                        }
+                    };
+                    if let Err(response) = self.propose(raft, command, ErrorCode::QueryExecutionError).await {
+                        return response;
+                    }
+                    Ok(())
+                }
+                _ => access.apply(write).await,
+            },
+            Ok(None) => Ok(()),
+            Err(e) => Err(e),
+        };
+        self.reload_access();
+        match result {
+            Ok(()) => Response::QueryResult {
+                columns: Vec::new(),
+                rows: Vec::new(),
+                rows_affected: 0,
+                execution_time_ms: start.elapsed().as_millis() as u64,
+            },
+            Err(e) => Response::error(ErrorCode::QueryExecutionError, e.to_string()),
+        }
     }
 
     async fn handle_traverse(
         &self,
-        _start_id: &str,
-        _depth: u32,
-        _edge_types: Option<Vec<String>>,
+        start_id: &str,
+        depth: u32,
+        edge_types: Option<Vec<String>>,
     ) -> Response {
-        // TODO: Implement traversal
-        Response::error(ErrorCode::InternalError, "Traversal not implemented")
+        let result = if let Some(db) = self.database() {
+            traverse_database(db, start_id, depth, edge_types.as_deref()).await
+        } else if let Some(ref shards) = self.shards {
+            match NodeId::parse(start_id) {
+                Ok(id) => shards
+                    .traverse(&id, depth, edge_types.as_deref())
+                    .await
+                    .map(|t| (t.nodes, t.edges)),
+                Err(e) => return Response::error(ErrorCode::InvalidRequest, e.to_string()),
+            }
+        } else {
+            return Response::error(ErrorCode::InternalError, "No storage configured");
+        };
+
+        match result {
+            Ok((nodes, edges)) => Response::TraversalResult { nodes, edges, depth },
+            Err(e) => Response::error(ErrorCode::NodeNotFound, e.to_string()),
+        }
+    }
+
+    async fn handle_shortest_path(&self, from_id: &str, to_id: &str, max_depth: u32) -> Response {
+        let result = if let Some(db) = self.database() {
+            shortest_path_database(db, from_id, to_id, max_depth).await
+        } else if let Some(ref shards) = self.shards {
+            match (NodeId::parse(from_id), NodeId::parse(to_id)) {
+                (Ok(from), Ok(to)) => shards.shortest_path(&from, &to, max_depth).await,
+                (Err(e), _) | (_, Err(e)) => return Response::error(ErrorCode::InvalidRequest, e.to_string()),
+            }
+        } else {
+            return Response::error(ErrorCode::InternalError, "No storage configured");
+        };
+
+        match result {
+            Ok(path) => Response::Path(path),
+            Err(e) => Response::error(ErrorCode::InternalError, e.to_string()),
+        }
     }
 
     async fn handle_status(&self) -> Response {
-        if let Some(ref db) = self.db {
+        if let Some(db) = self.database() {
             match db.status().await {
                 Ok(status) => Response::Status {
                     name: status.name,
@@ -341,6 +1206,109 @@ impl RequestHandler {
     }
 }
 
+/// Members after a membership operation, or why it failed
+fn members_response(raft: &RaftNode, result: Result<()>) -> Response {
+    match result {
+        Ok(()) => Response::Members(raft.members()),
+        Err(e) => raft_error(e, ErrorCode::InvalidRequest),
+    }
+}
+
+/// Map a replication failure to a response, redirecting to the leader
+fn raft_error(e: anyhow::Error, code: ErrorCode) -> Response {
+    match e.downcast::<NotLeader>() {
+        Ok(not_leader) => Response::NotLeader {
+            leader_id: not_leader.leader_id,
+            leader_addr: not_leader.leader_addr,
+        },
+        Err(e) => Response::error(code, e.to_string()),
+    }
+}
+
+/// Breadth-first traversal over a single database
+async fn traverse_database(
+    db: &Database,
+    start_id: &str,
+    max_depth: u32,
+    edge_types: Option<&[String]>,
+) -> Result<(Vec<Node>, Vec<Edge>)> {
+    let root = db
+        .get_node(start_id)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Start node not found: {}", start_id))?;
+
+    let mut visited = HashSet::from([start_id.to_string()]);
+    let mut nodes = vec![root];
+    let mut edges = Vec::new();
+    let mut frontier = vec![start_id.to_string()];
+
+    for _ in 0..max_depth {
+        let mut next = Vec::new();
+        for id in &frontier {
+            for edge in db.get_edges_from(id, None).await? {
+                if edge_types.is_some_and(|types| !types.contains(&edge.edge_type)) {
+                    continue;
+                }
+                let to = edge.to.to_string();
+                if visited.insert(to.clone()) {
+                    nodes.extend(db.get_node(&to).await?);
+                    next.push(to);
+                }
+                edges.push(edge);
+            }
+        }
+        frontier = next;
+    }
+
+    Ok((nodes, edges))
+}
+
+/// Shortest path of at most `max_depth` edges over a single database
+async fn shortest_path_database(
+    db: &Database,
+    from_id: &str,
+    to_id: &str,
+    max_depth: u32,
+) -> Result<Option<Vec<Node>>> {
+    let mut parents: HashMap<String, String> = HashMap::new();
+    let mut visited = HashSet::from([from_id.to_string()]);
+    let mut frontier = vec![from_id.to_string()];
+    let mut found = from_id == to_id;
+
+    for _ in 0..max_depth {
+        if found || frontier.is_empty() {
+            break;
+        }
+        let mut next = Vec::new();
+        for id in &frontier {
+            for edge in db.get_edges_from(id, None).await? {
+                let to = edge.to.to_string();
+                if visited.insert(to.clone()) {
+                    found |= to == to_id;
+                    parents.insert(to.clone(), id.clone());
+                    next.push(to);
+                }
+            }
+        }
+        frontier = next;
+    }
+
+    if !found {
+        return Ok(None);
+    }
+
+    let mut path = vec![to_id.to_string()];
+    while let Some(parent) = parents.get(path.last().unwrap()) {
+        path.push(parent.clone());
+    }
+
+    let mut nodes = Vec::with_capacity(path.len());
+    for id in path.iter().rev() {
+        nodes.extend(db.get_node(id).await?);
+    }
+    Ok(Some(nodes))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -400,4 +1368,288 @@ mod tests {
         let response = handler.handle(Request::CommitTransaction { tx_id }).await;
         assert!(matches!(response, Response::TransactionCommitted));
     }
+
+    #[tokio::test]
+    async fn test_handler_trigger_statements() {
+        let temp = TempDir::new().unwrap();
+        let db = Database::create(temp.path(), "test").await.unwrap();
+        let handler = RequestHandler::new(db);
+        let sql = |sql: &str| Request::Query { sql: sql.to_string(), limit: None };
+        let insert = |properties: serde_json::Value| Request::InsertNode {
+            node_type: "user".to_string(),
+            properties: Value::from_json(properties).unwrap(),
+        };
+
+        let response = handler.handle(sql("CREATE TRIGGER rules BEFORE INSERT ON user DEFAULT role = 'member', REQUIRE name")).await;
+        assert!(!response.is_error(), "{:?}", response);
+        let response = handler.handle(sql("CREATE TRIGGER rules BEFORE INSERT ON user REQUIRE email")).await;
+        assert!(matches!(response, Response::Error { code: ErrorCode::QueryExecutionError, .. }));
+        let response = handler.handle(sql("CREATE TRIGGER broken AFTER INSERT ON user")).await;
+        assert!(matches!(response, Response::Error { code: ErrorCode::QueryParseError, .. }));
+
+        match handler.handle(insert(serde_json::json!({ "name": "Ada" }))).await {
+            Response::Node(node) => assert_eq!(node.get("role").and_then(Value::as_str), Some("member")),
+            other => panic!("Expected Node response, got {:?}", other),
+        }
+        assert!(handler.handle(insert(serde_json::json!({}))).await.is_error());
+
+        assert!(!handler.handle(sql("DROP TRIGGER rules")).await.is_error());
+        assert!(handler.handle(sql("DROP TRIGGER rules")).await.is_error());
+        assert!(!handler.handle(sql("DROP TRIGGER IF EXISTS rules")).await.is_error());
+        assert!(matches!(handler.handle(insert(serde_json::json!({}))).await, Response::Node(_)));
+    }
+
+    #[tokio::test]
+    async fn test_handler_trigger_nodes_reserved() {
+        use crate::schema::DEAD_LETTER_TYPE;
+        use crate::server::{AccessStore, ADMIN_ROLE};
+
+        let temp = TempDir::new().unwrap();
+        let db = Database::create(temp.path().join("data"), "data").await.unwrap();
+        let accounts = Database::create(temp.path().join("accounts"), "accounts").await.unwrap();
+        let users = UserStore::new(&accounts);
+        users.create_user("root", "pw").await.unwrap();
+        users.create_user("ada", "pw").await.unwrap();
+        AccessStore::new(&accounts).assign_role("root", ADMIN_ROLE).await.unwrap();
+        let handler = RequestHandler::new(db.clone()).with_accounts(accounts);
+
+        let sql = |sql: &str| Request::Query { sql: sql.to_string(), limit: None };
+        let denied = |response: &Response| {
+            matches!(response, Response::Error { code: ErrorCode::PermissionDenied, .. })
+        };
+        let root = Some("root");
+        let ada = Some("ada");
+        for statement in ["GRANT ALL ON * TO writer", "GRANT ROLE writer TO ada"] {
+            let response = handler.handle_as(root, sql(statement)).await;
+            assert!(!response.is_error(), "{}: {:?}", statement, response);
+        }
+
+        // Write access to every type doesn't reach trigger definitions or dead letters
+        for node_type in [TRIGGER_TYPE, DEAD_LETTER_TYPE] {
+            let insert = Request::InsertNode {
+                node_type: node_type.to_string(),
+                properties: Value::from_json(serde_json::json!({"name": "leak"})).unwrap(),
+            };
+            assert!(denied(&handler.handle_as(ada, insert).await));
+            let read = Request::GetNodesByType { node_type: node_type.to_string(), limit: None };
+            assert!(denied(&handler.handle_as(ada, read).await));
+        }
+        let response = handler.handle_as(ada, sql("INSERT INTO __trigger__ (name) VALUES ('leak')")).await;
+        assert!(denied(&response));
+        assert!(denied(&handler.handle_as(ada, sql("CREATE TRIGGER audit BEFORE INSERT ON user REQUIRE name")).await));
+        assert!(db.get_all_by_type(TRIGGER_TYPE, None).await.unwrap().is_empty());
+
+        // Only the admin-checked statement installs one
+        let response = handler.handle_as(root, sql("CREATE TRIGGER audit BEFORE INSERT ON user REQUIRE name")).await;
+        assert!(!response.is_error(), "{:?}", response);
+        assert_eq!(db.get_all_by_type(TRIGGER_TYPE, None).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_handler_access_control() {
+        use crate::server::{AccessStore, ADMIN_ROLE};
+
+        let temp = TempDir::new().unwrap();
+        let shards = ShardManager::new(crate::distributed::ShardConfig {
+            num_shards: 2,
+            virtual_nodes: 100,
+            base_path: temp.path().join("shards"),
+        }).await.unwrap();
+        let accounts = Database::create(temp.path().join("accounts"), "accounts").await.unwrap();
+        let users = UserStore::new(&accounts);
+        users.create_user("root", "pw").await.unwrap();
+        users.create_user("ada", "pw").await.unwrap();
+        AccessStore::new(&accounts).assign_role("root", ADMIN_ROLE).await.unwrap();
+        let handler = RequestHandler::with_shards(shards).with_accounts(accounts);
+
+        let sql = |sql: &str| Request::Query { sql: sql.to_string(), limit: None };
+        let denied = |response: &Response| {
+            matches!(response, Response::Error { code: ErrorCode::PermissionDenied, .. })
+        };
+        let insert = |node_type: &str| Request::InsertNode {
+            node_type: node_type.to_string(),
+            properties: Value::from_json(serde_json::json!({"name": "x"})).unwrap(),
+        };
+
+        let root = Some("root");
+        let ada = Some("ada");
+        for statement in ["GRANT READ ON user TO analyst", "GRANT ROLE analyst TO ada"] {
+            let response = handler.handle_as(root, sql(statement)).await;
+            assert!(!response.is_error(), "{}: {:?}", statement, response);
+        }
+
+        let mut ids = Vec::new();
+        for node_type in ["user", "user", "post"] {
+            match handler.handle_as(root, insert(node_type)).await {
+                Response::Node(node) => ids.push(node.id.to_string()),
+                other => panic!("Expected Node response, got {:?}", other),
+            }
+        }
+        let edge = handler.handle_as(root, Request::CreateEdge {
+            from_id: ids[0].clone(),
+            to_id: ids[1].clone(),
+            edge_type: "follows".to_string(),
+            properties: None,
+        }).await;
+        assert!(matches!(edge, Response::Edge(_)));
+
+        // Reads of granted types pass, everything else is refused
+        let response = handler.handle_as(ada, Request::GetNodesByType { node_type: "user".into(), limit: None }).await;
+        assert!(matches!(response, Response::Nodes(ref nodes) if nodes.len() == 2));
+        assert!(denied(&handler.handle_as(ada, Request::GetNode { id: ids[2].clone() }).await));
+        assert!(denied(&handler.handle_as(ada, insert("user")).await));
+        assert!(denied(&handler.handle_as(ada, Request::DeleteNode { id: ids[0].clone() }).await));
+        assert!(denied(&handler.handle_as(None, Request::GetNodesByType { node_type: "user".into(), limit: None }).await));
+
+        // Queries are checked against every type the plan touches
+        let response = handler.handle_as(ada, sql("SELECT * FROM user")).await;
+        assert!(matches!(response, Response::QueryResult { ref rows, .. } if rows.len() == 2));
+        assert!(denied(&handler.handle_as(ada, sql("SELECT * FROM post")).await));
+        assert!(denied(&handler.handle_as(ada, sql("DELETE FROM user")).await));
+        assert!(denied(&handler.handle_as(ada, sql("GRANT ALL ON * TO analyst")).await));
+
+        // Edges of types without a grant are dropped from results
+        let response = handler.handle_as(ada, Request::GetEdgesFrom { node_id: ids[0].clone(), edge_type: None }).await;
+        assert!(matches!(response, Response::Edges(ref edges) if edges.is_empty()));
+        handler.handle_as(root, sql("GRANT READ ON EDGE follows TO analyst")).await;
+        let response = handler.handle_as(ada, Request::GetEdgesFrom { node_id: ids[0].clone(), edge_type: None }).await;
+        assert!(matches!(response, Response::Edges(ref edges) if edges.len() == 1));
+
+        let denials = handler.access().unwrap().denials(None).await.unwrap();
+        assert_eq!(denials.len(), 7);
+        assert!(denials.iter().any(|d| d.operation == "DeleteNode" && d.user.as_deref() == Some("ada")));
+        assert!(denials.iter().any(|d| d.user.is_none()));
+    }
+
+    #[tokio::test]
+    async fn test_handler_sharded_graph() {
+        let temp = TempDir::new().unwrap();
+        let shards = ShardManager::new(crate::distributed::ShardConfig {
+            num_shards: 4,
+            virtual_nodes: 100,
+            base_path: temp.path().to_path_buf(),
+        }).await.unwrap();
+        let handler = RequestHandler::with_shards(shards);
+
+        let mut ids = Vec::new();
+        for i in 0..6 {
+            let response = handler.handle(Request::InsertNode {
+                node_type: "user".to_string(),
+                properties: Value::from_json(serde_json::json!({"n": i})).unwrap(),
+            }).await;
+            match response {
+                Response::Node(node) => ids.push(node.id.to_string()),
+                _ => panic!("Expected Node response"),
+            }
+        }
+
+        let mut edge_ids = Vec::new();
+        for pair in ids.windows(2) {
+            let response = handler.handle(Request::CreateEdge {
+                from_id: pair[0].clone(),
+                to_id: pair[1].clone(),
+                edge_type: "next".to_string(),
+                properties: None,
+            }).await;
+            match response {
+                Response::Edge(edge) => edge_ids.push(edge.id.to_string()),
+                _ => panic!("Expected Edge response"),
+            }
+        }
+
+        let response = handler.handle(Request::GetEdgesTo { node_id: ids[3].clone(), edge_type: None }).await;
+        assert!(matches!(response, Response::Edges(edges) if edges.len() == 1));
+
+        let response = handler.handle(Request::Traverse { start_id: ids[0].clone(), depth: 10, edge_types: None }).await;
+        assert!(matches!(response, Response::TraversalResult { nodes, edges, .. } if nodes.len() == 6 && edges.len() == 5));
+
+        let response = handler.handle(Request::DeleteEdge { edge_id: edge_ids[2].clone() }).await;
+        assert!(matches!(response, Response::Ok));
+
+        let response = handler.handle(Request::ShortestPath { from_id: ids[0].clone(), to_id: ids[2].clone(), max_depth: 5 }).await;
+        assert!(matches!(response, Response::Path(Some(path)) if path.len() == 3));
+        let response = handler.handle(Request::ShortestPath { from_id: ids[0].clone(), to_id: ids[5].clone(), max_depth: 5 }).await;
+        assert!(matches!(response, Response::Path(None)));
+
+        let response = handler.handle(Request::Query {
+            sql: "SELECT n FROM user WHERE n > 1 ORDER BY n DESC".to_string(),
+            limit: Some(2),
+        }).await;
+        match response {
+            Response::QueryResult { columns, rows, .. } => {
+                let n = columns.iter().position(|c| c == "n").unwrap();
+                let values: Vec<Value> = rows.iter().map(|r| r[n].clone()).collect();
+                assert_eq!(values, vec![Value::Int(5), Value::Int(4)]);
+            }
+            other => panic!("Expected QueryResult response, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replicated_grants_reach_followers() {
+        use crate::distributed::{InProcessNetwork, ReplicaConfig, ReplicaSet};
+        use std::time::Duration;
+
+        let temp = TempDir::new().unwrap();
+        let ids = ["a", "b"];
+        let network = InProcessNetwork::new();
+        let mut handlers = Vec::new();
+        for id in ids {
+            let dir = temp.path().join(id);
+            let db = Database::create(&dir, id).await.unwrap();
+            let config = ReplicaConfig {
+                node_id: id.to_string(),
+                peers: ids.iter().filter(|p| **p != id).map(|p| p.to_string()).collect(),
+                election_timeout_ms: (100, 200),
+                heartbeat_interval_ms: 10,
+                ..Default::default()
+            };
+            let replica = ReplicaSet::open(config, dir.join(".aresadb/raft")).unwrap();
+            let raft = RaftNode::start(replica, db, network.transport(id));
+            network.register(id, raft.inbox());
+            let accounts = Database::create(dir.join(".aresadb/accounts"), id).await.unwrap();
+            handlers.push(RequestHandler::with_replication(raft).with_accounts(accounts));
+        }
+
+        let mut leader = None;
+        for _ in 0..300 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            leader = handlers.iter().position(|h| h.raft().unwrap().replica().is_leader());
+            if leader.is_some() {
+                break;
+            }
+        }
+        let leader = leader.expect("leader elected");
+        let (leader, follower) = (&handlers[leader], &handlers[1 - leader]);
+
+        // An account on the follower turns access control on there
+        UserStore::new(follower.accounts.as_ref().unwrap()).create_user("ada", "secret").await.unwrap();
+        follower.reload_access();
+        let read = || Request::GetNodesByType { node_type: "item".to_string(), limit: None };
+        let response = follower.handle_as(Some("ada"), read()).await;
+        assert!(matches!(response, Response::Error { code: ErrorCode::PermissionDenied, .. }));
+
+        let grant = Request::Query { sql: "GRANT READ ON item TO public".to_string(), limit: None };
+        let response = leader.handle(grant).await;
+        assert!(!response.is_error(), "{:?}", response);
+
+        let mut allowed = false;
+        for _ in 0..300 {
+            if !follower.handle_as(Some("ada"), read()).await.is_error() {
+                allowed = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(allowed, "grant never reached the follower");
+
+        // Denials stay in the follower's own accounts, out of the log
+        assert!(!follower.access().unwrap().denials(None).await.unwrap().is_empty());
+        let replicated = follower.raft().unwrap().database();
+        assert!(replicated.get_all_by_type(AUDIT_TYPE, None).await.unwrap().is_empty());
+
+        for handler in &handlers {
+            handler.raft().unwrap().shutdown();
+        }
+    }
 }
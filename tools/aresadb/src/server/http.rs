@@ -0,0 +1,1379 @@
+//! HTTP/JSON API
+//!
+//! Serves the requests of the binary protocol as JSON over HTTP/1.1, for
+//! clients that cannot speak its framing. Every route maps onto a
+//! [`Request`] run by the shared [`RequestHandler`], so authentication,
+//! access control, replication and sharding behave exactly as they do on
+//! the TCP port. The same route table generates the OpenAPI document
+//! served at `/openapi.json`. Prometheus metrics are served at `/metrics`,
+//! like the OpenAPI document without authentication.
+//!
+//! Lists, query results and traversals are a single JSON document, or one
+//! JSON value per line (NDJSON) when the client sends
+//! `Accept: application/x-ndjson`. NDJSON bodies are written as they are
+//! encoded instead of being built up in memory first.
+
+use anyhow::{Result, Context};
+use base64::Engine;
+use bytes::Bytes;
+use hyper::header::{self, HeaderValue};
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Method, StatusCode};
+use moka::sync::Cache;
+use ring::digest;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value as Json};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn, debug};
+
+use super::auth::Credentials;
+use super::handler::RequestHandler;
+use super::pool::Connections;
+use super::protocol::{ErrorCode, HandshakeReply, Request, Response, SearchHit};
+use super::tls::{Stream, TlsConfig};
+use crate::metrics;
+use crate::storage::{DistanceMetric, Edge, Node, Value};
+
+/// Default limit on request bodies
+pub const DEFAULT_MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+/// Media type of newline-delimited JSON
+const NDJSON: &str = "application/x-ndjson";
+
+/// NDJSON is sent in chunks of about this size
+const NDJSON_CHUNK_SIZE: usize = 64 * 1024;
+
+/// How long verified credentials are trusted without checking them again
+///
+/// A password change or revoked token takes up to this long to apply to
+/// HTTP clients already using the old one.
+const CREDENTIAL_TTL: Duration = Duration::from_secs(60);
+
+/// Most verified credentials remembered at once
+const MAX_VERIFIED_CREDENTIALS: u64 = 10_000;
+
+/// HTTP listener configuration
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    /// Address to bind to
+    pub bind_addr: SocketAddr,
+    /// Serve HTTPS instead of plain HTTP
+    pub tls: Option<TlsConfig>,
+    /// Reject requests without credentials
+    pub require_auth: bool,
+    /// Largest request body accepted, in bytes
+    pub max_body_size: usize,
+    /// Seconds allowed for the TLS handshake and to receive a request body
+    pub read_timeout_secs: u64,
+    /// Seconds a connection may wait for the headers of its next request
+    /// before it is closed
+    pub idle_timeout_secs: u64,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            bind_addr: "127.0.0.1:7480".parse().unwrap(),
+            tls: None,
+            require_auth: false,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            read_timeout_secs: 30,
+            idle_timeout_secs: 300,
+        }
+    }
+}
+
+impl HttpConfig {
+    fn read_timeout(&self) -> Duration {
+        Duration::from_secs(self.read_timeout_secs)
+    }
+
+    fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.idle_timeout_secs)
+    }
+}
+
+/// HTTP/JSON front end for a [`RequestHandler`]
+pub struct HttpServer {
+    config: HttpConfig,
+    api: Arc<Api>,
+}
+
+impl HttpServer {
+    /// Serve `handler`, typically shared with a [`super::Server`]
+    pub fn new(handler: Arc<RequestHandler>, config: HttpConfig) -> Self {
+        let api = Arc::new(Api {
+            handler,
+            require_auth: config.require_auth,
+            max_body_size: config.max_body_size,
+            read_timeout: config.read_timeout(),
+            verified: Cache::builder()
+                .max_capacity(MAX_VERIFIED_CREDENTIALS)
+                .time_to_live(CREDENTIAL_TTL)
+                .build(),
+        });
+        Self { config, api }
+    }
+
+    /// Accept connections until the task is dropped
+    ///
+    /// Use [`super::Server::with_http`] instead to share the server's
+    /// connection limit and shutdown.
+    pub async fn run(&self) -> Result<()> {
+        let listener = self.bind().await?;
+        self.serve(listener, CancellationToken::new(), Arc::new(Connections::unlimited())).await
+    }
+
+    /// Bind the listening socket
+    pub(crate) async fn bind(&self) -> Result<(TcpListener, Option<TlsAcceptor>)> {
+        let acceptor = self.config.tls.as_ref().map(TlsConfig::acceptor).transpose()?;
+        let listener = TcpListener::bind(&self.config.bind_addr)
+            .await
+            .context("Failed to bind HTTP listener")?;
+
+        info!(
+            "HTTP API listening on {}://{}",
+            if acceptor.is_some() { "https" } else { "http" },
+            self.config.bind_addr
+        );
+        Ok((listener, acceptor))
+    }
+
+    /// Accept connections as part of `connections` until `shutdown`
+    ///
+    /// On shutdown, open connections close once their current request
+    /// has been answered.
+    pub(crate) async fn serve(
+        &self,
+        (listener, acceptor): (TcpListener, Option<TlsAcceptor>),
+        shutdown: CancellationToken,
+        connections: Arc<Connections>,
+    ) -> Result<()> {
+        loop {
+            let accepted = tokio::select! {
+                _ = shutdown.cancelled() => return Ok(()),
+                accepted = listener.accept() => accepted,
+            };
+            let (stream, addr) = match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("HTTP accept error: {}", e);
+                    continue;
+                }
+            };
+            let Some(slot) = connections.reserve() else {
+                warn!("Connection limit reached, rejecting HTTP client {}", addr);
+                continue;
+            };
+            let _ = stream.set_nodelay(true);
+            let acceptor = acceptor.clone();
+            let api = Arc::clone(&self.api);
+            let config = self.config.clone();
+            let shutdown = shutdown.clone();
+
+            connections.spawn(slot, async move {
+                let stream: Box<dyn Stream> = match acceptor {
+                    Some(acceptor) => match timeout(config.read_timeout(), acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => Box::new(stream),
+                        Ok(Err(e)) => {
+                            debug!("TLS handshake failed for {}: {}", addr, e);
+                            return;
+                        }
+                        Err(_) => {
+                            debug!("Timed out in TLS handshake with {}", addr);
+                            return;
+                        }
+                    },
+                    None => Box::new(stream),
+                };
+
+                let service = service_fn(move |request| {
+                    let api = Arc::clone(&api);
+                    async move { Ok::<_, Infallible>(api.serve(request).await) }
+                });
+                // The header timeout also runs while waiting for the next
+                // request, so it closes idle keep-alive connections
+                let connection = Http::new()
+                    .http1_only(true)
+                    .http1_header_read_timeout(config.idle_timeout())
+                    .serve_connection(stream, service);
+                tokio::pin!(connection);
+                let result = tokio::select! {
+                    result = connection.as_mut() => result,
+                    _ = shutdown.cancelled() => {
+                        connection.as_mut().graceful_shutdown();
+                        connection.await
+                    }
+                };
+                if let Err(e) = result {
+                    debug!("HTTP connection error from {}: {}", addr, e);
+                }
+            });
+        }
+    }
+}
+
+/// What a route does; each maps onto one [`Request`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operation {
+    Status,
+    InsertNode,
+    ListNodes,
+    GetNode,
+    UpdateNode,
+    DeleteNode,
+    ListEdges,
+    CreateEdge,
+    DeleteEdge,
+    Query,
+    Search,
+    Traverse,
+    ShortestPath,
+}
+
+/// Shape of a route's successful response
+#[derive(Debug, Clone, Copy)]
+enum Reply {
+    /// No body
+    Empty,
+    /// One instance of a schema
+    One(&'static str),
+    /// A list of a schema, streamable as NDJSON
+    Many(&'static str),
+}
+
+/// A query string parameter
+struct Param {
+    name: &'static str,
+    kind: &'static str,
+    required: bool,
+    description: &'static str,
+}
+
+/// One endpoint: its method and path, the request it makes and how it is
+/// documented
+struct Route {
+    method: Method,
+    path: &'static str,
+    operation: Operation,
+    id: &'static str,
+    summary: &'static str,
+    query: &'static [Param],
+    body: Option<&'static str>,
+    status: StatusCode,
+    reply: Reply,
+}
+
+const ROUTES: &[Route] = &[
+    Route {
+        method: Method::GET,
+        path: "/v1/status",
+        operation: Operation::Status,
+        id: "getStatus",
+        summary: "Database status and size",
+        query: &[],
+        body: None,
+        status: StatusCode::OK,
+        reply: Reply::One("Status"),
+    },
+    Route {
+        method: Method::POST,
+        path: "/v1/nodes",
+        operation: Operation::InsertNode,
+        id: "insertNode",
+        summary: "Insert a node",
+        query: &[],
+        body: Some("NewNode"),
+        status: StatusCode::CREATED,
+        reply: Reply::One("Node"),
+    },
+    Route {
+        method: Method::GET,
+        path: "/v1/nodes",
+        operation: Operation::ListNodes,
+        id: "listNodes",
+        summary: "List the nodes of a type",
+        query: &[
+            Param { name: "type", kind: "string", required: true, description: "Node type" },
+            Param { name: "limit", kind: "integer", required: false, description: "Maximum number of nodes" },
+        ],
+        body: None,
+        status: StatusCode::OK,
+        reply: Reply::Many("Node"),
+    },
+    Route {
+        method: Method::GET,
+        path: "/v1/nodes/{id}",
+        operation: Operation::GetNode,
+        id: "getNode",
+        summary: "Get a node",
+        query: &[],
+        body: None,
+        status: StatusCode::OK,
+        reply: Reply::One("Node"),
+    },
+    Route {
+        method: Method::PATCH,
+        path: "/v1/nodes/{id}",
+        operation: Operation::UpdateNode,
+        id: "updateNode",
+        summary: "Merge properties into a node",
+        query: &[],
+        body: Some("NodeUpdate"),
+        status: StatusCode::OK,
+        reply: Reply::One("Node"),
+    },
+    Route {
+        method: Method::DELETE,
+        path: "/v1/nodes/{id}",
+        operation: Operation::DeleteNode,
+        id: "deleteNode",
+        summary: "Delete a node",
+        query: &[],
+        body: None,
+        status: StatusCode::NO_CONTENT,
+        reply: Reply::Empty,
+    },
+    Route {
+        method: Method::GET,
+        path: "/v1/nodes/{id}/edges",
+        operation: Operation::ListEdges,
+        id: "listEdges",
+        summary: "List the edges of a node",
+        query: &[
+            Param { name: "direction", kind: "string", required: false, description: "`out` (default) or `in`" },
+            Param { name: "type", kind: "string", required: false, description: "Edge type" },
+        ],
+        body: None,
+        status: StatusCode::OK,
+        reply: Reply::Many("Edge"),
+    },
+    Route {
+        method: Method::POST,
+        path: "/v1/edges",
+        operation: Operation::CreateEdge,
+        id: "createEdge",
+        summary: "Create an edge",
+        query: &[],
+        body: Some("NewEdge"),
+        status: StatusCode::CREATED,
+        reply: Reply::One("Edge"),
+    },
+    Route {
+        method: Method::DELETE,
+        path: "/v1/edges/{id}",
+        operation: Operation::DeleteEdge,
+        id: "deleteEdge",
+        summary: "Delete an edge",
+        query: &[],
+        body: None,
+        status: StatusCode::NO_CONTENT,
+        reply: Reply::Empty,
+    },
+    Route {
+        method: Method::POST,
+        path: "/v1/query",
+        operation: Operation::Query,
+        id: "query",
+        summary: "Run a SQL statement; NDJSON streams one object per row",
+        query: &[],
+        body: Some("Query"),
+        status: StatusCode::OK,
+        reply: Reply::One("QueryResult"),
+    },
+    Route {
+        method: Method::POST,
+        path: "/v1/search",
+        operation: Operation::Search,
+        id: "similaritySearch",
+        summary: "Nodes whose embedding is nearest to a vector",
+        query: &[],
+        body: Some("Search"),
+        status: StatusCode::OK,
+        reply: Reply::Many("SearchHit"),
+    },
+    Route {
+        method: Method::POST,
+        path: "/v1/traverse",
+        operation: Operation::Traverse,
+        id: "traverse",
+        summary: "Nodes and edges reachable from a node; NDJSON streams them one per line",
+        query: &[],
+        body: Some("Traverse"),
+        status: StatusCode::OK,
+        reply: Reply::One("Traversal"),
+    },
+    Route {
+        method: Method::GET,
+        path: "/v1/path",
+        operation: Operation::ShortestPath,
+        id: "shortestPath",
+        summary: "Nodes along the shortest path between two nodes",
+        query: &[
+            Param { name: "from", kind: "string", required: true, description: "Start node ID" },
+            Param { name: "to", kind: "string", required: true, description: "End node ID" },
+            Param { name: "max_depth", kind: "integer", required: false, description: "Longest path searched (default 6)" },
+        ],
+        body: None,
+        status: StatusCode::OK,
+        reply: Reply::Many("Node"),
+    },
+];
+
+/// Request bodies
+#[derive(Deserialize)]
+struct NewNode {
+    #[serde(rename = "type")]
+    node_type: String,
+    #[serde(default)]
+    properties: Option<Json>,
+}
+
+#[derive(Deserialize)]
+struct NodeUpdate {
+    properties: Json,
+}
+
+#[derive(Deserialize)]
+struct NewEdge {
+    from: String,
+    to: String,
+    #[serde(rename = "type")]
+    edge_type: String,
+    #[serde(default)]
+    properties: Option<Json>,
+}
+
+#[derive(Deserialize)]
+struct QueryBody {
+    sql: String,
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct SearchBody {
+    #[serde(rename = "type")]
+    node_type: String,
+    field: String,
+    vector: Vec<f32>,
+    #[serde(default = "default_k")]
+    k: usize,
+    #[serde(default)]
+    metric: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TraverseBody {
+    start: String,
+    #[serde(default = "default_depth")]
+    depth: u32,
+    #[serde(default)]
+    edge_types: Option<Vec<String>>,
+}
+
+fn default_k() -> usize {
+    10
+}
+
+fn default_depth() -> u32 {
+    3
+}
+
+/// An error reply
+struct HttpError {
+    status: StatusCode,
+    code: String,
+    message: String,
+}
+
+impl HttpError {
+    fn new(status: StatusCode, code: impl Into<String>, message: impl Into<String>) -> Self {
+        Self { status, code: code.into(), message: message.into() }
+    }
+
+    fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "InvalidRequest", message)
+    }
+
+    fn from_code(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self::new(status_of(code), format!("{:?}", code), message)
+    }
+
+    fn into_response(self) -> hyper::Response<Body> {
+        json_response(self.status, &json!({ "error": { "code": self.code, "message": self.message } }))
+    }
+}
+
+/// HTTP status for a protocol error
+fn status_of(code: ErrorCode) -> StatusCode {
+    match code {
+        ErrorCode::InvalidRequest | ErrorCode::QueryParseError => StatusCode::BAD_REQUEST,
+        ErrorCode::NodeNotFound | ErrorCode::EdgeNotFound => StatusCode::NOT_FOUND,
+        ErrorCode::QueryExecutionError => StatusCode::UNPROCESSABLE_ENTITY,
+        ErrorCode::TransactionError => StatusCode::CONFLICT,
+        ErrorCode::PermissionDenied => StatusCode::FORBIDDEN,
+        ErrorCode::AuthenticationFailed => StatusCode::UNAUTHORIZED,
+        ErrorCode::ServerOverloaded | ErrorCode::ServerBusy => StatusCode::SERVICE_UNAVAILABLE,
+        ErrorCode::FrameTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+        ErrorCode::RequestTimeout => StatusCode::GATEWAY_TIMEOUT,
+        ErrorCode::Unknown | ErrorCode::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// State shared by every HTTP connection
+struct Api {
+    handler: Arc<RequestHandler>,
+    require_auth: bool,
+    max_body_size: usize,
+    /// Time allowed to receive a request body
+    read_timeout: Duration,
+    /// Users of recently verified `Authorization` headers, by digest, so a
+    /// client sending its password with every request is not hashed anew
+    verified: Cache<Vec<u8>, String>,
+}
+
+impl Api {
+    async fn serve(&self, request: hyper::Request<Body>) -> hyper::Response<Body> {
+        match self.try_serve(request).await {
+            Ok(response) => response,
+            Err(e) => e.into_response(),
+        }
+    }
+
+    async fn try_serve(&self, request: hyper::Request<Body>) -> std::result::Result<hyper::Response<Body>, HttpError> {
+        let path = request.uri().path().to_string();
+        if path == "/openapi.json" && request.method() == Method::GET {
+            return Ok(json_response(StatusCode::OK, &openapi()));
+        }
+        if path == "/metrics" && request.method() == Method::GET {
+            let mut response = hyper::Response::new(Body::from(self.handler.metrics().await));
+            response.headers_mut().insert(header::CONTENT_TYPE, HeaderValue::from_static(metrics::CONTENT_TYPE));
+            return Ok(response);
+        }
+
+        let (route, params) = find_route(request.method(), &path)?;
+
+        let credentials = credentials(&request)?;
+        let key = credentials.as_ref().and_then(|_| authorization_digest(&request));
+        let cached = key.as_ref().and_then(|key| self.verified.get(key));
+        let user = match cached {
+            Some(user) => Ok(Some(user)),
+            None => self.handler.authenticate(credentials.as_ref(), self.require_auth).await,
+        };
+        let user = match user {
+            Ok(user) => {
+                if let (Some(key), Some(user)) = (key, &user) {
+                    self.verified.insert(key, user.clone());
+                }
+                user
+            }
+            Err(HandshakeReply::Rejected { code, message }) => {
+                let mut response = HttpError::from_code(code, message).into_response();
+                if code == ErrorCode::AuthenticationFailed {
+                    response
+                        .headers_mut()
+                        .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer, Basic"));
+                }
+                return Ok(response);
+            }
+            Err(HandshakeReply::Welcome(_)) => None,
+        };
+
+        let query: HashMap<String, String> = request
+            .uri()
+            .query()
+            .map(|q| url::form_urlencoded::parse(q.as_bytes()).into_owned().collect())
+            .unwrap_or_default();
+        let ndjson = accepts_ndjson(&request);
+        let body = match route.body {
+            Some(_) => timeout(self.read_timeout, read_body(request.into_body(), self.max_body_size))
+                .await
+                .map_err(|_| {
+                    HttpError::new(StatusCode::REQUEST_TIMEOUT, "InvalidRequest", "Timed out reading the request body")
+                })??,
+            None => Bytes::new(),
+        };
+
+        let call = build_request(route.operation, &params, &query, &body)?;
+        let response = self.handler.handle_as(user.as_deref(), call).await;
+        render(route, response, ndjson)
+    }
+}
+
+/// Match a method and path against the route table
+fn find_route(method: &Method, path: &str) -> std::result::Result<(&'static Route, HashMap<&'static str, String>), HttpError> {
+    let mut path_matched = false;
+    for route in ROUTES {
+        if let Some(params) = match_path(route.path, path) {
+            if route.method == method {
+                return Ok((route, params));
+            }
+            path_matched = true;
+        }
+    }
+
+    if path_matched {
+        Err(HttpError::new(StatusCode::METHOD_NOT_ALLOWED, "MethodNotAllowed", format!("{} is not allowed on {}", method, path)))
+    } else {
+        Err(HttpError::new(StatusCode::NOT_FOUND, "NotFound", format!("No route for {}", path)))
+    }
+}
+
+/// Path parameters if `path` fits `template`
+fn match_path(template: &'static str, path: &str) -> Option<HashMap<&'static str, String>> {
+    let mut params = HashMap::new();
+    let mut segments = path.trim_end_matches('/').split('/');
+
+    for expected in template.split('/') {
+        let segment = segments.next()?;
+        match expected.strip_prefix('{').and_then(|p| p.strip_suffix('}')) {
+            Some(name) if !segment.is_empty() => {
+                params.insert(name, segment.to_string());
+            }
+            Some(_) => return None,
+            None if expected == segment => {}
+            None => return None,
+        }
+    }
+
+    segments.next().is_none().then_some(params)
+}
+
+/// Credentials from the `Authorization` header, `Bearer` or `Basic`
+fn credentials(request: &hyper::Request<Body>) -> std::result::Result<Option<Credentials>, HttpError> {
+    let Some(value) = request.headers().get(header::AUTHORIZATION) else {
+        return Ok(None);
+    };
+    let invalid = || HttpError::from_code(ErrorCode::AuthenticationFailed, "Invalid Authorization header");
+
+    let value = value.to_str().map_err(|_| invalid())?;
+    let (scheme, value) = value.split_once(' ').ok_or_else(invalid)?;
+    let value = value.trim();
+
+    if scheme.eq_ignore_ascii_case("bearer") {
+        Ok(Some(Credentials::token(value)))
+    } else if scheme.eq_ignore_ascii_case("basic") {
+        let decoded = base64::engine::general_purpose::STANDARD.decode(value).map_err(|_| invalid())?;
+        let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+        let (username, password) = decoded.split_once(':').ok_or_else(invalid)?;
+        Ok(Some(Credentials::password(username, password)))
+    } else {
+        Err(invalid())
+    }
+}
+
+/// SHA-256 of the `Authorization` header, keying verified credentials
+fn authorization_digest(request: &hyper::Request<Body>) -> Option<Vec<u8>> {
+    let value = request.headers().get(header::AUTHORIZATION)?;
+    Some(digest::digest(&digest::SHA256, value.as_bytes()).as_ref().to_vec())
+}
+
+fn accepts_ndjson(request: &hyper::Request<Body>) -> bool {
+    request
+        .headers()
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .any(|v| v.split(',').any(|t| t.trim().starts_with(NDJSON)))
+}
+
+/// Collect a request body, refusing it once it passes `limit`
+async fn read_body(mut body: Body, limit: usize) -> std::result::Result<Bytes, HttpError> {
+    use hyper::body::HttpBody;
+
+    let mut collected = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| HttpError::bad_request(e.to_string()))?;
+        if collected.len() + chunk.len() > limit {
+            return Err(HttpError::from_code(
+                ErrorCode::FrameTooLarge,
+                format!("Request body exceeds the limit of {} bytes", limit),
+            ));
+        }
+        collected.extend_from_slice(&chunk);
+    }
+    Ok(collected.into())
+}
+
+fn parse_body<T: DeserializeOwned>(body: &[u8]) -> std::result::Result<T, HttpError> {
+    serde_json::from_slice(body).map_err(|e| HttpError::bad_request(format!("Invalid request body: {}", e)))
+}
+
+fn to_value(json: Json) -> std::result::Result<Value, HttpError> {
+    Value::from_json(json).map_err(|e| HttpError::bad_request(e.to_string()))
+}
+
+fn parse_metric(name: &str) -> std::result::Result<DistanceMetric, HttpError> {
+    match name.to_lowercase().as_str() {
+        "cosine" => Ok(DistanceMetric::Cosine),
+        "euclidean" | "l2" => Ok(DistanceMetric::Euclidean),
+        "dot" | "dotproduct" | "inner" => Ok(DistanceMetric::DotProduct),
+        "manhattan" | "l1" => Ok(DistanceMetric::Manhattan),
+        other => Err(HttpError::bad_request(format!("Unknown metric: {}", other))),
+    }
+}
+
+/// Build the protocol request a route makes
+fn build_request(
+    operation: Operation,
+    params: &HashMap<&'static str, String>,
+    query: &HashMap<String, String>,
+    body: &[u8],
+) -> std::result::Result<Request, HttpError> {
+    let path_param = |name: &str| params.get(name).cloned().unwrap_or_default();
+    let required = |name: &str| {
+        query
+            .get(name)
+            .cloned()
+            .ok_or_else(|| HttpError::bad_request(format!("Missing query parameter: {}", name)))
+    };
+    let number = |name: &str| -> std::result::Result<Option<usize>, HttpError> {
+        query
+            .get(name)
+            .map(|v| v.parse().map_err(|_| HttpError::bad_request(format!("Invalid {}: {}", name, v))))
+            .transpose()
+    };
+
+    let request = match operation {
+        Operation::Status => Request::Status,
+        Operation::InsertNode => {
+            let body: NewNode = parse_body(body)?;
+            Request::InsertNode {
+                node_type: body.node_type,
+                properties: to_value(body.properties.unwrap_or_else(|| json!({})))?,
+            }
+        }
+        Operation::ListNodes => Request::GetNodesByType {
+            node_type: required("type")?,
+            limit: number("limit")?,
+        },
+        Operation::GetNode => Request::GetNode { id: path_param("id") },
+        Operation::UpdateNode => {
+            let body: NodeUpdate = parse_body(body)?;
+            Request::UpdateNode { id: path_param("id"), properties: to_value(body.properties)? }
+        }
+        Operation::DeleteNode => Request::DeleteNode { id: path_param("id") },
+        Operation::ListEdges => {
+            let node_id = path_param("id");
+            let edge_type = query.get("type").cloned();
+            match query.get("direction").map(String::as_str) {
+                None | Some("out") => Request::GetEdgesFrom { node_id, edge_type },
+                Some("in") => Request::GetEdgesTo { node_id, edge_type },
+                Some(other) => return Err(HttpError::bad_request(format!("Invalid direction: {}", other))),
+            }
+        }
+        Operation::CreateEdge => {
+            let body: NewEdge = parse_body(body)?;
+            Request::CreateEdge {
+                from_id: body.from,
+                to_id: body.to,
+                edge_type: body.edge_type,
+                properties: body.properties.map(to_value).transpose()?,
+            }
+        }
+        Operation::DeleteEdge => Request::DeleteEdge { edge_id: path_param("id") },
+        Operation::Query => {
+            let body: QueryBody = parse_body(body)?;
+            Request::Query { sql: body.sql, limit: body.limit }
+        }
+        Operation::Search => {
+            let body: SearchBody = parse_body(body)?;
+            Request::SimilaritySearch {
+                node_type: body.node_type,
+                field: body.field,
+                vector: body.vector,
+                k: body.k,
+                metric: body.metric.as_deref().map(parse_metric).transpose()?.unwrap_or(DistanceMetric::Cosine),
+            }
+        }
+        Operation::Traverse => {
+            let body: TraverseBody = parse_body(body)?;
+            Request::Traverse { start_id: body.start, depth: body.depth, edge_types: body.edge_types }
+        }
+        Operation::ShortestPath => Request::ShortestPath {
+            from_id: required("from")?,
+            to_id: required("to")?,
+            max_depth: number("max_depth")?.unwrap_or(6) as u32,
+        },
+    };
+    Ok(request)
+}
+
+/// Turn the handler's response into the route's HTTP reply
+fn render(route: &Route, response: Response, ndjson: bool) -> std::result::Result<hyper::Response<Body>, HttpError> {
+    let reply = match response {
+        Response::Error { code, message } => return Err(HttpError::from_code(code, message)),
+        Response::NotLeader { leader_id, leader_addr } => {
+            let body = json!({
+                "error": {
+                    "code": "NotLeader",
+                    "message": "Writes must be sent to the leader",
+                    "leader_id": leader_id,
+                    "leader_addr": leader_addr,
+                }
+            });
+            return Ok(json_response(StatusCode::MISDIRECTED_REQUEST, &body));
+        }
+
+        Response::Ok => return Ok(empty_response(route.status)),
+        Response::Node(node) => json_response(route.status, &node.to_json()),
+        Response::Edge(edge) => json_response(route.status, &edge.to_json()),
+        Response::MaybeNode(Some(node)) => json_response(route.status, &node.to_json()),
+        Response::MaybeNode(None) => return Err(HttpError::from_code(ErrorCode::NodeNotFound, "Node not found")),
+        Response::Path(None) => return Err(HttpError::from_code(ErrorCode::NodeNotFound, "No path found")),
+
+        Response::Nodes(nodes) | Response::Path(Some(nodes)) => list_response(nodes, Node::to_json, ndjson),
+        Response::Edges(edges) => list_response(edges, Edge::to_json, ndjson),
+        Response::SearchResults(hits) => list_response(hits, search_hit_json, ndjson),
+
+        Response::QueryResult { columns, rows, rows_affected, execution_time_ms } => {
+            if ndjson {
+                let columns = Arc::new(columns);
+                stream_lines(rows, move |row| {
+                    let object = columns.iter().cloned().zip(row.iter().map(Value::to_json)).collect();
+                    Json::Object(object)
+                })
+            } else {
+                let rows: Vec<Json> = rows
+                    .iter()
+                    .map(|row| Json::Array(row.iter().map(Value::to_json).collect()))
+                    .collect();
+                json_response(
+                    route.status,
+                    &json!({
+                        "columns": columns,
+                        "rows": rows,
+                        "rows_affected": rows_affected,
+                        "execution_time_ms": execution_time_ms,
+                    }),
+                )
+            }
+        }
+
+        Response::TraversalResult { nodes, edges, depth } => {
+            if ndjson {
+                let lines = nodes
+                    .into_iter()
+                    .map(|n| json!({ "node": n.to_json() }))
+                    .chain(edges.into_iter().map(|e| json!({ "edge": e.to_json() })))
+                    .collect();
+                stream_lines(lines, Json::clone)
+            } else {
+                json_response(
+                    route.status,
+                    &json!({
+                        "nodes": nodes.iter().map(Node::to_json).collect::<Vec<_>>(),
+                        "edges": edges.iter().map(Edge::to_json).collect::<Vec<_>>(),
+                        "depth": depth,
+                    }),
+                )
+            }
+        }
+
+        Response::Status { name, node_count, edge_count, size_bytes } => json_response(
+            route.status,
+            &json!({
+                "name": name,
+                "node_count": node_count,
+                "edge_count": edge_count,
+                "size_bytes": size_bytes,
+            }),
+        ),
+
+        other => {
+            return Err(HttpError::from_code(
+                ErrorCode::InternalError,
+                format!("Unexpected response: {:?}", other),
+            ))
+        }
+    };
+    Ok(reply)
+}
+
+fn search_hit_json(hit: &SearchHit) -> Json {
+    json!({ "node": hit.node.to_json(), "score": hit.score, "distance": hit.distance })
+}
+
+fn json_response(status: StatusCode, body: &Json) -> hyper::Response<Body> {
+    let mut response = hyper::Response::new(Body::from(serde_json::to_vec(body).unwrap_or_default()));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+fn empty_response(status: StatusCode) -> hyper::Response<Body> {
+    let mut response = hyper::Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+fn list_response<T: Send + 'static>(items: Vec<T>, encode: fn(&T) -> Json, ndjson: bool) -> hyper::Response<Body> {
+    if ndjson {
+        stream_lines(items, encode)
+    } else {
+        json_response(StatusCode::OK, &Json::Array(items.iter().map(encode).collect()))
+    }
+}
+
+/// Write one JSON value per line as the body is read
+fn stream_lines<T, F>(items: Vec<T>, encode: F) -> hyper::Response<Body>
+where
+    T: Send + 'static,
+    F: Fn(&T) -> Json + Send + 'static,
+{
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        let mut chunk = Vec::with_capacity(NDJSON_CHUNK_SIZE);
+        for item in items {
+            if serde_json::to_writer(&mut chunk, &encode(&item)).is_err() {
+                break;
+            }
+            chunk.push(b'\n');
+            if chunk.len() >= NDJSON_CHUNK_SIZE {
+                let full = std::mem::replace(&mut chunk, Vec::with_capacity(NDJSON_CHUNK_SIZE));
+                // The client went away
+                if sender.send_data(full.into()).await.is_err() {
+                    return;
+                }
+            }
+        }
+        if !chunk.is_empty() {
+            let _ = sender.send_data(chunk.into()).await;
+        }
+    });
+
+    let mut response = hyper::Response::new(body);
+    response
+        .headers_mut()
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static(NDJSON));
+    response
+}
+
+/// OpenAPI 3 description of the routes
+pub fn openapi() -> Json {
+    let mut paths = serde_json::Map::new();
+
+    for route in ROUTES {
+        let mut parameters: Vec<Json> = route
+            .path
+            .split('/')
+            .filter_map(|s| s.strip_prefix('{').and_then(|s| s.strip_suffix('}')))
+            .map(|name| json!({ "name": name, "in": "path", "required": true, "schema": { "type": "string" } }))
+            .collect();
+        parameters.extend(route.query.iter().map(|p| {
+            json!({
+                "name": p.name,
+                "in": "query",
+                "required": p.required,
+                "description": p.description,
+                "schema": { "type": p.kind },
+            })
+        }));
+
+        let schema_ref = |name: &str| json!({ "$ref": format!("#/components/schemas/{}", name) });
+        let success = match route.reply {
+            Reply::Empty => json!({ "description": "Done" }),
+            Reply::One(name) => {
+                let mut content = json!({ "application/json": { "schema": schema_ref(name) } });
+                if matches!(route.operation, Operation::Query | Operation::Traverse) {
+                    content[NDJSON] = json!({ "schema": { "type": "object" } });
+                }
+                json!({ "description": "Success", "content": content })
+            }
+            Reply::Many(name) => json!({
+                "description": "Success",
+                "content": {
+                    "application/json": { "schema": { "type": "array", "items": schema_ref(name) } },
+                    (NDJSON): { "schema": schema_ref(name) },
+                },
+            }),
+        };
+
+        let mut operation = json!({
+            "operationId": route.id,
+            "summary": route.summary,
+            "parameters": parameters,
+            "responses": {
+                (route.status.as_str()): success,
+                "default": { "$ref": "#/components/responses/Error" },
+            },
+        });
+        if let Some(body) = route.body {
+            operation["requestBody"] = json!({
+                "required": true,
+                "content": { "application/json": { "schema": schema_ref(body) } },
+            });
+        }
+
+        let path = paths.entry(route.path).or_insert_with(|| json!({}));
+        path[route.method.as_str().to_lowercase()] = operation;
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": { "title": "AresaDB", "version": env!("CARGO_PKG_VERSION") },
+        "paths": paths,
+        "components": {
+            "schemas": schemas(),
+            "responses": {
+                "Error": {
+                    "description": "Failure",
+                    "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Error" } } },
+                },
+            },
+            "securitySchemes": {
+                "bearer": { "type": "http", "scheme": "bearer" },
+                "basic": { "type": "http", "scheme": "basic" },
+            },
+        },
+        "security": [{ "bearer": [] }, { "basic": [] }, {}],
+    })
+}
+
+/// Schemas of the request and response bodies
+fn schemas() -> Json {
+    let properties = json!({ "type": "object", "additionalProperties": true });
+    json!({
+        "Node": {
+            "type": "object",
+            "properties": {
+                "id": { "type": "string" },
+                "node_type": { "type": "string" },
+                "properties": properties,
+                "created_at": { "type": "integer", "description": "Milliseconds since the Unix epoch" },
+                "updated_at": { "type": "integer", "description": "Milliseconds since the Unix epoch" },
+            },
+        },
+        "Edge": {
+            "type": "object",
+            "properties": {
+                "id": { "type": "string" },
+                "from": { "type": "string" },
+                "to": { "type": "string" },
+                "edge_type": { "type": "string" },
+                "properties": properties,
+                "created_at": { "type": "integer" },
+            },
+        },
+        "NewNode": {
+            "type": "object",
+            "required": ["type"],
+            "properties": { "type": { "type": "string" }, "properties": properties },
+        },
+        "NodeUpdate": {
+            "type": "object",
+            "required": ["properties"],
+            "properties": { "properties": properties },
+        },
+        "NewEdge": {
+            "type": "object",
+            "required": ["from", "to", "type"],
+            "properties": {
+                "from": { "type": "string" },
+                "to": { "type": "string" },
+                "type": { "type": "string" },
+                "properties": properties,
+            },
+        },
+        "Query": {
+            "type": "object",
+            "required": ["sql"],
+            "properties": { "sql": { "type": "string" }, "limit": { "type": "integer" } },
+        },
+        "QueryResult": {
+            "type": "object",
+            "properties": {
+                "columns": { "type": "array", "items": { "type": "string" } },
+                "rows": { "type": "array", "items": { "type": "array", "items": {} } },
+                "rows_affected": { "type": "integer" },
+                "execution_time_ms": { "type": "integer" },
+            },
+        },
+        "Search": {
+            "type": "object",
+            "required": ["type", "field", "vector"],
+            "properties": {
+                "type": { "type": "string" },
+                "field": { "type": "string" },
+                "vector": { "type": "array", "items": { "type": "number" } },
+                "k": { "type": "integer", "default": default_k() },
+                "metric": { "type": "string", "enum": ["cosine", "euclidean", "dot", "manhattan"] },
+            },
+        },
+        "SearchHit": {
+            "type": "object",
+            "properties": {
+                "node": { "$ref": "#/components/schemas/Node" },
+                "score": { "type": "number" },
+                "distance": { "type": "number" },
+            },
+        },
+        "Traverse": {
+            "type": "object",
+            "required": ["start"],
+            "properties": {
+                "start": { "type": "string" },
+                "depth": { "type": "integer", "default": default_depth() },
+                "edge_types": { "type": "array", "items": { "type": "string" } },
+            },
+        },
+        "Traversal": {
+            "type": "object",
+            "properties": {
+                "nodes": { "type": "array", "items": { "$ref": "#/components/schemas/Node" } },
+                "edges": { "type": "array", "items": { "$ref": "#/components/schemas/Edge" } },
+                "depth": { "type": "integer" },
+            },
+        },
+        "Status": {
+            "type": "object",
+            "properties": {
+                "name": { "type": "string" },
+                "node_count": { "type": "integer" },
+                "edge_count": { "type": "integer" },
+                "size_bytes": { "type": "integer" },
+            },
+        },
+        "Error": {
+            "type": "object",
+            "properties": {
+                "error": {
+                    "type": "object",
+                    "properties": {
+                        "code": { "type": "string" },
+                        "message": { "type": "string" },
+                    },
+                },
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::UserStore;
+    use crate::storage::Database;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_openapi_describes_every_route() {
+        let doc = openapi();
+        let schemas = doc["components"]["schemas"].as_object().unwrap();
+
+        for route in ROUTES {
+            let operation = &doc["paths"][route.path][route.method.as_str().to_lowercase()];
+            assert_eq!(operation["operationId"], route.id);
+            if let Some(body) = route.body {
+                assert!(schemas.contains_key(body), "missing schema {}", body);
+            }
+            if route.path.contains("{id}") {
+                assert_eq!(operation["parameters"][0]["name"], "id");
+            }
+        }
+
+        assert!(match_path("/v1/nodes/{id}/edges", "/v1/nodes/abc/edges").is_some());
+        assert!(match_path("/v1/nodes/{id}", "/v1/nodes/abc/edges").is_none());
+        assert!(match_path("/v1/nodes/{id}", "/v1/nodes/").is_none());
+    }
+
+    async fn start(db: Database, require_auth: bool) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let config = HttpConfig { bind_addr: addr, require_auth, ..Default::default() };
+        let server = HttpServer::new(Arc::new(RequestHandler::new(db)), config);
+        tokio::spawn(async move { server.run().await });
+
+        let base = format!("http://{}", addr);
+        for _ in 0..100 {
+            if reqwest::get(format!("{}/openapi.json", base)).await.is_ok() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        base
+    }
+
+    #[tokio::test]
+    async fn test_http_api() {
+        let temp = TempDir::new().unwrap();
+        let db = Database::create(temp.path(), "test").await.unwrap();
+        let base = start(db, false).await;
+        let http = reqwest::Client::new();
+
+        let alice: Json = http
+            .post(format!("{}/v1/nodes", base))
+            .json(&json!({ "type": "user", "properties": { "name": "Alice", "embedding": [1.0, 0.0] } }))
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let alice_id = alice["id"].as_str().unwrap().to_string();
+        assert_eq!(alice["properties"]["name"], "Alice");
+
+        let bob: Json = http
+            .post(format!("{}/v1/nodes", base))
+            .json(&json!({ "type": "user", "properties": { "name": "Bob", "embedding": [0.0, 1.0] } }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let bob_id = bob["id"].as_str().unwrap().to_string();
+
+        let response = http
+            .post(format!("{}/v1/edges", base))
+            .json(&json!({ "from": alice_id, "to": bob_id, "type": "follows" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 201);
+
+        let node: Json = reqwest::get(format!("{}/v1/nodes/{}", base, bob_id)).await.unwrap().json().await.unwrap();
+        assert_eq!(node["properties"]["name"], "Bob");
+
+        let edges: Json = reqwest::get(format!("{}/v1/nodes/{}/edges?type=follows", base, alice_id))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(edges.as_array().unwrap().len(), 1);
+        assert_eq!(edges[0]["to"], bob_id.as_str());
+
+        // Lists stream as NDJSON on request
+        let response = http
+            .get(format!("{}/v1/nodes?type=user", base))
+            .header(header::ACCEPT, NDJSON)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], NDJSON);
+        let text = response.text().await.unwrap();
+        let lines: Vec<Json> = text.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+
+        let hits: Json = http
+            .post(format!("{}/v1/search", base))
+            .json(&json!({ "type": "user", "field": "embedding", "vector": [0.9, 0.1], "k": 1 }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(hits[0]["node"]["id"], alice_id.as_str());
+
+        let traversal: Json = http
+            .post(format!("{}/v1/traverse", base))
+            .json(&json!({ "start": alice_id, "depth": 1 }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(traversal["edges"].as_array().unwrap().len(), 1);
+
+        let status: Json = reqwest::get(format!("{}/v1/status", base)).await.unwrap().json().await.unwrap();
+        assert_eq!(status["node_count"], 2);
+
+        let response = http.delete(format!("{}/v1/nodes/{}", base, bob_id)).send().await.unwrap();
+        assert_eq!(response.status(), 204);
+        let response = reqwest::get(format!("{}/v1/nodes/{}", base, bob_id)).await.unwrap();
+        assert_eq!(response.status(), 404);
+        let error: Json = response.json().await.unwrap();
+        assert_eq!(error["error"]["code"], "NodeNotFound");
+
+        let response = http.put(format!("{}/v1/nodes/{}", base, alice_id)).send().await.unwrap();
+        assert_eq!(response.status(), 405);
+        let response = http.post(format!("{}/v1/nodes", base)).body("{").send().await.unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_http_authentication() {
+        let temp = TempDir::new().unwrap();
+        let db = Database::create(temp.path(), "test").await.unwrap();
+        let users = UserStore::new(&db);
+        users.create_user("ada", "lovelace").await.unwrap();
+        let token = users.create_token("ada", None).await.unwrap();
+        let base = start(db, true).await;
+        let http = reqwest::Client::new();
+        let status = format!("{}/v1/status", base);
+
+        let response = http.get(&status).send().await.unwrap();
+        assert_eq!(response.status(), 401);
+        assert!(response.headers().contains_key(header::WWW_AUTHENTICATE));
+
+        let response = http.get(&status).bearer_auth("ares_wrong").send().await.unwrap();
+        assert_eq!(response.status(), 401);
+
+        // Authenticated, but no role grants anything yet
+        let response = http.get(&status).basic_auth("ada", Some("lovelace")).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        // Verified credentials are remembered; others are still checked
+        let response = http.get(&status).basic_auth("ada", Some("lovelace")).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        let response = http.get(&status).basic_auth("ada", Some("babbage")).send().await.unwrap();
+        assert_eq!(response.status(), 401);
+        let response = http
+            .get(format!("{}/v1/nodes?type=user", base))
+            .bearer_auth(&token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 403);
+
+        // The document itself needs no credentials
+        let response = http.get(format!("{}/openapi.json", base)).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_endpoint() {
+        let temp = TempDir::new().unwrap();
+        let db = Database::create(temp.path(), "test").await.unwrap();
+        db.insert_node("metric", serde_json::json!({ "n": 1 })).await.unwrap();
+        let base = start(db, false).await;
+        let http = reqwest::Client::new();
+
+        let response = http
+            .post(format!("{}/v1/query", base))
+            .json(&serde_json::json!({ "sql": "SELECT * FROM metric" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+
+        let response = http.get(format!("{}/metrics", base)).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], metrics::CONTENT_TYPE);
+        let text = response.text().await.unwrap();
+        assert!(text.contains("# TYPE aresadb_request_duration_seconds histogram"));
+        assert!(text.contains("aresadb_requests_total{request=\"Query\",status=\"ok\"}"));
+        assert!(text.contains("aresadb_query_duration_seconds_count{operation=\"select\"}"));
+        assert!(text.contains("aresadb_query_rows_scanned_total"));
+        assert!(text.contains("aresadb_cache_hits_total"));
+    }
+}
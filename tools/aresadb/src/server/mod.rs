@@ -1,40 +1,94 @@
 //! AresaDB Server
 //!
 //! TCP server for remote database access with connection pooling
-//! and request handling.
+//! and request handling. Connections may be wrapped in TLS, and each one
+//! opens with a handshake that can authenticate the client; requests are
+//! then checked against the user's role grants. [`HttpServer`] serves the
+//! same requests as JSON over HTTP, and [`PgServer`] runs SQL for clients
+//! speaking the PostgreSQL wire protocol. While it runs, the server posts
+//! changes to the webhooks of `AFTER` triggers.
 
 mod protocol;
 mod handler;
 mod pool;
+mod peer;
+mod auth;
+mod rbac;
+mod tls;
+mod http;
+mod pgwire;
+mod webhooks;
 
-pub use protocol::{Request, Response, ErrorCode};
+pub use protocol::{
+    Request, Response, ErrorCode, SearchHit, Hello, Welcome, HandshakeReply, FrameCodec, FrameTooLarge,
+    PROTOCOL_VERSION, MIN_PROTOCOL_VERSION, DEFAULT_MAX_FRAME_SIZE, COMPRESSION_LZ4, CONNECTION_ERROR_ID,
+};
 pub use handler::RequestHandler;
 pub use pool::ConnectionPool;
+pub use peer::{TcpTransport, PeerSecurity};
+pub use auth::{Credentials, UserStore, TokenInfo};
+pub use rbac::{
+    AccessStatement, AccessStore, Denial, Grant, Permission, Policy, Resource, ADMIN_ROLE, PUBLIC_ROLE,
+};
+pub use tls::{TlsConfig, ClientTlsConfig, Stream};
+pub use http::{HttpServer, HttpConfig, openapi, DEFAULT_MAX_BODY_SIZE};
+pub use pgwire::{PgServer, PgConfig};
+pub use webhooks::{WebhookConfig, WebhookDispatcher};
+pub(crate) use protocol::{handshake, read_handshake, read_message, write_handshake, write_message};
+pub(crate) use tls::dial;
 
 use anyhow::{Result, Context};
-use parking_lot::RwLock;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tracing::{info, warn, error, debug};
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::timeout;
+use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn, error, debug, Instrument};
 
-use crate::storage::Database;
-use crate::distributed::ShardManager;
+use pool::Connections;
+use protocol::read_payload;
+
+use crate::storage::{ChangeFilter, Database};
+use crate::distributed::{RaftNode, ShardManager};
 
 /// Server configuration
 #[derive(Debug, Clone)]
 pub struct ServerConfig {
     /// Address to bind to
     pub bind_addr: SocketAddr,
-    /// Maximum connections
+    /// Maximum connections; further clients are refused with `ServerBusy`
     pub max_connections: usize,
-    /// Read timeout in seconds
+    /// Seconds allowed to receive the handshake or a request once it starts
     pub read_timeout_secs: u64,
-    /// Write timeout in seconds
+    /// Seconds allowed to send a response
     pub write_timeout_secs: u64,
+    /// Seconds a connection may sit without requests before it is closed
+    pub idle_timeout_secs: u64,
+    /// Seconds a request may run before it fails
+    pub request_timeout_secs: u64,
+    /// Seconds to let in-flight requests finish when shutting down
+    pub shutdown_timeout_secs: u64,
     /// Enable compression
     pub compression: bool,
+    /// Serve TLS instead of plain TCP
+    pub tls: Option<TlsConfig>,
+    /// Reject connections that don't authenticate in the handshake
+    pub require_auth: bool,
+    /// Largest request frame accepted, in bytes
+    pub max_frame_size: usize,
+    /// Deliver the webhooks of `AFTER` triggers; `None` to leave that to
+    /// another process
+    pub webhooks: Option<WebhookConfig>,
+    /// Address to accept consensus messages from other replicas on;
+    /// required by replicated servers
+    pub peer_addr: Option<SocketAddr>,
+    /// Secret peers must present to the peer listener
+    pub cluster_secret: Option<String>,
 }
 
 impl Default for ServerConfig {
@@ -44,186 +98,520 @@ impl Default for ServerConfig {
             max_connections: 1000,
             read_timeout_secs: 30,
             write_timeout_secs: 30,
+            idle_timeout_secs: 300,
+            request_timeout_secs: 60,
+            shutdown_timeout_secs: 30,
             compression: true,
+            tls: None,
+            require_auth: false,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            webhooks: Some(WebhookConfig::default()),
+            peer_addr: None,
+            cluster_secret: None,
         }
     }
 }
 
+impl ServerConfig {
+    fn read_timeout(&self) -> Duration {
+        Duration::from_secs(self.read_timeout_secs)
+    }
+
+    fn write_timeout(&self) -> Duration {
+        Duration::from_secs(self.write_timeout_secs)
+    }
+}
+
 /// AresaDB TCP Server
 pub struct Server {
     config: ServerConfig,
     handler: Arc<RequestHandler>,
-    pool: Arc<ConnectionPool>,
-    /// Shutdown flag
-    pub shutdown: Arc<RwLock<bool>>,
+    /// Open connections of every listener
+    connections: Arc<Connections>,
+    http: Option<Arc<HttpServer>>,
+    pg: Option<Arc<PgServer>>,
+    /// Cancelled to stop accepting and drain connections
+    shutdown: CancellationToken,
 }
 
 impl Server {
     /// Create a new server with a database
     pub fn new(db: Database, config: ServerConfig) -> Self {
-        let handler = Arc::new(RequestHandler::new(db));
-        let pool = Arc::new(ConnectionPool::new(config.max_connections));
-
-        Self {
-            config,
-            handler,
-            pool,
-            shutdown: Arc::new(RwLock::new(false)),
-        }
+        Self::with_handler(RequestHandler::new(db), config)
     }
 
     /// Create a new server with a shard manager
-    pub fn with_shards(shards: ShardManager, config: ServerConfig) -> Self {
-        let handler = Arc::new(RequestHandler::with_shards(shards));
+    ///
+    /// Pass an `Arc` to keep a handle for rebalancing while serving.
+    pub fn with_shards(shards: impl Into<Arc<ShardManager>>, config: ServerConfig) -> Self {
+        Self::with_handler(RequestHandler::with_shards(shards), config)
+    }
+
+    /// Create a new server replicating writes through Raft
+    pub fn with_replication(raft: Arc<RaftNode>, config: ServerConfig) -> Self {
+        Self::with_handler(RequestHandler::with_replication(raft), config)
+    }
+
+    /// Create a new server around a configured request handler
+    pub fn with_handler(handler: RequestHandler, config: ServerConfig) -> Self {
         let pool = Arc::new(ConnectionPool::new(config.max_connections));
 
         Self {
             config,
-            handler,
-            pool,
-            shutdown: Arc::new(RwLock::new(false)),
+            handler: Arc::new(handler),
+            connections: Arc::new(Connections::new(pool)),
+            http: None,
+            pg: None,
+            shutdown: CancellationToken::new(),
         }
     }
 
-    /// Start the server
+    /// Also serve the HTTP API while running
+    ///
+    /// Its connections count against `max_connections` and are drained
+    /// with the others on shutdown.
+    pub fn with_http(mut self, config: HttpConfig) -> Self {
+        self.http = Some(Arc::new(HttpServer::new(self.handler(), config)));
+        self
+    }
+
+    /// Also serve the PostgreSQL wire protocol while running
+    ///
+    /// Its connections count against `max_connections` and are drained
+    /// with the others on shutdown.
+    pub fn with_pg(mut self, config: PgConfig) -> Self {
+        self.pg = Some(Arc::new(PgServer::new(self.handler(), config)));
+        self
+    }
+
+    /// Start the server, returning once it has shut down
+    ///
+    /// After [`shutdown`](Self::shutdown) no listener accepts connections.
+    /// Requests already running get up to `shutdown_timeout_secs` to
+    /// finish, later ones are refused with `ServerBusy`, connections still
+    /// open after that are closed, and storage is flushed before this
+    /// returns.
     pub async fn run(&self) -> Result<()> {
+        let acceptor = self.config.tls.as_ref().map(TlsConfig::acceptor).transpose()?;
         let listener = TcpListener::bind(&self.config.bind_addr)
             .await
             .context("Failed to bind server")?;
 
-        info!("AresaDB server listening on {}", self.config.bind_addr);
+        info!(
+            "AresaDB server listening on {}{}",
+            self.config.bind_addr,
+            if acceptor.is_some() { " (TLS)" } else { "" }
+        );
+
+        let peers = match (self.handler.raft(), self.config.peer_addr) {
+            (Some(raft), Some(peer_addr)) => {
+                if self.config.cluster_secret.is_none() {
+                    anyhow::bail!("The peer listener needs a cluster secret");
+                }
+                let peer_listener = TcpListener::bind(peer_addr)
+                    .await
+                    .context("Failed to bind peer listener")?;
+                info!("Accepting peers on {}", peer_addr);
+                let serve = peer::serve_peers(
+                    peer_listener,
+                    acceptor.clone(),
+                    Arc::clone(raft),
+                    self.config.clone(),
+                    self.shutdown.clone(),
+                );
+                Some(tokio::spawn(serve))
+            }
+            (Some(_), None) => anyhow::bail!("A replicated server needs a peer address"),
+            (None, _) => None,
+        };
 
-        while !*self.shutdown.read() {
-            match listener.accept().await {
-                Ok((stream, addr)) => {
-                    debug!("New connection from {}", addr);
+        let webhooks = match self.config.webhooks.clone().map(|config| self.handler.webhook_dispatcher(config)) {
+            Some(Some(dispatcher)) => Some(tokio::spawn(dispatcher?.run(self.shutdown.clone()))),
+            _ => None,
+        };
 
-                    // Check connection limit
-                    if !self.pool.try_acquire() {
-                        warn!("Connection limit reached, rejecting {}", addr);
-                        continue;
-                    }
+        let mut front_ends: Vec<JoinHandle<Result<()>>> = Vec::new();
+        if let Some(http) = &self.http {
+            let listener = http.bind().await?;
+            let (http, shutdown, connections) = (Arc::clone(http), self.shutdown.clone(), Arc::clone(&self.connections));
+            front_ends.push(tokio::spawn(async move { http.serve(listener, shutdown, connections).await }));
+        }
+        if let Some(pg) = &self.pg {
+            let listener = pg.bind().await?;
+            let (pg, shutdown, connections) = (Arc::clone(pg), self.shutdown.clone(), Arc::clone(&self.connections));
+            front_ends.push(tokio::spawn(async move { pg.serve(listener, shutdown, connections).await }));
+        }
 
-                    let handler = Arc::clone(&self.handler);
-                    let pool = Arc::clone(&self.pool);
-                    let compression = self.config.compression;
-
-                    tokio::spawn(async move {
-                        if let Err(e) = handle_connection(stream, handler, compression).await {
-                            warn!("Connection error from {}: {}", addr, e);
-                        }
-                        pool.release();
-                        debug!("Connection closed: {}", addr);
-                    });
-                }
+        loop {
+            let accepted = tokio::select! {
+                _ = self.shutdown.cancelled() => break,
+                accepted = listener.accept() => accepted,
+            };
+            let (stream, addr) = match accepted {
+                Ok(accepted) => accepted,
                 Err(e) => {
                     error!("Accept error: {}", e);
+                    continue;
                 }
+            };
+            debug!("New connection from {}", addr);
+            // Frames are small request/response pairs; Nagle would
+            // hold each reply back until the peer's delayed ACK
+            let _ = stream.set_nodelay(true);
+
+            let acceptor = acceptor.clone();
+            let config = self.config.clone();
+
+            // Check connection limit
+            let Some(slot) = self.connections.reserve() else {
+                warn!("Connection limit reached, rejecting {}", addr);
+                tokio::spawn(async move {
+                    let message = format!("Connection limit of {} reached", config.max_connections);
+                    if let Err(e) = refuse(stream, acceptor, &config, message).await {
+                        debug!("Failed to refuse {}: {}", addr, e);
+                    }
+                });
+                continue;
+            };
+
+            let handler = Arc::clone(&self.handler);
+            let shutdown = self.shutdown.clone();
+
+            let span = tracing::info_span!("connection", peer = %addr);
+            self.connections.spawn(slot, async move {
+                let result = match accept(stream, acceptor, &config).await {
+                    Ok(stream) => handle_connection(stream, handler, &config, shutdown).await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = result {
+                    warn!("Connection error from {}: {}", addr, e);
+                }
+                debug!("Connection closed: {}", addr);
+            }.instrument(span));
+        }
+
+        // Stop accepting on every listener before waiting on the connections
+        drop(listener);
+        for front_end in front_ends {
+            match front_end.await {
+                Ok(Err(e)) => error!("Listener failed: {:#}", e),
+                Err(e) => error!("Listener panicked: {}", e),
+                Ok(Ok(())) => {}
             }
         }
+        info!("Server shutting down, draining {} connections", self.connection_count());
 
-        info!("Server shutting down");
+        let deadline = Duration::from_secs(self.config.shutdown_timeout_secs);
+        let aborted = self.connections.drain(deadline).await;
+        if aborted > 0 {
+            warn!("{} connections still open after {:?}; closed them", aborted, deadline);
+        }
+
+        if let Some(webhooks) = webhooks {
+            let _ = webhooks.await;
+        }
+        if let Some(peers) = peers {
+            let _ = peers.await;
+        }
+        self.handler.flush().await.context("Failed to flush storage")?;
+        info!("Server stopped");
         Ok(())
     }
 
-    /// Shutdown the server
+    /// Stop accepting connections and drain the open ones
+    ///
+    /// Takes effect immediately, even while [`run`](Self::run) is waiting
+    /// for a connection.
     pub fn shutdown(&self) {
-        *self.shutdown.write() = true;
+        self.shutdown.cancel();
+    }
+
+    /// Token that shuts the server down when cancelled, for signal handlers
+    pub fn shutdown_token(&self) -> CancellationToken {
+        self.shutdown.clone()
+    }
+
+    /// Request handler, to share with a front end run on its own
+    pub fn handler(&self) -> Arc<RequestHandler> {
+        Arc::clone(&self.handler)
     }
 
     /// Get current connection count
     pub fn connection_count(&self) -> usize {
-        self.pool.active_count()
+        self.connections.count()
+    }
+}
+
+/// Requests a connection may have in progress at once
+const MAX_IN_FLIGHT: usize = 64;
+
+/// Complete the TLS handshake, if the server uses TLS
+async fn accept(stream: TcpStream, acceptor: Option<TlsAcceptor>, config: &ServerConfig) -> Result<Box<dyn Stream>> {
+    match acceptor {
+        Some(acceptor) => {
+            let stream = timeout(config.read_timeout(), acceptor.accept(stream))
+                .await
+                .context("Timed out in TLS handshake")?
+                .context("TLS handshake failed")?;
+            Ok(Box::new(stream))
+        }
+        None => Ok(Box::new(stream)),
     }
 }
 
+/// Turn a client away with `ServerBusy` in place of a welcome
+async fn refuse(stream: TcpStream, acceptor: Option<TlsAcceptor>, config: &ServerConfig, message: String) -> Result<()> {
+    let mut stream = accept(stream, acceptor, config).await?;
+    // Reading the hello first means closing doesn't reset the connection
+    // before the client has read the reply
+    let _: Hello = timeout(config.read_timeout(), read_handshake(&mut stream))
+        .await
+        .context("Timed out waiting for handshake")??;
+    let reply = HandshakeReply::Rejected { code: ErrorCode::ServerBusy, message };
+    timeout(config.write_timeout(), write_handshake(&mut stream, &reply))
+        .await
+        .context("Timed out sending handshake")??;
+    stream.shutdown().await?;
+    Ok(())
+}
+
 /// Handle a single client connection
 async fn handle_connection(
-    mut stream: TcpStream,
+    mut stream: Box<dyn Stream>,
     handler: Arc<RequestHandler>,
-    compression: bool,
+    config: &ServerConfig,
+    shutdown: CancellationToken,
 ) -> Result<()> {
-    let compressor = if compression {
-        Some(crate::distributed::Compressor::new())
-    } else {
-        None
+    // Nothing is served before the handshake succeeds
+    let hello: Hello = timeout(config.read_timeout(), read_handshake(&mut stream))
+        .await
+        .context("Timed out waiting for handshake")??;
+    let protocol_version = hello.protocol_version.min(PROTOCOL_VERSION);
+    if protocol_version < MIN_PROTOCOL_VERSION {
+        let reply = HandshakeReply::Rejected {
+            code: ErrorCode::InvalidRequest,
+            message: format!(
+                "Unsupported protocol version {} (server speaks {} to {})",
+                hello.protocol_version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+            ),
+        };
+        return write_handshake(&mut stream, &reply).await;
+    }
+
+    let session_user = match handler.authenticate(hello.credentials.as_ref(), config.require_auth).await {
+        Ok(user) => user,
+        Err(reply) => return write_handshake(&mut stream, &reply).await,
+    };
+    if let Some(user) = &session_user {
+        debug!("Authenticated as {}", user);
+    }
+
+    let compression = config.compression && hello.compression.iter().any(|c| c == COMPRESSION_LZ4);
+    let welcome = Welcome {
+        protocol_version,
+        compression: compression.then(|| COMPRESSION_LZ4.to_string()),
+        max_frame_size: config.max_frame_size as u64,
+        user: session_user.clone(),
     };
+    write_handshake(&mut stream, &HandshakeReply::Welcome(welcome)).await?;
+
+    let codec = Arc::new(FrameCodec::new(compression, hello.max_frame_size as usize, config.max_frame_size));
+    let session = Session { handler, codec, user: session_user, config: config.clone(), shutdown };
+    serve_requests(stream, session).await
+}
+
+/// What a connection's requests share
+struct Session {
+    handler: Arc<RequestHandler>,
+    codec: Arc<FrameCodec>,
+    user: Option<String>,
+    config: ServerConfig,
+    shutdown: CancellationToken,
+}
+
+/// Answer requests until the client disconnects
+///
+/// Each request runs as its own task and is answered as soon as it
+/// completes, so responses can overtake each other. Requests that depend
+/// on one another's effects must not be pipelined.
+///
+/// The connection is closed once it has had nothing in flight and no new
+/// request for the idle timeout, or when the server shuts down; either way
+/// requests already running are answered first.
+async fn serve_requests(stream: Box<dyn Stream>, session: Session) -> Result<()> {
+    let Session { handler, codec, user: session_user, config, shutdown } = session;
+    let (reader, mut writer) = tokio::io::split(stream);
+    // Buffered so waiting for the next frame can time out without losing bytes
+    let mut reader = BufReader::new(reader);
+    let (tx, mut rx) = mpsc::channel::<(u64, Response)>(MAX_IN_FLIGHT);
 
-    loop {
-        // Read message length (4 bytes)
-        let mut len_buf = [0u8; 4];
-        match stream.read_exact(&mut len_buf).await {
-            Ok(_) => {}
-            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
-            Err(e) => return Err(e.into()),
+    let writer_codec = Arc::clone(&codec);
+    let write_timeout = config.write_timeout();
+    let writer_task = tokio::spawn(async move {
+        while let Some((id, response)) = rx.recv().await {
+            let frame = match writer_codec.encode(id, &response) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    let code = if e.is::<FrameTooLarge>() { ErrorCode::FrameTooLarge } else { ErrorCode::InternalError };
+                    writer_codec.encode(id, &Response::error(code, e.to_string()))?
+                }
+            };
+            let write = async {
+                writer.write_all(&frame).await?;
+                // Responses already queued go out in the same flush
+                if rx.is_empty() {
+                    writer.flush().await?;
+                }
+                std::io::Result::Ok(())
+            };
+            timeout(write_timeout, write).await.context("Timed out sending a response")??;
         }
+        writer.shutdown().await?;
+        anyhow::Ok(())
+    });
 
-        let msg_len = u32::from_le_bytes(len_buf) as usize;
+    let idle_timeout = Duration::from_secs(config.idle_timeout_secs);
+    let request_timeout = Duration::from_secs(config.request_timeout_secs);
+    let in_flight = Arc::new(Semaphore::new(MAX_IN_FLIGHT));
+    let mut subscriptions = JoinSet::new();
+    let result = loop {
+        // Wait for the start of the next frame
+        let waited = tokio::select! {
+            _ = shutdown.cancelled() => break Ok(None),
+            waited = timeout(idle_timeout, reader.fill_buf()) => waited,
+        };
+        match waited {
+            Ok(Ok([])) => break Ok(None),
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => break Err(e.into()),
+            Err(_) => {
+                while subscriptions.try_join_next().is_some() {}
+                // Not idle while a response is still owed or changes stream
+                if in_flight.available_permits() < MAX_IN_FLIGHT || !subscriptions.is_empty() {
+                    continue;
+                }
+                debug!("Closing connection idle for {:?}", idle_timeout);
+                break Ok(None);
+            }
+        }
 
-        // Read message body
-        let mut body = vec![0u8; msg_len];
-        stream.read_exact(&mut body).await?;
+        let decoded = match timeout(config.read_timeout(), read_payload(&mut reader, codec.recv_limit())).await {
+            Ok(Ok(Some(payload))) => codec.decode::<Request>(&payload),
+            Ok(Ok(None)) => break Ok(None),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(anyhow::anyhow!("Timed out reading a request")),
+        };
 
-        // Decompress if needed
-        let body = if let Some(ref comp) = compressor {
-            comp.decompress(&body)?
-        } else {
-            body
+        let (id, request) = match decoded {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                // The stream can't be resynchronised after a bad frame
+                let code = if e.is::<FrameTooLarge>() { ErrorCode::FrameTooLarge } else { ErrorCode::InvalidRequest };
+                let _ = tx.send((CONNECTION_ERROR_ID, Response::error(code, e.to_string()))).await;
+                break Err(e);
+            }
         };
 
-        // Parse request
-        let request: Request = match bincode::deserialize(&body) {
-            Ok(req) => req,
+        let request = match request {
+            Ok(request) => request,
             Err(e) => {
-                let response = Response::Error {
-                    code: ErrorCode::InvalidRequest,
-                    message: format!("Failed to parse request: {}", e),
-                };
-                send_response(&mut stream, &response, compressor.as_ref()).await?;
+                let response = Response::error(ErrorCode::InvalidRequest, format!("Failed to parse request: {}", e));
+                let _ = tx.send((id, response)).await;
                 continue;
             }
         };
 
-        // Handle request
-        let response = handler.handle(request).await;
+        if matches!(request, Request::Disconnect) {
+            break Ok(Some(id));
+        }
 
-        // Send response
-        send_response(&mut stream, &response, compressor.as_ref()).await?;
+        // Subscriptions last as long as the connection, outside the request limits
+        if let Request::Subscribe { after, filter } = request {
+            let stream = stream_changes(Arc::clone(&handler), session_user.clone(), id, after, filter, tx.clone());
+            subscriptions.spawn(stream.instrument(tracing::debug_span!("subscription", id)));
+            continue;
+        }
 
-        // Check for disconnect request
-        if matches!(response, Response::Goodbye) {
-            break;
+        let permit = Arc::clone(&in_flight).acquire_owned().await?;
+        let handler = Arc::clone(&handler);
+        let tx = tx.clone();
+        let user = session_user.clone();
+        tokio::spawn(async move {
+            let name = request.name();
+            let response = match timeout(request_timeout, handler.handle_as(user.as_deref(), request)).await {
+                Ok(response) => response,
+                Err(_) => {
+                    warn!("{} timed out after {:?}", name, request_timeout);
+                    let message = format!("{} timed out after {:?}", name, request_timeout);
+                    Response::error(ErrorCode::RequestTimeout, message)
+                }
+            };
+            let _ = tx.send((id, response)).await;
+            drop(permit);
+        }.instrument(tracing::debug_span!("frame", id)));
+    };
+
+    // Answer everything still in progress first
+    subscriptions.shutdown().await;
+    let _ = in_flight.acquire_many(MAX_IN_FLIGHT as u32).await;
+    match result {
+        Ok(Some(id)) => {
+            let _ = tx.send((id, Response::Goodbye)).await;
+        }
+        Ok(None) if shutdown.is_cancelled() => {
+            let response = Response::error(ErrorCode::ServerBusy, "Server is shutting down");
+            let _ = tx.send((CONNECTION_ERROR_ID, response)).await;
         }
+        _ => {}
     }
 
-    Ok(())
+    // The writer ends once every pending response is sent
+    drop(tx);
+    let written = writer_task.await?;
+    result?;
+    written
 }
 
-/// Send a response to the client
-async fn send_response(
-    stream: &mut TcpStream,
-    response: &Response,
-    compressor: Option<&crate::distributed::Compressor>,
-) -> Result<()> {
-    let body = bincode::serialize(response)?;
+/// Send a subscription's changes until the connection goes away
+async fn stream_changes(
+    handler: Arc<RequestHandler>,
+    user: Option<String>,
+    id: u64,
+    after: Option<u64>,
+    filter: ChangeFilter,
+    tx: mpsc::Sender<(u64, Response)>,
+) {
+    use futures::StreamExt;
 
-    let body = if let Some(comp) = compressor {
-        comp.compress(&body)?
-    } else {
-        body
+    let user = user.as_deref();
+    let (last_seq, mut changes) = match handler.subscribe(user, after, filter).await {
+        Ok(opened) => opened,
+        Err(response) => {
+            let _ = tx.send((id, response)).await;
+            return;
+        }
     };
+    if tx.send((id, Response::Subscribed { last_seq })).await.is_err() {
+        return;
+    }
 
-    let len = body.len() as u32;
-    stream.write_all(&len.to_le_bytes()).await?;
-    stream.write_all(&body).await?;
-    stream.flush().await?;
-
-    Ok(())
+    while let Some(event) = changes.next().await {
+        let response = match event {
+            Ok(event) if !handler.can_see(user, &event.change).await => continue,
+            Ok(event) => Response::Change(event),
+            Err(e) => Response::error(ErrorCode::InternalError, e.to_string()),
+        };
+        let failed = matches!(response, Response::Error { .. });
+        if tx.send((id, response)).await.is_err() || failed {
+            return;
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::storage::Value;
     use tempfile::TempDir;
 
     #[tokio::test]
@@ -243,4 +631,489 @@ mod tests {
 
         assert_eq!(server.connection_count(), 0);
     }
+
+    #[tokio::test]
+    async fn test_tls_and_authentication() {
+        use crate::client::ClientBuilder;
+
+        let temp = TempDir::new().unwrap();
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert_path = temp.path().join("cert.pem");
+        let key_path = temp.path().join("key.pem");
+        std::fs::write(&cert_path, cert.serialize_pem().unwrap()).unwrap();
+        std::fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+
+        let db = Database::create(temp.path().join("db"), "test").await.unwrap();
+        let users = UserStore::new(&db);
+        users.create_user("ada", "lovelace").await.unwrap();
+        let token = users.create_token("ada", Some("ci")).await.unwrap();
+
+        let addr = local_addrs(&["s1"])["s1"];
+        let config = ServerConfig {
+            bind_addr: addr,
+            tls: Some(TlsConfig::new(&cert_path, &key_path)),
+            require_auth: true,
+            ..Default::default()
+        };
+        let server = Arc::new(Server::new(db, config));
+        tokio::spawn(async move { server.run().await });
+
+        let builder = ClientBuilder::new()
+            .address(&addr.to_string())
+            .tls_ca_file(&cert_path)
+            .tls_server_name("localhost");
+
+        let mut client = None;
+        for _ in 0..100 {
+            match builder.clone().credentials("ada", "lovelace").build().await {
+                Ok(c) => {
+                    client = Some(c);
+                    break;
+                }
+                Err(_) => tokio::time::sleep(std::time::Duration::from_millis(20)).await,
+            }
+        }
+        let mut client = client.expect("server accepts the password");
+        assert_eq!(client.user(), Some("ada"));
+        client.ping().await.unwrap();
+
+        // Account nodes are invisible to clients
+        assert!(client.get_nodes_by_type("__user__", None).await.is_err());
+
+        let client = builder.clone().token(token).build().await.unwrap();
+        assert_eq!(client.user(), Some("ada"));
+
+        assert!(builder.clone().credentials("ada", "wrong").build().await.is_err());
+        assert!(builder.clone().build().await.is_err());
+
+        // Plain TCP cannot talk to a TLS listener
+        let plain = ClientBuilder::new().address(&addr.to_string()).timeout(1);
+        assert!(plain.credentials("ada", "lovelace").build().await.is_err());
+    }
+
+    /// Secret the replicated test servers share
+    const CLUSTER_SECRET: &str = "test-cluster-secret";
+
+    /// Start a replicated server `id` on `addrs[id]`, with its peer listener
+    /// on `peer_addrs[id]`, talking to `peers`
+    async fn start_replicated(
+        dir: &std::path::Path,
+        id: &str,
+        addrs: &std::collections::HashMap<String, SocketAddr>,
+        peer_addrs: &std::collections::HashMap<String, SocketAddr>,
+        peers: &[&str],
+        join: bool,
+    ) -> Arc<crate::distributed::RaftNode> {
+        use crate::distributed::{RaftNode, ReplicaConfig, ReplicaSet};
+
+        let dir = dir.join(id);
+        let db = Database::create(&dir, id).await.unwrap();
+        let peers: std::collections::HashMap<String, SocketAddr> = peers.iter()
+            .map(|peer| (peer.to_string(), peer_addrs[*peer]))
+            .collect();
+        let replica_config = ReplicaConfig {
+            node_id: id.to_string(),
+            peers: peers.keys().cloned().collect(),
+            addresses: addrs.iter().map(|(k, v)| (k.clone(), v.to_string())).collect(),
+            peer_addresses: peer_addrs.iter().map(|(k, v)| (k.clone(), v.to_string())).collect(),
+            heartbeat_interval_ms: 20,
+            election_timeout_ms: (100, 200),
+            join,
+            ..Default::default()
+        };
+        let replica = ReplicaSet::open(replica_config, dir.join(".aresadb/raft")).unwrap();
+        let security = PeerSecurity { tls: None, cluster_secret: Some(CLUSTER_SECRET.to_string()) };
+        let raft = RaftNode::start(replica, db, TcpTransport::with_security(peers, true, security));
+
+        let config = ServerConfig {
+            bind_addr: addrs[id],
+            peer_addr: Some(peer_addrs[id]),
+            cluster_secret: Some(CLUSTER_SECRET.to_string()),
+            ..Default::default()
+        };
+        let server = Arc::new(Server::with_replication(Arc::clone(&raft), config));
+        tokio::spawn(async move { server.run().await });
+        raft
+    }
+
+    /// Free local addresses for `ids`
+    fn local_addrs(ids: &[&str]) -> std::collections::HashMap<String, SocketAddr> {
+        ids.iter()
+            .map(|id| {
+                let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+                (id.to_string(), listener.local_addr().unwrap())
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_requests_and_frame_limits() {
+        use crate::client::ClientBuilder;
+
+        let temp = TempDir::new().unwrap();
+        let db = Database::create(temp.path().join("db"), "test").await.unwrap();
+        let addr = local_addrs(&["s1"])["s1"];
+        let config = ServerConfig {
+            bind_addr: addr,
+            max_frame_size: 64 * 1024,
+            ..Default::default()
+        };
+        let server = Arc::new(Server::new(db, config));
+        tokio::spawn(async move { server.run().await });
+
+        let builder = ClientBuilder::new().address(&addr.to_string()).compression(false);
+        let mut client = None;
+        for _ in 0..100 {
+            match builder.clone().build().await {
+                Ok(c) => {
+                    client = Some(c);
+                    break;
+                }
+                Err(_) => tokio::time::sleep(std::time::Duration::from_millis(20)).await,
+            }
+        }
+        let mut client = client.expect("server accepts connections");
+
+        // Responses come back matched to their requests
+        let requests = (0..100)
+            .map(|i| Request::InsertNode {
+                node_type: "item".to_string(),
+                properties: Value::from_json(serde_json::json!({ "n": i })).unwrap(),
+            })
+            .collect();
+        let responses = client.pipeline(requests).await.unwrap();
+        assert_eq!(responses.len(), 100);
+        for (i, response) in responses.into_iter().enumerate() {
+            match response {
+                Response::Node(node) => assert_eq!(node.properties.get("n"), Some(&Value::Int(i as i64))),
+                other => panic!("Unexpected response: {:?}", other),
+            }
+        }
+
+        // A response over the client's limit is refused, not sent
+        let mut small = builder.clone().max_frame_size(1024).build().await.unwrap();
+        let id = small.send(Request::GetNodesByType { node_type: "item".to_string(), limit: None }).await.unwrap();
+        let (answered, response) = small.recv().await.unwrap();
+        assert_eq!(answered, id);
+        assert!(matches!(response, Response::Error { code: ErrorCode::FrameTooLarge, .. }));
+        small.ping().await.unwrap();
+
+        // The client refuses to send a request over the server's limit
+        let big = Request::InsertNode {
+            node_type: "item".to_string(),
+            properties: Value::String("x".repeat(100_000)),
+        };
+        assert!(client.send(big).await.unwrap_err().is::<FrameTooLarge>());
+        client.ping().await.unwrap();
+
+        // The server answers an oversized frame on ID 0 and hangs up
+        let mut raw: Box<dyn Stream> = Box::new(tokio::net::TcpStream::connect(addr).await.unwrap());
+        let hello = Hello::new(false, DEFAULT_MAX_FRAME_SIZE, None);
+        let (_, codec) = handshake(&mut raw, &hello).await.unwrap();
+        raw.write_all(&1_000_000u32.to_le_bytes()).await.unwrap();
+        let (id, response) = read_message::<_, Response>(&mut raw, &codec).await.unwrap();
+        assert_eq!(id, CONNECTION_ERROR_ID);
+        assert!(matches!(response, Response::Error { code: ErrorCode::FrameTooLarge, .. }));
+        assert!(read_message::<_, Response>(&mut raw, &codec).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_connection_limit_idle_timeout_and_shutdown() {
+        use crate::client::ClientBuilder;
+
+        let temp = TempDir::new().unwrap();
+        let db = Database::create(temp.path().join("db"), "test").await.unwrap();
+        let addr = local_addrs(&["s1"])["s1"];
+        let config = ServerConfig {
+            bind_addr: addr,
+            max_connections: 1,
+            idle_timeout_secs: 1,
+            ..Default::default()
+        };
+        let server = Arc::new(Server::new(db, config));
+        let running = tokio::spawn({
+            let server = Arc::clone(&server);
+            async move { server.run().await }
+        });
+
+        let builder = ClientBuilder::new().address(&addr.to_string());
+        let mut client = None;
+        for _ in 0..100 {
+            match builder.clone().build().await {
+                Ok(c) => {
+                    client = Some(c);
+                    break;
+                }
+                Err(_) => tokio::time::sleep(Duration::from_millis(20)).await,
+            }
+        }
+        let mut client = client.expect("server accepts connections");
+        client.insert_node("item", serde_json::json!({})).await.unwrap();
+
+        // A client over the limit is told why
+        let Err(error) = builder.clone().build().await else {
+            panic!("second connection accepted");
+        };
+        assert!(format!("{:#}", error).contains("Server busy"), "{:#}", error);
+
+        // An idle connection is closed, freeing its slot
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(client.ping().await.is_err());
+        for _ in 0..100 {
+            if server.connection_count() == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        // Open connections are told when the server shuts down
+        let mut raw: Box<dyn Stream> = Box::new(TcpStream::connect(addr).await.unwrap());
+        let (_, codec) = handshake(&mut raw, &Hello::new(false, DEFAULT_MAX_FRAME_SIZE, None)).await.unwrap();
+        write_message(&mut raw, &codec, 1, &Request::Ping).await.unwrap();
+        assert!(matches!(read_message::<_, Response>(&mut raw, &codec).await.unwrap(), (1, Response::Pong)));
+
+        server.shutdown();
+        let (id, response) = read_message::<_, Response>(&mut raw, &codec).await.unwrap();
+        assert_eq!(id, CONNECTION_ERROR_ID);
+        assert!(matches!(response, Response::Error { code: ErrorCode::ServerBusy, .. }));
+
+        timeout(Duration::from_secs(5), running).await.unwrap().unwrap().unwrap();
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_front_ends_share_the_limit_and_shutdown() {
+        use crate::client::ClientBuilder;
+
+        let temp = TempDir::new().unwrap();
+        let db = Database::create(temp.path().join("db"), "test").await.unwrap();
+        let addrs = local_addrs(&["s1", "pg"]);
+        let config = ServerConfig {
+            bind_addr: addrs["s1"],
+            max_connections: 1,
+            shutdown_timeout_secs: 1,
+            ..Default::default()
+        };
+        let pg = PgConfig { bind_addr: addrs["pg"], ..Default::default() };
+        let server = Arc::new(Server::new(db, config).with_pg(pg));
+        let running = tokio::spawn({
+            let server = Arc::clone(&server);
+            async move { server.run().await }
+        });
+
+        // A Postgres client that never starts up holds the only slot
+        let mut _stalled = None;
+        for _ in 0..100 {
+            if let Ok(stream) = TcpStream::connect(addrs["pg"]).await {
+                _stalled = Some(stream);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        for _ in 0..100 {
+            if server.connection_count() == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(server.connection_count(), 1);
+        let Err(error) = ClientBuilder::new().address(&addrs["s1"].to_string()).build().await else {
+            panic!("connection over the limit accepted");
+        };
+        assert!(format!("{:#}", error).contains("Server busy"), "{:#}", error);
+
+        // Shutdown stops the Postgres listener and closes the stalled client
+        // once the drain deadline passes
+        server.shutdown();
+        timeout(Duration::from_secs(5), running).await.unwrap().unwrap().unwrap();
+        assert_eq!(server.connection_count(), 0);
+        assert!(TcpStream::connect(addrs["pg"]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_streams_filtered_changes() {
+        use crate::client::ClientBuilder;
+
+        let temp = TempDir::new().unwrap();
+        let db = Database::create(temp.path().join("db"), "test").await.unwrap();
+        let addr = local_addrs(&["s1"])["s1"];
+        let config = ServerConfig {
+            bind_addr: addr,
+            idle_timeout_secs: 1,
+            ..Default::default()
+        };
+        let server = Arc::new(Server::new(db, config));
+        tokio::spawn(async move { server.run().await });
+
+        let builder = ClientBuilder::new().address(&addr.to_string());
+        let mut writer = None;
+        for _ in 0..100 {
+            match builder.clone().build().await {
+                Ok(c) => {
+                    writer = Some(c);
+                    break;
+                }
+                Err(_) => tokio::time::sleep(Duration::from_millis(20)).await,
+            }
+        }
+        let mut writer = writer.expect("server accepts connections");
+        let first = writer.insert_node("user", serde_json::json!({ "name": "Ada" })).await.unwrap();
+
+        let filter = ChangeFilter::all().node_type("user");
+        let mut users = builder.clone().build().await.unwrap().subscribe(Some(0), filter.clone()).await.unwrap();
+        assert_eq!(users.last_seq(), 1);
+        let event = users.next().await.unwrap();
+        assert_eq!((event.seq, event.change.node().map(|n| n.id.clone())), (1, Some(first.id)));
+
+        // Other types are left out, and streaming keeps an idle connection open
+        writer.insert_node("post", serde_json::json!({})).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        let mut writer = builder.clone().build().await.unwrap();
+        let second = writer.insert_node("user", serde_json::json!({ "name": "Grace" })).await.unwrap();
+        let event = users.next().await.unwrap();
+        assert_eq!(event.seq, 3);
+        assert_eq!(event.change.kind(), "node_inserted");
+
+        // Resuming picks up after the last sequence handled
+        let mut resumed = builder.clone().build().await.unwrap().subscribe(Some(1), filter).await.unwrap();
+        assert_eq!(resumed.next().await.unwrap().change.node().map(|n| n.id.clone()), Some(second.id));
+
+        // Account nodes are never streamed
+        let hidden = builder.clone().build().await.unwrap()
+            .subscribe(None, ChangeFilter::all().node_type(auth::USER_TYPE)).await;
+        assert!(hidden.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_replicated_servers_redirect_and_replicate() {
+        use crate::client::Client;
+
+        let temp = TempDir::new().unwrap();
+        let ids = ["s1", "s2", "s3"];
+        let addrs = local_addrs(&ids);
+        let peer_addrs = local_addrs(&ids);
+
+        let mut nodes = Vec::new();
+        for id in ids {
+            let peers: Vec<&str> = ids.iter().copied().filter(|p| *p != id).collect();
+            nodes.push(start_replicated(temp.path(), id, &addrs, &peer_addrs, &peers, false).await);
+        }
+
+        let mut leader = None;
+        for _ in 0..300 {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            if let Some(node) = nodes.iter().find(|n| n.replica().is_leader()) {
+                leader = Some(node.replica().node_id().to_string());
+                break;
+            }
+        }
+        let leader = leader.expect("leader elected");
+        let follower = ids.iter().find(|id| **id != leader).unwrap();
+
+        // Followers redirect writes to the leader's address
+        let mut client = Client::connect(addrs[*follower]).await.unwrap();
+        let missing = crate::storage::NodeId::new().to_string();
+        let err = client.delete_node(&missing).await.unwrap_err();
+        let redirect = err.downcast_ref::<crate::distributed::NotLeader>().unwrap();
+        assert_eq!(redirect.leader_addr.as_deref(), Some(addrs[&leader].to_string().as_str()));
+
+        let mut client = Client::connect(addrs[&leader]).await.unwrap();
+        client.delete_node(&missing).await.unwrap();
+
+        // Consensus messages are refused on the client port, and the peer
+        // listener turns away connections without the cluster secret
+        let heartbeat = Request::Raft(crate::distributed::ConsensusMessage::TimeoutNow {
+            term: u64::MAX,
+            leader_id: "intruder".to_string(),
+        });
+        let mut raw: Box<dyn Stream> = Box::new(TcpStream::connect(addrs[&leader]).await.unwrap());
+        let (_, codec) = handshake(&mut raw, &Hello::new(false, DEFAULT_MAX_FRAME_SIZE, None)).await.unwrap();
+        write_message(&mut raw, &codec, 1, &heartbeat).await.unwrap();
+        let (_, response) = read_message::<_, Response>(&mut raw, &codec).await.unwrap();
+        assert!(matches!(response, Response::Error { code: ErrorCode::PermissionDenied, .. }));
+
+        for credentials in [None, Some(Credentials::token("wrong"))] {
+            let mut raw: Box<dyn Stream> = Box::new(TcpStream::connect(peer_addrs[&leader]).await.unwrap());
+            assert!(handshake(&mut raw, &Hello::new(false, DEFAULT_MAX_FRAME_SIZE, credentials)).await.is_err());
+        }
+        assert!(nodes.iter().any(|n| n.replica().is_leader()));
+
+        // Writes committed on the leader become visible on the follower
+        let raft = nodes.iter().find(|n| n.replica().is_leader()).unwrap();
+        let node = crate::storage::Node::new("user", Value::from_json(serde_json::json!({"name": "Ada"})).unwrap());
+        raft.propose(crate::distributed::ReplicationCommand::insert_node(&node).unwrap()).await.unwrap();
+
+        let mut client = Client::connect(addrs[*follower]).await.unwrap();
+        let mut count = 0;
+        for _ in 0..100 {
+            count = client.status().await.unwrap().node_count;
+            if count == 1 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert_eq!(count, 1);
+
+        for node in nodes {
+            node.shutdown();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_admin_adds_member_and_transfers_leadership() {
+        use crate::distributed::{MemberRole, NotLeader};
+        use crate::client::Client;
+        use std::time::Duration;
+
+        let temp = TempDir::new().unwrap();
+        let ids = ["s1", "s2", "s3"];
+        let addrs = local_addrs(&["s1", "s2", "s3", "s4"]);
+        let peer_addrs = local_addrs(&["s1", "s2", "s3", "s4"]);
+
+        let mut nodes = Vec::new();
+        for id in ids {
+            let peers: Vec<&str> = ids.iter().copied().filter(|p| *p != id).collect();
+            nodes.push(start_replicated(temp.path(), id, &addrs, &peer_addrs, &peers, false).await);
+        }
+
+        let mut leader = None;
+        for _ in 0..300 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            if let Some(node) = nodes.iter().find(|n| n.replica().is_leader()) {
+                leader = Some(node.replica().node_id().to_string());
+                break;
+            }
+        }
+        let leader = leader.expect("leader elected");
+        let follower = ids.iter().find(|id| **id != leader).unwrap();
+
+        let joiner = start_replicated(temp.path(), "s4", &addrs, &peer_addrs, &ids, true).await;
+        let s4 = addrs["s4"].to_string();
+        let s4_peer = peer_addrs["s4"].to_string();
+
+        let mut client = Client::connect(addrs[*follower]).await.unwrap();
+        let err = client.add_member("s4", &s4, &s4_peer, true).await.unwrap_err();
+        assert!(err.downcast_ref::<NotLeader>().is_some());
+
+        let mut client = Client::connect(addrs[&leader]).await.unwrap();
+        let members = client.add_member("s4", &s4, &s4_peer, true).await.unwrap();
+        let added = members.iter().find(|m| m.id == "s4").unwrap();
+        assert_eq!(added.role, MemberRole::Voter);
+        assert_eq!(added.address.as_deref(), Some(s4.as_str()));
+        assert!(client.add_member("s4", &s4, &s4_peer, true).await.is_err());
+
+        let members = client.transfer_leadership("s4").await.unwrap();
+        assert!(members.iter().any(|m| m.id == "s4" && m.is_leader));
+        assert!(joiner.replica().is_leader());
+
+        let mut client = Client::connect(addrs["s4"]).await.unwrap();
+        let members = client.remove_member(&leader).await.unwrap();
+        assert_eq!(members.len(), 3);
+        assert!(members.iter().all(|m| m.id != leader));
+
+        joiner.shutdown();
+        for node in nodes {
+            node.shutdown();
+        }
+    }
 }
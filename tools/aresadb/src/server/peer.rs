@@ -0,0 +1,278 @@
+//! Raft Peer Transport
+//!
+//! Carries consensus messages between replicated servers as
+//! [`Request::Raft`] frames. Each server accepts them on a peer listener of
+//! its own, separate from the client port, where every connection must
+//! present the cluster secret in its handshake. Each peer gets a bounded
+//! queue drained by its own task, so a slow or unreachable peer never
+//! stalls the Raft loop; messages that do not fit are dropped. Members
+//! added to the cluster later get a queue as soon as their peer address
+//! appears in the replicated membership. Peers connect over TLS when the
+//! cluster serves it.
+
+use anyhow::{Context, Result};
+use ring::digest;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use parking_lot::RwLock;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+use super::auth::Credentials;
+use super::protocol::{
+    handshake, read_handshake, read_message, write_handshake, write_message, ErrorCode, FrameCodec,
+    HandshakeReply, Hello, Request, Response, Welcome, COMPRESSION_LZ4, DEFAULT_MAX_FRAME_SIZE, PROTOCOL_VERSION,
+};
+use super::tls::{dial, ClientTlsConfig, Stream};
+use super::{accept, ServerConfig};
+use crate::distributed::{ConsensusMessage, Envelope, Membership, RaftNode, RaftTransport};
+
+/// Messages buffered per peer before new ones are dropped
+const PEER_QUEUE_SIZE: usize = 1024;
+
+/// Time allowed to connect or exchange one message
+const PEER_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// TCP transport between replicated servers
+pub struct TcpTransport {
+    queues: RwLock<HashMap<String, Peer>>,
+    compression: bool,
+    security: PeerSecurity,
+}
+
+/// How a server connects and authenticates to its peers
+#[derive(Debug, Clone, Default)]
+pub struct PeerSecurity {
+    /// Connect over TLS, trusting these roots
+    pub tls: Option<ClientTlsConfig>,
+    /// Cluster secret presented to the peers' listeners
+    pub cluster_secret: Option<String>,
+}
+
+/// Queue feeding one peer's sender task
+struct Peer {
+    addr: SocketAddr,
+    queue: mpsc::Sender<ConsensusMessage>,
+}
+
+impl TcpTransport {
+    /// Start one sender task per peer
+    ///
+    /// `compression` must match the peers' server configuration.
+    pub fn new(peers: HashMap<String, SocketAddr>, compression: bool) -> Arc<Self> {
+        Self::with_security(peers, compression, PeerSecurity::default())
+    }
+
+    /// Start one sender task per peer, connecting with `security`
+    pub fn with_security(
+        peers: HashMap<String, SocketAddr>,
+        compression: bool,
+        security: PeerSecurity,
+    ) -> Arc<Self> {
+        let transport = Arc::new(Self {
+            queues: RwLock::new(HashMap::new()),
+            compression,
+            security,
+        });
+        for (id, addr) in peers {
+            transport.connect(id, addr);
+        }
+        transport
+    }
+
+    /// Start (or restart) the sender task for `id`
+    fn connect(&self, id: String, addr: SocketAddr) {
+        let (tx, rx) = mpsc::channel(PEER_QUEUE_SIZE);
+        tokio::spawn(peer_loop(id.clone(), addr, self.compression, self.security.clone(), rx));
+        // Dropping the old sender stops the old task
+        self.queues.write().insert(id, Peer { addr, queue: tx });
+    }
+}
+
+impl RaftTransport for TcpTransport {
+    fn send(&self, envelope: Envelope) {
+        if let Some(peer) = self.queues.read().get(&envelope.to) {
+            let _ = peer.queue.try_send(envelope.message);
+        }
+    }
+
+    fn update_members(&self, membership: &Membership) {
+        for (id, addr) in &membership.peer_addresses {
+            let Ok(addr) = addr.parse::<SocketAddr>() else {
+                debug!("Ignoring unparseable address {} for peer {}", addr, id);
+                continue;
+            };
+            let known = self.queues.read().get(id).map(|p| p.addr);
+            if known != Some(addr) {
+                self.connect(id.clone(), addr);
+            }
+        }
+    }
+}
+
+/// Deliver queued messages to one peer, reconnecting as needed
+async fn peer_loop(
+    id: String,
+    addr: SocketAddr,
+    compression: bool,
+    security: PeerSecurity,
+    mut rx: mpsc::Receiver<ConsensusMessage>,
+) {
+    let hello = Hello::new(compression, DEFAULT_MAX_FRAME_SIZE, security.cluster_secret.clone().map(Credentials::token));
+    let mut stream: Option<(Box<dyn Stream>, FrameCodec)> = None;
+    let mut next_id = 1u64;
+
+    while let Some(message) = rx.recv().await {
+        if stream.is_none() {
+            let open = async {
+                let host = addr.ip().to_string();
+                let mut s = dial(addr, &host, security.tls.as_ref()).await?;
+                let (_, codec) = handshake(&mut s, &hello).await?;
+                anyhow::Ok((s, codec))
+            };
+            match tokio::time::timeout(PEER_TIMEOUT, open).await {
+                Ok(Ok(s)) => stream = Some(s),
+                Ok(Err(e)) => {
+                    debug!("Failed to connect to peer {}: {:#}", id, e);
+                    continue;
+                }
+                // Unreachable: drop the message, Raft will retry
+                Err(_) => continue,
+            }
+        }
+
+        let Some((s, codec)) = stream.as_mut() else { continue };
+        let id = next_id;
+        next_id += 1;
+        let exchange = async {
+            write_message(s, codec, id, &Request::Raft(message)).await?;
+            // One message at a time, so the reply is this message's
+            let (_, response) = read_message::<_, Response>(s, codec).await?;
+            anyhow::Ok(response)
+        };
+
+        match tokio::time::timeout(PEER_TIMEOUT, exchange).await {
+            Ok(Ok(Response::Ok)) => {}
+            Ok(Ok(other)) => debug!("Peer {} rejected consensus message: {:?}", id, other),
+            Ok(Err(e)) => {
+                debug!("Lost connection to peer {}: {}", id, e);
+                stream = None;
+            }
+            Err(_) => {
+                debug!("Timed out sending to peer {}", id);
+                stream = None;
+            }
+        }
+    }
+}
+
+/// Accept consensus messages from other replicas until `shutdown`
+pub(crate) async fn serve_peers(
+    listener: TcpListener,
+    acceptor: Option<TlsAcceptor>,
+    raft: Arc<RaftNode>,
+    config: ServerConfig,
+    shutdown: CancellationToken,
+) {
+    let mut connections = JoinSet::new();
+    loop {
+        let accepted = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            accepted = listener.accept() => accepted,
+        };
+        let (stream, addr) = match accepted {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Peer accept error: {}", e);
+                continue;
+            }
+        };
+        let _ = stream.set_nodelay(true);
+
+        let acceptor = acceptor.clone();
+        let raft = Arc::clone(&raft);
+        let config = config.clone();
+        let shutdown = shutdown.clone();
+        connections.spawn(async move {
+            if let Err(e) = serve_peer(stream, acceptor, raft, &config, shutdown).await {
+                debug!("Peer connection from {} closed: {:#}", addr, e);
+            }
+        });
+        while connections.try_join_next().is_some() {}
+    }
+    // Raft tolerates the messages lost here
+    connections.shutdown().await;
+}
+
+/// Deliver one peer's consensus messages to `raft`
+///
+/// The peer must present the cluster secret as a token; nothing but
+/// [`Request::Raft`] is served.
+async fn serve_peer(
+    stream: TcpStream,
+    acceptor: Option<TlsAcceptor>,
+    raft: Arc<RaftNode>,
+    config: &ServerConfig,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    let mut stream = accept(stream, acceptor, config).await?;
+    let hello: Hello = timeout(config.read_timeout(), read_handshake(&mut stream))
+        .await
+        .context("Timed out waiting for handshake")??;
+
+    let authenticated = match (&hello.credentials, &config.cluster_secret) {
+        (Some(Credentials::Token(presented)), Some(secret)) => same_secret(presented, secret),
+        _ => false,
+    };
+    if !authenticated {
+        let reply = HandshakeReply::Rejected {
+            code: ErrorCode::AuthenticationFailed,
+            message: "Peers must present the cluster secret".to_string(),
+        };
+        return write_handshake(&mut stream, &reply).await;
+    }
+
+    let compression = config.compression && hello.compression.iter().any(|c| c == COMPRESSION_LZ4);
+    let welcome = Welcome {
+        protocol_version: hello.protocol_version.min(PROTOCOL_VERSION),
+        compression: compression.then(|| COMPRESSION_LZ4.to_string()),
+        max_frame_size: config.max_frame_size as u64,
+        user: None,
+    };
+    write_handshake(&mut stream, &HandshakeReply::Welcome(welcome)).await?;
+    let codec = FrameCodec::new(compression, hello.max_frame_size as usize, config.max_frame_size);
+
+    let idle_timeout = Duration::from_secs(config.idle_timeout_secs);
+    loop {
+        let read = tokio::select! {
+            _ = shutdown.cancelled() => return Ok(()),
+            read = timeout(idle_timeout, read_message::<_, Request>(&mut stream, &codec)) => read,
+        };
+        let (id, request) = read.context("Peer connection idle")??;
+        let response = match request {
+            Request::Raft(message) => {
+                raft.deliver(message);
+                Response::Ok
+            }
+            Request::Disconnect => return Ok(()),
+            _ => Response::error(ErrorCode::PermissionDenied, "The peer listener only accepts consensus messages"),
+        };
+        timeout(config.write_timeout(), write_message(&mut stream, &codec, id, &response))
+            .await
+            .context("Timed out sending a response")??;
+    }
+}
+
+/// Compare secrets by digest, so the time taken reveals nothing about them
+fn same_secret(presented: &str, secret: &str) -> bool {
+    let presented = digest::digest(&digest::SHA256, presented.as_bytes());
+    let secret = digest::digest(&digest::SHA256, secret.as_bytes());
+    presented.as_ref() == secret.as_ref()
+}
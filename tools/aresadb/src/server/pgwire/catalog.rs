@@ -0,0 +1,786 @@
+//! Catalog Queries
+//!
+//! Drivers and GUI tools probe a server before running anything else:
+//! `SELECT version()`, `SET`/`SHOW` of session settings, and lookups in
+//! `pg_catalog` and `information_schema`. None of these reach the query
+//! engine. They are answered here, with each schema in the registry
+//! exposed as a table of the `public` schema whose columns are `id`,
+//! `type` and the schema's fields.
+//!
+//! Catalog tables are evaluated in memory and support a single table with
+//! `WHERE` (comparisons, `AND`/`OR`/`NOT`, `IN`, `LIKE`, `IS NULL`),
+//! `ORDER BY`, `LIMIT` and `OFFSET`. Joins and subqueries over them are
+//! refused rather than answered wrongly.
+
+use anyhow::{anyhow, bail, Result};
+use sqlparser::ast::{
+    self, BinaryOperator, Expr, FunctionArg, FunctionArgExpr, ObjectName, Query, Select, SelectItem,
+    SetExpr, Statement, TableFactor, UnaryOperator,
+};
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::parser::Parser;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use super::{PgType, SERVER_VERSION};
+use crate::schema::{FieldType, Schema};
+use crate::storage::Value;
+
+/// OID of the `pg_catalog` namespace
+const PG_CATALOG_OID: i64 = 11;
+
+/// OID of the `public` namespace
+const PUBLIC_OID: i64 = 2200;
+
+/// OID of the `information_schema` namespace
+const INFORMATION_SCHEMA_OID: i64 = 13000;
+
+/// OID of the one database
+const DATABASE_OID: i64 = 5;
+
+/// Registered schemas get table OIDs from here on, as user tables do in Postgres
+const FIRST_TABLE_OID: i64 = 16384;
+
+/// Owner reported for every object
+const OWNER: &str = "aresadb";
+
+/// Connection state catalog queries read and `SET` changes
+pub(super) struct Session {
+    /// User the client logged in as
+    pub user: String,
+    /// Database named in the startup message
+    pub database: String,
+    /// Settings reported by `SHOW` and `current_setting()`
+    pub parameters: BTreeMap<String, String>,
+}
+
+/// Outcome of a statement answered from the catalog
+pub(super) enum Answer {
+    /// Result rows
+    Rows { columns: Vec<String>, rows: Vec<Vec<Value>> },
+    /// A command without rows, by its completion tag
+    Command(String),
+}
+
+/// Answer `sql` if it is a session command or a catalog query
+///
+/// `schemas` loads the registry and is only called for queries that read
+/// it. Returns `None` for statements the query engine should run.
+pub(super) async fn answer<F, Fut>(sql: &str, session: &mut Session, schemas: F) -> Option<Result<Answer>>
+where
+    F: FnOnce() -> Fut,
+    Fut: std::future::Future<Output = Result<Vec<Schema>>>,
+{
+    if let Some(answer) = session_command(sql, session) {
+        return Some(answer);
+    }
+
+    let statements = Parser::parse_sql(&PostgreSqlDialect {}, sql).ok()?;
+    let [Statement::Query(query)] = statements.as_slice() else {
+        return None;
+    };
+    let SetExpr::Select(select) = query.body.as_ref() else {
+        return reads_catalog(query).then(|| Err(anyhow!("Unsupported catalog query")));
+    };
+
+    match select.from.as_slice() {
+        [] => {
+            let row = Row { columns: &[], values: &[], session };
+            let mut columns = Vec::new();
+            let mut values = Vec::new();
+            for item in &select.projection {
+                let (name, expr) = match item {
+                    SelectItem::UnnamedExpr(expr) => (column_name(expr), expr),
+                    SelectItem::ExprWithAlias { expr, alias } => (alias.value.clone(), expr),
+                    _ => return None,
+                };
+                // Anything we can't evaluate is left for the query engine
+                columns.push(name);
+                values.push(row.eval(expr).ok()?);
+            }
+            Some(Ok(Answer::Rows { columns, rows: vec![values] }))
+        }
+        [from] if from.joins.is_empty() => {
+            let TableFactor::Table { name, .. } = &from.relation else {
+                return reads_catalog(query).then(|| Err(anyhow!("Unsupported catalog query")));
+            };
+            let table = CatalogTable::resolve(name)?;
+            let schemas = match table.reads_registry() {
+                true => match schemas().await {
+                    Ok(schemas) => schemas,
+                    Err(e) => return Some(Err(e)),
+                },
+                false => Vec::new(),
+            };
+            let (columns, rows) = table.contents(session, &schemas);
+            Some(select_from(query, select, &columns, rows, session).map(|(columns, rows)| Answer::Rows { columns, rows }))
+        }
+        _ => reads_catalog(query).then(|| Err(anyhow!("Unsupported catalog query: joins are not supported"))),
+    }
+}
+
+/// `SET`, `SHOW`, `RESET` and transaction control
+///
+/// Every statement commits on its own, so transaction control is
+/// acknowledged without doing anything.
+fn session_command(sql: &str, session: &mut Session) -> Option<Result<Answer>> {
+    let sql = sql.trim().trim_end_matches(';').trim();
+    let lower = sql.to_ascii_lowercase();
+    let mut words = lower.split_whitespace();
+    let tag = match words.next()? {
+        "begin" | "start" => "BEGIN",
+        "commit" | "end" => "COMMIT",
+        "rollback" | "abort" => "ROLLBACK",
+        "discard" => "DISCARD ALL",
+        "deallocate" => "DEALLOCATE",
+        "reset" => {
+            let name = words.next().unwrap_or("all");
+            if name != "all" {
+                session.parameters.remove(name);
+            }
+            "RESET"
+        }
+        "set" => {
+            return Some(set_parameter(&sql[3..], session).map(|()| Answer::Command("SET".to_string())));
+        }
+        "show" => {
+            let name = lower[4..].trim().trim_matches('"');
+            if name == "all" {
+                let rows = session
+                    .parameters
+                    .iter()
+                    .map(|(name, value)| vec![Value::String(name.clone()), Value::String(value.clone())])
+                    .collect();
+                return Some(Ok(Answer::Rows { columns: vec!["name".to_string(), "setting".to_string()], rows }));
+            }
+            let value = match session.parameters.get(name) {
+                Some(value) => value.clone(),
+                None => return Some(Err(anyhow!("unrecognized configuration parameter \"{}\"", name))),
+            };
+            return Some(Ok(Answer::Rows { columns: vec![name.to_string()], rows: vec![vec![Value::String(value)]] }));
+        }
+        _ => return None,
+    };
+    Some(Ok(Answer::Command(tag.to_string())))
+}
+
+/// Apply `[SESSION | LOCAL] name { TO | = } value` or `TIME ZONE value`
+fn set_parameter(rest: &str, session: &mut Session) -> Result<()> {
+    let mut rest = rest.trim();
+    for scope in ["session ", "local "] {
+        if rest.get(..scope.len()).is_some_and(|prefix| prefix.eq_ignore_ascii_case(scope)) {
+            rest = rest[scope.len()..].trim_start();
+        }
+    }
+
+    let (name, value) = if rest.get(..10).is_some_and(|prefix| prefix.eq_ignore_ascii_case("time zone ")) {
+        ("timezone".to_string(), &rest[10..])
+    } else {
+        let split = rest
+            .find('=')
+            .map(|i| (i, 1))
+            .or_else(|| rest.to_ascii_lowercase().find(" to ").map(|i| (i, 4)))
+            .ok_or_else(|| anyhow!("syntax error in SET"))?;
+        (rest[..split.0].trim().trim_matches('"').to_ascii_lowercase(), &rest[split.0 + split.1..])
+    };
+    if name.is_empty() {
+        bail!("syntax error in SET");
+    }
+
+    let value = value.trim().trim_matches('\'').trim_matches('"').to_string();
+    if value.eq_ignore_ascii_case("default") {
+        session.parameters.remove(&name);
+    } else {
+        session.parameters.insert(name, value);
+    }
+    Ok(())
+}
+
+/// Whether `query` touches a catalog table anywhere
+fn reads_catalog(query: &Query) -> bool {
+    let sql = query.to_string().to_ascii_lowercase();
+    sql.contains("pg_catalog") || sql.contains("information_schema") || sql.contains(" pg_")
+}
+
+/// Catalog tables that can be queried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CatalogTable {
+    Schemata,
+    Tables,
+    Columns,
+    PgNamespace,
+    PgClass,
+    PgTables,
+    PgAttribute,
+    PgType,
+    PgDatabase,
+    PgSettings,
+}
+
+impl CatalogTable {
+    /// Look up a table by its possibly schema-qualified name
+    fn resolve(name: &ObjectName) -> Option<Self> {
+        let parts: Vec<String> = name.0.iter().map(|ident| ident.value.to_ascii_lowercase()).collect();
+        let (schema, table) = match parts.as_slice() {
+            [table] => (None, table.as_str()),
+            [schema, table] => (Some(schema.as_str()), table.as_str()),
+            _ => return None,
+        };
+
+        match (schema, table) {
+            (Some("information_schema"), "schemata") => Some(Self::Schemata),
+            (Some("information_schema"), "tables") => Some(Self::Tables),
+            (Some("information_schema"), "columns") => Some(Self::Columns),
+            (Some("pg_catalog") | None, "pg_namespace") => Some(Self::PgNamespace),
+            (Some("pg_catalog") | None, "pg_class") => Some(Self::PgClass),
+            (Some("pg_catalog") | None, "pg_tables") => Some(Self::PgTables),
+            (Some("pg_catalog") | None, "pg_attribute") => Some(Self::PgAttribute),
+            (Some("pg_catalog") | None, "pg_type") => Some(Self::PgType),
+            (Some("pg_catalog") | None, "pg_database") => Some(Self::PgDatabase),
+            (Some("pg_catalog") | None, "pg_settings") => Some(Self::PgSettings),
+            _ => None,
+        }
+    }
+
+    /// Whether the contents come from the schema registry
+    fn reads_registry(self) -> bool {
+        matches!(self, Self::Tables | Self::Columns | Self::PgClass | Self::PgTables | Self::PgAttribute)
+    }
+
+    /// Column names and rows
+    fn contents(self, session: &Session, schemas: &[Schema]) -> (Vec<String>, Vec<Vec<Value>>) {
+        let text = |s: &str| Value::String(s.to_string());
+        let tables = || schemas.iter().enumerate().map(|(i, schema)| (FIRST_TABLE_OID + i as i64, schema));
+
+        let (columns, rows): (&[&str], Vec<Vec<Value>>) = match self {
+            Self::Schemata => (
+                &["catalog_name", "schema_name", "schema_owner"],
+                ["public", "pg_catalog", "information_schema"]
+                    .iter()
+                    .map(|schema| vec![text(&session.database), text(schema), text(OWNER)])
+                    .collect(),
+            ),
+            Self::Tables => (
+                &["table_catalog", "table_schema", "table_name", "table_type"],
+                tables()
+                    .map(|(_, schema)| {
+                        vec![text(&session.database), text("public"), text(&schema.name), text("BASE TABLE")]
+                    })
+                    .collect(),
+            ),
+            Self::Columns => (
+                &[
+                    "table_catalog", "table_schema", "table_name", "column_name", "ordinal_position",
+                    "column_default", "is_nullable", "data_type", "udt_name",
+                ],
+                tables()
+                    .flat_map(|(_, schema)| {
+                        table_columns(schema).into_iter().enumerate().map(|(i, column)| {
+                            vec![
+                                text(&session.database),
+                                text("public"),
+                                text(&schema.name),
+                                text(&column.name),
+                                Value::Int(i as i64 + 1),
+                                column.default.clone().map(Value::String).unwrap_or(Value::Null),
+                                text(if column.nullable { "YES" } else { "NO" }),
+                                text(column.pg_type.sql_name()),
+                                text(column.pg_type.name()),
+                            ]
+                        })
+                    })
+                    .collect(),
+            ),
+            Self::PgNamespace => (
+                &["oid", "nspname", "nspowner"],
+                [(PG_CATALOG_OID, "pg_catalog"), (PUBLIC_OID, "public"), (INFORMATION_SCHEMA_OID, "information_schema")]
+                    .iter()
+                    .map(|(oid, name)| vec![Value::Int(*oid), text(name), Value::Int(10)])
+                    .collect(),
+            ),
+            Self::PgClass => (
+                &["oid", "relname", "relnamespace", "relkind", "relowner", "relnatts"],
+                tables()
+                    .map(|(oid, schema)| {
+                        vec![
+                            Value::Int(oid),
+                            text(&schema.name),
+                            Value::Int(PUBLIC_OID),
+                            text("r"),
+                            Value::Int(10),
+                            Value::Int(table_columns(schema).len() as i64),
+                        ]
+                    })
+                    .collect(),
+            ),
+            Self::PgTables => (
+                &["schemaname", "tablename", "tableowner", "hasindexes"],
+                tables()
+                    .map(|(_, schema)| {
+                        let indexed = schema.fields.iter().any(|field| field.indexed || field.unique);
+                        vec![text("public"), text(&schema.name), text(OWNER), Value::Bool(indexed)]
+                    })
+                    .collect(),
+            ),
+            Self::PgAttribute => (
+                &["attrelid", "attname", "atttypid", "attnum", "attnotnull", "attisdropped"],
+                tables()
+                    .flat_map(|(oid, schema)| {
+                        table_columns(schema).into_iter().enumerate().map(move |(i, column)| {
+                            vec![
+                                Value::Int(oid),
+                                Value::String(column.name),
+                                Value::Int(column.pg_type.oid() as i64),
+                                Value::Int(i as i64 + 1),
+                                Value::Bool(!column.nullable),
+                                Value::Bool(false),
+                            ]
+                        })
+                    })
+                    .collect(),
+            ),
+            Self::PgType => (
+                &["oid", "typname", "typnamespace", "typlen", "typtype"],
+                PgType::ALL
+                    .iter()
+                    .map(|ty| {
+                        vec![Value::Int(ty.oid() as i64), text(ty.name()), Value::Int(PG_CATALOG_OID), Value::Int(ty.len() as i64), text("b")]
+                    })
+                    .collect(),
+            ),
+            Self::PgDatabase => (
+                &["oid", "datname", "encoding", "datallowconn"],
+                vec![vec![Value::Int(DATABASE_OID), text(&session.database), Value::Int(6), Value::Bool(true)]],
+            ),
+            Self::PgSettings => (
+                &["name", "setting"],
+                session.parameters.iter().map(|(name, value)| vec![text(name), text(value)]).collect(),
+            ),
+        };
+        (columns.iter().map(|column| column.to_string()).collect(), rows)
+    }
+}
+
+/// A column of a registered schema as a table column
+struct TableColumn {
+    name: String,
+    pg_type: PgType,
+    nullable: bool,
+    default: Option<String>,
+}
+
+/// `id` and `type`, then the schema's fields, as query results return them
+fn table_columns(schema: &Schema) -> Vec<TableColumn> {
+    let builtin = |name: &str| TableColumn { name: name.to_string(), pg_type: PgType::Text, nullable: false, default: None };
+    let mut columns = vec![builtin("id"), builtin("type")];
+    columns.extend(schema.fields.iter().map(|field| TableColumn {
+        name: field.name.clone(),
+        pg_type: field_pg_type(&field.field_type),
+        nullable: field.nullable,
+        default: field.default.clone(),
+    }));
+    columns
+}
+
+/// Type of column `name` in the table for `schema`
+pub(super) fn column_type(schema: &Schema, name: &str) -> Option<PgType> {
+    table_columns(schema).into_iter().find(|column| column.name == name).map(|column| column.pg_type)
+}
+
+/// Type a field's values are sent as
+fn field_pg_type(field_type: &FieldType) -> PgType {
+    match field_type {
+        FieldType::Int => PgType::Int8,
+        FieldType::Float => PgType::Float8,
+        FieldType::Bool => PgType::Bool,
+        FieldType::Bytes => PgType::Bytea,
+        FieldType::Json | FieldType::Array(_) => PgType::Json,
+        FieldType::String | FieldType::DateTime | FieldType::Uuid | FieldType::Enum(_) | FieldType::Reference(_) => {
+            PgType::Text
+        }
+    }
+}
+
+/// Filter, sort, limit and project catalog rows
+fn select_from(
+    query: &Query,
+    select: &Select,
+    columns: &[String],
+    rows: Vec<Vec<Value>>,
+    session: &Session,
+) -> Result<(Vec<String>, Vec<Vec<Value>>)> {
+    let mut selected = Vec::new();
+    for values in rows {
+        let keep = match &select.selection {
+            Some(condition) => Row { columns, values: &values, session }.eval(condition)? == Value::Bool(true),
+            None => true,
+        };
+        if keep {
+            selected.push(values);
+        }
+    }
+
+    if !query.order_by.is_empty() {
+        let mut keyed = selected
+            .into_iter()
+            .map(|values| {
+                let keys = query.order_by.iter().map(|order| Row { columns, values: &values, session }.eval(&order.expr)).collect::<Result<Vec<_>>>()?;
+                Ok((keys, values))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        keyed.sort_by(|(a, _), (b, _)| {
+            for (order, (a, b)) in query.order_by.iter().zip(a.iter().zip(b)) {
+                let ordering = compare(a, b).unwrap_or(Ordering::Equal);
+                let ordering = if order.asc == Some(false) { ordering.reverse() } else { ordering };
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            Ordering::Equal
+        });
+        selected = keyed.into_iter().map(|(_, values)| values).collect();
+    }
+
+    let offset = match &query.offset {
+        Some(offset) => count(&offset.value)?,
+        None => 0,
+    };
+    let limit = match &query.limit {
+        Some(limit) => count(limit)?,
+        None => usize::MAX,
+    };
+    let selected: Vec<_> = selected.into_iter().skip(offset).take(limit).collect();
+
+    let mut names = Vec::new();
+    let mut exprs = Vec::new();
+    for item in &select.projection {
+        match item {
+            SelectItem::Wildcard(_) | SelectItem::QualifiedWildcard(..) => {
+                for name in columns {
+                    names.push(name.clone());
+                    exprs.push(None);
+                }
+            }
+            SelectItem::UnnamedExpr(expr) => {
+                names.push(column_name(expr));
+                exprs.push(Some(expr));
+            }
+            SelectItem::ExprWithAlias { expr, alias } => {
+                names.push(alias.value.clone());
+                exprs.push(Some(expr));
+            }
+        }
+    }
+
+    let mut projected = Vec::with_capacity(selected.len());
+    for values in &selected {
+        let mut out = Vec::with_capacity(exprs.len());
+        let mut wildcard = values.iter();
+        for expr in &exprs {
+            out.push(match expr {
+                Some(expr) => Row { columns, values, session }.eval(expr)?,
+                None => wildcard.next().cloned().unwrap_or(Value::Null),
+            });
+        }
+        projected.push(out);
+    }
+    Ok((names, projected))
+}
+
+/// A `LIMIT` or `OFFSET` count
+fn count(expr: &Expr) -> Result<usize> {
+    match expr {
+        Expr::Value(ast::Value::Number(n, _)) => n.parse().map_err(|_| anyhow!("Invalid row count: {}", n)),
+        _ => bail!("Unsupported row count: {}", expr),
+    }
+}
+
+/// Name Postgres gives an unaliased result column
+fn column_name(expr: &Expr) -> String {
+    match expr {
+        Expr::Identifier(ident) => ident.value.clone(),
+        Expr::CompoundIdentifier(idents) => idents.last().map(|ident| ident.value.clone()).unwrap_or_default(),
+        Expr::Function(function) => function.name.0.last().map(|ident| ident.value.to_ascii_lowercase()).unwrap_or_default(),
+        Expr::Cast { expr, .. } | Expr::Nested(expr) => column_name(expr),
+        _ => "?column?".to_string(),
+    }
+}
+
+/// A row being evaluated against
+struct Row<'a> {
+    columns: &'a [String],
+    values: &'a [Value],
+    session: &'a Session,
+}
+
+impl Row<'_> {
+    fn eval(&self, expr: &Expr) -> Result<Value> {
+        match expr {
+            Expr::Identifier(ident) => self.column(&ident.value),
+            Expr::CompoundIdentifier(idents) => match idents.last() {
+                Some(ident) => self.column(&ident.value),
+                None => bail!("Empty identifier"),
+            },
+            Expr::Value(value) => literal(value),
+            Expr::Nested(expr) => self.eval(expr),
+            // Casts to reg* types and the like; values keep their own type
+            Expr::Cast { expr, .. } => self.eval(expr),
+            Expr::IsNull(expr) => Ok(Value::Bool(self.eval(expr)?.is_null())),
+            Expr::IsNotNull(expr) => Ok(Value::Bool(!self.eval(expr)?.is_null())),
+            Expr::UnaryOp { op: UnaryOperator::Not, expr } => match self.eval(expr)? {
+                Value::Bool(b) => Ok(Value::Bool(!b)),
+                _ => Ok(Value::Null),
+            },
+            Expr::UnaryOp { op: UnaryOperator::Minus, expr } => match self.eval(expr)? {
+                Value::Int(i) => Ok(Value::Int(-i)),
+                Value::Float(f) => Ok(Value::Float(-f)),
+                _ => Ok(Value::Null),
+            },
+            Expr::InList { expr, list, negated } => {
+                let value = self.eval(expr)?;
+                let mut found = false;
+                for item in list {
+                    found |= compare(&value, &self.eval(item)?) == Some(Ordering::Equal);
+                }
+                Ok(Value::Bool(found != *negated))
+            }
+            Expr::Like { negated, expr, pattern, .. } => self.like(expr, pattern, *negated, false),
+            Expr::ILike { negated, expr, pattern, .. } => self.like(expr, pattern, *negated, true),
+            Expr::BinaryOp { left, op, right } => {
+                let (left, right) = (self.eval(left)?, self.eval(right)?);
+                let ordering = compare(&left, &right);
+                let result = match op {
+                    BinaryOperator::And => Value::Bool(left == Value::Bool(true) && right == Value::Bool(true)),
+                    BinaryOperator::Or => Value::Bool(left == Value::Bool(true) || right == Value::Bool(true)),
+                    BinaryOperator::StringConcat => match (left, right) {
+                        (Value::Null, _) | (_, Value::Null) => Value::Null,
+                        (left, right) => Value::String(format!("{}{}", text(&left), text(&right))),
+                    },
+                    _ if ordering.is_none() => Value::Null,
+                    BinaryOperator::Eq => Value::Bool(ordering == Some(Ordering::Equal)),
+                    BinaryOperator::NotEq => Value::Bool(ordering != Some(Ordering::Equal)),
+                    BinaryOperator::Lt => Value::Bool(ordering == Some(Ordering::Less)),
+                    BinaryOperator::LtEq => Value::Bool(ordering != Some(Ordering::Greater)),
+                    BinaryOperator::Gt => Value::Bool(ordering == Some(Ordering::Greater)),
+                    BinaryOperator::GtEq => Value::Bool(ordering != Some(Ordering::Less)),
+                    _ => bail!("Unsupported operator in catalog query: {}", op),
+                };
+                Ok(result)
+            }
+            Expr::Function(function) => {
+                let name = function.name.0.last().map(|ident| ident.value.to_ascii_lowercase()).unwrap_or_default();
+                let args = function
+                    .args
+                    .iter()
+                    .map(|arg| match arg {
+                        FunctionArg::Unnamed(FunctionArgExpr::Expr(expr)) => self.eval(expr),
+                        _ => bail!("Unsupported argument to {}", name),
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                self.function(&name, &args)
+            }
+            _ => bail!("Unsupported expression in catalog query: {}", expr),
+        }
+    }
+
+    /// `LIKE`, or `ILIKE` when `case_insensitive`
+    fn like(&self, expr: &Expr, pattern: &Expr, negated: bool, case_insensitive: bool) -> Result<Value> {
+        let (Value::String(text), Value::String(pattern)) = (self.eval(expr)?, self.eval(pattern)?) else {
+            return Ok(Value::Null);
+        };
+        let matched = match case_insensitive {
+            true => like(&text.to_lowercase(), &pattern.to_lowercase()),
+            false => like(&text, &pattern),
+        };
+        Ok(Value::Bool(matched != negated))
+    }
+
+    /// Value of a column, or of a function Postgres lets be called bare
+    fn column(&self, name: &str) -> Result<Value> {
+        let lower = name.to_ascii_lowercase();
+        match self.columns.iter().position(|column| *column == lower) {
+            Some(i) => Ok(self.values.get(i).cloned().unwrap_or(Value::Null)),
+            None => self.function(&lower, &[]),
+        }
+    }
+
+    fn function(&self, name: &str, args: &[Value]) -> Result<Value> {
+        let session = self.session;
+        let value = match (name, args) {
+            ("version", []) => {
+                Value::String(format!("PostgreSQL {} (AresaDB {})", SERVER_VERSION, env!("CARGO_PKG_VERSION")))
+            }
+            ("current_schema", []) => Value::String("public".to_string()),
+            ("current_schemas", [_]) => Value::Array(vec![Value::String("public".to_string())]),
+            ("current_database" | "current_catalog", []) => Value::String(session.database.clone()),
+            ("current_user" | "session_user" | "user", []) => Value::String(session.user.clone()),
+            ("current_setting", [Value::String(setting)]) => match session.parameters.get(&setting.to_ascii_lowercase()) {
+                Some(value) => Value::String(value.clone()),
+                None => bail!("unrecognized configuration parameter \"{}\"", setting),
+            },
+            ("pg_backend_pid", []) => Value::Int(std::process::id() as i64),
+            ("pg_is_in_recovery", []) => Value::Bool(false),
+            ("lower", [Value::String(s)]) => Value::String(s.to_lowercase()),
+            ("upper", [Value::String(s)]) => Value::String(s.to_uppercase()),
+            _ => bail!("Unsupported function in catalog query: {}", name),
+        };
+        Ok(value)
+    }
+}
+
+/// A literal from the AST
+fn literal(value: &ast::Value) -> Result<Value> {
+    Ok(match value {
+        ast::Value::Number(n, _) => match n.parse::<i64>() {
+            Ok(i) => Value::Int(i),
+            Err(_) => Value::Float(n.parse().map_err(|_| anyhow!("Invalid number: {}", n))?),
+        },
+        ast::Value::SingleQuotedString(s) | ast::Value::EscapedStringLiteral(s) => Value::String(s.clone()),
+        ast::Value::DollarQuotedString(s) => Value::String(s.value.clone()),
+        ast::Value::Boolean(b) => Value::Bool(*b),
+        ast::Value::Null => Value::Null,
+        _ => bail!("Unsupported literal in catalog query: {}", value),
+    })
+}
+
+/// Text form of a value, for concatenation
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Compare values of matching kinds; numbers compare with numeric strings
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+        (Value::Int(a), Value::Float(b)) => (*a as f64).partial_cmp(b),
+        (Value::Float(a), Value::Int(b)) => a.partial_cmp(&(*b as f64)),
+        (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        (Value::Int(_) | Value::Float(_), Value::String(s)) => compare(a, &literal(&ast::Value::Number(s.clone(), false)).ok()?),
+        (Value::String(s), Value::Int(_) | Value::Float(_)) => compare(&literal(&ast::Value::Number(s.clone(), false)).ok()?, b),
+        _ => None,
+    }
+}
+
+/// SQL `LIKE` with `%` and `_`
+fn like(text: &str, pattern: &str) -> bool {
+    let text: Vec<char> = text.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+
+    fn matches(text: &[char], pattern: &[char]) -> bool {
+        match pattern.split_first() {
+            None => text.is_empty(),
+            Some(('%', rest)) => (0..=text.len()).any(|i| matches(&text[i..], rest)),
+            Some(('_', rest)) => !text.is_empty() && matches(&text[1..], rest),
+            Some((c, rest)) => text.first() == Some(c) && matches(&text[1..], rest),
+        }
+    }
+    matches(&text, &pattern)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::SchemaField;
+
+    fn session() -> Session {
+        let mut parameters = BTreeMap::new();
+        parameters.insert("server_version".to_string(), SERVER_VERSION.to_string());
+        parameters.insert("timezone".to_string(), "UTC".to_string());
+        Session { user: "alice".to_string(), database: "app".to_string(), parameters }
+    }
+
+    fn schemas() -> Vec<Schema> {
+        vec![
+            Schema::new("post", vec![SchemaField::new("title", FieldType::String)]),
+            Schema::new("user", vec![SchemaField::new("age", FieldType::Int), SchemaField::new("name", FieldType::String)]),
+        ]
+    }
+
+    async fn run(sql: &str, session: &mut Session) -> Option<Result<Answer>> {
+        answer(sql, session, || async { Ok(schemas()) }).await
+    }
+
+    async fn select(sql: &str) -> (Vec<String>, Vec<Vec<Value>>) {
+        match run(sql, &mut session()).await {
+            Some(Ok(Answer::Rows { columns, rows })) => (columns, rows),
+            Some(Err(e)) => panic!("{} failed: {}", sql, e),
+            _ => panic!("{} was not answered with rows", sql),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_functions_without_from() {
+        let (columns, rows) = select("SELECT version(), current_database() AS db, 1").await;
+        assert_eq!(columns, vec!["version", "db", "?column?"]);
+        let Value::String(version) = &rows[0][0] else { panic!("version is not text") };
+        assert!(version.starts_with("PostgreSQL "));
+        assert_eq!(rows[0][1], Value::String("app".to_string()));
+        assert_eq!(rows[0][2], Value::Int(1));
+
+        let (_, rows) = select("SELECT current_schema(), current_user").await;
+        assert_eq!(rows[0], vec![Value::String("public".to_string()), Value::String("alice".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn test_session_commands() {
+        let mut session = session();
+        assert!(matches!(run("SET application_name = 'psql'", &mut session).await, Some(Ok(Answer::Command(tag))) if tag == "SET"));
+        assert!(matches!(run("SET TIME ZONE 'Europe/Paris'", &mut session).await, Some(Ok(Answer::Command(_)))));
+        assert_eq!(session.parameters["application_name"], "psql");
+        assert_eq!(session.parameters["timezone"], "Europe/Paris");
+
+        match run("SHOW application_name", &mut session).await {
+            Some(Ok(Answer::Rows { rows, .. })) => assert_eq!(rows, vec![vec![Value::String("psql".to_string())]]),
+            _ => panic!("SHOW was not answered"),
+        }
+        assert!(matches!(run("SHOW nonexistent", &mut session).await, Some(Err(_))));
+        assert!(matches!(run("BEGIN", &mut session).await, Some(Ok(Answer::Command(tag))) if tag == "BEGIN"));
+
+        // Ordinary queries are left to the engine
+        assert!(run("SELECT * FROM user WHERE age > 3", &mut session).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_catalog_tables() {
+        let (_, rows) = select(
+            "SELECT table_name FROM information_schema.tables WHERE table_schema = 'public' ORDER BY table_name DESC",
+        )
+        .await;
+        assert_eq!(rows, vec![vec![Value::String("user".to_string())], vec![Value::String("post".to_string())]]);
+
+        let (columns, rows) = select(
+            "SELECT column_name, data_type FROM information_schema.columns \
+             WHERE table_name = 'user' ORDER BY ordinal_position",
+        )
+        .await;
+        assert_eq!(columns, vec!["column_name", "data_type"]);
+        let names: Vec<_> = rows.iter().map(|row| row[0].clone()).collect();
+        assert_eq!(
+            names,
+            ["id", "type", "age", "name"].iter().map(|s| Value::String(s.to_string())).collect::<Vec<_>>()
+        );
+        assert_eq!(rows[2][1], Value::String("bigint".to_string()));
+
+        let (_, rows) = select("SELECT relname FROM pg_catalog.pg_class WHERE relkind IN ('r', 'v') AND relname LIKE 'p%'").await;
+        assert_eq!(rows, vec![vec![Value::String("post".to_string())]]);
+
+        let (_, rows) = select("SELECT oid, typname FROM pg_type WHERE oid = 20").await;
+        assert_eq!(rows, vec![vec![Value::Int(20), Value::String("int8".to_string())]]);
+
+        let (columns, rows) = select("SELECT * FROM pg_namespace ORDER BY oid LIMIT 1").await;
+        assert_eq!(columns, vec!["oid", "nspname", "nspowner"]);
+        assert_eq!(rows[0][1], Value::String("pg_catalog".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_catalog_queries_fail() {
+        let sql = "SELECT c.relname FROM pg_catalog.pg_class c \
+                   LEFT JOIN pg_catalog.pg_namespace n ON n.oid = c.relnamespace";
+        assert!(matches!(run(sql, &mut session()).await, Some(Err(_))));
+    }
+}
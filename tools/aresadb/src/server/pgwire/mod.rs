@@ -0,0 +1,1526 @@
+//! PostgreSQL Wire Protocol
+//!
+//! Lets `psql`, drivers and BI tools connect as if to PostgreSQL. Both the
+//! simple and the extended query protocol are spoken; every statement runs
+//! as a [`Request::Query`] on the shared [`RequestHandler`], so access
+//! control, replication and sharding apply as on the other listeners.
+//!
+//! Result columns are typed from the values they hold: booleans, integers
+//! and floats as `bool`, `int8` and `float8`, bytes as `bytea`, vectors as
+//! `float4[]`, arrays and objects as `json`, and anything else as `text`.
+//! Session commands and the catalog queries clients issue on connect are
+//! answered by [`catalog`] instead of the query engine.
+//!
+//! There are no transactions: every statement commits on its own and
+//! `BEGIN`/`COMMIT` are acknowledged without effect. Passwords are sent in
+//! cleartext, so configure TLS when authentication is on.
+
+mod catalog;
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn, debug};
+
+use self::catalog::{Answer, Session};
+use super::auth::{Credentials, TOKEN_PREFIX};
+use super::handler::RequestHandler;
+use super::pool::Connections;
+use super::protocol::{ErrorCode, HandshakeReply, Request, Response, DEFAULT_MAX_FRAME_SIZE};
+use super::tls::{Stream, TlsConfig};
+use crate::query::{QueryOperation, QueryParser};
+use crate::schema::Schema;
+use crate::storage::Value;
+
+/// Version reported to clients, which gate features on it
+const SERVER_VERSION: &str = "14.0";
+
+/// Protocol 3.0 startup message code
+const PROTOCOL_V3: i32 = 196_608;
+
+/// Request to switch the connection to TLS
+const SSL_REQUEST: i32 = 80_877_103;
+
+/// Request for GSSAPI encryption, which is always declined
+const GSSENC_REQUEST: i32 = 80_877_104;
+
+/// Request to cancel a query on another connection
+const CANCEL_REQUEST: i32 = 80_877_102;
+
+/// Largest startup packet accepted
+const MAX_STARTUP_SIZE: usize = 10_000;
+
+/// Buffered output is written out once it grows past this
+const FLUSH_THRESHOLD: usize = 64 * 1024;
+
+/// Types result columns are sent as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PgType {
+    Bool,
+    Int8,
+    Float8,
+    Text,
+    Bytea,
+    Json,
+    Float4Array,
+}
+
+impl PgType {
+    /// Every type, as listed in `pg_type`
+    const ALL: [PgType; 7] =
+        [PgType::Bool, PgType::Int8, PgType::Float8, PgType::Text, PgType::Bytea, PgType::Json, PgType::Float4Array];
+
+    fn oid(self) -> i32 {
+        match self {
+            PgType::Bool => 16,
+            PgType::Int8 => 20,
+            PgType::Float8 => 701,
+            PgType::Text => 25,
+            PgType::Bytea => 17,
+            PgType::Json => 114,
+            PgType::Float4Array => 1021,
+        }
+    }
+
+    /// Name in `pg_type`
+    fn name(self) -> &'static str {
+        match self {
+            PgType::Bool => "bool",
+            PgType::Int8 => "int8",
+            PgType::Float8 => "float8",
+            PgType::Text => "text",
+            PgType::Bytea => "bytea",
+            PgType::Json => "json",
+            PgType::Float4Array => "_float4",
+        }
+    }
+
+    /// Name in `information_schema.columns.data_type`
+    fn sql_name(self) -> &'static str {
+        match self {
+            PgType::Bool => "boolean",
+            PgType::Int8 => "bigint",
+            PgType::Float8 => "double precision",
+            PgType::Text => "text",
+            PgType::Bytea => "bytea",
+            PgType::Json => "json",
+            PgType::Float4Array => "ARRAY",
+        }
+    }
+
+    /// Size in bytes, or -1 for variable length
+    fn len(self) -> i16 {
+        match self {
+            PgType::Bool => 1,
+            PgType::Int8 | PgType::Float8 => 8,
+            PgType::Text | PgType::Bytea | PgType::Json | PgType::Float4Array => -1,
+        }
+    }
+
+    /// Type of a single value, `None` for null
+    fn of(value: &Value) -> Option<PgType> {
+        match value {
+            Value::Null => None,
+            Value::Bool(_) => Some(PgType::Bool),
+            Value::Int(_) => Some(PgType::Int8),
+            Value::Float(_) => Some(PgType::Float8),
+            Value::String(_) => Some(PgType::Text),
+            Value::Bytes(_) => Some(PgType::Bytea),
+            Value::Vector(_) => Some(PgType::Float4Array),
+            Value::Array(_) | Value::Object(_) => Some(PgType::Json),
+        }
+    }
+
+    /// Type of column `index`: that of its values, widening integers mixed
+    /// with floats to `float8` and falling back to `text` if they differ.
+    /// `None` if every value is null.
+    fn of_column(rows: &[Vec<Value>], index: usize) -> Option<PgType> {
+        let mut column = None;
+        for ty in rows.iter().filter_map(|row| row.get(index).and_then(PgType::of)) {
+            column = match (column, ty) {
+                (None, ty) => Some(ty),
+                (Some(a), b) if a == b => Some(a),
+                (Some(PgType::Int8 | PgType::Float8), PgType::Int8 | PgType::Float8) => Some(PgType::Float8),
+                _ => return Some(PgType::Text),
+            };
+        }
+        column
+    }
+}
+
+/// Postgres listener configuration
+#[derive(Debug, Clone)]
+pub struct PgConfig {
+    /// Address to bind to
+    pub bind_addr: SocketAddr,
+    /// Require clients to switch to TLS before starting up
+    pub tls: Option<TlsConfig>,
+    /// Reject clients that don't log in as an existing user
+    pub require_auth: bool,
+    /// Seconds allowed to start up or to receive a message once it starts
+    pub read_timeout_secs: u64,
+    /// Seconds a connection may wait for its next message before it is closed
+    pub idle_timeout_secs: u64,
+}
+
+impl Default for PgConfig {
+    fn default() -> Self {
+        Self {
+            bind_addr: "127.0.0.1:5433".parse().unwrap(),
+            tls: None,
+            require_auth: false,
+            read_timeout_secs: 30,
+            idle_timeout_secs: 300,
+        }
+    }
+}
+
+impl PgConfig {
+    fn read_timeout(&self) -> Duration {
+        Duration::from_secs(self.read_timeout_secs)
+    }
+
+    fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.idle_timeout_secs)
+    }
+}
+
+/// PostgreSQL wire protocol front end for a [`RequestHandler`]
+pub struct PgServer {
+    config: PgConfig,
+    handler: Arc<RequestHandler>,
+}
+
+impl PgServer {
+    /// Serve `handler`, typically shared with a [`super::Server`]
+    pub fn new(handler: Arc<RequestHandler>, config: PgConfig) -> Self {
+        Self { config, handler }
+    }
+
+    /// Accept connections until the task is dropped
+    ///
+    /// Use [`super::Server::with_pg`] instead to share the server's
+    /// connection limit and shutdown.
+    pub async fn run(&self) -> Result<()> {
+        let listener = self.bind().await?;
+        self.serve(listener, CancellationToken::new(), Arc::new(Connections::unlimited())).await
+    }
+
+    /// Bind the listening socket
+    pub(crate) async fn bind(&self) -> Result<(TcpListener, Option<TlsAcceptor>)> {
+        let acceptor = self.config.tls.as_ref().map(TlsConfig::acceptor).transpose()?;
+        let listener = TcpListener::bind(&self.config.bind_addr)
+            .await
+            .context("Failed to bind Postgres listener")?;
+
+        info!(
+            "Postgres protocol listening on {}{}",
+            self.config.bind_addr,
+            if acceptor.is_some() { " (TLS)" } else { "" }
+        );
+        Ok((listener, acceptor))
+    }
+
+    /// Accept connections as part of `connections` until `shutdown`
+    ///
+    /// On shutdown, open connections close before their next message.
+    pub(crate) async fn serve(
+        &self,
+        (listener, acceptor): (TcpListener, Option<TlsAcceptor>),
+        shutdown: CancellationToken,
+        connections: Arc<Connections>,
+    ) -> Result<()> {
+        loop {
+            let accepted = tokio::select! {
+                _ = shutdown.cancelled() => return Ok(()),
+                accepted = listener.accept() => accepted,
+            };
+            let (stream, addr) = match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Postgres accept error: {}", e);
+                    continue;
+                }
+            };
+            let Some(slot) = connections.reserve() else {
+                warn!("Connection limit reached, rejecting Postgres client {}", addr);
+                continue;
+            };
+            let _ = stream.set_nodelay(true);
+            let acceptor = acceptor.clone();
+            let handler = Arc::clone(&self.handler);
+            let config = self.config.clone();
+            let shutdown = shutdown.clone();
+
+            connections.spawn(slot, async move {
+                let startup = startup(Box::new(stream), acceptor, handler, config.require_auth);
+                let connection = match timeout(config.read_timeout(), startup).await {
+                    Ok(Ok(Some(connection))) => connection,
+                    Ok(Ok(None)) => return,
+                    Ok(Err(e)) => {
+                        debug!("Postgres startup failed for {}: {:#}", addr, e);
+                        return;
+                    }
+                    Err(_) => {
+                        debug!("Postgres startup timed out for {}", addr);
+                        return;
+                    }
+                };
+                if let Err(e) = connection.serve(&config, &shutdown).await {
+                    debug!("Postgres connection error from {}: {:#}", addr, e);
+                }
+            });
+        }
+    }
+}
+
+/// An error reported to the client with its SQLSTATE
+#[derive(Debug)]
+struct PgError {
+    code: &'static str,
+    message: String,
+}
+
+impl PgError {
+    fn new(code: &'static str, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+
+    /// Error for a refused request
+    fn from_response(code: ErrorCode, message: String) -> Self {
+        let sqlstate = match code {
+            ErrorCode::QueryParseError => "42601",
+            ErrorCode::PermissionDenied => "42501",
+            ErrorCode::AuthenticationFailed => "28000",
+            ErrorCode::InvalidRequest => "0A000",
+            ErrorCode::ServerOverloaded => "53300",
+            ErrorCode::FrameTooLarge => "54000",
+            ErrorCode::ServerBusy => "57P03",
+            ErrorCode::RequestTimeout => "57014",
+            _ => "XX000",
+        };
+        Self::new(sqlstate, message)
+    }
+}
+
+/// A result column
+struct Column {
+    name: String,
+    ty: PgType,
+}
+
+/// What running a statement produced
+enum Outcome {
+    Rows { columns: Vec<Column>, rows: Vec<Vec<Value>> },
+    Command(String),
+    Empty,
+}
+
+impl Outcome {
+    /// Rows with their columns typed from the values, or by `fallback`
+    /// for columns that are entirely null
+    fn rows(names: Vec<String>, rows: Vec<Vec<Value>>, fallback: impl Fn(&str) -> Option<PgType>) -> Self {
+        let columns = names
+            .into_iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let ty = PgType::of_column(&rows, i).or_else(|| fallback(&name)).unwrap_or(PgType::Text);
+                Column { name, ty }
+            })
+            .collect();
+        Outcome::Rows { columns, rows }
+    }
+}
+
+/// A statement from a Parse message
+struct Prepared {
+    sql: String,
+    param_types: Vec<i32>,
+}
+
+/// A bound statement from a Bind message, run on first Describe or Execute
+struct Portal {
+    sql: String,
+    result_formats: Vec<i16>,
+    outcome: Option<Outcome>,
+    /// Rows already sent by earlier Executes
+    sent: usize,
+}
+
+/// Negotiate encryption, log the client in and greet it
+///
+/// Returns `None` if the client went away or was refused.
+async fn startup(
+    mut stream: Box<dyn Stream>,
+    mut acceptor: Option<tokio_rustls::TlsAcceptor>,
+    handler: Arc<RequestHandler>,
+    require_auth: bool,
+) -> Result<Option<Connection>> {
+    let tls_required = acceptor.is_some();
+    let mut encrypted = false;
+
+    let params = loop {
+        let len = stream.read_i32().await? as usize;
+        if !(8..=MAX_STARTUP_SIZE).contains(&len) {
+            bail!("Invalid startup packet length {}", len);
+        }
+        let mut body = vec![0u8; len - 4];
+        stream.read_exact(&mut body).await?;
+        let mut body = Reader::new(&body);
+
+        match body.i32()? {
+            SSL_REQUEST => match acceptor.take() {
+                Some(tls) => {
+                    stream.write_all(b"S").await?;
+                    stream = Box::new(tls.accept(stream).await.context("TLS handshake failed")?);
+                    encrypted = true;
+                }
+                None => stream.write_all(b"N").await?,
+            },
+            GSSENC_REQUEST => stream.write_all(b"N").await?,
+            // Queries finish on their own; there is nothing to cancel
+            CANCEL_REQUEST => return Ok(None),
+            PROTOCOL_V3 => {
+                let mut params = BTreeMap::new();
+                loop {
+                    let name = body.cstr()?;
+                    if name.is_empty() {
+                        break;
+                    }
+                    params.insert(name.to_ascii_lowercase(), body.cstr()?.to_string());
+                }
+                break params;
+            }
+            version => {
+                let mut out = Out::default();
+                out.error("FATAL", &PgError::new("0A000", format!("Unsupported protocol version {}", version)));
+                stream.write_all(&out.0).await?;
+                return Ok(None);
+            }
+        }
+    };
+
+    let mut out = Out::default();
+    if tls_required && !encrypted {
+        out.error("FATAL", &PgError::new("28000", "TLS is required"));
+        stream.write_all(&out.0).await?;
+        return Ok(None);
+    }
+
+    let user_name = params.get("user").cloned().unwrap_or_default();
+    let database = params.get("database").cloned().unwrap_or_else(|| user_name.clone());
+
+    let user = if require_auth || handler.has_user(&user_name).await {
+        out.message(b'R', |b| put_i32(b, 3));
+        stream.write_all(&out.take()).await?;
+
+        let (tag, body) = read_message(&mut stream).await?;
+        if tag != b'p' {
+            bail!("Expected a password message, got {:?}", tag as char);
+        }
+        let password = Reader::new(&body).cstr()?.to_string();
+        match login(&handler, &user_name, password).await {
+            Ok(user) => Some(user),
+            Err(message) => {
+                out.error("FATAL", &PgError::new("28P01", message));
+                stream.write_all(&out.0).await?;
+                return Ok(None);
+            }
+        }
+    } else {
+        None
+    };
+
+    let mut parameters: BTreeMap<String, String> = [
+        ("server_version", SERVER_VERSION),
+        ("server_encoding", "UTF8"),
+        ("client_encoding", "UTF8"),
+        ("datestyle", "ISO, MDY"),
+        ("integer_datetimes", "on"),
+        ("standard_conforming_strings", "on"),
+        ("timezone", "UTC"),
+        ("is_superuser", "off"),
+        ("application_name", ""),
+    ]
+    .iter()
+    .map(|(name, value)| (name.to_string(), value.to_string()))
+    .collect();
+    parameters.insert("session_authorization".to_string(), user_name.clone());
+    parameters.extend(params.into_iter().filter(|(name, _)| name != "user" && name != "database"));
+
+    out.message(b'R', |b| put_i32(b, 0));
+    for (name, value) in &parameters {
+        let name = match name.as_str() {
+            "datestyle" => "DateStyle",
+            "timezone" => "TimeZone",
+            "options" | "replication" => continue,
+            name => name,
+        };
+        out.message(b'S', |b| {
+            put_cstr(b, name);
+            put_cstr(b, value);
+        });
+    }
+    out.message(b'K', |b| {
+        put_i32(b, std::process::id() as i32);
+        put_i32(b, rand::random());
+    });
+    out.ready();
+    stream.write_all(&out.take()).await?;
+
+    Ok(Some(Connection {
+        stream,
+        out,
+        handler,
+        user,
+        session: Session { user: user_name, database, parameters },
+        statements: HashMap::new(),
+        portals: HashMap::new(),
+    }))
+}
+
+/// Check a password, or an API token of `user_name` given as one
+async fn login(handler: &RequestHandler, user_name: &str, password: String) -> std::result::Result<String, String> {
+    let token = password.starts_with(TOKEN_PREFIX);
+    let credentials = match token {
+        true => Credentials::token(password),
+        false => Credentials::password(user_name, password),
+    };
+    match handler.authenticate(Some(&credentials), true).await {
+        Ok(Some(user)) if user == user_name => Ok(user),
+        Ok(_) => Err(format!("Token does not belong to user \"{}\"", user_name)),
+        Err(HandshakeReply::Rejected { message, .. }) => Err(message),
+        Err(HandshakeReply::Welcome(_)) => Err("Authentication failed".to_string()),
+    }
+}
+
+/// A logged in client
+struct Connection {
+    stream: Box<dyn Stream>,
+    out: Out,
+    handler: Arc<RequestHandler>,
+    /// Authenticated user, `None` for anonymous clients
+    user: Option<String>,
+    session: Session,
+    statements: HashMap<String, Prepared>,
+    portals: HashMap<String, Portal>,
+}
+
+impl Connection {
+    /// Answer messages until the client terminates, goes idle or the
+    /// server shuts down
+    async fn serve(mut self, config: &PgConfig, shutdown: &CancellationToken) -> Result<()> {
+        // After an error in the extended protocol, messages are skipped up to the next Sync
+        let mut failed = false;
+
+        loop {
+            let tag = tokio::select! {
+                _ = shutdown.cancelled() => return Ok(()),
+                tag = timeout(config.idle_timeout(), self.stream.read_u8()) => match tag {
+                    Ok(Ok(tag)) => tag,
+                    Ok(Err(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+                    Ok(Err(e)) => return Err(e.into()),
+                    Err(_) => {
+                        debug!("Closing idle Postgres connection");
+                        return Ok(());
+                    }
+                },
+            };
+            let body = timeout(config.read_timeout(), read_body(&mut self.stream))
+                .await
+                .context("Timed out reading a message")??;
+            let mut body = Reader::new(&body);
+
+            match tag {
+                b'Q' => {
+                    let sql = body.cstr()?.to_string();
+                    self.simple_query(&sql).await?;
+                    self.out.ready();
+                    self.flush().await?;
+                }
+                b'S' => {
+                    failed = false;
+                    self.portals.clear();
+                    self.out.ready();
+                    self.flush().await?;
+                }
+                b'H' => self.flush().await?,
+                b'X' => return Ok(()),
+                _ if failed => {}
+                b'P' | b'B' | b'D' | b'E' | b'C' => {
+                    if let Err(e) = self.extended(tag, &mut body).await {
+                        self.out.error("ERROR", &e);
+                        failed = true;
+                    }
+                }
+                _ => {
+                    self.out.error("ERROR", &PgError::new("08P01", format!("Unsupported message {:?}", tag as char)));
+                    self.out.ready();
+                    self.flush().await?;
+                }
+            }
+        }
+    }
+
+    /// Run each statement of a Query message, stopping at the first error
+    async fn simple_query(&mut self, sql: &str) -> Result<()> {
+        let statements = split_statements(sql);
+        if statements.is_empty() {
+            self.out.message(b'I', |_| {});
+            return Ok(());
+        }
+
+        for statement in statements {
+            match self.run(statement).await {
+                Ok(Outcome::Rows { columns, rows }) => {
+                    self.out.row_description(&columns, &[]);
+                    for row in &rows {
+                        self.out.data_row(row, &columns, &[]);
+                        if self.out.0.len() > FLUSH_THRESHOLD {
+                            self.flush().await?;
+                        }
+                    }
+                    self.out.command_complete(&format!("SELECT {}", rows.len()));
+                }
+                Ok(Outcome::Command(tag)) => self.out.command_complete(&tag),
+                Ok(Outcome::Empty) => self.out.message(b'I', |_| {}),
+                Err(e) => {
+                    self.out.error("ERROR", &e);
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle Parse, Bind, Describe, Execute or Close
+    async fn extended(&mut self, tag: u8, body: &mut Reader<'_>) -> std::result::Result<(), PgError> {
+        let malformed = |e: anyhow::Error| PgError::new("08P01", e.to_string());
+
+        match tag {
+            b'P' => {
+                let name = body.cstr().map_err(malformed)?.to_string();
+                let sql = body.cstr().map_err(malformed)?.to_string();
+                let count = body.i16().map_err(malformed)?;
+                let param_types = (0..count).map(|_| body.i32()).collect::<Result<Vec<_>>>().map_err(malformed)?;
+                if split_statements(&sql).len() > 1 {
+                    return Err(PgError::new("42601", "cannot insert multiple commands into a prepared statement"));
+                }
+                self.statements.insert(name, Prepared { sql, param_types });
+                self.out.message(b'1', |_| {});
+            }
+            b'B' => {
+                let portal = body.cstr().map_err(malformed)?.to_string();
+                let name = body.cstr().map_err(malformed)?;
+                let statement = self
+                    .statements
+                    .get(name)
+                    .ok_or_else(|| PgError::new("26000", format!("prepared statement \"{}\" does not exist", name)))?;
+
+                let formats = body.formats().map_err(malformed)?;
+                let count = body.i16().map_err(malformed)? as usize;
+                let mut params = Vec::with_capacity(count);
+                for i in 0..count {
+                    let len = body.i32().map_err(malformed)?;
+                    let value = match len {
+                        -1 => None,
+                        len => Some(body.bytes(len.max(0) as usize).map_err(malformed)?),
+                    };
+                    let ty = statement.param_types.get(i).copied().unwrap_or(0);
+                    let format = match formats.as_slice() {
+                        [] => 0,
+                        [format] => *format,
+                        formats => formats.get(i).copied().unwrap_or(0),
+                    };
+                    params.push(match value {
+                        Some(value) => Some(decode_param(value, ty, format).map_err(|e| PgError::new("22P02", e.to_string()))?),
+                        None => None,
+                    });
+                }
+                let result_formats = body.formats().map_err(malformed)?;
+
+                let sql = bind_parameters(&statement.sql, &params, &statement.param_types)
+                    .map_err(|e| PgError::new("08P01", e.to_string()))?;
+                self.portals.insert(portal, Portal { sql, result_formats, outcome: None, sent: 0 });
+                self.out.message(b'2', |_| {});
+            }
+            b'D' => {
+                let kind = body.u8().map_err(malformed)?;
+                let name = body.cstr().map_err(malformed)?.to_string();
+                match kind {
+                    b'S' => self.describe_statement(&name).await?,
+                    _ => self.describe_portal(&name).await?,
+                }
+            }
+            b'E' => {
+                let name = body.cstr().map_err(malformed)?.to_string();
+                let max_rows = body.i32().map_err(malformed)?;
+                self.execute(&name, max_rows).await?;
+            }
+            b'C' => {
+                let kind = body.u8().map_err(malformed)?;
+                let name = body.cstr().map_err(malformed)?;
+                match kind {
+                    b'S' => self.statements.remove(name).map(|_| ()),
+                    _ => self.portals.remove(name).map(|_| ()),
+                };
+                self.out.message(b'3', |_| {});
+            }
+            _ => unreachable!("not an extended query message"),
+        }
+        Ok(())
+    }
+
+    /// Report a statement's parameter types, and its columns by running it
+    /// with every parameter null if it returns rows
+    async fn describe_statement(&mut self, name: &str) -> std::result::Result<(), PgError> {
+        let statement = self
+            .statements
+            .get(name)
+            .ok_or_else(|| PgError::new("26000", format!("prepared statement \"{}\" does not exist", name)))?;
+
+        let count = parameter_count(&statement.sql);
+        let types: Vec<i32> = (0..count)
+            .map(|i| match statement.param_types.get(i).copied().unwrap_or(0) {
+                0 => PgType::Text.oid(),
+                oid => oid,
+            })
+            .collect();
+        self.out.message(b't', |b| {
+            put_i16(b, types.len() as i16);
+            types.iter().for_each(|oid| put_i32(b, *oid));
+        });
+
+        let outcome = match returns_rows(&statement.sql) {
+            true => {
+                let sql = bind_parameters(&statement.sql, &vec![None; count], &[])
+                    .map_err(|e| PgError::new("08P01", e.to_string()))?;
+                self.run(&sql).await?
+            }
+            false => Outcome::Empty,
+        };
+        match outcome {
+            Outcome::Rows { columns, .. } => self.out.row_description(&columns, &[]),
+            _ => self.out.message(b'n', |_| {}),
+        }
+        Ok(())
+    }
+
+    /// Report a portal's columns, running it now
+    async fn describe_portal(&mut self, name: &str) -> std::result::Result<(), PgError> {
+        self.run_portal(name).await?;
+        let portal = &self.portals[name];
+        match &portal.outcome {
+            Some(Outcome::Rows { columns, .. }) => self.out.row_description(columns, &portal.result_formats),
+            _ => self.out.message(b'n', |_| {}),
+        }
+        Ok(())
+    }
+
+    /// Send up to `max_rows` of a portal's rows, all of them if zero
+    async fn execute(&mut self, name: &str, max_rows: i32) -> std::result::Result<(), PgError> {
+        self.run_portal(name).await?;
+        let portal = self.portals.get_mut(name).expect("portal was just run");
+
+        match &portal.outcome {
+            Some(Outcome::Rows { columns, rows }) => {
+                let remaining = &rows[portal.sent..];
+                let batch = match max_rows {
+                    n if n > 0 => remaining.len().min(n as usize),
+                    _ => remaining.len(),
+                };
+                for row in &remaining[..batch] {
+                    self.out.data_row(row, columns, &portal.result_formats);
+                }
+                portal.sent += batch;
+                if portal.sent < rows.len() {
+                    self.out.message(b's', |_| {});
+                } else {
+                    self.out.command_complete(&format!("SELECT {}", portal.sent));
+                }
+            }
+            Some(Outcome::Command(tag)) => self.out.command_complete(tag),
+            Some(Outcome::Empty) | None => self.out.message(b'I', |_| {}),
+        }
+        Ok(())
+    }
+
+    /// Run a portal's statement unless it already ran
+    async fn run_portal(&mut self, name: &str) -> std::result::Result<(), PgError> {
+        let portal = self
+            .portals
+            .get(name)
+            .ok_or_else(|| PgError::new("34000", format!("portal \"{}\" does not exist", name)))?;
+        if portal.outcome.is_some() {
+            return Ok(());
+        }
+
+        let sql = portal.sql.clone();
+        let outcome = self.run(&sql).await?;
+        if let Some(portal) = self.portals.get_mut(name) {
+            portal.outcome = Some(outcome);
+        }
+        Ok(())
+    }
+
+    /// Run one statement, from the catalog or through the handler
+    async fn run(&mut self, sql: &str) -> std::result::Result<Outcome, PgError> {
+        let sql = sql.trim().trim_end_matches(';').trim_end();
+        if sql.is_empty() {
+            return Ok(Outcome::Empty);
+        }
+
+        let handler = &self.handler;
+        let user = self.user.as_deref();
+        match catalog::answer(sql, &mut self.session, || handler.readable_schemas(user)).await {
+            Some(Ok(Answer::Rows { columns, rows })) => return Ok(Outcome::rows(columns, rows, |_| None)),
+            Some(Ok(Answer::Command(tag))) => return Ok(Outcome::Command(tag)),
+            Some(Err(e)) => {
+                let code = if e.to_string().starts_with("unrecognized configuration parameter") { "42704" } else { "0A000" };
+                return Err(PgError::new(code, e.to_string()));
+            }
+            None => {}
+        }
+
+        let verb = sql.split_whitespace().next().unwrap_or_default().to_ascii_uppercase();
+        let request = Request::Query { sql: sql.to_string(), limit: None };
+        match self.handler.handle_as(self.user.as_deref(), request).await {
+            Response::QueryResult { columns, rows, rows_affected, .. } => Ok(match verb.as_str() {
+                "INSERT" => Outcome::Command(format!("INSERT 0 {}", rows_affected)),
+                "UPDATE" | "DELETE" => Outcome::Command(format!("{} {}", verb, rows_affected)),
+                _ => {
+                    let (target, columns, rows) = project(sql, columns, rows);
+                    let schema = match (0..columns.len()).any(|i| PgType::of_column(&rows, i).is_none()) {
+                        true => self.schema(target.as_deref()).await,
+                        false => None,
+                    };
+                    Outcome::rows(columns, rows, |name| catalog::column_type(schema.as_ref()?, name))
+                }
+            }),
+            Response::Ok => Ok(Outcome::Command(verb)),
+            Response::Error { code, message } => Err(PgError::from_response(code, message)),
+            Response::NotLeader { leader_addr, .. } => Err(PgError::new(
+                "25006",
+                match leader_addr {
+                    Some(addr) => format!("Not the leader; connect to {}", addr),
+                    None => "Not the leader".to_string(),
+                },
+            )),
+            other => Err(PgError::new("XX000", format!("Unexpected response: {:?}", other))),
+        }
+    }
+
+    /// Registered schema of `target`, if the user may read it
+    async fn schema(&mut self, target: Option<&str>) -> Option<Schema> {
+        let target = target?;
+        let schemas = self.handler.readable_schemas(self.user.as_deref()).await.ok()?;
+        schemas.into_iter().find(|schema| schema.name == target)
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        self.stream.write_all(&self.out.take()).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+}
+
+/// Reshape rows to the columns a `SELECT` lists, in its order, and find
+/// the node type it reads
+///
+/// The engine always returns `id` and `type` and orders columns its own
+/// way, but clients match result columns to their select list.
+fn project(sql: &str, columns: Vec<String>, rows: Vec<Vec<Value>>) -> (Option<String>, Vec<String>, Vec<Vec<Value>>) {
+    let (target, selected) = match QueryParser::new().parse(sql) {
+        Ok(query) if query.operation == QueryOperation::Select && !query.is_aggregate() => (query.target, query.columns),
+        Ok(query) => return (Some(query.target), columns, rows),
+        Err(_) => return (None, columns, rows),
+    };
+    if selected.is_empty() || selected == columns {
+        return (Some(target), columns, rows);
+    }
+
+    let indices: Vec<Option<usize>> =
+        selected.iter().map(|name| columns.iter().position(|column| column == name)).collect();
+    let rows = rows
+        .into_iter()
+        .map(|row| indices.iter().map(|i| i.and_then(|i| row.get(i).cloned()).unwrap_or(Value::Null)).collect())
+        .collect();
+    (Some(target), selected, rows)
+}
+
+/// Read a tagged message after startup
+async fn read_message(stream: &mut Box<dyn Stream>) -> Result<(u8, Vec<u8>)> {
+    let tag = stream.read_u8().await?;
+    Ok((tag, read_body(stream).await?))
+}
+
+/// Read the length and body of a message whose tag has been read
+async fn read_body(stream: &mut Box<dyn Stream>) -> Result<Vec<u8>> {
+    let len = stream.read_i32().await?;
+    if len < 4 || len as usize - 4 > DEFAULT_MAX_FRAME_SIZE {
+        bail!("Invalid message length {}", len);
+    }
+    let mut body = vec![0u8; len as usize - 4];
+    stream.read_exact(&mut body).await?;
+    Ok(body)
+}
+
+/// Messages waiting to be written
+#[derive(Default)]
+struct Out(Vec<u8>);
+
+impl Out {
+    /// Append a message, patching in its length once `body` has written it
+    fn message(&mut self, tag: u8, body: impl FnOnce(&mut Vec<u8>)) {
+        self.0.push(tag);
+        let start = self.0.len();
+        self.0.extend_from_slice(&[0; 4]);
+        body(&mut self.0);
+        let len = (self.0.len() - start) as i32;
+        self.0[start..start + 4].copy_from_slice(&len.to_be_bytes());
+    }
+
+    fn take(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.0)
+    }
+
+    fn ready(&mut self) {
+        self.message(b'Z', |b| b.push(b'I'));
+    }
+
+    fn command_complete(&mut self, tag: &str) {
+        self.message(b'C', |b| put_cstr(b, tag));
+    }
+
+    fn error(&mut self, severity: &str, error: &PgError) {
+        self.message(b'E', |b| {
+            for (field, value) in [(b'S', severity), (b'V', severity), (b'C', error.code), (b'M', error.message.as_str())] {
+                b.push(field);
+                put_cstr(b, value);
+            }
+            b.push(0);
+        });
+    }
+
+    fn row_description(&mut self, columns: &[Column], formats: &[i16]) {
+        self.message(b'T', |b| {
+            put_i16(b, columns.len() as i16);
+            for (i, column) in columns.iter().enumerate() {
+                put_cstr(b, &column.name);
+                put_i32(b, 0);
+                put_i16(b, 0);
+                put_i32(b, column.ty.oid());
+                put_i16(b, column.ty.len());
+                put_i32(b, -1);
+                put_i16(b, format_of(formats, i));
+            }
+        });
+    }
+
+    fn data_row(&mut self, row: &[Value], columns: &[Column], formats: &[i16]) {
+        self.message(b'D', |b| {
+            put_i16(b, columns.len() as i16);
+            for (i, column) in columns.iter().enumerate() {
+                let value = row.get(i).unwrap_or(&Value::Null);
+                let encoded = match format_of(formats, i) {
+                    1 => encode_binary(value, column.ty),
+                    _ => encode_text(value, column.ty).map(String::into_bytes),
+                };
+                match encoded {
+                    Some(bytes) => {
+                        put_i32(b, bytes.len() as i32);
+                        b.extend_from_slice(&bytes);
+                    }
+                    None => put_i32(b, -1),
+                }
+            }
+        });
+    }
+}
+
+/// Format code of column `index`: one code for all, one per column, or text
+fn format_of(formats: &[i16], index: usize) -> i16 {
+    match formats {
+        [] => 0,
+        [format] => *format,
+        formats => formats.get(index).copied().unwrap_or(0),
+    }
+}
+
+fn put_i16(buf: &mut Vec<u8>, value: i16) {
+    buf.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_i32(buf: &mut Vec<u8>, value: i32) {
+    buf.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_cstr(buf: &mut Vec<u8>, value: &str) {
+    buf.extend_from_slice(value.as_bytes());
+    buf.push(0);
+}
+
+/// Parser for message bodies
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.buf.len() < len {
+            bail!("Malformed message");
+        }
+        let (head, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn i16(&mut self) -> Result<i16> {
+        Ok(i16::from_be_bytes(self.bytes(2)?.try_into()?))
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        Ok(i32::from_be_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    fn cstr(&mut self) -> Result<&'a str> {
+        let end = self.buf.iter().position(|&b| b == 0).ok_or_else(|| anyhow!("Malformed message"))?;
+        let value = std::str::from_utf8(&self.buf[..end]).context("Invalid UTF-8 in message")?;
+        self.buf = &self.buf[end + 1..];
+        Ok(value)
+    }
+
+    /// A count followed by that many format codes
+    fn formats(&mut self) -> Result<Vec<i16>> {
+        let count = self.i16()?;
+        (0..count).map(|_| self.i16()).collect()
+    }
+}
+
+/// Text representation of `value` in a column of type `ty`
+fn encode_text(value: &Value, ty: PgType) -> Option<String> {
+    let float = |f: f64| match f {
+        f if f.is_nan() => "NaN".to_string(),
+        f if f == f64::INFINITY => "Infinity".to_string(),
+        f if f == f64::NEG_INFINITY => "-Infinity".to_string(),
+        f => f.to_string(),
+    };
+
+    Some(match value {
+        Value::Null => return None,
+        Value::Bool(b) if ty == PgType::Bool => if *b { "t" } else { "f" }.to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Int(i) if ty == PgType::Float8 => float(*i as f64),
+        Value::Int(i) => i.to_string(),
+        Value::Float(f) => float(*f),
+        Value::String(s) => s.clone(),
+        Value::Bytes(bytes) => {
+            let mut hex = String::with_capacity(2 + bytes.len() * 2);
+            hex.push_str("\\x");
+            bytes.iter().for_each(|b| hex.push_str(&format!("{:02x}", b)));
+            hex
+        }
+        Value::Vector(v) => {
+            let items: Vec<String> = v.iter().map(|f| float(*f as f64)).collect();
+            format!("{{{}}}", items.join(","))
+        }
+        Value::Array(_) | Value::Object(_) => value.to_json().to_string(),
+    })
+}
+
+/// Binary representation of `value` in a column of type `ty`
+fn encode_binary(value: &Value, ty: PgType) -> Option<Vec<u8>> {
+    Some(match (ty, value) {
+        (_, Value::Null) => return None,
+        (PgType::Bool, Value::Bool(b)) => vec![*b as u8],
+        (PgType::Int8, Value::Int(i)) => i.to_be_bytes().to_vec(),
+        (PgType::Float8, Value::Int(i)) => (*i as f64).to_be_bytes().to_vec(),
+        (PgType::Float8, Value::Float(f)) => f.to_be_bytes().to_vec(),
+        (PgType::Bytea, Value::Bytes(bytes)) => bytes.clone(),
+        (PgType::Float4Array, Value::Vector(v)) => {
+            let mut buf = Vec::with_capacity(20 + v.len() * 8);
+            // One dimension, no nulls, float4 elements, lower bound 1
+            for header in [1, 0, 700, v.len() as i32, 1] {
+                put_i32(&mut buf, header);
+            }
+            for f in v {
+                put_i32(&mut buf, 4);
+                buf.extend_from_slice(&f.to_be_bytes());
+            }
+            buf
+        }
+        // Text and json share their text form; mixed columns are text
+        _ => encode_text(value, ty)?.into_bytes(),
+    })
+}
+
+/// Text of a bound parameter, converting binary values by declared type
+fn decode_param(value: &[u8], ty: i32, format: i16) -> Result<String> {
+    if format == 0 {
+        return Ok(std::str::from_utf8(value).context("Parameter is not valid UTF-8")?.to_string());
+    }
+
+    let fixed = |len: usize| -> Result<&[u8]> {
+        if value.len() != len {
+            bail!("Binary parameter of type {} has {} bytes", ty, value.len());
+        }
+        Ok(value)
+    };
+    Ok(match ty {
+        16 => (fixed(1)?[0] != 0).to_string(),
+        21 => i16::from_be_bytes(fixed(2)?.try_into()?).to_string(),
+        23 => i32::from_be_bytes(fixed(4)?.try_into()?).to_string(),
+        20 => i64::from_be_bytes(fixed(8)?.try_into()?).to_string(),
+        700 => f32::from_be_bytes(fixed(4)?.try_into()?).to_string(),
+        701 => f64::from_be_bytes(fixed(8)?.try_into()?).to_string(),
+        0 | 25 | 114 | 1043 | 3802 => std::str::from_utf8(value).context("Parameter is not valid UTF-8")?.to_string(),
+        _ => bail!("Binary parameters of type {} are not supported", ty),
+    })
+}
+
+/// Byte offsets of `sql` outside string literals, quoted identifiers and comments
+fn unquoted(sql: &str) -> Vec<usize> {
+    let bytes = sql.as_bytes();
+    let mut positions = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            quote @ (b'\'' | b'"') => {
+                // A doubled quote inside reads as closing and reopening
+                i += 1;
+                while i < bytes.len() && bytes[i] != quote {
+                    i += 1;
+                }
+            }
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            _ => positions.push(i),
+        }
+        i += 1;
+    }
+    positions
+}
+
+/// Split a Query message on semicolons, dropping empty statements
+fn split_statements(sql: &str) -> Vec<&str> {
+    let mut statements = Vec::new();
+    let mut start = 0;
+    for i in unquoted(sql) {
+        if sql.as_bytes()[i] == b';' {
+            statements.push(&sql[start..i]);
+            start = i + 1;
+        }
+    }
+    statements.push(&sql[start..]);
+    statements.into_iter().filter(|statement| !statement.trim().is_empty()).collect()
+}
+
+/// `$n` placeholders in `sql`, as (offset, length, n)
+fn placeholders(sql: &str) -> Vec<(usize, usize, usize)> {
+    let bytes = sql.as_bytes();
+    unquoted(sql)
+        .into_iter()
+        .filter(|&i| bytes[i] == b'$')
+        .filter_map(|i| {
+            let digits = bytes[i + 1..].iter().take_while(|b| b.is_ascii_digit()).count();
+            let n = sql[i + 1..i + 1 + digits].parse().ok()?;
+            Some((i, digits + 1, n))
+        })
+        .collect()
+}
+
+/// Highest placeholder number in `sql`
+fn parameter_count(sql: &str) -> usize {
+    placeholders(sql).iter().map(|&(_, _, n)| n).max().unwrap_or(0)
+}
+
+/// Substitute parameters into `sql` as literals
+///
+/// Values of numeric and boolean types are inlined as they are; so are
+/// numeric-looking values of unspecified type, since many drivers don't
+/// declare parameter types. Everything else becomes a quoted string.
+fn bind_parameters(sql: &str, params: &[Option<String>], types: &[i32]) -> Result<String> {
+    let mut bound = String::with_capacity(sql.len());
+    let mut last = 0;
+    for (offset, len, n) in placeholders(sql) {
+        let index = n.checked_sub(1).filter(|&i| i < params.len()).ok_or_else(|| {
+            anyhow!("Statement uses ${} but {} parameters were bound", n, params.len())
+        })?;
+        bound.push_str(&sql[last..offset]);
+        bound.push_str(&literal(params[index].as_deref(), types.get(index).copied().unwrap_or(0)));
+        last = offset + len;
+    }
+    bound.push_str(&sql[last..]);
+    Ok(bound)
+}
+
+/// A parameter value as an SQL literal
+fn literal(value: Option<&str>, ty: i32) -> String {
+    let Some(value) = value else {
+        return "NULL".to_string();
+    };
+    let numeric = || value.parse::<i64>().is_ok() || value.parse::<f64>().is_ok_and(f64::is_finite);
+    match ty {
+        16 => match value.to_ascii_lowercase().as_str() {
+            "t" | "true" | "1" | "yes" | "on" => "TRUE".to_string(),
+            _ => "FALSE".to_string(),
+        },
+        20 | 21 | 23 | 26 | 700 | 701 | 1700 if numeric() => value.to_string(),
+        0 if numeric() => value.to_string(),
+        _ => format!("'{}'", value.replace('\'', "''")),
+    }
+}
+
+/// Whether a statement produces rows, so Describe should run it
+fn returns_rows(sql: &str) -> bool {
+    let verb = sql.split_whitespace().next().unwrap_or_default().to_ascii_lowercase();
+    matches!(verb.as_str(), "select" | "with" | "values" | "show" | "table")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::SchemaManager;
+    use crate::server::UserStore;
+    use crate::storage::Database;
+    use tempfile::TempDir;
+    use tokio::net::TcpStream;
+
+    #[test]
+    fn test_parameter_binding() {
+        let sql = "SELECT * FROM user WHERE name = $1 AND age > $2 AND note = '$1' -- $3\n";
+        assert_eq!(parameter_count(sql), 2);
+        let bound = bind_parameters(sql, &[Some("O'Brien".to_string()), Some("30".to_string())], &[25]).unwrap();
+        assert_eq!(bound, "SELECT * FROM user WHERE name = 'O''Brien' AND age > 30 AND note = '$1' -- $3\n");
+
+        assert_eq!(bind_parameters("SELECT $1", &[None], &[]).unwrap(), "SELECT NULL");
+        assert_eq!(bind_parameters("SELECT $1", &[Some("t".to_string())], &[16]).unwrap(), "SELECT TRUE");
+        assert!(bind_parameters("SELECT $2", &[Some("1".to_string())], &[]).is_err());
+
+        assert_eq!(split_statements("SET a = 1; SELECT ';' ;; "), vec!["SET a = 1", " SELECT ';' "]);
+        assert_eq!(decode_param(&42i32.to_be_bytes(), 23, 1).unwrap(), "42");
+    }
+
+    #[test]
+    fn test_value_encoding() {
+        let rows = vec![vec![Value::Int(1), Value::Null], vec![Value::Float(2.5), Value::String("x".to_string())]];
+        assert_eq!(PgType::of_column(&rows, 0), Some(PgType::Float8));
+        assert_eq!(PgType::of_column(&rows, 1), Some(PgType::Text));
+        assert_eq!(PgType::of_column(&[vec![Value::Bool(true), Value::Int(1)]], 1), Some(PgType::Int8));
+        assert_eq!(PgType::of_column(&[vec![Value::Null]], 0), None);
+
+        assert_eq!(encode_text(&Value::Bool(true), PgType::Bool).unwrap(), "t");
+        assert_eq!(encode_text(&Value::Int(3), PgType::Float8).unwrap(), "3");
+        assert_eq!(encode_text(&Value::Bytes(vec![0xde, 0xad]), PgType::Bytea).unwrap(), "\\xdead");
+        assert_eq!(encode_text(&Value::Vector(vec![1.0, 0.5]), PgType::Float4Array).unwrap(), "{1,0.5}");
+        assert_eq!(encode_text(&Value::Null, PgType::Text), None);
+        assert_eq!(encode_binary(&Value::Int(7), PgType::Int8).unwrap(), 7i64.to_be_bytes());
+        assert_eq!(encode_binary(&Value::Int(7), PgType::Float8).unwrap(), 7f64.to_be_bytes());
+    }
+
+    /// Minimal frontend speaking the protocol directly
+    struct Client {
+        stream: TcpStream,
+    }
+
+    /// What the server sent up to ReadyForQuery
+    #[derive(Debug, Default)]
+    struct Reply {
+        columns: Vec<(String, i32)>,
+        rows: Vec<Vec<Option<Vec<u8>>>>,
+        tags: Vec<String>,
+        errors: Vec<(String, String)>,
+        other: Vec<u8>,
+    }
+
+    impl Reply {
+        fn text(&self, row: usize, column: usize) -> Option<String> {
+            self.rows[row][column].as_ref().map(|v| String::from_utf8(v.clone()).unwrap())
+        }
+    }
+
+    impl Client {
+        async fn connect(addr: SocketAddr, user: &str, password: Option<&str>) -> std::result::Result<Self, String> {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let mut startup = Vec::new();
+            put_i32(&mut startup, PROTOCOL_V3);
+            for value in ["user", user, "database", "test", "application_name", "tests", ""] {
+                put_cstr(&mut startup, value);
+            }
+            let mut packet = Vec::new();
+            put_i32(&mut packet, startup.len() as i32 + 4);
+            packet.extend(startup);
+            stream.write_all(&packet).await.unwrap();
+
+            let mut client = Self { stream };
+            loop {
+                let (tag, body) = client.read().await;
+                match tag {
+                    b'R' if body[..4] == 3i32.to_be_bytes() => {
+                        let mut out = Out::default();
+                        out.message(b'p', |b| put_cstr(b, password.unwrap_or("")));
+                        client.stream.write_all(&out.0).await.unwrap();
+                    }
+                    b'E' => return Err(error_fields(&body).1),
+                    b'Z' => return Ok(client),
+                    _ => {}
+                }
+            }
+        }
+
+        async fn read(&mut self) -> (u8, Vec<u8>) {
+            let tag = self.stream.read_u8().await.unwrap();
+            let len = self.stream.read_i32().await.unwrap();
+            let mut body = vec![0; len as usize - 4];
+            self.stream.read_exact(&mut body).await.unwrap();
+            (tag, body)
+        }
+
+        async fn send(&mut self, out: Out) {
+            self.stream.write_all(&out.0).await.unwrap();
+        }
+
+        async fn reply(&mut self) -> Reply {
+            let mut reply = Reply::default();
+            loop {
+                let (tag, body) = self.read().await;
+                let mut body = Reader::new(&body);
+                match tag {
+                    b'T' => {
+                        for _ in 0..body.i16().unwrap() {
+                            let name = body.cstr().unwrap().to_string();
+                            body.bytes(6).unwrap();
+                            reply.columns.push((name, body.i32().unwrap()));
+                            body.bytes(8).unwrap();
+                        }
+                    }
+                    b'D' => {
+                        let row = (0..body.i16().unwrap())
+                            .map(|_| match body.i32().unwrap() {
+                                -1 => None,
+                                len => Some(body.bytes(len as usize).unwrap().to_vec()),
+                            })
+                            .collect();
+                        reply.rows.push(row);
+                    }
+                    b'C' => reply.tags.push(body.cstr().unwrap().to_string()),
+                    b'E' => reply.errors.push(error_fields(body.buf)),
+                    b'Z' => return reply,
+                    other => reply.other.push(other),
+                }
+            }
+        }
+
+        async fn query(&mut self, sql: &str) -> Reply {
+            let mut out = Out::default();
+            out.message(b'Q', |b| put_cstr(b, sql));
+            self.send(out).await;
+            self.reply().await
+        }
+    }
+
+    /// SQLSTATE and message of an ErrorResponse
+    fn error_fields(body: &[u8]) -> (String, String) {
+        let mut reader = Reader::new(body);
+        let (mut code, mut message) = (String::new(), String::new());
+        while let Ok(field) = reader.u8() {
+            if field == 0 {
+                break;
+            }
+            let value = reader.cstr().unwrap().to_string();
+            match field {
+                b'C' => code = value,
+                b'M' => message = value,
+                _ => {}
+            }
+        }
+        (code, message)
+    }
+
+    async fn start(db: Database, require_auth: bool) -> SocketAddr {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let config = PgConfig { bind_addr: addr, require_auth, ..Default::default() };
+        let server = PgServer::new(Arc::new(RequestHandler::new(db)), config);
+        tokio::spawn(async move { server.run().await });
+
+        for _ in 0..100 {
+            if TcpStream::connect(addr).await.is_ok() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_simple_query_protocol() {
+        let temp = TempDir::new().unwrap();
+        let db = Database::create(temp.path(), "test").await.unwrap();
+        SchemaManager::new(db.clone()).create_schema("user", "name:string,age:int").await.unwrap();
+        let addr = start(db, false).await;
+        let mut client = Client::connect(addr, "anyone", None).await.unwrap();
+
+        let reply = client
+            .query("INSERT INTO user (name, age) VALUES ('Ada', 36); INSERT INTO user (name, age) VALUES ('Bob', 7)")
+            .await;
+        assert!(reply.errors.is_empty(), "{:?}", reply.errors);
+        assert_eq!(reply.tags, vec!["INSERT 0 1", "INSERT 0 1"]);
+
+        let reply = client.query("SELECT name, age FROM user WHERE age > 10").await;
+        assert_eq!(reply.columns, vec![("name".to_string(), 25), ("age".to_string(), 20)]);
+        assert_eq!(reply.rows.len(), 1);
+        assert_eq!(reply.text(0, 0).as_deref(), Some("Ada"));
+        assert_eq!(reply.text(0, 1).as_deref(), Some("36"));
+        assert_eq!(reply.tags, vec!["SELECT 1"]);
+
+        let reply = client.query("SELECT version(); SHOW application_name").await;
+        assert!(reply.text(0, 0).unwrap().starts_with("PostgreSQL 14.0"));
+        assert_eq!(reply.text(1, 0).as_deref(), Some("tests"));
+
+        let reply = client.query("SELECT table_name FROM information_schema.tables").await;
+        assert_eq!(reply.text(0, 0).as_deref(), Some("user"));
+
+        // An error ends the batch but not the connection
+        let reply = client.query("SELEC nonsense; SELECT 1").await;
+        assert_eq!(reply.errors[0].0, "42601");
+        assert!(reply.tags.is_empty());
+        let reply = client.query("").await;
+        assert_eq!(reply.other, vec![b'I']);
+        let reply = client.query("SELECT 1").await;
+        assert_eq!(reply.text(0, 0).as_deref(), Some("1"));
+    }
+
+    #[tokio::test]
+    async fn test_extended_query_protocol() {
+        let temp = TempDir::new().unwrap();
+        let db = Database::create(temp.path(), "test").await.unwrap();
+        SchemaManager::new(db.clone()).create_schema("user", "name:string,age:int").await.unwrap();
+        let addr = start(db, false).await;
+        let mut client = Client::connect(addr, "anyone", None).await.unwrap();
+        for (name, age) in [("Ada", 36), ("Bob", 7), ("Cy", 50)] {
+            client.query(&format!("INSERT INTO user (name, age) VALUES ('{}', {})", name, age)).await;
+        }
+
+        // Parse with an int4 parameter, bind it in binary and fetch in two batches
+        let mut out = Out::default();
+        out.message(b'P', |b| {
+            put_cstr(b, "adults");
+            put_cstr(b, "SELECT name, age FROM user WHERE age > $1 ORDER BY age");
+            put_i16(b, 1);
+            put_i32(b, 23);
+        });
+        out.message(b'B', |b| {
+            put_cstr(b, "");
+            put_cstr(b, "adults");
+            put_i16(b, 1);
+            put_i16(b, 1);
+            put_i16(b, 1);
+            put_i32(b, 4);
+            b.extend_from_slice(&18i32.to_be_bytes());
+            put_i16(b, 1);
+            put_i16(b, 1);
+        });
+        out.message(b'D', |b| {
+            b.push(b'P');
+            put_cstr(b, "");
+        });
+        out.message(b'E', |b| {
+            put_cstr(b, "");
+            put_i32(b, 1);
+        });
+        out.message(b'E', |b| {
+            put_cstr(b, "");
+            put_i32(b, 0);
+        });
+        out.message(b'S', |_| {});
+        client.send(out).await;
+
+        let reply = client.reply().await;
+        assert!(reply.errors.is_empty(), "{:?}", reply.errors);
+        assert_eq!(reply.columns, vec![("name".to_string(), 25), ("age".to_string(), 20)]);
+        assert_eq!(reply.rows.len(), 2);
+        assert_eq!(reply.rows[0][0].as_deref(), Some(&b"Ada"[..]));
+        assert_eq!(reply.rows[0][1].as_deref(), Some(&36i64.to_be_bytes()[..]));
+        assert_eq!(reply.rows[1][1].as_deref(), Some(&50i64.to_be_bytes()[..]));
+        assert_eq!(reply.other, vec![b'1', b'2', b's']);
+        assert_eq!(reply.tags, vec!["SELECT 2"]);
+
+        // After an error everything up to Sync is skipped
+        let mut out = Out::default();
+        out.message(b'B', |b| {
+            put_cstr(b, "");
+            put_cstr(b, "missing");
+            put_i16(b, 0);
+            put_i16(b, 0);
+            put_i16(b, 0);
+        });
+        out.message(b'E', |b| {
+            put_cstr(b, "");
+            put_i32(b, 0);
+        });
+        out.message(b'S', |_| {});
+        client.send(out).await;
+        let reply = client.reply().await;
+        assert_eq!(reply.errors.len(), 1);
+        assert_eq!(reply.errors[0].0, "26000");
+        assert!(reply.tags.is_empty());
+
+        // The prepared statement survives Sync; describing it reports the parameter
+        let mut out = Out::default();
+        out.message(b'D', |b| {
+            b.push(b'S');
+            put_cstr(b, "adults");
+        });
+        out.message(b'S', |_| {});
+        client.send(out).await;
+        let reply = client.reply().await;
+        assert_eq!(reply.other, vec![b't']);
+        // No rows match a null parameter, so the types come from the schema
+        assert_eq!(reply.columns, vec![("name".to_string(), 25), ("age".to_string(), 20)]);
+    }
+
+    #[tokio::test]
+    async fn test_pg_authentication() {
+        let temp = TempDir::new().unwrap();
+        let db = Database::create(temp.path(), "test").await.unwrap();
+        let users = UserStore::new(&db);
+        users.create_user("ada", "lovelace").await.unwrap();
+        let token = users.create_token("ada", None).await.unwrap();
+        users.create_user("bob", "builder").await.unwrap();
+        let other_token = users.create_token("bob", None).await.unwrap();
+        let addr = start(db, true).await;
+
+        assert!(Client::connect(addr, "ada", Some("wrong")).await.is_err());
+        assert!(Client::connect(addr, "nobody", Some("x")).await.is_err());
+        assert!(Client::connect(addr, "ada", Some(&other_token)).await.is_err());
+
+        let mut client = Client::connect(addr, "ada", Some(&token)).await.unwrap();
+        let reply = client.query("SELECT current_user").await;
+        assert_eq!(reply.text(0, 0).as_deref(), Some("ada"));
+
+        let mut client = Client::connect(addr, "ada", Some("lovelace")).await.unwrap();
+        let reply = client.query("SELECT current_user").await;
+        assert_eq!(reply.text(0, 0).as_deref(), Some("ada"));
+    }
+}
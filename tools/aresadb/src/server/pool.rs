@@ -2,8 +2,16 @@
 //!
 //! Manages concurrent connections with semaphore-based limiting.
 
+use parking_lot::Mutex;
+use std::future::Future;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+
+use crate::metrics::metrics;
 
 /// Connection pool for limiting concurrent connections
 pub struct ConnectionPool {
@@ -27,7 +35,9 @@ impl ConnectionPool {
 
     /// Try to acquire a connection slot
     pub fn try_acquire(&self) -> bool {
-        if self.semaphore.try_acquire().is_ok() {
+        if let Ok(permit) = self.semaphore.try_acquire() {
+            // Returned explicitly through `release`
+            permit.forget();
             self.active.fetch_add(1, Ordering::SeqCst);
             true
         } else {
@@ -57,6 +67,83 @@ impl ConnectionPool {
     }
 }
 
+/// A connection's place in a [`ConnectionPool`], given back when dropped
+pub(crate) struct Slot(Arc<ConnectionPool>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.release();
+        metrics().connections.dec();
+    }
+}
+
+/// Connection tasks of every listener of a server, limited by one pool
+///
+/// Tasks hold their [`Slot`] until they end, so aborted connections are
+/// released as well.
+pub(crate) struct Connections {
+    pool: Arc<ConnectionPool>,
+    tasks: Mutex<JoinSet<()>>,
+}
+
+impl Connections {
+    pub(crate) fn new(pool: Arc<ConnectionPool>) -> Self {
+        Self { pool, tasks: Mutex::new(JoinSet::new()) }
+    }
+
+    /// Connections without a limit, for a listener running on its own
+    pub(crate) fn unlimited() -> Self {
+        Self::new(Arc::new(ConnectionPool::new(Semaphore::MAX_PERMITS)))
+    }
+
+    /// Reserve a slot for a new connection, or `None` at the limit
+    pub(crate) fn reserve(&self) -> Option<Slot> {
+        if !self.pool.try_acquire() {
+            metrics().connections_rejected.inc();
+            return None;
+        }
+        metrics().connections_accepted.inc();
+        metrics().connections.inc();
+        Some(Slot(Arc::clone(&self.pool)))
+    }
+
+    /// Serve a connection on its own task, holding `slot` until it ends
+    pub(crate) fn spawn<F>(&self, slot: Slot, connection: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let mut tasks = self.tasks.lock();
+        // Reap finished connections so the set only holds open ones
+        while tasks.try_join_next().is_some() {}
+        tasks.spawn(async move {
+            let _slot = slot;
+            connection.await;
+        });
+    }
+
+    /// Number of open connections
+    pub(crate) fn count(&self) -> usize {
+        self.pool.active_count()
+    }
+
+    /// Wait up to `deadline` for the open connections to end, then abort the rest
+    ///
+    /// Returns how many were aborted. Call once no listener spawns more.
+    pub(crate) async fn drain(&self, deadline: Duration) -> usize {
+        let mut tasks = std::mem::take(&mut *self.tasks.lock());
+        let ended = timeout(deadline, async {
+            while tasks.join_next().await.is_some() {}
+        })
+        .await;
+        if ended.is_ok() {
+            return 0;
+        }
+        let open = tasks.len();
+        tasks.shutdown().await;
+        open
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,9 +1,292 @@
 //! Wire Protocol for Client-Server Communication
 //!
-//! Binary protocol using bincode for efficient serialization.
-
+//! Version 2. A connection opens with a JSON handshake: the client sends a
+//! [`Hello`] and the server answers with a [`HandshakeReply`], settling the
+//! protocol version, compression and frame size limits. Being JSON, the
+//! handshake stays readable whatever later versions change.
+//!
+//! Every message after that is a frame:
+//!
+//! ```text
+//! u32 LE length | u8 encoding (0 raw, 1 LZ4) | bincode (u64 request ID, message)
+//! ```
+//!
+//! LZ4 bodies start with their u32 LE uncompressed size. A response carries
+//! the ID of the request it answers and responses may arrive in any order,
+//! so clients can pipeline many requests on one connection. ID 0 is
+//! reserved for an error about the connection itself, sent just before the
+//! server closes it. Frames over the receiver's limit are refused before
+//! they are read.
+
+use anyhow::{Result, Context, bail};
 use serde::{Serialize, Deserialize};
-use crate::storage::{Node, Edge, Value};
+use serde::de::DeserializeOwned;
+use std::borrow::Cow;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::storage::{ChangeEvent, ChangeFilter, DistanceMetric, Node, Edge, Value};
+use super::auth::Credentials;
+use crate::distributed::{Compressor, ConsensusMessage, MemberInfo};
+
+/// Highest protocol version spoken
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Lowest protocol version still accepted
+pub const MIN_PROTOCOL_VERSION: u32 = 2;
+
+/// Default limit on the size of a frame either side accepts
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// Request ID of errors about the connection as a whole
+pub const CONNECTION_ERROR_ID: u64 = 0;
+
+/// Name of the LZ4 frame compression in the handshake
+pub const COMPRESSION_LZ4: &str = "lz4";
+
+/// Limit on handshake messages, which are small JSON documents
+const MAX_HANDSHAKE_SIZE: usize = 64 * 1024;
+
+/// Frame encoding bytes; these match [`Compressor`]'s markers
+const ENCODING_RAW: u8 = 0x00;
+const ENCODING_LZ4: u8 = 0x01;
+
+/// First message on every connection, from the client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hello {
+    /// Highest version the client speaks
+    pub protocol_version: u32,
+    /// Compression the client accepts, in order of preference
+    #[serde(default)]
+    pub compression: Vec<String>,
+    /// Largest frame the client accepts
+    pub max_frame_size: u64,
+    /// Credentials, if the client authenticates
+    #[serde(default)]
+    pub credentials: Option<Credentials>,
+}
+
+impl Hello {
+    /// Hello for the current protocol version
+    pub fn new(compression: bool, max_frame_size: usize, credentials: Option<Credentials>) -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            compression: if compression { vec![COMPRESSION_LZ4.to_string()] } else { Vec::new() },
+            max_frame_size: max_frame_size as u64,
+            credentials,
+        }
+    }
+}
+
+/// Server's acceptance of a [`Hello`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Welcome {
+    /// Version both sides speak from now on
+    pub protocol_version: u32,
+    /// Compression chosen for frames, if any
+    pub compression: Option<String>,
+    /// Largest frame the server accepts
+    pub max_frame_size: u64,
+    /// User the credentials belong to
+    pub user: Option<String>,
+}
+
+/// Server's answer to a [`Hello`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum HandshakeReply {
+    /// Connection accepted
+    Welcome(Welcome),
+    /// Connection refused; the server closes it
+    Rejected {
+        /// Why, e.g. [`ErrorCode::AuthenticationFailed`]
+        code: ErrorCode,
+        /// Details for the user
+        message: String,
+    },
+}
+
+/// A frame larger than the receiving side accepts
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("Frame of {size} bytes exceeds the limit of {limit} bytes")]
+pub struct FrameTooLarge {
+    /// Size of the frame
+    pub size: usize,
+    /// Largest frame accepted
+    pub limit: usize,
+}
+
+/// Encodes and decodes frames as settled in the handshake
+#[derive(Debug, Clone)]
+pub struct FrameCodec {
+    compressor: Option<Compressor>,
+    /// Largest frame the other side accepts
+    send_limit: usize,
+    /// Largest frame this side accepts
+    recv_limit: usize,
+}
+
+impl FrameCodec {
+    /// Codec compressing with LZ4 if `compression` is set
+    pub fn new(compression: bool, send_limit: usize, recv_limit: usize) -> Self {
+        Self {
+            compressor: compression.then(Compressor::new),
+            send_limit,
+            recv_limit,
+        }
+    }
+
+    /// Largest frame this side accepts
+    pub fn recv_limit(&self) -> usize {
+        self.recv_limit
+    }
+
+    /// Encode `message` as a complete frame, length prefix included
+    ///
+    /// Fails with [`FrameTooLarge`] if the other side would refuse it.
+    pub fn encode<T: Serialize>(&self, id: u64, message: &T) -> Result<Vec<u8>> {
+        let body = bincode::serialize(&(id, message))?;
+        let payload = match &self.compressor {
+            Some(compressor) => compressor.compress(&body)?,
+            None => {
+                let mut payload = Vec::with_capacity(body.len() + 1);
+                payload.push(ENCODING_RAW);
+                payload.extend_from_slice(&body);
+                payload
+            }
+        };
+
+        if payload.len() > self.send_limit {
+            return Err(FrameTooLarge { size: payload.len(), limit: self.send_limit }.into());
+        }
+
+        let mut frame = Vec::with_capacity(payload.len() + 4);
+        frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&payload);
+        Ok(frame)
+    }
+
+    /// Decode a frame payload (without its length prefix)
+    ///
+    /// The outer error means the frame is unreadable; the inner one that
+    /// only the message is, so the request ID can still be answered.
+    pub fn decode<T: DeserializeOwned>(&self, payload: &[u8]) -> Result<(u64, Result<T>)> {
+        let body = match payload.first() {
+            Some(&ENCODING_RAW) => Cow::Borrowed(&payload[1..]),
+            Some(&ENCODING_LZ4) => {
+                let Some(compressor) = &self.compressor else {
+                    bail!("Compressed frame on an uncompressed connection");
+                };
+                let size = payload
+                    .get(1..5)
+                    .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+                    .context("Truncated frame")?;
+                if size > self.recv_limit {
+                    return Err(FrameTooLarge { size, limit: self.recv_limit }.into());
+                }
+                Cow::Owned(compressor.decompress(payload)?)
+            }
+            Some(encoding) => bail!("Unknown frame encoding {}", encoding),
+            None => bail!("Empty frame"),
+        };
+
+        let id: u64 = bincode::deserialize(body.get(..8).context("Truncated frame")?)?;
+        let message = bincode::deserialize::<(u64, T)>(&body)
+            .map(|(_, message)| message)
+            .map_err(anyhow::Error::from);
+        Ok((id, message))
+    }
+}
+
+/// Read one frame's payload, or `None` if the stream ended cleanly first
+pub(crate) async fn read_payload<S: AsyncRead + Unpin + ?Sized>(
+    stream: &mut S,
+    limit: usize,
+) -> Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 4];
+    match stream.read_exact(&mut len).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    let len = u32::from_le_bytes(len) as usize;
+    if len > limit {
+        return Err(FrameTooLarge { size: len, limit }.into());
+    }
+
+    let mut payload = vec![0u8; len];
+    stream.read_exact(&mut payload).await?;
+    Ok(Some(payload))
+}
+
+/// Send one message
+pub(crate) async fn write_message<S: AsyncWrite + Unpin + ?Sized, T: Serialize>(
+    stream: &mut S,
+    codec: &FrameCodec,
+    id: u64,
+    message: &T,
+) -> Result<()> {
+    let frame = codec.encode(id, message)?;
+    stream.write_all(&frame).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Receive one message with its request ID
+pub(crate) async fn read_message<S: AsyncRead + Unpin + ?Sized, T: DeserializeOwned>(
+    stream: &mut S,
+    codec: &FrameCodec,
+) -> Result<(u64, T)> {
+    let payload = read_payload(stream, codec.recv_limit)
+        .await?
+        .context("Connection closed")?;
+    let (id, message) = codec.decode(&payload)?;
+    Ok((id, message?))
+}
+
+/// Send a handshake message
+pub(crate) async fn write_handshake<S: AsyncWrite + Unpin + ?Sized, T: Serialize>(
+    stream: &mut S,
+    message: &T,
+) -> Result<()> {
+    let body = serde_json::to_vec(message)?;
+    stream.write_all(&(body.len() as u32).to_le_bytes()).await?;
+    stream.write_all(&body).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Receive a handshake message
+pub(crate) async fn read_handshake<S: AsyncRead + Unpin + ?Sized, T: DeserializeOwned>(stream: &mut S) -> Result<T> {
+    let body = read_payload(stream, MAX_HANDSHAKE_SIZE)
+        .await?
+        .context("Connection closed during handshake")?;
+    serde_json::from_slice(&body).context("Invalid handshake message")
+}
+
+/// Open a session as the client, returning the server's terms and the codec
+/// for the rest of the connection
+pub(crate) async fn handshake<S: AsyncRead + AsyncWrite + Unpin + ?Sized>(
+    stream: &mut S,
+    hello: &Hello,
+) -> Result<(Welcome, FrameCodec)> {
+    write_handshake(stream, hello).await?;
+
+    match read_handshake(stream).await? {
+        HandshakeReply::Welcome(welcome) => {
+            if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&welcome.protocol_version) {
+                bail!("Server chose unsupported protocol version {}", welcome.protocol_version);
+            }
+            let compression = match welcome.compression.as_deref() {
+                None => false,
+                Some(COMPRESSION_LZ4) if hello.compression.iter().any(|c| c == COMPRESSION_LZ4) => true,
+                Some(other) => bail!("Server chose unsupported compression {}", other),
+            };
+            let codec = FrameCodec::new(compression, welcome.max_frame_size as usize, hello.max_frame_size as usize);
+            Ok((welcome, codec))
+        }
+        HandshakeReply::Rejected { code, message } => bail!("{}: {}", code, message),
+    }
+}
 
 /// Request types from client to server
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -95,6 +378,74 @@ pub enum Request {
     RollbackTransaction {
         tx_id: u64,
     },
+
+    /// Consensus message from a peer; only accepted on the peer listener
+    Raft(ConsensusMessage),
+
+    /// List cluster members (replicated mode)
+    ListMembers,
+
+    /// Add a replica; voters are promoted once caught up (replicated mode)
+    AddMember {
+        /// Node ID
+        id: String,
+        /// Client-facing address of the replica
+        address: String,
+        /// Address of the replica's peer listener
+        peer_address: String,
+        /// Promote to voter once caught up; otherwise stay a learner
+        voter: bool,
+    },
+
+    /// Remove a replica (replicated mode)
+    RemoveMember {
+        /// Node ID
+        id: String,
+    },
+
+    /// Hand leadership to another voter (replicated mode)
+    TransferLeadership {
+        /// Node ID of the new leader
+        id: String,
+    },
+
+    /// Shortest path of at most `max_depth` edges
+    ShortestPath {
+        /// Node the path starts at
+        from_id: String,
+        /// Node the path ends at
+        to_id: String,
+        /// Longest path considered, in edges
+        max_depth: u32,
+    },
+
+    /// The `k` nodes of a type whose embedding is nearest to `vector`
+    SimilaritySearch {
+        /// Node type searched
+        node_type: String,
+        /// Property holding each node's embedding
+        field: String,
+        /// Embedding to search near
+        vector: Vec<f32>,
+        /// Number of nodes returned
+        k: usize,
+        /// Distance used to rank the nodes
+        metric: DistanceMetric,
+    },
+
+    /// Stream changes after sequence `after`, or from now on if `None`
+    ///
+    /// Answered with [`Response::Subscribed`] and then a
+    /// [`Response::Change`] under the same request ID for each change,
+    /// until the connection closes.
+    Subscribe {
+        /// Sequence of the last change already seen; later ones are
+        /// replayed before live changes follow
+        after: Option<u64>,
+        /// Node and edge types to receive; empty for every change
+        filter: ChangeFilter,
+    },
+
 }
 
 /// Response types from server to client
@@ -163,6 +514,44 @@ pub enum Response {
         code: ErrorCode,
         message: String,
     },
+
+    /// Write sent to a follower; retry against the leader
+    NotLeader {
+        /// ID of the current leader, if known
+        leader_id: Option<String>,
+        /// Client-facing address of the current leader, if known
+        leader_addr: Option<String>,
+    },
+
+    /// Cluster members
+    Members(Vec<MemberInfo>),
+
+    /// Nodes along a path, `None` if there is none
+    Path(Option<Vec<Node>>),
+
+    /// Nearest nodes, closest first
+    SearchResults(Vec<SearchHit>),
+
+    /// Subscription started; changes follow
+    Subscribed {
+        /// Latest sequence when the subscription started
+        last_seq: u64,
+    },
+
+    /// A change on a subscription
+    Change(ChangeEvent),
+
+}
+
+/// A node found by similarity search
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchHit {
+    /// Matching node
+    pub node: Node,
+    /// Similarity score; higher is closer
+    pub score: f64,
+    /// Distance under the requested metric
+    pub distance: f64,
 }
 
 /// Error codes
@@ -188,6 +577,14 @@ pub enum ErrorCode {
     ServerOverloaded = 8,
     /// Internal error
     InternalError = 9,
+    /// Missing or invalid credentials
+    AuthenticationFailed = 10,
+    /// Message larger than the receiver accepts
+    FrameTooLarge = 11,
+    /// At the connection limit or shutting down; try again later or elsewhere
+    ServerBusy = 12,
+    /// Request ran past the server's time limit; it may still have taken effect
+    RequestTimeout = 13,
 }
 
 impl std::fmt::Display for ErrorCode {
@@ -203,6 +600,67 @@ impl std::fmt::Display for ErrorCode {
             ErrorCode::PermissionDenied => write!(f, "Permission denied"),
             ErrorCode::ServerOverloaded => write!(f, "Server overloaded"),
             ErrorCode::InternalError => write!(f, "Internal error"),
+            ErrorCode::AuthenticationFailed => write!(f, "Authentication failed"),
+            ErrorCode::FrameTooLarge => write!(f, "Frame too large"),
+            ErrorCode::ServerBusy => write!(f, "Server busy"),
+            ErrorCode::RequestTimeout => write!(f, "Request timeout"),
+        }
+    }
+}
+
+impl Request {
+    /// Name of the request kind, for logs and the audit trail
+    pub fn name(&self) -> &'static str {
+        match self {
+            Request::Ping => "Ping",
+            Request::Disconnect => "Disconnect",
+            Request::InsertNode { .. } => "InsertNode",
+            Request::GetNode { .. } => "GetNode",
+            Request::UpdateNode { .. } => "UpdateNode",
+            Request::DeleteNode { .. } => "DeleteNode",
+            Request::GetNodesByType { .. } => "GetNodesByType",
+            Request::CreateEdge { .. } => "CreateEdge",
+            Request::GetEdgesFrom { .. } => "GetEdgesFrom",
+            Request::GetEdgesTo { .. } => "GetEdgesTo",
+            Request::DeleteEdge { .. } => "DeleteEdge",
+            Request::Query { .. } => "Query",
+            Request::Traverse { .. } => "Traverse",
+            Request::Status => "Status",
+            Request::BeginTransaction => "BeginTransaction",
+            Request::CommitTransaction { .. } => "CommitTransaction",
+            Request::RollbackTransaction { .. } => "RollbackTransaction",
+            Request::Raft(_) => "Raft",
+            Request::ListMembers => "ListMembers",
+            Request::AddMember { .. } => "AddMember",
+            Request::RemoveMember { .. } => "RemoveMember",
+            Request::TransferLeadership { .. } => "TransferLeadership",
+            Request::ShortestPath { .. } => "ShortestPath",
+            Request::SimilaritySearch { .. } => "SimilaritySearch",
+            Request::Subscribe { .. } => "Subscribe",
+        }
+    }
+
+    /// Whether running the request twice has the same effect as once
+    ///
+    /// Only these are safe to resend when a connection fails before the
+    /// response arrives, since the first attempt may have been applied.
+    pub fn is_idempotent(&self) -> bool {
+        match self {
+            Request::Ping
+            | Request::GetNode { .. }
+            | Request::GetNodesByType { .. }
+            | Request::GetEdgesFrom { .. }
+            | Request::GetEdgesTo { .. }
+            | Request::Traverse { .. }
+            | Request::Status
+            | Request::ListMembers
+            | Request::ShortestPath { .. }
+            | Request::SimilaritySearch { .. } => true,
+            Request::Query { sql, .. } => sql
+                .split_whitespace()
+                .next()
+                .is_some_and(|verb| verb.eq_ignore_ascii_case("select")),
+            _ => false,
         }
     }
 }
@@ -225,6 +683,128 @@ impl Response {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::storage::{NodeId, Timestamp};
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+
+    /// Node with fixed ID and timestamps, so its encoding never changes
+    fn fixed_node() -> Node {
+        let mut properties = BTreeMap::new();
+        properties.insert("name".to_string(), Value::String("Alice".to_string()));
+        properties.insert("age".to_string(), Value::Int(30));
+        properties.insert("score".to_string(), Value::Float(9.5));
+        properties.insert("tags".to_string(), Value::Array(vec![Value::Bool(true), Value::Null]));
+        Node {
+            id: NodeId::parse("6f1c2a4e-8b3d-4f5a-9c7e-0d1b2a3c4d5e").unwrap(),
+            node_type: "user".to_string(),
+            properties,
+            created_at: Timestamp { millis: 1_700_000_000_000 },
+            updated_at: Timestamp { millis: 1_700_000_000_500 },
+        }
+    }
+
+    /// Compare `bytes` with a checked-in golden file
+    ///
+    /// Set `ARESADB_UPDATE_GOLDEN=1` to rewrite the files after a
+    /// deliberate protocol change, which also needs a version bump.
+    fn assert_golden(name: &str, bytes: &[u8]) {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/golden/protocol")
+            .join(name);
+
+        if std::env::var_os("ARESADB_UPDATE_GOLDEN").is_some() {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, bytes).unwrap();
+            return;
+        }
+
+        let expected = std::fs::read(&path)
+            .unwrap_or_else(|e| panic!("Missing golden file {}: {}", path.display(), e));
+        assert!(expected == bytes, "Encoding of {} changed; this breaks protocol version {}", name, PROTOCOL_VERSION);
+    }
+
+    #[test]
+    fn test_golden_frames() {
+        let codec = FrameCodec::new(false, DEFAULT_MAX_FRAME_SIZE, DEFAULT_MAX_FRAME_SIZE);
+        let node = fixed_node();
+
+        let hello = Hello::new(true, DEFAULT_MAX_FRAME_SIZE, Some(Credentials::password("alice", "secret")));
+        assert_golden("hello.json", &serde_json::to_vec(&hello).unwrap());
+
+        let insert = Request::InsertNode {
+            node_type: node.node_type.clone(),
+            properties: Value::Object(node.properties.clone()),
+        };
+        assert_golden("insert_node.bin", &codec.encode(1, &insert).unwrap());
+        assert_golden("node.bin", &codec.encode(1, &Response::Node(node.clone())).unwrap());
+        assert_golden("nodes.bin", &codec.encode(2, &Response::Nodes(vec![node])).unwrap());
+        assert_golden("ping.bin", &codec.encode(3, &Request::Ping).unwrap());
+        assert_golden(
+            "error.bin",
+            &codec.encode(CONNECTION_ERROR_ID, &Response::error(ErrorCode::FrameTooLarge, "too large")).unwrap(),
+        );
+
+        // The golden bytes must still decode to the same messages
+        let frame = codec.encode(1, &Response::Node(fixed_node())).unwrap();
+        let (id, response) = codec.decode::<Response>(&frame[4..]).unwrap();
+        assert_eq!(id, 1);
+        match response.unwrap() {
+            Response::Node(node) => assert_eq!(node.properties, fixed_node().properties),
+            other => panic!("Wrong response: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_frame_codec() {
+        for compression in [false, true] {
+            let codec = FrameCodec::new(compression, DEFAULT_MAX_FRAME_SIZE, DEFAULT_MAX_FRAME_SIZE);
+            let request = Request::InsertNode {
+                node_type: "doc".to_string(),
+                properties: Value::String("x".repeat(4096)),
+            };
+
+            let frame = codec.encode(42, &request).unwrap();
+            let len = u32::from_le_bytes(frame[..4].try_into().unwrap()) as usize;
+            assert_eq!(len, frame.len() - 4);
+            assert_eq!(frame[4], if compression { ENCODING_LZ4 } else { ENCODING_RAW });
+
+            let (id, decoded) = codec.decode::<Request>(&frame[4..]).unwrap();
+            assert_eq!(id, 42);
+            assert!(matches!(decoded.unwrap(), Request::InsertNode { .. }));
+        }
+
+        // An unreadable message keeps its ID so it can still be answered
+        let codec = FrameCodec::new(false, DEFAULT_MAX_FRAME_SIZE, DEFAULT_MAX_FRAME_SIZE);
+        let frame = codec.encode(7, &u32::MAX).unwrap();
+        let (id, decoded) = codec.decode::<Request>(&frame[4..]).unwrap();
+        assert_eq!(id, 7);
+        assert!(decoded.is_err());
+    }
+
+    #[test]
+    fn test_idempotent_requests() {
+        assert!(Request::GetNode { id: "a".to_string() }.is_idempotent());
+        assert!(Request::Query { sql: " select * from user".to_string(), limit: None }.is_idempotent());
+        assert!(!Request::Query { sql: "DELETE FROM user".to_string(), limit: None }.is_idempotent());
+        assert!(!Request::DeleteNode { id: "a".to_string() }.is_idempotent());
+        assert!(!Request::BeginTransaction.is_idempotent());
+    }
+
+    #[test]
+    fn test_frame_size_limits() {
+        let big = Value::String("x".repeat(10_000));
+
+        let codec = FrameCodec::new(false, 1024, 1024);
+        let err = codec.encode(1, &big).unwrap_err();
+        assert!(err.is::<FrameTooLarge>());
+
+        // A compressed frame may be small on the wire but too large unpacked
+        let sender = FrameCodec::new(true, 1024, DEFAULT_MAX_FRAME_SIZE);
+        let frame = sender.encode(1, &big).unwrap();
+        let receiver = FrameCodec::new(true, DEFAULT_MAX_FRAME_SIZE, 1024);
+        let err = receiver.decode::<Value>(&frame[4..]).unwrap_err();
+        assert!(err.is::<FrameTooLarge>());
+    }
 
     #[test]
     fn test_request_serialization() {
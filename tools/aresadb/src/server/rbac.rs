@@ -0,0 +1,724 @@
+//! Access Control
+//!
+//! Roles hold grants of a [`Permission`] on a [`Resource`]: every type, one
+//! node type or one edge type. Roles are stored as `__role__` nodes and a
+//! user's roles as a list on their `__user__` node.
+//!
+//! Access control is off until the first account exists. From then on every
+//! request is checked against the grants of the connection's user plus those
+//! of the `public` role, which is all an anonymous connection gets. The
+//! built-in `admin` role holds every permission. Denied requests are logged
+//! under the `aresadb::audit` target and recorded as `__audit__` nodes.
+//!
+//! A replicated server keeps the roles in the replicated data and changes
+//! them through the log. Accounts kept in a database of their own, with
+//! their roles and the audit log, stay with each node.
+
+use anyhow::{Result, bail};
+use serde::{Serialize, Deserialize};
+use std::collections::{BTreeMap, HashMap};
+
+use super::auth::{string_property, UserStore, USER_TYPE};
+use crate::query::{ParsedQuery, PlanStep, QueryOperation, QueryPlanner};
+use crate::storage::{Database, Node, Timestamp, Value};
+
+/// Node type holding roles and their grants
+pub(crate) const ROLE_TYPE: &str = "__role__";
+
+/// Node type holding denied requests
+pub(crate) const AUDIT_TYPE: &str = "__audit__";
+
+/// Built-in role holding every permission
+pub const ADMIN_ROLE: &str = "admin";
+
+/// Role whose grants apply to every connection, including anonymous ones
+pub const PUBLIC_ROLE: &str = "public";
+
+/// Something a grant allows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    /// Read nodes or edges
+    Read,
+    /// Insert and update nodes, create edges
+    Write,
+    /// Delete nodes or edges
+    Delete,
+    /// Everything above; on every type also grants, roles and cluster
+    /// membership
+    Admin,
+}
+
+impl Permission {
+    /// Whether holding `self` allows `other`
+    pub fn implies(self, other: Permission) -> bool {
+        self == other || self == Permission::Admin
+    }
+
+    /// Permissions named by a `GRANT` keyword, accepting SQL privilege names
+    fn from_keyword(word: &str) -> Result<Vec<Permission>> {
+        Ok(match word.to_ascii_uppercase().as_str() {
+            "READ" | "SELECT" => vec![Permission::Read],
+            "WRITE" | "INSERT" | "UPDATE" => vec![Permission::Write],
+            "DELETE" => vec![Permission::Delete],
+            "ADMIN" => vec![Permission::Admin],
+            "ALL" => vec![Permission::Read, Permission::Write, Permission::Delete],
+            _ => bail!("Unknown permission: {}", word),
+        })
+    }
+}
+
+impl std::fmt::Display for Permission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Permission::Read => write!(f, "READ"),
+            Permission::Write => write!(f, "WRITE"),
+            Permission::Delete => write!(f, "DELETE"),
+            Permission::Admin => write!(f, "ADMIN"),
+        }
+    }
+}
+
+/// What a grant applies to
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Resource {
+    /// Every node and edge type
+    All,
+    /// Nodes of one type
+    Node(String),
+    /// Edges of one type
+    Edge(String),
+}
+
+impl Resource {
+    /// Nodes of `node_type`
+    pub fn node(node_type: impl Into<String>) -> Self {
+        Resource::Node(node_type.into())
+    }
+
+    /// Edges of `edge_type`
+    pub fn edge(edge_type: impl Into<String>) -> Self {
+        Resource::Edge(edge_type.into())
+    }
+
+    /// Whether a grant on `self` applies to `other`
+    pub fn covers(&self, other: &Resource) -> bool {
+        *self == Resource::All || self == other
+    }
+}
+
+impl std::fmt::Display for Resource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Resource::All => write!(f, "*"),
+            Resource::Node(t) => write!(f, "NODE {}", t),
+            Resource::Edge(t) => write!(f, "EDGE {}", t),
+        }
+    }
+}
+
+/// A permission on a resource
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Grant {
+    /// What is allowed
+    pub permission: Permission,
+    /// What it is allowed on
+    pub resource: Resource,
+}
+
+impl Grant {
+    /// Grant `permission` on `resource`
+    pub fn new(permission: Permission, resource: Resource) -> Self {
+        Self { permission, resource }
+    }
+
+    /// Whether this grant allows `permission` on `resource`
+    pub fn allows(&self, permission: Permission, resource: &Resource) -> bool {
+        self.permission.implies(permission) && self.resource.covers(resource)
+    }
+}
+
+impl std::fmt::Display for Grant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ON {}", self.permission, self.resource)
+    }
+}
+
+/// A `GRANT` or `REVOKE` statement
+///
+/// ```text
+/// GRANT READ, WRITE ON user TO analyst
+/// GRANT READ ON EDGE follows TO analyst
+/// REVOKE ALL ON * FROM analyst
+/// GRANT ROLE analyst TO ada
+/// REVOKE ROLE analyst FROM ada
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessStatement {
+    /// Add grants to a role, creating it if needed
+    Grant {
+        /// Grants to add
+        grants: Vec<Grant>,
+        /// Role receiving them
+        role: String,
+    },
+    /// Remove grants from a role
+    Revoke {
+        /// Grants to remove
+        grants: Vec<Grant>,
+        /// Role losing them
+        role: String,
+    },
+    /// Give a user a role
+    GrantRole {
+        /// Role to give
+        role: String,
+        /// User receiving it
+        user: String,
+    },
+    /// Take a role from a user
+    RevokeRole {
+        /// Role to take
+        role: String,
+        /// User losing it
+        user: String,
+    },
+}
+
+impl AccessStatement {
+    /// Parse a `GRANT` or `REVOKE` statement; `None` if `sql` is neither
+    pub fn parse(sql: &str) -> Option<Result<Self>> {
+        let sql = sql.trim().trim_end_matches(';');
+        let tokens: Vec<String> = sql
+            .replace(',', " , ")
+            .split_whitespace()
+            .map(|t| t.trim_matches(|c| c == '"' || c == '\'' || c == '`').to_string())
+            .collect();
+
+        let first = tokens.first()?;
+        if !first.eq_ignore_ascii_case("GRANT") && !first.eq_ignore_ascii_case("REVOKE") {
+            return None;
+        }
+        Some(Self::parse_tokens(&tokens))
+    }
+
+    fn parse_tokens(tokens: &[String]) -> Result<Self> {
+        let is = |i: usize, word: &str| tokens.get(i).is_some_and(|t| t.eq_ignore_ascii_case(word));
+        let grant = is(0, "GRANT");
+        let direction = if grant { "TO" } else { "FROM" };
+
+        if is(1, "ROLE") {
+            return match tokens {
+                [_, _, role, _, user] if is(3, direction) => Ok(if grant {
+                    AccessStatement::GrantRole { role: role.clone(), user: user.clone() }
+                } else {
+                    AccessStatement::RevokeRole { role: role.clone(), user: user.clone() }
+                }),
+                _ => bail!("Expected {} ROLE <role> {} <user>", tokens[0].to_uppercase(), direction),
+            };
+        }
+
+        let on = (1..tokens.len())
+            .find(|&i| is(i, "ON"))
+            .ok_or_else(|| anyhow::anyhow!("Expected ON after the permissions"))?;
+        let target = (on..tokens.len())
+            .find(|&i| is(i, direction))
+            .ok_or_else(|| anyhow::anyhow!("Expected {} <role>", direction))?;
+
+        let mut permissions = Vec::new();
+        for word in tokens[1..on].iter().filter(|t| *t != "," && !t.eq_ignore_ascii_case("PRIVILEGES")) {
+            for permission in Permission::from_keyword(word)? {
+                if !permissions.contains(&permission) {
+                    permissions.push(permission);
+                }
+            }
+        }
+        if permissions.is_empty() {
+            bail!("Expected at least one permission");
+        }
+
+        let resource = match &tokens[on + 1..target] {
+            [all] if all == "*" || all.eq_ignore_ascii_case("ALL") => Resource::All,
+            [kind, name] if kind.eq_ignore_ascii_case("NODE") || kind.eq_ignore_ascii_case("TABLE") => {
+                Resource::node(name)
+            }
+            [kind, name] if kind.eq_ignore_ascii_case("EDGE") => Resource::edge(name),
+            [name] => Resource::node(name),
+            _ => bail!("Expected *, <type>, NODE <type> or EDGE <type> after ON"),
+        };
+
+        let role = match &tokens[target + 1..] {
+            [role] => role.clone(),
+            _ => bail!("Expected a single role after {}", direction),
+        };
+
+        let grants = permissions.into_iter().map(|p| Grant::new(p, resource.clone())).collect();
+        Ok(if grant {
+            AccessStatement::Grant { grants, role }
+        } else {
+            AccessStatement::Revoke { grants, role }
+        })
+    }
+}
+
+/// Resolved grants of every user, loaded once and checked per request
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    enforced: bool,
+    users: HashMap<String, Vec<Grant>>,
+    public: Vec<Grant>,
+}
+
+impl Policy {
+    /// Whether requests are checked at all
+    pub fn is_enforced(&self) -> bool {
+        self.enforced
+    }
+
+    /// A policy that checks every request and grants nothing
+    pub(crate) fn deny_all() -> Self {
+        Self {
+            enforced: true,
+            ..Default::default()
+        }
+    }
+
+    /// Whether `user` (or an anonymous connection) may do `permission` on
+    /// `resource`
+    pub fn allows(&self, user: Option<&str>, permission: Permission, resource: &Resource) -> bool {
+        if !self.enforced {
+            return true;
+        }
+        let own = user.and_then(|u| self.users.get(u)).into_iter().flatten();
+        self.public.iter().chain(own).any(|g| g.allows(permission, resource))
+    }
+}
+
+/// A request refused for lack of a permission
+#[derive(Debug, Clone)]
+pub struct Denial {
+    /// Authenticated user, `None` for anonymous connections
+    pub user: Option<String>,
+    /// Request that was refused, e.g. `DeleteNode` or `Query`
+    pub operation: String,
+    /// Permission that was missing
+    pub permission: Permission,
+    /// Resource it was missing on
+    pub resource: Resource,
+    /// When the request was refused
+    pub at: Timestamp,
+}
+
+impl std::fmt::Display for Denial {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} denied {} ({} ON {})",
+            self.user.as_deref().unwrap_or("anonymous"),
+            self.operation,
+            self.permission,
+            self.resource
+        )
+    }
+}
+
+/// A change to access control data
+///
+/// [`AccessStore::apply`] makes it directly; a replicated server proposes
+/// it instead, so every replica makes it as it applies the log.
+#[derive(Debug, Clone)]
+pub(crate) enum AccessWrite {
+    /// Store a new node
+    Insert(Node),
+    /// Merge properties into an existing node
+    Update {
+        /// Node being changed
+        node: Node,
+        /// Properties to merge
+        properties: serde_json::Value,
+    },
+}
+
+impl AccessWrite {
+    /// Type of the node written
+    pub(crate) fn node_type(&self) -> &str {
+        match self {
+            AccessWrite::Insert(node) | AccessWrite::Update { node, .. } => &node.node_type,
+        }
+    }
+}
+
+/// Roles, role memberships and the audit log stored in a database
+pub struct AccessStore<'a> {
+    /// Database holding the accounts, their roles and the audit log
+    accounts: &'a Database,
+    /// Database holding the roles' grants
+    roles: &'a Database,
+}
+
+impl<'a> AccessStore<'a> {
+    /// Access control data stored in `db`
+    pub fn new(db: &'a Database) -> Self {
+        Self { accounts: db, roles: db }
+    }
+
+    /// Access control data with the roles' grants kept in `roles`, apart
+    /// from the accounts
+    pub fn with_roles(accounts: &'a Database, roles: &'a Database) -> Self {
+        Self { accounts, roles }
+    }
+
+    /// Apply a `GRANT` or `REVOKE` statement
+    pub async fn execute(&self, statement: &AccessStatement) -> Result<()> {
+        match self.plan(statement).await? {
+            Some(write) => self.apply(write).await,
+            None => Ok(()),
+        }
+    }
+
+    /// The write a statement makes, if any, without making it
+    pub(crate) async fn plan(&self, statement: &AccessStatement) -> Result<Option<AccessWrite>> {
+        match statement {
+            AccessStatement::Grant { grants, role } => self.grant_write(role, grants).await.map(Some),
+            AccessStatement::Revoke { grants, role } => self.revoke_write(role, grants).await.map(Some),
+            AccessStatement::GrantRole { role, user } => self.assign_write(user, role).await,
+            AccessStatement::RevokeRole { role, user } => self.unassign_write(user, role).await.map(Some),
+        }
+    }
+
+    /// Make a write in the database holding its node type
+    pub(crate) async fn apply(&self, write: AccessWrite) -> Result<()> {
+        let db = if write.node_type() == ROLE_TYPE { self.roles } else { self.accounts };
+        match write {
+            AccessWrite::Insert(node) => {
+                db.insert_node(&node.node_type, Value::Object(node.properties).to_json()).await?;
+            }
+            AccessWrite::Update { node, properties } => {
+                db.update_node(&node.id.to_string(), properties).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Add grants to a role, creating it if needed
+    pub async fn grant(&self, role: &str, grants: &[Grant]) -> Result<()> {
+        self.apply(self.grant_write(role, grants).await?).await
+    }
+
+    /// Remove grants from a role
+    pub async fn revoke(&self, role: &str, grants: &[Grant]) -> Result<()> {
+        self.apply(self.revoke_write(role, grants).await?).await
+    }
+
+    /// Give a user a role
+    pub async fn assign_role(&self, username: &str, role: &str) -> Result<()> {
+        match self.assign_write(username, role).await? {
+            Some(write) => self.apply(write).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Take a role from a user
+    pub async fn unassign_role(&self, username: &str, role: &str) -> Result<()> {
+        self.apply(self.unassign_write(username, role).await?).await
+    }
+
+    async fn grant_write(&self, role: &str, grants: &[Grant]) -> Result<AccessWrite> {
+        if role == ADMIN_ROLE {
+            bail!("The {} role already holds every permission", ADMIN_ROLE);
+        }
+
+        Ok(match self.find_role(role).await? {
+            Some(node) => {
+                let mut current = role_grants(&node);
+                for grant in grants {
+                    if !current.contains(grant) {
+                        current.push(grant.clone());
+                    }
+                }
+                AccessWrite::Update { node, properties: serde_json::json!({ "grants": current }) }
+            }
+            None => {
+                let properties = Value::from_json(serde_json::json!({ "name": role, "grants": grants }))?;
+                AccessWrite::Insert(Node::new(ROLE_TYPE, properties))
+            }
+        })
+    }
+
+    async fn revoke_write(&self, role: &str, grants: &[Grant]) -> Result<AccessWrite> {
+        if role == ADMIN_ROLE {
+            bail!("The {} role cannot be changed", ADMIN_ROLE);
+        }
+
+        let node = self
+            .find_role(role)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Role not found: {}", role))?;
+        let remaining: Vec<Grant> = role_grants(&node).into_iter().filter(|g| !grants.contains(g)).collect();
+        Ok(AccessWrite::Update { node, properties: serde_json::json!({ "grants": remaining }) })
+    }
+
+    async fn assign_write(&self, username: &str, role: &str) -> Result<Option<AccessWrite>> {
+        if role != ADMIN_ROLE && role != PUBLIC_ROLE && self.find_role(role).await?.is_none() {
+            bail!("Role not found: {}", role);
+        }
+
+        let user = UserStore::new(self.accounts).require_user(username).await?;
+        let mut roles = user_roles(&user);
+        if roles.iter().any(|r| r == role) {
+            return Ok(None);
+        }
+        roles.push(role.to_string());
+        Ok(Some(AccessWrite::Update { node: user, properties: serde_json::json!({ "roles": roles }) }))
+    }
+
+    async fn unassign_write(&self, username: &str, role: &str) -> Result<AccessWrite> {
+        let user = UserStore::new(self.accounts).require_user(username).await?;
+        let mut roles = user_roles(&user);
+        roles.retain(|r| r != role);
+        Ok(AccessWrite::Update { node: user, properties: serde_json::json!({ "roles": roles }) })
+    }
+
+    /// Roles and their grants, by name
+    pub async fn roles(&self) -> Result<BTreeMap<String, Vec<Grant>>> {
+        let mut roles = BTreeMap::new();
+        roles.insert(ADMIN_ROLE.to_string(), vec![Grant::new(Permission::Admin, Resource::All)]);
+        for node in self.roles.get_all_by_type(ROLE_TYPE, None).await? {
+            if let Some(name) = string_property(&node, "name") {
+                roles.insert(name.to_string(), role_grants(&node));
+            }
+        }
+        Ok(roles)
+    }
+
+    /// Roles given to a user
+    pub async fn user_roles(&self, username: &str) -> Result<Vec<String>> {
+        Ok(user_roles(&UserStore::new(self.accounts).require_user(username).await?))
+    }
+
+    /// Resolve every user's grants
+    pub async fn policy(&self) -> Result<Policy> {
+        let roles = self.roles().await?;
+        let users = self.accounts.get_all_by_type(USER_TYPE, None).await?;
+
+        let users = users
+            .iter()
+            .filter_map(|node| {
+                let name = string_property(node, "username")?;
+                let grants = user_roles(node)
+                    .iter()
+                    .filter_map(|role| roles.get(role))
+                    .flatten()
+                    .cloned()
+                    .collect();
+                Some((name.to_string(), grants))
+            })
+            .collect::<HashMap<_, _>>();
+
+        Ok(Policy {
+            enforced: !users.is_empty(),
+            users,
+            public: roles.get(PUBLIC_ROLE).cloned().unwrap_or_default(),
+        })
+    }
+
+    /// Append a denial to the audit log
+    pub async fn record_denial(&self, denial: &Denial) -> Result<()> {
+        self.accounts
+            .insert_node(AUDIT_TYPE, serde_json::json!({
+                "user": denial.user,
+                "operation": denial.operation,
+                "permission": denial.permission,
+                "resource": denial.resource,
+            }))
+            .await?;
+        Ok(())
+    }
+
+    /// Recorded denials, most recent first
+    pub async fn denials(&self, limit: Option<usize>) -> Result<Vec<Denial>> {
+        let mut denials: Vec<Denial> = self
+            .accounts
+            .get_all_by_type(AUDIT_TYPE, None)
+            .await?
+            .iter()
+            .filter_map(|node| {
+                Some(Denial {
+                    user: string_property(node, "user").map(str::to_string),
+                    operation: string_property(node, "operation")?.to_string(),
+                    permission: from_property(node, "permission")?,
+                    resource: from_property(node, "resource")?,
+                    at: node.created_at,
+                })
+            })
+            .collect();
+
+        denials.sort_by_key(|d| std::cmp::Reverse(d.at));
+        if let Some(limit) = limit {
+            denials.truncate(limit);
+        }
+        Ok(denials)
+    }
+
+    async fn find_role(&self, name: &str) -> Result<Option<Node>> {
+        Ok(self
+            .roles
+            .get_all_by_type(ROLE_TYPE, None)
+            .await?
+            .into_iter()
+            .find(|n| string_property(n, "name") == Some(name)))
+    }
+}
+
+/// Permissions a parsed query needs on every type its plan touches
+pub(crate) fn query_access(query: &ParsedQuery) -> Result<Vec<(Permission, Resource)>> {
+    let plan = QueryPlanner::new().plan(query)?;
+    let mut access = Vec::new();
+    let mut scanned = None;
+
+    for step in &plan.steps {
+        match step {
+            PlanStep::FullScan { node_type } | PlanStep::IndexLookup { node_type, .. } => {
+                access.push((Permission::Read, Resource::node(node_type)));
+                scanned = Some(node_type.clone());
+            }
+            PlanStep::InsertNode { node_type, .. } => {
+                access.push((Permission::Write, Resource::node(node_type)));
+            }
+            PlanStep::UpdateNodes { .. } => {
+                let node_type = scanned.clone().unwrap_or_else(|| query.target.clone());
+                access.push((Permission::Write, Resource::node(node_type)));
+            }
+            PlanStep::DeleteNodes => {
+                let node_type = scanned.clone().unwrap_or_else(|| query.target.clone());
+                access.push((Permission::Delete, Resource::node(node_type)));
+            }
+            // A traversal can reach nodes of any type
+            PlanStep::Traverse { edge_types: Some(types), .. } => {
+                access.push((Permission::Read, Resource::All));
+                access.extend(types.iter().map(|t| (Permission::Read, Resource::edge(t))));
+            }
+            PlanStep::Traverse { edge_types: None, .. } => access.push((Permission::Read, Resource::All)),
+            _ => {}
+        }
+    }
+
+    match query.operation {
+        QueryOperation::VectorSearch => access.push((Permission::Read, Resource::node(&query.target))),
+        QueryOperation::CreateSchema | QueryOperation::DropSchema => access.push((Permission::Admin, Resource::All)),
+        _ => {}
+    }
+    Ok(access)
+}
+
+fn role_grants(node: &Node) -> Vec<Grant> {
+    from_property(node, "grants").unwrap_or_default()
+}
+
+fn user_roles(node: &Node) -> Vec<String> {
+    from_property(node, "roles").unwrap_or_default()
+}
+
+fn from_property<T: serde::de::DeserializeOwned>(node: &Node, key: &str) -> Option<T> {
+    node.properties
+        .get(key)
+        .map(Value::to_json)
+        .and_then(|json| serde_json::from_value(json).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_access_statements() {
+        assert!(AccessStatement::parse("SELECT * FROM user").is_none());
+
+        let statement = AccessStatement::parse("grant select, INSERT on user to analyst;").unwrap().unwrap();
+        assert_eq!(statement, AccessStatement::Grant {
+            grants: vec![
+                Grant::new(Permission::Read, Resource::node("user")),
+                Grant::new(Permission::Write, Resource::node("user")),
+            ],
+            role: "analyst".to_string(),
+        });
+
+        let statement = AccessStatement::parse("REVOKE ALL PRIVILEGES ON EDGE follows FROM analyst").unwrap().unwrap();
+        match statement {
+            AccessStatement::Revoke { grants, role } => {
+                assert_eq!(grants.len(), 3);
+                assert!(grants.iter().all(|g| g.resource == Resource::edge("follows")));
+                assert_eq!(role, "analyst");
+            }
+            other => panic!("unexpected statement: {:?}", other),
+        }
+
+        let statement = AccessStatement::parse("GRANT ROLE analyst TO ada").unwrap().unwrap();
+        assert_eq!(statement, AccessStatement::GrantRole { role: "analyst".into(), user: "ada".into() });
+
+        assert!(AccessStatement::parse("GRANT FLY ON user TO analyst").unwrap().is_err());
+        assert!(AccessStatement::parse("GRANT READ ON user FROM analyst").unwrap().is_err());
+        assert!(AccessStatement::parse("REVOKE ROLE analyst TO ada").unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_access_store_policy() {
+        let temp = TempDir::new().unwrap();
+        let db = Database::create(temp.path(), "test").await.unwrap();
+        let store = AccessStore::new(&db);
+
+        assert!(!store.policy().await.unwrap().is_enforced());
+
+        let users = UserStore::new(&db);
+        users.create_user("ada", "pw").await.unwrap();
+        users.create_user("root", "pw").await.unwrap();
+
+        store.grant("analyst", &[Grant::new(Permission::Read, Resource::node("user"))]).await.unwrap();
+        store.grant(PUBLIC_ROLE, &[Grant::new(Permission::Read, Resource::node("post"))]).await.unwrap();
+        store.assign_role("ada", "analyst").await.unwrap();
+        store.assign_role("root", ADMIN_ROLE).await.unwrap();
+        assert!(store.assign_role("ada", "missing").await.is_err());
+        assert!(store.grant(ADMIN_ROLE, &[]).await.is_err());
+
+        let policy = store.policy().await.unwrap();
+        assert!(policy.is_enforced());
+        assert!(policy.allows(Some("ada"), Permission::Read, &Resource::node("user")));
+        assert!(!policy.allows(Some("ada"), Permission::Write, &Resource::node("user")));
+        assert!(policy.allows(Some("ada"), Permission::Read, &Resource::node("post")));
+        assert!(policy.allows(None, Permission::Read, &Resource::node("post")));
+        assert!(!policy.allows(None, Permission::Read, &Resource::node("user")));
+        assert!(policy.allows(Some("root"), Permission::Delete, &Resource::edge("follows")));
+
+        store.revoke("analyst", &[Grant::new(Permission::Read, Resource::node("user"))]).await.unwrap();
+        let policy = store.policy().await.unwrap();
+        assert!(!policy.allows(Some("ada"), Permission::Read, &Resource::node("user")));
+        assert_eq!(store.user_roles("ada").await.unwrap(), vec!["analyst"]);
+
+        store.record_denial(&Denial {
+            user: Some("ada".into()),
+            operation: "DeleteNode".into(),
+            permission: Permission::Delete,
+            resource: Resource::node("user"),
+            at: Timestamp::now(),
+        }).await.unwrap();
+        let denials = store.denials(None).await.unwrap();
+        assert_eq!(denials.len(), 1);
+        assert_eq!(denials[0].resource, Resource::node("user"));
+        assert_eq!(denials[0].user.as_deref(), Some("ada"));
+    }
+
+    #[test]
+    fn test_query_access() {
+        let parser = crate::query::QueryParser::new();
+
+        let query = parser.parse("DELETE FROM post WHERE views < 10").unwrap();
+        let access = query_access(&query).unwrap();
+        assert!(access.contains(&(Permission::Read, Resource::node("post"))));
+        assert!(access.contains(&(Permission::Delete, Resource::node("post"))));
+
+        let query = parser.parse("INSERT INTO user (name) VALUES ('Ada')").unwrap();
+        assert_eq!(query_access(&query).unwrap(), vec![(Permission::Write, Resource::node("user"))]);
+    }
+}
@@ -0,0 +1,166 @@
+//! TLS Support
+//!
+//! Server certificates and client trust roots are loaded from PEM files.
+//! Connections are carried as a boxed [`Stream`], so plain TCP and TLS
+//! share the same framing code.
+
+use anyhow::{Result, Context, bail};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::{self, Certificate, PrivateKey, RootCertStore, ServerName};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+/// A bidirectional byte stream, plain or encrypted
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// Server-side TLS configuration
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    /// PEM file with the certificate chain, leaf first
+    pub cert_path: PathBuf,
+    /// PEM file with the private key (PKCS#8, PKCS#1 or SEC1)
+    pub key_path: PathBuf,
+}
+
+impl TlsConfig {
+    /// Create a configuration from certificate and key files
+    pub fn new(cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Self {
+        Self {
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+        }
+    }
+
+    /// Load the certificate and key into an acceptor
+    pub fn acceptor(&self) -> Result<TlsAcceptor> {
+        let certs = load_certs(&self.cert_path)?;
+        let key = load_key(&self.key_path)?;
+
+        let config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .context("Invalid TLS certificate or key")?;
+
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+/// Client-side TLS configuration: the roots server certificates must
+/// chain to
+#[derive(Clone)]
+pub struct ClientTlsConfig {
+    roots: RootCertStore,
+    server_name: Option<String>,
+}
+
+impl ClientTlsConfig {
+    /// Trust no roots yet; add some with [`add_ca_file`](Self::add_ca_file)
+    pub fn new() -> Self {
+        Self {
+            roots: RootCertStore::empty(),
+            server_name: None,
+        }
+    }
+
+    /// Trust every certificate in a PEM file
+    ///
+    /// A self-signed server certificate can be trusted directly this way.
+    pub fn add_ca_file(mut self, path: impl AsRef<Path>) -> Result<Self> {
+        for cert in load_certs(path.as_ref())? {
+            self.roots.add(&cert).context("Invalid CA certificate")?;
+        }
+        Ok(self)
+    }
+
+    /// Name to verify the server certificate against, instead of the host
+    /// connected to
+    pub fn server_name(mut self, name: impl Into<String>) -> Self {
+        self.server_name = Some(name.into());
+        self
+    }
+
+    /// Wrap a connected socket, verifying the server as `host`
+    pub async fn connect(&self, host: &str, stream: TcpStream) -> Result<Box<dyn Stream>> {
+        let config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(self.roots.clone())
+            .with_no_client_auth();
+
+        let name = self.server_name.as_deref().unwrap_or(host);
+        let name = ServerName::try_from(name)
+            .map_err(|_| anyhow::anyhow!("Invalid TLS server name: {}", name))?;
+
+        let stream = TlsConnector::from(Arc::new(config))
+            .connect(name, stream)
+            .await
+            .context("TLS handshake failed")?;
+        Ok(Box::new(stream))
+    }
+}
+
+impl Default for ClientTlsConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for ClientTlsConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientTlsConfig")
+            .field("roots", &self.roots.len())
+            .field("server_name", &self.server_name)
+            .finish()
+    }
+}
+
+/// Connect to `addr`, wrapping the socket in TLS when configured
+///
+/// `host` is the name the server certificate is verified against unless
+/// the TLS configuration overrides it.
+pub(crate) async fn dial(addr: SocketAddr, host: &str, tls: Option<&ClientTlsConfig>) -> Result<Box<dyn Stream>> {
+    let stream = TcpStream::connect(addr)
+        .await
+        .context("Failed to connect to server")?;
+    // Frames are small request/response pairs; don't let Nagle batch them
+    let _ = stream.set_nodelay(true);
+
+    match tls {
+        Some(tls) => tls.connect(host, stream).await,
+        None => Ok(Box::new(stream)),
+    }
+}
+
+fn load_certs(path: &Path) -> Result<Vec<Certificate>> {
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut std::io::BufReader::new(file))
+        .with_context(|| format!("Failed to read certificates from {}", path.display()))?;
+
+    if certs.is_empty() {
+        bail!("No certificates found in {}", path.display());
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn load_key(path: &Path) -> Result<PrivateKey> {
+    use rustls_pemfile::Item;
+
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let items = rustls_pemfile::read_all(&mut std::io::BufReader::new(file))
+        .with_context(|| format!("Failed to read private key from {}", path.display()))?;
+
+    items
+        .into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow::anyhow!("No private key found in {}", path.display()))
+}
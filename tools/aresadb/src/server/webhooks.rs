@@ -0,0 +1,601 @@
+//! Webhook delivery
+//!
+//! Follows the change feed and posts every change matched by an `AFTER`
+//! trigger to the trigger's webhook as JSON. A delivery is retried with
+//! doubling backoff; once its retries run out the payload is stored as a
+//! `__dead_letter__` node and delivery moves on.
+//!
+//! Each webhook follows the feed on its own, so a slow or failing receiver
+//! only holds back its own changes. The last sequence a webhook handled is
+//! kept in the database metadata, so changes committed while the server
+//! was down are delivered when it comes back.
+//! Delivery is at least once: a change may be posted again after a crash
+//! or a leader change, and carries its sequence in the `X-AresaDB-Event`
+//! header for receivers to drop repeats. In replicated mode only the
+//! leader delivers, and it saves the cursor through consensus so whichever
+//! node leads next carries on from there.
+
+use anyhow::{Context, Result};
+use futures::{FutureExt, StreamExt};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use crate::distributed::{NotLeader, RaftNode, ReplicationCommand};
+use crate::schema::{
+    Trigger, TriggerAction, TriggerEvent, TriggerSet, TriggerTiming, Webhook, DEAD_LETTER_TYPE, TRIGGER_TYPE,
+};
+use crate::storage::{Change, ChangeEvent, ChangeFilter, Database, Node, Value};
+
+/// Metadata key prefix of each webhook's cursor; on its own, the cursor
+/// all webhooks shared before each had one
+const CURSOR_KEY: &str = "webhook_cursor";
+
+/// Metadata key holding the last sequence handled by the webhook of `trigger`
+fn cursor_key(trigger: &str) -> String {
+    format!("{}:{}", CURSOR_KEY, trigger)
+}
+
+/// Changes handled between cursor saves while more are waiting
+const CURSOR_BATCH: usize = 100;
+
+/// How webhooks are delivered
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    /// Time allowed for one POST
+    pub timeout: Duration,
+    /// Wait before the first retry; doubles for each one after
+    pub backoff: Duration,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Delivery tasks of the webhooks being followed
+#[derive(Default)]
+struct Hooks {
+    /// Stops the task of each trigger, by name
+    stops: HashMap<String, CancellationToken>,
+    tasks: JoinSet<Result<()>>,
+}
+
+impl Hooks {
+    /// Start delivering the webhook of `trigger`, if it has one not already running
+    fn launch(&mut self, dispatcher: &Arc<WebhookDispatcher>, trigger: Trigger, from: u64, shutdown: &CancellationToken) {
+        if matches!(trigger.action, TriggerAction::Webhook(_)) && !self.stops.contains_key(&trigger.name) {
+            let stop = shutdown.child_token();
+            self.stops.insert(trigger.name.clone(), stop.clone());
+            self.tasks.spawn(Arc::clone(dispatcher).follow_hook(trigger, from, stop));
+        }
+    }
+
+    /// Stop delivering the webhook of the trigger named `name`
+    fn stop(&mut self, name: &str) {
+        if let Some(stop) = self.stops.remove(name) {
+            stop.cancel();
+        }
+    }
+}
+
+/// Posts changes to the webhooks of `AFTER` triggers
+pub struct WebhookDispatcher {
+    db: Database,
+    raft: Option<Arc<RaftNode>>,
+    config: WebhookConfig,
+    http: reqwest::Client,
+}
+
+impl WebhookDispatcher {
+    /// Deliver the changes to `db`
+    pub fn new(db: Database, config: WebhookConfig) -> Result<Self> {
+        let http = reqwest::Client::builder().timeout(config.timeout).build()?;
+        Ok(Self { db, raft: None, config, http })
+    }
+
+    /// Deliver the changes to a replicated database while this node leads,
+    /// dead-lettering through consensus
+    pub fn replicated(raft: Arc<RaftNode>, config: WebhookConfig) -> Result<Self> {
+        let mut dispatcher = Self::new(raft.database().clone(), config)?;
+        dispatcher.raft = Some(raft);
+        Ok(dispatcher)
+    }
+
+    /// Deliver until `shutdown` is cancelled
+    pub async fn run(self, shutdown: CancellationToken) {
+        let dispatcher = Arc::new(self);
+        while !shutdown.is_cancelled() {
+            if dispatcher.is_leader() {
+                match dispatcher.follow(&shutdown).await {
+                    Err(e) if e.downcast_ref::<NotLeader>().is_some() => {
+                        debug!("Webhook delivery handed over: {:#}", e);
+                    }
+                    Err(e) => warn!("Webhook delivery stopped: {:#}", e),
+                    Ok(()) => {}
+                }
+            }
+            tokio::select! {
+                _ = shutdown.cancelled() => {}
+                _ = tokio::time::sleep(Duration::from_secs(1)) => {}
+            }
+        }
+    }
+
+    fn is_leader(&self) -> bool {
+        self.raft.as_ref().is_none_or(|raft| raft.replica().is_leader())
+    }
+
+    /// Sequence the webhook of `trigger` carries on after, starting after
+    /// `from` the first time
+    async fn cursor(&self, trigger: &str, from: u64) -> Result<u64> {
+        let local = self.db.local();
+        let cursor = match local.get_metadata(&cursor_key(trigger))? {
+            Some(bytes) => serde_json::from_slice(&bytes)?,
+            None => {
+                self.save_cursor(trigger, from).await?;
+                from
+            }
+        };
+        if let Err(e) = local.check_retained(cursor) {
+            let latest = local.last_change_seq()?;
+            warn!("{}; skipping webhooks of trigger '{}' for changes up to {}", e, trigger, latest);
+            self.save_cursor(trigger, latest).await?;
+            return Ok(latest);
+        }
+        Ok(cursor)
+    }
+
+    /// Record `seq` as handled by the webhook of `trigger`, on every
+    /// replica when replicated
+    async fn save_cursor(&self, trigger: &str, seq: u64) -> Result<()> {
+        let key = cursor_key(trigger);
+        let value = serde_json::to_vec(&seq)?;
+        match self.raft {
+            Some(ref raft) => raft.propose(ReplicationCommand::put_metadata(&key, &value)?).await,
+            None => self.db.local().put_metadata(&key, &value),
+        }
+    }
+
+    /// Run a delivery task per webhook, starting and stopping them as
+    /// triggers are created and dropped, until leadership is lost or on
+    /// shutdown
+    ///
+    /// When replicated, a no-op is committed first so that every cursor
+    /// save of earlier leaders has been applied here before reading them.
+    async fn follow(self: &Arc<Self>, shutdown: &CancellationToken) -> Result<()> {
+        if let Some(ref raft) = self.raft {
+            raft.propose(ReplicationCommand::Nop).await?;
+        }
+        let local = self.db.local();
+        let latest = local.last_change_seq()?;
+        // Webhooks without a cursor of their own start from the shared one
+        // of earlier versions, or from the latest change the first time
+        let start = match local.get_metadata(CURSOR_KEY)? {
+            Some(bytes) => serde_json::from_slice(&bytes)?,
+            None => latest,
+        };
+
+        let mut hooks = Hooks::default();
+        for trigger in TriggerSet::load(local).await?.all() {
+            hooks.launch(self, trigger, start, shutdown);
+        }
+        // Definitions committed from here on start and stop webhooks
+        let mut definitions = self.db.changes(Some(latest), ChangeFilter::all().node_type(TRIGGER_TYPE))?;
+        debug!("Delivering {} webhooks", hooks.stops.len());
+
+        let result = loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break Ok(()),
+                Some(ended) = hooks.tasks.join_next() => match ended {
+                    Ok(Ok(())) if shutdown.is_cancelled() || !self.is_leader() => break Ok(()),
+                    // A dropped trigger's webhook
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => break Err(e),
+                    Err(e) => break Err(anyhow::anyhow!("Webhook task failed: {}", e)),
+                },
+                event = definitions.next() => {
+                    let event = match event.context("Change feed ended") {
+                        Ok(Ok(event)) => event,
+                        Ok(Err(e)) | Err(e) => break Err(e),
+                    };
+                    let (removed, added) = match event.change {
+                        Change::NodeInserted { node } => (None, Some(node)),
+                        Change::NodeUpdated { before, after } => (Some(before), Some(after)),
+                        Change::NodeDeleted { node } => (Some(node), None),
+                        _ => (None, None),
+                    };
+                    if let Some(trigger) = removed.as_ref().and_then(Trigger::from_node) {
+                        hooks.stop(&trigger.name);
+                    }
+                    if let Some(trigger) = added.as_ref().and_then(Trigger::from_node) {
+                        hooks.launch(self, trigger, event.seq, shutdown);
+                    }
+                }
+            }
+        };
+        for stop in hooks.stops.values() {
+            stop.cancel();
+        }
+        while hooks.tasks.join_next().await.is_some() {}
+        result
+    }
+
+    /// Deliver the changes the webhook of `trigger` runs on, in order,
+    /// until `stop`
+    async fn follow_hook(self: Arc<Self>, trigger: Trigger, from: u64, stop: CancellationToken) -> Result<()> {
+        let TriggerAction::Webhook(ref webhook) = trigger.action else {
+            return Ok(());
+        };
+        let cursor = self.cursor(&trigger.name, from).await?;
+        let filter = ChangeFilter::all().node_type(&trigger.node_type);
+        let mut changes = self.db.changes(Some(cursor), filter)?.peekable();
+        debug!("Delivering webhooks of trigger '{}' after change {}", trigger.name, cursor);
+
+        // Handled since the cursor was last saved
+        let mut unsaved = 0;
+
+        loop {
+            let event = tokio::select! {
+                _ = stop.cancelled() => return Ok(()),
+                event = changes.next() => event.context("Change feed ended")??,
+            };
+            let fires = match (TriggerEvent::of(&event.change), event.change.node()) {
+                (Some(kind), Some(node)) => trigger.fires(TriggerTiming::After, kind, &node.node_type),
+                _ => false,
+            };
+            if fires {
+                if !self.is_leader() {
+                    return Ok(());
+                }
+                tokio::select! {
+                    // Not marked done, so it goes out again after a restart
+                    _ = stop.cancelled() => return Ok(()),
+                    delivered = self.deliver(&trigger, webhook, &event) => delivered?,
+                }
+            }
+
+            // Saved once caught up with the feed, and every batch while behind
+            unsaved += 1;
+            let caught_up = Pin::new(&mut changes).peek().now_or_never().is_none();
+            if caught_up || unsaved >= CURSOR_BATCH {
+                self.save_cursor(&trigger.name, event.seq).await?;
+                unsaved = 0;
+            }
+        }
+    }
+
+    /// Post one change, dead-lettering it if every attempt fails
+    async fn deliver(
+        &self,
+        trigger: &Trigger,
+        webhook: &Webhook,
+        event: &ChangeEvent,
+    ) -> Result<()> {
+        let mut payload = event.to_json();
+        payload["trigger"] = trigger.name.clone().into();
+        payload["node_type"] = trigger.node_type.clone().into();
+
+        let mut backoff = self.config.backoff;
+        let mut attempts = 0;
+        let error = loop {
+            attempts += 1;
+            let request = self.http
+                .post(&webhook.url)
+                .header("X-AresaDB-Trigger", &trigger.name)
+                .header("X-AresaDB-Event", event.seq)
+                .json(&payload)
+                .send();
+            let error = match request.await {
+                Ok(response) if response.status().is_success() => {
+                    debug!("Delivered change {} to trigger '{}'", event.seq, trigger.name);
+                    return Ok(());
+                }
+                Ok(response) => format!("HTTP {}", response.status()),
+                Err(e) => e.to_string(),
+            };
+            if attempts > webhook.retries {
+                break error;
+            }
+            debug!("Webhook for trigger '{}' failed ({}); retrying in {:?}", trigger.name, error, backoff);
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        };
+
+        info!(
+            "Webhook for trigger '{}' failed {} times ({}); dead-lettering change {}",
+            trigger.name, attempts, error, event.seq
+        );
+        let letter = serde_json::json!({
+            "trigger": trigger.name,
+            "url": webhook.url,
+            "seq": event.seq,
+            "attempts": attempts,
+            "error": error,
+            "payload": payload,
+        });
+        match self.raft {
+            Some(ref raft) => {
+                let node = Node::new(DEAD_LETTER_TYPE, Value::from_json(letter)?);
+                raft.propose(ReplicationCommand::insert_node(&node)?).await?;
+            }
+            None => {
+                self.db.insert_node(DEAD_LETTER_TYPE, letter).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distributed::{InProcessNetwork, ReplicaConfig, ReplicaSet};
+    use crate::schema::{SchemaManager, TriggerStatement, TRIGGER_TYPE};
+    use tempfile::TempDir;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    /// Accept requests, answering each with `status` and sending on the body
+    async fn receiver(status: u16) -> (String, mpsc::UnboundedReceiver<serde_json::Value>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 4096];
+                // Read the headers, then as much body as they announce
+                let body_at = loop {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                    if let Some(at) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break at + 4;
+                    }
+                };
+                let headers = String::from_utf8_lossy(&request[..body_at]).to_lowercase();
+                let length: usize = headers
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length:"))
+                    .map(|v| v.trim().parse().unwrap())
+                    .unwrap_or(0);
+                while request.len() < body_at + length {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let _ = tx.send(serde_json::from_slice(&request[body_at..]).unwrap());
+                let response = format!("HTTP/1.1 {} X\r\ncontent-length: 0\r\nconnection: close\r\n\r\n", status);
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (url, rx)
+    }
+
+    async fn create_trigger(db: &Database, sql: &str) {
+        let statement = TriggerStatement::parse(sql).unwrap().unwrap();
+        SchemaManager::new(db.clone()).execute_trigger_statement(statement).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_webhooks_deliver_and_dead_letter() {
+        let temp = TempDir::new().unwrap();
+        let db = Database::create(temp.path(), "test").await.unwrap();
+        let (ok_url, mut delivered) = receiver(200).await;
+        let (failing_url, mut attempted) = receiver(503).await;
+
+        create_trigger(&db, &format!("CREATE TRIGGER users AFTER INSERT OR DELETE ON user WEBHOOK '{}'", ok_url)).await;
+        let config = WebhookConfig { timeout: Duration::from_secs(5), backoff: Duration::from_millis(10) };
+        let shutdown = CancellationToken::new();
+        let dispatcher = WebhookDispatcher::new(db.clone(), config.clone()).unwrap();
+        let task = tokio::spawn(dispatcher.run(shutdown.clone()));
+        // Let it start following before anything to deliver commits
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let ada = db.insert_node("user", serde_json::json!({ "name": "Ada" })).await.unwrap();
+        db.update_node(&ada.id.to_string(), serde_json::json!({ "name": "Ada L" })).await.unwrap();
+        db.insert_node("post", serde_json::json!({})).await.unwrap();
+        db.delete_node(&ada.id.to_string()).await.unwrap();
+
+        let first = delivered.recv().await.unwrap();
+        assert_eq!(first["trigger"], "users");
+        assert_eq!(first["kind"], "node_inserted");
+        assert_eq!(first["node"]["properties"]["name"], "Ada");
+        // Updates and other types are not delivered
+        let second = delivered.recv().await.unwrap();
+        assert_eq!(second["kind"], "node_deleted");
+
+        // A trigger created while running is picked up from the feed
+        create_trigger(&db, &format!(
+            "CREATE TRIGGER posts AFTER INSERT ON post WEBHOOK '{}' RETRIES 2", failing_url,
+        )).await;
+        let post = db.insert_node("post", serde_json::json!({ "title": "Hi" })).await.unwrap();
+        for _ in 0..3 {
+            assert_eq!(attempted.recv().await.unwrap()["node"]["id"], post.id.to_string());
+        }
+
+        let mut letters = Vec::new();
+        for _ in 0..100 {
+            letters = db.get_all_by_type(DEAD_LETTER_TYPE, None).await.unwrap();
+            if !letters.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].get("trigger").and_then(Value::as_str), Some("posts"));
+        assert_eq!(letters[0].get("attempts").and_then(Value::as_int), Some(3));
+        assert_eq!(letters[0].get("error").and_then(Value::as_str), Some("HTTP 503 Service Unavailable"));
+
+        shutdown.cancel();
+        task.await.unwrap();
+
+        // Changes committed while stopped go out on the next run
+        db.insert_node("user", serde_json::json!({ "name": "Grace" })).await.unwrap();
+        let shutdown = CancellationToken::new();
+        let task = tokio::spawn(WebhookDispatcher::new(db.clone(), config).unwrap().run(shutdown.clone()));
+        let next = delivered.recv().await.unwrap();
+        assert_eq!(next["node"]["properties"]["name"], "Grace");
+        shutdown.cancel();
+        task.await.unwrap();
+        assert!(delivered.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_webhooks_deliver_independently() {
+        let temp = TempDir::new().unwrap();
+        let db = Database::create(temp.path(), "test").await.unwrap();
+        let (ok_url, mut delivered) = receiver(200).await;
+        // Accepts requests and never answers them
+        let stalled = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stalled_url = format!("http://{}/hook", stalled.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut open = Vec::new();
+            while let Ok((stream, _)) = stalled.accept().await {
+                open.push(stream);
+            }
+        });
+
+        create_trigger(&db, &format!("CREATE TRIGGER a_stalled AFTER INSERT ON user WEBHOOK '{}'", stalled_url)).await;
+        create_trigger(&db, &format!("CREATE TRIGGER b_ok AFTER INSERT ON user WEBHOOK '{}'", ok_url)).await;
+        let config = WebhookConfig { timeout: Duration::from_secs(30), backoff: Duration::from_millis(10) };
+        let shutdown = CancellationToken::new();
+        let task = tokio::spawn(WebhookDispatcher::new(db.clone(), config).unwrap().run(shutdown.clone()));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // Both changes reach the answering webhook while the other is still
+        // waiting on the first
+        for name in ["Ada", "Grace"] {
+            db.insert_node("user", serde_json::json!({ "name": name })).await.unwrap();
+        }
+        for name in ["Ada", "Grace"] {
+            let payload = tokio::time::timeout(Duration::from_secs(5), delivered.recv()).await.unwrap().unwrap();
+            assert_eq!(payload["trigger"], "b_ok");
+            assert_eq!(payload["node"]["properties"]["name"], name);
+        }
+
+        shutdown.cancel();
+        tokio::time::timeout(Duration::from_secs(5), task).await.unwrap().unwrap();
+    }
+
+    /// Wait until `nodes` agree on a leader, returning its position
+    async fn leader_of(nodes: &[Arc<RaftNode>]) -> usize {
+        for _ in 0..300 {
+            if let Some(i) = nodes.iter().position(|n| n.replica().is_leader()) {
+                return i;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("no leader elected");
+    }
+
+    /// Name of the user in the next delivery
+    async fn next_user(delivered: &mut mpsc::UnboundedReceiver<serde_json::Value>) -> String {
+        let payload = tokio::time::timeout(Duration::from_secs(10), delivered.recv())
+            .await
+            .expect("a delivery")
+            .unwrap();
+        payload["node"]["properties"]["name"].as_str().unwrap().to_string()
+    }
+
+    /// Wait until `node` holds a cursor saved for its latest change
+    async fn cursor_settled(node: &RaftNode) {
+        let local = node.database().local();
+        for _ in 0..100 {
+            let saved = local.get_metadata(&cursor_key("users")).unwrap();
+            if saved == Some(serde_json::to_vec(&local.last_change_seq().unwrap()).unwrap()) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("cursor not saved");
+    }
+
+    #[tokio::test]
+    async fn test_webhook_cursor_survives_failover() {
+        let temp = TempDir::new().unwrap();
+        let ids = ["a", "b", "c"];
+        let network = InProcessNetwork::new();
+        let mut nodes = Vec::new();
+        for id in ids {
+            let dir = temp.path().join(id);
+            let db = Database::create(&dir, id).await.unwrap();
+            let config = ReplicaConfig {
+                node_id: id.to_string(),
+                peers: ids.iter().filter(|p| **p != id).map(|p| p.to_string()).collect(),
+                election_timeout_ms: (100, 200),
+                heartbeat_interval_ms: 10,
+                ..Default::default()
+            };
+            let replica = ReplicaSet::open(config, dir.join(".aresadb/raft")).unwrap();
+            let node = RaftNode::start(replica, db, network.transport(id));
+            network.register(id, node.inbox());
+            nodes.push(node);
+        }
+        let first = leader_of(&nodes).await;
+
+        let (url, mut delivered) = receiver(200).await;
+        let trigger = match TriggerStatement::parse(&format!("CREATE TRIGGER users AFTER INSERT ON user WEBHOOK '{}'", url)) {
+            Some(Ok(TriggerStatement::Create(trigger))) => trigger,
+            _ => panic!("expected CREATE"),
+        };
+        let definition = Node::new(TRIGGER_TYPE, Value::from_json(trigger.to_properties().unwrap()).unwrap());
+        nodes[first].propose(ReplicationCommand::insert_node(&definition).unwrap()).await.unwrap();
+
+        // Every node runs a dispatcher, as servers do
+        let config = WebhookConfig { timeout: Duration::from_secs(5), backoff: Duration::from_millis(10) };
+        let mut shutdowns = Vec::new();
+        for node in &nodes {
+            let shutdown = CancellationToken::new();
+            let dispatcher = WebhookDispatcher::replicated(node.clone(), config.clone()).unwrap();
+            tokio::spawn(dispatcher.run(shutdown.clone()));
+            shutdowns.push(shutdown);
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let user = |name: &str| {
+            let node = Node::new("user", Value::from_json(serde_json::json!({ "name": name })).unwrap());
+            ReplicationCommand::insert_node(&node).unwrap()
+        };
+        nodes[first].propose(user("Ada")).await.unwrap();
+        assert_eq!(next_user(&mut delivered).await, "Ada");
+
+        // The leader's dispatcher dies with a change still undelivered
+        cursor_settled(&nodes[first]).await;
+        shutdowns[first].cancel();
+        nodes[first].propose(user("Grace")).await.unwrap();
+
+        // The new leader carries on from the replicated cursor
+        let second = (first + 1) % nodes.len();
+        nodes[first].transfer_leadership(ids[second]).await.unwrap();
+        assert_eq!(next_user(&mut delivered).await, "Grace");
+
+        // So does the former leader when it takes over again, rather than
+        // from where it stopped
+        let shutdown = CancellationToken::new();
+        let dispatcher = WebhookDispatcher::replicated(nodes[first].clone(), config).unwrap();
+        tokio::spawn(dispatcher.run(shutdown.clone()));
+        shutdowns[first] = shutdown;
+        nodes[second].propose(user("Linus")).await.unwrap();
+        assert_eq!(next_user(&mut delivered).await, "Linus");
+        cursor_settled(&nodes[first]).await;
+        nodes[second].transfer_leadership(ids[first]).await.unwrap();
+        nodes[first].propose(user("Hopper")).await.unwrap();
+        assert_eq!(next_user(&mut delivered).await, "Hopper");
+
+        for (node, shutdown) in nodes.iter().zip(&shutdowns) {
+            shutdown.cancel();
+            node.shutdown();
+        }
+        assert!(delivered.try_recv().is_err());
+    }
+}
@@ -0,0 +1,708 @@
+//! Backup and Point-in-Time Restore
+//!
+//! A backup location holds a catalog and a chain of files: full backups are
+//! snapshots taken from a single read transaction, and incremental backups
+//! are WAL segments of the changes committed since the previous backup. A
+//! restore starts from the latest full backup before the requested point and
+//! replays the segments after it up to a change sequence or a time.
+//!
+//! Locations are local directories or bucket URLs (`s3://`, `gs://`,
+//! `file://`), written through [`BucketStorage`].
+
+use anyhow::{bail, Context, Result};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::distributed::{WalEntry, WalEntryType};
+use super::bucket::BucketStorage;
+use super::changes::{Change, ChangeEvent};
+use super::local::{self, ReplayOp, SnapshotSummary};
+use super::node::{Edge, EdgeId, Node, NodeId, Timestamp};
+use super::Database;
+
+/// Name of the catalog in a backup location
+const CATALOG_FILE: &str = "backup.json";
+
+/// Changes read from the change log at a time
+const CHANGE_BATCH: usize = 1024;
+
+/// Raw node or edge IDs, as tracked while verifying
+type IdSet = HashSet<[u8; 16]>;
+
+/// What a backup file holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackupKind {
+    /// Snapshot of every node and edge
+    Full,
+    /// WAL segment of the changes since the previous backup
+    Incremental,
+}
+
+/// One backup in a catalog
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupEntry {
+    /// Position in the catalog, from 1
+    pub id: u64,
+    /// Full or incremental
+    pub kind: BackupKind,
+    /// When the backup was taken
+    pub created_at: Timestamp,
+    /// Change sequence the backup starts from; equal to `seq` for a full one
+    pub base_seq: u64,
+    /// Last change sequence the backup reflects
+    pub seq: u64,
+    /// Nodes in the database as of `seq`
+    pub node_count: u64,
+    /// Edges in the database as of `seq`
+    pub edge_count: u64,
+    /// File name within the backup location
+    pub file: String,
+    /// File size in bytes
+    pub size: u64,
+    /// CRC32 of the file
+    pub checksum: u32,
+}
+
+/// Backups of one database, oldest first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupCatalog {
+    /// Name of the database
+    pub database: String,
+    /// Creation time of the database, telling it apart from others of the same name
+    pub created_at: Timestamp,
+    /// The backups
+    pub entries: Vec<BackupEntry>,
+}
+
+/// How far a restore replays
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestorePoint {
+    /// Everything in the backups
+    Latest,
+    /// Up to and including this change sequence
+    Seq(u64),
+    /// Changes committed at or before this time
+    Time(Timestamp),
+}
+
+/// Outcome of a restore
+#[derive(Debug, Clone)]
+pub struct RestoreReport {
+    /// Full backup the restore started from
+    pub full: u64,
+    /// Incremental backups replayed after it
+    pub incrementals: usize,
+    /// Changes replayed
+    pub changes: usize,
+    /// Change sequence the restored database reflects
+    pub seq: u64,
+    /// Nodes restored
+    pub node_count: u64,
+    /// Edges restored
+    pub edge_count: u64,
+}
+
+/// Outcome of verifying a backup location
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    /// Backups checked
+    pub checked: usize,
+    /// Problems found, one line each
+    pub problems: Vec<String>,
+}
+
+impl VerifyReport {
+    /// Whether every backup checked out
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+enum Target {
+    Dir(PathBuf),
+    Bucket(BucketStorage),
+}
+
+/// A location backups are written to and restored from
+pub struct BackupStore {
+    location: String,
+    target: Target,
+}
+
+impl BackupStore {
+    /// Open a backup location: a bucket URL or a local directory
+    pub async fn open(location: &str) -> Result<Self> {
+        let target = if location.contains("://") {
+            Target::Bucket(BucketStorage::connect(location).await?)
+        } else {
+            Target::Dir(PathBuf::from(location))
+        };
+        Ok(Self {
+            location: location.to_string(),
+            target,
+        })
+    }
+
+    /// The catalog, if anything has been backed up here
+    pub async fn catalog(&self) -> Result<Option<BackupCatalog>> {
+        match self.read(CATALOG_FILE).await? {
+            Some(data) => Ok(Some(
+                serde_json::from_slice(&data).context("Corrupt backup catalog")?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Back up `db`, returning the new entry, or `None` if an incremental
+    /// backup found no changes since the last one
+    ///
+    /// An incremental backup needs every change since the previous backup
+    /// to still be in the change log, and falls back to a full backup when
+    /// the location is empty.
+    pub async fn backup(&self, db: &Database, incremental: bool) -> Result<Option<BackupEntry>> {
+        let (name, created_at) = {
+            let config = db.config.read();
+            (config.name.clone(), config.created_at)
+        };
+        let mut catalog = match self.catalog().await? {
+            Some(catalog) => {
+                if catalog.created_at != created_at {
+                    bail!(
+                        "{} holds backups of another database '{}' created {}",
+                        self.location,
+                        catalog.database,
+                        catalog.created_at
+                    );
+                }
+                catalog
+            }
+            None => BackupCatalog {
+                database: name,
+                created_at,
+                entries: Vec::new(),
+            },
+        };
+
+        let id = catalog.entries.len() as u64 + 1;
+        let previous = catalog.entries.last().cloned();
+        let (entry, data) = match previous {
+            Some(previous) if incremental => {
+                match self.incremental(db, id, &previous)? {
+                    Some((entry, data)) => (entry, Payload::Bytes(data)),
+                    None => return Ok(None),
+                }
+            }
+            _ => {
+                let file = format!("{:06}-full.snap", id);
+                let (staged, snapshot) = self.stage_snapshot(db, &file).await?;
+                let entry = BackupEntry {
+                    id,
+                    kind: BackupKind::Full,
+                    created_at: Timestamp::now(),
+                    base_seq: snapshot.change_seq,
+                    seq: snapshot.change_seq,
+                    node_count: snapshot.node_count,
+                    edge_count: snapshot.edge_count,
+                    file,
+                    size: staged.size,
+                    checksum: staged.checksum,
+                };
+                (entry, Payload::File(staged))
+            }
+        };
+
+        // The file goes first so the catalog never names a missing one
+        match data {
+            Payload::Bytes(data) => self.write(&entry.file, data).await?,
+            Payload::File(staged) => self.write_staged(&entry.file, staged).await?,
+        }
+        catalog.entries.push(entry.clone());
+        self.write(CATALOG_FILE, serde_json::to_vec_pretty(&catalog)?).await?;
+        Ok(Some(entry))
+    }
+
+    fn incremental(
+        &self,
+        db: &Database,
+        id: u64,
+        previous: &BackupEntry,
+    ) -> Result<Option<(BackupEntry, Vec<u8>)>> {
+        let local = db.local();
+        let last = local.last_change_seq()?;
+        if last < previous.seq {
+            bail!(
+                "The database is at change {} but the last backup is at {}; take a full backup",
+                last,
+                previous.seq
+            );
+        }
+        if last == previous.seq {
+            return Ok(None);
+        }
+        local.check_retained(previous.seq)
+            .context("Changes since the last backup were pruned; take a full backup")?;
+
+        let mut events: Vec<ChangeEvent> = Vec::new();
+        let mut after = previous.seq;
+        while after < last {
+            let batch = local.changes_after(after, CHANGE_BATCH)?;
+            let Some(tail) = batch.last() else { break };
+            after = tail.seq;
+            events.extend(batch.into_iter().filter(|event| event.seq <= last));
+        }
+
+        let mut node_count = previous.node_count as i64;
+        let mut edge_count = previous.edge_count as i64;
+        let mut entries = Vec::with_capacity(events.len());
+        for event in &events {
+            match &event.change {
+                Change::NodeInserted { .. } => node_count += 1,
+                Change::NodeDeleted { .. } => node_count -= 1,
+                Change::EdgeInserted { .. } => edge_count += 1,
+                Change::EdgeDeleted { .. } => edge_count -= 1,
+                Change::NodeUpdated { .. } => {}
+            }
+            entries.push(wal_entry(event)?);
+        }
+
+        let data = WalEntry::encode_segment(&entries)?;
+        let entry = BackupEntry {
+            id,
+            kind: BackupKind::Incremental,
+            created_at: Timestamp::now(),
+            base_seq: previous.seq,
+            seq: last,
+            node_count: node_count.max(0) as u64,
+            edge_count: edge_count.max(0) as u64,
+            file: format!("{:06}-incr.wal", id),
+            size: data.len() as u64,
+            checksum: crc32fast::hash(&data),
+        };
+        Ok(Some((entry, data)))
+    }
+
+    /// Restore into a new database at `dest`, which must not exist or be empty
+    ///
+    /// The restored database starts a change log of its own, so back it up
+    /// to a new location.
+    pub async fn restore(&self, dest: impl AsRef<Path>, point: RestorePoint) -> Result<RestoreReport> {
+        let dest = dest.as_ref();
+        if dest.exists() && std::fs::read_dir(dest)?.next().is_some() {
+            bail!("Cannot restore into {}: it is not empty", dest.display());
+        }
+
+        let catalog = self.catalog().await?
+            .with_context(|| format!("No backups at {}", self.location))?;
+        let start = catalog.entries.iter()
+            .rposition(|entry| {
+                entry.kind == BackupKind::Full
+                    && match point {
+                        RestorePoint::Latest => true,
+                        RestorePoint::Seq(seq) => entry.seq <= seq,
+                        RestorePoint::Time(time) => entry.created_at <= time,
+                    }
+            })
+            .context("No full backup at or before the restore point")?;
+        let full = &catalog.entries[start];
+
+        let snapshot = self.read_checked(full).await?;
+        let mut seq = full.seq;
+        let mut incrementals = 0;
+        let mut ops = Vec::new();
+        for entry in chain(&catalog.entries[start + 1..], full.seq) {
+            let segment = WalEntry::decode_segment(&self.read_checked(entry).await?)
+                .with_context(|| format!("Corrupt backup {}", entry.file))?;
+            let before = ops.len();
+            let mut done = false;
+            for wal_entry in segment {
+                let included = match point {
+                    RestorePoint::Latest => true,
+                    RestorePoint::Seq(target) => wal_entry.lsn <= target,
+                    RestorePoint::Time(time) => wal_entry.timestamp <= time,
+                };
+                if !included {
+                    done = true;
+                    break;
+                }
+                seq = wal_entry.lsn;
+                ops.push(replay_op(&wal_entry)?);
+            }
+            if ops.len() > before {
+                incrementals += 1;
+            }
+            if done {
+                break;
+            }
+        }
+        if let RestorePoint::Seq(target) = point {
+            if target > seq {
+                bail!("The backups only reach change {}, not {}", seq, target);
+            }
+        }
+
+        let changes = ops.len();
+        let db = Database::create(dest, &catalog.database).await?;
+        db.local().restore_snapshot_data(snapshot.as_slice()).await?;
+        db.local().replay(ops).await?;
+        db.flush().await?;
+        let stats = db.local().stats().await?;
+
+        Ok(RestoreReport {
+            full: full.id,
+            incrementals,
+            changes,
+            seq,
+            node_count: stats.node_count,
+            edge_count: stats.edge_count,
+        })
+    }
+
+    /// Check every backup's size and checksum, and that replaying each chain
+    /// arrives at the node and edge counts recorded for it
+    pub async fn verify(&self) -> Result<VerifyReport> {
+        let catalog = self.catalog().await?
+            .with_context(|| format!("No backups at {}", self.location))?;
+        let mut report = VerifyReport::default();
+
+        // IDs present as of the backup last replayed; `None` until a full
+        // backup has been read
+        let mut state: Option<(IdSet, IdSet)> = None;
+        let mut seq = 0;
+        for entry in &catalog.entries {
+            report.checked += 1;
+            let data = match self.read_checked(entry).await {
+                Ok(data) => data,
+                Err(e) => {
+                    report.problems.push(format!("{}: {:#}", entry.file, e));
+                    if entry.kind == BackupKind::Full {
+                        state = None;
+                    }
+                    continue;
+                }
+            };
+
+            match entry.kind {
+                BackupKind::Full => match local::snapshot_rows(data.as_slice()) {
+                    Ok((nodes, edges)) => {
+                        state = Some((
+                            nodes.iter().map(|node| node.id.uuid).collect(),
+                            edges.iter().map(|edge| edge.id.uuid).collect(),
+                        ));
+                        seq = entry.seq;
+                    }
+                    Err(e) => {
+                        report.problems.push(format!("{}: {:#}", entry.file, e));
+                        state = None;
+                        continue;
+                    }
+                },
+                BackupKind::Incremental => {
+                    let Some((nodes, edges)) = state.as_mut() else {
+                        report.problems.push(format!(
+                            "{}: no readable full backup before it; counts not checked",
+                            entry.file
+                        ));
+                        continue;
+                    };
+                    if entry.base_seq != seq {
+                        report.problems.push(format!(
+                            "{}: starts at change {} but the chain is at {}",
+                            entry.file, entry.base_seq, seq
+                        ));
+                    }
+                    let applied = WalEntry::decode_segment(&data).and_then(|segment| {
+                        for wal_entry in &segment {
+                            match replay_op(wal_entry)? {
+                                ReplayOp::PutNode(node) => { nodes.insert(node.id.uuid); }
+                                ReplayOp::RemoveNode(id) => { nodes.remove(&id.uuid); }
+                                ReplayOp::PutEdge(edge) => { edges.insert(edge.id.uuid); }
+                                ReplayOp::RemoveEdge(id) => { edges.remove(&id.uuid); }
+                            }
+                        }
+                        Ok(())
+                    });
+                    if let Err(e) = applied {
+                        report.problems.push(format!("{}: {:#}", entry.file, e));
+                        state = None;
+                        continue;
+                    }
+                    seq = entry.seq;
+                }
+            }
+
+            if let Some((nodes, edges)) = &state {
+                if nodes.len() as u64 != entry.node_count || edges.len() as u64 != entry.edge_count {
+                    report.problems.push(format!(
+                        "{}: replays to {} nodes and {} edges, catalog records {} and {}",
+                        entry.file,
+                        nodes.len(),
+                        edges.len(),
+                        entry.node_count,
+                        entry.edge_count
+                    ));
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Read a backup file, checking its size and checksum against the catalog
+    async fn read_checked(&self, entry: &BackupEntry) -> Result<Vec<u8>> {
+        let data = self.read(&entry.file).await?
+            .with_context(|| format!("Backup file {} is missing", entry.file))?;
+        if data.len() as u64 != entry.size {
+            bail!("{} is {} bytes, expected {}", entry.file, data.len(), entry.size);
+        }
+        let checksum = crc32fast::hash(&data);
+        if checksum != entry.checksum {
+            bail!("{} has checksum {:08x}, expected {:08x}", entry.file, checksum, entry.checksum);
+        }
+        Ok(data)
+    }
+
+    /// Snapshot `db` into a file, beside the backup in a local directory or
+    /// in the system's temporary directory for a bucket
+    async fn stage_snapshot(&self, db: &Database, name: &str) -> Result<(Staged, SnapshotSummary)> {
+        let path = match &self.target {
+            Target::Dir(dir) => {
+                std::fs::create_dir_all(dir)
+                    .with_context(|| format!("Failed to create {}", dir.display()))?;
+                dir.join(format!("{}.tmp", name))
+            }
+            Target::Bucket(_) => std::env::temp_dir().join(format!("aresadb-{}-{}", uuid::Uuid::new_v4(), name)),
+        };
+        let mut staged = Staged { path, size: 0, checksum: 0 };
+        let file = File::create(&staged.path)
+            .with_context(|| format!("Failed to create {}", staged.path.display()))?;
+        let mut writer = Checksummed { inner: BufWriter::new(file), hasher: crc32fast::Hasher::new(), size: 0 };
+        let snapshot = db.local().snapshot_data(&mut writer).await?;
+        writer.inner.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        staged.size = writer.size;
+        staged.checksum = writer.hasher.finalize();
+        Ok((staged, snapshot))
+    }
+
+    /// Store a staged file as `name`, uploading it in parts to a bucket
+    async fn write_staged(&self, name: &str, staged: Staged) -> Result<()> {
+        match &self.target {
+            Target::Dir(dir) => {
+                std::fs::rename(&staged.path, dir.join(name))?;
+                Ok(())
+            }
+            Target::Bucket(bucket) => bucket.put_file(name, &staged.path).await,
+        }
+    }
+
+    async fn read(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        match &self.target {
+            Target::Dir(dir) => match std::fs::read(dir.join(name)) {
+                Ok(data) => Ok(Some(data)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e).with_context(|| format!("Failed to read {}", name)),
+            },
+            Target::Bucket(bucket) => match bucket.get(name).await {
+                Ok(data) => Ok(Some(data.to_vec())),
+                Err(e) if matches!(
+                    e.downcast_ref::<object_store::Error>(),
+                    Some(object_store::Error::NotFound { .. })
+                ) => Ok(None),
+                Err(e) => Err(e),
+            },
+        }
+    }
+
+    async fn write(&self, name: &str, data: Vec<u8>) -> Result<()> {
+        match &self.target {
+            Target::Dir(dir) => {
+                std::fs::create_dir_all(dir)
+                    .with_context(|| format!("Failed to create {}", dir.display()))?;
+                // Write aside and rename, so a crash never leaves half a file
+                let tmp = dir.join(format!("{}.tmp", name));
+                std::fs::write(&tmp, data)?;
+                std::fs::rename(&tmp, dir.join(name))?;
+                Ok(())
+            }
+            Target::Bucket(bucket) => bucket.put(name, Bytes::from(data)).await,
+        }
+    }
+}
+
+/// Contents of a backup file about to be stored
+enum Payload {
+    Bytes(Vec<u8>),
+    File(Staged),
+}
+
+/// A backup file written aside, removed when dropped unless moved into place
+struct Staged {
+    path: PathBuf,
+    size: u64,
+    checksum: u32,
+}
+
+impl Drop for Staged {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Counts and checksums the bytes written through it
+struct Checksummed<W> {
+    inner: W,
+    hasher: crc32fast::Hasher,
+    size: u64,
+}
+
+impl<W: Write> Write for Checksummed<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The incremental backups that continue, unbroken, from change `seq`
+fn chain(entries: &[BackupEntry], mut seq: u64) -> Vec<&BackupEntry> {
+    let mut chain = Vec::new();
+    for entry in entries {
+        if entry.kind != BackupKind::Incremental || entry.base_seq != seq {
+            break;
+        }
+        seq = entry.seq;
+        chain.push(entry);
+    }
+    chain
+}
+
+fn wal_entry(event: &ChangeEvent) -> Result<WalEntry> {
+    let (entry_type, data) = match &event.change {
+        Change::NodeInserted { node } => (WalEntryType::InsertNode, serde_json::to_vec(node)?),
+        Change::NodeUpdated { after, .. } => (WalEntryType::UpdateNode, serde_json::to_vec(after)?),
+        Change::NodeDeleted { node } => (WalEntryType::DeleteNode, node.id.uuid.to_vec()),
+        Change::EdgeInserted { edge } => (WalEntryType::InsertEdge, serde_json::to_vec(edge)?),
+        Change::EdgeDeleted { edge } => (WalEntryType::DeleteEdge, edge.id.uuid.to_vec()),
+    };
+    Ok(WalEntry {
+        lsn: event.seq,
+        timestamp: event.timestamp,
+        entry_type,
+        tx_id: None,
+        data,
+    })
+}
+
+fn replay_op(entry: &WalEntry) -> Result<ReplayOp> {
+    let uuid = || -> Result<[u8; 16]> {
+        entry.data.as_slice().try_into()
+            .with_context(|| format!("Bad ID in WAL entry {}", entry.lsn))
+    };
+    Ok(match entry.entry_type {
+        WalEntryType::InsertNode | WalEntryType::UpdateNode => {
+            ReplayOp::PutNode(serde_json::from_slice::<Node>(&entry.data)?)
+        }
+        WalEntryType::DeleteNode => ReplayOp::RemoveNode(NodeId { uuid: uuid()? }),
+        WalEntryType::InsertEdge => ReplayOp::PutEdge(serde_json::from_slice::<Edge>(&entry.data)?),
+        WalEntryType::DeleteEdge => ReplayOp::RemoveEdge(EdgeId { uuid: uuid()? }),
+        ref other => bail!("Unexpected {:?} entry {} in a backup", other, entry.lsn),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_full_and_incremental_restore() {
+        let temp = TempDir::new().unwrap();
+        let db = Database::create(temp.path().join("db"), "shop").await.unwrap();
+        let store = BackupStore::open(temp.path().join("backups").to_str().unwrap()).await.unwrap();
+
+        let alice = db.insert_node("user", json!({"name": "Alice"})).await.unwrap();
+        let full = store.backup(&db, true).await.unwrap().unwrap();
+        assert_eq!(full.kind, BackupKind::Full);
+        assert!(store.backup(&db, true).await.unwrap().is_none());
+
+        let bob = db.insert_node("user", json!({"name": "Bob"})).await.unwrap();
+        db.create_edge(&alice.id.to_string(), &bob.id.to_string(), "follows", None).await.unwrap();
+        let cutoff = db.last_change_seq().unwrap();
+        db.update_node(&bob.id.to_string(), json!({"name": "Robert"})).await.unwrap();
+        db.delete_node(&alice.id.to_string()).await.unwrap();
+        let incr = store.backup(&db, true).await.unwrap().unwrap();
+        assert_eq!(incr.kind, BackupKind::Incremental);
+        assert_eq!(incr.base_seq, full.seq);
+
+        let report = store.verify().await.unwrap();
+        assert!(report.is_ok(), "{:?}", report.problems);
+        assert_eq!(report.checked, 2);
+        // Snapshots are staged beside the backups and moved into place
+        assert!(std::fs::read_dir(temp.path().join("backups")).unwrap().all(|f| !f.unwrap().path().to_string_lossy().ends_with(".tmp")));
+
+        let latest = store.restore(temp.path().join("latest"), RestorePoint::Latest).await.unwrap();
+        assert_eq!((latest.node_count, latest.edge_count), (1, 0));
+        let restored = Database::open(temp.path().join("latest")).await.unwrap();
+        let bob_now = restored.get_node(&bob.id.to_string()).await.unwrap().unwrap();
+        assert_eq!(bob_now.get("name").and_then(|v| v.as_str()), Some("Robert"));
+        assert_eq!(restored.get_all_by_type("user", None).await.unwrap().len(), 1);
+        drop(restored);
+
+        let earlier = store.restore(temp.path().join("earlier"), RestorePoint::Seq(cutoff)).await.unwrap();
+        assert_eq!(earlier.seq, cutoff);
+        assert_eq!((earlier.node_count, earlier.edge_count), (2, 1));
+
+        assert!(store.restore(temp.path().join("latest"), RestorePoint::Latest).await.is_err());
+        assert!(store.restore(temp.path().join("beyond"), RestorePoint::Seq(incr.seq + 1)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_full_backup_to_bucket() {
+        let temp = TempDir::new().unwrap();
+        let db = Database::create(temp.path().join("db"), "shop").await.unwrap();
+        std::fs::create_dir(temp.path().join("bucket")).unwrap();
+        let store = BackupStore::open(&format!("file://{}", temp.path().join("bucket").display())).await.unwrap();
+
+        db.insert_node("user", json!({"name": "Alice"})).await.unwrap();
+        let full = store.backup(&db, false).await.unwrap().unwrap();
+        assert!(full.size > 0);
+        assert!(store.verify().await.unwrap().is_ok());
+        let restored = store.restore(temp.path().join("restored"), RestorePoint::Latest).await.unwrap();
+        assert_eq!(restored.node_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_verify_detects_corruption() {
+        let temp = TempDir::new().unwrap();
+        let db = Database::create(temp.path().join("db"), "shop").await.unwrap();
+        let backups = temp.path().join("backups");
+        let store = BackupStore::open(backups.to_str().unwrap()).await.unwrap();
+
+        db.insert_node("user", json!({"name": "Alice"})).await.unwrap();
+        let full = store.backup(&db, false).await.unwrap().unwrap();
+
+        let path = backups.join(&full.file);
+        let mut data = std::fs::read(&path).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        std::fs::write(&path, data).unwrap();
+
+        let report = store.verify().await.unwrap();
+        assert!(!report.is_ok());
+        assert!(report.problems[0].contains("checksum"));
+        assert!(store.restore(temp.path().join("restored"), RestorePoint::Latest).await.is_err());
+
+        let other = Database::create(temp.path().join("other"), "shop").await.unwrap();
+        assert!(store.backup(&other, false).await.is_err());
+    }
+}
@@ -8,16 +8,47 @@ use futures::StreamExt;
 use object_store::{ObjectStore, path::Path as ObjectPath};
 use object_store::aws::AmazonS3Builder;
 use object_store::gcp::GoogleCloudStorageBuilder;
+use object_store::local::LocalFileSystem;
+use object_store::PutMode;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
 use std::path::Path;
 use std::sync::Arc;
 
 use super::{DatabaseConfig, SyncStats};
+use super::node::Timestamp;
+use super::segment::{
+    Manifest, SegmentRef, SyncConflict, SyncState,
+    scan_local, read_segment, partial_path, encode_segment, decode_segment,
+    DEFAULT_SEGMENT_SIZE,
+};
+
+/// Prefix holding published manifests
+const MANIFEST_DIR: &str = "manifests";
+
+/// Object key of a generation's manifest
+fn manifest_key(generation: u64) -> String {
+    format!("{}/{:020}.json", MANIFEST_DIR, generation)
+}
+
+/// Bytes read from a local file per part of a multipart upload
+const UPLOAD_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Object key of a segment
+fn segment_key(hash: &str) -> String {
+    format!("segments/{}/{}", &hash[..2], hash)
+}
 
 /// Bucket storage backend for S3/GCS
+///
+/// A database is stored as immutable content-addressed segments plus one
+/// manifest per generation; see [`Manifest`].
 pub struct BucketStorage {
     store: Arc<dyn ObjectStore>,
     url: String,
     readonly: bool,
+    segment_size: usize,
+    writer: String,
 }
 
 impl BucketStorage {
@@ -47,14 +78,21 @@ impl BucketStorage {
                 .context("Failed to build GCS client")?;
 
             Arc::new(gcs)
+        } else if let Some(path) = url.strip_prefix("file://") {
+            // Local directory acting as a bucket (shared drives, testing)
+            std::fs::create_dir_all(path)
+                .with_context(|| format!("Failed to create bucket directory {}", path))?;
+            Arc::new(LocalFileSystem::new_with_prefix(path)?)
         } else {
-            bail!("Unsupported storage URL. Use s3://bucket/path or gs://bucket/path");
+            bail!("Unsupported storage URL. Use s3://bucket/path, gs://bucket/path or file:///path");
         };
 
         Ok(Self {
             store,
             url: url.to_string(),
             readonly: false,
+            segment_size: DEFAULT_SEGMENT_SIZE,
+            writer: uuid::Uuid::new_v4().to_string(),
         })
     }
 
@@ -63,6 +101,17 @@ impl BucketStorage {
         self.readonly = readonly;
     }
 
+    /// Set the segment size used for new pushes
+    pub fn with_segment_size(mut self, segment_size: usize) -> Self {
+        self.segment_size = segment_size.max(1);
+        self
+    }
+
+    /// Get the bucket URL
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
     /// Get the base path from URL
     fn base_path(&self) -> String {
         if self.url.starts_with("s3://") {
@@ -80,6 +129,17 @@ impl BucketStorage {
 
     /// Load database config from bucket
     pub async fn load_config(&self) -> Result<DatabaseConfig> {
+        if let Some(manifest) = self.latest_manifest().await? {
+            let entry = manifest.files.get(".aresadb/config.toml")
+                .ok_or_else(|| anyhow::anyhow!("Manifest {} has no config", manifest.generation))?;
+            let mut data = Vec::with_capacity(entry.len as usize);
+            for segment in &entry.segments {
+                data.extend(self.fetch_segment(segment).await?);
+            }
+            return Ok(toml::from_str(std::str::from_utf8(&data)?)?);
+        }
+
+        // Buckets written before manifests existed hold plain files
         let base = self.base_path();
         let config_path = if base.is_empty() {
             ObjectPath::from(".aresadb/config.toml")
@@ -114,174 +174,304 @@ impl BucketStorage {
     }
 
     /// Upload local database to bucket
+    ///
+    /// Publishes a new generation containing only segments the bucket does
+    /// not already hold. Fails with [`SyncConflict`] if the bucket moved on
+    /// since this copy last synced.
     pub async fn upload_from_local(&self, local_path: &Path) -> Result<()> {
+        self.push(local_path).await.map(|_| ())
+    }
+
+    /// Download bucket contents to local path
+    ///
+    /// Rebuilds the local files from the latest manifest, fetching only
+    /// segments that are not already present locally.
+    pub async fn download_to_local(&self, local_path: &Path) -> Result<()> {
+        let manifest = self.latest_manifest().await?
+            .ok_or_else(|| anyhow::anyhow!("No database found at {}", self.url))?;
+        self.pull(local_path, &manifest).await.map(|_| ())
+    }
+
+    /// Bidirectional sync with local path
+    ///
+    /// Pushes when only the local copy changed, pulls when only the bucket
+    /// changed, and returns a [`SyncConflict`] error when both did.
+    pub async fn sync_with_local(&self, local_path: &Path) -> Result<SyncStats> {
+        let state = SyncState::load(local_path, &self.url)?;
+        let base = state.as_ref().map(|s| s.generation);
+        let remote = self.latest_generation().await?;
+
+        let local_changed = match &state {
+            Some(state) => scan_local(local_path, state.segment_size as usize)? != state.files,
+            None => !scan_local(local_path, self.segment_size)?.is_empty(),
+        };
+
+        match (remote, base) {
+            (None, _) => {
+                if self.readonly || !local_changed {
+                    return Ok(SyncStats::default());
+                }
+                self.push(local_path).await
+            }
+            (Some(remote), Some(base)) if remote == base => {
+                if local_changed && !self.readonly {
+                    self.push(local_path).await
+                } else {
+                    Ok(SyncStats { generation: remote, ..Default::default() })
+                }
+            }
+            (Some(remote), Some(base)) if remote < base => {
+                bail!(
+                    "Bucket {} is at generation {}, behind the local copy's generation {}; was it reset?",
+                    self.url, remote, base
+                );
+            }
+            (Some(remote), base) => {
+                if local_changed {
+                    return Err(SyncConflict { base_generation: base, remote_generation: remote }.into());
+                }
+                let manifest = self.load_manifest(remote).await?;
+                self.pull(local_path, &manifest).await
+            }
+        }
+    }
+
+    /// Publish the local files as a new generation
+    pub async fn push(&self, local_path: &Path) -> Result<SyncStats> {
         if self.readonly {
             bail!("Cannot write to readonly bucket");
         }
 
-        let base = self.base_path();
+        let state = SyncState::load(local_path, &self.url)?;
+        let base = state.as_ref().map(|s| s.generation);
+        let remote = self.latest_generation().await?;
+        if let Some(remote) = remote {
+            if base != Some(remote) {
+                return Err(SyncConflict { base_generation: base, remote_generation: remote }.into());
+            }
+        }
 
-        // Upload all files in .aresadb directory
-        let aresadb_dir = local_path.join(".aresadb");
-        for entry in walkdir::WalkDir::new(&aresadb_dir) {
-            let entry = entry?;
-            if entry.file_type().is_file() {
-                let relative = entry.path().strip_prefix(local_path)?;
-                let object_path = if base.is_empty() {
-                    ObjectPath::from(relative.to_string_lossy().to_string())
-                } else {
-                    ObjectPath::from(format!("{}/{}", base, relative.to_string_lossy()))
-                };
+        let segment_size = state.as_ref()
+            .map(|s| s.segment_size as usize)
+            .unwrap_or(self.segment_size);
+        let files = scan_local(local_path, segment_size)?;
 
-                let data = tokio::fs::read(entry.path()).await?;
-                self.store.put(&object_path, Bytes::from(data)).await?;
+        if let (Some(state), Some(remote)) = (&state, remote) {
+            if state.files == files {
+                return Ok(SyncStats { generation: remote, ..Default::default() });
             }
         }
 
-        Ok(())
-    }
+        // Segments referenced by the base manifest are already in the bucket
+        let mut present: HashSet<String> = state.iter()
+            .flat_map(|s| s.files.values())
+            .flat_map(|f| f.segments.iter().map(|seg| seg.hash.clone()))
+            .collect();
 
-    /// Download bucket contents to local path
-    pub async fn download_to_local(&self, local_path: &Path) -> Result<()> {
-        let base = self.base_path();
-        let prefix = if base.is_empty() {
-            None
-        } else {
-            Some(ObjectPath::from(base.clone()))
+        let mut stats = SyncStats::default();
+        for (relative, entry) in &files {
+            let file_path = local_path.join(relative);
+            for (index, segment) in entry.segments.iter().enumerate() {
+                if present.contains(&segment.hash) {
+                    continue;
+                }
+                let data = read_segment(&file_path, index, segment_size)?;
+                if SegmentRef::of(&data) != *segment {
+                    bail!("{} changed while pushing; retry the sync", relative);
+                }
+                stats.bytes_uploaded += self.upload_segment(segment, &data).await?;
+                stats.uploaded += 1;
+                present.insert(segment.hash.clone());
+            }
+        }
+
+        let manifest = Manifest {
+            generation: remote.unwrap_or(0) + 1,
+            parent: remote,
+            created_at: Timestamp::now(),
+            writer: self.writer.clone(),
+            segment_size: segment_size as u64,
+            files,
         };
+        self.publish_manifest(&manifest).await?;
 
-        // List all objects
-        let mut stream = self.store.list(prefix.as_ref());
+        SyncState {
+            url: self.url.clone(),
+            generation: manifest.generation,
+            segment_size: manifest.segment_size,
+            files: manifest.files,
+        }.save(local_path)?;
 
-        while let Some(result) = stream.next().await {
-            let meta = result?;
-            let object_path = meta.location;
+        stats.generation = manifest.generation;
+        Ok(stats)
+    }
 
-            // Calculate local path
-            let relative = if base.is_empty() {
-                object_path.to_string()
-            } else {
-                object_path.to_string().strip_prefix(&format!("{}/", base))
-                    .unwrap_or(&object_path.to_string())
-                    .to_string()
-            };
+    /// Rebuild the local files from a manifest
+    ///
+    /// Files are written to a staging path and renamed into place. Handles
+    /// that already have the database open keep seeing the old contents
+    /// until they reopen it.
+    pub async fn pull(&self, local_path: &Path, manifest: &Manifest) -> Result<SyncStats> {
+        let segment_size = manifest.segment_size as usize;
+        let local = scan_local(local_path, segment_size)?;
+
+        // Any local segment can be reused, wherever it sits
+        let mut reusable: HashMap<&str, (&str, usize)> = HashMap::new();
+        for (relative, entry) in &local {
+            for (index, segment) in entry.segments.iter().enumerate() {
+                reusable.entry(segment.hash.as_str()).or_insert((relative.as_str(), index));
+            }
+        }
 
-            let local_file = local_path.join(&relative);
+        let mut stats = SyncStats { generation: manifest.generation, ..Default::default() };
+        let mut staged = Vec::new();
 
-            // Create parent directories
-            if let Some(parent) = local_file.parent() {
+        for (relative, entry) in &manifest.files {
+            if local.get(relative) == Some(entry) {
+                continue;
+            }
+
+            let dest = local_path.join(relative);
+            if let Some(parent) = dest.parent() {
                 tokio::fs::create_dir_all(parent).await?;
             }
+            let partial = partial_path(&dest);
+            let mut out = std::fs::File::create(&partial)
+                .with_context(|| format!("Failed to create {}", partial.display()))?;
+
+            for segment in &entry.segments {
+                let data = match reusable.get(segment.hash.as_str()) {
+                    Some((source, index)) => read_segment(&local_path.join(source), *index, segment_size)?,
+                    None => {
+                        let data = self.fetch_segment(segment).await?;
+                        stats.downloaded += 1;
+                        stats.bytes_downloaded += segment.len;
+                        data
+                    }
+                };
+                out.write_all(&data)?;
+            }
+            out.sync_all()?;
+            staged.push((partial, dest));
+        }
 
-            // Download file
-            let data = self.store.get(&object_path).await?;
-            let bytes = data.bytes().await?;
-            tokio::fs::write(&local_file, bytes).await?;
+        // Swap files in only once every segment has been fetched
+        for (partial, dest) in staged {
+            std::fs::rename(&partial, &dest)?;
         }
 
-        Ok(())
-    }
+        // Remove files the previous generation had but this one dropped
+        if let Some(previous) = SyncState::load(local_path, &self.url)? {
+            for relative in previous.files.keys() {
+                if !manifest.files.contains_key(relative) {
+                    let _ = std::fs::remove_file(local_path.join(relative));
+                }
+            }
+        }
 
-    /// Bidirectional sync with local path
-    pub async fn sync_with_local(&self, local_path: &Path) -> Result<SyncStats> {
-        let mut stats = SyncStats::default();
-        let base = self.base_path();
+        SyncState {
+            url: self.url.clone(),
+            generation: manifest.generation,
+            segment_size: manifest.segment_size,
+            files: manifest.files.clone(),
+        }.save(local_path)?;
 
-        // Get list of remote files with their modification times
-        let prefix = if base.is_empty() {
-            None
-        } else {
-            Some(ObjectPath::from(base.clone()))
-        };
+        Ok(stats)
+    }
 
-        let mut remote_files = std::collections::HashMap::new();
-        let mut stream = self.store.list(prefix.as_ref());
+    /// Latest published generation, if any
+    pub async fn latest_generation(&self) -> Result<Option<u64>> {
+        let prefix = self.object_path(MANIFEST_DIR);
+        let mut stream = self.store.list(Some(&prefix));
+        let mut latest = None;
 
         while let Some(result) = stream.next().await {
             let meta = result?;
-            let path = meta.location.to_string();
-            let relative = if base.is_empty() {
-                path.clone()
-            } else {
-                path.strip_prefix(&format!("{}/", base))
-                    .unwrap_or(&path)
-                    .to_string()
-            };
-            remote_files.insert(relative, meta.last_modified);
-        }
-
-        // Get list of local files
-        let aresadb_dir = local_path.join(".aresadb");
-        let mut local_files = std::collections::HashMap::new();
-
-        if aresadb_dir.exists() {
-            for entry in walkdir::WalkDir::new(&aresadb_dir) {
-                let entry = entry?;
-                if entry.file_type().is_file() {
-                    let relative = entry.path().strip_prefix(local_path)?;
-                    let modified = entry.metadata()?.modified()?;
-                    local_files.insert(relative.to_string_lossy().to_string(), modified);
-                }
+            let generation = meta.location.filename()
+                .and_then(|name| name.strip_suffix(".json"))
+                .and_then(|stem| stem.parse::<u64>().ok());
+            if let Some(generation) = generation {
+                latest = latest.max(Some(generation));
             }
         }
 
-        // Upload newer local files
-        if !self.readonly {
-            for (path, local_time) in &local_files {
-                let should_upload = if let Some(remote_time) = remote_files.get(path) {
-                    let local_datetime = chrono::DateTime::<chrono::Utc>::from(*local_time);
-                    local_datetime > *remote_time
-                } else {
-                    true
-                };
-
-                if should_upload {
-                    let local_file = local_path.join(path);
-                    let data = tokio::fs::read(&local_file).await?;
+        Ok(latest)
+    }
 
-                    let object_path = if base.is_empty() {
-                        ObjectPath::from(path.clone())
-                    } else {
-                        ObjectPath::from(format!("{}/{}", base, path))
-                    };
+    /// Load the manifest of a generation
+    pub async fn load_manifest(&self, generation: u64) -> Result<Manifest> {
+        let path = self.object_path(&manifest_key(generation));
+        let bytes = self.store.get(&path).await
+            .with_context(|| format!("Failed to fetch manifest for generation {}", generation))?
+            .bytes().await?;
+        serde_json::from_slice(&bytes)
+            .with_context(|| format!("Corrupt manifest for generation {}", generation))
+    }
 
-                    self.store.put(&object_path, Bytes::from(data)).await?;
-                    stats.uploaded += 1;
-                }
-            }
+    /// Load the latest manifest, if any
+    pub async fn latest_manifest(&self) -> Result<Option<Manifest>> {
+        match self.latest_generation().await? {
+            Some(generation) => Ok(Some(self.load_manifest(generation).await?)),
+            None => Ok(None),
         }
+    }
 
-        // Download newer remote files
-        for (path, remote_time) in &remote_files {
-            let should_download = if let Some(local_time) = local_files.get(path) {
-                let local_datetime = chrono::DateTime::<chrono::Utc>::from(*local_time);
-                *remote_time > local_datetime
-            } else {
-                true
-            };
-
-            if should_download {
-                let object_path = if base.is_empty() {
-                    ObjectPath::from(path.clone())
-                } else {
-                    ObjectPath::from(format!("{}/{}", base, path))
-                };
+    /// Fetch and verify one segment
+    pub async fn fetch_segment(&self, segment: &SegmentRef) -> Result<Vec<u8>> {
+        let path = self.object_path(&segment_key(&segment.hash));
+        let bytes = self.store.get(&path).await
+            .with_context(|| format!("Failed to fetch segment {}", segment.hash))?
+            .bytes().await?;
+        decode_segment(&bytes, segment)
+    }
 
-                let local_file = local_path.join(path);
+    /// Upload a segment unless the bucket already has it; returns bytes written
+    async fn upload_segment(&self, segment: &SegmentRef, data: &[u8]) -> Result<u64> {
+        let path = self.object_path(&segment_key(&segment.hash));
+        if self.store.head(&path).await.is_ok() {
+            return Ok(0);
+        }
+        let encoded = encode_segment(data);
+        let len = encoded.len() as u64;
+        self.store.put(&path, Bytes::from(encoded)).await?;
+        Ok(len)
+    }
 
-                // Create parent directories
-                if let Some(parent) = local_file.parent() {
-                    tokio::fs::create_dir_all(parent).await?;
-                }
+    /// Publish a manifest, failing if its generation already exists
+    async fn publish_manifest(&self, manifest: &Manifest) -> Result<()> {
+        let path = self.object_path(&manifest_key(manifest.generation));
+        let data = Bytes::from(serde_json::to_vec_pretty(manifest)?);
+        let conflict = || SyncConflict {
+            base_generation: manifest.parent,
+            remote_generation: manifest.generation,
+        };
 
-                let data = self.store.get(&object_path).await?;
-                let bytes = data.bytes().await?;
-                tokio::fs::write(&local_file, bytes).await?;
-                stats.downloaded += 1;
+        match self.store.put_opts(&path, data.clone(), PutMode::Create.into()).await {
+            Ok(_) => Ok(()),
+            Err(object_store::Error::AlreadyExists { .. }) => Err(conflict().into()),
+            Err(object_store::Error::NotImplemented) => {
+                // The store cannot create-if-absent (e.g. S3 without a
+                // conditional put configuration); fall back to a best-effort
+                // existence check, which leaves a small race window.
+                tracing::warn!("bucket does not support conditional writes; concurrent pushes may race");
+                if self.store.head(&path).await.is_ok() {
+                    return Err(conflict().into());
+                }
+                self.store.put(&path, data).await?;
+                Ok(())
             }
+            Err(e) => Err(e.into()),
         }
+    }
 
-        Ok(stats)
+    /// Object path for a key relative to the bucket prefix
+    fn object_path(&self, relative: &str) -> ObjectPath {
+        let base = self.base_path();
+        if base.is_empty() {
+            ObjectPath::from(relative.to_string())
+        } else {
+            ObjectPath::from(format!("{}/{}", base, relative))
+        }
     }
 
     /// Get a single object from bucket
@@ -315,6 +505,37 @@ impl BucketStorage {
         Ok(())
     }
 
+    /// Upload a local file to bucket in parts, without reading it into memory
+    ///
+    /// A failed upload is aborted, leaving no object behind.
+    pub async fn put_file(&self, path: &str, local_path: &Path) -> Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        if self.readonly {
+            bail!("Cannot write to readonly bucket");
+        }
+
+        let object_path = self.object_path(path);
+        let (upload_id, mut writer) = self.store.put_multipart(&object_path).await?;
+        let upload = async {
+            let mut file = tokio::fs::File::open(local_path).await?;
+            let mut chunk = vec![0u8; UPLOAD_CHUNK_SIZE];
+            loop {
+                let n = file.read(&mut chunk).await?;
+                if n == 0 {
+                    break;
+                }
+                writer.write_all(&chunk[..n]).await?;
+            }
+            writer.shutdown().await
+        };
+        if let Err(e) = upload.await {
+            let _ = self.store.abort_multipart(&object_path, &upload_id).await;
+            return Err(e).with_context(|| format!("Failed to upload {}", path));
+        }
+        Ok(())
+    }
+
     /// Delete a single object from bucket
     pub async fn delete(&self, path: &str) -> Result<()> {
         if self.readonly {
@@ -349,3 +570,134 @@ impl BucketStorage {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Database;
+    use tempfile::TempDir;
+
+    async fn bucket(dir: &TempDir) -> BucketStorage {
+        let url = format!("file://{}", dir.path().display());
+        BucketStorage::connect(&url).await.unwrap().with_segment_size(4096)
+    }
+
+    fn write_db_file(root: &Path, data: &[u8]) {
+        std::fs::create_dir_all(root.join(".aresadb")).unwrap();
+        std::fs::write(root.join(".aresadb/data.redb"), data).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_push_uploads_only_changed_segments() {
+        let remote = TempDir::new().unwrap();
+        let local = TempDir::new().unwrap();
+        let bucket = bucket(&remote).await;
+
+        let mut data: Vec<u8> = (0..40_000u32).map(|i| (i * 31 % 253) as u8).collect();
+        write_db_file(local.path(), &data);
+
+        let stats = bucket.push(local.path()).await.unwrap();
+        assert_eq!(stats.generation, 1);
+        assert_eq!(stats.uploaded, 10);
+
+        // Nothing changed: no new generation
+        let stats = bucket.sync_with_local(local.path()).await.unwrap();
+        assert_eq!(stats.uploaded, 0);
+        assert_eq!(bucket.latest_generation().await.unwrap(), Some(1));
+
+        // Touch a single segment
+        data[5000] ^= 0xff;
+        write_db_file(local.path(), &data);
+        let stats = bucket.sync_with_local(local.path()).await.unwrap();
+        assert_eq!(stats.generation, 2);
+        assert_eq!(stats.uploaded, 1);
+
+        let manifest = bucket.latest_manifest().await.unwrap().unwrap();
+        assert_eq!(manifest.parent, Some(1));
+        assert_eq!(manifest.total_bytes(), 40_000);
+    }
+
+    #[tokio::test]
+    async fn test_pull_fetches_missing_segments() {
+        let remote = TempDir::new().unwrap();
+        let writer = TempDir::new().unwrap();
+        let reader = TempDir::new().unwrap();
+        let bucket = bucket(&remote).await;
+
+        let mut data: Vec<u8> = (0..20_000u32).map(|i| (i % 199) as u8).collect();
+        write_db_file(writer.path(), &data);
+        bucket.push(writer.path()).await.unwrap();
+
+        bucket.download_to_local(reader.path()).await.unwrap();
+        assert_eq!(std::fs::read(reader.path().join(".aresadb/data.redb")).unwrap(), data);
+
+        data[100] = 0;
+        write_db_file(writer.path(), &data);
+        bucket.sync_with_local(writer.path()).await.unwrap();
+
+        let stats = bucket.sync_with_local(reader.path()).await.unwrap();
+        assert_eq!(stats.generation, 2);
+        assert_eq!(stats.downloaded, 1);
+        assert_eq!(std::fs::read(reader.path().join(".aresadb/data.redb")).unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_writers_conflict() {
+        let remote = TempDir::new().unwrap();
+        let a = TempDir::new().unwrap();
+        let b = TempDir::new().unwrap();
+        let bucket = bucket(&remote).await;
+
+        write_db_file(a.path(), b"first");
+        bucket.push(a.path()).await.unwrap();
+        bucket.download_to_local(b.path()).await.unwrap();
+
+        write_db_file(a.path(), b"from a");
+        write_db_file(b.path(), b"from b");
+        bucket.sync_with_local(a.path()).await.unwrap();
+
+        let err = bucket.sync_with_local(b.path()).await.unwrap_err();
+        let conflict = err.downcast_ref::<SyncConflict>().expect("conflict error");
+        assert_eq!(conflict.base_generation, Some(1));
+        assert_eq!(conflict.remote_generation, 2);
+
+        // B's changes were not published
+        let manifest = bucket.latest_manifest().await.unwrap().unwrap();
+        assert_eq!(manifest.generation, 2);
+        assert_eq!(std::fs::read(b.path().join(".aresadb/data.redb")).unwrap(), b"from b");
+    }
+
+    #[tokio::test]
+    async fn test_publish_rejects_existing_generation() {
+        let remote = TempDir::new().unwrap();
+        let local = TempDir::new().unwrap();
+        let bucket = bucket(&remote).await;
+
+        write_db_file(local.path(), b"data");
+        bucket.push(local.path()).await.unwrap();
+
+        let mut manifest = bucket.load_manifest(1).await.unwrap();
+        manifest.parent = Some(0);
+        let err = bucket.publish_manifest(&manifest).await.unwrap_err();
+        assert!(err.downcast_ref::<SyncConflict>().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_database_roundtrip_through_bucket() {
+        let remote = TempDir::new().unwrap();
+        let source = TempDir::new().unwrap();
+        let clone = TempDir::new().unwrap();
+        let url = format!("file://{}", remote.path().display());
+
+        let db = Database::create(source.path(), "synced").await.unwrap();
+        db.insert_node("user", serde_json::json!({"name": "Alice"})).await.unwrap();
+        db.push_to_bucket(&url).await.unwrap();
+
+        let bucket = BucketStorage::connect(&url).await.unwrap();
+        assert_eq!(bucket.load_config().await.unwrap().name, "synced");
+        bucket.download_to_local(clone.path()).await.unwrap();
+
+        let copy = Database::open(clone.path()).await.unwrap();
+        assert_eq!(copy.get_all_by_type("user", None).await.unwrap().len(), 1);
+    }
+}
@@ -5,9 +5,12 @@
 use anyhow::Result;
 use bytes::Bytes;
 use moka::sync::Cache;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
+use crate::metrics::metrics;
+
 /// Cache entry metadata
 #[derive(Debug, Clone)]
 pub struct CacheEntry {
@@ -21,32 +24,54 @@ pub struct CacheLayer {
     cache: Cache<String, Arc<CacheEntry>>,
     /// Maximum cache size in bytes
     max_size: u64,
-    /// Current cache size in bytes
-    current_size: std::sync::atomic::AtomicU64,
+    /// Current cache size in bytes, less whatever the cache has dropped
+    current_size: Arc<AtomicU64>,
+    /// Lookups that found an entry
+    hits: AtomicU64,
+    /// Lookups that missed
+    misses: AtomicU64,
 }
 
 impl CacheLayer {
     /// Create a new cache layer with the given maximum size in bytes
     pub fn new(max_size_bytes: u64) -> Self {
+        let current_size = Arc::new(AtomicU64::new(0));
+        let dropped = Arc::clone(&current_size);
         let cache = Cache::builder()
-            .max_capacity(10000) // Max number of entries
+            .max_capacity(max_size_bytes) // Total weight in bytes
             .time_to_idle(Duration::from_secs(3600)) // 1 hour TTL
             .weigher(|_key: &String, value: &Arc<CacheEntry>| -> u32 {
                 // Weight by size (capped at u32::MAX)
                 value.size.min(u32::MAX as usize) as u32
             })
+            // Every way out of the cache lands here: removal, replacement,
+            // expiry and eviction for space
+            .eviction_listener(move |_key, entry: Arc<CacheEntry>, _cause| {
+                dropped.fetch_sub(entry.size as u64, Ordering::Relaxed);
+                metrics().cache_size.sub(entry.size as i64);
+            })
             .build();
 
         Self {
             cache,
             max_size: max_size_bytes,
-            current_size: std::sync::atomic::AtomicU64::new(0),
+            current_size,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
         }
     }
 
     /// Get an entry from cache
     pub fn get(&self, key: &str) -> Option<Bytes> {
-        self.cache.get(key).map(|entry| entry.data.clone())
+        let data = self.cache.get(key).map(|entry| entry.data.clone());
+        if data.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            metrics().cache_hits.inc();
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            metrics().cache_misses.inc();
+        }
+        data
     }
 
     /// Put an entry in cache
@@ -57,26 +82,27 @@ impl CacheLayer {
             size,
         });
 
+        // Counted before inserting, as the listener may drop it right away
+        self.current_size.fetch_add(size as u64, Ordering::Relaxed);
+        metrics().cache_size.add(size as i64);
         self.cache.insert(key.to_string(), entry);
-        self.current_size.fetch_add(size as u64, std::sync::atomic::Ordering::Relaxed);
     }
 
     /// Remove an entry from cache
     pub fn remove(&self, key: &str) {
-        if let Some(entry) = self.cache.remove(key) {
-            self.current_size.fetch_sub(entry.size as u64, std::sync::atomic::Ordering::Relaxed);
-        }
+        self.cache.invalidate(key);
     }
 
     /// Clear all entries from cache
     pub fn clear(&self) {
         self.cache.invalidate_all();
-        self.current_size.store(0, std::sync::atomic::Ordering::Relaxed);
+        self.cache.run_pending_tasks();
     }
 
     /// Get current cache size in bytes
     pub fn size(&self) -> u64 {
-        self.current_size.load(std::sync::atomic::Ordering::Relaxed)
+        self.cache.run_pending_tasks();
+        self.current_size.load(Ordering::Relaxed)
     }
 
     /// Get maximum cache size in bytes
@@ -86,6 +112,7 @@ impl CacheLayer {
 
     /// Get number of entries in cache
     pub fn entry_count(&self) -> u64 {
+        self.cache.run_pending_tasks();
         self.cache.entry_count()
     }
 
@@ -122,6 +149,18 @@ pub struct CacheStats {
     pub size_bytes: u64,
     pub max_size_bytes: u64,
     pub utilization_percent: f64,
+    /// Lookups that found an entry
+    pub hits: u64,
+    /// Lookups that missed
+    pub misses: u64,
+}
+
+impl CacheStats {
+    /// Fraction of lookups that hit, or 0 before any lookup
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 { 0.0 } else { self.hits as f64 / lookups as f64 }
+    }
 }
 
 impl CacheLayer {
@@ -135,6 +174,8 @@ impl CacheLayer {
             size_bytes: size,
             max_size_bytes: max_size,
             utilization_percent: (size as f64 / max_size as f64) * 100.0,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
         }
     }
 }
@@ -180,6 +221,25 @@ mod tests {
         cache.clear();
         assert!(!cache.contains("key1"));
         assert!(!cache.contains("key2"));
+        assert_eq!(cache.size(), 0);
+    }
+
+    #[test]
+    fn test_cache_size_follows_replacements_and_evictions() {
+        let cache = CacheLayer::new(16);
+
+        cache.put("key1", Bytes::from("hello"));
+        cache.put("key1", Bytes::from("hi"));
+        assert_eq!(cache.size(), 2);
+
+        for key in ["key2", "key3", "key4", "key5"] {
+            cache.put(key, Bytes::from("12345678"));
+        }
+        assert!(cache.size() <= 16, "{} bytes cached", cache.size());
+
+        cache.remove("key1");
+        cache.clear();
+        assert_eq!(cache.size(), 0);
     }
 
     #[tokio::test]
@@ -197,6 +257,10 @@ mod tests {
             Ok(Bytes::from("should not see this"))
         }).await.unwrap();
         assert_eq!(&data[..], b"fetched");
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+        assert_eq!(stats.hit_rate(), 0.5);
     }
 }
 
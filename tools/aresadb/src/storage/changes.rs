@@ -0,0 +1,360 @@
+//! Change Data Capture
+//!
+//! Every committed mutation of nodes and edges is recorded as a
+//! [`ChangeEvent`] in the same redb transaction, numbered by a sequence
+//! that only grows. Readers replay the retained log from any sequence and
+//! then follow new events as they commit, so a consumer that remembers the
+//! last sequence it handled can resume without missing or repeating one.
+
+use anyhow::Result;
+use futures::stream::{self, BoxStream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use tokio::sync::broadcast;
+
+use super::local::LocalStorage;
+use super::node::{Edge, Node, Timestamp};
+
+/// Events kept in the change log; older ones are pruned as new ones commit
+pub const CHANGE_RETENTION: u64 = 100_000;
+
+/// Events read from the log at a time while catching up
+const REPLAY_BATCH: usize = 1024;
+
+/// Live events buffered per subscriber before it has to catch up from the log
+pub(crate) const LIVE_BUFFER: usize = 1024;
+
+/// A committed change to a node or edge
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Change {
+    /// A node was inserted
+    NodeInserted {
+        /// The node as inserted
+        node: Node,
+    },
+    /// A node's properties were updated
+    NodeUpdated {
+        /// The node before the update
+        before: Node,
+        /// The node after the update
+        after: Node,
+    },
+    /// A node was deleted
+    NodeDeleted {
+        /// The node as it was
+        node: Node,
+    },
+    /// An edge was inserted
+    EdgeInserted {
+        /// The edge as inserted
+        edge: Edge,
+    },
+    /// An edge was deleted, directly or along with one of its nodes
+    EdgeDeleted {
+        /// The edge as it was
+        edge: Edge,
+    },
+}
+
+impl Change {
+    /// Short name of the kind of change
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Change::NodeInserted { .. } => "node_inserted",
+            Change::NodeUpdated { .. } => "node_updated",
+            Change::NodeDeleted { .. } => "node_deleted",
+            Change::EdgeInserted { .. } => "edge_inserted",
+            Change::EdgeDeleted { .. } => "edge_deleted",
+        }
+    }
+
+    /// The node changed, as it is after the change where it still exists
+    pub fn node(&self) -> Option<&Node> {
+        match self {
+            Change::NodeInserted { node } | Change::NodeDeleted { node } => Some(node),
+            Change::NodeUpdated { after, .. } => Some(after),
+            _ => None,
+        }
+    }
+
+    /// The edge changed
+    pub fn edge(&self) -> Option<&Edge> {
+        match self {
+            Change::EdgeInserted { edge } | Change::EdgeDeleted { edge } => Some(edge),
+            _ => None,
+        }
+    }
+}
+
+/// A change with its place in the log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangeEvent {
+    /// Position in the log, starting at 1
+    pub seq: u64,
+    /// When the change committed
+    pub timestamp: Timestamp,
+    /// What changed
+    pub change: Change,
+}
+
+impl ChangeEvent {
+    /// Convert to JSON, flattened with the kind of change alongside
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut json = serde_json::json!({
+            "seq": self.seq,
+            "timestamp": self.timestamp.millis,
+            "kind": self.change.kind(),
+        });
+        match &self.change {
+            Change::NodeInserted { node } | Change::NodeDeleted { node } => json["node"] = node.to_json(),
+            Change::NodeUpdated { before, after } => {
+                json["before"] = before.to_json();
+                json["after"] = after.to_json();
+            }
+            Change::EdgeInserted { edge } | Change::EdgeDeleted { edge } => json["edge"] = edge.to_json(),
+        }
+        json
+    }
+}
+
+/// Which changes a subscriber receives
+///
+/// An empty filter passes everything. Otherwise a node change passes if
+/// its type is in `node_types` and an edge change if its type is in
+/// `edge_types`, so naming only node types leaves out edges altogether.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangeFilter {
+    /// Node types to receive
+    pub node_types: Vec<String>,
+    /// Edge types to receive
+    pub edge_types: Vec<String>,
+}
+
+impl ChangeFilter {
+    /// Filter passing every change
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    /// Also receive changes to nodes of `node_type`
+    pub fn node_type(mut self, node_type: impl Into<String>) -> Self {
+        self.node_types.push(node_type.into());
+        self
+    }
+
+    /// Also receive changes to edges of `edge_type`
+    pub fn edge_type(mut self, edge_type: impl Into<String>) -> Self {
+        self.edge_types.push(edge_type.into());
+        self
+    }
+
+    /// Whether the filter passes everything
+    pub fn is_empty(&self) -> bool {
+        self.node_types.is_empty() && self.edge_types.is_empty()
+    }
+
+    /// Whether `change` passes the filter
+    pub fn matches(&self, change: &Change) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        match (change.node(), change.edge()) {
+            (Some(node), _) => self.node_types.contains(&node.node_type),
+            (_, Some(edge)) => self.edge_types.contains(&edge.edge_type),
+            _ => false,
+        }
+    }
+}
+
+/// Change events in sequence order; it only ends on an error
+pub type ChangeStream = BoxStream<'static, Result<ChangeEvent>>;
+
+/// Where a [`ChangeStream`] is up to
+struct Cursor {
+    storage: LocalStorage,
+    live: broadcast::Receiver<ChangeEvent>,
+    filter: ChangeFilter,
+    /// Last sequence delivered or skipped
+    last: u64,
+    /// Events read but not yet delivered
+    pending: VecDeque<ChangeEvent>,
+    /// Read from the log before waiting for live events
+    catch_up: bool,
+}
+
+impl Cursor {
+    async fn next(&mut self) -> Result<ChangeEvent> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                self.last = event.seq;
+                if self.filter.matches(&event.change) {
+                    return Ok(event);
+                }
+                continue;
+            }
+
+            if self.catch_up {
+                let batch = self.storage.changes_after(self.last, REPLAY_BATCH)?;
+                self.catch_up = batch.len() == REPLAY_BATCH;
+                self.pending.extend(batch);
+                continue;
+            }
+
+            match self.live.recv().await {
+                // Already replayed from the log
+                Ok(event) if event.seq <= self.last => {}
+                Ok(event) if event.seq == self.last + 1 => self.pending.push_back(event),
+                // Fell behind the live buffer; the log has everything missed
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => self.catch_up = true,
+                Err(broadcast::error::RecvError::Closed) => anyhow::bail!("Change feed closed"),
+            }
+        }
+    }
+}
+
+/// Stream the changes to `storage` after sequence `after`, or only those
+/// still to come when `after` is `None`
+pub(crate) fn stream(storage: LocalStorage, after: Option<u64>, filter: ChangeFilter) -> Result<ChangeStream> {
+    // Listen before reading the log so nothing commits in between unseen
+    let live = storage.subscribe_changes();
+    let (last, catch_up) = match after {
+        Some(after) => {
+            storage.check_retained(after)?;
+            (after, true)
+        }
+        None => (storage.last_change_seq()?, false),
+    };
+
+    let cursor = Cursor { storage, live, filter, last, pending: VecDeque::new(), catch_up };
+    let events = stream::unfold(Some(cursor), |cursor| async move {
+        let mut cursor = cursor?;
+        match cursor.next().await {
+            Ok(event) => Some((Ok(event), Some(cursor))),
+            Err(e) => Some((Err(e), None)),
+        }
+    });
+    Ok(events.boxed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{Database, Value};
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_changes_replay_and_follow() {
+        let temp = TempDir::new().unwrap();
+        let db = Database::create(temp.path(), "test").await.unwrap();
+
+        let alice = db.insert_node("user", serde_json::json!({ "name": "Alice" })).await.unwrap();
+        let bob = db.insert_node("user", serde_json::json!({ "name": "Bob" })).await.unwrap();
+        let post = db.insert_node("post", serde_json::json!({ "title": "Hi" })).await.unwrap();
+        let alice_id = alice.id.to_string();
+        db.create_edge(&alice_id, &post.id.to_string(), "wrote", None).await.unwrap();
+        db.update_node(&alice_id, serde_json::json!({ "name": "Alicia" })).await.unwrap();
+        assert_eq!(db.last_change_seq().unwrap(), 5);
+
+        // Replay everything, then follow
+        let mut all = db.changes(Some(0), ChangeFilter::all()).unwrap();
+        let mut seqs = Vec::new();
+        for _ in 0..5 {
+            seqs.push(all.next().await.unwrap().unwrap().seq);
+        }
+        assert_eq!(seqs, vec![1, 2, 3, 4, 5]);
+
+        // Only node changes of one type, resuming part way through
+        let mut users = db.changes(Some(1), ChangeFilter::all().node_type("user")).unwrap();
+        let event = users.next().await.unwrap().unwrap();
+        assert_eq!(event.seq, 2);
+        assert!(matches!(event.change, Change::NodeInserted { ref node } if node.id == bob.id));
+        let event = users.next().await.unwrap().unwrap();
+        let Change::NodeUpdated { before, after } = event.change else { panic!("expected an update") };
+        assert_eq!(before.properties.get("name").and_then(Value::as_str), Some("Alice"));
+        assert_eq!(after.properties.get("name").and_then(Value::as_str), Some("Alicia"));
+
+        // Deleting a node also reports its edges, and live events follow the replay
+        db.delete_node(&alice_id).await.unwrap();
+        let event = all.next().await.unwrap().unwrap();
+        assert_eq!(event.seq, 6);
+        assert!(matches!(event.change, Change::EdgeDeleted { ref edge } if edge.edge_type == "wrote"));
+        let event = all.next().await.unwrap().unwrap();
+        assert!(matches!(event.change, Change::NodeDeleted { ref node } if node.id == alice.id));
+        let event = users.next().await.unwrap().unwrap();
+        assert_eq!(event.seq, 7);
+
+        // Subscribing without a sequence starts with the next change
+        let mut posts = db.changes(None, ChangeFilter::all().node_type("post")).unwrap();
+        db.insert_node("user", serde_json::json!({})).await.unwrap();
+        db.delete_node(&post.id.to_string()).await.unwrap();
+        let event = posts.next().await.unwrap().unwrap();
+        assert_eq!(event.seq, 9);
+        assert_eq!(event.change.kind(), "node_deleted");
+    }
+
+    #[tokio::test]
+    async fn test_changes_survive_reopen_and_lagging() {
+        let temp = TempDir::new().unwrap();
+        let db = Database::create(temp.path(), "test").await.unwrap();
+        db.insert_node("item", serde_json::json!({})).await.unwrap();
+        drop(db);
+
+        let db = Database::open(temp.path()).await.unwrap();
+        let mut events = db.changes(None, ChangeFilter::all()).unwrap();
+        // More than the live buffer holds before anything is read
+        for n in 0..LIVE_BUFFER + 10 {
+            db.insert_node("item", serde_json::json!({ "n": n })).await.unwrap();
+        }
+        for seq in 2..LIVE_BUFFER as u64 + 12 {
+            assert_eq!(events.next().await.unwrap().unwrap().seq, seq);
+        }
+
+        let tx = {
+            let mut tx = db.local().begin_transaction().unwrap();
+            tx.insert_node(Node::new("item", Value::Object(Default::default())));
+            tx
+        };
+        tx.commit().unwrap();
+        assert_eq!(events.next().await.unwrap().unwrap().change.kind(), "node_inserted");
+    }
+
+    #[tokio::test]
+    async fn test_replaced_node_reports_update() {
+        use crate::distributed::ReplicationCommand;
+
+        let temp = TempDir::new().unwrap();
+        let db = Database::create(temp.path(), "test").await.unwrap();
+        let node = db.insert_node("user", serde_json::json!({ "name": "Alice" })).await.unwrap();
+        let mut events = db.changes(None, ChangeFilter::all()).unwrap();
+
+        // A replicated update rewrites the whole node
+        let command = ReplicationCommand::update_node(&node.id, Value::from_json(serde_json::json!({ "name": "Alicia" })).unwrap()).unwrap();
+        command.apply(db.local()).await.unwrap();
+        let Change::NodeUpdated { before, after } = events.next().await.unwrap().unwrap().change else {
+            panic!("expected an update")
+        };
+        assert_eq!(before.properties.get("name").and_then(Value::as_str), Some("Alice"));
+        assert_eq!(after.properties.get("name").and_then(Value::as_str), Some("Alicia"));
+
+        // So does a transaction inserting a node that exists
+        let mut tx = db.local().begin_transaction().unwrap();
+        tx.insert_node(after);
+        tx.commit().unwrap();
+        assert_eq!(events.next().await.unwrap().unwrap().change.kind(), "node_updated");
+    }
+
+    #[test]
+    fn test_filter() {
+        let node = Node::new("user", Value::Object(Default::default()));
+        let edge = Edge::new(node.id.clone(), node.id.clone(), "follows", Value::Object(Default::default()));
+        let inserted = Change::NodeInserted { node };
+        let linked = Change::EdgeInserted { edge };
+
+        assert!(ChangeFilter::all().matches(&inserted));
+        assert!(ChangeFilter::all().matches(&linked));
+        assert!(ChangeFilter::all().node_type("user").matches(&inserted));
+        assert!(!ChangeFilter::all().node_type("user").matches(&linked));
+        assert!(!ChangeFilter::all().node_type("post").matches(&inserted));
+        assert!(ChangeFilter::all().node_type("post").edge_type("follows").matches(&linked));
+    }
+}
@@ -0,0 +1,412 @@
+//! CSV dumps
+//!
+//! A directory with one file per node type (`nodes-<type>.csv`) and edge
+//! type (`edges-<type>.csv`), and `mapping.json` describing each file:
+//!
+//! ```json
+//! {"files": [{"file": "nodes-user.csv", "kind": "node", "type": "user",
+//!             "columns": [{"column": ":id", "field": "id"},
+//!                         {"column": "age", "type": "int"}]}]}
+//! ```
+//!
+//! A column maps to the `id`, `from`, `to`, `created_at` or `updated_at`
+//! of a record, or by default to the property of its name (or `property`).
+//! Property columns have a `type`: `string` (the default), `int`, `float`,
+//! `bool`, `bytes` (base64), `vector` (a JSON array) or `json` (a tagged
+//! JSON value). An empty cell leaves the property out. Writing a mapping by
+//! hand imports CSV from elsewhere; records without an `id` column get new
+//! IDs, and timestamps may be milliseconds or RFC 3339.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use super::{
+    decode_tagged, decode_value, encode_value, parse_timestamp, Record, Records, Sink,
+};
+use crate::storage::node::{base64, Edge, EdgeId, Node, NodeId, Timestamp, Value};
+
+/// Name of the column mapping in an exported directory
+pub(crate) const MAPPING_FILE: &str = "mapping.json";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Mapping {
+    files: Vec<FileMapping>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct FileMapping {
+    file: String,
+    kind: Kind,
+    #[serde(rename = "type")]
+    record_type: String,
+    columns: Vec<ColumnMapping>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Kind {
+    Node,
+    Edge,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ColumnMapping {
+    column: String,
+    #[serde(default)]
+    field: Field,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    property: Option<String>,
+    #[serde(default, rename = "type")]
+    value_type: ColumnType,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Field {
+    Id,
+    From,
+    To,
+    CreatedAt,
+    UpdatedAt,
+    #[default]
+    Property,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ColumnType {
+    #[default]
+    String,
+    Int,
+    Float,
+    Bool,
+    Bytes,
+    Vector,
+    Json,
+}
+
+impl ColumnType {
+    /// The narrowest column type that holds `value` exactly
+    fn of(value: &Value) -> Self {
+        match value {
+            Value::Bool(_) => ColumnType::Bool,
+            Value::Int(_) => ColumnType::Int,
+            Value::Float(_) => ColumnType::Float,
+            // An empty cell means a missing property, so empty strings need quoting
+            Value::String(s) if !s.is_empty() => ColumnType::String,
+            Value::Bytes(_) => ColumnType::Bytes,
+            Value::Vector(_) => ColumnType::Vector,
+            _ => ColumnType::Json,
+        }
+    }
+}
+
+/// Property columns of each node and edge type, gathered before writing
+#[derive(Debug, Default)]
+pub(crate) struct Layout {
+    nodes: BTreeMap<String, BTreeMap<String, ColumnType>>,
+    edges: BTreeMap<String, BTreeMap<String, ColumnType>>,
+}
+
+impl Layout {
+    pub(crate) fn observe(&mut self, record: &Record) {
+        let (columns, properties) = match record {
+            Record::Node(node) => (self.nodes.entry(node.node_type.clone()).or_default(), &node.properties),
+            Record::Edge(edge) => (self.edges.entry(edge.edge_type.clone()).or_default(), &edge.properties),
+        };
+        for (name, value) in properties {
+            let value_type = ColumnType::of(value);
+            columns.entry(name.clone())
+                .and_modify(|existing| {
+                    // Mixed types fall back to tagged JSON
+                    if *existing != value_type {
+                        *existing = ColumnType::Json;
+                    }
+                })
+                .or_insert(value_type);
+        }
+    }
+}
+
+struct Table {
+    writer: ::csv::Writer<File>,
+    columns: Vec<(String, ColumnType)>,
+}
+
+impl Table {
+    fn row(&mut self, fixed: &[String], properties: &BTreeMap<String, Value>) -> Result<()> {
+        let mut row = fixed.to_vec();
+        for (name, value_type) in &self.columns {
+            row.push(match properties.get(name) {
+                Some(value) => cell(value, *value_type)?,
+                None => String::new(),
+            });
+        }
+        self.writer.write_record(&row)?;
+        Ok(())
+    }
+}
+
+pub(crate) struct CsvSink {
+    dir: PathBuf,
+    mapping: Mapping,
+    nodes: HashMap<String, Table>,
+    edges: HashMap<String, Table>,
+    files: Vec<PathBuf>,
+}
+
+impl CsvSink {
+    pub(crate) fn create(dir: &Path, layout: Layout) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+
+        let mut sink = Self {
+            dir: dir.to_path_buf(),
+            mapping: Mapping { files: Vec::new() },
+            nodes: HashMap::new(),
+            edges: HashMap::new(),
+            files: Vec::new(),
+        };
+        let mut names = HashSet::new();
+        for (node_type, columns) in layout.nodes {
+            let fixed = [(":id", Field::Id), (":created_at", Field::CreatedAt), (":updated_at", Field::UpdatedAt)];
+            let table = sink.table(&mut names, Kind::Node, &node_type, &fixed, columns)?;
+            sink.nodes.insert(node_type, table);
+        }
+        for (edge_type, columns) in layout.edges {
+            let fixed = [(":id", Field::Id), (":from", Field::From), (":to", Field::To), (":created_at", Field::CreatedAt)];
+            let table = sink.table(&mut names, Kind::Edge, &edge_type, &fixed, columns)?;
+            sink.edges.insert(edge_type, table);
+        }
+        Ok(sink)
+    }
+
+    fn table(
+        &mut self,
+        names: &mut HashSet<String>,
+        kind: Kind,
+        record_type: &str,
+        fixed: &[(&str, Field)],
+        columns: BTreeMap<String, ColumnType>,
+    ) -> Result<Table> {
+        let prefix = match kind {
+            Kind::Node => "nodes",
+            Kind::Edge => "edges",
+        };
+        let stem: String = record_type.chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+            .collect();
+        let mut file = format!("{}-{}.csv", prefix, stem);
+        let mut n = 1;
+        while !names.insert(file.clone()) {
+            n += 1;
+            file = format!("{}-{}-{}.csv", prefix, stem, n);
+        }
+
+        let mut mapped: Vec<ColumnMapping> = fixed.iter()
+            .map(|(column, field)| ColumnMapping {
+                column: column.to_string(),
+                field: *field,
+                property: None,
+                value_type: ColumnType::String,
+            })
+            .collect();
+        for (name, value_type) in &columns {
+            if fixed.iter().any(|(column, _)| column == name) {
+                bail!("Property '{}' of {} clashes with a CSV column of the same name", name, record_type);
+            }
+            mapped.push(ColumnMapping {
+                column: name.clone(),
+                field: Field::Property,
+                property: None,
+                value_type: *value_type,
+            });
+        }
+
+        let path = self.dir.join(&file);
+        let mut writer = ::csv::Writer::from_path(&path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        writer.write_record(mapped.iter().map(|c| c.column.as_str()))?;
+        self.files.push(path);
+        self.mapping.files.push(FileMapping {
+            file,
+            kind,
+            record_type: record_type.to_string(),
+            columns: mapped,
+        });
+
+        Ok(Table {
+            writer,
+            columns: columns.into_iter().collect(),
+        })
+    }
+}
+
+impl Sink for CsvSink {
+    fn node(&mut self, node: &Node) -> Result<()> {
+        let table = self.nodes.get_mut(&node.node_type)
+            .with_context(|| format!("Node type {} appeared after the layout was taken", node.node_type))?;
+        let fixed = [
+            node.id.to_string(),
+            node.created_at.millis.to_string(),
+            node.updated_at.millis.to_string(),
+        ];
+        table.row(&fixed, &node.properties)
+    }
+
+    fn edge(&mut self, edge: &Edge) -> Result<()> {
+        let table = self.edges.get_mut(&edge.edge_type)
+            .with_context(|| format!("Edge type {} appeared after the layout was taken", edge.edge_type))?;
+        let fixed = [
+            edge.id.to_string(),
+            edge.from.to_string(),
+            edge.to.to_string(),
+            edge.created_at.millis.to_string(),
+        ];
+        table.row(&fixed, &edge.properties)
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<Vec<PathBuf>> {
+        for table in self.nodes.values_mut().chain(self.edges.values_mut()) {
+            table.writer.flush()?;
+        }
+        let path = self.dir.join(MAPPING_FILE);
+        std::fs::write(&path, serde_json::to_vec_pretty(&self.mapping)?)?;
+        self.files.push(path);
+        Ok(self.files)
+    }
+}
+
+/// `value` written in a column of `value_type`
+fn cell(value: &Value, value_type: ColumnType) -> Result<String> {
+    Ok(match (value_type, value) {
+        (ColumnType::String, Value::String(s)) => s.clone(),
+        (ColumnType::Int, Value::Int(i)) => i.to_string(),
+        (ColumnType::Float, Value::Float(f)) => f.to_string(),
+        (ColumnType::Bool, Value::Bool(b)) => b.to_string(),
+        (ColumnType::Bytes, Value::Bytes(b)) => base64::encode(b),
+        (ColumnType::Vector, Value::Vector(_)) => match encode_value(value) {
+            serde_json::Value::Object(mut tagged) => serde_json::to_string(&tagged.remove("$vector"))?,
+            _ => unreachable!("vectors encode as a tagged object"),
+        },
+        (ColumnType::Json, value) => serde_json::to_string(&encode_value(value))?,
+        (value_type, value) => bail!("Cannot write {:?} in a {:?} column", value, value_type),
+    })
+}
+
+/// Read a non-empty cell of a column of `value_type`
+fn parse_cell(text: &str, value_type: ColumnType) -> Result<Value> {
+    Ok(match value_type {
+        ColumnType::String => Value::String(text.to_string()),
+        ColumnType::Int => Value::Int(text.trim().parse().with_context(|| format!("Invalid int '{}'", text))?),
+        ColumnType::Float => Value::Float(text.trim().parse().with_context(|| format!("Invalid float '{}'", text))?),
+        ColumnType::Bool => Value::Bool(
+            text.trim().to_ascii_lowercase().parse().with_context(|| format!("Invalid bool '{}'", text))?,
+        ),
+        ColumnType::Bytes => Value::Bytes(base64::decode(text.trim())?),
+        ColumnType::Vector => decode_tagged("$vector", serde_json::from_str(text)?)?,
+        ColumnType::Json => decode_value(serde_json::from_str(text)?)?,
+    })
+}
+
+/// Records of the files listed in the mapping at `mapping_path`, node
+/// files first, read a row at a time
+pub(crate) fn read(mapping_path: &Path) -> Result<Records> {
+    let text = std::fs::read_to_string(mapping_path)
+        .with_context(|| format!("Failed to read CSV mapping {}", mapping_path.display()))?;
+    let mut mapping: Mapping = serde_json::from_str(&text)
+        .with_context(|| format!("Invalid CSV mapping {}", mapping_path.display()))?;
+    // Nodes first, so remapped edges find their endpoints
+    mapping.files.sort_by_key(|file| file.kind == Kind::Edge);
+    let dir = mapping_path.parent().map(Path::to_path_buf).unwrap_or_default();
+
+    Ok(Box::new(mapping.files.into_iter().flat_map(move |file| {
+        match read_file(&dir, file) {
+            Ok(records) => records,
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        }
+    })))
+}
+
+fn read_file(dir: &Path, file: FileMapping) -> Result<Records> {
+    let path = dir.join(&file.file);
+    let mut reader = ::csv::Reader::from_path(&path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let headers = reader.headers()?.clone();
+
+    let mut columns = Vec::with_capacity(file.columns.len());
+    for column in file.columns {
+        let index = headers.iter().position(|h| h == column.column)
+            .with_context(|| format!("{} has no column '{}'", file.file, column.column))?;
+        columns.push((index, column));
+    }
+    let has = |field: Field| columns.iter().any(|(_, c)| c.field == field);
+    if file.kind == Kind::Edge && !(has(Field::From) && has(Field::To)) {
+        bail!("{} maps edges but has no 'from' and 'to' columns", file.file);
+    }
+
+    let name = file.file;
+    let kind = file.kind;
+    let record_type = file.record_type;
+    Ok(Box::new(reader.into_records().enumerate().map(move |(index, row)| {
+        let row = row?;
+        parse_row(&row, kind, &record_type, &columns)
+            .with_context(|| format!("{} record {}", name, index + 1))
+    })))
+}
+
+fn parse_row(
+    row: &::csv::StringRecord,
+    kind: Kind,
+    record_type: &str,
+    columns: &[(usize, ColumnMapping)],
+) -> Result<Record> {
+    let mut id = None;
+    let mut from = None;
+    let mut to = None;
+    let mut created_at = None;
+    let mut updated_at = None;
+    let mut properties = BTreeMap::new();
+
+    for (index, column) in columns {
+        let text = row.get(*index).unwrap_or_default();
+        if text.is_empty() {
+            continue;
+        }
+        match column.field {
+            Field::Id => id = Some(text.to_string()),
+            Field::From => from = Some(NodeId::parse(text)?),
+            Field::To => to = Some(NodeId::parse(text)?),
+            Field::CreatedAt => created_at = Some(parse_timestamp(text)?),
+            Field::UpdatedAt => updated_at = Some(parse_timestamp(text)?),
+            Field::Property => {
+                let name = column.property.clone().unwrap_or_else(|| column.column.clone());
+                let value = parse_cell(text, column.value_type)
+                    .with_context(|| format!("Column '{}'", column.column))?;
+                properties.insert(name, value);
+            }
+        }
+    }
+
+    let created_at = created_at.unwrap_or_else(Timestamp::now);
+    Ok(match kind {
+        Kind::Node => Record::Node(Node {
+            id: id.map(|id| NodeId::parse(&id)).transpose()?.unwrap_or_else(NodeId::new),
+            node_type: record_type.to_string(),
+            properties,
+            created_at,
+            updated_at: updated_at.unwrap_or(created_at),
+        }),
+        Kind::Edge => Record::Edge(Edge {
+            id: id.map(|id| EdgeId::parse(&id)).transpose()?.unwrap_or_else(EdgeId::new),
+            from: from.context("Missing 'from'")?,
+            to: to.context("Missing 'to'")?,
+            edge_type: record_type.to_string(),
+            properties,
+            created_at,
+        }),
+    })
+}
@@ -0,0 +1,126 @@
+//! JSONL dumps
+//!
+//! One object per line, tagged by `kind`:
+//!
+//! ```text
+//! {"kind":"node","id":"…","type":"user","created_at":1700000000000,"updated_at":…,"properties":{…}}
+//! {"kind":"edge","id":"…","type":"follows","from":"…","to":"…","created_at":…,"properties":{…}}
+//! ```
+
+use anyhow::{bail, Context, Result};
+use serde_json::json;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use super::{decode_properties, encode_properties, Record, Records, Sink};
+use crate::storage::node::{Edge, EdgeId, Node, NodeId, Timestamp};
+
+pub(crate) struct JsonlSink {
+    path: PathBuf,
+    out: BufWriter<File>,
+}
+
+impl JsonlSink {
+    pub(crate) fn create(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            out: BufWriter::new(file),
+        })
+    }
+
+    fn line(&mut self, value: serde_json::Value) -> Result<()> {
+        serde_json::to_writer(&mut self.out, &value)?;
+        self.out.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+impl Sink for JsonlSink {
+    fn node(&mut self, node: &Node) -> Result<()> {
+        self.line(json!({
+            "kind": "node",
+            "id": node.id.to_string(),
+            "type": node.node_type,
+            "created_at": node.created_at.millis,
+            "updated_at": node.updated_at.millis,
+            "properties": encode_properties(&node.properties),
+        }))
+    }
+
+    fn edge(&mut self, edge: &Edge) -> Result<()> {
+        self.line(json!({
+            "kind": "edge",
+            "id": edge.id.to_string(),
+            "type": edge.edge_type,
+            "from": edge.from.to_string(),
+            "to": edge.to.to_string(),
+            "created_at": edge.created_at.millis,
+            "properties": encode_properties(&edge.properties),
+        }))
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<Vec<PathBuf>> {
+        self.out.flush()?;
+        Ok(vec![self.path])
+    }
+}
+
+/// Records of the JSONL file at `path`, read a line at a time
+pub(crate) fn read(path: &Path) -> Result<Records> {
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let lines = BufReader::new(file).lines().enumerate();
+
+    Ok(Box::new(lines.filter_map(|(index, line)| {
+        let record = line.map_err(anyhow::Error::from).and_then(|line| {
+            if line.trim().is_empty() {
+                return Ok(None);
+            }
+            parse(serde_json::from_str(&line)?).map(Some)
+        });
+        record.with_context(|| format!("Line {}", index + 1)).transpose()
+    })))
+}
+
+fn parse(mut line: serde_json::Value) -> Result<Record> {
+    let kind = text(&line, "kind")?;
+    let properties = decode_properties(line["properties"].take())?;
+    let created_at = millis(&line, "created_at")?;
+
+    Ok(match kind.as_str() {
+        "node" => Record::Node(Node {
+            id: NodeId::parse(&text(&line, "id")?)?,
+            node_type: text(&line, "type")?,
+            properties,
+            created_at,
+            updated_at: millis(&line, "updated_at")?,
+        }),
+        "edge" => Record::Edge(Edge {
+            id: EdgeId::parse(&text(&line, "id")?)?,
+            from: NodeId::parse(&text(&line, "from")?)?,
+            to: NodeId::parse(&text(&line, "to")?)?,
+            edge_type: text(&line, "type")?,
+            properties,
+            created_at,
+        }),
+        other => bail!("Unknown record kind '{}'", other),
+    })
+}
+
+fn text(line: &serde_json::Value, field: &str) -> Result<String> {
+    line[field].as_str()
+        .map(str::to_string)
+        .with_context(|| format!("Missing '{}'", field))
+}
+
+fn millis(line: &serde_json::Value, field: &str) -> Result<Timestamp> {
+    line[field].as_i64()
+        .map(|millis| Timestamp { millis })
+        .with_context(|| format!("Missing '{}'", field))
+}
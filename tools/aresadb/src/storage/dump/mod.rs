@@ -0,0 +1,623 @@
+//! Logical Export and Import
+//!
+//! Dumps every node and edge with its ID, type, timestamps and properties,
+//! in a form that reads back into an identical graph:
+//!
+//! - **JSONL**: one file, a JSON object per node or edge, nodes first
+//! - **CSV**: a directory with one file per node or edge type, described
+//!   by a column mapping file that also lets other CSV data be imported
+//! - **Parquet**: a directory with `nodes.parquet` and `edges.parquet`
+//!   (needs the `parquet` feature)
+//!
+//! Property values keep their exact type: vectors, bytes and non-finite
+//! floats are tagged in JSON (`{"$vector": [...]}`, `{"$bytes": "..."}`,
+//! `{"$float": "NaN"}`), so they don't come back as arrays or strings.
+//!
+//! Exports page through one read transaction, so they are a consistent
+//! point-in-time copy even while writes continue. Imports read their input
+//! as a stream, storing nodes in batches through
+//! [`ParallelExecutor::parallel_insert_nodes`], so neither holds the dataset
+//! in memory.
+
+mod csv;
+mod jsonl;
+#[cfg(feature = "parquet")]
+mod parquet;
+
+use anyhow::{bail, Context, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use super::local::{LocalStorage, ReadView};
+use super::node::{base64, Edge, Node, NodeId, Timestamp, Value};
+use super::parallel::ParallelExecutor;
+use super::{is_reserved_type, Database};
+
+/// Nodes or edges read from storage at a time while exporting
+const DEFAULT_PAGE_SIZE: usize = 1024;
+
+/// Nodes or edges stored at a time while importing
+const DEFAULT_BATCH_SIZE: usize = 10_000;
+
+/// File format of a dump
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum DumpFormat {
+    /// One JSON object per line
+    #[default]
+    Jsonl,
+    /// CSV files with a column mapping
+    Csv,
+    /// Parquet files
+    Parquet,
+}
+
+impl std::fmt::Display for DumpFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DumpFormat::Jsonl => write!(f, "JSONL"),
+            DumpFormat::Csv => write!(f, "CSV"),
+            DumpFormat::Parquet => write!(f, "Parquet"),
+        }
+    }
+}
+
+/// Outcome of an export
+#[derive(Debug, Clone, Default)]
+pub struct ExportReport {
+    /// Nodes written
+    pub nodes: u64,
+    /// Edges written
+    pub edges: u64,
+    /// Files written
+    pub files: Vec<PathBuf>,
+}
+
+/// Outcome of an import
+#[derive(Debug, Clone, Default)]
+pub struct ImportReport {
+    /// Nodes stored
+    pub nodes: u64,
+    /// Edges stored
+    pub edges: u64,
+    /// Whether nodes and edges were given new IDs
+    pub remapped: bool,
+}
+
+/// A node or edge in a dump
+#[derive(Debug)]
+pub(crate) enum Record {
+    Node(Node),
+    Edge(Edge),
+}
+
+/// Records read from a dump, in file order
+pub(crate) type Records = Box<dyn Iterator<Item = Result<Record>>>;
+
+/// Where an export writes records
+pub(crate) trait Sink {
+    fn node(&mut self, node: &Node) -> Result<()>;
+    fn edge(&mut self, edge: &Edge) -> Result<()>;
+    /// Flush everything and return the files written
+    fn finish(self: Box<Self>) -> Result<Vec<PathBuf>>;
+}
+
+/// Writes a database out as a dump
+pub struct Exporter {
+    format: DumpFormat,
+    page_size: usize,
+}
+
+impl Exporter {
+    /// Export in `format`
+    pub fn new(format: DumpFormat) -> Self {
+        Self {
+            format,
+            page_size: DEFAULT_PAGE_SIZE,
+        }
+    }
+
+    /// Read this many nodes or edges from storage at a time
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size.max(1);
+        self
+    }
+
+    /// Export every node and edge of `db` to `path`: a file for JSONL, a
+    /// directory for CSV and Parquet
+    ///
+    /// The export reflects the database at a single commit; writes made
+    /// while it runs are left out.
+    pub async fn export(&self, db: &Database, path: impl AsRef<Path>) -> Result<ExportReport> {
+        let path = path.as_ref();
+        let view = db.local().read_view()?;
+
+        let mut sink: Box<dyn Sink> = match self.format {
+            DumpFormat::Jsonl => Box::new(jsonl::JsonlSink::create(path)?),
+            DumpFormat::Csv => {
+                // Columns have to be known before the first row is written
+                let mut layout = csv::Layout::default();
+                scan(&view, self.page_size, |record| {
+                    layout.observe(&record);
+                    Ok(())
+                })?;
+                Box::new(csv::CsvSink::create(path, layout)?)
+            }
+            DumpFormat::Parquet => parquet_sink(path)?,
+        };
+
+        let (nodes, edges) = scan(&view, self.page_size, |record| match record {
+            Record::Node(node) => sink.node(&node),
+            Record::Edge(edge) => sink.edge(&edge),
+        })?;
+
+        Ok(ExportReport {
+            nodes,
+            edges,
+            files: sink.finish()?,
+        })
+    }
+}
+
+/// Loads a dump into a database
+pub struct Importer {
+    format: DumpFormat,
+    mapping: Option<PathBuf>,
+    remap_ids: bool,
+    batch_size: usize,
+    executor: ParallelExecutor,
+}
+
+impl Importer {
+    /// Import from `format`
+    pub fn new(format: DumpFormat) -> Self {
+        Self {
+            format,
+            mapping: None,
+            remap_ids: false,
+            batch_size: DEFAULT_BATCH_SIZE,
+            executor: ParallelExecutor::new(),
+        }
+    }
+
+    /// Column mapping file for CSV; defaults to `mapping.json` in the
+    /// directory imported
+    pub fn with_mapping(mut self, mapping: impl Into<PathBuf>) -> Self {
+        self.mapping = Some(mapping.into());
+        self
+    }
+
+    /// Give every imported node and edge a new ID, rewriting edge
+    /// endpoints to match, so the dump can go into a non-empty database
+    pub fn with_remap_ids(mut self, remap_ids: bool) -> Self {
+        self.remap_ids = remap_ids;
+        self
+    }
+
+    /// Store this many nodes or edges at a time
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Import the dump at `path` into `db`
+    ///
+    /// Without ID remapping the database must hold no nodes or edges beyond
+    /// its internal `__` types, so no imported ID can collide with an
+    /// existing one. With it, every edge must come after the nodes it
+    /// connects, as exports write them.
+    pub async fn import(&self, db: &Database, path: impl AsRef<Path>) -> Result<ImportReport> {
+        let path = path.as_ref();
+        db.ensure_writable()?;
+        let local = db.local();
+
+        if !self.remap_ids {
+            let stats = local.stats().await?;
+            let node_count = if stats.node_count > 0 {
+                user_node_count(&local.read_view()?, self.batch_size)?
+            } else {
+                0
+            };
+            if node_count > 0 || stats.edge_count > 0 {
+                bail!(
+                    "The database already has {} nodes and {} edges; import with ID remapping to add to it",
+                    node_count,
+                    stats.edge_count
+                );
+            }
+        }
+
+        let records: Records = match self.format {
+            DumpFormat::Jsonl => jsonl::read(path)?,
+            DumpFormat::Csv => {
+                let mapping = self.mapping.clone()
+                    .unwrap_or_else(|| path.join(csv::MAPPING_FILE));
+                csv::read(&mapping)?
+            }
+            DumpFormat::Parquet => parquet_records(path, self.batch_size)?,
+        };
+
+        let mut report = ImportReport {
+            remapped: self.remap_ids,
+            ..Default::default()
+        };
+        let mut ids: HashMap<[u8; 16], NodeId> = HashMap::new();
+        let mut nodes = Vec::new();
+        let mut edges = Vec::new();
+
+        for record in records {
+            match record? {
+                Record::Node(mut node) => {
+                    if self.remap_ids {
+                        let id = NodeId::new();
+                        if ids.insert(node.id.uuid, id.clone()).is_some() {
+                            bail!("Node {} appears more than once", node.id);
+                        }
+                        node.id = id;
+                    }
+                    nodes.push(node);
+                    if nodes.len() >= self.batch_size {
+                        report.nodes += self.store_nodes(local, &mut nodes).await?;
+                    }
+                }
+                Record::Edge(mut edge) => {
+                    if self.remap_ids {
+                        edge.id = super::node::EdgeId::new();
+                        edge.from = remapped(&ids, &edge.from, "from")?;
+                        edge.to = remapped(&ids, &edge.to, "to")?;
+                    }
+                    edges.push(edge);
+                    if edges.len() >= self.batch_size {
+                        // Endpoints first, so no committed edge dangles if the import stops here
+                        report.nodes += self.store_nodes(local, &mut nodes).await?;
+                        report.edges += store_edges(local, &mut edges)?;
+                    }
+                }
+            }
+        }
+
+        report.nodes += self.store_nodes(local, &mut nodes).await?;
+        report.edges += store_edges(local, &mut edges)?;
+        Ok(report)
+    }
+
+    async fn store_nodes(&self, local: &LocalStorage, nodes: &mut Vec<Node>) -> Result<u64> {
+        if nodes.is_empty() {
+            return Ok(0);
+        }
+        let stored = self.executor.parallel_insert_nodes(local, std::mem::take(nodes)).await?;
+        Ok(stored as u64)
+    }
+}
+
+fn remapped(ids: &HashMap<[u8; 16], NodeId>, id: &NodeId, end: &str) -> Result<NodeId> {
+    ids.get(&id.uuid)
+        .cloned()
+        .with_context(|| format!("Edge {} node {} is not in the import before the edge", end, id))
+}
+
+fn store_edges(local: &LocalStorage, edges: &mut Vec<Edge>) -> Result<u64> {
+    if edges.is_empty() {
+        return Ok(0);
+    }
+    let count = edges.len() as u64;
+    let mut txn = local.begin_transaction()?;
+    for edge in edges.drain(..) {
+        txn.insert_edge(edge);
+    }
+    txn.commit()?;
+    Ok(count)
+}
+
+/// Number of nodes outside the reserved `__` types
+fn user_node_count(view: &ReadView, page_size: usize) -> Result<u64> {
+    let mut count = 0;
+    let mut after = None;
+    loop {
+        let page = view.scan_nodes(after.as_ref(), page_size)?;
+        let Some(last) = page.last() else { break };
+        after = Some(last.id.clone());
+        count += page.iter().filter(|node| !is_reserved_type(&node.node_type)).count() as u64;
+    }
+    Ok(count)
+}
+
+/// Hand every node, then every edge, to `visit`, a page at a time
+fn scan(
+    view: &ReadView,
+    page_size: usize,
+    mut visit: impl FnMut(Record) -> Result<()>,
+) -> Result<(u64, u64)> {
+    let mut node_count = 0;
+    let mut after = None;
+    loop {
+        let page = view.scan_nodes(after.as_ref(), page_size)?;
+        let Some(last) = page.last() else { break };
+        after = Some(last.id.clone());
+        for node in page {
+            visit(Record::Node(node))?;
+            node_count += 1;
+        }
+    }
+
+    let mut edge_count = 0;
+    let mut after = None;
+    loop {
+        let page = view.scan_edges(after.as_ref(), page_size)?;
+        let Some(last) = page.last() else { break };
+        after = Some(last.id.clone());
+        for edge in page {
+            visit(Record::Edge(edge))?;
+            edge_count += 1;
+        }
+    }
+
+    Ok((node_count, edge_count))
+}
+
+#[cfg(feature = "parquet")]
+fn parquet_sink(path: &Path) -> Result<Box<dyn Sink>> {
+    Ok(Box::new(parquet::ParquetSink::create(path)?))
+}
+
+#[cfg(not(feature = "parquet"))]
+fn parquet_sink(_path: &Path) -> Result<Box<dyn Sink>> {
+    bail!("Parquet needs aresadb built with the `parquet` feature")
+}
+
+#[cfg(feature = "parquet")]
+fn parquet_records(path: &Path, batch_size: usize) -> Result<Records> {
+    parquet::read(path, batch_size)
+}
+
+#[cfg(not(feature = "parquet"))]
+fn parquet_records(_path: &Path, _batch_size: usize) -> Result<Records> {
+    bail!("Parquet needs aresadb built with the `parquet` feature")
+}
+
+// ========== Typed JSON ==========
+
+/// A property value as JSON that [`decode_value`] reads back exactly
+pub(crate) fn encode_value(value: &Value) -> serde_json::Value {
+    use serde_json::Value as Json;
+
+    match value {
+        Value::Null => Json::Null,
+        Value::Bool(b) => Json::Bool(*b),
+        Value::Int(i) => Json::from(*i),
+        Value::Float(f) => match serde_json::Number::from_f64(*f) {
+            Some(n) => Json::Number(n),
+            None => tagged("$float", Json::from(f.to_string())),
+        },
+        Value::String(s) => Json::String(s.clone()),
+        Value::Bytes(b) => tagged("$bytes", Json::String(base64::encode(b))),
+        Value::Vector(v) => tagged(
+            "$vector",
+            Json::Array(v.iter()
+                .map(|f| serde_json::Number::from_f64(*f as f64).map_or(Json::Null, Json::Number))
+                .collect()),
+        ),
+        Value::Array(a) => Json::Array(a.iter().map(encode_value).collect()),
+        Value::Object(o) => {
+            let object = encode_properties(o);
+            // An object that looks like a tag is wrapped so it isn't read as one
+            match &object {
+                Json::Object(map) if map.len() == 1 && map.keys().all(|k| k.starts_with('$')) => {
+                    tagged("$object", object)
+                }
+                _ => object,
+            }
+        }
+    }
+}
+
+/// Read a value written by [`encode_value`]
+pub(crate) fn decode_value(json: serde_json::Value) -> Result<Value> {
+    use serde_json::Value as Json;
+
+    Ok(match json {
+        Json::Null => Value::Null,
+        Json::Bool(b) => Value::Bool(b),
+        Json::Number(n) => match n.as_i64() {
+            Some(i) => Value::Int(i),
+            None => Value::Float(n.as_f64().with_context(|| format!("Invalid number {}", n))?),
+        },
+        Json::String(s) => Value::String(s),
+        Json::Array(a) => Value::Array(a.into_iter().map(decode_value).collect::<Result<_>>()?),
+        Json::Object(mut map) => {
+            if map.len() == 1 {
+                let key = map.keys().next().cloned().unwrap_or_default();
+                if key.starts_with('$') {
+                    let inner = map.remove(&key).unwrap_or(Json::Null);
+                    return decode_tagged(&key, inner);
+                }
+            }
+            Value::Object(decode_properties(Json::Object(map))?)
+        }
+    })
+}
+
+fn decode_tagged(tag: &str, inner: serde_json::Value) -> Result<Value> {
+    use serde_json::Value as Json;
+
+    Ok(match (tag, inner) {
+        ("$float", Json::String(s)) => Value::Float(s.parse().with_context(|| format!("Invalid float {}", s))?),
+        ("$bytes", Json::String(s)) => Value::Bytes(base64::decode(&s)?),
+        ("$vector", Json::Array(a)) => Value::Vector(
+            a.iter().map(|f| f.as_f64().map_or(f32::NAN, |f| f as f32)).collect(),
+        ),
+        ("$object", object @ Json::Object(_)) => Value::Object(decode_properties(object)?),
+        (tag, inner) => bail!("Invalid {} value {}", tag, inner),
+    })
+}
+
+/// Properties as a JSON object of [`encode_value`] values
+pub(crate) fn encode_properties(properties: &BTreeMap<String, Value>) -> serde_json::Value {
+    serde_json::Value::Object(
+        properties.iter().map(|(k, v)| (k.clone(), encode_value(v))).collect(),
+    )
+}
+
+/// Read properties written by [`encode_properties`]
+pub(crate) fn decode_properties(json: serde_json::Value) -> Result<BTreeMap<String, Value>> {
+    match json {
+        serde_json::Value::Object(map) => map.into_iter()
+            .map(|(k, v)| decode_value(v).map(|v| (k, v)))
+            .collect(),
+        serde_json::Value::Null => Ok(BTreeMap::new()),
+        other => bail!("Properties must be an object, not {}", other),
+    }
+}
+
+/// A timestamp as written in a dump (milliseconds), or as RFC 3339
+pub(crate) fn parse_timestamp(text: &str) -> Result<Timestamp> {
+    if let Ok(millis) = text.parse::<i64>() {
+        return Ok(Timestamp { millis });
+    }
+    let time = chrono::DateTime::parse_from_rfc3339(text)
+        .with_context(|| format!("Invalid timestamp '{}'", text))?;
+    Ok(Timestamp::from_datetime(time.with_timezone(&chrono::Utc)))
+}
+
+fn tagged(tag: &str, inner: serde_json::Value) -> serde_json::Value {
+    let mut map = serde_json::Map::new();
+    map.insert(tag.to_string(), inner);
+    serde_json::Value::Object(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    /// A graph with every kind of value, and the database it's in
+    async fn sample(dir: &Path) -> (Database, Node, Node, Edge) {
+        let db = Database::create(dir, "sample").await.unwrap();
+        let mut props = BTreeMap::new();
+        props.insert("name".to_string(), Value::String("Alice".into()));
+        props.insert("empty".to_string(), Value::String(String::new()));
+        props.insert("age".to_string(), Value::Int(30));
+        props.insert("score".to_string(), Value::Float(1.0));
+        props.insert("avatar".to_string(), Value::Bytes(vec![0, 1, 2, 255]));
+        props.insert("embedding".to_string(), Value::Vector(vec![0.1, -2.5, 3.0]));
+        props.insert("tags".to_string(), Value::Array(vec![Value::Int(1), Value::Null]));
+        let mut tricky = BTreeMap::new();
+        tricky.insert("$vector".to_string(), Value::Bool(true));
+        props.insert("meta".to_string(), Value::Object(tricky));
+        let alice = db.local().insert_node(&Node {
+            id: NodeId::new(),
+            node_type: "user".into(),
+            properties: props,
+            created_at: Timestamp { millis: 1_000 },
+            updated_at: Timestamp { millis: 2_000 },
+        }).await.unwrap();
+
+        let bob = db.insert_node("user", json!({"name": "Bob", "age": 41})).await.unwrap();
+        let edge = db.create_edge(&alice.id.to_string(), &bob.id.to_string(), "follows", Some(json!({"since": 2020})))
+            .await.unwrap();
+        (db, alice, bob, edge)
+    }
+
+    fn same_node(a: &Node, b: &Node) {
+        assert_eq!(a.id, b.id);
+        assert_eq!(a.node_type, b.node_type);
+        assert_eq!(a.created_at, b.created_at);
+        assert_eq!(a.updated_at, b.updated_at);
+        // Compare the tagged form, which tells every variant apart
+        assert_eq!(encode_properties(&a.properties), encode_properties(&b.properties));
+    }
+
+    async fn round_trip(format: DumpFormat, target: &str) {
+        let temp = TempDir::new().unwrap();
+        let (db, alice, bob, edge) = sample(&temp.path().join("source")).await;
+
+        let path = temp.path().join(target);
+        let exported = Exporter::new(format).with_page_size(1).export(&db, &path).await.unwrap();
+        assert_eq!((exported.nodes, exported.edges), (2, 1));
+
+        // Internal nodes, such as a server's accounts, don't count as data
+        let copy = Database::create(temp.path().join("copy"), "copy").await.unwrap();
+        copy.insert_node("__user__", json!({"name": "admin"})).await.unwrap();
+        let imported = Importer::new(format).with_batch_size(1).import(&copy, &path).await.unwrap();
+        assert_eq!((imported.nodes, imported.edges), (2, 1));
+
+        same_node(&alice, &copy.local().get_node(&alice.id).await.unwrap().unwrap());
+        same_node(&bob, &copy.local().get_node(&bob.id).await.unwrap().unwrap());
+        let copied = copy.local().get_edge(&edge.id).await.unwrap().unwrap();
+        assert_eq!((copied.from, copied.to), (edge.from.clone(), edge.to.clone()));
+        assert_eq!(copied.created_at, edge.created_at);
+        assert_eq!(encode_properties(&copied.properties), encode_properties(&edge.properties));
+        assert_eq!(copy.local().get_edges_by_type("follows", None).await.unwrap().len(), 1);
+
+        // A second import collides with what is there unless IDs are remapped
+        assert!(Importer::new(format).import(&copy, &path).await.is_err());
+        let remapped = Importer::new(format).with_remap_ids(true).import(&copy, &path).await.unwrap();
+        assert!(remapped.remapped);
+        let users = copy.get_all_by_type("user", None).await.unwrap();
+        assert_eq!(users.len(), 4);
+        let new_alice = users.iter()
+            .find(|n| n.id != alice.id && n.get("empty").is_some())
+            .unwrap();
+        let edges = copy.local().get_edges_from(&new_alice.id, None).await.unwrap();
+        assert_eq!(edges.len(), 1);
+        assert_ne!(edges[0].id, edge.id);
+        assert_ne!(edges[0].to, bob.id);
+    }
+
+    #[tokio::test]
+    async fn test_jsonl_round_trip() {
+        round_trip(DumpFormat::Jsonl, "dump.jsonl").await;
+    }
+
+    #[tokio::test]
+    async fn test_csv_round_trip() {
+        round_trip(DumpFormat::Csv, "dump").await;
+    }
+
+    #[cfg(feature = "parquet")]
+    #[tokio::test]
+    async fn test_parquet_round_trip() {
+        round_trip(DumpFormat::Parquet, "dump").await;
+    }
+
+    #[tokio::test]
+    async fn test_failed_import_leaves_no_dangling_edges() {
+        let temp = TempDir::new().unwrap();
+        let (db, alice, bob, _) = sample(&temp.path().join("source")).await;
+        for _ in 0..2 {
+            db.create_edge(&alice.id.to_string(), &bob.id.to_string(), "follows", None).await.unwrap();
+        }
+        let path = temp.path().join("dump.jsonl");
+        Exporter::new(DumpFormat::Jsonl).export(&db, &path).await.unwrap();
+
+        // Two nodes and three edges: the edge batch fills while the nodes
+        // are still pending, then the import fails
+        let mut dump = std::fs::read_to_string(&path).unwrap();
+        dump.push_str("not json\n");
+        std::fs::write(&path, dump).unwrap();
+
+        let copy = Database::create(temp.path().join("copy"), "copy").await.unwrap();
+        assert!(Importer::new(DumpFormat::Jsonl).with_batch_size(3).import(&copy, &path).await.is_err());
+        let edges = copy.local().get_edges_by_type("follows", None).await.unwrap();
+        assert_eq!(edges.len(), 3);
+        for end in edges.iter().flat_map(|edge| [&edge.from, &edge.to]) {
+            assert!(copy.local().get_node(end).await.unwrap().is_some());
+        }
+    }
+
+    #[test]
+    fn test_typed_json() {
+        let values = vec![
+            Value::Float(2.0),
+            Value::Float(f64::INFINITY),
+            Value::Bytes(b"hello".to_vec()),
+            Value::Vector(vec![1.0, 0.5]),
+            Value::Array(vec![Value::Int(1), Value::Int(2)]),
+        ];
+        for value in values {
+            let decoded = decode_value(encode_value(&value)).unwrap();
+            assert_eq!(format!("{:?}", decoded), format!("{:?}", value));
+        }
+        assert!(decode_value(json!({"$vector": "oops"})).is_err());
+    }
+}
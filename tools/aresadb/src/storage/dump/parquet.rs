@@ -0,0 +1,241 @@
+//! Parquet dumps
+//!
+//! A directory with `nodes.parquet` (`id`, `type`, `created_at`,
+//! `updated_at`, `properties`) and `edges.parquet` (`id`, `type`, `from`,
+//! `to`, `created_at`, `properties`). Timestamps are milliseconds and
+//! properties a tagged JSON object, so every value reads back exactly.
+
+use anyhow::{Context, Result};
+use arrow_array::builder::{Int64Builder, StringBuilder};
+use arrow_array::{Array, ArrayRef, Int64Array, RecordBatch, StringArray};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use ::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use ::parquet::arrow::ArrowWriter;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::{decode_properties, encode_properties, Record, Records, Sink};
+use crate::storage::node::{Edge, EdgeId, Node, NodeId, Timestamp};
+
+const NODES_FILE: &str = "nodes.parquet";
+const EDGES_FILE: &str = "edges.parquet";
+
+/// Rows buffered per record batch while writing
+const ROWS_PER_BATCH: usize = 8192;
+
+fn nodes_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Utf8, false),
+        Field::new("type", DataType::Utf8, false),
+        Field::new("created_at", DataType::Int64, false),
+        Field::new("updated_at", DataType::Int64, false),
+        Field::new("properties", DataType::Utf8, false),
+    ]))
+}
+
+fn edges_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Utf8, false),
+        Field::new("type", DataType::Utf8, false),
+        Field::new("from", DataType::Utf8, false),
+        Field::new("to", DataType::Utf8, false),
+        Field::new("created_at", DataType::Int64, false),
+        Field::new("properties", DataType::Utf8, false),
+    ]))
+}
+
+/// One Parquet file, written a record batch at a time
+struct Table {
+    path: PathBuf,
+    schema: SchemaRef,
+    writer: ArrowWriter<File>,
+    strings: Vec<StringBuilder>,
+    ints: Vec<Int64Builder>,
+    rows: usize,
+}
+
+impl Table {
+    fn create(path: PathBuf, schema: SchemaRef) -> Result<Self> {
+        let file = File::create(&path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        let writer = ArrowWriter::try_new(file, schema.clone(), None)?;
+        let strings = schema.fields().iter()
+            .filter(|f| f.data_type() == &DataType::Utf8)
+            .map(|_| StringBuilder::new())
+            .collect();
+        let ints = schema.fields().iter()
+            .filter(|f| f.data_type() == &DataType::Int64)
+            .map(|_| Int64Builder::new())
+            .collect();
+        Ok(Self { path, schema, writer, strings, ints, rows: 0 })
+    }
+
+    /// Append a row, given its string and integer columns in schema order
+    fn push(&mut self, strings: &[&str], ints: &[i64]) -> Result<()> {
+        for (builder, value) in self.strings.iter_mut().zip(strings) {
+            builder.append_value(value);
+        }
+        for (builder, value) in self.ints.iter_mut().zip(ints) {
+            builder.append_value(*value);
+        }
+        self.rows += 1;
+        if self.rows >= ROWS_PER_BATCH {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.rows == 0 {
+            return Ok(());
+        }
+        let mut strings = self.strings.iter_mut();
+        let mut ints = self.ints.iter_mut();
+        let columns: Vec<ArrayRef> = self.schema.fields().iter()
+            .map(|field| -> ArrayRef {
+                match field.data_type() {
+                    DataType::Utf8 => Arc::new(strings.next().expect("a builder per column").finish()),
+                    _ => Arc::new(ints.next().expect("a builder per column").finish()),
+                }
+            })
+            .collect();
+        self.writer.write(&RecordBatch::try_new(self.schema.clone(), columns)?)?;
+        self.rows = 0;
+        Ok(())
+    }
+
+    fn close(mut self) -> Result<PathBuf> {
+        self.flush()?;
+        self.writer.close()?;
+        Ok(self.path)
+    }
+}
+
+pub(crate) struct ParquetSink {
+    nodes: Table,
+    edges: Table,
+}
+
+impl ParquetSink {
+    pub(crate) fn create(dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        Ok(Self {
+            nodes: Table::create(dir.join(NODES_FILE), nodes_schema())?,
+            edges: Table::create(dir.join(EDGES_FILE), edges_schema())?,
+        })
+    }
+}
+
+impl Sink for ParquetSink {
+    fn node(&mut self, node: &Node) -> Result<()> {
+        let properties = encode_properties(&node.properties).to_string();
+        self.nodes.push(
+            &[&node.id.to_string(), &node.node_type, &properties],
+            &[node.created_at.millis, node.updated_at.millis],
+        )
+    }
+
+    fn edge(&mut self, edge: &Edge) -> Result<()> {
+        let properties = encode_properties(&edge.properties).to_string();
+        self.edges.push(
+            &[&edge.id.to_string(), &edge.edge_type, &edge.from.to_string(), &edge.to.to_string(), &properties],
+            &[edge.created_at.millis],
+        )
+    }
+
+    fn finish(self: Box<Self>) -> Result<Vec<PathBuf>> {
+        Ok(vec![self.nodes.close()?, self.edges.close()?])
+    }
+}
+
+/// Records of the Parquet dump in `dir`, nodes then edges, read
+/// `batch_size` rows at a time
+pub(crate) fn read(dir: &Path, batch_size: usize) -> Result<Records> {
+    let nodes = batches(&dir.join(NODES_FILE), batch_size, node_rows)?;
+    let edges_path = dir.join(EDGES_FILE);
+    let edges: Records = if edges_path.exists() {
+        batches(&edges_path, batch_size, edge_rows)?
+    } else {
+        Box::new(std::iter::empty())
+    };
+    Ok(Box::new(nodes.chain(edges)))
+}
+
+fn batches(
+    path: &Path,
+    batch_size: usize,
+    rows: fn(&RecordBatch) -> Result<Vec<Record>>,
+) -> Result<Records> {
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let reader = ParquetRecordBatchReaderBuilder::try_new(file)?
+        .with_batch_size(batch_size)
+        .build()?;
+    let name = path.display().to_string();
+
+    Ok(Box::new(reader.flat_map(move |batch| {
+        let records = batch.map_err(anyhow::Error::from)
+            .and_then(|batch| rows(&batch))
+            .with_context(|| format!("Failed to read {}", name));
+        match records {
+            Ok(records) => records.into_iter().map(Ok).collect::<Vec<_>>(),
+            Err(e) => vec![Err(e)],
+        }
+    })))
+}
+
+fn node_rows(batch: &RecordBatch) -> Result<Vec<Record>> {
+    let id = strings(batch, "id")?;
+    let node_type = strings(batch, "type")?;
+    let created_at = ints(batch, "created_at")?;
+    let updated_at = ints(batch, "updated_at")?;
+    let properties = strings(batch, "properties")?;
+
+    (0..batch.num_rows())
+        .map(|row| {
+            Ok(Record::Node(Node {
+                id: NodeId::parse(id.value(row))?,
+                node_type: node_type.value(row).to_string(),
+                properties: decode_properties(serde_json::from_str(properties.value(row))?)?,
+                created_at: Timestamp { millis: created_at.value(row) },
+                updated_at: Timestamp { millis: updated_at.value(row) },
+            }))
+        })
+        .collect()
+}
+
+fn edge_rows(batch: &RecordBatch) -> Result<Vec<Record>> {
+    let id = strings(batch, "id")?;
+    let edge_type = strings(batch, "type")?;
+    let from = strings(batch, "from")?;
+    let to = strings(batch, "to")?;
+    let created_at = ints(batch, "created_at")?;
+    let properties = strings(batch, "properties")?;
+
+    (0..batch.num_rows())
+        .map(|row| {
+            Ok(Record::Edge(Edge {
+                id: EdgeId::parse(id.value(row))?,
+                from: NodeId::parse(from.value(row))?,
+                to: NodeId::parse(to.value(row))?,
+                edge_type: edge_type.value(row).to_string(),
+                properties: decode_properties(serde_json::from_str(properties.value(row))?)?,
+                created_at: Timestamp { millis: created_at.value(row) },
+            }))
+        })
+        .collect()
+}
+
+fn strings<'a>(batch: &'a RecordBatch, name: &str) -> Result<&'a StringArray> {
+    batch.column_by_name(name)
+        .and_then(|column| column.as_any().downcast_ref::<StringArray>())
+        .with_context(|| format!("Missing string column '{}'", name))
+}
+
+fn ints<'a>(batch: &'a RecordBatch, name: &str) -> Result<&'a Int64Array> {
+    batch.column_by_name(name)
+        .and_then(|column| column.as_any().downcast_ref::<Int64Array>())
+        .with_context(|| format!("Missing integer column '{}'", name))
+}
@@ -0,0 +1,85 @@
+//! Write-path hooks
+//!
+//! A [`WriteHook`] sees every node written through [`LocalStorage`] before
+//! it is stored, and every change after it commits. Triggers are built on
+//! it; storage itself knows nothing about what a hook does.
+//!
+//! [`LocalStorage`]: super::LocalStorage
+
+use anyhow::Result;
+use parking_lot::RwLock;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+use super::changes::{self, ChangeEvent};
+use super::node::Node;
+
+/// How a node is being written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteKind {
+    /// The node does not exist yet
+    Insert,
+    /// The node exists and is being replaced or merged
+    Update,
+}
+
+/// Observes and shapes writes to storage
+pub trait WriteHook: Send + Sync {
+    /// Called with the node about to be stored, inside the write
+    /// transaction; may change it, and an error aborts the write
+    fn before_write(&self, kind: WriteKind, node: &mut Node) -> Result<()>;
+
+    /// Called with the changes of a transaction once it has committed
+    fn after_commit(&self, _events: &[ChangeEvent]) {}
+}
+
+/// Hook and live subscribers shared by every handle on one storage
+#[derive(Clone)]
+pub(crate) struct WritePath {
+    hook: Arc<RwLock<Option<Arc<dyn WriteHook>>>>,
+    changes: broadcast::Sender<ChangeEvent>,
+}
+
+impl WritePath {
+    pub(crate) fn new() -> Self {
+        Self {
+            hook: Arc::new(RwLock::new(None)),
+            changes: broadcast::channel(changes::LIVE_BUFFER).0,
+        }
+    }
+
+    /// Install `hook`, replacing any before it
+    pub(crate) fn set_hook(&self, hook: Arc<dyn WriteHook>) {
+        *self.hook.write() = Some(hook);
+    }
+
+    pub(crate) fn before_write(&self, kind: WriteKind, node: &mut Node) -> Result<()> {
+        let hook = self.hook.read().clone();
+        match hook {
+            Some(hook) => hook.before_write(kind, node),
+            None => Ok(()),
+        }
+    }
+
+    /// Hand committed changes to the hook, then to live subscribers
+    ///
+    /// Called with the write lock still held, so events go out in sequence
+    /// order.
+    pub(crate) fn publish(&self, events: Vec<ChangeEvent>) {
+        if events.is_empty() {
+            return;
+        }
+        let hook = self.hook.read().clone();
+        if let Some(hook) = hook {
+            hook.after_commit(&events);
+        }
+        for event in events {
+            // No receivers is fine
+            let _ = self.changes.send(event);
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<ChangeEvent> {
+        self.changes.subscribe()
+    }
+}
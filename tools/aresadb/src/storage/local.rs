@@ -5,9 +5,14 @@
 use anyhow::{Result, Context};
 use parking_lot::RwLock;
 use redb::{Database as RedbDatabase, TableDefinition, ReadableTable, ReadableMultimapTable, MultimapTableDefinition, ReadableTableMetadata};
+use std::io::{Read, Write};
+use std::ops::Bound;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use tokio::sync::broadcast;
 
+use super::changes::{self, Change, ChangeEvent, ChangeFilter, ChangeStream, CHANGE_RETENTION};
+use super::hooks::{WriteHook, WriteKind, WritePath};
 use super::node::{Node, Edge, NodeId, EdgeId, Value, Timestamp};
 
 // Table definitions for redb
@@ -18,6 +23,50 @@ const EDGE_FROM_INDEX: MultimapTableDefinition<&[u8], &[u8]> = MultimapTableDefi
 const EDGE_TO_INDEX: MultimapTableDefinition<&[u8], &[u8]> = MultimapTableDefinition::new("edge_to_index");
 const EDGE_TYPE_INDEX: MultimapTableDefinition<&str, &[u8]> = MultimapTableDefinition::new("edge_type_index");
 const METADATA_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("metadata");
+/// Edges whose target lives here but whose source may be on another shard,
+/// keyed by target ID followed by edge ID
+const REVERSE_EDGES_TABLE: TableDefinition<&[u8], &[u8]> = TableDefinition::new("reverse_edges");
+/// Change log, keyed by sequence
+const CHANGES_TABLE: TableDefinition<u64, &[u8]> = TableDefinition::new("changes");
+
+/// Reverse-index key: target node ID followed by edge ID
+fn reverse_key(to: &NodeId, id: &EdgeId) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    key[..16].copy_from_slice(&to.uuid);
+    key[16..].copy_from_slice(&id.uuid);
+    key
+}
+
+/// Append changes to the log within a write transaction, returning them
+/// numbered
+///
+/// Every [`CHANGE_RETENTION`] / 100 events the oldest beyond the retention
+/// are pruned; the newest is always kept so numbering carries on.
+fn record_changes(write_txn: &redb::WriteTransaction, changes: Vec<Change>) -> Result<Vec<ChangeEvent>> {
+    if changes.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut table = write_txn.open_table(CHANGES_TABLE)?;
+    let mut seq = table.last()?.map(|(k, _)| k.value()).unwrap_or(0);
+    let timestamp = Timestamp::now();
+    let prune_every = CHANGE_RETENTION / 100;
+
+    let mut events = Vec::with_capacity(changes.len());
+    let mut prune = false;
+    for change in changes {
+        seq += 1;
+        prune |= seq % prune_every == 0;
+        let event = ChangeEvent { seq, timestamp, change };
+        table.insert(seq, serde_json::to_vec(&event)?.as_slice())?;
+        events.push(event);
+    }
+
+    if prune && seq > CHANGE_RETENTION {
+        table.retain_in(..=seq - CHANGE_RETENTION, |_, _| false)?;
+    }
+    Ok(events)
+}
 
 /// Storage statistics
 #[derive(Debug, Clone, Default)]
@@ -29,11 +78,18 @@ pub struct StorageStats {
 }
 
 /// Local storage backend using redb
+///
+/// Clones share the same redb handle.
+#[derive(Clone)]
 pub struct LocalStorage {
     /// Path to the database directory
     path: PathBuf,
     /// redb database handle
     db: Arc<RwLock<RedbDatabase>>,
+    /// Reject all writes
+    readonly: bool,
+    /// Write hook and live change subscribers
+    write_path: WritePath,
 }
 
 impl LocalStorage {
@@ -61,6 +117,8 @@ impl LocalStorage {
                 let _ = write_txn.open_multimap_table(EDGE_TO_INDEX)?;
                 let _ = write_txn.open_multimap_table(EDGE_TYPE_INDEX)?;
                 let _ = write_txn.open_table(METADATA_TABLE)?;
+                let _ = write_txn.open_table(REVERSE_EDGES_TABLE)?;
+                let _ = write_txn.open_table(CHANGES_TABLE)?;
             }
             write_txn.commit()?;
         }
@@ -83,6 +141,8 @@ impl LocalStorage {
         Ok(Self {
             path,
             db: Arc::new(RwLock::new(db)),
+            readonly: false,
+            write_path: WritePath::new(),
         })
     }
 
@@ -97,9 +157,54 @@ impl LocalStorage {
         Ok(Self {
             path,
             db: Arc::new(RwLock::new(db)),
+            readonly: false,
+            write_path: WritePath::new(),
+        })
+    }
+
+    /// Open storage over a custom redb backend (e.g. a remote bucket)
+    pub fn with_backend(
+        path: impl AsRef<Path>,
+        backend: impl redb::StorageBackend,
+        readonly: bool,
+    ) -> Result<Self> {
+        let db = redb::Builder::new()
+            .create_with_backend(backend)
+            .context("Failed to open redb database")?;
+
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            db: Arc::new(RwLock::new(db)),
+            readonly,
+            write_path: WritePath::new(),
         })
     }
 
+    /// Check if storage rejects writes
+    pub fn is_readonly(&self) -> bool {
+        self.readonly
+    }
+
+    /// Wait for writes in progress and make everything committed durable
+    pub async fn flush(&self) -> Result<()> {
+        if self.readonly {
+            return Ok(());
+        }
+        // Writers hold the lock for their whole transaction, and committing
+        // an empty one syncs the file
+        let db = self.db.write();
+        db.begin_write()?.commit()?;
+        Ok(())
+    }
+
+    /// Fail if storage is readonly
+    fn check_writable(&self) -> Result<()> {
+        if self.readonly {
+            anyhow::bail!("Database is readonly");
+        }
+        Ok(())
+    }
+
     /// Get storage statistics
     pub async fn stats(&self) -> Result<StorageStats> {
         let db = self.db.read();
@@ -128,27 +233,41 @@ impl LocalStorage {
 
     // ========== Node Operations ==========
 
-    /// Insert a new node
-    pub async fn insert_node(&self, node: &Node) -> Result<()> {
+    /// Insert a node, or replace the node with the same ID
+    ///
+    /// Returns the node as stored, after any write hook has run.
+    pub async fn insert_node(&self, node: &Node) -> Result<Node> {
+        self.check_writable()?;
         let db = self.db.write();
         let write_txn = db.begin_write()?;
 
-        {
-            // Serialize node
-            let node_bytes = serde_json::to_vec(node)?;
-            let id_bytes = node.id.uuid;
+        let mut node = node.clone();
+        let change = {
+            let mut nodes_table = write_txn.open_table(NODES_TABLE)?;
+            let before: Option<Node> = nodes_table.get(node.id.uuid.as_slice())?
+                .map(|data| serde_json::from_slice(data.value()))
+                .transpose()?;
+            let kind = if before.is_some() { WriteKind::Update } else { WriteKind::Insert };
+            self.write_path.before_write(kind, &mut node)?;
 
             // Insert into nodes table
-            let mut nodes_table = write_txn.open_table(NODES_TABLE)?;
-            nodes_table.insert(id_bytes.as_slice(), node_bytes.as_slice())?;
+            let node_bytes = serde_json::to_vec(&node)?;
+            nodes_table.insert(node.id.uuid.as_slice(), node_bytes.as_slice())?;
 
             // Update type index
             let mut type_index = write_txn.open_multimap_table(NODE_TYPE_INDEX)?;
-            type_index.insert(node.node_type.as_str(), id_bytes.as_slice())?;
-        }
+            type_index.insert(node.node_type.as_str(), node.id.uuid.as_slice())?;
 
+            match before {
+                Some(before) => Change::NodeUpdated { before, after: node.clone() },
+                None => Change::NodeInserted { node: node.clone() },
+            }
+        };
+
+        let events = record_changes(&write_txn, vec![change])?;
         write_txn.commit()?;
-        Ok(())
+        self.write_path.publish(events);
+        Ok(node)
     }
 
     /// Get a node by ID
@@ -168,10 +287,11 @@ impl LocalStorage {
 
     /// Update a node's properties
     pub async fn update_node(&self, id: &NodeId, properties: Value) -> Result<Node> {
+        self.check_writable()?;
         let db = self.db.write();
         let write_txn = db.begin_write()?;
 
-        let node = {
+        let (before, node) = {
             let mut nodes_table = write_txn.open_table(NODES_TABLE)?;
 
             // Get existing node - clone data to release borrow
@@ -181,7 +301,8 @@ impl LocalStorage {
                 guard.value().to_vec()
             };
 
-            let mut node: Node = serde_json::from_slice(&node_data)?;
+            let before: Node = serde_json::from_slice(&node_data)?;
+            let mut node = before.clone();
 
             // Update properties
             if let Value::Object(new_props) = properties {
@@ -190,29 +311,35 @@ impl LocalStorage {
                 }
             }
             node.updated_at = Timestamp::now();
+            self.write_path.before_write(WriteKind::Update, &mut node)?;
 
             // Save updated node
             let node_bytes = serde_json::to_vec(&node)?;
             nodes_table.insert(id.uuid.as_slice(), node_bytes.as_slice())?;
 
-            node
+            (before, node)
         };
 
+        let events = record_changes(&write_txn, vec![Change::NodeUpdated { before, after: node.clone() }])?;
         write_txn.commit()?;
+        self.write_path.publish(events);
         Ok(node)
     }
 
     /// Delete a node and its edges
     pub async fn delete_node(&self, id: &NodeId) -> Result<()> {
+        self.check_writable()?;
         let db = self.db.write();
         let write_txn = db.begin_write()?;
 
+        let mut changes = Vec::new();
         {
             // Get node to find its type
             let nodes_table = write_txn.open_table(NODES_TABLE)?;
-            if let Some(data) = nodes_table.get(id.uuid.as_slice())? {
-                let node: Node = serde_json::from_slice(data.value())?;
-
+            let deleted = nodes_table.get(id.uuid.as_slice())?
+                .map(|data| serde_json::from_slice::<Node>(data.value()))
+                .transpose()?;
+            if let Some(ref node) = deleted {
                 // Remove from type index
                 let mut type_index = write_txn.open_multimap_table(NODE_TYPE_INDEX)?;
                 type_index.remove(node.node_type.as_str(), id.uuid.as_slice())?;
@@ -236,7 +363,9 @@ impl LocalStorage {
             let mut edge_to = write_txn.open_multimap_table(EDGE_TO_INDEX)?;
 
             for edge_id in edge_ids {
-                edges_table.remove(edge_id.as_slice())?;
+                if let Some(data) = edges_table.remove(edge_id.as_slice())? {
+                    changes.push(Change::EdgeDeleted { edge: serde_json::from_slice(data.value())? });
+                }
                 edge_from.remove(id.uuid.as_slice(), edge_id.as_slice())?;
             }
 
@@ -256,9 +385,44 @@ impl LocalStorage {
             let mut edge_to = write_txn.open_multimap_table(EDGE_TO_INDEX)?;
 
             for edge_id in edge_ids {
-                edges_table.remove(edge_id.as_slice())?;
+                if let Some(data) = edges_table.remove(edge_id.as_slice())? {
+                    changes.push(Change::EdgeDeleted { edge: serde_json::from_slice(data.value())? });
+                }
                 edge_to.remove(id.uuid.as_slice(), edge_id.as_slice())?;
             }
+
+            if let Some(node) = deleted {
+                changes.push(Change::NodeDeleted { node });
+            }
+        }
+
+        let events = record_changes(&write_txn, changes)?;
+        write_txn.commit()?;
+        self.write_path.publish(events);
+        Ok(())
+    }
+
+    /// Remove a node without touching any edges
+    ///
+    /// Used when a node moves to another shard: edges pointing at it from
+    /// nodes that stay behind must survive. The node still exists, so this
+    /// is not recorded in the change log.
+    pub async fn evict_node(&self, id: &NodeId) -> Result<()> {
+        self.check_writable()?;
+        let db = self.db.write();
+        let write_txn = db.begin_write()?;
+
+        {
+            let mut nodes_table = write_txn.open_table(NODES_TABLE)?;
+            let removed = nodes_table
+                .remove(id.uuid.as_slice())?
+                .map(|data| serde_json::from_slice::<Node>(data.value()))
+                .transpose()?;
+
+            if let Some(node) = removed {
+                let mut type_index = write_txn.open_multimap_table(NODE_TYPE_INDEX)?;
+                type_index.remove(node.node_type.as_str(), id.uuid.as_slice())?;
+            }
         }
 
         write_txn.commit()?;
@@ -318,6 +482,7 @@ impl LocalStorage {
 
     /// Insert a new edge
     pub async fn insert_edge(&self, edge: &Edge) -> Result<()> {
+        self.check_writable()?;
         let db = self.db.write();
         let write_txn = db.begin_write()?;
 
@@ -343,7 +508,9 @@ impl LocalStorage {
             type_index.insert(edge.edge_type.as_str(), id_bytes.as_slice())?;
         }
 
+        let events = record_changes(&write_txn, vec![Change::EdgeInserted { edge: edge.clone() }])?;
         write_txn.commit()?;
+        self.write_path.publish(events);
         Ok(())
     }
 
@@ -422,9 +589,11 @@ impl LocalStorage {
 
     /// Delete an edge
     pub async fn delete_edge(&self, id: &EdgeId) -> Result<()> {
+        self.check_writable()?;
         let db = self.db.write();
         let write_txn = db.begin_write()?;
 
+        let mut changes = Vec::new();
         {
             // Get edge to find its from/to nodes
             let edges_table = write_txn.open_table(EDGES_TABLE)?;
@@ -440,6 +609,8 @@ impl LocalStorage {
 
                 let mut type_index = write_txn.open_multimap_table(EDGE_TYPE_INDEX)?;
                 type_index.remove(edge.edge_type.as_str(), id.uuid.as_slice())?;
+
+                changes.push(Change::EdgeDeleted { edge });
             }
             drop(edges_table);
 
@@ -448,7 +619,9 @@ impl LocalStorage {
             edges_table.remove(id.uuid.as_slice())?;
         }
 
+        let events = record_changes(&write_txn, changes)?;
         write_txn.commit()?;
+        self.write_path.publish(events);
         Ok(())
     }
 
@@ -478,22 +651,598 @@ impl LocalStorage {
         Ok(edges)
     }
 
+    /// A point-in-time view of every node and edge
+    ///
+    /// The view holds one redb read transaction, so everything read through
+    /// it reflects the same commit however long reading takes.
+    pub(crate) fn read_view(&self) -> Result<ReadView> {
+        let db = self.db.read();
+        Ok(ReadView { txn: db.begin_read()? })
+    }
+
+    // ========== Reverse Edge Index ==========
+
+    /// Record `edge` under its target node
+    ///
+    /// Sharded stores keep edges on the source node's shard; this entry
+    /// on the target's shard answers incoming-edge lookups.
+    pub async fn insert_reverse_edge(&self, edge: &Edge) -> Result<()> {
+        self.check_writable()?;
+        let db = self.db.write();
+        let write_txn = db.begin_write()?;
+
+        {
+            let mut table = write_txn.open_table(REVERSE_EDGES_TABLE)?;
+            let key = reverse_key(&edge.to, &edge.id);
+            table.insert(key.as_slice(), serde_json::to_vec(edge)?.as_slice())?;
+        }
+
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    /// Remove the reverse entry for an edge into `to`
+    pub async fn delete_reverse_edge(&self, to: &NodeId, id: &EdgeId) -> Result<()> {
+        self.check_writable()?;
+        let db = self.db.write();
+        let write_txn = db.begin_write()?;
+
+        {
+            let mut table = write_txn.open_table(REVERSE_EDGES_TABLE)?;
+            table.remove(reverse_key(to, id).as_slice())?;
+        }
+
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    /// Edges into `node_id` recorded in the reverse index
+    pub async fn get_reverse_edges(&self, node_id: &NodeId, edge_type: Option<&str>) -> Result<Vec<Edge>> {
+        let db = self.db.read();
+        let read_txn = db.begin_read()?;
+
+        // Stores created before the index existed have no table yet
+        let table = match read_txn.open_table(REVERSE_EDGES_TABLE) {
+            Ok(table) => table,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let start = reverse_key(node_id, &EdgeId { uuid: [0; 16] });
+        let end = reverse_key(node_id, &EdgeId { uuid: [0xff; 16] });
+
+        let mut edges = Vec::new();
+        for result in table.range(start.as_slice()..=end.as_slice())? {
+            let (_, data) = result?;
+            let edge: Edge = serde_json::from_slice(data.value())?;
+            if edge_type.is_none_or(|t| edge.edge_type == t) {
+                edges.push(edge);
+            }
+        }
+
+        Ok(edges)
+    }
+
     // ========== Transaction Support ==========
 
     /// Begin a transaction
     pub fn begin_transaction(&self) -> Result<Transaction> {
-        Transaction::new(self.db.clone())
+        self.check_writable()?;
+        Transaction::new(self.db.clone(), self.write_path.clone())
     }
 
     /// Get database path
     pub fn path(&self) -> &Path {
         &self.path
     }
+
+    // ========== Change Data Capture ==========
+
+    /// Receive changes as they commit
+    pub fn subscribe_changes(&self) -> broadcast::Receiver<ChangeEvent> {
+        self.write_path.subscribe()
+    }
+
+    /// Run `hook` on every write through this storage and its clones,
+    /// replacing any hook installed before
+    pub fn set_write_hook(&self, hook: Arc<dyn WriteHook>) {
+        self.write_path.set_hook(hook);
+    }
+
+    /// Stream changes after sequence `after`, or only those still to come
+    /// when `after` is `None`
+    pub fn changes(&self, after: Option<u64>, filter: ChangeFilter) -> Result<ChangeStream> {
+        changes::stream(self.clone(), after, filter)
+    }
+
+    /// Sequence of the latest change, 0 if there has been none
+    pub fn last_change_seq(&self) -> Result<u64> {
+        let db = self.db.read();
+        let read_txn = db.begin_read()?;
+        let table = match read_txn.open_table(CHANGES_TABLE) {
+            Ok(table) => table,
+            // Databases from before the change log
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+        let last = table.last()?.map(|(k, _)| k.value()).unwrap_or(0);
+        Ok(last)
+    }
+
+    /// Fail if changes after `after` have been pruned from the log
+    pub fn check_retained(&self, after: u64) -> Result<()> {
+        let db = self.db.read();
+        let read_txn = db.begin_read()?;
+        let table = match read_txn.open_table(CHANGES_TABLE) {
+            Ok(table) => table,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        if let Some((first, _)) = table.first()? {
+            if after + 1 < first.value() {
+                anyhow::bail!(
+                    "Changes after sequence {} are no longer retained; the oldest is {}",
+                    after,
+                    first.value()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Up to `limit` changes after sequence `after`, oldest first
+    pub fn changes_after(&self, after: u64, limit: usize) -> Result<Vec<ChangeEvent>> {
+        let db = self.db.read();
+        let read_txn = db.begin_read()?;
+        let table = match read_txn.open_table(CHANGES_TABLE) {
+            Ok(table) => table,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut events = Vec::new();
+        for item in table.range(after.saturating_add(1)..)?.take(limit) {
+            let (_, data) = item?;
+            events.push(serde_json::from_slice(data.value())?);
+        }
+        Ok(events)
+    }
+
+    // ========== Metadata ==========
+
+    /// Read a metadata entry
+    pub fn get_metadata(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let db = self.db.read();
+        let read_txn = db.begin_read()?;
+        let table = read_txn.open_table(METADATA_TABLE)?;
+        Ok(table.get(key)?.map(|v| v.value().to_vec()))
+    }
+
+    /// Write a metadata entry
+    pub fn put_metadata(&self, key: &str, value: &[u8]) -> Result<()> {
+        self.check_writable()?;
+        let db = self.db.write();
+        let write_txn = db.begin_write()?;
+        write_txn.open_table(METADATA_TABLE)?.insert(key, value)?;
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    /// Remove a metadata entry, returning its value
+    pub fn take_metadata(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.check_writable()?;
+        let db = self.db.write();
+        let write_txn = db.begin_write()?;
+        let value = write_txn
+            .open_table(METADATA_TABLE)?
+            .remove(key)?
+            .map(|v| v.value().to_vec());
+        write_txn.commit()?;
+        Ok(value)
+    }
+
+    /// IDs of every stored node and edge
+    pub async fn keys(&self) -> Result<Vec<Vec<u8>>> {
+        let db = self.db.read();
+        let read_txn = db.begin_read()?;
+
+        let mut keys = Vec::new();
+        for item in read_txn.open_table(NODES_TABLE)?.iter()? {
+            keys.push(item?.0.value().to_vec());
+        }
+        for item in read_txn.open_table(EDGES_TABLE)?.iter()? {
+            keys.push(item?.0.value().to_vec());
+        }
+        Ok(keys)
+    }
+
+    // ========== Snapshots ==========
+
+    /// Stream a consistent dump of the database into `writer`
+    ///
+    /// Everything is read in one redb read transaction, so the snapshot
+    /// reflects a single point in time even while writes continue. Besides
+    /// nodes and edges it holds the reverse edges, metadata and retained
+    /// change log, so a replica restored from it carries on from the same
+    /// point. Rows are compressed and written as they are read.
+    pub async fn snapshot(&self, writer: impl Write) -> Result<SnapshotSummary> {
+        self.read_view()?.snapshot(writer, true)
+    }
+
+    /// Stream the nodes, edges and reverse edges of a consistent dump into
+    /// `writer`, leaving out the metadata and change log
+    ///
+    /// The summary still gives the last change the dump reflects.
+    pub(crate) async fn snapshot_data(&self, writer: impl Write) -> Result<SnapshotSummary> {
+        self.read_view()?.snapshot(writer, false)
+    }
+
+    /// Replace the whole database with a snapshot read from `reader`
+    ///
+    /// Nodes, edges, reverse edges, metadata and the change log are all
+    /// replaced in one transaction. Changes in the snapshot newer than the
+    /// last one here then go to the write hook and live subscribers, as if
+    /// they had been made here.
+    pub async fn restore_snapshot(&self, reader: impl Read) -> Result<SnapshotSummary> {
+        self.load_snapshot(reader, true)
+    }
+
+    /// Replace the nodes, edges and reverse edges with those of a snapshot,
+    /// keeping this database's metadata and change log
+    ///
+    /// The replacement is not recorded in the change log.
+    pub(crate) async fn restore_snapshot_data(&self, reader: impl Read) -> Result<SnapshotSummary> {
+        self.load_snapshot(reader, false)
+    }
+
+    /// Blocking core of [`restore_snapshot`](Self::restore_snapshot) and
+    /// [`restore_snapshot_data`](Self::restore_snapshot_data)
+    pub(crate) fn load_snapshot(&self, reader: impl Read, everything: bool) -> Result<SnapshotSummary> {
+        self.check_writable()?;
+        let mut rows = lz4_flex::frame::FrameDecoder::new(reader);
+        let mut summary = SnapshotSummary::default();
+
+        let db = self.db.write();
+        let write_txn = db.begin_write()?;
+        let previous = write_txn.open_table(CHANGES_TABLE)?.last()?.map(|(k, _)| k.value()).unwrap_or(0);
+
+        {
+            write_txn.delete_table(NODES_TABLE)?;
+            write_txn.delete_table(EDGES_TABLE)?;
+            write_txn.delete_multimap_table(NODE_TYPE_INDEX)?;
+            write_txn.delete_multimap_table(EDGE_FROM_INDEX)?;
+            write_txn.delete_multimap_table(EDGE_TO_INDEX)?;
+            write_txn.delete_multimap_table(EDGE_TYPE_INDEX)?;
+            write_txn.delete_table(REVERSE_EDGES_TABLE)?;
+            if everything {
+                write_txn.delete_table(METADATA_TABLE)?;
+                write_txn.delete_table(CHANGES_TABLE)?;
+            }
+
+            let mut nodes_table = write_txn.open_table(NODES_TABLE)?;
+            let mut type_index = write_txn.open_multimap_table(NODE_TYPE_INDEX)?;
+            let mut edges_table = write_txn.open_table(EDGES_TABLE)?;
+            let mut from_index = write_txn.open_multimap_table(EDGE_FROM_INDEX)?;
+            let mut to_index = write_txn.open_multimap_table(EDGE_TO_INDEX)?;
+            let mut edge_type_index = write_txn.open_multimap_table(EDGE_TYPE_INDEX)?;
+            let mut reverse_table = write_txn.open_table(REVERSE_EDGES_TABLE)?;
+            let mut metadata_table = write_txn.open_table(METADATA_TABLE)?;
+            let mut changes_table = write_txn.open_table(CHANGES_TABLE)?;
+
+            loop {
+                let row: SnapshotRow = bincode::deserialize_from(&mut rows).context("Corrupt snapshot")?;
+                match row {
+                    SnapshotRow::Node(bytes) => {
+                        let node: Node = serde_json::from_slice(&bytes).context("Corrupt snapshot node")?;
+                        nodes_table.insert(node.id.uuid.as_slice(), bytes.as_slice())?;
+                        type_index.insert(node.node_type.as_str(), node.id.uuid.as_slice())?;
+                        summary.node_count += 1;
+                    }
+                    SnapshotRow::Edge(bytes) => {
+                        let edge: Edge = serde_json::from_slice(&bytes).context("Corrupt snapshot edge")?;
+                        edges_table.insert(edge.id.uuid.as_slice(), bytes.as_slice())?;
+                        from_index.insert(edge.from.uuid.as_slice(), edge.id.uuid.as_slice())?;
+                        to_index.insert(edge.to.uuid.as_slice(), edge.id.uuid.as_slice())?;
+                        edge_type_index.insert(edge.edge_type.as_str(), edge.id.uuid.as_slice())?;
+                        summary.edge_count += 1;
+                    }
+                    SnapshotRow::ReverseEdge(key, bytes) => {
+                        reverse_table.insert(key.as_slice(), bytes.as_slice())?;
+                    }
+                    SnapshotRow::Metadata(key, value) if everything => {
+                        metadata_table.insert(key.as_str(), value.as_slice())?;
+                    }
+                    SnapshotRow::Change(seq, event) if everything => {
+                        changes_table.insert(seq, event.as_slice())?;
+                        summary.change_seq = seq;
+                    }
+                    SnapshotRow::Metadata(..) | SnapshotRow::Change(..) => {}
+                    SnapshotRow::End => break,
+                }
+            }
+        }
+
+        write_txn.commit()?;
+
+        // Published with the write lock still held, like any other commit
+        if everything {
+            let read_txn = db.begin_read()?;
+            let table = read_txn.open_table(CHANGES_TABLE)?;
+            let mut events = Vec::new();
+            for item in table.range(previous.saturating_add(1)..)? {
+                let (_, data) = item?;
+                events.push(serde_json::from_slice(data.value())?);
+                if events.len() == changes::LIVE_BUFFER {
+                    self.write_path.publish(std::mem::take(&mut events));
+                }
+            }
+            self.write_path.publish(events);
+        }
+        Ok(summary)
+    }
+}
+
+/// Reads from a single read transaction, from [`LocalStorage::read_view`]
+pub(crate) struct ReadView {
+    txn: redb::ReadTransaction,
+}
+
+impl ReadView {
+    /// Stream this view as a snapshot into `writer`, as in
+    /// [`LocalStorage::snapshot`]; `everything` adds the metadata and
+    /// change log
+    ///
+    /// Blocks while writing, and the view can be moved to another thread
+    /// first, so a snapshot can be taken at one point and written off the
+    /// async runtime.
+    pub(crate) fn snapshot(&self, writer: impl Write, everything: bool) -> Result<SnapshotSummary> {
+        let read_txn = &self.txn;
+        let mut out = lz4_flex::frame::FrameEncoder::new(writer);
+        let mut summary = SnapshotSummary::default();
+
+        for item in read_txn.open_table(NODES_TABLE)?.iter()? {
+            let (_, value) = item?;
+            write_row(&mut out, &SnapshotRow::Node(value.value().to_vec()))?;
+            summary.node_count += 1;
+        }
+        for item in read_txn.open_table(EDGES_TABLE)?.iter()? {
+            let (_, value) = item?;
+            write_row(&mut out, &SnapshotRow::Edge(value.value().to_vec()))?;
+            summary.edge_count += 1;
+        }
+        match read_txn.open_table(REVERSE_EDGES_TABLE) {
+            Ok(table) => {
+                for item in table.iter()? {
+                    let (key, value) = item?;
+                    write_row(&mut out, &SnapshotRow::ReverseEdge(key.value().to_vec(), value.value().to_vec()))?;
+                }
+            }
+            Err(redb::TableError::TableDoesNotExist(_)) => {}
+            Err(e) => return Err(e.into()),
+        }
+        if everything {
+            for item in read_txn.open_table(METADATA_TABLE)?.iter()? {
+                let (key, value) = item?;
+                write_row(&mut out, &SnapshotRow::Metadata(key.value().to_string(), value.value().to_vec()))?;
+            }
+        }
+        match read_txn.open_table(CHANGES_TABLE) {
+            Ok(table) => {
+                summary.change_seq = table.last()?.map(|(k, _)| k.value()).unwrap_or(0);
+                if everything {
+                    for item in table.iter()? {
+                        let (seq, event) = item?;
+                        write_row(&mut out, &SnapshotRow::Change(seq.value(), event.value().to_vec()))?;
+                    }
+                }
+            }
+            Err(redb::TableError::TableDoesNotExist(_)) => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        write_row(&mut out, &SnapshotRow::End)?;
+        out.finish()?.flush()?;
+        Ok(summary)
+    }
+
+    /// Nodes of `node_type`, read one at a time from the type index
+    pub(crate) fn nodes_of_type(&self, node_type: &str) -> Result<impl Iterator<Item = Result<Node>>> {
+        let ids = self.txn.open_multimap_table(NODE_TYPE_INDEX)?.get(node_type)?;
+        let nodes_table = self.txn.open_table(NODES_TABLE)?;
+        Ok(ids.filter_map(move |id| {
+            let read = || -> Result<Option<Node>> {
+                match nodes_table.get(id?.value())? {
+                    Some(data) => Ok(Some(serde_json::from_slice(data.value())?)),
+                    None => Ok(None),
+                }
+            };
+            read().transpose()
+        }))
+    }
+
+    /// Whether a node with `id` exists in this view
+    pub(crate) fn contains_node(&self, id: &NodeId) -> Result<bool> {
+        let nodes_table = self.txn.open_table(NODES_TABLE)?;
+        Ok(nodes_table.get(id.uuid.as_slice())?.is_some())
+    }
+
+    /// Up to `limit` nodes with IDs after `after`, in ID order
+    ///
+    /// Pass the last ID returned to get the next page.
+    pub(crate) fn scan_nodes(&self, after: Option<&NodeId>, limit: usize) -> Result<Vec<Node>> {
+        let nodes_table = self.txn.open_table(NODES_TABLE)?;
+        let start = match after {
+            Some(id) => Bound::Excluded(id.uuid.as_slice()),
+            None => Bound::Unbounded,
+        };
+        let mut nodes = Vec::new();
+        for result in nodes_table.range::<&[u8]>((start, Bound::Unbounded))?.take(limit) {
+            let (_, data) = result?;
+            nodes.push(serde_json::from_slice(data.value())?);
+        }
+        Ok(nodes)
+    }
+
+    /// Up to `limit` edges with IDs after `after`, in ID order
+    pub(crate) fn scan_edges(&self, after: Option<&EdgeId>, limit: usize) -> Result<Vec<Edge>> {
+        let edges_table = self.txn.open_table(EDGES_TABLE)?;
+        let start = match after {
+            Some(id) => Bound::Excluded(id.uuid.as_slice()),
+            None => Bound::Unbounded,
+        };
+        let mut edges = Vec::new();
+        for result in edges_table.range::<&[u8]>((start, Bound::Unbounded))?.take(limit) {
+            let (_, data) = result?;
+            edges.push(serde_json::from_slice(data.value())?);
+        }
+        Ok(edges)
+    }
+}
+
+/// What a snapshot holds, from [`LocalStorage::snapshot`] and the restores
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SnapshotSummary {
+    /// Last change in the snapshot's log, 0 if there is none
+    pub change_seq: u64,
+    /// Nodes in the snapshot
+    pub node_count: u64,
+    /// Edges in the snapshot
+    pub edge_count: u64,
+}
+
+/// A write replayed from a backup
+#[derive(Debug)]
+pub(crate) enum ReplayOp {
+    /// Store the node, replacing any with its ID
+    PutNode(Node),
+    /// Remove the node if present; its edges are removed by their own ops
+    RemoveNode(NodeId),
+    /// Store the edge, replacing any with its ID
+    PutEdge(Edge),
+    /// Remove the edge if present
+    RemoveEdge(EdgeId),
+}
+
+impl LocalStorage {
+    /// Apply writes read back from a backup, in one transaction
+    ///
+    /// Like [`restore_snapshot_data`](Self::restore_snapshot_data), the
+    /// writes bypass the write hook and are not recorded in the change log.
+    pub(crate) async fn replay(&self, ops: Vec<ReplayOp>) -> Result<()> {
+        self.check_writable()?;
+        let db = self.db.write();
+        let write_txn = db.begin_write()?;
+
+        {
+            let mut nodes_table = write_txn.open_table(NODES_TABLE)?;
+            let mut type_index = write_txn.open_multimap_table(NODE_TYPE_INDEX)?;
+            let mut edges_table = write_txn.open_table(EDGES_TABLE)?;
+            let mut from_index = write_txn.open_multimap_table(EDGE_FROM_INDEX)?;
+            let mut to_index = write_txn.open_multimap_table(EDGE_TO_INDEX)?;
+            let mut edge_type_index = write_txn.open_multimap_table(EDGE_TYPE_INDEX)?;
+            // Reverse entries are kept for edges into nodes stored here, as
+            // the sharded write path keeps them on the target's store
+            let mut reverse_table = write_txn.open_table(REVERSE_EDGES_TABLE)?;
+
+            for op in ops {
+                match op {
+                    ReplayOp::PutNode(node) => {
+                        let previous: Option<Node> = nodes_table.get(node.id.uuid.as_slice())?
+                            .map(|data| serde_json::from_slice(data.value()))
+                            .transpose()?;
+                        if let Some(previous) = previous {
+                            type_index.remove(previous.node_type.as_str(), node.id.uuid.as_slice())?;
+                        }
+                        nodes_table.insert(node.id.uuid.as_slice(), serde_json::to_vec(&node)?.as_slice())?;
+                        type_index.insert(node.node_type.as_str(), node.id.uuid.as_slice())?;
+                    }
+                    ReplayOp::RemoveNode(id) => {
+                        let removed: Option<Node> = nodes_table.remove(id.uuid.as_slice())?
+                            .map(|data| serde_json::from_slice(data.value()))
+                            .transpose()?;
+                        if let Some(node) = removed {
+                            type_index.remove(node.node_type.as_str(), id.uuid.as_slice())?;
+                        }
+                        let start = reverse_key(&id, &EdgeId { uuid: [0; 16] });
+                        let end = reverse_key(&id, &EdgeId { uuid: [0xff; 16] });
+                        reverse_table.retain_in(start.as_slice()..=end.as_slice(), |_, _| false)?;
+                    }
+                    ReplayOp::PutEdge(edge) => {
+                        let data = serde_json::to_vec(&edge)?;
+                        let previous: Option<Edge> = edges_table.insert(edge.id.uuid.as_slice(), data.as_slice())?
+                            .map(|data| serde_json::from_slice(data.value()))
+                            .transpose()?;
+                        if let Some(previous) = previous {
+                            from_index.remove(previous.from.uuid.as_slice(), edge.id.uuid.as_slice())?;
+                            to_index.remove(previous.to.uuid.as_slice(), edge.id.uuid.as_slice())?;
+                            edge_type_index.remove(previous.edge_type.as_str(), edge.id.uuid.as_slice())?;
+                            reverse_table.remove(reverse_key(&previous.to, &edge.id).as_slice())?;
+                        }
+                        from_index.insert(edge.from.uuid.as_slice(), edge.id.uuid.as_slice())?;
+                        to_index.insert(edge.to.uuid.as_slice(), edge.id.uuid.as_slice())?;
+                        edge_type_index.insert(edge.edge_type.as_str(), edge.id.uuid.as_slice())?;
+                        if nodes_table.get(edge.to.uuid.as_slice())?.is_some() {
+                            reverse_table.insert(reverse_key(&edge.to, &edge.id).as_slice(), data.as_slice())?;
+                        }
+                    }
+                    ReplayOp::RemoveEdge(id) => {
+                        let removed: Option<Edge> = edges_table.remove(id.uuid.as_slice())?
+                            .map(|data| serde_json::from_slice(data.value()))
+                            .transpose()?;
+                        if let Some(edge) = removed {
+                            from_index.remove(edge.from.uuid.as_slice(), id.uuid.as_slice())?;
+                            to_index.remove(edge.to.uuid.as_slice(), id.uuid.as_slice())?;
+                            edge_type_index.remove(edge.edge_type.as_str(), id.uuid.as_slice())?;
+                            reverse_table.remove(reverse_key(&edge.to, &id).as_slice())?;
+                        }
+                    }
+                }
+            }
+        }
+
+        write_txn.commit()?;
+        Ok(())
+    }
+}
+
+/// Nodes and edges of a snapshot taken by [`LocalStorage::snapshot`]
+pub(crate) fn snapshot_rows(reader: impl Read) -> Result<(Vec<Node>, Vec<Edge>)> {
+    let mut rows = lz4_flex::frame::FrameDecoder::new(reader);
+    let mut nodes = Vec::new();
+    let mut edges = Vec::new();
+    loop {
+        let row: SnapshotRow = bincode::deserialize_from(&mut rows).context("Corrupt snapshot")?;
+        match row {
+            SnapshotRow::Node(bytes) => nodes.push(serde_json::from_slice(&bytes).context("Corrupt snapshot node")?),
+            SnapshotRow::Edge(bytes) => edges.push(serde_json::from_slice(&bytes).context("Corrupt snapshot edge")?),
+            SnapshotRow::End => return Ok((nodes, edges)),
+            _ => {}
+        }
+    }
+}
+
+fn write_row(out: &mut impl Write, row: &SnapshotRow) -> Result<()> {
+    bincode::serialize_into(out, row).context("Failed to write snapshot")
+}
+
+/// One record of a snapshot stream, holding rows as stored
+#[derive(serde::Serialize, serde::Deserialize)]
+enum SnapshotRow {
+    Node(Vec<u8>),
+    Edge(Vec<u8>),
+    /// Reverse-index key and edge
+    ReverseEdge(Vec<u8>, Vec<u8>),
+    Metadata(String, Vec<u8>),
+    /// Sequence and change event
+    Change(u64, Vec<u8>),
+    /// Last record, so a truncated stream is caught
+    End,
 }
 
 /// A database transaction for atomic operations
 pub struct Transaction {
     db: Arc<RwLock<RedbDatabase>>,
+    write_path: WritePath,
     operations: Vec<TransactionOp>,
 }
 
@@ -507,9 +1256,10 @@ enum TransactionOp {
 }
 
 impl Transaction {
-    fn new(db: Arc<RwLock<RedbDatabase>>) -> Result<Self> {
+    fn new(db: Arc<RwLock<RedbDatabase>>, write_path: WritePath) -> Result<Self> {
         Ok(Self {
             db,
+            write_path,
             operations: Vec::new(),
         })
     }
@@ -543,16 +1293,30 @@ impl Transaction {
     pub fn commit(self) -> Result<()> {
         let db = self.db.write();
         let write_txn = db.begin_write()?;
+        let mut changes = Vec::new();
 
         for op in self.operations {
             match op {
-                TransactionOp::InsertNode(node) => {
-                    let node_bytes = serde_json::to_vec(&node)?;
+                TransactionOp::InsertNode(mut node) => {
                     let mut nodes_table = write_txn.open_table(NODES_TABLE)?;
+                    let before: Option<Node> = nodes_table.get(node.id.uuid.as_slice())?
+                        .map(|data| serde_json::from_slice(data.value()))
+                        .transpose()?;
+                    let kind = if before.is_some() { WriteKind::Update } else { WriteKind::Insert };
+                    self.write_path.before_write(kind, &mut node)?;
+
+                    let node_bytes = serde_json::to_vec(&node)?;
                     nodes_table.insert(node.id.uuid.as_slice(), node_bytes.as_slice())?;
 
                     let mut type_index = write_txn.open_multimap_table(NODE_TYPE_INDEX)?;
+                    if let Some(before) = &before {
+                        type_index.remove(before.node_type.as_str(), node.id.uuid.as_slice())?;
+                    }
                     type_index.insert(node.node_type.as_str(), node.id.uuid.as_slice())?;
+                    changes.push(match before {
+                        Some(before) => Change::NodeUpdated { before, after: node },
+                        None => Change::NodeInserted { node },
+                    });
                 }
                 TransactionOp::UpdateNode(id, properties) => {
                     let mut nodes_table = write_txn.open_table(NODES_TABLE)?;
@@ -561,20 +1325,29 @@ impl Transaction {
                             .map(|d| d.value().to_vec())
                     };
                     if let Some(data) = node_data {
-                        let mut node: Node = serde_json::from_slice(&data)?;
+                        let before: Node = serde_json::from_slice(&data)?;
+                        let mut node = before.clone();
                         if let Value::Object(new_props) = properties {
                             for (k, v) in new_props {
                                 node.properties.insert(k, v);
                             }
                         }
                         node.updated_at = Timestamp::now();
+                        self.write_path.before_write(WriteKind::Update, &mut node)?;
                         let node_bytes = serde_json::to_vec(&node)?;
                         nodes_table.insert(id.uuid.as_slice(), node_bytes.as_slice())?;
+                        changes.push(Change::NodeUpdated { before, after: node });
                     }
                 }
                 TransactionOp::DeleteNode(id) => {
                     let mut nodes_table = write_txn.open_table(NODES_TABLE)?;
-                    nodes_table.remove(id.uuid.as_slice())?;
+                    let removed = nodes_table
+                        .remove(id.uuid.as_slice())?
+                        .map(|data| serde_json::from_slice(data.value()))
+                        .transpose()?;
+                    if let Some(node) = removed {
+                        changes.push(Change::NodeDeleted { node });
+                    }
                 }
                 TransactionOp::InsertEdge(edge) => {
                     let edge_bytes = serde_json::to_vec(&edge)?;
@@ -586,15 +1359,27 @@ impl Transaction {
 
                     let mut to_index = write_txn.open_multimap_table(EDGE_TO_INDEX)?;
                     to_index.insert(edge.to.uuid.as_slice(), edge.id.uuid.as_slice())?;
+
+                    let mut type_index = write_txn.open_multimap_table(EDGE_TYPE_INDEX)?;
+                    type_index.insert(edge.edge_type.as_str(), edge.id.uuid.as_slice())?;
+                    changes.push(Change::EdgeInserted { edge });
                 }
                 TransactionOp::DeleteEdge(id) => {
                     let mut edges_table = write_txn.open_table(EDGES_TABLE)?;
-                    edges_table.remove(id.uuid.as_slice())?;
+                    let removed = edges_table
+                        .remove(id.uuid.as_slice())?
+                        .map(|data| serde_json::from_slice(data.value()))
+                        .transpose()?;
+                    if let Some(edge) = removed {
+                        changes.push(Change::EdgeDeleted { edge });
+                    }
                 }
             }
         }
 
+        let events = record_changes(&write_txn, changes)?;
         write_txn.commit()?;
+        self.write_path.publish(events);
         Ok(())
     }
 
@@ -701,6 +1486,129 @@ mod tests {
         let nodes = storage.get_nodes_by_type("user", None).await.unwrap();
         assert_eq!(nodes.len(), 2);
     }
-}
 
+    #[tokio::test]
+    async fn test_snapshot_restore_replaces_contents() {
+        let source_dir = TempDir::new().unwrap();
+        let target_dir = TempDir::new().unwrap();
+        let source = LocalStorage::create(source_dir.path()).await.unwrap();
+        let target = LocalStorage::create(target_dir.path()).await.unwrap();
+
+        let a = Node::new("user", Value::from_json(serde_json::json!({"name": "a"})).unwrap());
+        let b = Node::new("user", Value::from_json(serde_json::json!({"name": "b"})).unwrap());
+        source.insert_node(&a).await.unwrap();
+        source.insert_node(&b).await.unwrap();
+        let edge = Edge::new(a.id.clone(), b.id.clone(), "knows", Value::Null);
+        source.insert_edge(&edge).await.unwrap();
+
+        let stale = Node::new("stale", Value::Null);
+        target.insert_node(&stale).await.unwrap();
+
+        let mut snapshot = Vec::new();
+        source.snapshot(&mut snapshot).await.unwrap();
+        target.restore_snapshot(snapshot.as_slice()).await.unwrap();
+
+        assert!(target.get_node(&stale.id).await.unwrap().is_none());
+        assert!(target.get_nodes_by_type("stale", None).await.unwrap().is_empty());
+        assert_eq!(target.get_nodes_by_type("user", None).await.unwrap().len(), 2);
+        assert_eq!(target.get_edges_from(&a.id, Some("knows")).await.unwrap().len(), 1);
+        assert_eq!(target.get_edges_to(&b.id, None).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_carries_reverse_edges_metadata_and_changes() {
+        let source_dir = TempDir::new().unwrap();
+        let target_dir = TempDir::new().unwrap();
+        let source = LocalStorage::create(source_dir.path()).await.unwrap();
+        let target = LocalStorage::create(target_dir.path()).await.unwrap();
+
+        let a = Node::new("user", Value::Null);
+        source.insert_node(&a).await.unwrap();
+        let remote = Edge::new(NodeId::new(), a.id.clone(), "follows", Value::Null);
+        source.insert_reverse_edge(&remote).await.unwrap();
+        source.put_metadata("cursor", b"7").unwrap();
+
+        // The target already saw the first change
+        target.insert_node(&a).await.unwrap();
+        let b = Node::new("user", Value::Null);
+        source.insert_node(&b).await.unwrap();
+
+        let mut live = target.subscribe_changes();
+        let mut snapshot = Vec::new();
+        let taken = source.snapshot(&mut snapshot).await.unwrap();
+        let restored = target.restore_snapshot(snapshot.as_slice()).await.unwrap();
+        assert_eq!(taken, restored);
+        assert_eq!(restored.change_seq, 2);
+
+        assert_eq!(target.get_reverse_edges(&a.id, None).await.unwrap().len(), 1);
+        assert_eq!(target.get_metadata("cursor").unwrap().as_deref(), Some(b"7".as_slice()));
+        assert_eq!(target.last_change_seq().unwrap(), 2);
+        assert_eq!(target.changes_after(0, 10).unwrap().len(), 2);
+
+        // Only the change the target had not made is published
+        let event = live.try_recv().unwrap();
+        assert_eq!(event.seq, 2);
+        assert!(matches!(event.change, Change::NodeInserted { ref node } if node.id == b.id));
+        assert!(live.try_recv().is_err());
+
+        // Numbering carries on from the snapshot
+        let c = Node::new("user", Value::Null);
+        target.insert_node(&c).await.unwrap();
+        assert_eq!(target.last_change_seq().unwrap(), 3);
+
+        // A truncated stream is rejected and leaves the target as it was
+        let cut = &snapshot[..snapshot.len() / 2];
+        assert!(target.restore_snapshot(cut).await.is_err());
+        assert!(target.get_node(&c.id).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_replay_maintains_reverse_edges() {
+        let temp = TempDir::new().unwrap();
+        let storage = LocalStorage::create(temp.path()).await.unwrap();
+
+        let a = Node::new("user", Value::Null);
+        let b = Node::new("user", Value::Null);
+        let edge = Edge::new(a.id.clone(), b.id.clone(), "knows", Value::Null);
+        // Into a node held by another store
+        let outgoing = Edge::new(a.id.clone(), NodeId::new(), "knows", Value::Null);
+        storage.replay(vec![
+            ReplayOp::PutNode(a.clone()),
+            ReplayOp::PutNode(b.clone()),
+            ReplayOp::PutEdge(edge.clone()),
+            ReplayOp::PutEdge(outgoing.clone()),
+        ]).await.unwrap();
+        assert_eq!(storage.get_reverse_edges(&b.id, None).await.unwrap().len(), 1);
+        assert!(storage.get_reverse_edges(&outgoing.to, None).await.unwrap().is_empty());
+
+        storage.replay(vec![ReplayOp::RemoveEdge(edge.id.clone())]).await.unwrap();
+        assert!(storage.get_reverse_edges(&b.id, None).await.unwrap().is_empty());
+
+        // Entries of a removed node go with it, whichever store holds the edge
+        let remote = Edge::new(NodeId::new(), b.id.clone(), "follows", Value::Null);
+        storage.insert_reverse_edge(&remote).await.unwrap();
+        storage.replay(vec![ReplayOp::PutEdge(edge), ReplayOp::RemoveNode(b.id.clone())]).await.unwrap();
+        assert!(storage.get_reverse_edges(&b.id, None).await.unwrap().is_empty());
+    }
 
+    #[tokio::test]
+    async fn test_read_view_is_point_in_time() {
+        let temp = TempDir::new().unwrap();
+        let storage = LocalStorage::create(temp.path()).await.unwrap();
+        let a = Node::new("user", Value::Object(Default::default()));
+        storage.insert_node(&a).await.unwrap();
+
+        let view = storage.read_view().unwrap();
+        let b = Node::new("user", Value::Object(Default::default()));
+        storage.insert_node(&b).await.unwrap();
+        storage.insert_edge(&Edge::new(a.id.clone(), b.id.clone(), "knows", Value::Object(Default::default())))
+            .await.unwrap();
+
+        // Pages read after the writes still show the database as it was
+        let first = view.scan_nodes(None, 1).unwrap();
+        assert_eq!(first.len(), 1);
+        assert!(view.scan_nodes(Some(&first[0].id), 1).unwrap().is_empty());
+        assert!(view.scan_edges(None, 10).unwrap().is_empty());
+        assert_eq!(storage.read_view().unwrap().scan_nodes(None, 10).unwrap().len(), 2);
+    }
+}
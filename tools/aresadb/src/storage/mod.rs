@@ -4,16 +4,30 @@
 
 mod node;
 mod local;
+mod changes;
+mod hooks;
+mod backup;
 mod bucket;
 mod cache;
+mod dump;
 mod parallel;
+mod segment;
+mod remote;
 pub mod vector;
 pub mod vector_index;
 
 pub use node::{Node, Edge, NodeId, EdgeId, Value, Timestamp, DistanceMetric, SimilarityResult};
-pub use local::LocalStorage;
+pub use local::{LocalStorage, SnapshotSummary};
+pub(crate) use node::is_reserved_type;
+pub(crate) use local::ReadView;
+pub use changes::{Change, ChangeEvent, ChangeFilter, ChangeStream, CHANGE_RETENTION};
+pub use hooks::{WriteHook, WriteKind};
+pub use backup::{BackupStore, BackupCatalog, BackupEntry, BackupKind, RestorePoint, RestoreReport, VerifyReport};
 pub use bucket::BucketStorage;
-pub use cache::CacheLayer;
+pub use dump::{DumpFormat, Exporter, ExportReport, Importer, ImportReport};
+pub use cache::{CacheLayer, CacheStats};
+pub use remote::RemoteBackend;
+pub use segment::{Manifest, FileEntry, SegmentRef, SyncConflict, SyncState, DEFAULT_SEGMENT_SIZE};
 pub use parallel::{ParallelExecutor, ParallelTraversalResult, SnapshotReader};
 pub use vector::{VectorSearch, VectorNodeBuilder};
 pub use vector_index::{VectorIndex, IndexStats};
@@ -47,8 +61,16 @@ pub struct DatabaseStatus {
 /// Sync statistics
 #[derive(Debug, Clone, Default)]
 pub struct SyncStats {
+    /// Segments uploaded
     pub uploaded: u64,
+    /// Segments downloaded
     pub downloaded: u64,
+    /// Compressed bytes uploaded
+    pub bytes_uploaded: u64,
+    /// Bytes downloaded (uncompressed)
+    pub bytes_downloaded: u64,
+    /// Generation the local copy is at after the sync
+    pub generation: u64,
 }
 
 /// Graph representation for visualization
@@ -65,6 +87,10 @@ pub struct KvView {
 }
 
 /// Main database handle
+///
+/// Cloning is cheap and gives another handle onto the same storage, for
+/// components such as a query engine that need one of their own.
+#[derive(Clone)]
 pub struct Database {
     /// Path to the database
     path: PathBuf,
@@ -72,10 +98,12 @@ pub struct Database {
     config: Arc<RwLock<DatabaseConfig>>,
     /// Local storage backend
     local: LocalStorage,
-    /// Optional bucket storage backend
-    bucket: Option<BucketStorage>,
     /// Cache layer for remote storage
-    cache: CacheLayer,
+    cache: Arc<CacheLayer>,
+    /// Manifest being served when connected directly to a bucket
+    remote: Option<Manifest>,
+    /// Reject all mutations
+    readonly: bool,
 }
 
 impl Database {
@@ -103,14 +131,16 @@ impl Database {
 
         // Initialize local storage
         let local = LocalStorage::create(&path).await?;
+        crate::schema::TriggerSet::install(&local).await?;
         let cache = CacheLayer::new(1024 * 1024 * 100); // 100MB cache
 
         Ok(Self {
             path,
             config: Arc::new(RwLock::new(config)),
             local,
-            bucket: None,
-            cache,
+            cache: Arc::new(cache),
+            remote: None,
+            readonly: false,
         })
     }
 
@@ -124,59 +154,97 @@ impl Database {
             .context("Failed to read database config. Is this an aresadb database?")?;
         let config: DatabaseConfig = toml::from_str(&config_str)?;
 
-        // Open local storage
+        // Open local storage, running its triggers on every write
         let local = LocalStorage::open(&path).await?;
+        crate::schema::TriggerSet::install(&local).await?;
         let cache = CacheLayer::new(1024 * 1024 * 100);
 
-        // Connect to bucket if configured
-        let bucket = if let Some(ref url) = config.bucket_url {
-            Some(BucketStorage::connect(url).await?)
-        } else {
-            None
-        };
-
         Ok(Self {
             path,
             config: Arc::new(RwLock::new(config)),
             local,
-            bucket,
-            cache,
+            cache: Arc::new(cache),
+            remote: None,
+            readonly: false,
         })
     }
 
     /// Connect to a remote bucket database
+    ///
+    /// Serves the latest published generation without downloading it:
+    /// pages are fetched segment by segment as queries touch them and kept
+    /// in the cache layer. When not readonly, writes are accepted but stay
+    /// local to this session; clone with `sync` to publish changes.
     pub async fn connect_bucket(url: &str, readonly: bool) -> Result<Self> {
-        let bucket = BucketStorage::connect(url).await?;
+        let mut bucket = BucketStorage::connect(url).await?;
+        bucket.set_readonly(readonly);
+        let bucket = Arc::new(bucket);
+
+        let manifest = bucket.latest_manifest().await?
+            .ok_or_else(|| anyhow::anyhow!("No database found at {}", url))?;
         let config = bucket.load_config().await?;
+        let data = manifest.files.get(".aresadb/data.redb")
+            .ok_or_else(|| anyhow::anyhow!("Manifest {} has no data file", manifest.generation))?;
 
-        // Create temporary local cache
+        let cache = Arc::new(CacheLayer::new(1024 * 1024 * 500)); // 500MB cache for remote
+        let backend = RemoteBackend::new(bucket.clone(), cache.clone(), data, manifest.segment_size)?;
+
+        // Scratch directory for anything that needs a path; data stays remote
         let temp_path = std::env::temp_dir().join(format!("aresadb-{}", uuid::Uuid::new_v4()));
         std::fs::create_dir_all(&temp_path)?;
 
-        let local = LocalStorage::create(&temp_path).await?;
-        let cache = CacheLayer::new(1024 * 1024 * 500); // 500MB cache for remote
+        let local = LocalStorage::with_backend(&temp_path, backend, readonly)?;
+        if !readonly {
+            crate::schema::TriggerSet::install(&local).await?;
+        }
 
         Ok(Self {
             path: temp_path,
             config: Arc::new(RwLock::new(config)),
             local,
-            bucket: Some(bucket),
             cache,
+            remote: Some(manifest),
+            readonly,
         })
     }
 
+    /// Check if the database rejects mutations
+    pub fn is_readonly(&self) -> bool {
+        self.readonly
+    }
+
+    /// Generation being served when connected directly to a bucket
+    pub fn remote_generation(&self) -> Option<u64> {
+        self.remote.as_ref().map(|m| m.generation)
+    }
+
+    /// Get the cache layer
+    pub fn cache(&self) -> &CacheLayer {
+        &self.cache
+    }
+
+    /// Fail if the database is readonly
+    fn ensure_writable(&self) -> Result<()> {
+        if self.readonly {
+            anyhow::bail!("Database is readonly");
+        }
+        Ok(())
+    }
+
     /// Get database status
     pub async fn status(&self) -> Result<DatabaseStatus> {
-        let config = self.config.read();
         let stats = self.local.stats().await?;
+        let name = self.config.read().name.clone();
 
         Ok(DatabaseStatus {
-            name: config.name.clone(),
+            name,
             path: self.path.display().to_string(),
             node_count: stats.node_count,
             edge_count: stats.edge_count,
             schema_count: stats.schema_count,
-            size_bytes: stats.size_bytes,
+            size_bytes: self.remote.as_ref()
+                .map(|m| m.total_bytes())
+                .unwrap_or(stats.size_bytes),
         })
     }
 
@@ -184,10 +252,10 @@ impl Database {
 
     /// Insert a new node
     pub async fn insert_node(&self, node_type: &str, properties: serde_json::Value) -> Result<Node> {
+        self.ensure_writable()?;
         let props = Value::from_json(properties)?;
         let node = Node::new(node_type, props);
-        self.local.insert_node(&node).await?;
-        Ok(node)
+        self.local.insert_node(&node).await
     }
 
     /// Get a node by ID
@@ -198,6 +266,7 @@ impl Database {
 
     /// Update a node's properties
     pub async fn update_node(&self, id: &str, properties: serde_json::Value) -> Result<Node> {
+        self.ensure_writable()?;
         let node_id = NodeId::parse(id)?;
         let props = Value::from_json(properties)?;
         self.local.update_node(&node_id, props).await
@@ -205,6 +274,7 @@ impl Database {
 
     /// Delete a node and its edges
     pub async fn delete_node(&self, id: &str) -> Result<()> {
+        self.ensure_writable()?;
         let node_id = NodeId::parse(id)?;
         self.local.delete_node(&node_id).await
     }
@@ -224,6 +294,7 @@ impl Database {
         edge_type: &str,
         properties: Option<serde_json::Value>,
     ) -> Result<Edge> {
+        self.ensure_writable()?;
         let from = NodeId::parse(from_id)?;
         let to = NodeId::parse(to_id)?;
         let props = properties
@@ -250,10 +321,28 @@ impl Database {
 
     /// Delete an edge
     pub async fn delete_edge(&self, edge_id: &str) -> Result<()> {
+        self.ensure_writable()?;
         let id = EdgeId::parse(edge_id)?;
         self.local.delete_edge(&id).await
     }
 
+    // ========== Change Data Capture ==========
+
+    /// Stream committed changes to nodes and edges
+    ///
+    /// Replays the retained log after sequence `after` and then follows
+    /// changes as they commit; with `after` set to `None` only changes
+    /// still to come are sent. Fails if the changes after `after` have
+    /// already been pruned.
+    pub fn changes(&self, after: Option<u64>, filter: ChangeFilter) -> Result<ChangeStream> {
+        self.local.changes(after, filter)
+    }
+
+    /// Sequence of the latest change, 0 if there has been none
+    pub fn last_change_seq(&self) -> Result<u64> {
+        self.local.last_change_seq()
+    }
+
     // ========== View Operations ==========
 
     /// Get data as a graph view
@@ -284,25 +373,28 @@ impl Database {
 
     /// Push database to a cloud bucket
     pub async fn push_to_bucket(&self, url: &str) -> Result<()> {
+        self.ensure_writable()?;
+        if self.remote.is_some() {
+            anyhow::bail!("Cannot push a database served from a bucket; sync it to a local path first");
+        }
         let bucket = BucketStorage::connect(url).await?;
 
-        // Save config
-        let config = self.config.read().clone();
-        bucket.save_config(&config).await?;
-
-        // Upload data files
-        bucket.upload_from_local(&self.path).await?;
-
-        // Update local config with bucket URL
-        drop(config);
+        // Record the bucket URL first so the pushed config matches the local one
         self.config.write().bucket_url = Some(url.to_string());
         self.save_config()?;
 
+        // Upload changed segments and publish a new generation
+        bucket.upload_from_local(&self.path).await?;
+
         Ok(())
     }
 
     /// Sync local database with remote bucket
     pub async fn sync_with_bucket(&self, url: &str) -> Result<SyncStats> {
+        self.ensure_writable()?;
+        if self.remote.is_some() {
+            anyhow::bail!("Cannot push a database served from a bucket; sync it to a local path first");
+        }
         let bucket = BucketStorage::connect(url).await?;
 
         // Bidirectional sync
@@ -313,12 +405,18 @@ impl Database {
 
     /// Save config to disk
     fn save_config(&self) -> Result<()> {
+        self.ensure_writable()?;
         let config = self.config.read();
         let config_str = toml::to_string_pretty(&*config)?;
         std::fs::write(self.path.join(".aresadb/config.toml"), config_str)?;
         Ok(())
     }
 
+    /// Wait for writes in progress and make everything committed durable
+    pub async fn flush(&self) -> Result<()> {
+        self.local.flush().await
+    }
+
     /// Get the local storage handle
     pub fn local(&self) -> &LocalStorage {
         &self.local
@@ -344,6 +442,7 @@ impl Database {
         embedding_field: &str,
         embedding: Vec<f32>,
     ) -> Result<Node> {
+        self.ensure_writable()?;
         let mut props = Value::from_json(properties)?;
 
         // Add embedding to properties
@@ -352,8 +451,7 @@ impl Database {
         }
 
         let node = Node::new(node_type, props);
-        self.local.insert_node(&node).await?;
-        Ok(node)
+        self.local.insert_node(&node).await
     }
 
     /// Perform similarity search on vector embeddings
@@ -438,6 +536,71 @@ mod tests {
         let retrieved = retrieved.unwrap();
         assert_eq!(retrieved.node_type, "user");
     }
-}
 
+    #[tokio::test]
+    async fn test_connect_bucket_serves_lazily() {
+        let temp = TempDir::new().unwrap();
+        let remote = TempDir::new().unwrap();
+        let url = format!("file://{}", remote.path().display());
+
+        let db = Database::create(temp.path(), "remotedb").await.unwrap();
+        let mut ids = Vec::new();
+        for i in 0..200 {
+            let node = db.insert_node("item", serde_json::json!({ "n": i, "pad": "x".repeat(200) }))
+                .await
+                .unwrap();
+            ids.push(node.id.to_string());
+        }
+        db.create_edge(&ids[0], &ids[1], "next", None).await.unwrap();
+        BucketStorage::connect(&url).await.unwrap()
+            .with_segment_size(4096)
+            .upload_from_local(temp.path())
+            .await
+            .unwrap();
+
+        let remote_db = Database::connect_bucket(&url, true).await.unwrap();
+        assert!(remote_db.is_readonly());
+        assert_eq!(remote_db.remote_generation(), Some(1));
+        assert_eq!(remote_db.name(), "remotedb");
+
+        let node = remote_db.get_node(&ids[42]).await.unwrap().unwrap();
+        assert_eq!(node.properties.get("n").and_then(|v| v.as_int()), Some(42));
+        assert_eq!(remote_db.get_edges_from(&ids[0], None).await.unwrap().len(), 1);
+
+        // Only the touched segments were fetched
+        let manifest_bytes = remote_db.status().await.unwrap().size_bytes;
+        assert!(remote_db.cache().size() > 0);
+        assert!(remote_db.cache().size() < manifest_bytes);
+    }
 
+    #[tokio::test]
+    async fn test_readonly_rejects_mutations() {
+        let temp = TempDir::new().unwrap();
+        let remote = TempDir::new().unwrap();
+        let url = format!("file://{}", remote.path().display());
+
+        let db = Database::create(temp.path(), "remotedb").await.unwrap();
+        let a = db.insert_node("item", serde_json::json!({ "n": 1 })).await.unwrap().id.to_string();
+        let b = db.insert_node("item", serde_json::json!({ "n": 2 })).await.unwrap().id.to_string();
+        let edge = db.create_edge(&a, &b, "next", None).await.unwrap().id.to_string();
+        db.push_to_bucket(&url).await.unwrap();
+
+        let remote_db = Database::connect_bucket(&url, true).await.unwrap();
+        assert!(remote_db.insert_node("item", serde_json::json!({})).await.is_err());
+        assert!(remote_db.update_node(&a, serde_json::json!({ "n": 3 })).await.is_err());
+        assert!(remote_db.delete_node(&a).await.is_err());
+        assert!(remote_db.create_edge(&a, &b, "next", None).await.is_err());
+        assert!(remote_db.delete_edge(&edge).await.is_err());
+        assert!(remote_db.insert_with_embedding("item", serde_json::json!({}), "e", vec![1.0]).await.is_err());
+        assert!(remote_db.local().begin_transaction().is_err());
+        assert!(remote_db.push_to_bucket(&url).await.is_err());
+        assert_eq!(remote_db.get_all_by_type("item", None).await.unwrap().len(), 2);
+
+        // Writable sessions keep their changes local
+        let session = Database::connect_bucket(&url, false).await.unwrap();
+        session.insert_node("item", serde_json::json!({ "n": 3 })).await.unwrap();
+        assert_eq!(session.get_all_by_type("item", None).await.unwrap().len(), 3);
+        let fresh = Database::connect_bucket(&url, true).await.unwrap();
+        assert_eq!(fresh.get_all_by_type("item", None).await.unwrap().len(), 2);
+    }
+}
@@ -215,8 +215,12 @@ impl<'de> SerdeDeserialize<'de> for Timestamp {
 /// Note: We use serde for serialization instead of rkyv for the Value type
 /// because rkyv has issues with recursive types. The performance impact is
 /// minimal since we batch serialize nodes/edges anyway.
-#[derive(Debug, Clone, PartialEq, SerdeSerialize, SerdeDeserialize)]
-#[serde(untagged)]
+///
+/// Human-readable formats (JSON) see plain untagged values. Binary formats
+/// such as bincode cannot tell untagged variants apart, so they get the
+/// variant tag first; the variant order below is therefore part of the wire
+/// protocol and the WAL format and must only ever be appended to.
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Null,
     Bool(bool),
@@ -230,8 +234,120 @@ pub enum Value {
     Object(BTreeMap<String, Value>),
 }
 
+/// Borrowed mirror of [`Value`] for serializing either representation
+#[derive(SerdeSerialize)]
+enum ValueRef<'a> {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(&'a str),
+    Bytes(&'a [u8]),
+    Vector(&'a [f32]),
+    Array(&'a [Value]),
+    Object(&'a BTreeMap<String, Value>),
+}
+
+/// Untagged mirror of [`Value`], as it appears in JSON
+#[derive(SerdeSerialize)]
+#[serde(untagged)]
+enum UntaggedRef<'a> {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(&'a str),
+    Bytes(&'a [u8]),
+    Vector(&'a [f32]),
+    Array(&'a [Value]),
+    Object(&'a BTreeMap<String, Value>),
+}
+
+/// Owned mirror of [`Value`] for binary formats
+#[derive(SerdeDeserialize)]
+enum Tagged {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    Vector(Vec<f32>),
+    Array(Vec<Value>),
+    Object(BTreeMap<String, Value>),
+}
+
+/// Owned untagged mirror of [`Value`] for human-readable formats
+#[derive(SerdeDeserialize)]
+#[serde(untagged)]
+enum Untagged {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    Vector(Vec<f32>),
+    Array(Vec<Value>),
+    Object(BTreeMap<String, Value>),
+}
+
+macro_rules! convert_value {
+    ($from:ident, $to:ident, $value:expr) => {
+        match $value {
+            $from::Null => $to::Null,
+            $from::Bool(b) => $to::Bool(b),
+            $from::Int(i) => $to::Int(i),
+            $from::Float(f) => $to::Float(f),
+            $from::String(s) => $to::String(s),
+            $from::Bytes(b) => $to::Bytes(b),
+            $from::Vector(v) => $to::Vector(v),
+            $from::Array(a) => $to::Array(a),
+            $from::Object(o) => $to::Object(o),
+        }
+    };
+}
+
+impl SerdeSerialize for Value {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let value = match self {
+            Value::Null => ValueRef::Null,
+            Value::Bool(b) => ValueRef::Bool(*b),
+            Value::Int(i) => ValueRef::Int(*i),
+            Value::Float(f) => ValueRef::Float(*f),
+            Value::String(s) => ValueRef::String(s),
+            Value::Bytes(b) => ValueRef::Bytes(b),
+            Value::Vector(v) => ValueRef::Vector(v),
+            Value::Array(a) => ValueRef::Array(a),
+            Value::Object(o) => ValueRef::Object(o),
+        };
+
+        if serializer.is_human_readable() {
+            convert_value!(ValueRef, UntaggedRef, value).serialize(serializer)
+        } else {
+            value.serialize(serializer)
+        }
+    }
+}
+
+impl<'de> SerdeDeserialize<'de> for Value {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        if deserializer.is_human_readable() {
+            <Untagged as SerdeDeserialize>::deserialize(deserializer).map(|v| convert_value!(Untagged, Value, v))
+        } else {
+            <Tagged as SerdeDeserialize>::deserialize(deserializer).map(|v| convert_value!(Tagged, Value, v))
+        }
+    }
+}
+
 /// Distance metrics for vector similarity search
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SerdeSerialize, SerdeDeserialize)]
 pub enum DistanceMetric {
     /// Cosine similarity (1 - cosine_distance)
     Cosine,
@@ -532,9 +648,31 @@ impl fmt::Display for Value {
 }
 
 // Implement base64 encoding helper
-mod base64 {
+pub(crate) mod base64 {
+    use anyhow::{bail, Result};
+
     const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
 
+    pub fn decode(text: &str) -> Result<Vec<u8>> {
+        let text = text.trim_end_matches('=');
+        let mut result = Vec::with_capacity(text.len() * 3 / 4);
+        let mut buffer = 0u32;
+        let mut bits = 0;
+        for c in text.bytes() {
+            let Some(index) = ALPHABET.iter().position(|&a| a == c) else {
+                bail!("Invalid base64 character '{}'", c as char);
+            };
+            buffer = (buffer << 6) | index as u32;
+            bits += 6;
+            if bits >= 8 {
+                bits -= 8;
+                result.push((buffer >> bits) as u8);
+                buffer &= (1 << bits) - 1;
+            }
+        }
+        Ok(result)
+    }
+
     pub fn encode(data: &[u8]) -> String {
         let mut result = String::new();
         for chunk in data.chunks(3) {
@@ -561,6 +699,15 @@ mod base64 {
     }
 }
 
+/// Whether nodes of this type hold internal state rather than user data
+///
+/// Every `__`-prefixed type holds internal state (accounts, roles, the
+/// audit log, schemas, triggers and dead letters), written only by the
+/// statements that manage it; requests may not touch them.
+pub(crate) fn is_reserved_type(node_type: &str) -> bool {
+    node_type.starts_with("__")
+}
+
 /// A node in the property graph
 #[derive(Debug, Clone, SerdeSerialize, SerdeDeserialize)]
 pub struct Node {
@@ -772,6 +919,28 @@ mod tests {
         assert_eq!(v, parsed);
     }
 
+    #[test]
+    fn test_value_binary_roundtrip() {
+        let mut value = Value::from_json(serde_json::json!({
+            "name": "Ada",
+            "age": 36,
+            "score": 9.5,
+            "tags": ["math", null, true],
+        })).unwrap();
+        if let Value::Object(ref mut map) = value {
+            map.insert("embedding".into(), Value::vector(vec![0.5, 1.5]));
+            map.insert("raw".into(), Value::Bytes(vec![1, 2, 3]));
+        }
+
+        // Binary formats carry the variant, so nothing is reinterpreted
+        let bytes = bincode::serialize(&value).unwrap();
+        assert_eq!(bincode::deserialize::<Value>(&bytes).unwrap(), value);
+
+        // JSON is unchanged: plain values without tags
+        let json = serde_json::to_string(&Value::Array(vec![Value::Int(1), Value::String("a".into())])).unwrap();
+        assert_eq!(json, r#"[1,"a"]"#);
+    }
+
     #[test]
     fn test_cosine_similarity() {
         // Identical vectors should have similarity 1.0
@@ -165,16 +165,15 @@ impl ParallelExecutor {
         let handles: Vec<_> = batches.into_iter().map(|batch| {
             let results = Arc::clone(&results);
             let batch = batch.to_vec();
-            let storage_path = storage.path().to_path_buf();
+            // Share the open handle; redb refuses to open a file twice
+            let storage = storage.clone();
 
             tokio::spawn(async move {
-                let storage = LocalStorage::open(&storage_path).await?;
                 let mut local_results = Vec::new();
 
                 for (node_type, props) in batch {
                     let node = Node::new(&node_type, props);
-                    storage.insert_node(&node).await?;
-                    local_results.push(node);
+                    local_results.push(storage.insert_node(&node).await?);
                 }
 
                 results.write().extend(local_results);
@@ -193,6 +192,39 @@ impl ParallelExecutor {
         Ok(final_results)
     }
 
+    /// Parallel bulk insert of complete nodes, keeping their IDs and timestamps
+    ///
+    /// Each worker commits its share in one transaction, so a call stores
+    /// few large transactions rather than one per node. Returns the number
+    /// of nodes stored.
+    pub async fn parallel_insert_nodes(
+        &self,
+        storage: &LocalStorage,
+        nodes: Vec<Node>,
+    ) -> Result<usize> {
+        let count = nodes.len();
+        let batch_size = (count / self.num_threads).max(1);
+
+        let handles: Vec<_> = nodes.chunks(batch_size).map(|batch| {
+            let batch = batch.to_vec();
+            let storage = storage.clone();
+
+            tokio::spawn(async move {
+                let mut txn = storage.begin_transaction()?;
+                for node in batch {
+                    txn.insert_node(node);
+                }
+                txn.commit()
+            })
+        }).collect();
+
+        for handle in handles {
+            handle.await??;
+        }
+
+        Ok(count)
+    }
+
     /// Parallel query execution across multiple tables
     pub async fn parallel_query(
         &self,
@@ -0,0 +1,185 @@
+//! Lazily fetched storage backend for remote databases
+//!
+//! Serves redb page reads from the segments of a bucket manifest, fetching
+//! each segment on first touch through the shared [`CacheLayer`]. Writes
+//! (redb updates its header even when only reading) land in an in-memory
+//! overlay and are never uploaded.
+
+use anyhow::Result;
+use bytes::Bytes;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use super::bucket::BucketStorage;
+use super::cache::CacheLayer;
+use super::segment::{FileEntry, SegmentRef};
+
+/// redb storage backend reading segments from a bucket on demand
+pub struct RemoteBackend {
+    bucket: Arc<BucketStorage>,
+    cache: Arc<CacheLayer>,
+    segments: Vec<SegmentRef>,
+    segment_size: u64,
+    remote_len: u64,
+    len: AtomicU64,
+    /// Segments modified locally, keyed by segment index
+    overlay: Mutex<HashMap<u64, Vec<u8>>>,
+    /// Runtime used to drive fetches from redb's synchronous calls
+    runtime: Option<tokio::runtime::Runtime>,
+}
+
+impl RemoteBackend {
+    /// Create a backend for one file of a manifest
+    pub fn new(
+        bucket: Arc<BucketStorage>,
+        cache: Arc<CacheLayer>,
+        file: &FileEntry,
+        segment_size: u64,
+    ) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .thread_name("aresadb-remote")
+            .enable_all()
+            .build()?;
+
+        Ok(Self {
+            bucket,
+            cache,
+            segments: file.segments.clone(),
+            segment_size,
+            remote_len: file.len,
+            len: AtomicU64::new(file.len),
+            overlay: Mutex::new(HashMap::new()),
+            runtime: Some(runtime),
+        })
+    }
+
+    /// Contents of one segment, from the overlay, the cache or the bucket
+    fn segment(&self, index: u64) -> io::Result<Bytes> {
+        if let Some(data) = self.overlay.lock().get(&index) {
+            return Ok(Bytes::copy_from_slice(data));
+        }
+
+        let Some(segment) = self.segments.get(index as usize).cloned() else {
+            // Beyond the remote file: zero-filled until written
+            return Ok(Bytes::from(vec![0u8; self.segment_size as usize]));
+        };
+
+        let bucket = self.bucket.clone();
+        let cache = self.cache.clone();
+        let key = format!("segment:{}", segment.hash);
+
+        // redb calls us synchronously, often from inside an async task, so
+        // run the fetch on our own runtime and wait for it on this thread.
+        let (tx, rx) = std::sync::mpsc::channel();
+        self.runtime.as_ref().expect("runtime present until drop").spawn(async move {
+            let result = cache.get_or_fetch(&key, || async {
+                bucket.fetch_segment(&segment).await.map(Bytes::from)
+            }).await;
+            let _ = tx.send(result);
+        });
+
+        rx.recv()
+            .map_err(|_| io::Error::other("segment fetch was cancelled"))?
+            .map_err(|e| io::Error::other(format!("{:#}", e)))
+    }
+}
+
+impl std::fmt::Debug for RemoteBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RemoteBackend")
+            .field("url", &self.bucket.url())
+            .field("len", &self.len.load(Ordering::Relaxed))
+            .field("segments", &self.segments.len())
+            .finish()
+    }
+}
+
+impl Drop for RemoteBackend {
+    fn drop(&mut self) {
+        // Dropping a runtime blocks, which is not allowed inside async code
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+impl redb::StorageBackend for RemoteBackend {
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.len.load(Ordering::Acquire))
+    }
+
+    fn read(&self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        if offset + len as u64 > self.len.load(Ordering::Acquire) {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "read past end of remote file"));
+        }
+
+        let mut out = Vec::with_capacity(len);
+        let mut pos = offset;
+        let end = offset + len as u64;
+        while pos < end {
+            let index = pos / self.segment_size;
+            let start = (pos % self.segment_size) as usize;
+            let data = self.segment(index)?;
+            let take = ((end - pos) as usize).min(self.segment_size as usize - start);
+            let available = data.len().saturating_sub(start).min(take);
+            out.extend_from_slice(&data[start..start + available]);
+            // A short final segment reads as zeros past its end
+            out.resize(out.len() + (take - available), 0);
+            pos += take as u64;
+        }
+        Ok(out)
+    }
+
+    fn set_len(&self, len: u64) -> io::Result<()> {
+        let old = self.len.swap(len, Ordering::AcqRel);
+        if len < old {
+            let mut overlay = self.overlay.lock();
+            let last = len.div_ceil(self.segment_size);
+            overlay.retain(|&index, _| index < last);
+            // Zero the tail of a partially truncated segment
+            if let Some(data) = overlay.get_mut(&(len / self.segment_size)) {
+                let keep = (len % self.segment_size) as usize;
+                if keep < data.len() {
+                    data[keep..].fill(0);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn sync_data(&self, _eventual: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write(&self, offset: u64, data: &[u8]) -> io::Result<()> {
+        let mut pos = offset;
+        let mut written = 0usize;
+        while written < data.len() {
+            let index = pos / self.segment_size;
+            let start = (pos % self.segment_size) as usize;
+            let take = (data.len() - written).min(self.segment_size as usize - start);
+
+            if !self.overlay.lock().contains_key(&index) {
+                let mut base = if index * self.segment_size < self.remote_len {
+                    self.segment(index)?.to_vec()
+                } else {
+                    Vec::new()
+                };
+                base.resize(self.segment_size as usize, 0);
+                self.overlay.lock().entry(index).or_insert(base);
+            }
+
+            let mut overlay = self.overlay.lock();
+            let segment = overlay.get_mut(&index).expect("segment materialized");
+            segment[start..start + take].copy_from_slice(&data[written..written + take]);
+
+            written += take;
+            pos += take as u64;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,302 @@
+//! Content-addressed segments and manifests for bucket persistence
+//!
+//! Database files are split into fixed-size segments named by the hash of
+//! their contents. A manifest maps every file to its ordered segment list
+//! and is published under a monotonically increasing generation number, so
+//! a bucket only ever gains immutable objects and readers always observe a
+//! complete snapshot.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use xxhash_rust::xxh3::xxh3_128;
+
+use super::node::Timestamp;
+
+/// Default segment size (1 MiB)
+pub const DEFAULT_SEGMENT_SIZE: usize = 1024 * 1024;
+
+/// Local file recording the manifest the working copy was last synced to
+pub const SYNC_STATE_FILE: &str = ".aresadb/sync.json";
+
+/// Suffix of files being written during a pull
+const PARTIAL_SUFFIX: &str = ".partial";
+
+/// Error returned when another writer published a generation first
+#[derive(Debug, Clone, thiserror::Error)]
+#[error(
+    "sync conflict: local copy is based on generation {}, but the bucket is at generation {remote_generation}; \
+     another writer pushed changes",
+    base_generation.map(|g| g.to_string()).unwrap_or_else(|| "none".to_string())
+)]
+pub struct SyncConflict {
+    /// Generation the local copy was last synced to
+    pub base_generation: Option<u64>,
+    /// Latest generation in the bucket
+    pub remote_generation: u64,
+}
+
+/// Reference to one immutable segment
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SegmentRef {
+    /// Content hash (hex-encoded xxh3-128)
+    pub hash: String,
+    /// Uncompressed length in bytes
+    pub len: u64,
+}
+
+impl SegmentRef {
+    /// Describe a segment's contents
+    pub fn of(data: &[u8]) -> Self {
+        Self {
+            hash: segment_hash(data),
+            len: data.len() as u64,
+        }
+    }
+}
+
+/// A file as an ordered list of segments
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileEntry {
+    /// Total file length in bytes
+    pub len: u64,
+    /// Segments in file order
+    pub segments: Vec<SegmentRef>,
+}
+
+/// Snapshot of a database's files at one generation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Generation number (1 for the first push)
+    pub generation: u64,
+    /// Generation this one was derived from
+    pub parent: Option<u64>,
+    /// When the manifest was written
+    pub created_at: Timestamp,
+    /// Identifier of the writer that published it
+    pub writer: String,
+    /// Segment size used to split files
+    pub segment_size: u64,
+    /// Files keyed by path relative to the database root
+    pub files: BTreeMap<String, FileEntry>,
+}
+
+impl Manifest {
+    /// All segment hashes referenced by this manifest
+    pub fn segment_hashes(&self) -> HashSet<&str> {
+        self.files
+            .values()
+            .flat_map(|f| f.segments.iter().map(|s| s.hash.as_str()))
+            .collect()
+    }
+
+    /// Total bytes across all files
+    pub fn total_bytes(&self) -> u64 {
+        self.files.values().map(|f| f.len).sum()
+    }
+}
+
+/// Local record of the last successful sync
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncState {
+    /// Bucket URL the copy was synced with
+    pub url: String,
+    /// Generation the copy was synced to
+    pub generation: u64,
+    /// Segment size of that generation
+    pub segment_size: u64,
+    /// File contents at that generation
+    pub files: BTreeMap<String, FileEntry>,
+}
+
+impl SyncState {
+    /// Load the sync state for `url`, ignoring state recorded for other buckets
+    pub fn load(root: &Path, url: &str) -> Result<Option<Self>> {
+        let path = root.join(SYNC_STATE_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = std::fs::read(&path).context("Failed to read sync state")?;
+        let state: SyncState = serde_json::from_slice(&data).context("Corrupt sync state")?;
+        Ok(if state.url == url { Some(state) } else { None })
+    }
+
+    /// Persist the sync state atomically
+    pub fn save(&self, root: &Path) -> Result<()> {
+        let path = root.join(SYNC_STATE_FILE);
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+}
+
+/// Hex-encoded content hash of a segment
+pub fn segment_hash(data: &[u8]) -> String {
+    format!("{:032x}", xxh3_128(data))
+}
+
+/// Compress a segment for upload
+pub fn encode_segment(data: &[u8]) -> Vec<u8> {
+    lz4_flex::compress_prepend_size(data)
+}
+
+/// Decompress a downloaded segment and verify it matches `expected`
+pub fn decode_segment(data: &[u8], expected: &SegmentRef) -> Result<Vec<u8>> {
+    let raw = lz4_flex::decompress_size_prepended(data)
+        .with_context(|| format!("Failed to decompress segment {}", expected.hash))?;
+    if raw.len() as u64 != expected.len || segment_hash(&raw) != expected.hash {
+        anyhow::bail!("Segment {} failed verification", expected.hash);
+    }
+    Ok(raw)
+}
+
+/// Whether a path under `.aresadb` is part of the synced database
+fn is_synced_file(relative: &str) -> bool {
+    relative != SYNC_STATE_FILE
+        && !relative.ends_with(PARTIAL_SUFFIX)
+        && !relative.ends_with(".tmp")
+}
+
+/// Split every database file under `root/.aresadb` into segments
+pub fn scan_local(root: &Path, segment_size: usize) -> Result<BTreeMap<String, FileEntry>> {
+    let mut files = BTreeMap::new();
+    let dir = root.join(".aresadb");
+    if !dir.exists() {
+        return Ok(files);
+    }
+
+    for entry in walkdir::WalkDir::new(&dir).sort_by_file_name() {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = relative_path(root, entry.path())?;
+        if !is_synced_file(&relative) {
+            continue;
+        }
+        files.insert(relative, scan_file(entry.path(), segment_size)?);
+    }
+
+    Ok(files)
+}
+
+/// Split one file into segments
+pub fn scan_file(path: &Path, segment_size: usize) -> Result<FileEntry> {
+    let mut file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut segments = Vec::new();
+    let mut len = 0u64;
+    let mut buf = vec![0u8; segment_size];
+
+    loop {
+        let n = read_full(&mut file, &mut buf)?;
+        if n == 0 {
+            break;
+        }
+        segments.push(SegmentRef::of(&buf[..n]));
+        len += n as u64;
+        if n < segment_size {
+            break;
+        }
+    }
+
+    Ok(FileEntry { len, segments })
+}
+
+/// Read the `index`-th segment of a local file
+pub fn read_segment(path: &Path, index: usize, segment_size: usize) -> Result<Vec<u8>> {
+    let mut file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    file.seek(SeekFrom::Start((index * segment_size) as u64))?;
+    let mut buf = vec![0u8; segment_size];
+    let n = read_full(&mut file, &mut buf)?;
+    buf.truncate(n);
+    Ok(buf)
+}
+
+/// Path where a file is staged while it is being rebuilt
+pub fn partial_path(path: &Path) -> std::path::PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(PARTIAL_SUFFIX);
+    name.into()
+}
+
+/// Path of `path` relative to `root`, with `/` separators
+fn relative_path(root: &Path, path: &Path) -> Result<String> {
+    let relative = path.strip_prefix(root)?;
+    Ok(relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/"))
+}
+
+/// Fill `buf` as far as the reader allows
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = reader.read(&mut buf[filled..])?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_scan_splits_into_segments() {
+        let temp = TempDir::new().unwrap();
+        std::fs::create_dir_all(temp.path().join(".aresadb")).unwrap();
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(temp.path().join(".aresadb/data.redb"), &data).unwrap();
+        std::fs::write(temp.path().join(SYNC_STATE_FILE), b"{}").unwrap();
+
+        let files = scan_local(temp.path(), 4096).unwrap();
+        assert_eq!(files.len(), 1);
+        let entry = &files[".aresadb/data.redb"];
+        assert_eq!(entry.len, 10_000);
+        assert_eq!(entry.segments.len(), 3);
+        assert_eq!(entry.segments[2].len, 10_000 - 8192);
+
+        let segment = read_segment(&temp.path().join(".aresadb/data.redb"), 1, 4096).unwrap();
+        assert_eq!(segment, data[4096..8192]);
+        assert_eq!(SegmentRef::of(&segment), entry.segments[1]);
+    }
+
+    #[test]
+    fn test_segment_roundtrip_and_verification() {
+        let data = vec![7u8; 5000];
+        let reference = SegmentRef::of(&data);
+        let encoded = encode_segment(&data);
+        assert!(encoded.len() < data.len());
+        assert_eq!(decode_segment(&encoded, &reference).unwrap(), data);
+
+        let other = SegmentRef::of(b"something else");
+        assert!(decode_segment(&encoded, &other).is_err());
+    }
+
+    #[test]
+    fn test_sync_state_is_per_url() {
+        let temp = TempDir::new().unwrap();
+        std::fs::create_dir_all(temp.path().join(".aresadb")).unwrap();
+        let state = SyncState {
+            url: "s3://a/db".to_string(),
+            generation: 3,
+            segment_size: DEFAULT_SEGMENT_SIZE as u64,
+            files: BTreeMap::new(),
+        };
+        state.save(temp.path()).unwrap();
+
+        assert_eq!(SyncState::load(temp.path(), "s3://a/db").unwrap().unwrap().generation, 3);
+        assert!(SyncState::load(temp.path(), "s3://b/db").unwrap().is_none());
+    }
+}